@@ -0,0 +1,100 @@
+//! Runs the firmware on the mock board of [`pico_iox16_firmware::mock`], answering requests on
+//! stdin and stdout, or on the serial port given as argument. With a pty pair from `socat`, the
+//! tool can talk to it like to a real board:
+//!
+//! ```sh
+//! socat pty,raw,echo=0,link=/tmp/iox16-board pty,raw,echo=0,link=/tmp/iox16-tool &
+//! cargo +nightly run --features std --example host -- /tmp/iox16-board &
+//! pico_iox16_tool /tmp/iox16-tool ping 0xFFFF -c 3
+//! ```
+//!
+//! The board starts unconfigured at address `0xFFFF` and forgets its configuration when the
+//! example exits. Reboots start the firmware again.
+
+use std::{
+    env,
+    fs::OpenOptions,
+    io,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use pico_iox16_firmware::{
+    mock::{self, Flash, Host},
+    runtime::{Read, ReadError, Write},
+};
+
+/// A byte stream whose reads don't block, as the firmware polls it. A thread of its own reads
+/// from the blocking reader, std has no non-blocking reads from stdin or a pty.
+struct Port<W> {
+    received: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    writer: W,
+}
+impl<W: io::Write> Port<W> {
+    fn new(mut reader: impl io::Read + Send + 'static, writer: W) -> Self {
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => {
+                        if sender.send(buf[..n].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            received,
+            pending: Vec::new(),
+            writer,
+        }
+    }
+}
+impl<W> Read<Host> for Port<W> {
+    type Error = io::Error;
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        if self.pending.is_empty() {
+            self.pending = match self.received.try_recv() {
+                Ok(bytes) => bytes,
+                Err(TryRecvError::Empty) => return Err(nb::Error::WouldBlock),
+                // the end of the input ends the main loop
+                Err(TryRecvError::Disconnected) => {
+                    return Err(nb::Error::Other(ReadError::UnrecoverableError(
+                        io::ErrorKind::UnexpectedEof.into(),
+                    )));
+                }
+            };
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+impl<W: io::Write> Write<Host> for Port<W> {
+    type Error = io::Error;
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        Ok(self.writer.write(buf)?)
+    }
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let flash = Flash::default();
+    let on_reboot = |mode| eprintln!("Rebooting into {mode}");
+    match env::args_os().nth(1) {
+        Some(path) => {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            mock::run(&mut Port::new(file.try_clone()?, file), &flash, on_reboot);
+        }
+        None => mock::run(&mut Port::new(io::stdin(), io::stdout()), &flash, on_reboot),
+    }
+    eprintln!("The input was closed");
+    Ok(())
+}
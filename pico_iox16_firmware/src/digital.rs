@@ -0,0 +1,29 @@
+use core::marker::PhantomData;
+
+use pico_iox16_protocol::{DigitalGetReq, DigitalGetRes};
+
+use crate::HandleMessage;
+
+/// Spare GPIOs of a board that are read as digital inputs, e.g. for door switches. The board
+/// debounces them itself.
+pub trait DigitalInputs<Board: ?Sized> {
+    /// The GPIOs that are digital inputs, bit `n` for GPIO `n`.
+    fn available(&self) -> u32;
+    /// The debounced levels of the digital inputs, bit `n` for GPIO `n`, set if high.
+    fn levels(&self) -> u32;
+}
+
+impl<D: DigitalInputs<Board>, Board: ?Sized> HandleMessage
+    for (&DigitalGetReq, &D, PhantomData<Board>)
+{
+    type Response = DigitalGetRes;
+    type Error = !;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (_, digital, _) = self;
+        let available = digital.available();
+        Ok(DigitalGetRes {
+            available: available.into(),
+            levels: (digital.levels() & available).into(),
+        })
+    }
+}
@@ -3,14 +3,16 @@ use core::{array, cell::Cell, marker::PhantomData, ops::Deref};
 use fugit::{Duration, Instant};
 use futures::future::Either;
 use pico_iox16_protocol::{
-    InputGetFullReq, InputGetFullRes, InputGetReq, InputGetRes, InputGetThresholdStatesReq,
-    InputGetThresholdStatesRes, InputGetThresholdTimesReq, InputGetThresholdTimesRes, InputStat,
-    InputThresholdTimes,
+    INPUT_EVENTS_PER_RESPONSE, InputDebounce, InputEdge, InputEvent, InputGetDebounceReq,
+    InputGetDebounceRes, InputGetEventsReq, InputGetEventsRes, InputGetFullReq, InputGetFullRes,
+    InputGetReq, InputGetRes, InputGetThresholdStatesReq, InputGetThresholdStatesRes,
+    InputGetThresholdTimesReq, InputGetThresholdTimesRes, InputStat, InputThresholdTimes,
+    SampleInterval, settings::Threshold,
 };
 
 use crate::{
     HandleMessage, nb_await,
-    nvm::{self, NonvolatileStorage, Nvm},
+    nvm::{NonvolatileStorage, Nvm},
     runtime::{Timer, WaitUntil as _, yield_now},
 };
 
@@ -21,22 +23,61 @@ pub enum InputError<T> {
 
 pub trait Input<Board: ?Sized> {
     type Error;
+    /// Time in µs the multiplexers need after a change of the selection until their outputs
+    /// follow the newly selected input.
+    const SETTLE_TIME_US: u32 = 3;
+    /// The input each multiplexer position is wired to, for the left and the right half of the
+    /// board. Each of the 16 inputs needs to appear once.
+    const CHANNELS: [[u8; 8]; 2] = [[0, 1, 2, 3, 4, 5, 6, 7], [8, 9, 10, 11, 12, 13, 14, 15]];
     /// Set the first output pin that selectes the input to read.
     fn select0(&mut self, value: bool) -> nb::Result<(), Self::Error>;
     /// Set the second output pin that selectes the input to read.
     fn select1(&mut self, value: bool) -> nb::Result<(), Self::Error>;
     /// Set the third output pin that selectes the input to read.
     fn select2(&mut self, value: bool) -> nb::Result<(), Self::Error>;
-    /// Start reading the selected input on the left half of the board.
-    /// The value can be read by [`read_last`](Self::read_last).
-    fn start_read0(&mut self) -> nb::Result<(), Self::Error>;
-    /// Start reading the selected input on the right half of the board.
-    /// The value can be read by [`read_last`](Self::read_last).
-    fn start_read1(&mut self) -> nb::Result<(), Self::Error>;
-    /// Read the last value read by `start_read0` or `start_read1`. 
-    /// Returns `Err(InputError::RecoverableError)` if the reading failed but can be started again, 
+    /// Discard everything sampled so far, so that [`read`](Self::read) only returns samples of
+    /// the input selected now.
+    fn discard(&mut self) -> nb::Result<(), Self::Error>;
+    /// Read the samples taken since the last call into `buf`, each as a pair of the values of the
+    /// selected input on the left and right half of the board. Returns the number of pairs read.
+    /// Returns `Err(InputError::RecoverableError)` if samples were lost and sampling has to be
+    /// restarted with [`discard`](Self::discard),
     /// or `Err(InputError::UnrecoverableError(e))` if there was an unrecoverable error.
-    fn read_last(&mut self) -> nb::Result<u16, InputError<Self::Error>>;
+    fn read(&mut self, buf: &mut [[u16; 2]]) -> nb::Result<usize, InputError<Self::Error>>;
+    /// Stop sampling between two sweeps over the inputs, e.g. to power down the ADC, or start
+    /// again. Sampling is followed by a [`discard`](Self::discard).
+    fn set_paused(&mut self, _paused: bool) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+    /// The errors counted since boot that [`read`](Self::read) recovered from on its own.
+    fn errors(&self) -> InputErrors {
+        InputErrors::default()
+    }
+}
+
+/// Errors of an [`Input`] that don't stop it, but lose samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InputErrors {
+    /// Failed conversions of the left and right half of the board, whose samples were dropped.
+    pub conversion_errors: [u32; 2],
+    /// Times that samples were lost because they weren't read in time. Always both halves at
+    /// once, since they are sampled in turn.
+    pub overruns: u32,
+}
+
+/// Whether each of the 16 inputs appears exactly once.
+const fn is_permutation(channels: [[u8; 8]; 2]) -> bool {
+    let mut seen = 0u16;
+    let mut i = 0;
+    while i < 16 {
+        let channel = channels[i / 8][i % 8];
+        if channel >= 16 || seen & 1 << channel != 0 {
+            return false;
+        }
+        seen |= 1 << channel;
+        i += 1;
+    }
+    true
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -90,12 +131,105 @@ impl InputData {
         self.max = self.max.max(value);
         self.count = self.count.wrapping_add(1);
         if self.count == 0 {
-            self.sum = (self.sum + 1 - (1 - self.sum % 2)) / 2;
-            self.sum_squares = (self.sum_squares + 2 - (1 - self.sum_squares / 2 % 2)) / 4;
-            self.count = 0x8000;
+            self = Self {
+                count: 0x8000,
+                ..self.halved()
+            };
         }
         self
     }
+    /// Adds the values of `other`, read after those of `self`, halving both first if their
+    /// count would overflow.
+    pub fn merge(self, other: Self) -> Self {
+        let (a, b) = if u32::from(self.count) + u32::from(other.count) > u32::from(u16::MAX) {
+            (self.halved(), other.halved())
+        } else {
+            (self, other)
+        };
+        Self {
+            previous_value: self.previous_value,
+            sum: a.sum + b.sum,
+            sum_squares: a.sum_squares + b.sum_squares,
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+            count: a.count + b.count,
+        }
+    }
+    /// The average of the values, or the previous one if there are none.
+    pub fn average(&self) -> i16 {
+        if self.count == 0 {
+            self.previous_value
+        } else {
+            (self.sum / i32::from(self.count)) as i16
+        }
+    }
+    /// Halves the sum, sum of squares and count, see `count`.
+    fn halved(self) -> Self {
+        Self {
+            sum: (self.sum + 1 - (1 - self.sum % 2)) / 2,
+            sum_squares: (self.sum_squares + 2 - (1 - self.sum_squares / 2 % 2)) / 4,
+            count: self.count / 2,
+            ..self
+        }
+    }
+}
+
+/// The intervals between the sweeps of the input loop over an input, measured from the first
+/// samples of one sweep to those of the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalData<const NOM: u32, const DENOM: u32> {
+    /// When the input was last sampled, `None` before the first sweep
+    pub last_sampled: Option<Instant<u64, NOM, DENOM>>,
+    /// The shortest interval in µs since the statistics were last taken
+    pub min_us: u32,
+    /// The longest interval in µs since the statistics were last taken
+    pub max_us: u32,
+    /// The sum of the intervals in µs since the statistics were last taken
+    pub sum_us: u64,
+    /// The number of intervals since the statistics were last taken
+    pub count: u32,
+}
+impl<const NOM: u32, const DENOM: u32> Default for IntervalData<NOM, DENOM> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<const NOM: u32, const DENOM: u32> IntervalData<NOM, DENOM> {
+    pub const fn new() -> Self {
+        Self {
+            last_sampled: None,
+            min_us: u32::MAX,
+            max_us: 0,
+            sum_us: 0,
+            count: 0,
+        }
+    }
+    fn update(mut self, now: Instant<u64, NOM, DENOM>) -> Self {
+        if let Some(last_sampled) = self.last_sampled
+            && let Some(interval) = now.checked_duration_since(last_sampled)
+        {
+            let interval = u32::try_from(interval.to_micros()).unwrap_or(u32::MAX);
+            self.min_us = self.min_us.min(interval);
+            self.max_us = self.max_us.max(interval);
+            self.sum_us += u64::from(interval);
+            self.count = self.count.saturating_add(1);
+        }
+        self.last_sampled = Some(now);
+        self
+    }
+}
+impl<const NOM: u32, const DENOM: u32> From<IntervalData<NOM, DENOM>> for SampleInterval {
+    fn from(value: IntervalData<NOM, DENOM>) -> Self {
+        if value.count == 0 {
+            return Self::EMPTY;
+        }
+        Self {
+            min_us: value.min_us.into(),
+            max_us: value.max_us.into(),
+            mean_us: ((value.sum_us / u64::from(value.count)) as u32).into(),
+            count: value.count.into(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -124,12 +258,7 @@ impl<const NOM: u32, const DENOM: u32> ThresholdData<NOM, DENOM> {
             last_below_threshold_debounced: now,
         }
     }
-    fn update(
-        mut self,
-        value: i16,
-        now: Instant<u64, NOM, DENOM>,
-        threshold: &nvm::Threshold,
-    ) -> Self {
+    fn update(mut self, value: i16, now: Instant<u64, NOM, DENOM>, threshold: &Threshold) -> Self {
         let above_threshold = value > threshold.threshold_high;
         let below_threshold = value < threshold.threshold_low;
         if above_threshold {
@@ -162,11 +291,76 @@ impl<const NOM: u32, const DENOM: u32> ThresholdData<NOM, DENOM> {
         }
         self
     }
+    /// Whether the input is above `threshold_high`, but that crossing didn't pass the debounce
+    /// yet.
+    fn pending_high(&self) -> bool {
+        self.above_count > 0 && self.last_above_threshold_debounced != self.last_above_threshold
+    }
+    /// Whether the input is below `threshold_low`, but that crossing didn't pass the debounce
+    /// yet.
+    fn pending_low(&self) -> bool {
+        self.below_count > 0 && self.last_below_threshold_debounced != self.last_below_threshold
+    }
+}
+
+/// How many threshold crossings the device keeps until the master takes them with
+/// `InputGetEvents`.
+const EVENT_QUEUE_SIZE: usize = 64;
+
+/// The threshold crossings of all inputs that the master didn't take yet, oldest first. When it
+/// is full, the newest crossings are dropped, so that the ones taken stay in a row.
+struct EventQueue {
+    events: [Cell<InputEvent>; EVENT_QUEUE_SIZE],
+    /// The index of the oldest event
+    start: Cell<usize>,
+    len: Cell<usize>,
+    /// The number of events dropped since the events were last taken
+    lost: Cell<u16>,
+}
+impl EventQueue {
+    const fn new() -> Self {
+        Self {
+            events: [const { Cell::new(InputEvent::EMPTY) }; EVENT_QUEUE_SIZE],
+            start: Cell::new(0),
+            len: Cell::new(0),
+            lost: Cell::new(0),
+        }
+    }
+    fn push(&self, event: InputEvent) {
+        let len = self.len.get();
+        if len == EVENT_QUEUE_SIZE {
+            self.lost.update(|lost| lost.saturating_add(1));
+            return;
+        }
+        self.events[(self.start.get() + len) % EVENT_QUEUE_SIZE].set(event);
+        self.len.set(len + 1);
+    }
+    fn pop(&self) -> Option<InputEvent> {
+        let len = self.len.get();
+        if len == 0 {
+            return None;
+        }
+        let start = self.start.get();
+        self.start.set((start + 1) % EVENT_QUEUE_SIZE);
+        self.len.set(len - 1);
+        Some(self.events[start].get())
+    }
 }
 
 pub struct InputLoop<const NOM: u32, const DENOM: u32> {
+    /// The data of the sweeps over all inputs completed since the inputs were last read, so that
+    /// each response covers the same sweeps for every input
     inputs: [Cell<InputData>; 16],
+    /// The data of the sweep in progress, added to `inputs` when it is complete
+    sweep: [Cell<InputData>; 16],
     thresholds: [Cell<ThresholdData<NOM, DENOM>>; 16],
+    intervals: [Cell<IntervalData<NOM, DENOM>>; 16],
+    /// The threshold crossings since the master last took them
+    events: EventQueue,
+    /// Incremented for every input read, so that the watchdog can tell whether the loop is stuck
+    progress: Cell<u32>,
+    /// The errors of the input as of the last read
+    errors: Cell<InputErrors>,
 }
 impl<I: Deref<Target = InputLoop<NOM, DENOM>>, const NOM: u32, const DENOM: u32> HandleMessage
     for (&InputGetReq, I)
@@ -176,19 +370,7 @@ impl<I: Deref<Target = InputLoop<NOM, DENOM>>, const NOM: u32, const DENOM: u32>
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (InputGetReq, input_loop) = self;
         Ok(InputGetRes {
-            values: input_loop.inputs.each_ref().map(|v| {
-                let data = v.get();
-                let avg = if data.count == 0 {
-                    data.previous_value
-                } else {
-                    (data.sum / i32::from(data.count)) as i16
-                };
-                v.set(InputData {
-                    previous_value: avg,
-                    ..InputData::default()
-                });
-                avg.into()
-            }),
+            values: input_loop.take_inputs().map(|data| data.average().into()),
         })
     }
 }
@@ -200,19 +382,7 @@ impl<I: Deref<Target = InputLoop<NOM, DENOM>>, const NOM: u32, const DENOM: u32>
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (InputGetFullReq, input_loop) = self;
         Ok(InputGetFullRes {
-            stats: input_loop.inputs.each_ref().map(|v| {
-                let data = v.get();
-                let avg = if data.count == 0 {
-                    data.previous_value
-                } else {
-                    (data.sum / i32::from(data.count)) as i16
-                };
-                v.set(InputData {
-                    previous_value: avg,
-                    ..InputData::default()
-                });
-                data.into()
-            }),
+            stats: input_loop.take_inputs().map(Into::into),
         })
     }
 }
@@ -247,6 +417,75 @@ impl<
         Ok(InputGetThresholdTimesRes { now, inputs })
     }
 }
+impl<
+    I: Deref<Target = InputLoop<NOM, DENOM>>,
+    T: Timer<Board, u64, NOM, DENOM>,
+    Board: ?Sized,
+    const NOM: u32,
+    const DENOM: u32,
+> HandleMessage for (&InputGetDebounceReq, &T, I, PhantomData<Board>)
+{
+    type Response = InputGetDebounceRes;
+    type Error = !;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (InputGetDebounceReq, timer, input_loop, PhantomData) = self;
+        let now = timer.now().ticks().into();
+        let inputs = input_loop.thresholds.each_ref().map(|threshold| {
+            let threshold = threshold.get();
+            let mut pending = 0;
+            if threshold.pending_high() {
+                pending |= InputDebounce::PENDING_HIGH;
+            }
+            if threshold.pending_low() {
+                pending |= InputDebounce::PENDING_LOW;
+            }
+            InputDebounce {
+                raw_high: threshold.last_above_threshold.ticks().into(),
+                raw_low: threshold.last_below_threshold.ticks().into(),
+                debounced_high: threshold.last_above_threshold_debounced.ticks().into(),
+                debounced_low: threshold.last_below_threshold_debounced.ticks().into(),
+                above_count: threshold.above_count.into(),
+                below_count: threshold.below_count.into(),
+                pending,
+                _reserved: [0; 3],
+            }
+        });
+        Ok(InputGetDebounceRes { now, inputs })
+    }
+}
+impl<
+    I: Deref<Target = InputLoop<NOM, DENOM>>,
+    T: Timer<Board, u64, NOM, DENOM>,
+    Board: ?Sized,
+    const NOM: u32,
+    const DENOM: u32,
+> HandleMessage for (&InputGetEventsReq, &T, I, PhantomData<Board>)
+{
+    type Response = InputGetEventsRes;
+    type Error = !;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (req, timer, input_loop, PhantomData) = self;
+        let now = timer.now().ticks().into();
+        let queue = &input_loop.events;
+        let mut response = InputGetEventsRes {
+            now,
+            lost: queue.lost.replace(0).into(),
+            remaining: 0,
+            len: 0,
+            events: [InputEvent::EMPTY; INPUT_EVENTS_PER_RESPONSE],
+        };
+        let max = usize::from(req.max).min(INPUT_EVENTS_PER_RESPONSE);
+        for event in &mut response.events[..max] {
+            let Some(next) = queue.pop() else {
+                break;
+            };
+            *event = next;
+            response.len += 1;
+        }
+        response.remaining = queue.len.get().try_into().unwrap_or(u8::MAX);
+        Ok(response)
+    }
+}
 impl<I: Deref<Target = InputLoop<NOM, DENOM>>, const NOM: u32, const DENOM: u32> HandleMessage
     for (&InputGetThresholdStatesReq, I)
 {
@@ -277,34 +516,81 @@ impl<const NOM: u32, const DENOM: u32> InputLoop<NOM, DENOM> {
     pub fn new(now: Instant<u64, NOM, DENOM>) -> Self {
         Self {
             inputs: [const { Cell::new(InputData::new()) }; 16],
+            sweep: [const { Cell::new(InputData::new()) }; 16],
             thresholds: array::from_fn(|_| Cell::new(ThresholdData::new(now))),
+            intervals: [const { Cell::new(IntervalData::new()) }; 16],
+            events: EventQueue::new(),
+            progress: Cell::new(0),
+            errors: Cell::new(InputErrors::default()),
         }
     }
-    async fn wait_read0<Board: ?Sized, I: Input<Board>>(input: &mut I) -> Result<u16, I::Error> {
-        loop {
-            match nb_await!(input.read_last()) {
-                Ok(v) => return Ok(v),
-                Err(InputError::RecoverableError) => {
-                    nb_await!(input.start_read0())?;
-                    continue;
-                }
-                Err(InputError::UnrecoverableError(e)) => return Err(e),
-            }
+
+    pub(crate) fn progress(&self) -> u32 {
+        self.progress.get()
+    }
+    pub(crate) fn errors(&self) -> InputErrors {
+        self.errors.get()
+    }
+    /// The data of the sweeps completed since the inputs were last taken, keeping their averages
+    /// for when no sweep completes until the next time.
+    fn take_inputs(&self) -> [InputData; 16] {
+        self.inputs.each_ref().map(|input| {
+            let data = input.get();
+            input.set(InputData {
+                previous_value: data.average(),
+                ..InputData::new()
+            });
+            data
+        })
+    }
+    /// Adds the sweep that just completed to the data for the next read.
+    fn complete_sweep(&self) {
+        for (input, sweep) in self.inputs.iter().zip(&self.sweep) {
+            input.update(|data| data.merge(sweep.replace(InputData::new())));
         }
     }
-    async fn wait_read1<Board: ?Sized, I: Input<Board>>(input: &mut I) -> Result<u16, I::Error> {
-        loop {
-            match nb_await!(input.read_last()) {
-                Ok(v) => return Ok(v),
-                Err(InputError::RecoverableError) => {
-                    nb_await!(input.start_read1())?;
-                    continue;
-                }
-                Err(InputError::UnrecoverableError(e)) => return Err(e),
+    /// The sample intervals of the inputs since they were last taken.
+    pub(crate) fn take_sample_intervals(&self) -> [SampleInterval; 16] {
+        self.intervals.each_ref().map(|interval| {
+            let data = interval.get();
+            interval.set(IntervalData {
+                last_sampled: data.last_sampled,
+                ..IntervalData::new()
+            });
+            data.into()
+        })
+    }
+    /// Queues the crossings of input `index` that passed the debounce from `previous` to
+    /// `updated`.
+    fn queue_crossings(
+        &self,
+        index: usize,
+        previous: &ThresholdData<NOM, DENOM>,
+        updated: &ThresholdData<NOM, DENOM>,
+    ) {
+        let crossings = [
+            (
+                InputEdge::High,
+                previous.last_above_threshold_debounced,
+                updated.last_above_threshold_debounced,
+            ),
+            (
+                InputEdge::Low,
+                previous.last_below_threshold_debounced,
+                updated.last_below_threshold_debounced,
+            ),
+        ];
+        for (edge, previous, updated) in crossings {
+            if updated != previous {
+                self.events.push(InputEvent {
+                    time: updated.ticks().into(),
+                    input: index as u8,
+                    edge,
+                    _reserved: [0; 6],
+                });
             }
         }
     }
-
     /// Run the input loop, which continuously reads the inputs and updates the input data and threshold data.
     pub async fn run<Board: ?Sized, I: Input<Board>, NVM: NonvolatileStorage<Board>>(
         &self,
@@ -313,39 +599,89 @@ impl<const NOM: u32, const DENOM: u32> InputLoop<NOM, DENOM> {
         nvm: &Nvm<NVM, Board>,
     ) -> Result<!, Either<I::Error, NVM::Error>> {
         const GRAY_CODE_INCREMENT: [u8; 8] = [1, 3, 6, 2, 0, 4, 7, 5];
+        const SAMPLES_PER_SELECTION: usize = 8;
+        const {
+            assert!(
+                is_permutation(I::CHANNELS),
+                "each input needs to appear once"
+            )
+        };
+        // how often to show progress to the watchdog while waiting for the next sweep
+        const ALIVE_INTERVAL_MS: u64 = 20;
         let mut i = 0;
+        let mut selected = timer.now();
+        let mut sweep_start = selected;
         loop {
-            nb_await!(input.start_read0()).map_err(Either::Left)?;
-            // make sure to at least one guarantied yield per iteration of the loop to prevent starvation of other tasks
-            yield_now().await;
-            let v0 = Self::wait_read0(input).await.map_err(Either::Left)?;
-            let now0 = timer.now();
-            // start next read as early as possible
-            nb_await!(input.start_read1()).map_err(Either::Left)?;
-            let calibration = nvm.get().calibrations[i];
-            let v0 = calibration.apply(v0);
-            self.inputs[i].update(|data| data.update(v0));
-            let threshold = nvm.get().thresholds[i];
-            self.thresholds[i].update(|t| t.update(v0, now0, &threshold));
+            if i == 0 {
+                let interval = nvm.sample_interval_ms();
+                if interval > 0 {
+                    let next = sweep_start + Duration::<u64, NOM, DENOM>::millis(interval.into());
+                    nb_await!(input.set_paused(true)).map_err(Either::Left)?;
+                    while timer.now() < next {
+                        let alive =
+                            timer.now() + Duration::<u64, NOM, DENOM>::millis(ALIVE_INTERVAL_MS);
+                        timer.wait_until(alive.min(next)).await;
+                        self.progress.set(self.progress.get().wrapping_add(1));
+                    }
+                    nb_await!(input.set_paused(false)).map_err(Either::Left)?;
+                    selected = timer.now();
+                }
+                sweep_start = timer.now();
+            }
+            // let inputs settle
+            timer
+                .wait_until(
+                    selected + Duration::<u64, NOM, DENOM>::micros(I::SETTLE_TIME_US.into()),
+                )
+                .await;
+            nb_await!(input.discard()).map_err(Either::Left)?;
+            let mut samples = [[0; 2]; SAMPLES_PER_SELECTION];
+            let mut count = 0;
+            while count < samples.len() {
+                let read = nb_await!(input.read(&mut samples[count..]));
+                self.errors.set(input.errors());
+                let read = match read {
+                    Ok(read) => read,
+                    Err(InputError::RecoverableError) => {
+                        nb_await!(input.discard()).map_err(Either::Left)?;
+                        continue;
+                    }
+                    Err(InputError::UnrecoverableError(e)) => return Err(Either::Left(e)),
+                };
+                let now = timer.now();
+                if count == 0 && read > 0 {
+                    for channels in I::CHANNELS {
+                        self.intervals[usize::from(channels[i])].update(|data| data.update(now));
+                    }
+                }
+                for &[v0, v1] in &samples[count..count + read] {
+                    for (j, v) in [(I::CHANNELS[0][i], v0), (I::CHANNELS[1][i], v1)] {
+                        let j = usize::from(j);
+                        let calibration = nvm.get().settings.calibrations[j];
+                        let v = calibration.apply(v);
+                        self.sweep[j].update(|data| data.update(v));
+                        let threshold = nvm.get().settings.thresholds[j];
+                        let previous = self.thresholds[j].get();
+                        let updated = previous.update(v, now, &threshold);
+                        self.thresholds[j].set(updated);
+                        self.queue_crossings(j, &previous, &updated);
+                    }
+                }
+                count += read;
+                // make sure to at least one guarantied yield per iteration of the loop to prevent starvation of other tasks
+                yield_now().await;
+            }
 
-            let v1 = Self::wait_read1(input).await.map_err(Either::Left)?;
-            let now1 = timer.now();
-            // select next input as early as possible
             let i_tmp = GRAY_CODE_INCREMENT[i] as usize;
+            if i_tmp == 0 {
+                self.complete_sweep();
+            }
             nb_await!(input.select0(i_tmp & 0x1 != 0)).map_err(Either::Left)?;
             nb_await!(input.select1(i_tmp & 0x2 != 0)).map_err(Either::Left)?;
             nb_await!(input.select2(i_tmp & 0x4 != 0)).map_err(Either::Left)?;
-            let calibration = nvm.get().calibrations[i + 8];
-            let v1 = calibration.apply(v1);
-            self.inputs[i + 8].update(|data| data.update(v1));
-            let threshold = nvm.get().thresholds[i + 8];
-            self.thresholds[i + 8].update(|t| t.update(v1, now1, &threshold));
-
+            selected = timer.now();
             i = i_tmp;
-            // let inputs settle
-            timer
-                .wait_until(now1 + Duration::<u64, NOM, DENOM>::micros(3))
-                .await;
+            self.progress.set(self.progress.get().wrapping_add(1));
         }
     }
 }
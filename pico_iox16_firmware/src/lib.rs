@@ -1,33 +1,98 @@
 #![no_std]
 #![feature(never_type)]
 
+pub mod digital;
 pub mod input;
+#[cfg(feature = "std")]
+pub mod mock;
 pub mod nvm;
 pub mod output;
+pub mod panic;
+mod repeater;
 pub mod runtime;
+pub mod status;
+pub mod transport;
+mod update;
 
-use core::{marker::PhantomData, ops::Sub, pin::pin};
-use defmt::info;
+use core::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    ops::Sub,
+    pin::pin,
+};
+use defmt::{info, warn};
 use embedded_hal::digital::OutputPin;
 use fugit::{Duration, Instant};
 use futures::future::{Either, select};
 use pico_iox16_protocol::{
-    CheckReq, CheckRes, Command, ConfigGetReq, InfoGetReq, InfoGetRes, InputGetReq, Message, OutputGetReq, RebootReq, Request, slave_next
+    BaudratesGetReq, BaudratesGetRes, CAPABILITY_DIGITAL_INPUTS, CheckReq, CheckRes, Command, ConfigGetReq, DeviceIdGetReq, DeviceIdGetRes, DiagnosticsGetReq, DiagnosticsGetRes, DigitalGetReq, ErrorCode, InfoGetReq, InfoGetRes, InputGetReq, MAX_FRAME_SIZE, MAX_REQUEST_SIZE, Message, OutputGetReq, OutputSetMaskedReq, OutputSetReq, PROTOCOL_VERSION, PROTOCOL_VERSION_MINOR, PowerGetReq, ProtocolVersionGetReq, ProtocolVersionGetRes, RebootReq, Rejected, ReportConfigSetRes, Request, ResetCause, Transport, next_message, slave_next
 };
-use runtime::{Read, Timer, Write};
-use zerocopy::{Immutable, IntoBytes};
+use runtime::{Read, Timer, Watchdog, Write};
 
 use crate::{
+    digital::DigitalInputs,
     input::InputLoop,
-    runtime::{Elapsed as _, ReadError, System, WaitFor as _, yield_now},
+    repeater::Downstream,
+    runtime::{System, WaitFor as _, WaitUntil as _},
+    status::StatusLed,
+    transport::SerialTransport,
 };
 
+/// The info string of `InfoGet`, e.g. `IOx16 id:0123456789abcdef wdt`. The name is kept short,
+/// so that the unique ID and the reset cause fit into the 32 bytes.
+fn info_string(unique_id: u64, reset_cause: ResetCause) -> [u8; 32] {
+    let mut info = [0u8; 32];
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        info[len..len + bytes.len()].copy_from_slice(bytes);
+        len += bytes.len();
+    };
+    push(b"IOx16 id:");
+    for shift in (0..16).rev() {
+        push(&[b"0123456789abcdef"[(unique_id >> (shift * 4)) as usize & 0xF]]);
+    }
+    match reset_cause {
+        ResetCause::Watchdog => push(b" wdt"),
+        ResetCause::BrownOut => push(b" bor"),
+        ResetCause::Other | ResetCause::PowerOn => {}
+    }
+    info
+}
+
 trait HandleMessage {
     type Response;
     type Error;
     fn handle(self) -> impl Future<Output = Result<Self::Response, Self::Error>>;
 }
 
+/// A stage of the dispatcher in [`MainLoop::run`], which gets the frames that the device doesn't
+/// answer itself, e.g. to pass them on as a repeater.
+trait Stage<E> {
+    /// Takes care of `frame`, read at `at_us` of `upstream`'s clock, if it is meant to, answering
+    /// on `upstream`. Returns whether it did.
+    fn handle<U: Transport<Error = E>>(
+        &mut self,
+        upstream: &mut U,
+        frame: &[u8],
+        at_us: u64,
+    ) -> impl Future<Output = Result<bool, E>>;
+    /// The `CAPABILITY_*` bits that the stage adds to the response to `ProtocolVersionGet`.
+    fn capabilities(&self) -> u32 {
+        0
+    }
+}
+/// Leaves the frames for other devices alone.
+impl<E> Stage<E> for () {
+    async fn handle<U: Transport<Error = E>>(
+        &mut self,
+        _: &mut U,
+        _: &[u8],
+        _: u64,
+    ) -> Result<bool, E> {
+        Ok(false)
+    }
+}
+
 #[derive(Debug, thiserror::Error, defmt::Format)]
 pub enum MainLoopError<ReadError, WriteError, IoSendError, OutputError, InputError, NvmError> {
     // IO read error
@@ -76,9 +141,19 @@ macro_rules! error_coerce {
     };
 }
 
+/// How long the port waits for the first frame with stored line settings other than the default
+/// before it tries the default ones, and the other way round, see [`MainLoop::run`].
+const BAUDRATE_FALLBACK_MS: u32 = 10_000;
+
 pub struct MainLoop<const NOM: u32, const DENOM: u32> {
     started: Instant<u64, NOM, DENOM>,
     input_loop: InputLoop<NOM, DENOM>,
+    /// Incremented whenever a control loop polls its IO, so that the watchdog can tell whether
+    /// it is stuck
+    progress: Cell<u32>,
+    /// Whether all outputs were last set to 0%, one of the conditions of low-power mode
+    outputs_idle: Cell<bool>,
+    status: StatusLed,
 }
 impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
     pub fn new<Board: ?Sized>(timer: &impl Timer<Board, u64, NOM, DENOM>) -> Self {
@@ -86,39 +161,77 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
         Self {
             started: now,
             input_loop: InputLoop::new(now),
+            progress: Cell::new(0),
+            outputs_idle: Cell::new(true),
+            status: StatusLed::default(),
         }
     }
 
-    async fn write_all_bytes<
-        Board: ?Sized,
-        IO: Write<Board>,
-        IoSend: OutputPin,
-        P: IntoBytes + Immutable + ?Sized,
-    >(
-        io: &mut IO,
-        io_send: &mut IoSend,
-        payload: &P,
-    ) -> Result<(), MainLoopError<!, IO::Error, IoSend::Error, !, !, !>> {
-        let mut bytes = payload.as_bytes();
-        io_send.set_high().map_err(MainLoopError::IoSend)?;
-        {
-            let mut preamble = 2;
-            while preamble > 0 {
-                let written = nb_await!(io.write(&[0xFF])).map_err(MainLoopError::Write)?;
-                preamble -= written;
-            }
-        }
-        while !bytes.is_empty() {
-            let written = nb_await!(io.write(bytes)).map_err(MainLoopError::Write)?;
-            assert!(written > 0);
-            bytes = &bytes[written..];
+    /// The status LED, for the board to report conditions that only it can tell.
+    pub fn status(&self) -> &StatusLed {
+        &self.status
+    }
+
+    /// Enters low-power mode if a sample interval is set and all outputs are idle, or leaves it.
+    fn update_low_power<NVM, Board: ?Sized>(&self, nvm: &nvm::Nvm<NVM, Board>) {
+        let low_power = self.outputs_idle.get() && nvm.sample_interval_ms() > 0;
+        if low_power != runtime::low_power() {
+            info!("Low-power mode: {}", low_power);
+            runtime::set_low_power(low_power);
         }
-        nb_await!(io.flush()).map_err(MainLoopError::Write)?;
-        io_send.set_low().map_err(MainLoopError::IoSend)?;
+    }
+
+    /// Sets the outputs to the defaults stored with `OutputSetDefaults`, if any.
+    async fn apply_output_defaults<Board: ?Sized, O: output::Output<Board>, NVM>(
+        &self,
+        output: &mut O,
+        nvm: &nvm::Nvm<NVM, Board>,
+    ) -> Result<(), O::Error> {
+        let Some(defaults) = nvm.output_defaults() else {
+            return Ok(());
+        };
+        info!("Setting the outputs to their defaults");
+        let request = OutputSetReq(defaults.0);
+        (&request, &mut *output, PhantomData).handle().await?;
+        self.outputs_idle.set(
+            request
+                .0
+                .iter()
+                .flat_map(|group| group.duty_cycle)
+                .all(|d| d == 0),
+        );
         Ok(())
     }
 
-    /// Continuously read requests from the IO, handle them and write the responses back to the IO.
+    /// Feed the watchdog as long as both the control and the input loop make progress.
+    async fn feed_watchdog<Board: ?Sized, W: Watchdog<Board>, E>(
+        &self,
+        watchdog: &W,
+    ) -> Result<!, E> {
+        let mut control = self.progress.get();
+        let mut input = self.input_loop.progress();
+        loop {
+            // the loops being watched keep the executor busy anyway
+            runtime::sleep().await;
+            if self.progress.get() != control && self.input_loop.progress() != input {
+                watchdog.feed();
+                control = self.progress.get();
+                input = self.input_loop.progress();
+            }
+        }
+    }
+
+    /// Continuously read requests from `transport` into `frame`, handle them and write the
+    /// responses back to `transport`. Frames the device doesn't answer itself go to `stage`.
+    ///
+    /// Until the first frame arrives, a port at a stored baudrate, parity or number of stop bits
+    /// other than the default alternates between the stored ones and the default baudrate with
+    /// 8N1 every [`BAUDRATE_FALLBACK_MS`], so that a host that can't use the stored ones can still
+    /// reach the device to fix them. The port stays at the line settings of the first frame until
+    /// the next reboot.
+    // The output handlers never suspend, so the borrow of `output` can't overlap with another
+    // transport's loop.
+    #[allow(clippy::await_holding_refcell_ref)]
     async fn run<
         Board: ?Sized,
         IO: Read<Board> + Write<Board>,
@@ -126,14 +239,17 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
         T: Timer<Board, u64, NOM, DENOM>,
         O: output::Output<Board>,
         NVM: nvm::NonvolatileStorage<Board>,
+        const N: usize,
     >(
         &self,
-        io: &mut IO,
-        io_send: &mut S,
+        transport: &mut SerialTransport<'_, Board, IO, S, T, NOM, DENOM, N>,
+        frame: &mut [u8],
+        stage: &mut impl Stage<transport::SerialError<IO, S, Board>>,
         timer: &T,
-        output: &mut O,
+        output: &RefCell<&mut O>,
         nvm: &nvm::Nvm<NVM, Board>,
         input_loop: &InputLoop<NOM, DENOM>,
+        digital: &impl DigitalInputs<Board>,
         system: &impl System<Board>,
     ) -> Result<
         !,
@@ -149,266 +265,818 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
     where
         Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
     {
-        let address = nvm.get().config.address;
+        let address = nvm.get_config().address;
         info!("Starting main loop with {:?}", nvm.get_config());
-        let mut buf_len = 0;
-        let mut buf = [0; 256];
-        let mut last_receive = timer.now();
+        // the interval set with `ReportConfigSet` and when the next report is due
+        let mut report: Option<(Duration<u64, NOM, DENOM>, Instant<u64, NOM, DENOM>)> = None;
+        let mut report_sequence = 0u8;
+        // when the outputs go to their safe state unless a valid request arrives first, `None`
+        // while the failsafe is disabled or once they are in it
+        let failsafe_due = |now: Instant<u64, NOM, DENOM>| {
+            let timeout_ms = nvm.failsafe().timeout_ms.get();
+            (timeout_ms > 0).then(|| now + Duration::<u64, NOM, DENOM>::millis(timeout_ms.into()))
+        };
+        let mut failsafe = failsafe_due(timer.now());
+        let fallback_interval = Duration::<u64, NOM, DENOM>::millis(BAUDRATE_FALLBACK_MS.into());
+        let stored_line = nvm.get_config();
+        let default_line = nvm::Config::DEFAULT;
+        let line = |config: &nvm::Config| {
+            (
+                config.effective_baudrate(),
+                config.parity(),
+                config.stop_bits(),
+            )
+        };
+        // when to switch to the other line settings, `None` once a frame arrived
+        let mut baudrate_fallback =
+            (line(&stored_line) != line(&default_line)).then(|| timer.now() + fallback_interval);
+        let mut at_default_line = false;
         loop {
-            let received = match nb_await!(io.read(&mut buf[buf_len..])) {
-                Ok(received) => received,
-                Err(err) => {
-                    buf_len = 0;
-                    if let ReadError::UnrecoverableError(e) = err {
-                        return Err(MainLoopError::Read(e));
+            let received = {
+                let receive = pin!(transport.receive(frame));
+                let due = [report.map(|(_, due)| due), failsafe, baudrate_fallback]
+                    .into_iter()
+                    .flatten()
+                    .min();
+                match due {
+                    Some(due) => match select(receive, pin!(timer.wait_until(due))).await {
+                        Either::Left((received, _)) => Some(received),
+                        Either::Right(_) => None,
+                    },
+                    None => Some(receive.await),
+                }
+            };
+            let Some(received) = received else {
+                let now = timer.now();
+                if baudrate_fallback.is_some_and(|due| due <= now) {
+                    at_default_line = !at_default_line;
+                    let config = if at_default_line {
+                        default_line
                     } else {
-                        continue;
+                        stored_line
+                    };
+                    baudrate_fallback = transport
+                        .set_line(&config)
+                        .then(|| now + fallback_interval);
+                    if baudrate_fallback.is_some() {
+                        info!(
+                            "No frame yet, listening at {} Hz, parity {}, {} stop bits",
+                            config.effective_baudrate(),
+                            config.parity(),
+                            config.stop_bits()
+                        );
                     }
                 }
+                if failsafe.is_some_and(|due| due <= now) {
+                    failsafe = None;
+                    let duty_cycles = nvm.failsafe().duty_cycles;
+                    let duty_cycles = duty_cycles.map(|duty_cycle| duty_cycle.get());
+                    warn!(
+                        "No request for too long, forcing the outputs to {}",
+                        duty_cycles
+                    );
+                    let request = OutputSetMaskedReq::new(duty_cycles.into_iter().enumerate());
+                    (&request, &mut **output.borrow_mut(), PhantomData)
+                        .handle()
+                        .await
+                        .map_err(MainLoopError::Output)?;
+                    self.outputs_idle.set(duty_cycles.iter().all(|&d| d == 0));
+                    self.update_low_power(nvm);
+                    self.status.set_failsafe(true);
+                }
+                if let Some((interval, due)) = report
+                    && due <= now
+                {
+                    // skips the reports there was no time for instead of catching up
+                    report = Some((interval, (due + interval).max(now)));
+                    let values = (&InputGetReq, input_loop)
+                        .handle()
+                        .await
+                        .map_err(MainLoopError::Input)?;
+                    report_sequence = report_sequence.wrapping_add(1);
+                    transport.mute(false);
+                    transport
+                        .send_message(&Message::new_report(
+                            address,
+                            Command::InputGet,
+                            report_sequence,
+                            values,
+                        ))
+                        .await
+                        .map_err(|err| error_coerce!(err))?;
+                }
+                continue;
             };
-            if timer.elapsed(last_receive).to_micros() > 1000 {
-                buf_len = 0;
+            let received = received.map_err(|err| error_coerce!(err))?;
+            if baudrate_fallback.take().is_some() && at_default_line {
+                warn!(
+                    "Staying at 8N1 and the default baudrate instead of {} Hz, parity {}, {} stop bits",
+                    stored_line.effective_baudrate(),
+                    stored_line.parity(),
+                    stored_line.stop_bits()
+                );
             }
-            if received > 0 {
-                last_receive = timer.now();
-                buf_len += received;
+            let (maybe_request, _) = slave_next(&frame[..received.len], address);
+            // the responses echo the sequence number of the request
+            let (broadcast, sequence) = next_message(&frame[..received.len])
+                .0
+                .map_or((false, 0), |(header, _)| {
+                    (header.is_broadcast(), header.sequence)
+                });
+            // all devices execute a broadcast, and their responses would collide
+            transport.mute(broadcast);
+            // broadcasts are passed on before they are executed, a reboot wouldn't get that far
+            if (maybe_request.is_none() || broadcast)
+                && stage
+                    .handle(transport, &frame[..received.len], received.at_us)
+                    .await
+                    .map_err(|err| error_coerce!(err))?
+            {
+                self.status.activity();
             }
-
-            loop {
-                let (maybe_request, processed) = slave_next(&buf[..buf_len], address);
-                if let Some(request) = maybe_request {
-                    info!("Received request: {:?}", request.command());
-                    match request {
-                        Request::Check(CheckReq) => {
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::Check, CheckRes),
-                            )
+            let request = match maybe_request {
+                None => continue,
+                Some(Err(rejected)) => {
+                    info!("Rejected request: {:?}", rejected);
+                    self.status.activity();
+                    transport
+                        .send_message(&Message::new_error(address, rejected))
+                        .await
+                        .map_err(|err| error_coerce!(err))?;
+                    continue;
+                }
+                Some(Ok(request)) => request,
+            };
+            // the host is still there, the outputs are up to it again
+            self.status.set_failsafe(false);
+            failsafe = failsafe_due(timer.now());
+            info!("Received request: {:?}", request.command());
+            self.status.activity();
+            let handled = 'handled: {
+                match request {
+                    Request::Check(CheckReq) => {
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::Check,
+                                sequence,
+                                CheckRes,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InfoGet(InfoGetReq) => {
-                            let info = "Pico I∴O×16 v1.0".as_bytes();
-                            let mut info_array = [0u8; 32];
-                            for (a, b) in info_array.iter_mut().zip(info.iter().copied()) {
-                                *a = b;
-                            }
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InfoGet,
-                                    InfoGetRes {
-                                        info: info_array,
-                                        firmware_version_major: 0,
-                                        firmware_version_minor: 1,
-                                        firmware_version_patch: 0.into(),
-                                        uptime: ((timer.now() - self.started).to_secs() as u32)
-                                            .into(),
-                                    },
-                                ),
-                            )
+                    }
+                    Request::InfoGet(InfoGetReq) => {
+                        let info_array = info_string(system.unique_id(), system.reset_cause());
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InfoGet,
+                                sequence,
+                                InfoGetRes {
+                                    info: info_array,
+                                    firmware_version_major: 0,
+                                    firmware_version_minor: 1,
+                                    firmware_version_patch: 0.into(),
+                                    uptime: ((timer.now() - self.started).to_secs() as u32).into(),
+                                    protocol_version: PROTOCOL_VERSION.into(),
+                                    config_schema_version: nvm::SCHEMA_VERSION.into(),
+                                },
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::ConfigGet(ConfigGetReq) => {
-                            let Ok(response) = (&ConfigGetReq, nvm, PhantomData).handle().await;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::ConfigGet, response),
-                            )
+                    }
+                    Request::ConfigGet(ConfigGetReq) => {
+                        let Ok(response) = (&ConfigGetReq, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ConfigGet,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::ConfigSet(request) => {
+                        if let Err(err) = request.0.validate() {
+                            warn!("Rejected configuration: {}", err);
+                            break 'handled Err(ErrorCode::InvalidPayload);
                         }
-                        Request::ConfigSet(request) => {
-                            let response = (request, nvm, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Nvm)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::ConfigSet, response),
-                            )
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ConfigSet,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::OutputSet(request) => {
-                            let response = (request, &mut *output, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Output)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::OutputSet, response),
-                            )
+                    }
+                    Request::OutputSet(request) => {
+                        let response = (request, &mut **output.borrow_mut(), PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Output)?;
+                        self.outputs_idle.set(
+                            request
+                                .0
+                                .iter()
+                                .flat_map(|group| group.duty_cycle)
+                                .all(|d| d == 0),
+                        );
+                        self.update_low_power(nvm);
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::OutputSet,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::OutputGet(OutputGetReq) => {
-                            let response = (&OutputGetReq, &mut *output, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Output)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::OutputGet, response),
-                            )
+                    }
+                    Request::OutputSetMasked(request) => {
+                        let response = (request, &mut **output.borrow_mut(), PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Output)?;
+                        // the outputs left alone count as well
+                        let outputs = (&OutputGetReq, &**output.borrow(), PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Output)?;
+                        self.outputs_idle.set(
+                            outputs
+                                .0
+                                .iter()
+                                .flat_map(|group| group.duty_cycle)
+                                .all(|d| d == 0),
+                        );
+                        self.update_low_power(nvm);
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::OutputSetMasked,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGet(InputGetReq) => {
-                            let response = (&InputGetReq, input_loop)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Input)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::InputGet, response),
-                            )
+                    }
+                    Request::OutputGet(OutputGetReq) => {
+                        let response = (&OutputGetReq, &**output.borrow(), PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Output)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::OutputGet,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGetFull(request) => {
-                            let response = (request, input_loop)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Input)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::InputGetFull, response),
-                            )
+                    }
+                    Request::InputGet(InputGetReq) => {
+                        let response = (&InputGetReq, input_loop)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGet,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputSetCalibrations(request) => {
-                            let response = (request, nvm, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Nvm)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputSetCalibrations,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputGetFull(request) => {
+                        let response = (request, input_loop)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetFull,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGetCalibrations(request) => {
-                            let Ok(response) = (request, nvm, PhantomData).handle().await;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputGetCalibrations,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputSetCalibrations(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputSetCalibrations,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputSetThresholds(request) => {
-                            let response = (request, nvm, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Nvm)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputSetThresholds,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputGetCalibrations(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetCalibrations,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGetThresholds(request) => {
-                            let Ok(response) = (request, nvm, PhantomData).handle().await;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputGetThresholds,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputSetThresholds(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputSetThresholds,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGetThresholdTimes(request) => {
-                            let response = (request, timer, input_loop, PhantomData)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Input)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputGetThresholdTimes,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputGetThresholds(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetThresholds,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::InputGetThresholdStates(request) => {
-                            let response = (request, input_loop)
-                                .handle()
-                                .await
-                                .map_err(MainLoopError::Input)?;
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(
-                                    address,
-                                    Command::InputGetThresholdStates,
-                                    response,
-                                ),
-                            )
+                    }
+                    Request::InputGetThresholdTimes(request) => {
+                        let response = (request, timer, input_loop, PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetThresholdTimes,
+                                sequence,
+                                response,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                        }
-                        Request::Reboot(RebootReq) => {
-                            info!("Rebooting address {} @ {} Hz", nvm.get().config.address, nvm.get().config.baudrate);
-                            Self::write_all_bytes(
-                                io,
-                                io_send,
-                                &Message::new_response(address, Command::Reboot, ()),
-                            )
+                    }
+                    Request::InputGetDebounce(request) => {
+                        let response = (request, timer, input_loop, PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetDebounce,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::ReportConfigSet(request) => {
+                        let interval_ms = request.interval_ms.get();
+                        info!("Reporting every {} ms", interval_ms);
+                        report = (interval_ms > 0).then(|| {
+                            let interval = Duration::<u64, NOM, DENOM>::millis(interval_ms.into());
+                            (interval, timer.now() + interval)
+                        });
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ReportConfigSet,
+                                sequence,
+                                ReportConfigSetRes,
+                            ))
                             .await
                             .map_err(|err| error_coerce!(err))?;
-                            timer.wait_for(Duration::<u64, _, _>::millis(1)).await;
-                            system.reboot();
+                    }
+                    Request::InputGetEvents(request) => {
+                        let response = (request, timer, input_loop, PhantomData)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetEvents,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::InputGetThresholdStates(request) => {
+                        let response = (request, input_loop)
+                            .handle()
+                            .await
+                            .map_err(MainLoopError::Input)?;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::InputGetThresholdStates,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::DiagnosticsGet(DiagnosticsGetReq) => {
+                        let errors = input_loop.errors();
+                        let flags = if nvm.corrupted() {
+                            DiagnosticsGetRes::CONFIG_CORRUPTED
+                        } else {
+                            0
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::DiagnosticsGet,
+                                sequence,
+                                DiagnosticsGetRes {
+                                    reset_cause: system.reset_cause(),
+                                    flags,
+                                    _reserved: [0; 2],
+                                    brownouts: nvm.brownouts().into(),
+                                    conversion_errors: errors.conversion_errors.map(Into::into),
+                                    overruns: errors.overruns.into(),
+                                    sample_intervals: input_loop.take_sample_intervals(),
+                                },
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::PowerSet(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        self.update_low_power(nvm);
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::PowerSet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::PowerGet(PowerGetReq) => {
+                        let Ok(response) = (&PowerGetReq, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::PowerGet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::ForwardingSet(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ForwardingSet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::FailsafeSet(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        failsafe = failsafe_due(timer.now());
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::FailsafeSet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::BaudratesGet(BaudratesGetReq) => {
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::BaudratesGet,
+                                sequence,
+                                BaudratesGetRes::new(nvm::BAUDRATES),
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::OutputSetDefaults(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::OutputSetDefaults,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::OutputGetDefaults(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::OutputGetDefaults,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::FailsafeGet(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::FailsafeGet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::ForwardingGet(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ForwardingGet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::UserDataRead(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::UserDataRead,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::UserDataWrite(request) => {
+                        let Ok(response) = (request, nvm, PhantomData).handle().await else {
+                            break 'handled Err(ErrorCode::StorageFailed);
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::UserDataWrite,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::ProtocolVersionGet(ProtocolVersionGetReq) => {
+                        let mut capabilities = stage.capabilities();
+                        if digital.available() != 0 {
+                            capabilities |= CAPABILITY_DIGITAL_INPUTS;
                         }
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::ProtocolVersionGet,
+                                sequence,
+                                ProtocolVersionGetRes {
+                                    major: PROTOCOL_VERSION.into(),
+                                    minor: PROTOCOL_VERSION_MINOR.into(),
+                                    capabilities: capabilities.into(),
+                                },
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::DeviceIdGet(DeviceIdGetReq) => {
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::DeviceIdGet,
+                                sequence,
+                                DeviceIdGetRes::new(system.unique_id(), system.hardware_revision()),
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::DigitalGet(DigitalGetReq) => {
+                        let Ok(response) = (&DigitalGetReq, digital, PhantomData).handle().await;
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::DigitalGet,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::FlashEraseRegion(request) => {
+                        let response =
+                            match (request, nvm, &self.progress, PhantomData).handle().await {
+                                Ok(response) => response,
+                                Err(code) => break 'handled Err(code),
+                            };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::FlashEraseRegion,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::FlashWriteChunk(request) => {
+                        let response = match (request, nvm, PhantomData).handle().await {
+                            Ok(response) => response,
+                            Err(code) => break 'handled Err(code),
+                        };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::FlashWriteChunk,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::FlashVerify(request) => {
+                        let response =
+                            match (request, nvm, &self.progress, PhantomData).handle().await {
+                                Ok(response) => response,
+                                Err(code) => break 'handled Err(code),
+                            };
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::FlashVerify,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                    }
+                    Request::BootSwap(request) => {
+                        let response =
+                            match (request, nvm, &self.progress, PhantomData).handle().await {
+                                Ok(response) => response,
+                                Err(code) => break 'handled Err(code),
+                            };
+                        info!("Booting the staged firmware of {} bytes", request.len.get());
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::BootSwap,
+                                sequence,
+                                response,
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                        timer.wait_for(Duration::<u64, _, _>::millis(1)).await;
+                        nvm.storage().boot_staged(request.len.get());
+                    }
+                    Request::Reboot(RebootReq { mode, .. }) => {
+                        info!(
+                            "Rebooting into {} at address {} @ {} Hz",
+                            mode,
+                            nvm.get_config().address,
+                            nvm.get_config().baudrate
+                        );
+                        transport
+                            .send_message(&Message::new_response(
+                                address,
+                                Command::Reboot,
+                                sequence,
+                                (),
+                            ))
+                            .await
+                            .map_err(|err| error_coerce!(err))?;
+                        timer.wait_for(Duration::<u64, _, _>::millis(1)).await;
+                        system.reboot(*mode);
                     }
-                    info!("Handled request, response sent");
-                }
-                if processed == 0 {
-                    break;
                 }
-                buf.copy_within(processed..buf_len, 0);
-                buf_len -= processed;
+                Ok(())
+            };
+            if let Err(code) = handled {
+                warn!("Failed to handle request: {}", code);
+                let rejected = Rejected {
+                    command: request.command().into(),
+                    sequence,
+                    code,
+                };
+                transport
+                    .send_message(&Message::new_error(address, rejected))
+                    .await
+                    .map_err(|err| error_coerce!(err))?;
+                continue;
             }
-            // make sure to at least one guarantied yield per iteration of the loop to prevent starvation of other tasks
-            yield_now().await;
+            info!("Handled request, response sent");
         }
     }
+    /// Runs `control`, which answers requests on the transports of a main loop, alongside the
+    /// input loop, the watchdog and the status LED. Before that, records the reset, sets the
+    /// outputs to their defaults and enters low-power mode if it applies. `control` gets the
+    /// outputs to share among its transports.
+    async fn serve<
+        Board: ?Sized,
+        T: Timer<Board, u64, NOM, DENOM>,
+        O: output::Output<Board>,
+        I: input::Input<Board, Error: From<!>>,
+        NVM: nvm::NonvolatileStorage<Board>,
+        S: System<Board>,
+        W: Watchdog<Board>,
+        L: OutputPin,
+        ReadError,
+        WriteError,
+        IoSendError,
+    >(
+        &self,
+        control: impl AsyncFnOnce(
+            &RefCell<&mut O>,
+        ) -> Result<
+            !,
+            MainLoopError<ReadError, WriteError, IoSendError, O::Error, !, NVM::Error>,
+        >,
+        timer: &T,
+        output: &mut O,
+        input: &mut I,
+        nvm: &nvm::Nvm<NVM, Board>,
+        system: &S,
+        watchdog: &W,
+        led: &mut L,
+    ) -> Result<!, MainLoopError<ReadError, WriteError, IoSendError, O::Error, I::Error, NVM::Error>>
+    where
+        Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+    {
+        nvm.record_reset(system.reset_cause())
+            .await
+            .map_err(MainLoopError::Nvm)?;
+        self.status
+            .set_unconfigured(nvm.get_config().address == nvm::UNCONFIGURED_ADDRESS);
+        self.apply_output_defaults(output, nvm)
+            .await
+            .map_err(MainLoopError::Output)?;
+        self.update_low_power(nvm);
+        let output = RefCell::new(output);
+        let control = pin!(async { control(&output).await.map_err(|err| err.convert()) });
+        let input = pin!(async {
+            self.input_loop
+                .run(input, timer, nvm)
+                .await
+                .map_err(|err| match err {
+                    Either::Left(err) => MainLoopError::Input(err),
+                    Either::Right(err) => MainLoopError::Nvm(err),
+                })
+        });
+        let watchdog = pin!(self.feed_watchdog(watchdog));
+        let status = pin!(self.status.run(led, timer));
+        let watchdog = pin!(async { select(watchdog, status).await.factor_first().0 });
+        let control = pin!(async { select(control, watchdog).await.factor_first().0 });
+        select(control, input).await.factor_first().0
+    }
 
     /// Run the main loop of the firmware.
     pub async fn main_loop<
@@ -418,8 +1086,11 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
         T: Timer<Board, u64, NOM, DENOM>,
         O: output::Output<Board>,
         I: input::Input<Board, Error: From<!>>,
+        D: DigitalInputs<Board>,
         NVM: nvm::NonvolatileStorage<Board>,
         S: System<Board>,
+        W: Watchdog<Board>,
+        L: OutputPin,
     >(
         &mut self,
         io: &mut Io,
@@ -427,8 +1098,11 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
         timer: &T,
         output: &mut O,
         input: &mut I,
+        digital: &D,
         nvm: &nvm::Nvm<NVM, Board>,
         system: &S,
+        watchdog: &W,
+        led: &mut L,
     ) -> Result<
         !,
         MainLoopError<
@@ -443,44 +1117,210 @@ impl<const NOM: u32, const DENOM: u32> MainLoop<NOM, DENOM> {
     where
         Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
     {
-        let control = pin!(async {
-            let r: Result<
-                !,
-                MainLoopError<
-                    <Io as Read<Board>>::Error,
-                    <Io as Write<Board>>::Error,
-                    <IoSend as embedded_hal::digital::ErrorType>::Error,
-                    <O as output::Output<Board>>::Error,
-                    <I as input::Input<Board>>::Error,
-                    <NVM as nvm::NonvolatileStorage<Board>>::Error,
-                >,
-            > = self
-                .run(io, io_send, timer, output, nvm, &self.input_loop, system)
+        self.serve(
+            async |output| {
+                let mut transport: SerialTransport<'_, _, _, _, _, NOM, DENOM> =
+                    SerialTransport::new(io, io_send, timer, &self.progress);
+                let mut frame = [0; MAX_REQUEST_SIZE];
+                self.run(
+                    &mut transport,
+                    &mut frame,
+                    &mut (),
+                    timer,
+                    output,
+                    nvm,
+                    &self.input_loop,
+                    digital,
+                    system,
+                )
                 .await
-                .map_err(|err| err.convert());
-            r
-        });
-        let input = pin!(async {
-            let r: Result<
-                !,
-                MainLoopError<
-                    <Io as Read<Board>>::Error,
-                    <Io as Write<Board>>::Error,
-                    <IoSend as embedded_hal::digital::ErrorType>::Error,
-                    <O as output::Output<Board>>::Error,
-                    <I as input::Input<Board>>::Error,
-                    <NVM as nvm::NonvolatileStorage<Board>>::Error,
-                >,
-            > = self
-                .input_loop
-                .run(input, timer, nvm)
+            },
+            timer,
+            output,
+            input,
+            nvm,
+            system,
+            watchdog,
+            led,
+        )
+        .await
+    }
+
+    /// Run the main loop of the firmware, answering requests on two transports at the same time,
+    /// e.g. two RS-485 buses or a bus and a debug console. Both transports need to share the same
+    /// error types.
+    pub async fn dual_main_loop<
+        Board: ?Sized,
+        Io: Read<Board> + Write<Board>,
+        IoSend: OutputPin,
+        Io2: Read<Board, Error = <Io as Read<Board>>::Error>
+            + Write<Board, Error = <Io as Write<Board>>::Error>,
+        IoSend2: OutputPin<Error = IoSend::Error>,
+        T: Timer<Board, u64, NOM, DENOM>,
+        O: output::Output<Board>,
+        I: input::Input<Board, Error: From<!>>,
+        D: DigitalInputs<Board>,
+        NVM: nvm::NonvolatileStorage<Board>,
+        S: System<Board>,
+        W: Watchdog<Board>,
+        L: OutputPin,
+    >(
+        &mut self,
+        io: &mut Io,
+        io_send: &mut IoSend,
+        io2: &mut Io2,
+        io_send2: &mut IoSend2,
+        timer: &T,
+        output: &mut O,
+        input: &mut I,
+        digital: &D,
+        nvm: &nvm::Nvm<NVM, Board>,
+        system: &S,
+        watchdog: &W,
+        led: &mut L,
+    ) -> Result<
+        !,
+        MainLoopError<
+            <Io as Read<Board>>::Error,
+            <Io as Write<Board>>::Error,
+            <IoSend as embedded_hal::digital::ErrorType>::Error,
+            <O as output::Output<Board>>::Error,
+            <I as input::Input<Board>>::Error,
+            <NVM as nvm::NonvolatileStorage<Board>>::Error,
+        >,
+    >
+    where
+        Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+    {
+        self.serve(
+            async |output| {
+                let mut transport: SerialTransport<'_, _, _, _, _, NOM, DENOM> =
+                    SerialTransport::new(io, io_send, timer, &self.progress);
+                let mut transport2: SerialTransport<'_, _, _, _, _, NOM, DENOM> =
+                    SerialTransport::new(io2, io_send2, timer, &self.progress);
+                let (mut frame, mut frame2) = ([0; MAX_REQUEST_SIZE], [0; MAX_REQUEST_SIZE]);
+                select(
+                    pin!(self.run(
+                        &mut transport,
+                        &mut frame,
+                        &mut (),
+                        timer,
+                        output,
+                        nvm,
+                        &self.input_loop,
+                        digital,
+                        system,
+                    )),
+                    pin!(self.run(
+                        &mut transport2,
+                        &mut frame2,
+                        &mut (),
+                        timer,
+                        output,
+                        nvm,
+                        &self.input_loop,
+                        digital,
+                        system,
+                    )),
+                )
                 .await
-                .map_err(|err| match err {
-                    Either::Left(err) => MainLoopError::Input(err),
-                    Either::Right(err) => MainLoopError::Nvm(err),
-                });
-            r
-        });
-        select(control, input).await.factor_first().0
+                .factor_first()
+                .0
+            },
+            timer,
+            output,
+            input,
+            nvm,
+            system,
+            watchdog,
+            led,
+        )
+        .await
+    }
+
+    /// Run the main loop of the firmware as a repeater: requests to the addresses set with
+    /// `ForwardingSet` are passed on to a second bus segment on `downstream`, on which the device
+    /// is the master, and the responses are sent back upstream. `timeout_us` is how long the
+    /// devices there take to respond. The device answers requests to its own address like
+    /// [`MainLoop::main_loop`]. Both ports need to share the same error types.
+    pub async fn repeater_main_loop<
+        Board: ?Sized,
+        Io: Read<Board> + Write<Board>,
+        IoSend: OutputPin,
+        Io2: Read<Board, Error = <Io as Read<Board>>::Error>
+            + Write<Board, Error = <Io as Write<Board>>::Error>,
+        IoSend2: OutputPin<Error = IoSend::Error>,
+        T: Timer<Board, u64, NOM, DENOM>,
+        O: output::Output<Board>,
+        I: input::Input<Board, Error: From<!>>,
+        D: DigitalInputs<Board>,
+        NVM: nvm::NonvolatileStorage<Board>,
+        S: System<Board>,
+        W: Watchdog<Board>,
+        L: OutputPin,
+    >(
+        &mut self,
+        io: &mut Io,
+        io_send: &mut IoSend,
+        downstream: &mut Io2,
+        downstream_send: &mut IoSend2,
+        timeout_us: u32,
+        timer: &T,
+        output: &mut O,
+        input: &mut I,
+        digital: &D,
+        nvm: &nvm::Nvm<NVM, Board>,
+        system: &S,
+        watchdog: &W,
+        led: &mut L,
+    ) -> Result<
+        !,
+        MainLoopError<
+            <Io as Read<Board>>::Error,
+            <Io as Write<Board>>::Error,
+            <IoSend as embedded_hal::digital::ErrorType>::Error,
+            <O as output::Output<Board>>::Error,
+            <I as input::Input<Board>>::Error,
+            <NVM as nvm::NonvolatileStorage<Board>>::Error,
+        >,
+    >
+    where
+        Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+    {
+        self.serve(
+            async |output| {
+                let mut transport: SerialTransport<'_, _, _, _, _, NOM, DENOM, MAX_FRAME_SIZE> =
+                    SerialTransport::new(io, io_send, timer, &self.progress);
+                let mut frame = [0; MAX_FRAME_SIZE];
+                let mut downstream = Downstream::new(
+                    downstream,
+                    downstream_send,
+                    timer,
+                    &self.progress,
+                    nvm,
+                    timeout_us,
+                );
+                self.run(
+                    &mut transport,
+                    &mut frame,
+                    &mut downstream,
+                    timer,
+                    output,
+                    nvm,
+                    &self.input_loop,
+                    digital,
+                    system,
+                )
+                .await
+            },
+            timer,
+            output,
+            input,
+            nvm,
+            system,
+            watchdog,
+            led,
+        )
+        .await
     }
 }
@@ -0,0 +1,405 @@
+//! A board that exists only on the host, for running the firmware without hardware: the inputs
+//! read fixed values and the outputs, flash and GPIOs only keep what they were set to. The serial
+//! port is up to the user, e.g. the in-memory stream of `pico_iox16_integration` or stdin and
+//! stdout in `examples/host.rs`.
+
+extern crate std;
+
+use core::convert::Infallible;
+use std::{
+    boxed::Box,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::Instant,
+    vec::Vec,
+};
+
+use pico_iox16_protocol::{
+    FLASH_CHUNK_SIZE, FLASH_SECTOR_SIZE, RebootMode, ResetCause, USER_DATA_SIZE,
+};
+
+use crate::{
+    MainLoop,
+    digital::DigitalInputs,
+    input::{Input, InputError},
+    nvm::{self, NonvolatileStorage, default_nonvolatile_data},
+    output::{Output, Pwm, PwmChannel},
+    runtime::{DeviceId, Read, System, Timer, Watchdog, Write, block_on},
+};
+
+/// Discards the firmware's log, there is no probe to send it to.
+#[defmt::global_logger]
+struct Logger;
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+/// The board type parameter of the firmware's traits.
+pub struct Host;
+
+/// Ticks of the firmware's clock per second.
+pub const TICK_HZ: u32 = 1_000_000;
+
+pub type Instant64 = fugit::Instant<u64, 1, TICK_HZ>;
+
+/// Microseconds since the board was created.
+pub struct Clock(Instant);
+impl Clock {
+    pub fn new() -> Self {
+        Self(Instant::now())
+    }
+}
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Timer<Host, u64, 1, TICK_HZ> for Clock {
+    fn now(&self) -> Instant64 {
+        Instant64::from_ticks(self.0.elapsed().as_micros() as u64)
+    }
+}
+
+/// A pin that isn't connected to anything, for the driver enable and the LED.
+pub struct NoPin;
+impl embedded_hal::digital::ErrorType for NoPin {
+    type Error = Infallible;
+}
+impl embedded_hal::digital::OutputPin for NoPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// One output, whose duty cycle is scaled like the protocol's.
+#[derive(Default)]
+pub struct Channel(u16);
+impl PwmChannel<Host> for Channel {
+    type Error = Infallible;
+    fn max_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(0x8000)
+    }
+    fn get_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(self.0)
+    }
+    fn set_duty_cycle(&mut self, duty_cycle: u16) -> Result<(), Self::Error> {
+        self.0 = duty_cycle;
+        Ok(())
+    }
+}
+
+/// A group of two outputs sharing a frequency.
+pub struct Group {
+    frequency: u16,
+    a: Channel,
+    b: Channel,
+}
+impl Default for Group {
+    fn default() -> Self {
+        Self {
+            frequency: 1000,
+            a: Channel::default(),
+            b: Channel::default(),
+        }
+    }
+}
+impl Pwm<Host> for Group {
+    type Error = Infallible;
+    type ChannelA = Channel;
+    type ChannelB = Channel;
+    fn get_frequency(&self) -> Result<u16, Self::Error> {
+        Ok(self.frequency)
+    }
+    fn channel_a(&self) -> &Self::ChannelA {
+        &self.a
+    }
+    fn channel_b(&self) -> &Self::ChannelB {
+        &self.b
+    }
+    fn set_frequency(&mut self, frequency: u16) -> Result<(), Self::Error> {
+        self.frequency = frequency;
+        Ok(())
+    }
+    fn channel_a_mut(&mut self) -> &mut Self::ChannelA {
+        &mut self.a
+    }
+    fn channel_b_mut(&mut self) -> &mut Self::ChannelB {
+        &mut self.b
+    }
+}
+
+#[derive(Default)]
+pub struct Outputs([Group; 8]);
+macro_rules! groups {
+    ($($ty:ident, $get:ident, $get_mut:ident, $index:literal;)*) => {
+        $(
+            type $ty = Group;
+            fn $get(&self) -> &Group {
+                &self.0[$index]
+            }
+            fn $get_mut(&mut self) -> &mut Group {
+                &mut self.0[$index]
+            }
+        )*
+    };
+}
+impl Output<Host> for Outputs {
+    type Error = Infallible;
+    groups! {
+        Pwm0, pwm0, pwm0_mut, 0;
+        Pwm1, pwm1, pwm1_mut, 1;
+        Pwm2, pwm2, pwm2_mut, 2;
+        Pwm3, pwm3, pwm3_mut, 3;
+        Pwm4, pwm4, pwm4_mut, 4;
+        Pwm5, pwm5, pwm5_mut, 5;
+        Pwm6, pwm6, pwm6_mut, 6;
+        Pwm7, pwm7, pwm7_mut, 7;
+    }
+}
+
+/// The raw value the board reads for an input.
+pub const fn raw_value(input: u8) -> u16 {
+    1000 + 10 * input as u16
+}
+
+/// Multiplexers in front of an ADC that always reads [`raw_value`] of the selected inputs.
+#[derive(Default)]
+pub struct Inputs {
+    selection: usize,
+}
+impl Inputs {
+    fn select(&mut self, bit: usize, value: bool) -> nb::Result<(), Infallible> {
+        self.selection = self.selection & !(1 << bit) | usize::from(value) << bit;
+        Ok(())
+    }
+}
+impl Input<Host> for Inputs {
+    type Error = Infallible;
+    fn select0(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.select(0, value)
+    }
+    fn select1(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.select(1, value)
+    }
+    fn select2(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.select(2, value)
+    }
+    fn discard(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+    fn read(&mut self, buf: &mut [[u16; 2]]) -> nb::Result<usize, InputError<Self::Error>> {
+        let sample = Self::CHANNELS.map(|half| raw_value(half[self.selection]));
+        buf.fill(sample);
+        Ok(buf.len())
+    }
+}
+
+/// GPIOs that are digital inputs.
+pub const DIGITAL_AVAILABLE: u32 = 1 << 23 | 1 << 24;
+/// The levels of [`DIGITAL_AVAILABLE`], GP23 high and GP24 low.
+pub const DIGITAL_LEVELS: u32 = 1 << 23;
+
+pub struct Digital;
+impl DigitalInputs<Host> for Digital {
+    fn available(&self) -> u32 {
+        DIGITAL_AVAILABLE
+    }
+    fn levels(&self) -> u32 {
+        DIGITAL_LEVELS
+    }
+}
+
+/// Size of the staging area of the mock board's flash, enough for a few test images.
+pub const STAGING_SIZE: u32 = 64 * 1024;
+
+/// Flash that outlives reboots of the firmware: the nonvolatile data, the user data, the staging
+/// area and the firmware image, which is what a firmware update last copied there.
+#[derive(Clone)]
+pub struct Flash(
+    pub Arc<Mutex<[u8; 4096]>>,
+    pub Arc<Mutex<[u8; USER_DATA_SIZE]>>,
+    pub Arc<Mutex<Vec<u8>>>,
+    pub Arc<Mutex<Vec<u8>>>,
+);
+impl Default for Flash {
+    /// Flash of a fresh board.
+    fn default() -> Self {
+        Self(
+            Arc::new(Mutex::new(default_nonvolatile_data())),
+            Arc::new(Mutex::new([0xFF; USER_DATA_SIZE])),
+            Arc::new(Mutex::new(std::vec![0xFF; STAGING_SIZE as usize])),
+            Arc::new(Mutex::new(Vec::new())),
+        )
+    }
+}
+impl Flash {
+    /// Flash of a board that was configured with `config` before.
+    pub fn with_config(config: nvm::Config) -> Self {
+        let flash = Self::default();
+        *flash.0.lock().unwrap() = nvm::configured_nonvolatile_data(config);
+        flash
+    }
+}
+impl NonvolatileStorage<Host> for Flash {
+    type Error = Infallible;
+    fn read(&self) -> nb::Result<[u8; 4096], Self::Error> {
+        Ok(*self.0.lock().unwrap())
+    }
+    fn write(&self, data: &[u8; 4096]) -> nb::Result<(), Self::Error> {
+        *self.0.lock().unwrap() = *data;
+        Ok(())
+    }
+    fn read_user_data(&self) -> nb::Result<[u8; USER_DATA_SIZE], Self::Error> {
+        Ok(*self.1.lock().unwrap())
+    }
+    fn write_user_data(&self, data: &[u8; USER_DATA_SIZE]) -> nb::Result<(), Self::Error> {
+        *self.1.lock().unwrap() = *data;
+        Ok(())
+    }
+    fn staging_size(&self) -> u32 {
+        STAGING_SIZE
+    }
+    fn erase_staging(&self, offset: u32) -> nb::Result<(), Self::Error> {
+        let offset = offset as usize;
+        self.2.lock().unwrap()[offset..offset + FLASH_SECTOR_SIZE as usize].fill(0xFF);
+        Ok(())
+    }
+    fn write_staging(
+        &self,
+        offset: u32,
+        data: &[u8; FLASH_CHUNK_SIZE],
+    ) -> nb::Result<(), Self::Error> {
+        let offset = offset as usize;
+        // programming only clears bits, like on a real flash
+        for (byte, data) in self.2.lock().unwrap()[offset..].iter_mut().zip(data) {
+            *byte &= data;
+        }
+        Ok(())
+    }
+    fn read_staging(&self, offset: u32, buf: &mut [u8]) -> nb::Result<(), Self::Error> {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.2.lock().unwrap()[offset..offset + buf.len()]);
+        Ok(())
+    }
+    fn boot_staged(&self, len: u32) -> ! {
+        *self.3.lock().unwrap() = self.2.lock().unwrap()[..len as usize].to_vec();
+        panic::resume_unwind(Box::new(Reboot(RebootMode::Firmware)))
+    }
+}
+
+pub struct NoWatchdog;
+impl Watchdog<Host> for NoWatchdog {
+    fn feed(&self) {}
+}
+
+/// Unwinds out of the firmware to have it started again, see [`run`].
+pub struct Reboot(pub RebootMode);
+
+/// Unique ID of the board's chip.
+pub const UNIQUE_ID: u64 = 0x0123_4567_89ab_cdef;
+/// Revision of the mock board.
+pub const HARDWARE_REVISION: u16 = 3;
+
+pub struct HostSystem {
+    pub reset_cause: ResetCause,
+}
+impl DeviceId<Host> for HostSystem {
+    fn unique_id(&self) -> u64 {
+        UNIQUE_ID
+    }
+    fn hardware_revision(&self) -> u16 {
+        HARDWARE_REVISION
+    }
+}
+impl System<Host> for HostSystem {
+    fn reboot(&self, mode: RebootMode) -> ! {
+        // doesn't run the panic hook, as this isn't an error
+        std::panic::resume_unwind(Box::new(Reboot(mode)))
+    }
+    fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+}
+
+/// Runs the firmware on `link` until reading from it fails, e.g. because the other end went away.
+/// Every reboot starts the firmware again with the same flash, after telling `on_reboot` the mode
+/// it was asked for.
+pub fn run<L: Read<Host> + Write<Host>>(
+    link: &mut L,
+    flash: &Flash,
+    on_reboot: impl FnMut(RebootMode),
+) {
+    boot(flash, on_reboot, |main_loop, timer, nvm, system| {
+        let Err(_) = block_on(main_loop.main_loop(
+            link,
+            &mut NoPin,
+            timer,
+            &mut Outputs::default(),
+            &mut Inputs::default(),
+            &Digital,
+            nvm,
+            system,
+            &NoWatchdog,
+            &mut NoPin,
+        ));
+    })
+}
+
+/// Like [`run`], but as a repeater that passes requests on to the bus segment on `downstream`,
+/// waiting `timeout_us` for the devices there to respond.
+pub fn run_repeater<L: Read<Host> + Write<Host>>(
+    link: &mut L,
+    downstream: &mut L,
+    timeout_us: u32,
+    flash: &Flash,
+    on_reboot: impl FnMut(RebootMode),
+) {
+    boot(flash, on_reboot, |main_loop, timer, nvm, system| {
+        let Err(_) = block_on(main_loop.repeater_main_loop(
+            link,
+            &mut NoPin,
+            downstream,
+            &mut NoPin,
+            timeout_us,
+            timer,
+            &mut Outputs::default(),
+            &mut Inputs::default(),
+            &Digital,
+            nvm,
+            system,
+            &NoWatchdog,
+            &mut NoPin,
+        ));
+    })
+}
+
+/// Starts the firmware in `main` until it returns, and again after every reboot.
+fn boot(
+    flash: &Flash,
+    mut on_reboot: impl FnMut(RebootMode),
+    mut main: impl FnMut(&mut MainLoop<1, TICK_HZ>, &Clock, &nvm::Nvm<Flash, Host>, &HostSystem),
+) {
+    let mut reset_cause = ResetCause::PowerOn;
+    loop {
+        let boot = panic::catch_unwind(AssertUnwindSafe(|| {
+            let timer = Clock::new();
+            let Ok(nvm) = block_on(nvm::Nvm::new(flash.clone()));
+            let mut main_loop = MainLoop::new(&timer);
+            main(&mut main_loop, &timer, &nvm, &HostSystem { reset_cause });
+        }));
+        match boot {
+            Ok(()) => return,
+            Err(payload) => match payload.downcast::<Reboot>() {
+                Ok(reboot) => on_reboot(reboot.0),
+                Err(payload) => panic::resume_unwind(payload),
+            },
+        }
+        reset_cause = ResetCause::Other;
+    }
+}
@@ -1,40 +1,25 @@
-use core::{cell::Cell, convert::Infallible, marker::PhantomData, ops::Deref};
+use core::{cell::Cell, convert::Infallible, marker::PhantomData, mem::offset_of, ops::Deref};
 
+use defmt::warn;
 use pico_iox16_protocol::{
-    ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes, InputGetCalibrationsReq,
-    InputGetCalibrationsRes, InputGetThresholdsReq, InputGetThresholdsRes, InputSetCalibrationsReq,
-    InputSetCalibrationsRes, InputSetThresholdsReq, InputSetThresholdsRes,
+    AddressRange, CHECKSUM, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes,
+    FailsafeGetReq, FailsafeGetRes, FailsafeSetReq, FailsafeSetRes, ForwardingGetReq,
+    ForwardingGetRes, ForwardingSetReq, ForwardingSetRes,
+    FLASH_CHUNK_SIZE, InputGetCalibrationsReq, InputGetCalibrationsRes, InputGetThresholdsReq, InputGetThresholdsRes,
+    InputSetCalibrationsReq, InputSetCalibrationsRes, InputSetThresholdsReq, InputSetThresholdsRes,
+    MAX_FORWARDING_RANGES, OutputGetDefaultsReq, OutputGetDefaultsRes, OutputSetDefaultsReq, OutputSetDefaultsRes, PowerGetReq, PowerGetRes, PowerSetReq, PowerSetRes, ResetCause,
+    USER_DATA_SIZE, UserDataReadReq, UserDataReadRes, UserDataWriteReq, UserDataWriteRes,
 };
 use static_assertions::const_assert;
 use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes};
 
 use crate::{HandleMessage, nb_await};
 
-#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, Immutable, defmt::Format)]
-#[repr(C)]
-pub struct Config {
-    pub address: u16,
-    pub _padding: [u8; 2],
-    pub baudrate: u32,
-}
-impl From<pico_iox16_protocol::Config> for Config {
-    fn from(value: pico_iox16_protocol::Config) -> Self {
-        Self {
-            address: value.address.into(),
-            _padding: [0; 2],
-            baudrate: value.baudrate.into(),
-        }
-    }
-}
-impl From<Config> for pico_iox16_protocol::Config {
-    fn from(value: Config) -> Self {
-        Self {
-            address: value.address.into(),
-            _reserved: [0; 2],
-            baudrate: value.baudrate.into(),
-        }
-    }
-}
+pub use pico_iox16_protocol::settings::{
+    BAUDRATES, Config, DEFAULT_BAUDRATE, OutputDefaults, SCHEMA_VERSION, Settings,
+    UNCONFIGURED_ADDRESS,
+};
+
 impl<I: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
     HandleMessage for (&InputSetThresholdsReq, I, PhantomData<(NVM, Board)>)
 {
@@ -42,9 +27,13 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board:
     type Error = <NVM as NonvolatileStorage<Board>>::Error;
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (InputSetThresholdsReq(trips), storage, PhantomData) = self;
+        let data = storage.get();
         let new_data = NonvolatileData {
-            thresholds: trips.each_ref().map(|trip| (*trip).into()),
-            ..storage.get()
+            settings: Settings {
+                thresholds: trips.each_ref().map(|trip| (*trip).into()),
+                ..data.settings
+            },
+            ..data
         };
         storage.set(&new_data).await?;
         Ok(InputSetThresholdsRes)
@@ -60,6 +49,7 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
         Ok(InputGetThresholdsRes(
             storage
                 .get()
+                .settings
                 .thresholds
                 .each_ref()
                 .map(|trip| (*trip).into()),
@@ -67,54 +57,6 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
     }
 }
 
-#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, Immutable, defmt::Format)]
-#[repr(C)]
-pub(crate) struct Calibration {
-    /// The value to multiply the raw input by.
-    /// But stored XOR 0xFFFE so that the default is 1.
-    pub multiply: i16,
-    /// The value to divide by after multiplication.
-    /// But stored XOR 0xFFFE so that the default is 1.
-    pub divide: i16,
-    /// The value to add to the raw input after division.
-    /// But stored XOR 0xFFFF so that the default is 0.
-    pub add: i16,
-    /// The minimum value after addition.
-    /// But stored XOR 0xFFFF so that the default is 0.
-    pub min: i16,
-    /// The maximum value after addition.
-    /// Stored directly, so the default is 0xFFFF.
-    pub max: i16,
-}
-impl Calibration {
-    pub fn apply(&self, value: u16) -> i16 {
-        let value =
-            (value as i32 * (self.multiply) as i32) / (self.divide) as i32 + (self.add) as i32;
-        value.clamp((self.min) as i32, self.max as i32) as i16
-    }
-}
-impl From<pico_iox16_protocol::InputCalibration> for Calibration {
-    fn from(value: pico_iox16_protocol::InputCalibration) -> Self {
-        Self {
-            multiply: i16::from(value.multiply),
-            divide: i16::from(value.divide),
-            add: i16::from(value.add),
-            min: i16::from(value.min),
-            max: i16::from(value.max),
-        }
-    }
-}
-impl From<Calibration> for pico_iox16_protocol::InputCalibration {
-    fn from(value: Calibration) -> Self {
-        Self {
-            multiply: (value.multiply).into(),
-            divide: (value.divide).into(),
-            add: (value.add).into(),
-            min: (value.min).into(),
-            max: (value.max).into(),
-        }
-    }
-}
 impl<I: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
     HandleMessage for (&InputSetCalibrationsReq, I, PhantomData<(NVM, Board)>)
 {
@@ -122,9 +64,13 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board:
     type Error = <NVM as NonvolatileStorage<Board>>::Error;
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (InputSetCalibrationsReq(calibrations), storage, PhantomData) = self;
+        let data = storage.get();
         let new_data = NonvolatileData {
-            calibrations: calibrations.each_ref().map(|cal| (*cal).into()),
-            ..storage.get()
+            settings: Settings {
+                calibrations: calibrations.each_ref().map(|cal| (*cal).into()),
+                ..data.settings
+            },
+            ..data
         };
         storage.set(&new_data).await?;
         Ok(InputSetCalibrationsRes)
@@ -140,6 +86,7 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
         Ok(InputGetCalibrationsRes(
             storage
                 .get()
+                .settings
                 .calibrations
                 .each_ref()
                 .map(|cal| (*cal).into()),
@@ -147,108 +94,342 @@ impl<I: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
     }
 }
 
-#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, Immutable, defmt::Format)]
+/// Counters that need to survive resets to be of any use.
+#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
 #[repr(C)]
-pub(crate) struct Threshold {
-    /// The value above which the input is considered above threshold
-    pub threshold_high: i16,
-    /// The value below which the input is considered below threshold
-    pub threshold_low: i16,
-    /// The time duration for which the input must remain above or below the threshold to be considered debounced
-    pub debounce_time_us: u32,
-    /// The number of consecutive readings minus one above or below the threshold required for debouncing
-    pub debounce_count: u16,
-    #[doc(hidden)]
-    pub _padding: [u8; 2],
+pub(crate) struct Diagnostics {
+    /// Number of brown-outs, `u32::MAX` if never written since the firmware didn't have it
+    pub brownouts: u32,
 }
-impl From<pico_iox16_protocol::InputThreshold> for Threshold {
-    fn from(value: pico_iox16_protocol::InputThreshold) -> Self {
-        Self {
-            threshold_high: value.threshold_high.into(),
-            threshold_low: value.threshold_low.into(),
-            debounce_time_us: value.debounce_time_us.into(),
-            debounce_count: value.debounce_count.into(),
-            _padding: [0xFF; 2],
-        }
-    }
+
+/// Settings for running from a battery.
+#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
+#[repr(C)]
+pub(crate) struct Power {
+    /// Time between the starts of two sweeps over the inputs in ms, 0 to sample continuously,
+    /// `u32::MAX` if never written since the firmware didn't have it
+    pub sample_interval_ms: u32,
 }
-impl From<Threshold> for pico_iox16_protocol::InputThreshold {
-    fn from(value: Threshold) -> Self {
-        Self {
-            threshold_high: value.threshold_high.into(),
-            threshold_low: value.threshold_low.into(),
-            debounce_time_us: value.debounce_time_us.into(),
-            debounce_count: value.debounce_count.into(),
-        }
-    }
+
+/// The address ranges a repeater passes on, see [`pico_iox16_protocol::Forwarding`].
+#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
+#[repr(C)]
+pub(crate) struct Forwarding {
+    /// First and last address of each range, empty if the first is above the last
+    pub ranges: [[u16; 2]; MAX_FORWARDING_RANGES],
+}
+
+impl Forwarding {
+    const NONE: Self = Self {
+        ranges: [[u16::MAX, 0]; MAX_FORWARDING_RANGES],
+    };
+}
+
+/// The safe state of the outputs, see [`pico_iox16_protocol::Failsafe`].
+#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
+#[repr(C)]
+pub(crate) struct Failsafe {
+    /// Time without a valid request in ms, 0 if disabled
+    pub timeout_ms: u32,
+    pub duty_cycles: [u16; 16],
+}
+
+impl Failsafe {
+    const DISABLED: Self = Self {
+        timeout_ms: 0,
+        duty_cycles: [0; 16],
+    };
+}
+
+/// Version of the layout of [`NonvolatileData`], to be bumped along with [`SCHEMA_VERSION`] when
+/// fields change meaning or are added. [`NonvolatileData::from_flash`] migrates older layouts.
+const LAYOUT_VERSION: u16 = SCHEMA_VERSION;
+
+/// Marks data written by a firmware with [`LAYOUT_VERSION`] 5 or later, so that garbage left by
+/// an interrupted write has to match it on top of the version and the checksum.
+const MAGIC: u32 = u32::from_le_bytes(*b"IOXN");
+
+/// Detects data that was corrupted in flash, or written by a firmware with another layout.
+#[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
+#[repr(C)]
+pub(crate) struct Integrity {
+    /// [`LAYOUT_VERSION`] of the firmware that wrote the data, `u16::MAX` if never written
+    /// since the firmware didn't have it
+    pub layout_version: u16,
+    /// [`CHECKSUM`] over the bytes of the data before this struct, `u16::MAX` along with the
+    /// version
+    pub checksum: u16,
 }
 
 #[derive(Debug, Clone, Copy, IntoBytes, TryFromBytes, KnownLayout, Immutable, defmt::Format)]
 #[repr(C)]
 pub(crate) struct NonvolatileData {
-    pub config: Config,
-    pub calibrations: [Calibration; 16],
-    pub thresholds: [Threshold; 16],
+    pub settings: Settings,
+    pub diagnostics: Diagnostics,
+    pub power: Power,
+    /// Added in layout version 2
+    pub forwarding: Forwarding,
+    /// Added in layout version 3
+    pub failsafe: Failsafe,
+    /// Added in layout version 4
+    pub output_defaults: OutputDefaults,
+    /// [`MAGIC`], added in layout version 5. Must stay right before the integrity check.
+    pub magic: u32,
+    /// Must stay last, as it covers everything before it.
+    pub integrity: Integrity,
 }
 const_assert!(core::mem::size_of::<NonvolatileData>() <= 4096);
 
+impl NonvolatileData {
+    const DEFAULT: Self = Self {
+        settings: Settings::DEFAULT,
+        diagnostics: Diagnostics { brownouts: 0 },
+        power: Power {
+            sample_interval_ms: 0,
+        },
+        forwarding: Forwarding::NONE,
+        failsafe: Failsafe::DISABLED,
+        output_defaults: OutputDefaults::NONE,
+        magic: MAGIC,
+        integrity: Integrity {
+            layout_version: u16::MAX,
+            checksum: u16::MAX,
+        },
+    };
+    const CHECKED_LEN: usize = offset_of!(Self, integrity);
+    /// Layout version 1 ended with its integrity check where the forwarding ranges are now.
+    const CHECKED_LEN_V1: usize = offset_of!(Self, forwarding);
+    /// Layout version 2 ended with its integrity check where the failsafe is now.
+    const CHECKED_LEN_V2: usize = offset_of!(Self, failsafe);
+    /// Layout version 3 ended with its integrity check where the output defaults are now.
+    const CHECKED_LEN_V3: usize = offset_of!(Self, output_defaults);
+    /// Layout version 4 ended with its integrity check where the magic is now.
+    const CHECKED_LEN_V4: usize = offset_of!(Self, magic);
+
+    /// The data stored in `flash`, `None` if it fails the integrity check.
+    fn from_flash(flash: &[u8; 4096]) -> Option<Self> {
+        // copied out, as the buffer isn't necessarily aligned for it
+        let (data, _) = Self::try_read_from_prefix(flash).ok()?;
+        let checksum = CHECKSUM.checksum(&flash[..Self::CHECKED_LEN]);
+        let (v1, _) = Integrity::try_read_from_prefix(&flash[Self::CHECKED_LEN_V1..]).ok()?;
+        let (v2, _) = Integrity::try_read_from_prefix(&flash[Self::CHECKED_LEN_V2..]).ok()?;
+        let (v3, _) = Integrity::try_read_from_prefix(&flash[Self::CHECKED_LEN_V3..]).ok()?;
+        let (v4, _) = Integrity::try_read_from_prefix(&flash[Self::CHECKED_LEN_V4..]).ok()?;
+        match data.integrity {
+            Integrity {
+                layout_version: LAYOUT_VERSION,
+                checksum: stored,
+            } if stored == checksum && data.magic == MAGIC => Some(data),
+            // written by a firmware without the magic, which takes the place of its check
+            _ if v4.layout_version == 4
+                && v4.checksum == CHECKSUM.checksum(&flash[..Self::CHECKED_LEN_V4]) =>
+            {
+                Some(Self {
+                    magic: MAGIC,
+                    ..data
+                })
+            }
+            // written by a firmware without output defaults either
+            _ if v3.layout_version == 3
+                && v3.checksum == CHECKSUM.checksum(&flash[..Self::CHECKED_LEN_V3]) =>
+            {
+                Some(Self {
+                    output_defaults: OutputDefaults::NONE,
+                    ..data
+                })
+            }
+            // written by a firmware without the failsafe either
+            _ if v2.layout_version == 2
+                && v2.checksum == CHECKSUM.checksum(&flash[..Self::CHECKED_LEN_V2]) =>
+            {
+                Some(Self {
+                    failsafe: Failsafe::DISABLED,
+                    output_defaults: OutputDefaults::NONE,
+                    ..data
+                })
+            }
+            // written by a firmware without forwarding either
+            _ if v1.layout_version == 1
+                && v1.checksum == CHECKSUM.checksum(&flash[..Self::CHECKED_LEN_V1]) =>
+            {
+                Some(Self {
+                    forwarding: Forwarding::NONE,
+                    failsafe: Failsafe::DISABLED,
+                    output_defaults: OutputDefaults::NONE,
+                    ..data
+                })
+            }
+            // erased, e.g. by a write that was cut off before it programmed anything
+            _ if flash[..Self::CHECKED_LEN_V1].iter().all(|&byte| byte == 0xFF) => None,
+            // written by a firmware without the check, or without forwarding either
+            Integrity {
+                layout_version: u16::MAX,
+                checksum: u16::MAX,
+            } => Some(Self {
+                forwarding: Forwarding::NONE,
+                failsafe: Failsafe::DISABLED,
+                output_defaults: OutputDefaults::NONE,
+                ..data
+            }),
+            _ => None,
+        }
+    }
+
+    /// The flash contents to store the data, with the integrity check filled in.
+    const fn to_flash(self) -> [u8; 4096] {
+        const fn copy(data: &NonvolatileData, flash: &mut [u8; 4096]) {
+            let mut i = 0;
+            while i < core::mem::size_of::<NonvolatileData>() {
+                flash[i] = unsafe { core::ptr::from_ref(data).cast::<u8>().add(i).read() };
+                i += 1;
+            }
+        }
+        let data = Self {
+            magic: MAGIC,
+            ..self
+        };
+        let mut flash = [0xFF; 4096];
+        copy(&data, &mut flash);
+        let checked = flash.split_at(Self::CHECKED_LEN).0;
+        let sealed = Self {
+            integrity: Integrity {
+                layout_version: LAYOUT_VERSION,
+                checksum: CHECKSUM.checksum(checked),
+            },
+            ..data
+        };
+        copy(&sealed, &mut flash);
+        flash
+    }
+}
+
 pub trait NonvolatileStorage<Board: ?Sized> {
     type Error;
     fn read(&self) -> nb::Result<[u8; 4096], Self::Error>;
     fn write(&self, data: &[u8; 4096]) -> nb::Result<(), Self::Error>;
+    /// The host's user data, erased flash if it was never written. Stored apart from the rest,
+    /// so that the host writing it often doesn't wear out the configuration.
+    fn read_user_data(&self) -> nb::Result<[u8; USER_DATA_SIZE], Self::Error>;
+    fn write_user_data(&self, data: &[u8; USER_DATA_SIZE]) -> nb::Result<(), Self::Error>;
+    /// Size of the area a new firmware image is staged in, a multiple of
+    /// [`pico_iox16_protocol::FLASH_SECTOR_SIZE`]. 0 if the board can't update itself.
+    fn staging_size(&self) -> u32;
+    /// Erases the sector at `offset` of the staging area.
+    fn erase_staging(&self, offset: u32) -> nb::Result<(), Self::Error>;
+    /// Programs the chunk at `offset` of the staging area, which has to be erased.
+    fn write_staging(
+        &self,
+        offset: u32,
+        data: &[u8; FLASH_CHUNK_SIZE],
+    ) -> nb::Result<(), Self::Error>;
+    fn read_staging(&self, offset: u32, buf: &mut [u8]) -> nb::Result<(), Self::Error>;
+    /// Copies the first `len` bytes of the staging area over the running firmware and boots
+    /// the copy. The configuration and the user data stay.
+    fn boot_staged(&self, len: u32) -> !;
 }
 
 pub const fn default_nonvolatile_data() -> [u8; 4096] {
-    let default = NonvolatileData {
-        config: Config {
-            address: 0xFFFF,
-            baudrate: 1000000,
-            _padding: [0xFF; 2],
+    NonvolatileData::DEFAULT.to_flash()
+}
+
+/// The data in flash of a board that was configured with `config`, defaults otherwise.
+pub const fn configured_nonvolatile_data(config: Config) -> [u8; 4096] {
+    NonvolatileData {
+        settings: Settings {
+            config,
+            ..Settings::DEFAULT
         },
-        calibrations: [Calibration {
-            multiply: 1,
-            divide: 1,
-            add: 0,
-            min: i16::MIN,
-            max: i16::MAX,
-        }; 16],
-        thresholds: [Threshold {
-            threshold_high: i16::MAX,
-            threshold_low: i16::MIN,
-            debounce_time_us: 0,
-            debounce_count: 0,
-            _padding: [0xFF; 2],
-        }; 16],
-    };
-    let mut data = [0xFF; 4096];
-    let mut i = 0;
-    while i < core::mem::size_of::<NonvolatileData>() {
-        data[i] = unsafe { core::ptr::addr_of!(default).cast::<u8>().add(i).read() };
-        i += 1;
+        ..NonvolatileData::DEFAULT
     }
-    data
+    .to_flash()
 }
 
-pub struct Nvm<NVM, Board: ?Sized>(Cell<NonvolatileData>, NVM, PhantomData<Board>);
+/// The data in flash, cached, and whether it failed the integrity check at boot.
+pub struct Nvm<NVM, Board: ?Sized>(Cell<NonvolatileData>, NVM, Cell<bool>, PhantomData<Board>);
 impl<NVM, Board: ?Sized> Nvm<NVM, Board> {
     pub(crate) fn get(&self) -> NonvolatileData {
         self.0.get()
     }
+    /// The stored data was unusable and the defaults are used instead, until the next write.
+    pub(crate) fn corrupted(&self) -> bool {
+        self.2.get()
+    }
+    pub(crate) fn storage(&self) -> &NVM {
+        &self.1
+    }
     pub fn get_config(&self) -> Config {
-        self.get().config
+        self.get().settings.config
+    }
+    pub(crate) fn brownouts(&self) -> u32 {
+        match self.get().diagnostics.brownouts {
+            u32::MAX => 0,
+            brownouts => brownouts,
+        }
+    }
+    pub(crate) fn forwarding(&self) -> pico_iox16_protocol::Forwarding {
+        pico_iox16_protocol::Forwarding {
+            ranges: self
+                .get()
+                .forwarding
+                .ranges
+                .map(|[first, last]| AddressRange {
+                    first: first.into(),
+                    last: last.into(),
+                }),
+        }
+    }
+    pub(crate) fn failsafe(&self) -> pico_iox16_protocol::Failsafe {
+        let Failsafe {
+            timeout_ms,
+            duty_cycles,
+        } = self.get().failsafe;
+        pico_iox16_protocol::Failsafe {
+            timeout_ms: timeout_ms.into(),
+            duty_cycles: duty_cycles.map(Into::into),
+        }
+    }
+    /// The outputs to set at startup, `None` to leave them as the board starts them.
+    pub(crate) fn output_defaults(&self) -> Option<OutputSetDefaultsReq> {
+        self.get().output_defaults.groups().map(OutputSetDefaultsReq)
+    }
+    pub(crate) fn sample_interval_ms(&self) -> u32 {
+        match self.get().power.sample_interval_ms {
+            u32::MAX => 0,
+            interval => interval,
+        }
     }
 }
 impl<NVM: NonvolatileStorage<Board>, Board: ?Sized> Nvm<NVM, Board> {
     pub async fn new(nvm: NVM) -> Result<Self, NVM::Error> {
-        let data = nb_await!(nvm.read())?;
-        let data = NonvolatileData::try_ref_from_prefix(&data).unwrap().0;
-        Ok(Self(Cell::new(*data), nvm, PhantomData))
+        let flash = nb_await!(nvm.read())?;
+        let (data, corrupted) = match NonvolatileData::from_flash(&flash) {
+            Some(data) => (data, false),
+            None => {
+                warn!("Nonvolatile data is corrupted, running with the defaults");
+                (NonvolatileData::DEFAULT, true)
+            }
+        };
+        Ok(Self(
+            Cell::new(data),
+            nvm,
+            Cell::new(corrupted),
+            PhantomData,
+        ))
+    }
+    /// Counts the reset if it was a brown-out. Not while the data is corrupted, to leave it
+    /// for the host to see until it provisions the device again.
+    pub(crate) async fn record_reset(&self, cause: ResetCause) -> Result<(), NVM::Error> {
+        if cause != ResetCause::BrownOut || self.corrupted() {
+            return Ok(());
+        }
+        let mut data = self.get();
+        data.diagnostics.brownouts = self.brownouts().saturating_add(1).min(u32::MAX - 1);
+        self.set(&data).await
     }
     pub(crate) async fn set(&self, data: &NonvolatileData) -> Result<(), NVM::Error> {
         self.0.set(*data);
-        let mut buf = [0xFF; 4096];
-        data.write_to_prefix(&mut buf).unwrap();
-        nb_await!(self.1.write(&buf))?;
+        nb_await!(self.1.write(&data.to_flash()))?;
+        self.2.set(false);
         Ok(())
     }
 }
@@ -260,9 +441,13 @@ impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board:
     type Error = <NVM as NonvolatileStorage<Board>>::Error;
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (ConfigSetReq(config), storage, PhantomData) = self;
+        let data = storage.get();
         let new_data = NonvolatileData {
-            config: (*config).into(),
-            ..storage.get()
+            settings: Settings {
+                config: (*config).into(),
+                ..data.settings
+            },
+            ..data
         };
         storage.set(&new_data).await?;
         Ok(ConfigSetRes)
@@ -275,6 +460,156 @@ impl<O: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
     type Error = Infallible;
     async fn handle(self) -> Result<Self::Response, Self::Error> {
         let (ConfigGetReq, storage, PhantomData) = self;
-        Ok(ConfigGetRes(storage.get().config.into()))
+        Ok(ConfigGetRes(storage.get_config().into()))
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&PowerSetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = PowerSetRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (PowerSetReq(power), storage, PhantomData) = self;
+        let new_data = NonvolatileData {
+            power: Power {
+                sample_interval_ms: power.sample_interval_ms.get().min(u32::MAX - 1),
+            },
+            ..storage.get()
+        };
+        storage.set(&new_data).await?;
+        Ok(PowerSetRes)
+    }
+}
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
+    for (&PowerGetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = PowerGetRes;
+    type Error = Infallible;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (PowerGetReq, storage, PhantomData) = self;
+        Ok(PowerGetRes(pico_iox16_protocol::Power {
+            sample_interval_ms: storage.sample_interval_ms().into(),
+        }))
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&ForwardingSetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = ForwardingSetRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (ForwardingSetReq(forwarding), storage, PhantomData) = self;
+        let new_data = NonvolatileData {
+            forwarding: Forwarding {
+                ranges: forwarding
+                    .ranges
+                    .map(|range| [range.first.get(), range.last.get()]),
+            },
+            ..storage.get()
+        };
+        storage.set(&new_data).await?;
+        Ok(ForwardingSetRes)
+    }
+}
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
+    for (&ForwardingGetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = ForwardingGetRes;
+    type Error = Infallible;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (ForwardingGetReq, storage, PhantomData) = self;
+        Ok(ForwardingGetRes(storage.forwarding()))
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&FailsafeSetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = FailsafeSetRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (FailsafeSetReq(failsafe), storage, PhantomData) = self;
+        let new_data = NonvolatileData {
+            failsafe: Failsafe {
+                timeout_ms: failsafe.timeout_ms.get(),
+                duty_cycles: failsafe.duty_cycles.map(|duty_cycle| duty_cycle.get()),
+            },
+            ..storage.get()
+        };
+        storage.set(&new_data).await?;
+        Ok(FailsafeSetRes)
+    }
+}
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
+    for (&FailsafeGetReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = FailsafeGetRes;
+    type Error = Infallible;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (FailsafeGetReq, storage, PhantomData) = self;
+        Ok(FailsafeGetRes(storage.failsafe()))
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&OutputSetDefaultsReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = OutputSetDefaultsRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (OutputSetDefaultsReq(groups), storage, PhantomData) = self;
+        let new_data = NonvolatileData {
+            output_defaults: (*groups).into(),
+            ..storage.get()
+        };
+        storage.set(&new_data).await?;
+        Ok(OutputSetDefaultsRes)
+    }
+}
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM, Board: ?Sized> HandleMessage
+    for (&OutputGetDefaultsReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = OutputGetDefaultsRes;
+    type Error = Infallible;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (OutputGetDefaultsReq, storage, PhantomData) = self;
+        Ok(storage.get().output_defaults.into())
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&UserDataReadReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = UserDataReadRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (UserDataReadReq(span), storage, PhantomData) = self;
+        let user_data = nb_await!(storage.1.read_user_data())?;
+        let range = span.clamped();
+        let mut data = [0; USER_DATA_SIZE];
+        data[..range.len()].copy_from_slice(&user_data[range.clone()]);
+        Ok(UserDataReadRes {
+            span: range.into(),
+            data,
+        })
+    }
+}
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&UserDataWriteReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = UserDataWriteRes;
+    type Error = <NVM as NonvolatileStorage<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (UserDataWriteReq { span, data }, storage, PhantomData) = self;
+        let mut user_data = nb_await!(storage.1.read_user_data())?;
+        let range = span.clamped();
+        // spares the flash a write that changes nothing, e.g. a retried request
+        if user_data[range.clone()] != data[..range.len()] {
+            user_data[range.clone()].copy_from_slice(&data[..range.len()]);
+            nb_await!(storage.1.write_user_data(&user_data))?;
+        }
+        Ok(UserDataWriteRes(range.into()))
     }
 }
@@ -3,7 +3,10 @@ use core::{
     ops::{Deref, DerefMut},
 };
 
-use pico_iox16_protocol::{OutputGetReq, OutputGetRes, OutputGroup, OutputSetReq, OutputSetRes};
+use pico_iox16_protocol::{
+    OutputGetReq, OutputGetRes, OutputGroup, OutputSetMaskedReq, OutputSetMaskedRes, OutputSetReq,
+    OutputSetRes,
+};
 use rounded_div::RoundedDiv as _;
 
 use crate::HandleMessage;
@@ -56,6 +59,36 @@ pub trait Output<Board: ?Sized> {
     type Pwm7: Pwm<Board, Error = Self::Error>;
     fn pwm7(&self) -> &Self::Pwm7;
     fn pwm7_mut(&mut self) -> &mut Self::Pwm7;
+    /// Restarts the periods of all groups at once, so that groups of the same frequency are in
+    /// phase. Called after a frequency changed, which leaves the groups at arbitrary phases.
+    fn synchronize(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Sets the duty cycles of `group` selected in `channels`, for channel A and B, scaled from
+/// 0..=0x8000 to the range of the channel.
+fn set_duty_cycles<P: Pwm<Board>, Board: ?Sized>(
+    pwm: &mut P,
+    group: &OutputGroup,
+    channels: [bool; 2],
+) -> Result<(), P::Error> {
+    fn scaled<C: PwmChannel<Board>, Board: ?Sized>(
+        channel: &C,
+        duty_cycle: u16,
+    ) -> Result<u16, C::Error> {
+        let duty_cycle = duty_cycle.clamp(0, 0x8000);
+        Ok((u32::from(duty_cycle) * 0x8000).rounded_div(channel.max_duty_cycle()? as u32) as u16)
+    }
+    if channels[0] {
+        let duty_cycle = scaled(pwm.channel_a(), group.duty_cycle[0].get())?;
+        pwm.channel_a_mut().set_duty_cycle(duty_cycle)?;
+    }
+    if channels[1] {
+        let duty_cycle = scaled(pwm.channel_b(), group.duty_cycle[1].get())?;
+        pwm.channel_b_mut().set_duty_cycle(duty_cycle)?;
+    }
+    Ok(())
 }
 
 impl<O: DerefMut<Target: Output<Board>>, Board: ?Sized> HandleMessage
@@ -64,38 +97,71 @@ impl<O: DerefMut<Target: Output<Board>>, Board: ?Sized> HandleMessage
     type Response = OutputSetRes;
     type Error = <O::Target as Output<Board>>::Error;
     async fn handle(self) -> Result<Self::Response, Self::Error> {
+        /// Returns whether the frequency changed.
         fn handle_group<P: Pwm<Board>, Board: ?Sized>(
             pwm: &mut P,
             group: &OutputGroup,
-        ) -> Result<(), P::Error> {
+        ) -> Result<bool, P::Error> {
             let frequency = group.frequency.get().clamp(10, 50_000);
+            let previous = pwm.get_frequency()?;
             pwm.set_frequency(frequency)?;
-            let duty_cycle_a = group.duty_cycle[0].get().clamp(0, 0x8000);
-            let duty_cycle_a = (u32::from(duty_cycle_a) * 0x8000)
-                .rounded_div(pwm.channel_a().max_duty_cycle()? as u32)
-                as u16;
-            let duty_cycle_b = group.duty_cycle[1].get().clamp(0, 0x8000);
-            let duty_cycle_b = (u32::from(duty_cycle_b) * 0x8000)
-                .rounded_div(pwm.channel_b().max_duty_cycle()? as u32)
-                as u16;
-            pwm.channel_a_mut().set_duty_cycle(duty_cycle_a)?;
-            pwm.channel_b_mut().set_duty_cycle(duty_cycle_b)?;
-            Ok(())
+            let changed = pwm.get_frequency()? != previous;
+            set_duty_cycles(pwm, group, [true; 2])?;
+            Ok(changed)
         }
 
         let (req, mut output, _) = self;
-        handle_group(output.pwm0_mut(), &req.0[0])?;
-        handle_group(output.pwm1_mut(), &req.0[1])?;
-        handle_group(output.pwm2_mut(), &req.0[2])?;
-        handle_group(output.pwm3_mut(), &req.0[3])?;
-        handle_group(output.pwm4_mut(), &req.0[4])?;
-        handle_group(output.pwm5_mut(), &req.0[5])?;
-        handle_group(output.pwm6_mut(), &req.0[6])?;
-        handle_group(output.pwm7_mut(), &req.0[7])?;
+        let changed = [
+            handle_group(output.pwm0_mut(), &req.0[0])?,
+            handle_group(output.pwm1_mut(), &req.0[1])?,
+            handle_group(output.pwm2_mut(), &req.0[2])?,
+            handle_group(output.pwm3_mut(), &req.0[3])?,
+            handle_group(output.pwm4_mut(), &req.0[4])?,
+            handle_group(output.pwm5_mut(), &req.0[5])?,
+            handle_group(output.pwm6_mut(), &req.0[6])?,
+            handle_group(output.pwm7_mut(), &req.0[7])?,
+        ];
+        // restarting all periods cuts one short, so only when they drifted apart anyway
+        if changed.contains(&true) {
+            output.synchronize()?;
+        }
         Ok(OutputSetRes)
     }
 }
 
+impl<O: DerefMut<Target: Output<Board>>, Board: ?Sized> HandleMessage
+    for (&OutputSetMaskedReq, O, PhantomData<Board>)
+{
+    type Response = OutputSetMaskedRes;
+    type Error = <O::Target as Output<Board>>::Error;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        fn handle_group<P: Pwm<Board>, Board: ?Sized>(
+            pwm: &mut P,
+            req: &OutputSetMaskedReq,
+            index: usize,
+        ) -> Result<(), P::Error> {
+            let group = OutputGroup {
+                duty_cycle: [req.duty_cycles[2 * index], req.duty_cycles[2 * index + 1]],
+                frequency: 0.into(),
+            };
+            let mask = req.mask.get() >> (2 * index);
+            set_duty_cycles(pwm, &group, [mask & 1 != 0, mask & 2 != 0])
+        }
+
+        let (req, mut output, _) = self;
+        // the frequencies stay, so the groups stay in phase
+        handle_group(output.pwm0_mut(), req, 0)?;
+        handle_group(output.pwm1_mut(), req, 1)?;
+        handle_group(output.pwm2_mut(), req, 2)?;
+        handle_group(output.pwm3_mut(), req, 3)?;
+        handle_group(output.pwm4_mut(), req, 4)?;
+        handle_group(output.pwm5_mut(), req, 5)?;
+        handle_group(output.pwm6_mut(), req, 6)?;
+        handle_group(output.pwm7_mut(), req, 7)?;
+        Ok(OutputSetMaskedRes)
+    }
+}
+
 impl<O: Deref<Target: Output<Board>>, Board: ?Sized> HandleMessage
     for (&OutputGetReq, O, PhantomData<Board>)
 {
@@ -0,0 +1,89 @@
+//! Keeping what a panic said across the reboot, so that the next boot can report what crashed.
+//!
+//! The board's panic handler formats the `PanicInfo` into a [`PanicRecord`] and hands it to its
+//! [`PanicStorage`]. The next boot logs it with [`report`] and clears the storage for the next one.
+
+use core::{fmt::Write as _, panic::PanicInfo};
+
+use defmt::error;
+use static_assertions::const_assert_eq;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+use crate::nb_await;
+
+/// Marks a written record, as opposed to erased flash or a record from before a power loss.
+const MAGIC: u32 = u32::from_le_bytes(*b"PNC1");
+/// Room for the message, so that a record fills a flash page.
+pub const MESSAGE_LEN: usize = 248;
+
+/// The message of a panic, as the `Display` of `PanicInfo` gives it, truncated to
+/// [`MESSAGE_LEN`] bytes.
+#[derive(Clone, FromBytes, IntoBytes, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct PanicRecord {
+    magic: u32,
+    len: u16,
+    _reserved: u16,
+    message: [u8; MESSAGE_LEN],
+}
+const_assert_eq!(size_of::<PanicRecord>(), 256);
+
+impl PanicRecord {
+    pub fn new(info: &PanicInfo) -> Self {
+        let mut record = Self {
+            magic: MAGIC,
+            len: 0,
+            _reserved: 0,
+            message: [0; MESSAGE_LEN],
+        };
+        // the writer only ever truncates
+        let _ = write!(record, "{info}");
+        record
+    }
+
+    /// The message, unless the bytes aren't a record.
+    pub fn message(&self) -> Option<&str> {
+        if self.magic != MAGIC {
+            return None;
+        }
+        let message = self.message.get(..usize::from(self.len))?;
+        core::str::from_utf8(message).ok()
+    }
+}
+impl core::fmt::Write for PanicRecord {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = usize::from(self.len);
+        let mut count = s.len().min(MESSAGE_LEN - len);
+        // cut at a character, so that the message stays valid UTF-8
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        self.message[len..len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count as u16;
+        Ok(())
+    }
+}
+
+/// Where a board keeps the record of a panic until the next boot.
+pub trait PanicStorage<Board: ?Sized> {
+    type Error;
+    /// Stores the record, unless the last one wasn't cleared yet. Called by the panic handler,
+    /// so this can neither panic nor rely on interrupts.
+    fn store(&self, record: &PanicRecord);
+    /// The stored record, if there is one.
+    fn load(&self) -> Option<PanicRecord>;
+    fn clear(&self) -> nb::Result<(), Self::Error>;
+}
+
+/// Logs the panic that ended the last boot, if any, and clears it. Returns whether there was one.
+pub async fn report<Board: ?Sized, S: PanicStorage<Board>>(storage: &S) -> Result<bool, S::Error> {
+    let Some(record) = storage.load() else {
+        return Ok(false);
+    };
+    match record.message() {
+        Some(message) => error!("The last boot panicked: {}", message),
+        None => error!("The last boot panicked, but the record is unreadable"),
+    }
+    nb_await!(storage.clear())?;
+    Ok(true)
+}
@@ -0,0 +1,200 @@
+//! Forwarding of requests to a second bus segment behind the device, so that a daisy chain can
+//! grow beyond the length of one segment or cross an isolation boundary without a gateway.
+
+use core::{cell::Cell, ops::Sub, pin::pin};
+
+use embedded_hal::digital::OutputPin;
+use fugit::{Duration, Instant};
+use futures::future::{Either, select};
+use pico_iox16_protocol::{
+    AddressRange, CAPABILITY_REPEATER, ERROR_FLAG, FRAGMENT_FLAG, Footer, FragmentHeader, Header,
+    MAX_FRAME_SIZE, Transport, next_message,
+};
+use zerocopy::TryFromBytes as _;
+
+use crate::{
+    Stage,
+    nvm::Nvm,
+    runtime::{Elapsed as _, Read, Timer, WaitFor as _, Write},
+    transport::{SerialError, SerialTransport},
+};
+
+/// How soon after sending a frame upstream it is read back if it is an echo. The echo is there as
+/// soon as the frame is sent, while a master needs longer to receive the frame and send the next
+/// request, even an identical `Check`.
+const ECHO_WINDOW_US: u64 = 200;
+
+/// The master end of the downstream port, a [`Stage`] that passes on the requests to the
+/// addresses set with `ForwardingSet` and sends the responses back upstream. Broadcasts are
+/// passed on as soon as any range is set.
+///
+/// A frame read upstream right after the repeater sent the same one there is its own, e.g. from
+/// a transceiver that hears itself or a segment wired back onto itself, and not passed on again,
+/// so that it can't circle forever. Neither is a request to the device's own address.
+pub(crate) struct Downstream<'a, Board: ?Sized, IO, S, T, NVM, const NOM: u32, const DENOM: u32> {
+    /// Takes fragments of any size
+    transport: SerialTransport<'a, Board, IO, S, T, NOM, DENOM, MAX_FRAME_SIZE>,
+    timer: &'a T,
+    nvm: &'a Nvm<NVM, Board>,
+    /// The device's own address, which it answers itself
+    address: u16,
+    /// How long to wait for each frame of a response
+    timeout: Duration<u64, NOM, DENOM>,
+    /// Header and checksum of the last frame sent upstream, and when it was done in microseconds
+    /// of the upstream transport's clock
+    relayed: Option<(Header, Footer, u64)>,
+    frame: [u8; MAX_FRAME_SIZE],
+}
+
+impl<'a, Board, IO, S, T, NVM, const NOM: u32, const DENOM: u32>
+    Downstream<'a, Board, IO, S, T, NVM, NOM, DENOM>
+where
+    Board: ?Sized,
+    IO: Read<Board> + Write<Board>,
+    S: OutputPin,
+    T: Timer<Board, u64, NOM, DENOM>,
+    Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+{
+    /// `timeout_us` is how long a downstream device takes to start its response, and at most
+    /// between the fragments of a response. The time on the wire is added according to the
+    /// baudrate.
+    pub(crate) fn new(
+        io: &'a mut IO,
+        io_send: &'a mut S,
+        timer: &'a T,
+        progress: &'a Cell<u32>,
+        nvm: &'a Nvm<NVM, Board>,
+        timeout_us: u32,
+    ) -> Self {
+        let config = nvm.get_config();
+        // start bit, 8 data bits, parity bit and two stop bits at most
+        let wire_us =
+            MAX_FRAME_SIZE as u64 * 12 * 1_000_000 / u64::from(config.effective_baudrate().max(1));
+        Self {
+            transport: SerialTransport::new(io, io_send, timer, progress),
+            timer,
+            nvm,
+            address: config.address,
+            timeout: Duration::<u64, NOM, DENOM>::micros(u64::from(timeout_us) + wire_us),
+            relayed: None,
+            frame: [0; MAX_FRAME_SIZE],
+        }
+    }
+
+    /// Whether the frame read upstream at `at_us` is the last one sent there coming back.
+    fn is_echo(&self, header: &Header, footer: &Footer, at_us: u64) -> bool {
+        self.relayed
+            .is_some_and(|(relayed_header, relayed_footer, sent_us)| {
+                (relayed_header, relayed_footer) == (*header, *footer)
+                    && at_us <= sent_us + ECHO_WINDOW_US
+            })
+    }
+
+    /// Sends the frames of the response to the request to `address` with `command` and
+    /// `sequence` upstream as they arrive, until the last one or a timeout. The master times out
+    /// on a missing response as well.
+    async fn relay<U: Transport<Error = SerialError<IO, S, Board>>>(
+        &mut self,
+        upstream: &mut U,
+        address: u16,
+        command: u16,
+        sequence: u8,
+    ) -> Result<(), SerialError<IO, S, Board>> {
+        let mut start = self.timer.now();
+        loop {
+            let received = {
+                let receive = pin!(self.transport.receive(&mut self.frame));
+                let Some(remaining) = self.timeout.checked_sub(self.timer.elapsed(start)) else {
+                    return Ok(());
+                };
+                match select(receive, pin!(self.timer.wait_for(remaining))).await {
+                    Either::Left((received, _)) => received?,
+                    Either::Right(_) => return Ok(()),
+                }
+            };
+            let response = &self.frame[..received.len];
+            let Some((header, payload)) = next_message(response).0 else {
+                continue;
+            };
+            // fragments of the response, or the error response to the request
+            let response_command = header.command.get() & !(FRAGMENT_FLAG | ERROR_FLAG);
+            if header.address.get() != address
+                || response_command != command & !FRAGMENT_FLAG
+                || header.sequence != sequence
+            {
+                continue;
+            }
+            upstream.send(response).await?;
+            let (_, footer) = Footer::try_read_from_suffix(response).unwrap();
+            self.relayed = Some((*header, footer, upstream.now_us()));
+            match fragment(header.command.get(), payload) {
+                Some(fragment) if fragment.index.get() + 1 < fragment.total.get() => {
+                    start = self.timer.now();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<Board, IO, S, T, NVM, const NOM: u32, const DENOM: u32> Stage<SerialError<IO, S, Board>>
+    for Downstream<'_, Board, IO, S, T, NVM, NOM, DENOM>
+where
+    Board: ?Sized,
+    IO: Read<Board> + Write<Board>,
+    S: OutputPin,
+    T: Timer<Board, u64, NOM, DENOM>,
+    Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+{
+    async fn handle<U: Transport<Error = SerialError<IO, S, Board>>>(
+        &mut self,
+        upstream: &mut U,
+        frame: &[u8],
+        at_us: u64,
+    ) -> Result<bool, SerialError<IO, S, Board>> {
+        let Some((header, payload)) = next_message(frame).0 else {
+            return Ok(false);
+        };
+        let (address, command, sequence) =
+            (header.address.get(), header.command.get(), header.sequence);
+        let forwarding = self.nvm.forwarding();
+        if header.is_broadcast() {
+            // meant for the devices behind any of the ranges, none of which responds
+            if forwarding.ranges.iter().all(AddressRange::is_empty) {
+                return Ok(false);
+            }
+            self.transport.send(frame).await?;
+            return Ok(true);
+        }
+        if address == self.address || !forwarding.forwards(address) {
+            return Ok(false);
+        }
+        let (_, footer) = Footer::try_read_from_suffix(frame).unwrap();
+        if self.is_echo(header, &footer, at_us) {
+            return Ok(true);
+        }
+        self.transport.send(frame).await?;
+        // the device only responds to the last fragment of a request
+        if let Some(fragment) = fragment(command, payload)
+            && fragment.index.get() + 1 < fragment.total.get()
+        {
+            return Ok(true);
+        }
+        self.relay(upstream, address, command, sequence).await?;
+        Ok(true)
+    }
+
+    fn capabilities(&self) -> u32 {
+        CAPABILITY_REPEATER
+    }
+}
+
+/// The fragment header of a message, if it is a fragment.
+fn fragment(command: u16, payload: &[u8]) -> Option<&FragmentHeader> {
+    if command & FRAGMENT_FLAG == 0 {
+        return None;
+    }
+    FragmentHeader::try_ref_from_prefix(payload)
+        .ok()
+        .map(|(header, _)| header)
+}
@@ -1,15 +1,24 @@
 use core::{
     ops::{Add, Sub},
     pin::pin,
-    task::{Context, Waker},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, RawWaker, RawWakerVTable, Waker},
 };
 
 use fugit::{Duration, Instant};
+use pico_iox16_protocol::{RebootMode, ResetCause};
 
 /// Timer counter abstraction
 pub trait Timer<Board: ?Sized, T, const NOM: u32, const DENOM: u32> {
     /// Returns the current counter
     fn now(&self) -> Instant<T, NOM, DENOM>;
+    /// Arranges for the executor to be woken up at the given instant, e.g. by a timer interrupt.
+    /// Returns `false` if the timer can't do that, in which case waiting keeps the executor
+    /// spinning.
+    fn wake_at(&self, _at: Instant<T, NOM, DENOM>) -> bool {
+        false
+    }
 }
 /// Convenience trait for calculating elapsed time since an instant
 pub trait Elapsed<Board: ?Sized, T, const NOM: u32, const DENOM: u32>:
@@ -42,6 +51,16 @@ pub trait Read<Board: ?Sized> {
     /// Reads bytes into `buf`, returning the number of bytes read. If no data is available, returns `nb::Error::WouldBlock`. 
     /// If an error occurs, returns `nb::Error::Other`.
     fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>>;
+    /// Whether a received byte wakes the executor, e.g. by an interrupt, so that the main loop
+    /// can sleep instead of polling while it waits for a request in low-power mode.
+    fn wakes_on_receive(&self) -> bool {
+        false
+    }
+    /// Switches the port to the baudrate, parity and stop bits of `config`. Returns `false` if
+    /// the port has no line settings, e.g. USB.
+    fn set_line(&mut self, _config: &crate::nvm::Config) -> bool {
+        false
+    }
 }
 
 // IO write abstraction
@@ -55,41 +74,97 @@ pub trait Write<Board: ?Sized> {
     fn flush(&mut self) -> nb::Result<(), Self::Error>;
 }
 
+struct YieldNow {
+    yielded: bool,
+    /// Whether to ask the executor to poll again right away
+    wake: bool,
+}
+impl core::future::Future for YieldNow {
+    type Output = ();
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        if self.yielded {
+            core::task::Poll::Ready(())
+        } else {
+            self.yielded = true;
+            if self.wake {
+                cx.waker().wake_by_ref();
+            }
+            core::task::Poll::Pending
+        }
+    }
+}
+
 /// Yield to the executor, allowing other tasks to run.
 /// 
 /// Since we are using [`nb`] for async IO, we have to make sure to call this function
 /// at least once in every iteration of long-running (usually infinite) loops.
 pub fn yield_now() -> impl core::future::Future<Output = ()> + Send + Sync {
-    struct YieldNow {
-        yielded: bool,
+    YieldNow {
+        yielded: false,
+        wake: true,
     }
-    impl core::future::Future for YieldNow {
-        type Output = ();
-        fn poll(
-            mut self: core::pin::Pin<&mut Self>,
-            _cx: &mut Context<'_>,
-        ) -> core::task::Poll<Self::Output> {
-            if self.yielded {
-                core::task::Poll::Ready(())
-            } else {
-                self.yielded = true;
-                core::task::Poll::Pending
-            }
-        }
+}
+
+/// Yield to the executor, allowing it to go idle until something else wakes it up.
+pub(crate) fn sleep() -> impl core::future::Future<Output = ()> + Send + Sync {
+    YieldNow {
+        yielded: false,
+        wake: false,
     }
-    YieldNow { yielded: false }
+}
+
+/// Set by the waker of [`block_on_with_idle`], there is only ever one executor running.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+/// Set by the main loop while it is in low-power mode.
+static LOW_POWER: AtomicBool = AtomicBool::new(false);
+
+/// Whether the main loop is in low-power mode, i.e. a sample interval is set and all outputs are
+/// idle. The `idle` function of [`block_on_with_idle`] can sleep deeper then, since only the
+/// timer and received bytes need to wake the board.
+pub fn low_power() -> bool {
+    LOW_POWER.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_low_power(low_power: bool) {
+    LOW_POWER.store(low_power, Ordering::Relaxed);
+}
+
+fn waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(ptr::null(), &VTABLE),
+        |_| WOKEN.store(true, Ordering::Relaxed),
+        |_| WOKEN.store(true, Ordering::Relaxed),
+        |_| {},
+    );
+    // SAFETY: the vtable functions don't use the data pointer
+    unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) }
 }
 
 /// Extremely simple single-threaded executor that runs a single future to completion.
 /// This is used to run the main loop of the firmware.
 pub fn block_on<F: core::future::Future>(f: F) -> F::Output {
-    let waker = Waker::noop();
-    let mut ctx = Context::from_waker(waker);
+    block_on_with_idle(f, || {})
+}
+
+/// Like [`block_on`], but calls `idle` whenever no task asked to be polled again right away,
+/// i.e. all of them are waiting for a wake-up from [`Timer::wake_at`]. Any interrupt has to
+/// end `idle`, as the executor doesn't know what the tasks are waiting for.
+pub fn block_on_with_idle<F: core::future::Future>(f: F, mut idle: impl FnMut()) -> F::Output {
+    let waker = waker();
+    let mut ctx = Context::from_waker(&waker);
     let mut f = pin!(f);
     loop {
+        WOKEN.store(false, Ordering::Relaxed);
         match f.as_mut().poll(&mut ctx) {
             core::task::Poll::Ready(v) => return v,
-            core::task::Poll::Pending => {}
+            core::task::Poll::Pending => {
+                if !WOKEN.load(Ordering::Relaxed) {
+                    idle();
+                }
+            }
         }
     }
 }
@@ -104,14 +179,18 @@ pub trait WaitUntil<Board: ?Sized, T, const NOM: u32, const DENOM: u32>:
 impl<Board: ?Sized, T, const NOM: u32, const DENOM: u32, U> WaitUntil<Board, T, NOM, DENOM> for U
 where
     U: Timer<Board, T, NOM, DENOM>,
-    Instant<T, NOM, DENOM>: PartialOrd,
+    Instant<T, NOM, DENOM>: PartialOrd + Copy,
 {
     async fn wait_until(&self, until: Instant<T, NOM, DENOM>) {
         loop {
             if self.now() >= until {
                 break;
             }
-            yield_now().await;
+            if self.wake_at(until) {
+                sleep().await;
+            } else {
+                yield_now().await;
+            }
         }
     }
 }
@@ -151,6 +230,23 @@ macro_rules! nb_await {
 }
 pub use nb_await;
 
-pub trait System<Board: ?Sized>: Sized {
-    fn reboot(&self) -> !;
+/// Hardware watchdog that resets the board unless it is fed regularly.
+pub trait Watchdog<Board: ?Sized> {
+    /// Restarts the countdown of the watchdog.
+    fn feed(&self);
+}
+
+/// Identification of the board, which the board crate reads at boot.
+pub trait DeviceId<Board: ?Sized> {
+    /// Unique ID of the chip, which tells otherwise identical boards apart before they are
+    /// addressed.
+    fn unique_id(&self) -> u64;
+    /// Revision of the board the firmware runs on, 0 if unknown.
+    fn hardware_revision(&self) -> u16;
+}
+
+pub trait System<Board: ?Sized>: DeviceId<Board> + Sized {
+    fn reboot(&self, mode: RebootMode) -> !;
+    /// Why the board was last reset.
+    fn reset_cause(&self) -> ResetCause;
 }
\ No newline at end of file
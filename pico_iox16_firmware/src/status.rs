@@ -0,0 +1,104 @@
+//! Blink patterns of the status LED, which tell what a board is up to without a laptop.
+//!
+//! The patterns repeat every two seconds. Bus activity additionally flickers the LED, and a
+//! panic blinks it at 5 Hz from the board's panic handler.
+
+use core::cell::Cell;
+
+use embedded_hal::digital::OutputPin;
+use fugit::Duration;
+
+use crate::runtime::{Timer, WaitUntil as _};
+
+/// Length of a slot of a blink pattern.
+const SLOT_MS: u32 = 125;
+/// How long the LED flips for a request.
+const FLICKER_MS: u32 = 30;
+
+/// Conditions with their own blink pattern, from the most important one down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Status {
+    /// The stored data didn't make sense, so the board runs with the defaults.
+    NvmCorrupt,
+    /// The host went quiet and the outputs are in their safe state.
+    Failsafe,
+    /// The board still has the address of a fresh device.
+    Unconfigured,
+    /// Everything is fine.
+    Running,
+}
+impl Status {
+    /// Whether the LED is on in each of the 16 slots of the period, starting with the LSB.
+    fn pattern(self) -> u16 {
+        match self {
+            // three long blinks
+            Status::NvmCorrupt => 0b0000_0111_0111_0111,
+            // three short blinks
+            Status::Failsafe => 0b0000_0000_0001_0101,
+            // two short blinks
+            Status::Unconfigured => 0b0000_0000_0000_0101,
+            // 1 Hz
+            Status::Running => 0b0000_1111_0000_1111,
+        }
+    }
+}
+
+/// What the status LED shows, updated by the loops and played back by [`StatusLed::run`].
+#[derive(Default)]
+pub struct StatusLed {
+    activity: Cell<bool>,
+    nvm_corrupt: Cell<bool>,
+    failsafe: Cell<bool>,
+    unconfigured: Cell<bool>,
+}
+impl StatusLed {
+    /// Flickers the LED at the start of the next slot.
+    pub fn activity(&self) {
+        self.activity.set(true);
+    }
+    pub fn set_nvm_corrupt(&self, value: bool) {
+        self.nvm_corrupt.set(value);
+    }
+    pub fn set_failsafe(&self, value: bool) {
+        self.failsafe.set(value);
+    }
+    pub fn set_unconfigured(&self, value: bool) {
+        self.unconfigured.set(value);
+    }
+
+    pub fn status(&self) -> Status {
+        if self.nvm_corrupt.get() {
+            Status::NvmCorrupt
+        } else if self.failsafe.get() {
+            Status::Failsafe
+        } else if self.unconfigured.get() {
+            Status::Unconfigured
+        } else {
+            Status::Running
+        }
+    }
+
+    /// Plays the pattern of the current status on the LED. Errors of the LED pin are ignored, as
+    /// there is nowhere to show them.
+    pub async fn run<Board: ?Sized, const NOM: u32, const DENOM: u32, E>(
+        &self,
+        led: &mut impl OutputPin,
+        timer: &impl Timer<Board, u64, NOM, DENOM>,
+    ) -> Result<!, E> {
+        let mut slot_start = timer.now();
+        let mut slot = 0;
+        loop {
+            let on = self.status().pattern() & (1 << slot) != 0;
+            if self.activity.replace(false) {
+                let _ = led.set_state((!on).into());
+                timer
+                    .wait_until(slot_start + Duration::<u64, NOM, DENOM>::millis(FLICKER_MS as u64))
+                    .await;
+            }
+            let _ = led.set_state(on.into());
+            slot_start += Duration::<u64, NOM, DENOM>::millis(SLOT_MS as u64);
+            timer.wait_until(slot_start).await;
+            slot = (slot + 1) % 16;
+        }
+    }
+}
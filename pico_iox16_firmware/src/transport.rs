@@ -0,0 +1,198 @@
+//! The serial port of the board as a [`Transport`].
+
+use core::{cell::Cell, marker::PhantomData, ops::Sub};
+
+use embedded_hal::digital::OutputPin;
+use fugit::{Duration, Instant};
+use pico_iox16_protocol::{
+    Footer, Header, MAX_REQUEST_SIZE, MAX_RESPONSE_SIZE, Received, Transport, next_message,
+};
+
+use crate::{
+    MainLoopError, nb_await, nvm,
+    runtime::{self, Elapsed as _, Read, ReadError, Timer, Write, yield_now},
+};
+
+/// Errors of a [`SerialTransport`], in terms of the main loop's errors.
+pub type SerialError<IO, S, Board> = MainLoopError<
+    <IO as Read<Board>>::Error,
+    <IO as Write<Board>>::Error,
+    <S as embedded_hal::digital::ErrorType>::Error,
+    !,
+    !,
+    !,
+>;
+
+/// Holds the frames of any command, but not all fragments.
+pub(crate) const BUF_SIZE: usize = if MAX_REQUEST_SIZE > MAX_RESPONSE_SIZE {
+    MAX_REQUEST_SIZE
+} else {
+    MAX_RESPONSE_SIZE
+};
+
+/// Frames over a half-duplex serial port, e.g. RS-485, whose driver is enabled by `io_send`
+/// while sending. Frames longer than `N` bytes are skipped.
+pub struct SerialTransport<
+    'a,
+    Board: ?Sized,
+    IO,
+    S,
+    T,
+    const NOM: u32,
+    const DENOM: u32,
+    const N: usize = BUF_SIZE,
+> {
+    io: &'a mut IO,
+    io_send: &'a mut S,
+    timer: &'a T,
+    /// Incremented whenever the port is polled, see [`crate::MainLoop`]
+    progress: &'a Cell<u32>,
+    /// Holds the frames of any command, so responses of other devices pass through
+    buf: [u8; N],
+    buf_len: usize,
+    last_receive: Instant<u64, NOM, DENOM>,
+    /// Drops the frames to send, see [`Self::mute`]
+    muted: bool,
+    _board: PhantomData<Board>,
+}
+
+impl<'a, Board, IO, S, T, const NOM: u32, const DENOM: u32, const N: usize>
+    SerialTransport<'a, Board, IO, S, T, NOM, DENOM, N>
+where
+    Board: ?Sized,
+    IO: Read<Board> + Write<Board>,
+    S: OutputPin,
+    T: Timer<Board, u64, NOM, DENOM>,
+{
+    pub fn new(io: &'a mut IO, io_send: &'a mut S, timer: &'a T, progress: &'a Cell<u32>) -> Self {
+        Self {
+            io,
+            io_send,
+            timer,
+            progress,
+            buf: [0; N],
+            buf_len: 0,
+            last_receive: timer.now(),
+            muted: false,
+            _board: PhantomData,
+        }
+    }
+
+    /// Drops the frames sent from now on instead of transmitting them, or transmits them again.
+    /// Lets the handling of a broadcast go through the same motions as that of any request.
+    pub(crate) fn mute(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Switches the port to the line settings of `config`, dropping what arrived with the
+    /// previous ones. Returns `false` if the port has no line settings.
+    pub(crate) fn set_line(&mut self, config: &nvm::Config) -> bool {
+        self.buf_len = 0;
+        self.io.set_line(config)
+    }
+
+    /// Reads whatever arrived, waiting for at least one byte or a recoverable error.
+    async fn read(&mut self) -> Result<usize, ReadError<<IO as Read<Board>>::Error>> {
+        loop {
+            self.progress.set(self.progress.get().wrapping_add(1));
+            match self.io.read(&mut self.buf[self.buf_len..]) {
+                Ok(received) => return Ok(received),
+                Err(nb::Error::Other(err)) => return Err(err),
+                // nothing else needs polling in low-power mode, so wait for the byte to wake us
+                Err(nb::Error::WouldBlock)
+                    if runtime::low_power() && self.io.wakes_on_receive() =>
+                {
+                    runtime::sleep().await
+                }
+                Err(nb::Error::WouldBlock) => yield_now().await,
+            }
+        }
+    }
+}
+
+impl<Board, IO, S, T, const NOM: u32, const DENOM: u32, const N: usize> Transport
+    for SerialTransport<'_, Board, IO, S, T, NOM, DENOM, N>
+where
+    Board: ?Sized,
+    IO: Read<Board> + Write<Board>,
+    S: OutputPin,
+    T: Timer<Board, u64, NOM, DENOM>,
+    Instant<u64, NOM, DENOM>: Sub<Output = Duration<u64, NOM, DENOM>>,
+{
+    type Error = SerialError<IO, S, Board>;
+
+    fn now_us(&self) -> u64 {
+        self.timer.now().duration_since_epoch().to_micros()
+    }
+
+    async fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        if self.muted {
+            return Ok(());
+        }
+        let mut bytes = frame;
+        self.io_send.set_high().map_err(MainLoopError::IoSend)?;
+        {
+            let mut preamble = 2;
+            while preamble > 0 {
+                let written = nb_await!(self.io.write(&[0xFF])).map_err(MainLoopError::Write)?;
+                preamble -= written;
+            }
+        }
+        while !bytes.is_empty() {
+            let written = nb_await!(self.io.write(bytes)).map_err(MainLoopError::Write)?;
+            assert!(written > 0);
+            bytes = &bytes[written..];
+        }
+        nb_await!(self.io.flush()).map_err(MainLoopError::Write)?;
+        self.io_send.set_low().map_err(MainLoopError::IoSend)?;
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<Received, Self::Error> {
+        loop {
+            // several frames may have arrived at once
+            loop {
+                let (maybe_message, processed) = next_message(&self.buf[..self.buf_len]);
+                let frame = maybe_message.map(|(_, payload)| {
+                    let len = size_of::<Header>() + payload.len() + size_of::<Footer>();
+                    processed - len..processed
+                });
+                if let Some(frame) = &frame
+                    && let Some(buf) = buf.get_mut(..frame.len())
+                {
+                    buf.copy_from_slice(&self.buf[frame.clone()]);
+                }
+                self.buf.copy_within(processed..self.buf_len, 0);
+                self.buf_len -= processed;
+                match frame {
+                    Some(frame) if frame.len() <= buf.len() => {
+                        return Ok(Received {
+                            len: frame.len(),
+                            at_us: self.last_receive.duration_since_epoch().to_micros(),
+                        });
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            // make sure to yield at least once per read to prevent starvation of other tasks
+            yield_now().await;
+            let received = match self.read().await {
+                Ok(received) => received,
+                Err(ReadError::UnrecoverableError(err)) => return Err(MainLoopError::Read(err)),
+                Err(ReadError::RecoverableError) => {
+                    self.buf_len = 0;
+                    continue;
+                }
+            };
+            // a pause in the middle of a frame means it was cut off
+            if self.timer.elapsed(self.last_receive).to_micros() > 1000 {
+                self.buf_len = 0;
+            }
+            if received > 0 {
+                self.last_receive = self.timer.now();
+                self.buf_len += received;
+            }
+        }
+    }
+}
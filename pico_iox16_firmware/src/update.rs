@@ -0,0 +1,129 @@
+//! Firmware updates over the bus. The new image is written to a staging area, and only copied
+//! over the running firmware once it is complete and its checksum matches.
+
+use core::{cell::Cell, marker::PhantomData, ops::Deref};
+
+use pico_iox16_protocol::{
+    BootSwapReq, BootSwapRes, ErrorCode, FLASH_CHUNK_SIZE, FLASH_SECTOR_SIZE, FlashEraseRegionReq,
+    FlashEraseRegionRes, FlashVerifyReq, FlashVerifyRes, FlashWriteChunkReq, FlashWriteChunkRes,
+    IMAGE_CHECKSUM,
+};
+
+use crate::{
+    HandleMessage, nb_await,
+    nvm::{NonvolatileStorage, Nvm},
+    runtime::yield_now,
+};
+
+/// The part of the staging area from `offset` that is `len` bytes long, if it lies within the
+/// staging area and both are multiples of `align`.
+fn staged_region<NVM: NonvolatileStorage<Board>, Board: ?Sized>(
+    storage: &NVM,
+    offset: u32,
+    len: u32,
+    align: u32,
+) -> Result<core::ops::Range<u32>, ErrorCode> {
+    let end = offset
+        .checked_add(len)
+        .filter(|&end| end <= storage.staging_size())
+        .ok_or(ErrorCode::InvalidPayload)?;
+    if !offset.is_multiple_of(align) || !len.is_multiple_of(align) {
+        return Err(ErrorCode::InvalidPayload);
+    }
+    Ok(offset..end)
+}
+
+/// Computes the [`IMAGE_CHECKSUM`] of the first `len` bytes of the staging area. Yields after
+/// every sector, counting it as `progress` of the control loop, as a whole image takes longer
+/// than the watchdog waits.
+async fn checksum<NVM: NonvolatileStorage<Board>, Board: ?Sized>(
+    storage: &NVM,
+    len: u32,
+    progress: &Cell<u32>,
+) -> Result<u32, ErrorCode> {
+    let range = staged_region(storage, 0, len, 1)?;
+    let mut digest = IMAGE_CHECKSUM.digest();
+    let mut buf = [0; FLASH_CHUNK_SIZE];
+    for offset in range.step_by(FLASH_CHUNK_SIZE) {
+        let chunk = &mut buf[..(len - offset).min(FLASH_CHUNK_SIZE as u32) as usize];
+        nb_await!(storage.read_staging(offset, chunk)).map_err(|_| ErrorCode::StorageFailed)?;
+        digest.update(chunk);
+        if offset.is_multiple_of(FLASH_SECTOR_SIZE) {
+            progress.set(progress.get().wrapping_add(1));
+            yield_now().await;
+        }
+    }
+    Ok(digest.finalize())
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage
+    for (
+        &FlashEraseRegionReq,
+        O,
+        &Cell<u32>,
+        PhantomData<(NVM, Board)>,
+    )
+{
+    type Response = FlashEraseRegionRes;
+    type Error = ErrorCode;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (FlashEraseRegionReq { offset, len }, nvm, progress, PhantomData) = self;
+        let storage = nvm.storage();
+        let region = staged_region(storage, offset.get(), len.get(), FLASH_SECTOR_SIZE)?;
+        for sector in region.step_by(FLASH_SECTOR_SIZE as usize) {
+            nb_await!(storage.erase_staging(sector)).map_err(|_| ErrorCode::StorageFailed)?;
+            progress.set(progress.get().wrapping_add(1));
+            yield_now().await;
+        }
+        Ok(FlashEraseRegionRes)
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&FlashWriteChunkReq, O, PhantomData<(NVM, Board)>)
+{
+    type Response = FlashWriteChunkRes;
+    type Error = ErrorCode;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (FlashWriteChunkReq { offset, data }, nvm, PhantomData) = self;
+        let storage = nvm.storage();
+        let chunk = FLASH_CHUNK_SIZE as u32;
+        let region = staged_region(storage, offset.get(), chunk, chunk)?;
+        nb_await!(storage.write_staging(region.start, data))
+            .map_err(|_| ErrorCode::StorageFailed)?;
+        Ok(FlashWriteChunkRes)
+    }
+}
+
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&FlashVerifyReq, O, &Cell<u32>, PhantomData<(NVM, Board)>)
+{
+    type Response = FlashVerifyRes;
+    type Error = ErrorCode;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (FlashVerifyReq { len }, nvm, progress, PhantomData) = self;
+        let storage = nvm.storage();
+        Ok(FlashVerifyRes {
+            size: storage.staging_size().into(),
+            checksum: checksum(storage, len.get(), progress).await?.into(),
+        })
+    }
+}
+
+/// Checks the staged image before [`NonvolatileStorage::boot_staged`] may replace the running
+/// firmware with it. An empty image or one with another checksum is rejected.
+impl<O: Deref<Target = Nvm<NVM, Board>>, NVM: NonvolatileStorage<Board>, Board: ?Sized>
+    HandleMessage for (&BootSwapReq, O, &Cell<u32>, PhantomData<(NVM, Board)>)
+{
+    type Response = BootSwapRes;
+    type Error = ErrorCode;
+    async fn handle(self) -> Result<Self::Response, Self::Error> {
+        let (request, nvm, progress, PhantomData) = self;
+        let (len, expected) = (request.len.get(), request.checksum.get());
+        if len == 0 || checksum(nvm.storage(), len, progress).await? != expected {
+            return Err(ErrorCode::InvalidPayload);
+        }
+        Ok(BootSwapRes)
+    }
+}
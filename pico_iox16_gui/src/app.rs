@@ -0,0 +1,351 @@
+use std::time::Duration;
+
+use eframe::egui::{self, Color32, ComboBox, DragValue, Grid, ProgressBar, RichText, Slider};
+use pico_iox16_protocol::settings::{Calibration, Threshold};
+use pico_iox16_tool::{device::Outputs, settings::Settings, units::Units};
+
+use crate::worker::{Connection, Request, Update, Worker};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Inputs,
+    Outputs,
+    Thresholds,
+    Calibrations,
+}
+
+/// The state of the connected device as last read.
+struct DeviceState {
+    worker: Worker,
+    units: Units,
+    inputs: Option<([i16; 16], u16, u16)>,
+    outputs: Outputs,
+    thresholds: Option<[Threshold; 16]>,
+    calibrations: Option<[Calibration; 16]>,
+}
+
+pub struct App {
+    settings: Settings,
+    ports: Vec<String>,
+    port: String,
+    baudrate: u32,
+    address: String,
+    tab: Tab,
+    device: Option<DeviceState>,
+    /// The last error or confirmation, shown in the status bar.
+    status: Option<(String, bool)>,
+}
+
+impl App {
+    pub fn new(settings: Settings) -> Self {
+        let ports = available_ports();
+        Self {
+            port: settings
+                .device
+                .clone()
+                .or_else(|| ports.first().cloned())
+                .unwrap_or_default(),
+            baudrate: settings.baudrate.unwrap_or(1_000_000),
+            address: "0".to_string(),
+            ports,
+            settings,
+            tab: Tab::Inputs,
+            device: None,
+            status: None,
+        }
+    }
+
+    fn connect(&mut self, ctx: &egui::Context) {
+        let address = match self.settings.resolve_address(self.address.trim()) {
+            Ok(address) => address,
+            Err(err) => return self.status = Some((format!("{err:#}"), true)),
+        };
+        let units = match self.settings.units(address) {
+            Ok(units) => units,
+            Err(err) => return self.status = Some((format!("{err:#}"), true)),
+        };
+        let connection = Connection {
+            port: self.port.clone(),
+            baudrate: self.baudrate,
+            address,
+            retries: self.settings.retries.unwrap_or(0),
+            timeout: Duration::from_millis(self.settings.timeout.unwrap_or(1)),
+        };
+        let ctx = ctx.clone();
+        self.device = Some(DeviceState {
+            worker: Worker::spawn(connection, move || ctx.request_repaint()),
+            units,
+            inputs: None,
+            outputs: Outputs::default(),
+            thresholds: None,
+            calibrations: None,
+        });
+        self.status = Some((format!("Connecting to device {address}..."), false));
+    }
+
+    fn receive(&mut self) {
+        let Some(device) = &mut self.device else {
+            return;
+        };
+        for update in device.worker.updates() {
+            match update {
+                Update::Connected(info) => {
+                    self.status = Some((
+                        format!(
+                            "Connected to {} (firmware {}.{}.{})",
+                            info.info, info.version.0, info.version.1, info.version.2
+                        ),
+                        false,
+                    ));
+                }
+                Update::Inputs {
+                    values,
+                    above,
+                    below,
+                } => device.inputs = Some((values, above, below)),
+                Update::Outputs(outputs) => device.outputs = outputs,
+                Update::Thresholds(thresholds) => device.thresholds = Some(thresholds),
+                Update::Calibrations(calibrations) => device.calibrations = Some(calibrations),
+                Update::Written(what) => self.status = Some((format!("Wrote {what}"), false)),
+                Update::Error(err) => self.status = Some((err, true)),
+            }
+        }
+    }
+
+    fn connection_bar(&mut self, ui: &mut egui::Ui) {
+        let connected = self.device.is_some();
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(!connected, |ui| {
+                ui.label("Port");
+                ComboBox::from_id_salt("port")
+                    .selected_text(&self.port)
+                    .show_ui(ui, |ui| {
+                        for port in &self.ports {
+                            ui.selectable_value(&mut self.port, port.clone(), port);
+                        }
+                    });
+                if ui
+                    .button("⟳")
+                    .on_hover_text("Refresh the port list")
+                    .clicked()
+                {
+                    self.ports = available_ports();
+                }
+                ui.label("Baudrate");
+                ui.add(DragValue::new(&mut self.baudrate).range(9600..=3_000_000));
+                ui.label("Address");
+                ui.add(egui::TextEdit::singleline(&mut self.address).desired_width(120.0))
+                    .on_hover_text("A numeric address or an alias from the configuration file");
+            });
+            if connected {
+                if ui.button("Disconnect").clicked() {
+                    self.device = None;
+                    self.status = None;
+                }
+            } else if ui.button("Connect").clicked() {
+                self.connect(ui.ctx());
+            }
+        });
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.receive();
+        egui::TopBottomPanel::top("connection").show(ctx, |ui| {
+            ui.add_space(4.0);
+            self.connection_bar(ui);
+            ui.add_space(4.0);
+        });
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| match &self.status {
+            Some((message, true)) => {
+                ui.label(RichText::new(message).color(Color32::LIGHT_RED));
+            }
+            Some((message, false)) => {
+                ui.label(message);
+            }
+            None => {
+                ui.label("Not connected");
+            }
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some(device) = &mut self.device else {
+                ui.centered_and_justified(|ui| {
+                    ui.label(
+                        "Select the serial port and the address of the device and press Connect.",
+                    );
+                });
+                return;
+            };
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Inputs, "Inputs");
+                ui.selectable_value(&mut self.tab, Tab::Outputs, "Outputs");
+                ui.selectable_value(&mut self.tab, Tab::Thresholds, "Thresholds");
+                ui.selectable_value(&mut self.tab, Tab::Calibrations, "Calibrations");
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().show(ui, |ui| match self.tab {
+                Tab::Inputs => inputs(ui, device),
+                Tab::Outputs => outputs(ui, device),
+                Tab::Thresholds => thresholds(ui, device),
+                Tab::Calibrations => calibrations(ui, device),
+            });
+        });
+    }
+}
+
+fn available_ports() -> Vec<String> {
+    tokio_serial::available_ports()
+        .map(|ports| ports.into_iter().map(|port| port.port_name).collect())
+        .unwrap_or_default()
+}
+
+/// One gauge per input, scaled to the ADC range, with the threshold state next to it.
+fn inputs(ui: &mut egui::Ui, device: &DeviceState) {
+    let Some((values, above, below)) = device.inputs else {
+        ui.label("Waiting for data...");
+        return;
+    };
+    Grid::new("inputs")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            for (channel, &value) in values.iter().enumerate() {
+                ui.label(format!("Input {channel}"));
+                let fraction = (f64::from(value) / device.units.full_scale).clamp(0.0, 1.0);
+                ui.add(
+                    ProgressBar::new(fraction as f32)
+                        .desired_width(300.0)
+                        .text(device.units.format(channel, value)),
+                );
+                if above & (1 << channel) != 0 {
+                    ui.label(RichText::new("above").color(Color32::LIGHT_RED));
+                } else if below & (1 << channel) != 0 {
+                    ui.label(RichText::new("below").color(Color32::LIGHT_BLUE));
+                } else {
+                    ui.label("");
+                }
+                ui.end_row();
+            }
+        });
+}
+
+/// Sliders for the duty cycles, sent to the device as soon as they change. Only the outputs
+/// that changed are sent, unless a frequency did.
+fn outputs(ui: &mut egui::Ui, device: &mut DeviceState) {
+    let mut changed = false;
+    let mut mask = 0u16;
+    Grid::new("outputs")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            for group in 0..8 {
+                ui.label(format!("Outputs {} and {}", 2 * group, 2 * group + 1));
+                ui.label("Frequency");
+                changed |= ui
+                    .add(
+                        DragValue::new(&mut device.outputs.frequencies[group])
+                            .range(10..=50_000)
+                            .suffix(" Hz"),
+                    )
+                    .changed();
+                ui.end_row();
+                for channel in [2 * group, 2 * group + 1] {
+                    ui.label("");
+                    ui.label(format!("Output {channel}"));
+                    if ui
+                        .add(
+                            Slider::new(&mut device.outputs.duty_cycles[channel], 0.0..=100.0)
+                                .suffix(" %"),
+                        )
+                        .changed()
+                    {
+                        mask |= 1 << channel;
+                    }
+                    ui.end_row();
+                }
+            }
+        });
+    ui.horizontal(|ui| {
+        if ui.button("All off").clicked() {
+            device.outputs.duty_cycles = [0.0; 16];
+            mask = u16::MAX;
+        }
+        if ui.button("Read from device").clicked() {
+            device.worker.send(Request::ReadSettings);
+        }
+    });
+    if changed {
+        device.worker.send(Request::SetOutputs(device.outputs));
+    } else if mask != 0 {
+        device
+            .worker
+            .send(Request::SetDutyCycles(device.outputs, mask));
+    }
+}
+
+/// Buttons to read the settings from and write a form to the device.
+fn read_write(ui: &mut egui::Ui, device: &DeviceState, write: impl FnOnce() -> Request) {
+    ui.horizontal(|ui| {
+        if ui.button("Read from device").clicked() {
+            device.worker.send(Request::ReadSettings);
+        }
+        if ui.button("Write to device").clicked() {
+            device.worker.send(write());
+        }
+    });
+}
+
+fn thresholds(ui: &mut egui::Ui, device: &mut DeviceState) {
+    let Some(thresholds) = &mut device.thresholds else {
+        ui.label("Waiting for data...");
+        return;
+    };
+    Grid::new("thresholds")
+        .num_columns(5)
+        .striped(true)
+        .show(ui, |ui| {
+            for header in ["", "High", "Low", "Debounce time", "Debounce count"] {
+                ui.strong(header);
+            }
+            ui.end_row();
+            for (channel, threshold) in thresholds.iter_mut().enumerate() {
+                ui.label(format!("Input {channel}"));
+                ui.add(DragValue::new(&mut threshold.threshold_high));
+                ui.add(DragValue::new(&mut threshold.threshold_low));
+                ui.add(DragValue::new(&mut threshold.debounce_time_us).suffix(" µs"));
+                ui.add(DragValue::new(&mut threshold.debounce_count));
+                ui.end_row();
+            }
+        });
+    let thresholds = *thresholds;
+    read_write(ui, device, || Request::SetThresholds(thresholds));
+}
+
+fn calibrations(ui: &mut egui::Ui, device: &mut DeviceState) {
+    let Some(calibrations) = &mut device.calibrations else {
+        ui.label("Waiting for data...");
+        return;
+    };
+    ui.label("value = raw × multiply / divide + add, limited to min..max");
+    Grid::new("calibrations")
+        .num_columns(6)
+        .striped(true)
+        .show(ui, |ui| {
+            for header in ["", "Multiply", "Divide", "Add", "Min", "Max"] {
+                ui.strong(header);
+            }
+            ui.end_row();
+            for (channel, calibration) in calibrations.iter_mut().enumerate() {
+                ui.label(format!("Input {channel}"));
+                ui.add(DragValue::new(&mut calibration.multiply));
+                ui.add(DragValue::new(&mut calibration.divide).range(1..=i16::MAX));
+                ui.add(DragValue::new(&mut calibration.add));
+                ui.add(DragValue::new(&mut calibration.min));
+                ui.add(DragValue::new(&mut calibration.max));
+                ui.end_row();
+            }
+        });
+    let calibrations = *calibrations;
+    read_write(ui, device, || Request::SetCalibrations(calibrations));
+}
@@ -0,0 +1,19 @@
+//! Dashboard for a single device with live input gauges, output sliders and forms for the
+//! thresholds and calibrations. The defaults for the connection and the units of the inputs
+//! are taken from the configuration file of `pico_iox16_tool`.
+
+mod app;
+mod worker;
+
+use anyhow::{Result, anyhow};
+use pico_iox16_tool::settings::Settings;
+
+fn main() -> Result<()> {
+    let settings = Settings::load()?;
+    eframe::run_native(
+        "Pico I∴O×16",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::new(app::App::new(settings)))),
+    )
+    .map_err(|err| anyhow!("{err}"))
+}
@@ -0,0 +1,180 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::settings::{Calibration, Threshold};
+use pico_iox16_tool::{
+    Error, Protocol,
+    device::{Device, Info, Outputs},
+};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio_serial::SerialPortBuilderExt as _;
+
+/// Time between two reads of the inputs.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A request from the user interface to the device.
+#[derive(Debug, Clone)]
+pub enum Request {
+    SetOutputs(Outputs),
+    /// Sets the duty cycles of the outputs selected in the mask, bit `n` for output `n`.
+    SetDutyCycles(Outputs, u16),
+    /// Reads the outputs, thresholds and calibrations.
+    ReadSettings,
+    SetThresholds([Threshold; 16]),
+    SetCalibrations([Calibration; 16]),
+}
+
+/// A result reported from the device to the user interface.
+#[derive(Debug, Clone)]
+pub enum Update {
+    Connected(Info),
+    Inputs {
+        values: [i16; 16],
+        above: u16,
+        below: u16,
+    },
+    Outputs(Outputs),
+    Thresholds([Threshold; 16]),
+    Calibrations([Calibration; 16]),
+    /// A request was written successfully.
+    Written(&'static str),
+    Error(String),
+}
+
+/// Connection settings of a worker.
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub port: String,
+    pub baudrate: u32,
+    pub address: u16,
+    pub retries: u32,
+    pub timeout: Duration,
+}
+
+/// Talks to the device on a background thread, so that the user interface never blocks on
+/// the serial port. The thread stops when the worker is dropped.
+pub struct Worker {
+    requests: UnboundedSender<Request>,
+    updates: mpsc::Receiver<Update>,
+}
+
+impl Worker {
+    /// Starts the worker. `repaint` is called whenever an update is available.
+    pub fn spawn(connection: Connection, repaint: impl Fn() + Send + 'static) -> Self {
+        let (request_sender, mut requests) = unbounded_channel();
+        let (update_sender, updates) = mpsc::channel();
+        thread::spawn(move || {
+            let send = |update| {
+                // the receiver is gone when the worker was dropped, which also ends the loop below
+                let _ = update_sender.send(update);
+                repaint();
+            };
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => return send(Update::Error(format!("Starting runtime: {err}"))),
+            };
+            runtime.block_on(async {
+                let mut device = match connect(&connection).await {
+                    Ok((device, info)) => {
+                        send(Update::Connected(info));
+                        device
+                    }
+                    Err(err) => return send(Update::Error(format!("{err:#}"))),
+                };
+                if let Err(err) = read_settings(&mut device, &send).await {
+                    send(Update::Error(format!("{err:#}")));
+                }
+                let mut ticks = tokio::time::interval(POLL_INTERVAL);
+                ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    let result = tokio::select! {
+                        _ = ticks.tick() => poll(&mut device, &send).await,
+                        request = requests.recv() => match request {
+                            Some(request) => handle(&mut device, request, &send).await,
+                            None => break,
+                        },
+                    };
+                    if let Err(err) = result {
+                        send(Update::Error(format!("{err:#}")));
+                    }
+                }
+            });
+        });
+        Self {
+            requests: request_sender,
+            updates,
+        }
+    }
+
+    pub fn send(&self, request: Request) {
+        // a failed worker reports its error as an update, so a closed channel is not an error
+        let _ = self.requests.send(request);
+    }
+
+    /// Returns the updates received since the previous call.
+    pub fn updates(&self) -> impl Iterator<Item = Update> + '_ {
+        self.updates.try_iter()
+    }
+}
+
+async fn connect(connection: &Connection) -> Result<(Device, Info)> {
+    let port = tokio_serial::new(&connection.port, connection.baudrate)
+        .timeout(Duration::from_micros(100))
+        .open_native_async()
+        .with_context(|| format!("Opening serial port {}", connection.port))?;
+    let mut protocol = Protocol::new(port);
+    protocol.set_retries(connection.retries);
+    protocol.set_min_timeout(connection.timeout);
+    let mut device = Device::new(protocol, connection.address);
+    let info = device
+        .info()
+        .await
+        .with_context(|| format!("Connecting to device {}", connection.address))?;
+    info.check_protocol_version()?;
+    Ok((device, info))
+}
+
+async fn poll(device: &mut Device, send: &impl Fn(Update)) -> Result<()> {
+    let values = device.inputs().await?;
+    let (above, below) = device.threshold_states().await?;
+    send(Update::Inputs {
+        values,
+        above,
+        below,
+    });
+    Ok(())
+}
+
+async fn read_settings(device: &mut Device, send: &impl Fn(Update)) -> Result<()> {
+    send(Update::Outputs(device.outputs().await?));
+    send(Update::Thresholds(device.thresholds().await?));
+    send(Update::Calibrations(device.calibrations().await?));
+    Ok(())
+}
+
+async fn handle(device: &mut Device, request: Request, send: &impl Fn(Update)) -> Result<()> {
+    match request {
+        Request::SetOutputs(outputs) => Ok(device.set_outputs(&outputs).await?),
+        Request::SetDutyCycles(outputs, mask) => {
+            match device.set_duty_cycles(mask, &outputs.duty_cycles).await {
+                // firmware that predates OutputSetMasked gets all outputs
+                Err(Error::Unsupported { .. }) => Ok(device.set_outputs(&outputs).await?),
+                result => Ok(result?),
+            }
+        }
+        Request::ReadSettings => read_settings(device, send).await,
+        Request::SetThresholds(thresholds) => {
+            device.set_thresholds(&thresholds).await?;
+            send(Update::Written("thresholds"));
+            Ok(())
+        }
+        Request::SetCalibrations(calibrations) => {
+            device.set_calibrations(&calibrations).await?;
+            send(Update::Written("calibrations"));
+            Ok(())
+        }
+    }
+}
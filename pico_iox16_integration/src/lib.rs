@@ -0,0 +1,152 @@
+//! Runs the firmware's main loop on the host against the mock board of
+//! [`pico_iox16_firmware::mock`], so that the tool can talk to it like to a real device and the
+//! tests in `tests/` can check protocol, firmware and tool together without hardware.
+
+pub mod link;
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use pico_iox16_firmware::{
+    mock::{self, Flash},
+    nvm::{self, DEFAULT_BAUDRATE},
+};
+use pico_iox16_protocol::{
+    Command, Fragments, MAX_FRAGMENT_SIZE, Reassembler, RebootMode, next_message,
+};
+use pico_iox16_tool::{Protocol, device::Device};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream};
+
+use link::{HostPort, Link};
+
+/// The firmware running on a thread of its own. It stops once the host's end of the stream is
+/// dropped.
+pub struct Firmware {
+    reboots: Arc<Mutex<Vec<RebootMode>>>,
+}
+
+impl Firmware {
+    /// Starts the firmware of a fresh board and returns it along with a device to talk to it at
+    /// its unconfigured address.
+    pub fn start() -> (Self, Device) {
+        Self::start_with(Flash::default())
+    }
+
+    /// Like [`Firmware::start`], but with the board's flash holding `flash`.
+    pub fn start_with(flash: Flash) -> (Self, Device) {
+        let (firmware_end, host_end) = tokio::io::duplex(1024);
+        let reboots = Arc::new(Mutex::new(Vec::new()));
+        thread::spawn({
+            let reboots = reboots.clone();
+            move || {
+                mock::run(&mut Link(firmware_end), &flash, |mode| {
+                    reboots.lock().unwrap().push(mode)
+                })
+            }
+        });
+        let mut protocol = Protocol::new(HostPort::new(host_end, DEFAULT_BAUDRATE));
+        // the firmware thread competes with the tests for the CPU
+        protocol.set_min_timeout(Duration::from_secs(1));
+        (
+            Self { reboots },
+            Device::new(protocol, nvm::UNCONFIGURED_ADDRESS),
+        )
+    }
+
+    /// Starts the firmware of two fresh boards, the first one as a repeater waiting `timeout_us`
+    /// for responses and the second one on its downstream port, and returns them along with a device to talk to
+    /// the first one at its unconfigured address. The reboots of both are recorded.
+    pub fn start_repeater(timeout_us: u32) -> (Self, Device) {
+        let (upstream_end, downstream_end) = tokio::io::duplex(1024);
+        let (firmware, device) = Self::start_repeater_with(timeout_us, upstream_end);
+        thread::spawn({
+            let reboots = firmware.reboots.clone();
+            move || {
+                mock::run(&mut Link(downstream_end), &Flash::default(), |mode| {
+                    reboots.lock().unwrap().push(mode)
+                })
+            }
+        });
+        (firmware, device)
+    }
+
+    /// Like [`Firmware::start_repeater`], but with whatever is on the other end of `downstream`
+    /// on the repeater's downstream port instead of a second board.
+    pub fn start_repeater_with(timeout_us: u32, downstream: DuplexStream) -> (Self, Device) {
+        let (firmware_end, host_end) = tokio::io::duplex(1024);
+        let reboots = Arc::new(Mutex::new(Vec::new()));
+        thread::spawn({
+            let reboots = reboots.clone();
+            move || {
+                mock::run_repeater(
+                    &mut Link(firmware_end),
+                    &mut Link(downstream),
+                    timeout_us,
+                    &Flash::default(),
+                    |mode| reboots.lock().unwrap().push(mode),
+                )
+            }
+        });
+        let mut protocol = Protocol::new(HostPort::new(host_end, DEFAULT_BAUDRATE));
+        protocol.set_min_timeout(Duration::from_secs(1));
+        (
+            Self { reboots },
+            Device::new(protocol, nvm::UNCONFIGURED_ADDRESS),
+        )
+    }
+
+    /// The modes the firmware was asked to reboot into so far.
+    pub fn reboots(&self) -> Vec<RebootMode> {
+        self.reboots.lock().unwrap().clone()
+    }
+}
+
+/// A device that answers every fragmented request with the reversed payload in fragments of
+/// `fragment_size` bytes, leaving out the fragment with the index `drop` if given, standing in
+/// for commands with payloads larger than a frame, which the firmware doesn't have yet. Runs
+/// until the other end of the stream is dropped.
+pub async fn reversing_device(mut stream: DuplexStream, fragment_size: usize, drop: Option<u16>) {
+    let mut reassembler = Reassembler::new();
+    let mut payload = Vec::new();
+    let mut buf = Vec::new();
+    let mut chunk = [0; 256];
+    let mut frame = [0; MAX_FRAGMENT_SIZE + 32];
+    loop {
+        let Ok(n @ 1..) = stream.read(&mut chunk).await else {
+            return;
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        loop {
+            let (maybe_message, processed) = next_message(&buf);
+            let complete = maybe_message.and_then(|(header, body)| {
+                let data = reassembler.push(header, body).ok()?;
+                payload.resize(data.size, 0);
+                payload[data.offset..data.offset + data.data.len()].copy_from_slice(data.data);
+                data.complete
+                    .then_some((header.address.get(), data.command, header.sequence))
+            });
+            let found = maybe_message.is_some();
+            buf.drain(..processed);
+            if let Some((address, command, sequence)) = complete {
+                payload.reverse();
+                let command = Command::try_from(command).unwrap();
+                let fragments =
+                    Fragments::new_response(address, command, sequence, &payload, fragment_size)
+                        .unwrap();
+                for fragment in fragments {
+                    if Some(fragment.fragment_header().index.get()) == drop {
+                        continue;
+                    }
+                    let len = fragment.write_to(&mut frame).unwrap();
+                    stream.write_all(&frame[..len]).await.unwrap();
+                }
+            }
+            if !found {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! The in-memory stream between the firmware running on the mock board of
+//! [`pico_iox16_firmware::mock`] and the tool.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use pico_iox16_firmware::{
+    mock::Host,
+    runtime::{Read, ReadError, Write},
+};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+/// The firmware's end of the in-memory stream. Closing the host's end reads as an
+/// unrecoverable error, which ends the main loop.
+pub struct Link(pub DuplexStream);
+impl Link {
+    /// Polls the stream once. The executor of the firmware polls again on its own, so nothing
+    /// needs to be woken.
+    fn poll<T>(
+        &mut self,
+        f: impl FnOnce(Pin<&mut DuplexStream>, &mut Context) -> Poll<T>,
+    ) -> Option<T> {
+        match f(
+            Pin::new(&mut self.0),
+            &mut Context::from_waker(Waker::noop()),
+        ) {
+            Poll::Ready(v) => Some(v),
+            Poll::Pending => None,
+        }
+    }
+}
+impl Read<Host> for Link {
+    type Error = io::Error;
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut read_buf = ReadBuf::new(buf);
+        match self.poll(|stream, cx| stream.poll_read(cx, &mut read_buf)) {
+            None => Err(nb::Error::WouldBlock),
+            Some(Err(err)) => Err(nb::Error::Other(ReadError::UnrecoverableError(err))),
+            Some(Ok(())) if read_buf.filled().is_empty() => Err(nb::Error::Other(
+                ReadError::UnrecoverableError(io::ErrorKind::UnexpectedEof.into()),
+            )),
+            Some(Ok(())) => Ok(read_buf.filled().len()),
+        }
+    }
+}
+impl Write<Host> for Link {
+    type Error = io::Error;
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        self.poll(|stream, cx| stream.poll_write(cx, buf))
+            .ok_or(nb::Error::WouldBlock)?
+            .map_err(nb::Error::Other)
+    }
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.poll(|stream, cx| stream.poll_flush(cx))
+            .ok_or(nb::Error::WouldBlock)?
+            .map_err(nb::Error::Other)
+    }
+}
+
+/// The host's end of the in-memory stream, for a [`pico_iox16_tool::Protocol`]. The baudrate
+/// doesn't change anything but is kept for the tool to read back.
+pub struct HostPort {
+    stream: DuplexStream,
+    baudrate: u32,
+}
+impl HostPort {
+    pub fn new(stream: DuplexStream, baudrate: u32) -> Self {
+        Self { stream, baudrate }
+    }
+}
+impl AsyncRead for HostPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+impl AsyncWrite for HostPort {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+impl pico_iox16_tool::Port for HostPort {
+    fn baud_rate(&self) -> io::Result<u32> {
+        Ok(self.baudrate)
+    }
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        self.baudrate = baudrate;
+        Ok(())
+    }
+}
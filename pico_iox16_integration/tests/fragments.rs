@@ -0,0 +1,64 @@
+//! Payloads larger than a frame, split and reassembled by the tool on one end and the protocol
+//! crate on the other. The firmware has no command with such payloads yet, so a device that
+//! answers every fragmented request with its payload reversed stands in for it.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use pico_iox16_integration::{link::HostPort, reversing_device};
+use pico_iox16_protocol::{Command, MAX_FRAGMENT_SIZE};
+use pico_iox16_tool::{Error, Protocol};
+
+fn protocol(fragment_size: usize, drop: Option<u16>) -> Protocol {
+    let (device_end, host_end) = tokio::io::duplex(1024);
+    tokio::spawn(reversing_device(device_end, fragment_size, drop));
+    let mut protocol = Protocol::new(HostPort::new(host_end, 1_000_000));
+    protocol.set_min_timeout(Duration::from_millis(200));
+    protocol
+}
+
+#[tokio::test]
+async fn large_payloads_round_trip() -> Result<()> {
+    let mut protocol = protocol(MAX_FRAGMENT_SIZE, None);
+    let payload: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    let mut expected = payload.clone();
+    expected.reverse();
+    let response = protocol
+        .send_fragmented(7, Command::InfoGet, &payload, 64, Duration::ZERO)
+        .await?;
+    assert_eq!(response, expected);
+
+    // lengths that are not whole words and payloads that fit into one fragment
+    for len in [0, 1, 3, 5, 1013] {
+        let payload = &payload[..len];
+        let mut expected = payload.to_vec();
+        expected.reverse();
+        let response = protocol
+            .send_fragmented(7, Command::InfoGet, payload, 128, Duration::ZERO)
+            .await?;
+        assert_eq!(response, expected);
+    }
+    assert_eq!(protocol.statistics().timeouts, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn lost_fragments_time_out() -> Result<()> {
+    let mut protocol = protocol(16, Some(2));
+    let err = protocol
+        .send_fragmented(7, Command::InfoGet, &[0; 100], 16, Duration::ZERO)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Fragment {
+                command: Command::InfoGet,
+                ..
+            }
+        ),
+        "{err:?}"
+    );
+    assert!(err.is_timeout());
+    Ok(())
+}
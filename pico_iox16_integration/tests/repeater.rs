@@ -0,0 +1,159 @@
+//! A device passing requests on to a second bus segment, with a device of its own there.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use pico_iox16_firmware::nvm::{DEFAULT_BAUDRATE, UNCONFIGURED_ADDRESS};
+use pico_iox16_integration::{Firmware, reversing_device};
+use pico_iox16_protocol::{
+    AddressRange, CAPABILITY_REPEATER, CheckReq, Command, Config, ConfigSetReq, Forwarding,
+    ForwardingGetReq, ForwardingGetRes, ForwardingSetReq, MAX_FRAGMENT_SIZE, OutputGetReq,
+    OutputGetRes, OutputGroup, OutputSetReq, PROTOCOL_VERSION, Parity, RebootMode, RebootReq,
+    StopBits,
+};
+use pico_iox16_tool::device::{Device, Info, ProtocolVersion};
+
+const REPEATER: u16 = 100;
+const DOWNSTREAM: u16 = 7;
+
+/// Moves the device answering at the unconfigured address to `address`.
+async fn configure(device: &mut Device, address: u16) -> Result<()> {
+    let config = Config {
+        address: address.into(),
+        baudrate: DEFAULT_BAUDRATE.into(),
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+    let protocol = device.protocol();
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, ConfigSetReq(config), |_| Ok(()))
+        .await?;
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, RebootReq::FIRMWARE, |_| Ok(()))
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn forwards_to_downstream_segment() -> Result<()> {
+    let (firmware, mut device) = Firmware::start_repeater(200_000);
+    let get = async |device: &mut Device| {
+        device
+            .protocol()
+            .send_request(
+                UNCONFIGURED_ADDRESS,
+                ForwardingGetReq,
+                |res: &ForwardingGetRes| Ok(res.0),
+            )
+            .await
+    };
+    assert_eq!(get(&mut device).await?, Forwarding::NONE);
+    let forwarding = Forwarding {
+        ranges: [
+            AddressRange::new(0..=99),
+            AddressRange::new(UNCONFIGURED_ADDRESS..=UNCONFIGURED_ADDRESS),
+            AddressRange::EMPTY,
+            AddressRange::EMPTY,
+        ],
+    };
+    device
+        .protocol()
+        .send_request(UNCONFIGURED_ADDRESS, ForwardingSetReq(forwarding), |_| {
+            Ok(())
+        })
+        .await?;
+    assert_eq!(get(&mut device).await?, forwarding);
+    // the repeater answers its own address even though it is forwarded
+    configure(&mut device, REPEATER).await?;
+    configure(&mut device, DOWNSTREAM).await?;
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware; 2]);
+
+    let protocol = device.protocol();
+    let info = Info::fetch(protocol, REPEATER).await?;
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    let version = ProtocolVersion::fetch(protocol, REPEATER).await?;
+    assert!(version.supports(CAPABILITY_REPEATER));
+    let version = ProtocolVersion::fetch(protocol, DOWNSTREAM).await?;
+    assert!(!version.supports(CAPABILITY_REPEATER));
+    let groups = [OutputGroup {
+        duty_cycle: [0x1000.into(), 0x2000.into()],
+        frequency: 500.into(),
+    }; 8];
+    protocol
+        .send_request(DOWNSTREAM, OutputSetReq(groups), |_| Ok(()))
+        .await?;
+    for (address, expected) in [(DOWNSTREAM, groups), (REPEATER, OutputSetReq::default().0)] {
+        let outputs = protocol
+            .send_request(address, OutputGetReq, |res: &OutputGetRes| Ok(res.0))
+            .await?;
+        assert_eq!(outputs, expected);
+    }
+    // an identical request right after the response is no echo
+    for _ in 0..3 {
+        protocol
+            .send_request(DOWNSTREAM, CheckReq, |_| Ok(()))
+            .await?;
+    }
+
+    // nobody at a forwarded address, nor at one that isn't forwarded
+    for address in [8, 200] {
+        let err = protocol
+            .send_request(address, CheckReq, |_| Ok(()))
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout(), "{err:?}");
+    }
+
+    // broadcasts reach the devices on both segments, and none of them responds
+    protocol.send_broadcast(OutputSetReq(groups)).await?;
+    assert_eq!(protocol.resync().await?, 0);
+    for address in [DOWNSTREAM, REPEATER] {
+        let outputs = protocol
+            .send_request(address, OutputGetReq, |res: &OutputGetRes| Ok(res.0))
+            .await?;
+        assert_eq!(outputs, groups);
+    }
+    protocol.send_broadcast(RebootReq::FIRMWARE).await?;
+    assert_eq!(protocol.resync().await?, 0);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware; 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn forwards_fragments_of_any_size() -> Result<()> {
+    let (repeater_end, device_end) = tokio::io::duplex(1024);
+    tokio::spawn(reversing_device(device_end, MAX_FRAGMENT_SIZE, None));
+    let (_firmware, mut device) = Firmware::start_repeater_with(200_000, repeater_end);
+    let forwarding = Forwarding {
+        ranges: [
+            AddressRange::new(DOWNSTREAM..=DOWNSTREAM),
+            AddressRange::EMPTY,
+            AddressRange::EMPTY,
+            AddressRange::EMPTY,
+        ],
+    };
+    let protocol = device.protocol();
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, ForwardingSetReq(forwarding), |_| {
+            Ok(())
+        })
+        .await?;
+    // fragments of the largest size both ways
+    let payload: Vec<u8> = (0..3 * MAX_FRAGMENT_SIZE)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let mut expected = payload.clone();
+    expected.reverse();
+    let response = protocol
+        .send_fragmented(
+            DOWNSTREAM,
+            Command::InfoGet,
+            &payload,
+            MAX_FRAGMENT_SIZE,
+            Duration::ZERO,
+        )
+        .await?;
+    assert_eq!(response, expected);
+    assert_eq!(protocol.statistics().timeouts, 0);
+    Ok(())
+}
@@ -0,0 +1,1109 @@
+//! Every command sent by the tool, answered by the firmware running on the mock board.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use pico_iox16_firmware::{
+    mock::{
+        DIGITAL_AVAILABLE, DIGITAL_LEVELS, Flash, HARDWARE_REVISION, STAGING_SIZE, UNIQUE_ID,
+        raw_value,
+    },
+    nvm::{DEFAULT_BAUDRATE, OutputDefaults, SCHEMA_VERSION, UNCONFIGURED_ADDRESS},
+};
+use pico_iox16_integration::Firmware;
+use pico_iox16_protocol::{
+    AddressRange, BROADCAST_ADDRESS, BootSwapReq, CAPABILITY_DIGITAL_INPUTS, CHECKSUM, CheckReq,
+    CheckRes, Command, Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ErrorCode,
+    ForwardingGetReq, ForwardingGetRes, InputCalibration, InputGetCalibrationsReq,
+    InputGetCalibrationsRes, InputGetFullReq, InputGetFullRes, InputGetThresholdTimesReq,
+    InputGetThresholdTimesRes, InputGetThresholdsReq, InputGetThresholdsRes,
+    InputSetCalibrationsReq, InputSetThresholdsReq, InputThreshold, Message, MessageRef,
+    OutputGroup, OutputSetReq, PROTOCOL_VERSION, PROTOCOL_VERSION_MINOR, Parity, Power,
+    PowerGetReq, PowerGetRes, PowerSetReq, RebootMode, RebootReq, ResetCause, Response, StopBits,
+    Transport, USER_DATA_SIZE, UserDataReadReq, UserDataReadRes, UserDataSpan, UserDataWriteReq,
+    UserDataWriteRes, master_next, next_message,
+    settings::{Settings, Threshold},
+};
+use pico_iox16_tool::{
+    Error,
+    device::{Device, DeviceId, Failsafe, Outputs, ProtocolVersion},
+    dump,
+    events::{Direction, Snapshot},
+    firmware,
+};
+use zerocopy::IntoBytes as _;
+
+/// Reads the inputs until the input loop has sampled all of them at least once.
+async fn settled_inputs(device: &mut Device) -> Result<[i16; 16]> {
+    for _ in 0..100 {
+        let values = device.inputs().await?;
+        if values.iter().all(|&value| value != 0) {
+            return Ok(values);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    anyhow::bail!("The inputs were never sampled")
+}
+
+fn raw_values() -> [i16; 16] {
+    std::array::from_fn(|input| raw_value(input as u8) as i16)
+}
+
+#[tokio::test]
+async fn check_and_info() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    device
+        .protocol()
+        .send_request(address, CheckReq, |&CheckRes| Ok(()))
+        .await?;
+    let info = device.info().await?;
+    assert_eq!(info.unique_id(), Some(format!("{UNIQUE_ID:016x}").as_str()));
+    assert_eq!(info.version, (0, 1, 0));
+    info.check_protocol_version()?;
+    assert_eq!(info.config_schema_version, Some(SCHEMA_VERSION));
+    assert_eq!(info.config_schema_mismatch(), None);
+    let version = device.protocol_version().await?;
+    assert_eq!(
+        version,
+        ProtocolVersion {
+            major: PROTOCOL_VERSION,
+            minor: PROTOCOL_VERSION_MINOR,
+            capabilities: CAPABILITY_DIGITAL_INPUTS,
+        }
+    );
+    // fetched before the first request to the device
+    assert_eq!(device.protocol().protocol_version(address), Some(version));
+    assert_eq!(
+        device.device_id().await?,
+        DeviceId {
+            unique_id: info.unique_id().map(String::from),
+            hardware_revision: Some(HARDWARE_REVISION),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn raw_frames() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let protocol = device.protocol();
+    let sent_at = protocol.now_us();
+    protocol
+        .send_message(&Message::new_request(address, Command::Check, 42, CheckReq))
+        .await?;
+    let mut frame = [0; 64];
+    let received = protocol.receive(&mut frame).await?;
+    assert!(sent_at <= received.at_us && received.at_us <= protocol.now_us());
+    let (maybe_response, _) = master_next(&frame[..received.len]);
+    assert_eq!(maybe_response, Some((address, Response::Check(&CheckRes))));
+    // the response echoes the sequence number of the request
+    let (header, _) = next_message(&frame[..received.len]).0.unwrap();
+    assert_eq!(header.sequence, 42);
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejected_requests() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let protocol = device.protocol();
+    let mut frame = [0; 64];
+    // sends a request of `command` without payload
+    let mut exchange = async |command: u16| -> Result<_> {
+        let mut request = [0; 64];
+        let len = MessageRef::new(address, command, 0, &[])
+            .unwrap()
+            .write_to(&mut request)
+            .unwrap();
+        protocol.send(&request[..len]).await?;
+        let received = protocol.receive(&mut frame).await?;
+        let (maybe_response, _) = master_next(&frame[..received.len]);
+        Ok(maybe_response.map(|(address, response)| match response {
+            Response::Error(command, error) => (address, command, error.code),
+            _ => panic!("Unexpected response {response:?}"),
+        }))
+    };
+    assert_eq!(
+        exchange(Command::PowerSet.into()).await?,
+        Some((address, Command::PowerSet, ErrorCode::InvalidLength))
+    );
+    // the command is unknown to the master too, so the response doesn't parse
+    assert_eq!(exchange(0x1fff).await?, None);
+
+    // the firmware doesn't take fragmented requests
+    let err = protocol
+        .send_fragmented(
+            address,
+            Command::PowerSet,
+            &[0; 4],
+            4,
+            Duration::from_millis(1),
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Rejected {
+                command: Command::PowerSet,
+                code: ErrorCode::UnknownCommand
+            }
+        ),
+        "{err}"
+    );
+    // the device goes on answering
+    device.info().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn broadcast() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let groups = [OutputGroup {
+        duty_cycle: [0x4000.into(), 0.into()],
+        frequency: 1000.into(),
+    }; 8];
+    let protocol = device.protocol();
+    protocol.send_broadcast(OutputSetReq(groups)).await?;
+    // the device doesn't respond, but sets its outputs
+    assert_eq!(protocol.resync().await?, 0);
+    assert_eq!(device.outputs().await?, Outputs::from(&groups));
+
+    // commands that read something aren't broadcast
+    let err = device
+        .protocol()
+        .send_broadcast(CheckReq)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Invalid(_)), "{err}");
+    let err = device
+        .protocol()
+        .send_request(BROADCAST_ADDRESS, CheckReq, |_| Ok(()))
+        .await
+        .unwrap_err();
+    assert!(err.is_timeout(), "{err}");
+
+    // nor can a device take the broadcast address
+    let address = device.address();
+    let config = Config {
+        address: BROADCAST_ADDRESS.into(),
+        baudrate: DEFAULT_BAUDRATE.into(),
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+    let err = device
+        .protocol()
+        .send_request(address, ConfigSetReq(config), |_| Ok(()))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::Rejected {
+                command: Command::ConfigSet,
+                code: ErrorCode::InvalidPayload
+            }
+        ),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn pipelined_scan() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let mut scanned = Vec::new();
+    let found = device
+        .protocol()
+        .scan(UNCONFIGURED_ADDRESS - 8..=UNCONFIGURED_ADDRESS, |address| {
+            scanned.push(address)
+        })
+        .await?;
+    assert_eq!(found, [UNCONFIGURED_ADDRESS]);
+    assert_eq!(scanned.len(), 9);
+    // the device is free for the next request right away
+    device.info().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn config_survives_reboot() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    let config = device
+        .protocol()
+        .send_request(UNCONFIGURED_ADDRESS, ConfigGetReq, |res: &ConfigGetRes| {
+            Ok(res.0)
+        })
+        .await?;
+    assert_eq!(config.address.get(), UNCONFIGURED_ADDRESS);
+    assert_eq!(config.baudrate.get(), DEFAULT_BAUDRATE);
+
+    let config = Config {
+        address: 7.into(),
+        baudrate: 115_200.into(),
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+    };
+    let protocol = device.protocol();
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, ConfigSetReq(config), |_| Ok(()))
+        .await?;
+    // the new address only takes effect after a reboot
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, RebootReq::FIRMWARE, |_| Ok(()))
+        .await?;
+    let stored = protocol
+        .send_request(7, ConfigGetReq, |res: &ConfigGetRes| Ok(res.0))
+        .await?;
+    assert_eq!(stored, config);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_config_is_rejected() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    assert_eq!(
+        device.baudrates().await?,
+        pico_iox16_protocol::settings::BAUDRATES
+    );
+    let valid = Config {
+        address: 7.into(),
+        baudrate: 115_200.into(),
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+    };
+    let invalid = [
+        Config {
+            address: BROADCAST_ADDRESS.into(),
+            ..valid
+        },
+        Config {
+            address: UNCONFIGURED_ADDRESS.into(),
+            ..valid
+        },
+        Config {
+            baudrate: 300.into(),
+            ..valid
+        },
+        Config {
+            baudrate: 10_000_000.into(),
+            ..valid
+        },
+    ];
+    let protocol = device.protocol();
+    for config in invalid {
+        let err = protocol
+            .send_request(UNCONFIGURED_ADDRESS, ConfigSetReq(config), |_| Ok(()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Rejected {
+                    command: Command::ConfigSet,
+                    code: ErrorCode::InvalidPayload
+                }
+            ),
+            "{config:?}: {err}"
+        );
+    }
+    // nothing was stored
+    let stored = protocol
+        .send_request(UNCONFIGURED_ADDRESS, ConfigGetReq, |res: &ConfigGetRes| {
+            Ok(res.0)
+        })
+        .await?;
+    assert_eq!(stored.address.get(), UNCONFIGURED_ADDRESS);
+    assert_eq!(stored.baudrate.get(), DEFAULT_BAUDRATE);
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, ConfigSetReq(valid), |_| Ok(()))
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn corrupted_config_falls_back_to_defaults() -> Result<()> {
+    let flash = Flash::default();
+    // flip bits of the stored address, which comes first
+    flash.0.lock().unwrap()[0] ^= 0x07;
+    let (_firmware, mut device) = Firmware::start_with(flash);
+    let diagnostics = device.diagnostics().await?;
+    assert!(diagnostics.config_corrupted);
+    let config = device
+        .protocol()
+        .send_request(UNCONFIGURED_ADDRESS, ConfigGetReq, |res: &ConfigGetRes| {
+            Ok(res.0)
+        })
+        .await?;
+    assert_eq!(config.address.get(), UNCONFIGURED_ADDRESS);
+
+    // provisioning stores valid data again, the address takes effect after a reboot
+    let config = Config {
+        address: 7.into(),
+        ..config
+    };
+    device
+        .protocol()
+        .send_request(UNCONFIGURED_ADDRESS, ConfigSetReq(config), |_| Ok(()))
+        .await?;
+    assert!(!device.diagnostics().await?.config_corrupted);
+    Ok(())
+}
+
+#[tokio::test]
+async fn erased_config_falls_back_to_defaults() -> Result<()> {
+    // a write that was cut off after erasing the sector
+    let flash = Flash::default();
+    flash.0.lock().unwrap().fill(0xFF);
+    let (_firmware, mut device) = Firmware::start_with(flash);
+    assert!(device.diagnostics().await?.config_corrupted);
+    let config = device
+        .protocol()
+        .send_request(UNCONFIGURED_ADDRESS, ConfigGetReq, |res: &ConfigGetRes| {
+            Ok(res.0)
+        })
+        .await?;
+    assert_eq!(config.baudrate.get(), DEFAULT_BAUDRATE);
+    Ok(())
+}
+
+/// The settings stored in [`old_layout`].
+fn old_settings() -> Settings {
+    let mut settings = Settings::DEFAULT;
+    settings.calibrations[5].multiply = 3;
+    settings.thresholds[2] = Threshold::new(1000, 900, 0, 2);
+    settings
+}
+
+/// The output defaults stored in [`old_layout`] 4.
+const OLD_OUTPUT_DEFAULTS: OutputDefaults = OutputDefaults {
+    groups: [[8192, 0, 1000]; 8],
+    stored: 1,
+};
+
+/// Flash as a firmware with layout `version` left it, `None` for those before the integrity
+/// check: [`old_settings`], 3 brownouts, a sample interval of 50 ms and the fields the version
+/// added, followed by its integrity check.
+fn old_layout(version: Option<u16>) -> Flash {
+    let mut data = old_settings().as_bytes().to_vec();
+    data.extend(3u32.to_ne_bytes());
+    data.extend(50u32.to_ne_bytes());
+    if let Some(version) = version {
+        if version >= 2 {
+            let ranges: [[u16; 2]; 4] = [[0x10, 0x1F], [u16::MAX, 0], [u16::MAX, 0], [u16::MAX, 0]];
+            data.extend(ranges.as_bytes());
+        }
+        if version >= 3 {
+            data.extend(500u32.to_ne_bytes());
+            data.extend([16384u16; 16].as_bytes());
+        }
+        if version >= 4 {
+            data.extend(OLD_OUTPUT_DEFAULTS.as_bytes());
+        }
+        let checksum = CHECKSUM.checksum(&data);
+        data.extend(version.to_ne_bytes());
+        data.extend(checksum.to_ne_bytes());
+    }
+    let flash = Flash::default();
+    let mut nvm = flash.0.lock().unwrap();
+    nvm.fill(0xFF);
+    nvm[..data.len()].copy_from_slice(&data);
+    drop(nvm);
+    flash
+}
+
+/// Starts the firmware on [`old_layout`] `version` and checks that it keeps what was stored and
+/// defaults the fields added since.
+async fn check_migration(version: Option<u16>) -> Result<()> {
+    let (_firmware, mut device) = Firmware::start_with(old_layout(version));
+    let address = device.address();
+    // without the check, nothing past the sample interval was stored
+    let version = version.unwrap_or(1);
+    let diagnostics = device.diagnostics().await?;
+    assert!(!diagnostics.config_corrupted);
+    assert_eq!(diagnostics.brownouts, 3);
+
+    let dump = dump::fetch(device.protocol(), address).await?;
+    assert_eq!(dump.settings, old_settings());
+    let expected = if version >= 4 {
+        OLD_OUTPUT_DEFAULTS
+    } else {
+        OutputDefaults::NONE
+    };
+    assert_eq!(dump.output_defaults, Some(expected));
+
+    let protocol = device.protocol();
+    let sample_interval = protocol
+        .send_request(address, PowerGetReq, |res: &PowerGetRes| {
+            Ok(res.0.sample_interval_ms.get())
+        })
+        .await?;
+    assert_eq!(sample_interval, 50);
+    let forwarding = protocol
+        .send_request(
+            address,
+            ForwardingGetReq,
+            |res: &ForwardingGetRes| Ok(res.0),
+        )
+        .await?;
+    let mut expected = [AddressRange::EMPTY; 4];
+    if version >= 2 {
+        expected[0] = AddressRange::new(0x10..=0x1F);
+    }
+    assert_eq!(forwarding.ranges, expected);
+
+    let failsafe = device.failsafe().await?;
+    if version >= 3 {
+        assert_eq!(failsafe.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(failsafe.duty_cycles, [50.0; 16]);
+    } else {
+        assert_eq!(failsafe.timeout, None);
+        assert_eq!(failsafe.duty_cycles, [0.0; 16]);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn migrate_unchecked_layout() -> Result<()> {
+    check_migration(None).await
+}
+
+#[tokio::test]
+async fn migrate_layout_1() -> Result<()> {
+    check_migration(Some(1)).await
+}
+
+#[tokio::test]
+async fn migrate_layout_2() -> Result<()> {
+    check_migration(Some(2)).await
+}
+
+#[tokio::test]
+async fn migrate_layout_3() -> Result<()> {
+    check_migration(Some(3)).await
+}
+
+#[tokio::test]
+async fn migrate_layout_4() -> Result<()> {
+    check_migration(Some(4)).await
+}
+
+#[tokio::test]
+async fn fix_drift() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    let expected: toml::Value = toml::from_str(
+        "[config]\n\
+         address = 9\n\
+         [[thresholds]]\n\
+         threshold_high = 1234\n",
+    )?;
+    let protocol = device.protocol();
+    let actual = dump::fetch(protocol, UNCONFIGURED_ADDRESS).await?;
+    assert_eq!(dump::diff(&expected, &actual)?.len(), 2);
+
+    let desired = dump::merge(&expected, &actual)?;
+    assert_eq!(desired.settings.calibrations, actual.settings.calibrations);
+    let written = dump::apply(protocol, UNCONFIGURED_ADDRESS, &actual, &desired).await?;
+    assert_eq!(
+        written,
+        dump::Written {
+            calibrations: false,
+            thresholds: true,
+            output_defaults: false,
+            config: true,
+        }
+    );
+    protocol
+        .send_request(UNCONFIGURED_ADDRESS, RebootReq::FIRMWARE, |_| Ok(()))
+        .await?;
+    let fixed = dump::fetch(protocol, 9).await?;
+    assert!(dump::diff(&expected, &fixed)?.is_empty());
+    assert_eq!(fixed, desired);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn restore_output_defaults() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let mut defaults = device.outputs().await?;
+    defaults.duty_cycles[3] = 50.0;
+    defaults.frequencies[4] = 300;
+    device.set_output_defaults(&defaults).await?;
+    let saved = dump::fetch(device.protocol(), address).await?;
+    let groups: [OutputGroup; 8] = (&defaults).into();
+    assert_eq!(saved.output_defaults, Some(groups.into()));
+    let expected = toml::Value::try_from(saved)?;
+
+    // a fresh device gets them from the dump
+    let (_firmware, mut device) = Firmware::start();
+    let protocol = device.protocol();
+    let actual = dump::fetch(protocol, address).await?;
+    assert_eq!(actual.output_defaults, Some(OutputDefaults::NONE));
+    assert!(!dump::diff(&expected, &actual)?.is_empty());
+    let desired = dump::merge(&expected, &actual)?;
+    let written = dump::apply(protocol, address, &actual, &desired).await?;
+    assert_eq!(
+        written,
+        dump::Written {
+            output_defaults: true,
+            ..dump::Written::default()
+        }
+    );
+    let restored = dump::fetch(protocol, address).await?;
+    assert!(dump::diff(&expected, &restored)?.is_empty());
+    assert_eq!(restored.output_defaults, saved.output_defaults);
+    Ok(())
+}
+
+#[tokio::test]
+async fn outputs() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    assert_eq!(device.outputs().await?, Outputs::default());
+    let mut groups = [OutputGroup {
+        duty_cycle: [0.into(); 2],
+        frequency: 1000.into(),
+    }; 8];
+    for (i, group) in groups.iter_mut().enumerate() {
+        let i = i as u16;
+        group.duty_cycle = [(i * 1000).into(), (0x8000 - i * 1000).into()];
+        group.frequency = (100 + i * 100).into();
+    }
+    let outputs = Outputs::from(&groups);
+    device.set_outputs(&outputs).await?;
+    assert_eq!(device.outputs().await?, outputs);
+
+    // only the selected outputs change, whatever the others are set to in the request
+    let mut changed = Outputs::from(
+        &[OutputGroup {
+            duty_cycle: [0x2000.into(); 2],
+            frequency: 1000.into(),
+        }; 8],
+    );
+    device
+        .set_duty_cycles(1 << 3 | 1 << 12, &changed.duty_cycles)
+        .await?;
+    let mut expected = outputs;
+    for output in [3, 12] {
+        expected.duty_cycles[output] = changed.duty_cycles[output];
+    }
+    assert_eq!(device.outputs().await?, expected);
+    changed.duty_cycles = [0.0; 16];
+    device
+        .set_duty_cycles(u16::MAX, &changed.duty_cycles)
+        .await?;
+    assert_eq!(
+        device.outputs().await?,
+        Outputs {
+            duty_cycles: [0.0; 16],
+            frequencies: outputs.frequencies,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn failsafe() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    assert_eq!(device.failsafe().await?.timeout, None);
+    let mut outputs = device.outputs().await?;
+    outputs.duty_cycles = [50.0; 16];
+    device.set_outputs(&outputs).await?;
+    let mut duty_cycles = [0.0; 16];
+    duty_cycles[0] = 100.0;
+    duty_cycles[5] = 25.0;
+    let failsafe = Failsafe {
+        timeout: Some(Duration::from_millis(300)),
+        duty_cycles,
+    };
+    device.set_failsafe(&failsafe).await?;
+    assert_eq!(device.failsafe().await?, failsafe);
+
+    // requests keep the outputs as they are
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        device.info().await?;
+    }
+    assert_eq!(device.outputs().await?, outputs);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let forced = device.outputs().await?;
+    assert_eq!(forced.duty_cycles, duty_cycles);
+    assert_eq!(forced.frequencies, outputs.frequencies);
+
+    // the failsafe is stored, and disabled again with a timeout of 0
+    let address = device.address();
+    device
+        .protocol()
+        .send_request(address, RebootReq::FIRMWARE, |_| Ok(()))
+        .await?;
+    assert_eq!(device.failsafe().await?, failsafe);
+    device
+        .set_failsafe(&Failsafe {
+            timeout: None,
+            duty_cycles: [0.0; 16],
+        })
+        .await?;
+    device.set_outputs(&outputs).await?;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(device.outputs().await?, outputs);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn output_defaults() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let reboot = async |device: &mut Device| {
+        device
+            .protocol()
+            .send_request(address, RebootReq::FIRMWARE, |_| Ok(()))
+            .await
+    };
+    // without stored defaults the outputs start as the board starts them
+    let initial = device.outputs().await?;
+    let mut outputs = initial;
+    outputs.duty_cycles = std::array::from_fn(|output| output as f64 * 5.0);
+    device.set_outputs(&outputs).await?;
+    reboot(&mut device).await?;
+    assert_eq!(device.outputs().await?, initial);
+
+    let mut defaults = initial;
+    defaults.duty_cycles[2] = 25.0;
+    defaults.duty_cycles[15] = 100.0;
+    defaults.frequencies[1] = 200;
+    device.set_output_defaults(&defaults).await?;
+    // storing them leaves the current outputs alone
+    assert_eq!(device.outputs().await?, initial);
+    device.set_outputs(&outputs).await?;
+    reboot(&mut device).await?;
+    assert_eq!(device.outputs().await?, defaults);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware; 2]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn inputs() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    assert_eq!(settled_inputs(&mut device).await?, raw_values());
+    // wait for samples of every input again
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let address = device.address();
+    let stats = device
+        .protocol()
+        .send_request(address, InputGetFullReq, |res: &InputGetFullRes| {
+            Ok(res.stats)
+        })
+        .await?;
+    for (stat, value) in stats.iter().zip(raw_values()) {
+        assert_eq!((stat.min.get(), stat.max.get()), (value, value));
+    }
+    // only whole sweeps over all inputs are reported
+    assert!(stats[0].count.get() > 0);
+    assert!(
+        stats.iter().all(|stat| stat.count == stats[0].count),
+        "{stats:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn reports() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    settled_inputs(&mut device).await?;
+    device
+        .set_report_interval(Duration::from_millis(10))
+        .await?;
+    let next_report = async |device: &mut Device| {
+        Ok::<_, anyhow::Error>(
+            tokio::time::timeout(Duration::from_secs(1), device.protocol().next_report()).await??,
+        )
+    };
+    let first = next_report(&mut device).await?;
+    assert_eq!(first.address, address);
+    assert_eq!(first.values, raw_values());
+    // requests still work, the reports arriving meanwhile are kept
+    assert_eq!(device.inputs().await?, raw_values());
+    let mut sequence = first.sequence;
+    for _ in 0..5 {
+        let report = next_report(&mut device).await?;
+        assert_eq!(report.sequence, sequence.wrapping_add(1));
+        assert_eq!(report.values, raw_values());
+        sequence = report.sequence;
+    }
+
+    // the reports sent before the response were taken in by the request
+    device.set_report_interval(Duration::ZERO).await?;
+    while let Ok(report) =
+        tokio::time::timeout(Duration::ZERO, device.protocol().next_report()).await
+    {
+        assert_eq!(report?.sequence, sequence.wrapping_add(1));
+        sequence = sequence.wrapping_add(1);
+    }
+    let after = tokio::time::timeout(Duration::from_millis(50), device.protocol().next_report());
+    assert!(after.await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn calibrations() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let calibrations: [InputCalibration; 16] = std::array::from_fn(|input| InputCalibration {
+        multiply: 2.into(),
+        divide: 1.into(),
+        add: (input as i16).into(),
+        min: i16::MIN.into(),
+        max: i16::MAX.into(),
+    });
+    let protocol = device.protocol();
+    protocol
+        .send_request(address, InputSetCalibrationsReq(calibrations), |_| Ok(()))
+        .await?;
+    let stored = protocol
+        .send_request(
+            address,
+            InputGetCalibrationsReq,
+            |res: &InputGetCalibrationsRes| Ok(res.0),
+        )
+        .await?;
+    assert_eq!(stored, calibrations);
+
+    // discard the averages from before the calibration
+    settled_inputs(&mut device).await?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let expected: [i16; 16] = std::array::from_fn(|input| raw_values()[input] * 2 + input as i16);
+    assert_eq!(device.inputs().await?, expected);
+    Ok(())
+}
+
+#[tokio::test]
+async fn thresholds() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    // the upper half of the inputs is above the thresholds, the lower half below
+    let middle = raw_value(8) as i16;
+    let thresholds = [InputThreshold {
+        threshold_high: (middle - 1).into(),
+        threshold_low: middle.into(),
+        debounce_time_us: 0.into(),
+        debounce_count: 0.into(),
+    }; 16];
+    let protocol = device.protocol();
+    protocol
+        .send_request(address, InputSetThresholdsReq(thresholds), |_| Ok(()))
+        .await?;
+    let stored = protocol
+        .send_request(
+            address,
+            InputGetThresholdsReq,
+            |res: &InputGetThresholdsRes| Ok(res.0),
+        )
+        .await?;
+    assert_eq!(stored, thresholds);
+
+    settled_inputs(&mut device).await?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(device.threshold_states().await?, (0xff00, 0x00ff));
+    let times = device
+        .protocol()
+        .send_request(
+            address,
+            InputGetThresholdTimesReq,
+            |res: &InputGetThresholdTimesRes| Ok(*res),
+        )
+        .await?;
+    for (input, crossings) in times.inputs.iter().enumerate() {
+        let (crossed, other) = if input >= 8 {
+            (crossings.last_high.get(), crossings.last_low.get())
+        } else {
+            (crossings.last_low.get(), crossings.last_high.get())
+        };
+        assert!(other < crossed && crossed <= times.now.get());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn queued_crossings() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let set_thresholds = async |device: &mut Device, high: i16, low: i16| {
+        let thresholds = [InputThreshold {
+            threshold_high: high.into(),
+            threshold_low: low.into(),
+            debounce_time_us: 0.into(),
+            debounce_count: 0.into(),
+        }; 16];
+        device
+            .protocol()
+            .send_request(address, InputSetThresholdsReq(thresholds), |_| Ok(()))
+            .await?;
+        settled_inputs(device).await?;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        anyhow::Ok(())
+    };
+    // as in `thresholds`, every input crosses once
+    let middle = raw_value(8) as i16;
+    set_thresholds(&mut device, middle - 1, middle).await?;
+    let snapshot = Snapshot::fetch(device.protocol(), address).await?;
+    let (crossings, lost) = snapshot.take_queued(device.protocol(), address).await?;
+    assert_eq!(lost, 0);
+    assert_eq!(crossings.len(), 16, "{crossings:?}");
+    assert!(crossings.is_sorted_by_key(|crossing| crossing.time));
+    for crossing in &crossings {
+        let direction = if crossing.channel >= 8 {
+            Direction::High
+        } else {
+            Direction::Low
+        };
+        assert_eq!(crossing.direction, direction);
+        assert!(0 < crossing.time && crossing.time <= snapshot.now);
+    }
+    let (crossings, _) = snapshot.take_queued(device.protocol(), address).await?;
+    assert!(crossings.is_empty());
+
+    // all inputs cross back and forth, more often than the queue holds, apart from the upper
+    // half at first, which is above already
+    for _ in 0..5 {
+        set_thresholds(&mut device, i16::MIN, i16::MIN).await?;
+        set_thresholds(&mut device, i16::MAX, i16::MAX).await?;
+    }
+    let (crossings, lost) = snapshot.take_queued(device.protocol(), address).await?;
+    assert_eq!(crossings.len(), 64);
+    assert_eq!(lost, 8 + 9 * 16 - 64);
+    // the oldest are kept
+    assert!(
+        crossings[..8]
+            .iter()
+            .all(|crossing| crossing.direction == Direction::High && crossing.channel < 8)
+    );
+    assert!(
+        crossings[8..24]
+            .iter()
+            .all(|crossing| crossing.direction == Direction::Low)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn debounce() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    // as in `thresholds`, but debounced for longer than the test runs
+    let middle = raw_value(8) as i16;
+    let thresholds = [InputThreshold {
+        threshold_high: (middle - 1).into(),
+        threshold_low: middle.into(),
+        debounce_time_us: 60_000_000.into(),
+        debounce_count: 0.into(),
+    }; 16];
+    device
+        .protocol()
+        .send_request(address, InputSetThresholdsReq(thresholds), |_| Ok(()))
+        .await?;
+
+    settled_inputs(&mut device).await?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    for (input, debounce) in device.debounce().await?.iter().enumerate() {
+        if input >= 8 {
+            assert!(debounce.pending_high && !debounce.pending_low);
+            assert!(debounce.above_count > 0 && debounce.below_count == 0);
+            assert!(debounce.raw_high < debounce.debounced_high);
+        } else {
+            assert!(debounce.pending_low && !debounce.pending_high);
+            assert!(debounce.below_count > 0 && debounce.above_count == 0);
+            assert!(debounce.raw_low < debounce.debounced_low);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn diagnostics_and_digital() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let diagnostics = device.diagnostics().await?;
+    assert_eq!(diagnostics.reset_cause, ResetCause::PowerOn);
+    assert!(!diagnostics.config_corrupted);
+    assert_eq!(diagnostics.brownouts, 0);
+    assert_eq!(diagnostics.conversion_errors, [0; 2]);
+    assert_eq!(diagnostics.overruns, 0);
+    let digital = device.digital_inputs().await?;
+    assert_eq!(digital.available, DIGITAL_AVAILABLE);
+    assert_eq!(digital.levels, DIGITAL_LEVELS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn power() -> Result<()> {
+    let (_firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let protocol = device.protocol();
+    let get = async |protocol: &mut pico_iox16_tool::Protocol| {
+        protocol
+            .send_request(address, PowerGetReq, |res: &PowerGetRes| {
+                Ok(res.0.sample_interval_ms.get())
+            })
+            .await
+    };
+    assert_eq!(get(protocol).await?, 0);
+    let power = Power {
+        sample_interval_ms: 50.into(),
+    };
+    protocol
+        .send_request(address, PowerSetReq(power), |_| Ok(()))
+        .await?;
+    assert_eq!(get(protocol).await?, 50);
+    // the inputs are still sampled, just less often
+    assert_eq!(settled_inputs(&mut device).await?, raw_values());
+
+    // the sample intervals count from the previous query
+    device.diagnostics().await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let diagnostics = device.diagnostics().await?;
+    for intervals in diagnostics.sample_intervals {
+        let intervals = intervals.expect("The input was not sampled");
+        assert!(intervals.min >= Duration::from_millis(40), "{intervals:?}");
+        assert!(intervals.min <= intervals.mean && intervals.mean <= intervals.max);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_data() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    let address = device.address();
+    let protocol = device.protocol();
+    let read = async |protocol: &mut pico_iox16_tool::Protocol, offset, len| {
+        protocol
+            .send_request(
+                address,
+                UserDataReadReq(UserDataSpan::new(offset, len)),
+                |res: &UserDataReadRes| Ok((res.span, res.data().to_vec())),
+            )
+            .await
+    };
+    let (span, data) = read(protocol, 0, USER_DATA_SIZE as u16).await?;
+    assert_eq!(span, UserDataSpan::new(0, USER_DATA_SIZE as u16));
+    assert_eq!(data, [0xFF; USER_DATA_SIZE]);
+
+    let request = UserDataWriteReq::new(250, b"sensor").unwrap();
+    let span = protocol
+        .send_request(address, request, |UserDataWriteRes(span)| Ok(*span))
+        .await?;
+    assert_eq!(span, UserDataSpan::new(250, 6));
+    assert!(UserDataWriteReq::new(251, b"sensor").is_none());
+    // what lies beyond the user data is left out
+    let request = UserDataWriteReq {
+        span: UserDataSpan::new(254, 4),
+        ..UserDataWriteReq::new(0, b"SENS").unwrap()
+    };
+    let span = protocol
+        .send_request(address, request, |UserDataWriteRes(span)| Ok(*span))
+        .await?;
+    assert_eq!(span, UserDataSpan::new(254, 2));
+
+    protocol
+        .send_request(address, RebootReq::FIRMWARE, |_| Ok(()))
+        .await?;
+    let (span, data) = read(protocol, 248, 100).await?;
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware]);
+    assert_eq!(span, UserDataSpan::new(248, 8));
+    assert_eq!(data, b"\xFF\xFFsensSE");
+    let (span, data) = read(protocol, 300, 4).await?;
+    assert_eq!(span, UserDataSpan::new(256, 0));
+    assert!(data.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn reboot_into_bootloader() -> Result<()> {
+    let (firmware, mut device) = Firmware::start();
+    let address = device.address();
+    device
+        .protocol()
+        .send_request(address, RebootReq::new(RebootMode::Bootloader), |_| Ok(()))
+        .await?;
+    // the mock board starts the firmware again either way
+    assert_eq!(device.diagnostics().await?.reset_cause, ResetCause::Other);
+    assert_eq!(firmware.reboots(), [RebootMode::Bootloader]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn firmware_update() -> Result<()> {
+    let flash = Flash::default();
+    let (firmware, mut device) = Firmware::start_with(flash.clone());
+    let address = device.address();
+    // the second chunk stays erased and isn't sent
+    let image: Vec<u8> = (0..5000u32)
+        .map(|i| {
+            if (256..512).contains(&i) {
+                0xFF
+            } else {
+                i as u8
+            }
+        })
+        .collect();
+    let protocol = device.protocol();
+
+    let bad = BootSwapReq {
+        len: (image.len() as u32).into(),
+        checksum: 0.into(),
+    };
+    let err = protocol
+        .send_request(address, bad, |_| Ok(()))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Rejected {
+            command: Command::BootSwap,
+            code: ErrorCode::InvalidPayload,
+        }
+    ));
+    let oversize = vec![0; STAGING_SIZE as usize + 1];
+    let err = firmware::update(protocol, address, &oversize, |_| {})
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Invalid(_)));
+
+    let mut written = 0;
+    let info = firmware::update(protocol, address, &image, |bytes| written = bytes).await?;
+    assert_eq!(written, image.len());
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(*flash.3.lock().unwrap(), image);
+    assert_eq!(firmware.reboots(), [RebootMode::Firmware]);
+    Ok(())
+}
+
+#[test]
+fn firmware_image_from_uf2() {
+    let block = |address: u32, flags: u32, data: &[u8]| {
+        let mut block = [0u8; 512];
+        let words = [0x0A32_4655, 0x9E5D_5157, flags, address, data.len() as u32];
+        for (i, word) in words.into_iter().enumerate() {
+            block[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        block[32..32 + data.len()].copy_from_slice(data);
+        block[508..].copy_from_slice(&0x0AB1_6F30u32.to_le_bytes());
+        block
+    };
+    let uf2 = [
+        block(0x1000_0100, 0, &[2; 256]),
+        block(0x1000_0000, 0, &[1; 256]),
+        // family blocks for other chips are skipped
+        block(0x1000_0200, 1, &[9; 256]),
+        // the configuration after a gap is left out
+        block(0x100F_F000, 0, &[3; 256]),
+    ]
+    .concat();
+    let image = firmware::parse_uf2(&uf2).unwrap();
+    assert_eq!(image, [[1; 256], [2; 256]].concat());
+    assert!(firmware::parse_uf2(&uf2[1..]).is_err());
+}
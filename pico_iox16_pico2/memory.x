@@ -2,10 +2,30 @@ MEMORY {
     /*
      * The RP2350 has either external or internal flash.
      *
-     * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
+     * 2 MiB is a safe default here, although a Pico 2 has 4 MiB. The
+     * image gets half of what the data below leaves.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2044K
-    CONFIG : ORIGIN = 0x10200000 - 4K, LENGTH = 4K
+    FLASH : ORIGIN = 0x10000000, LENGTH = 1004K
+    /*
+     * A new image received over the bus, copied over the running one
+     * once it is complete, see src/nvm.rs. As large as the image.
+     */
+    STAGING : ORIGIN = 0x10000000 + 1004K, LENGTH = 1004K
+    /*
+     * The record of the last panic, kept until the next boot reports it,
+     * see src/panic.rs.
+     */
+    PANIC : ORIGIN = 0x10200000 - 36K, LENGTH = 4K
+    /*
+     * The host's user data, see src/nvm.rs. Not part of the image, so
+     * that flashing a new firmware keeps it.
+     */
+    USER_DATA : ORIGIN = 0x10200000 - 40K, LENGTH = 4K
+    /*
+     * Nonvolatile configuration: two journal sectors followed by six data
+     * sectors that are written in turn, see src/nvm.rs.
+     */
+    CONFIG : ORIGIN = 0x10200000 - 32K, LENGTH = 32K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -76,6 +96,15 @@ SECTIONS {
     .config : ALIGN(4096) {
         KEEP(*(.config));
     } > CONFIG
+    .panic_record : ALIGN(4096) {
+        KEEP(*(.panic_record));
+    } > PANIC
+    .user_data (NOLOAD) : ALIGN(4096) {
+        KEEP(*(.user_data));
+    } > USER_DATA
+    .staging (NOLOAD) : ALIGN(4096) {
+        KEEP(*(.staging));
+    } > STAGING
 }
 
 PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
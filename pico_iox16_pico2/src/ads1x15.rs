@@ -0,0 +1,213 @@
+//! Inputs read by an external ADS1115 or ADS1015 on I2C instead of the RP2350's ADC, for a
+//! higher resolution or for differential measurements.
+//!
+//! The outputs of the multiplexers of the left and right half of the board go to AIN0 and AIN1.
+//! The chip converts them one after the other in single-shot mode, either against ground or,
+//! for differential measurements, against AIN3. Both chips have the same full scale of ±4.096 V,
+//! the ADS1015 just leaves the lower four bits zero. Single-ended samples are the conversion
+//! results, negative ones clamped to 0. Differential samples are offset by 0x8000, so that the
+//! inputs' calibrations need to subtract 32768 to get the signed difference back.
+
+use core::convert::Infallible;
+
+use defmt::warn;
+use embedded_hal::{digital::OutputPin, i2c::I2c};
+use fugit::{Instant, MicrosDurationU64};
+use pico_iox16_firmware::input::{InputError, InputErrors};
+use rp235x_hal::{
+    Timer,
+    gpio::{FunctionNull, FunctionSio, Pin, PinId, PullNone, PullType, SioOutput, ValidFunction},
+    timer::CopyableTimer0,
+};
+
+use crate::runtime::Board;
+
+/// The address with ADDR tied to ground.
+pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+const REG_CONVERSION: u8 = 0x00;
+const REG_CONFIG: u8 = 0x01;
+/// Start a single conversion, ±4.096 V full scale, single-shot mode, fastest data rate and the
+/// comparator disabled. The multiplexer is or'ed in.
+const CONFIG: u16 = 0x8000 | 0b001 << 9 | 0x0100 | 0b111 << 5 | 0b11;
+
+/// Time of a conversion at the fastest data rate, with room for the ±10% of the chip's
+/// oscillator and its wake-up from power-down.
+#[cfg(not(feature = "ads1015"))]
+const CONVERSION: MicrosDurationU64 = MicrosDurationU64::micros(1_300);
+#[cfg(feature = "ads1015")]
+const CONVERSION: MicrosDurationU64 = MicrosDurationU64::micros(350);
+
+/// What the inputs are measured against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[allow(dead_code)]
+pub enum Mux {
+    /// AIN0 and AIN1 against ground.
+    SingleEnded,
+    /// AIN0 and AIN1 against AIN3, e.g. a reference voltage in the middle of the range.
+    Differential,
+}
+impl Mux {
+    /// The multiplexer bits of the config register for the left or right half of the board.
+    fn bits(self, right: bool) -> u16 {
+        let mux = match (self, right) {
+            (Mux::SingleEnded, false) => 0b100,
+            (Mux::SingleEnded, true) => 0b101,
+            (Mux::Differential, false) => 0b001,
+            (Mux::Differential, true) => 0b010,
+        };
+        mux << 12
+    }
+}
+
+pub struct Ads1x15Input<Sel0: PinId, Sel1: PinId, Sel2: PinId, I2C: I2c> {
+    sel0: Pin<Sel0, FunctionSio<SioOutput>, PullNone>,
+    sel1: Pin<Sel1, FunctionSio<SioOutput>, PullNone>,
+    sel2: Pin<Sel2, FunctionSio<SioOutput>, PullNone>,
+    i2c: I2C,
+    address: u8,
+    mux: Mux,
+    timer: Timer<CopyableTimer0>,
+    /// The half of the board being converted, right if set, and when the result is ready
+    converting: Option<(bool, Instant<u64, 1, 1_000_000>)>,
+    /// Whether the running conversion was started before the last `discard`
+    stale: bool,
+    /// The sample of the left half, waiting for the one of the right half
+    left: u16,
+    /// I2C errors count as failed conversions of the half being converted
+    errors: InputErrors,
+}
+impl<
+    Sel0: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    Sel1: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    Sel2: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    I2C: I2c,
+> Ads1x15Input<Sel0, Sel1, Sel2, I2C>
+{
+    pub fn new<Pull0: PullType, Pull1: PullType, Pull2: PullType>(
+        sel0: Pin<Sel0, FunctionNull, Pull0>,
+        sel1: Pin<Sel1, FunctionNull, Pull1>,
+        sel2: Pin<Sel2, FunctionNull, Pull2>,
+        i2c: I2C,
+        address: u8,
+        mux: Mux,
+        timer: Timer<CopyableTimer0>,
+    ) -> Self {
+        Self {
+            sel0: sel0
+                .into_push_pull_output_in_state(false.into())
+                .into_pull_type::<PullNone>(),
+            sel1: sel1
+                .into_push_pull_output_in_state(false.into())
+                .into_pull_type::<PullNone>(),
+            sel2: sel2
+                .into_push_pull_output_in_state(false.into())
+                .into_pull_type::<PullNone>(),
+            i2c,
+            address,
+            mux,
+            timer,
+            converting: None,
+            stale: false,
+            left: 0,
+            errors: InputErrors::default(),
+        }
+    }
+
+    fn start(&mut self, right: bool) -> Result<(), I2C::Error> {
+        let config = CONFIG | self.mux.bits(right);
+        let [high, low] = config.to_be_bytes();
+        self.i2c.write(self.address, &[REG_CONFIG, high, low])?;
+        self.converting = Some((right, self.timer.get_counter() + CONVERSION));
+        Ok(())
+    }
+
+    fn result(&mut self) -> Result<u16, I2C::Error> {
+        let mut bytes = [0; 2];
+        self.i2c
+            .write_read(self.address, &[REG_CONVERSION], &mut bytes)?;
+        let value = i16::from_be_bytes(bytes);
+        Ok(match self.mux {
+            Mux::SingleEnded => value.max(0) as u16,
+            Mux::Differential => value as u16 ^ 0x8000,
+        })
+    }
+
+    /// Advances the conversions, returning a pair once both halves are converted.
+    fn poll(&mut self) -> nb::Result<[u16; 2], I2C::Error> {
+        let Some((right, ready)) = self.converting else {
+            self.start(false)?;
+            return Err(nb::Error::WouldBlock);
+        };
+        if self.timer.get_counter() < ready {
+            return Err(nb::Error::WouldBlock);
+        }
+        let value = self.result()?;
+        if core::mem::take(&mut self.stale) {
+            self.start(false)?;
+            return Err(nb::Error::WouldBlock);
+        }
+        // keep the chip busy, so that the next pair is ready as soon as possible
+        self.start(!right)?;
+        if right {
+            Ok([self.left, value])
+        } else {
+            self.left = value;
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+impl<
+    Sel0: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    Sel1: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    Sel2: PinId + ValidFunction<FunctionSio<SioOutput>>,
+    I2C: I2c,
+> pico_iox16_firmware::input::Input<Board> for Ads1x15Input<Sel0, Sel1, Sel2, I2C>
+{
+    type Error = Infallible;
+    const SETTLE_TIME_US: u32 = crate::board::MUX_SETTLE_TIME_US;
+    const CHANNELS: [[u8; 8]; 2] = crate::board::MUX_CHANNELS;
+    fn select0(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.sel0.set_state(value.into()).map_err(nb::Error::Other)
+    }
+    fn select1(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.sel1.set_state(value.into()).map_err(nb::Error::Other)
+    }
+    fn select2(&mut self, value: bool) -> nb::Result<(), Self::Error> {
+        self.sel2.set_state(value.into()).map_err(nb::Error::Other)
+    }
+
+    fn discard(&mut self) -> nb::Result<(), Self::Error> {
+        // a conversion can't be aborted, so its result is dropped once it is ready
+        self.stale = self.converting.is_some();
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [[u16; 2]]) -> nb::Result<usize, InputError<Self::Error>> {
+        let Some(pair) = buf.first_mut() else {
+            return Ok(0);
+        };
+        match self.poll() {
+            Ok(value) => {
+                *pair = value;
+                Ok(1)
+            }
+            Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(nb::Error::Other(err)) => {
+                warn!("I2C error of the ADC: {}", defmt::Debug2Format(&err));
+                let right = self.converting.is_some_and(|(right, _)| right);
+                let errors = &mut self.errors.conversion_errors[usize::from(right)];
+                *errors = errors.saturating_add(1);
+                // it is unknown whether a conversion was started, so give it the time to finish
+                // and start over, which also keeps a missing chip from hogging the loop
+                self.converting = Some((true, self.timer.get_counter() + CONVERSION));
+                self.stale = true;
+                Err(nb::Error::Other(InputError::RecoverableError))
+            }
+        }
+    }
+
+    fn errors(&self) -> InputErrors {
+        self.errors
+    }
+}
@@ -0,0 +1,369 @@
+//! Assignment of the GPIOs to their functions on the carrier board.
+//!
+//! The outputs always take GP0 to GP15, one PWM channel each. Everything else is listed in the
+//! pin maps below, so that a board that routes differently needs a new map instead of changes to
+//! `main.rs`. Spare GPIOs at the start of a map are read as digital inputs with the given pull.
+
+use rp235x_hal::gpio::{
+    DynPullType, FunctionNull, FunctionSioInput, Pin, Pins, PullDown, PullUp, bank0,
+};
+
+#[cfg(feature = "mcp4922")]
+use core::cell::RefCell;
+
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+use fugit::HertzU32;
+#[cfg(feature = "mcp4922")]
+use fugit::RateExtU32 as _;
+#[cfg(feature = "mcp4922")]
+use rp235x_hal::{
+    gpio::FunctionSioOutput,
+    gpio::FunctionSpi,
+    spi::{Enabled, Spi},
+};
+#[cfg(feature = "pio-pwm")]
+use rp235x_hal::{
+    gpio::{FunctionPio1, PinId, ValidFunction},
+    pio::{PIOExt as _, SM0, SM1, SM2, SM3},
+};
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+use rp235x_hal::{
+    pac,
+    pwm::{Pwm2, Pwm3, Pwm4, Pwm5, Slices},
+};
+
+#[cfg(feature = "mcp4922")]
+use crate::dac::{Mcp4922, Mcp4922Chip};
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+use crate::output::{Output, PwmGroup, pwm_group};
+#[cfg(feature = "pio-pwm")]
+use crate::pio_pwm::{self, PioPwm};
+use crate::{digital::DigitalPin, output::OutputPins};
+
+/// Revision of the carrier board the pin maps are for, reported by `DeviceIdGet`. Increased with
+/// every change to the board that the host may need to know about.
+pub const HARDWARE_REVISION: u16 = 1;
+
+macro_rules! pin_map {
+    (
+        digital: [$($(#[$dattr:meta])* $dgpio:ident: $pull:ident,)*],
+        $($(#[$attr:meta])* $name:ident: $gpio:ident as $id:ident,)*
+    ) => {
+        /// Number of spare GPIOs read as digital inputs.
+        pub const DIGITAL_INPUTS: usize = [$($(#[$dattr])* stringify!($dgpio),)*].len();
+
+        pub struct BoardPins {
+            pub outputs: OutputPins,
+            $($(#[$attr])* pub $name: Pin<bank0::$id, FunctionNull, PullDown>,)*
+            pub digital: [DigitalPin; DIGITAL_INPUTS],
+        }
+
+        pub fn take(pins: Pins) -> BoardPins {
+            let Pins {
+                gpio0,
+                gpio1,
+                gpio2,
+                gpio3,
+                gpio4,
+                gpio5,
+                gpio6,
+                gpio7,
+                gpio8,
+                gpio9,
+                gpio10,
+                gpio11,
+                gpio12,
+                gpio13,
+                gpio14,
+                gpio15,
+                $($(#[$attr])* $gpio,)*
+                $($(#[$dattr])* $dgpio,)*
+                ..
+            } = pins;
+            BoardPins {
+                outputs: OutputPins {
+                    gpio0,
+                    gpio1,
+                    gpio2,
+                    gpio3,
+                    gpio4,
+                    gpio5,
+                    gpio6,
+                    gpio7,
+                    gpio8,
+                    gpio9,
+                    gpio10,
+                    gpio11,
+                    gpio12,
+                    gpio13,
+                    gpio14,
+                    gpio15,
+                },
+                $($(#[$attr])* $name: $gpio,)*
+                digital: [$(
+                    $(#[$dattr])*
+                    $dgpio
+                        .into_pull_type::<$pull>()
+                        .into_pull_type::<DynPullType>()
+                        .into_function::<FunctionSioInput>()
+                        .into_dyn_pin(),
+                )*],
+            }
+        }
+    };
+}
+
+// the Pico IOx16 board
+#[cfg(not(feature = "pinmap-alt"))]
+pin_map! {
+    // switches to ground, GP18 only while the PIO UART doesn't use it, GP23 and GP24 only while
+    // the CYW43 of the Pico 2 W doesn't and GP24 also only while the log doesn't
+    digital: [
+        #[cfg(not(feature = "pio-uart"))]
+        gpio18: PullUp,
+        #[cfg(not(feature = "wifi"))]
+        gpio23: PullUp,
+        #[cfg(not(any(feature = "defmt-uart", feature = "wifi")))]
+        gpio24: PullUp,
+    ],
+    uart_tx: gpio16 as Gpio16,
+    uart_rx: gpio17 as Gpio17,
+    // RS-485 driver enable
+    de: gpio19 as Gpio19,
+    sel0: gpio22 as Gpio22,
+    sel1: gpio21 as Gpio21,
+    sel2: gpio20 as Gpio20,
+    // analog input of the left half of the board
+    #[cfg(not(feature = "ads1x15"))]
+    adc0: gpio26 as Gpio26,
+    // analog input of the right half of the board
+    #[cfg(not(feature = "ads1x15"))]
+    adc1: gpio27 as Gpio27,
+    // the external ADC takes over the analog inputs
+    #[cfg(feature = "ads1x15")]
+    i2c_sda: gpio26 as Gpio26,
+    #[cfg(feature = "ads1x15")]
+    i2c_scl: gpio27 as Gpio27,
+    #[cfg(not(feature = "wifi"))]
+    led: gpio25 as Gpio25,
+    #[cfg(feature = "pio-uart")]
+    pio_uart_tx: gpio18 as Gpio18,
+    #[cfg(feature = "pio-uart")]
+    pio_uart_rx: gpio28 as Gpio28,
+    #[cfg(feature = "defmt-uart")]
+    log_tx: gpio24 as Gpio24,
+    // the CYW43 of the Pico 2 W: power, data and interrupt, chip select and clock
+    #[cfg(feature = "wifi")]
+    wl_on: gpio23 as Gpio23,
+    #[cfg(feature = "wifi")]
+    wl_d: gpio24 as Gpio24,
+    #[cfg(feature = "wifi")]
+    wl_cs: gpio25 as Gpio25,
+    #[cfg(feature = "wifi")]
+    wl_clk: gpio29 as Gpio29,
+}
+
+/// Time in µs the 4067 multiplexers need to settle after a change of the selection.
+#[cfg(not(feature = "pinmap-alt"))]
+pub const MUX_SETTLE_TIME_US: u32 = 3;
+/// The input each multiplexer position is wired to, for the left and the right half.
+#[cfg(not(feature = "pinmap-alt"))]
+pub const MUX_CHANNELS: [[u8; 8]; 2] = [[0, 1, 2, 3, 4, 5, 6, 7], [8, 9, 10, 11, 12, 13, 14, 15]];
+
+// carrier boards with the RS-485 driver enable next to the UART and the multiplexer selects in
+// ascending order, which frees GP22 for the second port's TX
+#[cfg(feature = "pinmap-alt")]
+pin_map! {
+    // switches to ground, GP22 only while the PIO UART doesn't use it, GP23 and GP24 only while
+    // the CYW43 of the Pico 2 W doesn't and GP24 also only while the log doesn't
+    digital: [
+        #[cfg(not(feature = "pio-uart"))]
+        gpio22: PullUp,
+        #[cfg(not(feature = "wifi"))]
+        gpio23: PullUp,
+        #[cfg(not(any(feature = "defmt-uart", feature = "wifi")))]
+        gpio24: PullUp,
+    ],
+    uart_tx: gpio16 as Gpio16,
+    uart_rx: gpio17 as Gpio17,
+    // RS-485 driver enable
+    de: gpio18 as Gpio18,
+    sel0: gpio19 as Gpio19,
+    sel1: gpio20 as Gpio20,
+    sel2: gpio21 as Gpio21,
+    // analog input of the left half of the board
+    #[cfg(not(feature = "ads1x15"))]
+    adc0: gpio27 as Gpio27,
+    // analog input of the right half of the board
+    #[cfg(not(feature = "ads1x15"))]
+    adc1: gpio26 as Gpio26,
+    // the external ADC takes over the analog inputs
+    #[cfg(feature = "ads1x15")]
+    i2c_sda: gpio26 as Gpio26,
+    #[cfg(feature = "ads1x15")]
+    i2c_scl: gpio27 as Gpio27,
+    #[cfg(not(feature = "wifi"))]
+    led: gpio25 as Gpio25,
+    #[cfg(feature = "pio-uart")]
+    pio_uart_tx: gpio22 as Gpio22,
+    #[cfg(feature = "pio-uart")]
+    pio_uart_rx: gpio28 as Gpio28,
+    #[cfg(feature = "defmt-uart")]
+    log_tx: gpio24 as Gpio24,
+    // the CYW43 of the Pico 2 W: power, data and interrupt, chip select and clock
+    #[cfg(feature = "wifi")]
+    wl_on: gpio23 as Gpio23,
+    #[cfg(feature = "wifi")]
+    wl_d: gpio24 as Gpio24,
+    #[cfg(feature = "wifi")]
+    wl_cs: gpio25 as Gpio25,
+    #[cfg(feature = "wifi")]
+    wl_clk: gpio29 as Gpio29,
+}
+
+/// Time in µs the 4067 multiplexers need to settle after a change of the selection.
+#[cfg(feature = "pinmap-alt")]
+pub const MUX_SETTLE_TIME_US: u32 = 3;
+/// The input each multiplexer position is wired to, for the left and the right half.
+#[cfg(feature = "pinmap-alt")]
+pub const MUX_CHANNELS: [[u8; 8]; 2] = [[0, 1, 2, 3, 4, 5, 6, 7], [8, 9, 10, 11, 12, 13, 14, 15]];
+
+/// SPI1 to the DACs, with TX on GP15 and SCK on GP14.
+#[cfg(feature = "mcp4922")]
+type DacSpi = Spi<
+    Enabled,
+    pac::SPI1,
+    (
+        Pin<bank0::Gpio15, FunctionSpi, PullDown>,
+        Pin<bank0::Gpio14, FunctionSpi, PullDown>,
+    ),
+>;
+#[cfg(feature = "mcp4922")]
+type DacCs<Id> = Pin<Id, FunctionSioOutput, PullDown>;
+
+/// Groups 0 and 1 in PIO1 for a finer resolution at low frequencies, on their usual pins.
+#[cfg(feature = "pio-pwm")]
+type Group0 = PioPwm<pac::PIO1, SM0, SM1>;
+#[cfg(all(feature = "mcp4922", not(feature = "pio-pwm")))]
+type Group0 = PwmGroup<rp235x_hal::pwm::Pwm0>;
+#[cfg(feature = "pio-pwm")]
+type Group1 = PioPwm<pac::PIO1, SM2, SM3>;
+#[cfg(all(feature = "mcp4922", not(feature = "pio-pwm")))]
+type Group1 = PwmGroup<rp235x_hal::pwm::Pwm1>;
+
+/// Groups 6 and 7 with an MCP4922 each, which gives up their PWM pins for the SPI bus and the
+/// chip selects, GP12 for group 6 and GP13 for group 7.
+#[cfg(feature = "mcp4922")]
+type Group6 = Mcp4922<DacSpi, DacCs<bank0::Gpio12>>;
+#[cfg(all(feature = "pio-pwm", not(feature = "mcp4922")))]
+type Group6 = PwmGroup<rp235x_hal::pwm::Pwm6>;
+#[cfg(feature = "mcp4922")]
+type Group7 = Mcp4922<DacSpi, DacCs<bank0::Gpio13>>;
+#[cfg(all(feature = "pio-pwm", not(feature = "mcp4922")))]
+type Group7 = PwmGroup<rp235x_hal::pwm::Pwm7>;
+
+/// The output groups, with something else than a PWM slice for the groups the features ask
+/// for. A board that needs that for other groups changes the types and the constructor
+/// accordingly.
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+pub type Outputs = Output<
+    Group0,
+    Group1,
+    PwmGroup<Pwm2>,
+    PwmGroup<Pwm3>,
+    PwmGroup<Pwm4>,
+    PwmGroup<Pwm5>,
+    Group6,
+    Group7,
+>;
+
+/// What the groups of [`Outputs`] need besides the PWM slices.
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+pub struct GroupPeripherals<'a> {
+    #[cfg(feature = "mcp4922")]
+    pub spi1: pac::SPI1,
+    #[cfg(feature = "pio-pwm")]
+    pub pio1: pac::PIO1,
+    pub resets: &'a mut pac::RESETS,
+    #[cfg_attr(not(feature = "mcp4922"), allow(dead_code))]
+    pub peripheral_clock: HertzU32,
+    #[cfg_attr(not(feature = "pio-pwm"), allow(dead_code))]
+    pub system_clock: HertzU32,
+}
+
+#[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+pub fn outputs(pins: OutputPins, mut slices: Slices, peripherals: GroupPeripherals) -> Outputs {
+    slices.enable_simultaneous(Outputs::SLICE_MASK as u8);
+
+    #[cfg(feature = "pio-pwm")]
+    let (pwm0, pwm1) = {
+        let (mut pio, sm0, sm1, sm2, sm3) = peripherals.pio1.split(peripherals.resets);
+        let program = pio_pwm::install(&mut pio);
+        let group0 = PioPwm::new(
+            &program,
+            sm0,
+            sm1,
+            pio_pin(pins.gpio0),
+            pio_pin(pins.gpio1),
+            peripherals.system_clock,
+        );
+        let group1 = PioPwm::new(
+            &program,
+            sm2,
+            sm3,
+            pio_pin(pins.gpio2),
+            pio_pin(pins.gpio3),
+            peripherals.system_clock,
+        );
+        (group0, group1)
+    };
+    #[cfg(not(feature = "pio-pwm"))]
+    let (pwm0, pwm1) = (
+        pwm_group(slices.pwm0, pins.gpio0, pins.gpio1),
+        pwm_group(slices.pwm1, pins.gpio2, pins.gpio3),
+    );
+
+    #[cfg(feature = "mcp4922")]
+    let (pwm6, pwm7) = {
+        let spi = Spi::new(
+            peripherals.spi1,
+            (pins.gpio15.into_function(), pins.gpio14.into_function()),
+        )
+        .init(
+            peripherals.resets,
+            peripherals.peripheral_clock,
+            10.MHz(),
+            embedded_hal::spi::MODE_0,
+        );
+        let bus = cortex_m::singleton!(: RefCell<DacSpi> = RefCell::new(spi)).unwrap();
+        let dac6 = cortex_m::singleton!(: Mcp4922Chip<DacSpi, DacCs<bank0::Gpio12>> =
+            Mcp4922Chip::new(bus, pins.gpio12.into_push_pull_output()))
+        .unwrap();
+        let dac7 = cortex_m::singleton!(: Mcp4922Chip<DacSpi, DacCs<bank0::Gpio13>> =
+            Mcp4922Chip::new(bus, pins.gpio13.into_push_pull_output()))
+        .unwrap();
+        (Mcp4922::new(dac6), Mcp4922::new(dac7))
+    };
+    #[cfg(not(feature = "mcp4922"))]
+    let (pwm6, pwm7) = (
+        pwm_group(slices.pwm6, pins.gpio12, pins.gpio13),
+        pwm_group(slices.pwm7, pins.gpio14, pins.gpio15),
+    );
+
+    Output {
+        pwm0,
+        pwm1,
+        pwm2: pwm_group(slices.pwm2, pins.gpio4, pins.gpio5),
+        pwm3: pwm_group(slices.pwm3, pins.gpio6, pins.gpio7),
+        pwm4: pwm_group(slices.pwm4, pins.gpio8, pins.gpio9),
+        pwm5: pwm_group(slices.pwm5, pins.gpio10, pins.gpio11),
+        pwm6,
+        pwm7,
+    }
+}
+
+/// Hands the pin over to PIO1, returning its number for the state machine.
+#[cfg(feature = "pio-pwm")]
+fn pio_pin<Id: PinId + ValidFunction<FunctionPio1>>(pin: Pin<Id, FunctionNull, PullDown>) -> u8 {
+    pin.into_function::<FunctionPio1>().id().num
+}
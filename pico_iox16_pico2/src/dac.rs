@@ -0,0 +1,137 @@
+//! Output groups driven by an MCP4922 dual 12 bit DAC on SPI instead of PWM, so that they produce
+//! true analog voltages, e.g. for speed inputs of motor controllers.
+//!
+//! The duty cycle maps linearly to the voltage between 0 and the DAC's reference. A DAC has no
+//! frequency, the one set is only kept to be read back. LDAC needs to be tied low, so that each
+//! channel takes its new value as soon as its chip select goes high.
+
+use core::{cell::RefCell, convert::Infallible};
+
+use embedded_hal::{digital::OutputPin, spi::SpiBus};
+use rounded_div::RoundedDiv as _;
+
+use crate::runtime::Board;
+
+/// Duty cycle of 100%, as in the protocol.
+const FULL_DUTY_CYCLE: u16 = 0x8000;
+/// Largest code of the 12 bit DAC.
+const FULL_SCALE: u16 = 0x0FFF;
+/// Unbuffered reference, gain 1x and the output active.
+const CONFIG: u16 = 0b0011 << 12;
+
+/// An MCP4922 with its chip select, shared by its two channels. The SPI bus can be shared with
+/// other DACs.
+pub struct Mcp4922Chip<SPI: 'static, CS> {
+    bus: &'static RefCell<SPI>,
+    cs: RefCell<CS>,
+}
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>> Mcp4922Chip<SPI, CS> {
+    pub fn new(bus: &'static RefCell<SPI>, mut cs: CS) -> Self {
+        let Ok(()) = cs.set_high();
+        Self {
+            bus,
+            cs: RefCell::new(cs),
+        }
+    }
+
+    fn write(&self, channel_b: bool, code: u16) -> Result<(), Infallible> {
+        let word = u16::from(channel_b) << 15 | CONFIG | code.min(FULL_SCALE);
+        let mut bus = self.bus.borrow_mut();
+        let mut cs = self.cs.borrow_mut();
+        cs.set_low()?;
+        bus.write(&word.to_be_bytes())?;
+        bus.flush()?;
+        cs.set_high()
+    }
+}
+
+/// The output group of an MCP4922, with channel A on the first and B on the second output.
+pub struct Mcp4922<SPI: 'static, CS: 'static> {
+    frequency: u16,
+    channel_a: DacChannel<SPI, CS>,
+    channel_b: DacChannel<SPI, CS>,
+}
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>> Mcp4922<SPI, CS> {
+    /// Sets both channels to 0 V.
+    pub fn new(chip: &'static Mcp4922Chip<SPI, CS>) -> Self {
+        let mut channel_a = DacChannel {
+            chip,
+            channel_b: false,
+            duty_cycle: 0,
+        };
+        let mut channel_b = DacChannel {
+            chip,
+            channel_b: true,
+            duty_cycle: 0,
+        };
+        let Ok(()) = channel_a.set(0);
+        let Ok(()) = channel_b.set(0);
+        Self {
+            frequency: 0,
+            channel_a,
+            channel_b,
+        }
+    }
+}
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>>
+    pico_iox16_firmware::output::Pwm<Board> for Mcp4922<SPI, CS>
+{
+    type Error = Infallible;
+    type ChannelA = DacChannel<SPI, CS>;
+    type ChannelB = DacChannel<SPI, CS>;
+    fn get_frequency(&self) -> Result<u16, Self::Error> {
+        Ok(self.frequency)
+    }
+    fn channel_a(&self) -> &Self::ChannelA {
+        &self.channel_a
+    }
+    fn channel_b(&self) -> &Self::ChannelB {
+        &self.channel_b
+    }
+    fn set_frequency(&mut self, frequency: u16) -> Result<(), Self::Error> {
+        self.frequency = frequency;
+        Ok(())
+    }
+    fn channel_a_mut(&mut self) -> &mut Self::ChannelA {
+        &mut self.channel_a
+    }
+    fn channel_b_mut(&mut self) -> &mut Self::ChannelB {
+        &mut self.channel_b
+    }
+}
+
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>> crate::output::Group
+    for Mcp4922<SPI, CS>
+{
+    // no period to synchronize
+}
+
+pub struct DacChannel<SPI: 'static, CS: 'static> {
+    chip: &'static Mcp4922Chip<SPI, CS>,
+    channel_b: bool,
+    duty_cycle: u16,
+}
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>> DacChannel<SPI, CS> {
+    fn set(&mut self, duty_cycle: u16) -> Result<(), Infallible> {
+        let duty_cycle = duty_cycle.min(FULL_DUTY_CYCLE);
+        let code =
+            (u32::from(duty_cycle) * u32::from(FULL_SCALE)).rounded_div(u32::from(FULL_DUTY_CYCLE));
+        self.chip.write(self.channel_b, code as u16)?;
+        self.duty_cycle = duty_cycle;
+        Ok(())
+    }
+}
+impl<SPI: SpiBus<Error = Infallible>, CS: OutputPin<Error = Infallible>>
+    pico_iox16_firmware::output::PwmChannel<Board> for DacChannel<SPI, CS>
+{
+    type Error = Infallible;
+    fn max_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(FULL_DUTY_CYCLE)
+    }
+    fn get_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(self.duty_cycle)
+    }
+    fn set_duty_cycle(&mut self, duty_cycle: u16) -> Result<(), Self::Error> {
+        self.set(duty_cycle)
+    }
+}
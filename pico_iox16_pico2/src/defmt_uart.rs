@@ -0,0 +1,102 @@
+//! A defmt logger that writes to UART1 instead of RTT, so that units in the field can be looked
+//! into with a USB-serial dongle instead of a debug probe.
+//!
+//! The frames are the same as over RTT, so `defmt-print -e <elf> stdin` decodes them from the
+//! port set to 921600 baud, 8N1 and raw mode, e.g. with `stty`. Logging blocks until the frame is
+//! in the UART's FIFO, so it is slower than RTT, but the frames are only a few bytes each.
+//! Anything logged before [`init`] is dropped.
+
+use core::{
+    cell::RefCell,
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use cortex_m::{
+    interrupt::{self, Mutex},
+    register::primask,
+};
+use fugit::{HertzU32, RateExtU32 as _};
+use rp235x_hal::{
+    gpio::{FunctionNull, FunctionUart, Pin, PullDown, bank0::Gpio24},
+    pac::{self, UART1},
+    typelevel::{OptionTNone, OptionTSome},
+    uart::{DataBits, Enabled, Pins, StopBits, UartConfig, UartPeripheral},
+};
+
+const BAUDRATE: u32 = 921_600;
+
+type LogUart = UartPeripheral<
+    Enabled,
+    UART1,
+    Pins<OptionTSome<Pin<Gpio24, FunctionUart, PullDown>>, OptionTNone, OptionTNone, OptionTNone>,
+>;
+
+static UART: Mutex<RefCell<Option<LogUart>>> = Mutex::new(RefCell::new(None));
+
+/// Starts logging to the UART, with TX on GP24.
+pub fn init(
+    uart: UART1,
+    tx: Pin<Gpio24, FunctionNull, PullDown>,
+    resets: &mut pac::RESETS,
+    frequency: HertzU32,
+) {
+    let uart = UartPeripheral::new(uart, Pins::default().tx(tx.into_function()), resets)
+        .enable(
+            UartConfig::new(BAUDRATE.Hz(), DataBits::Eight, None, StopBits::One),
+            frequency,
+        )
+        .unwrap();
+    interrupt::free(|cs| UART.borrow(cs).replace(Some(uart)));
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+/// Whether interrupts were enabled before `acquire` disabled them
+static RESTORE: AtomicBool = AtomicBool::new(false);
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let active = primask::read().is_active();
+        interrupt::disable();
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        RESTORE.store(active, Ordering::Relaxed);
+        // SAFETY: only touched between `acquire` and `release`, with interrupts disabled
+        unsafe { (*addr_of_mut!(ENCODER)).start_frame(write) };
+    }
+
+    unsafe fn flush() {
+        interrupt::free(|cs| {
+            if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
+                while uart.uart_is_busy() {}
+            }
+        });
+    }
+
+    unsafe fn release() {
+        unsafe { (*addr_of_mut!(ENCODER)).end_frame(write) };
+        TAKEN.store(false, Ordering::Relaxed);
+        if RESTORE.load(Ordering::Relaxed) {
+            // SAFETY: interrupts were enabled when `acquire` disabled them
+            unsafe { interrupt::enable() };
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        unsafe { (*addr_of_mut!(ENCODER)).write(bytes, write) };
+    }
+}
+
+fn write(bytes: &[u8]) {
+    interrupt::free(|cs| {
+        if let Some(uart) = UART.borrow(cs).borrow().as_ref() {
+            uart.write_full_blocking(bytes);
+        }
+    });
+}
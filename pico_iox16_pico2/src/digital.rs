@@ -0,0 +1,80 @@
+//! Spare GPIOs read as digital inputs, for door switches and the like that don't justify an
+//! analog channel.
+//!
+//! All inputs are sampled at once from the SIO every [`SAMPLE_PERIOD_MS`], and a level only counts
+//! once it held for [`DEBOUNCE_SAMPLES`] samples in a row.
+
+use core::cell::Cell;
+
+use fugit::Duration;
+use pico_iox16_firmware::runtime::{Timer, WaitUntil as _};
+use rp235x_hal::{
+    gpio::{DynPinId, DynPullType, FunctionSioInput, Pin},
+    pac,
+};
+
+use crate::runtime::Board;
+
+/// A spare GPIO configured as input with its pull.
+pub type DigitalPin = Pin<DynPinId, FunctionSioInput, DynPullType>;
+
+const SAMPLE_PERIOD_MS: u64 = 2;
+const DEBOUNCE_SAMPLES: u8 = 5;
+
+pub struct DigitalInputs<const N: usize> {
+    /// Kept so that nothing else can take the pins
+    _pins: [DigitalPin; N],
+    available: u32,
+    levels: Cell<u32>,
+}
+impl<const N: usize> DigitalInputs<N> {
+    pub fn new(pins: [DigitalPin; N]) -> Self {
+        let available = pins.iter().fold(0, |mask, pin| mask | 1 << pin.id().num);
+        Self {
+            _pins: pins,
+            available,
+            levels: Cell::new(sample() & available),
+        }
+    }
+
+    /// Samples the inputs and updates the debounced levels, forever.
+    pub async fn run<const NOM: u32, const DENOM: u32, E>(
+        &self,
+        timer: &impl Timer<Board, u64, NOM, DENOM>,
+    ) -> Result<!, E> {
+        // number of samples in a row that differed from the debounced level, per GPIO
+        let mut counts = [0u8; 32];
+        let mut next = timer.now();
+        loop {
+            next += Duration::<u64, NOM, DENOM>::millis(SAMPLE_PERIOD_MS);
+            timer.wait_until(next).await;
+            let changed = (sample() ^ self.levels.get()) & self.available;
+            for (gpio, count) in counts.iter_mut().enumerate() {
+                if changed & 1 << gpio == 0 {
+                    *count = 0;
+                    continue;
+                }
+                *count += 1;
+                if *count == DEBOUNCE_SAMPLES {
+                    *count = 0;
+                    self.levels.set(self.levels.get() ^ 1 << gpio);
+                }
+            }
+        }
+    }
+}
+impl<const N: usize> pico_iox16_firmware::digital::DigitalInputs<Board> for DigitalInputs<N> {
+    fn available(&self) -> u32 {
+        self.available
+    }
+    fn levels(&self) -> u32 {
+        self.levels.get()
+    }
+}
+
+/// The levels of all GPIOs of bank 0.
+fn sample() -> u32 {
+    // SAFETY: reading the input levels has no side effects
+    let sio = unsafe { &*pac::SIO::ptr() };
+    sio.gpio_in().read().bits()
+}
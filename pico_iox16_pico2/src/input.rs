@@ -3,23 +3,67 @@ use core::convert::Infallible;
 use embedded_hal::digital::OutputPin;
 use rp235x_hal::{
     Adc,
-    adc::{AdcPin, Error},
+    adc::{AdcFifo, AdcPin},
+    dma::{ReadTarget as _, SingleChannel},
     gpio::{
         AnyPin, FunctionNull, FunctionSio, Pin, PinId, PullNone, PullType, SioOutput, ValidFunction,
     },
+    pac::{
+        self,
+        dma::ch::{ch_ctrl_trig::DATA_SIZE_A, ch_ctrl_trig::TREQ_SEL_A},
+    },
 };
 
-use pico_iox16_firmware::input::InputError;
+use pico_iox16_firmware::input::{InputError, InputErrors};
 
 use crate::runtime::Board;
 
-pub struct Input<Sel0: PinId, Sel1: PinId, Sel2: PinId, Pin0: AnyPin, Pin1: AnyPin> {
+/// Number of samples in the ring buffer the DMA writes to, needs to be a power of two.
+const RING_LEN: usize = 256;
+/// Size of the ring buffer in bytes.
+const RING_SIZE: usize = RING_LEN * 2;
+/// Number of transfers after which the DMA channel triggers itself again. Multiple of the ring
+/// length, so that the count of remaining transfers also tells the position in the ring.
+const TRANSFER_COUNT: u32 = 1 << 27;
+/// Bit of a sample that the ADC sets if the conversion failed.
+const CONVERSION_ERROR: u16 = 1 << 15;
+
+/// Ring buffer, aligned to its size for the DMA's address wrapping.
+#[repr(C, align(512))]
+pub struct Ring([u16; RING_LEN]);
+static_assertions::const_assert_eq!(core::mem::size_of::<Ring>(), RING_SIZE);
+static_assertions::const_assert_eq!(core::mem::align_of::<Ring>(), RING_SIZE);
+
+impl Ring {
+    pub const fn new() -> Self {
+        Self([0; RING_LEN])
+    }
+}
+
+/// Inputs read by the ADC in free-running round robin mode on both ADC pins, while a DMA channel
+/// streams the conversions into a ring buffer.
+pub struct Input<
+    Sel0: PinId,
+    Sel1: PinId,
+    Sel2: PinId,
+    Pin0: AnyPin,
+    Pin1: AnyPin,
+    CH: SingleChannel,
+> {
     sel0: Pin<Sel0, FunctionSio<SioOutput>, PullNone>,
     sel1: Pin<Sel1, FunctionSio<SioOutput>, PullNone>,
     sel2: Pin<Sel2, FunctionSio<SioOutput>, PullNone>,
-    adc: Adc,
-    pin0: AdcPin<Pin0>,
-    pin1: AdcPin<Pin1>,
+    fifo: AdcFifo<'static, u16>,
+    _pin0: AdcPin<Pin0>,
+    _pin1: AdcPin<Pin1>,
+    dma: CH,
+    /// The ring buffer, which is written by the DMA behind our back
+    ring: *const u16,
+    /// Number of samples read so far, modulo [`TRANSFER_COUNT`]
+    read: u32,
+    /// Whether the right half of the board is on the lower ADC channel
+    swapped: bool,
+    errors: InputErrors,
 }
 impl<
     Sel0: PinId + ValidFunction<FunctionSio<SioOutput>>,
@@ -27,9 +71,12 @@ impl<
     Sel2: PinId + ValidFunction<FunctionSio<SioOutput>>,
     Pin0: AnyPin,
     Pin1: AnyPin,
-> pico_iox16_firmware::input::Input<Board> for Input<Sel0, Sel1, Sel2, Pin0, Pin1>
+    CH: SingleChannel,
+> pico_iox16_firmware::input::Input<Board> for Input<Sel0, Sel1, Sel2, Pin0, Pin1, CH>
 {
     type Error = Infallible;
+    const SETTLE_TIME_US: u32 = crate::board::MUX_SETTLE_TIME_US;
+    const CHANNELS: [[u8; 8]; 2] = crate::board::MUX_CHANNELS;
     fn select0(&mut self, value: bool) -> nb::Result<(), Self::Error> {
         self.sel0.set_state(value.into()).map_err(nb::Error::Other)
     }
@@ -39,23 +86,85 @@ impl<
     fn select2(&mut self, value: bool) -> nb::Result<(), Self::Error> {
         self.sel2.set_state(value.into()).map_err(nb::Error::Other)
     }
-    fn start_read0(&mut self) -> nb::Result<(), Self::Error> {
-        self.adc.start_oneshot(&mut self.pin0)
+
+    fn discard(&mut self) -> nb::Result<(), Self::Error> {
+        // samples that are still in the FIFO or being converted right now may belong to the
+        // previous selection, so skip the pair they are in
+        let sampled = self.written() + self.fifo.len() as u32;
+        self.read = (sampled + 2) & !1 & (TRANSFER_COUNT - 1);
+        Ok(())
     }
 
-    fn start_read1(&mut self) -> nb::Result<(), Self::Error> {
-        self.adc.start_oneshot(&mut self.pin1)
+    fn read(&mut self, buf: &mut [[u16; 2]]) -> nb::Result<usize, InputError<Self::Error>> {
+        let available = self.written().wrapping_sub(self.read) & (TRANSFER_COUNT - 1);
+        if available > TRANSFER_COUNT / 2 {
+            // still skipping samples after `discard`
+            return Err(nb::Error::WouldBlock);
+        }
+        // a full FIFO drops conversions before the DMA gets to them
+        if available > RING_LEN as u32 || self.fifo.is_over() {
+            self.errors.overruns = self.errors.overruns.saturating_add(1);
+            return Err(nb::Error::Other(InputError::RecoverableError));
+        }
+        let pairs = (available as usize / 2).min(buf.len());
+        if pairs == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        let start = self.read;
+        let mut count = 0;
+        for _ in 0..pairs {
+            let index = self.read as usize % RING_LEN;
+            // SAFETY: the index is within the ring, and the sample is a plain u16 that the DMA
+            // may only overwrite as a whole
+            let (a, b) = unsafe {
+                (
+                    self.ring.add(index).read_volatile(),
+                    self.ring.add(index + 1).read_volatile(),
+                )
+            };
+            self.read = (self.read + 2) & (TRANSFER_COUNT - 1);
+            let pair = if self.swapped { [b, a] } else { [a, b] };
+            let failed = pair.map(|sample| sample & CONVERSION_ERROR != 0);
+            if failed.contains(&true) {
+                for (errors, failed) in self.errors.conversion_errors.iter_mut().zip(failed) {
+                    *errors = errors.saturating_add(u32::from(failed));
+                }
+                continue;
+            }
+            buf[count] = pair;
+            count += 1;
+        }
+        // the DMA may have wrapped around and overwritten samples while they were copied
+        if self.written().wrapping_sub(start) & (TRANSFER_COUNT - 1) > RING_LEN as u32 {
+            self.errors.overruns = self.errors.overruns.saturating_add(1);
+            return Err(nb::Error::Other(InputError::RecoverableError));
+        }
+        Ok(count)
     }
 
-    fn read_last(&mut self) -> nb::Result<u16, InputError<Self::Error>> {
-        if self.adc.is_ready() {
-            match self.adc.read_single() {
-                Ok(v) => Ok(v),
-                Err(Error::ConversionFailed) => Err(nb::Error::Other(InputError::RecoverableError)),
+    fn set_paused(&mut self, paused: bool) -> nb::Result<(), Self::Error> {
+        // SAFETY: only powers the ADC down and up, which the HAL doesn't look at after setting
+        // it up
+        let adc = unsafe { &*pac::ADC::ptr() };
+        if paused {
+            // the conversion in progress still goes into the ring, keeping the halves in order
+            self.fifo.pause();
+            if adc.cs().read().ready().bit_is_clear() {
+                return Err(nb::Error::WouldBlock);
             }
+            adc.cs().modify(|_, w| w.en().clear_bit());
         } else {
-            Err(nb::Error::WouldBlock)
+            adc.cs().modify(|_, w| w.en().set_bit());
+            if adc.cs().read().ready().bit_is_clear() {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.fifo.resume();
         }
+        Ok(())
+    }
+
+    fn errors(&self) -> InputErrors {
+        self.errors
     }
 }
 impl<
@@ -64,15 +173,18 @@ impl<
     Sel2: PinId + ValidFunction<FunctionSio<SioOutput>>,
     Pin0: AnyPin,
     Pin1: AnyPin,
-> Input<Sel0, Sel1, Sel2, Pin0, Pin1>
+    CH: SingleChannel,
+> Input<Sel0, Sel1, Sel2, Pin0, Pin1, CH>
 {
     pub fn new<Pull0: PullType, Pull1: PullType, Pull2: PullType>(
         sel0: Pin<Sel0, FunctionNull, Pull0>,
         sel1: Pin<Sel1, FunctionNull, Pull1>,
         sel2: Pin<Sel2, FunctionNull, Pull2>,
-        adc: Adc,
-        pin0: AdcPin<Pin0>,
-        pin1: AdcPin<Pin1>,
+        adc: &'static mut Adc,
+        mut pin0: AdcPin<Pin0>,
+        mut pin1: AdcPin<Pin1>,
+        dma: CH,
+        ring: &'static mut Ring,
     ) -> Self {
         let sel0 = sel0
             .into_push_pull_output_in_state(false.into())
@@ -83,13 +195,67 @@ impl<
         let sel2 = sel2
             .into_push_pull_output_in_state(false.into())
             .into_pull_type::<PullNone>();
+
+        // round robin always goes through the channels in increasing order, starting with the
+        // lower one keeps the left half on even and the right half on odd positions in the ring
+        let swapped = pin0.channel() > pin1.channel();
+        let fifo = adc.build_fifo().round_robin((&pin0, &pin1)).enable_dma();
+        let mut fifo = if swapped {
+            fifo.set_channel(&mut pin1)
+        } else {
+            fifo.set_channel(&mut pin0)
+        }
+        .start_paused();
+        // have the ADC flag failed conversions in the samples, which the builder has no option for
+        // SAFETY: the FIFO is paused and ours, and the HAL never touches the bit
+        let adc = unsafe { &*pac::ADC::ptr() };
+        adc.fcs().modify(|_, w| w.err().set_bit());
+
+        let ring = ring.0.as_mut_ptr();
+        let ch = dma.ch();
+        ch.ch_read_addr()
+            .write(|w| unsafe { w.bits(fifo.dma_read_target().rx_address_count().0) });
+        ch.ch_write_addr().write(|w| unsafe { w.bits(ring as u32) });
+        ch.ch_trans_count()
+            .write(|w| unsafe { w.mode().trigger_self().count().bits(TRANSFER_COUNT) });
+        ch.ch_ctrl_trig().write(|w| unsafe {
+            w.data_size()
+                .variant(DATA_SIZE_A::SIZE_HALFWORD)
+                .incr_read()
+                .clear_bit()
+                .incr_write()
+                .set_bit()
+                .ring_size()
+                .bits(RING_SIZE.trailing_zeros() as u8)
+                .ring_sel()
+                .set_bit()
+                .treq_sel()
+                .variant(TREQ_SEL_A::ADC)
+                .chain_to()
+                .bits(dma.id())
+                .en()
+                .set_bit()
+        });
+        fifo.resume();
+
         Self {
             sel0,
             sel1,
             sel2,
-            adc,
-            pin0,
-            pin1,
+            fifo,
+            _pin0: pin0,
+            _pin1: pin1,
+            dma,
+            ring,
+            read: 0,
+            swapped,
+            errors: InputErrors::default(),
         }
     }
+
+    /// Number of samples the DMA has written so far, modulo [`TRANSFER_COUNT`]
+    fn written(&self) -> u32 {
+        let remaining = self.dma.ch().ch_trans_count().read().count().bits();
+        (TRANSFER_COUNT - remaining) & (TRANSFER_COUNT - 1)
+    }
 }
@@ -8,45 +8,92 @@
 
 use core::pin::pin;
 
-use futures::future::{Either, select};
-
 use defmt::*;
+use futures::future::select;
+#[cfg(not(feature = "defmt-uart"))]
 use defmt_rtt as _;
-use embedded_hal::digital::OutputPin;
 use pico_iox16_firmware::nvm::NonvolatileStorage as _;
+#[cfg(not(feature = "ads1x15"))]
 use rp235x_hal::adc::AdcPin;
+#[cfg(not(feature = "ads1x15"))]
+use rp235x_hal::dma::DMAExt as _;
 use rp235x_hal::clocks::init_clocks_and_plls;
 use rp235x_hal::gpio::{Pins, PullNone};
-use rp235x_hal::{Adc, entry};
-use rp235x_hal::{Clock, pac};
+#[cfg(not(feature = "ads1x15"))]
+use rp235x_hal::Adc;
+use rp235x_hal::entry;
+use rp235x_hal::pac;
+#[cfg(any(
+    not(feature = "usb"),
+    feature = "pio-uart",
+    feature = "ads1x15",
+    feature = "mcp4922",
+    feature = "pio-pwm",
+    feature = "defmt-uart"
+))]
+use rp235x_hal::Clock;
 // use panic_probe as _;
-use rp235x_hal::fugit::{ExtU32 as _, RateExtU32 as _};
-use rp235x_hal::uart::{DataBits, StopBits, UartConfig};
+use rp235x_hal::fugit::ExtU32 as _;
+#[cfg(feature = "ads1x15")]
+use rp235x_hal::fugit::RateExtU32 as _;
 
 use crate::nvm::Nvm;
-use crate::output::OutputPins;
-use crate::runtime::{Timer0, Uart};
-use pico_iox16_firmware::{
-    runtime::Timer,
-    runtime::{WaitUntil as _, block_on},
-};
+use crate::runtime::Timer0;
+#[cfg(not(feature = "usb"))]
+use crate::runtime::Uart;
+use pico_iox16_firmware::runtime::{block_on, block_on_with_idle};
 
+#[cfg(feature = "ads1x15")]
+mod ads1x15;
+mod board;
+#[cfg(feature = "mcp4922")]
+mod dac;
+#[cfg(feature = "defmt-uart")]
+mod defmt_uart;
+mod digital;
+#[cfg(not(feature = "ads1x15"))]
 mod input;
 mod nvm;
 mod output;
 mod panic;
+#[cfg(feature = "pio-pwm")]
+mod pio_pwm;
+#[cfg(feature = "pio-uart")]
+mod pio_uart;
 mod runtime;
+#[cfg(feature = "usb")]
+mod usb;
+#[cfg(feature = "wifi")]
+mod wifi;
+
+#[cfg(all(feature = "wifi", feature = "pio-uart"))]
+compile_error!("The WiFi connection takes the place of the PIO UART as second port");
+#[cfg(all(feature = "wifi", feature = "defmt-uart"))]
+compile_error!("The log's GP24 is the data line of the CYW43 on the Pico 2 W");
 
 /// Tell the Boot ROM about our application
 #[unsafe(link_section = ".start_block")]
 #[used]
 pub static IMAGE_DEF: rp235x_hal::block::ImageDef = rp235x_hal::block::ImageDef::secure_exe();
 
+/// How long the RS-485 driver stays enabled after the last stop bit, so that the bus doesn't
+/// float while the receivers still look at it.
+#[cfg(not(feature = "usb"))]
+const DE_HOLD: fugit::MicrosDurationU32 = fugit::MicrosDurationU32::micros(10);
+
+/// How long requests passed on to the bus segment behind the PIO UART wait for a response. The
+/// slowest command writes the flash.
+#[cfg(feature = "repeater")]
+const DOWNSTREAM_TIMEOUT_US: u32 = 500_000;
+
 #[entry]
 #[allow(clippy::never_loop)]
 fn main() -> ! {
     info!("Program start");
     let mut pac = pac::Peripherals::take().unwrap();
+    // read before the clock setup touches the watchdog
+    let reset_cause = runtime::reset_cause(&pac.WATCHDOG, &pac.POWMAN);
+    runtime::enable_brownout_detection(&pac.POWMAN);
     let mut watchdog = rp235x_hal::Watchdog::new(pac.WATCHDOG);
     let sio = rp235x_hal::Sio::new(pac.SIO);
 
@@ -64,122 +111,245 @@ fn main() -> ! {
     .ok()
     .unwrap();
 
-    let timer = Timer0(rp235x_hal::Timer::new_timer0(
-        pac.TIMER0,
-        &mut pac.RESETS,
-        &clocks,
-    ));
-
-    let Pins {
-        gpio0,
-        gpio1,
-        gpio2,
-        gpio3,
-        gpio4,
-        gpio5,
-        gpio6,
-        gpio7,
-        gpio8,
-        gpio9,
-        gpio10,
-        gpio11,
-        gpio12,
-        gpio13,
-        gpio14,
-        gpio15,
-        gpio16,
-        gpio17,
-        gpio19,
-        gpio20,
-        gpio21,
-        gpio22,
-        gpio25,
-        gpio26,
-        gpio27,
-        ..
-    } = rp235x_hal::gpio::Pins::new(
+    let pins = board::take(Pins::new(
         pac.IO_BANK0,
         pac.PADS_BANK0,
         sio.gpio_bank0,
         &mut pac.RESETS,
+    ));
+    #[cfg(feature = "defmt-uart")]
+    defmt_uart::init(
+        pac.UART1,
+        pins.log_tx,
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
     );
 
-    let nvm = Nvm::take().unwrap();
+    info!("Reset cause: {}", reset_cause);
+    // erasing the record takes too long for the watchdog
+    let Ok(_) = block_on(pico_iox16_firmware::panic::report(&nvm::PanicFlash));
+    watchdog.pause_on_debug(true);
+    watchdog.start(100.millis());
+    let watchdog = cortex_m::singleton!(: runtime::Watchdog = runtime::Watchdog(watchdog)).unwrap();
+
+    let hal_timer = rp235x_hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    let timer = Timer0::new(hal_timer);
+
+    let nvm = Nvm::take(watchdog).unwrap();
     let Ok(nvm) = block_on(pico_iox16_firmware::nvm::Nvm::new(nvm));
-    let baudrate = nvm.get_config().baudrate;
-    let mut uart = Uart::new(
-        rp235x_hal::uart::UartPeripheral::new(
-            pac.UART0,
-            (gpio16.into_function(), gpio17.into_function()),
-            &mut pac.RESETS,
-        )
-        .enable(
-            UartConfig::new(baudrate.Hz(), DataBits::Eight, None, StopBits::One),
+    let config = nvm.get_config();
+    info!(
+        "Address {} @ {} Hz, parity {}, {} stop bits",
+        config.address,
+        config.effective_baudrate(),
+        config.parity(),
+        config.stop_bits()
+    );
+    #[cfg(not(feature = "usb"))]
+    let (mut io, mut io_send) = {
+        let uart = Uart::new(
+            rp235x_hal::uart::UartPeripheral::new(
+                pac.UART0,
+                (pins.uart_tx.into_function(), pins.uart_rx.into_function()),
+                &mut pac.RESETS,
+            ),
+            runtime::uart_config(&config),
             clocks.peripheral_clock.freq(),
+            pins.de.into_push_pull_output_in_state(rp235x_hal::gpio::PinState::Low),
+            hal_timer,
+            DE_HOLD,
         )
-        .unwrap(),
-    );
-    let mut uart_send = gpio19.into_push_pull_output_in_state(rp235x_hal::gpio::PinState::Low);
+        .unwrap();
+        // the UART drives the RS-485 driver enable pin itself
+        (uart, runtime::NoPin)
+    };
+    #[cfg(feature = "usb")]
+    let (mut io, mut io_send) = {
+        // the UART pins stay unused, so that the board doesn't drive an attached RS-485 bus
+        let _ = (pins.uart_tx, pins.uart_rx, pins.de);
+        let bus = cortex_m::singleton!(: usb_device::bus::UsbBusAllocator<rp235x_hal::usb::UsbBus> =
+            usb_device::bus::UsbBusAllocator::new(rp235x_hal::usb::UsbBus::new(
+                pac.USB,
+                pac.USB_DPRAM,
+                clocks.usb_clock,
+                true,
+                &mut pac.RESETS,
+            )))
+        .unwrap();
+        (usb::UsbSerial::new(bus), runtime::NoPin)
+    };
+    #[cfg(feature = "pio-uart")]
+    let (mut io2, mut io_send2) = {
+        use rp235x_hal::gpio::{FunctionPio0, Pin, PullDown};
+        use rp235x_hal::pio::PIOExt as _;
+        let (pio, sm0, sm1, _, _) = pac.PIO0.split(&mut pac.RESETS);
+        let tx: Pin<_, FunctionPio0, PullDown> = pins.pio_uart_tx.into_function();
+        let rx: Pin<_, FunctionPio0, PullDown> = pins.pio_uart_rx.into_function();
+        let uart = pio_uart::PioUart::new(
+            pio,
+            sm0,
+            sm1,
+            tx.id().num,
+            rx.id().num,
+            config.effective_baudrate(),
+            clocks.system_clock.freq().to_Hz(),
+        );
+        (uart, runtime::NoPin)
+    };
 
-    let mut led_pin = gpio25.into_push_pull_output().into_pull_type::<PullNone>();
+    #[cfg(not(feature = "wifi"))]
+    let mut led_pin = pins.led.into_push_pull_output().into_pull_type::<PullNone>();
+    #[cfg(feature = "wifi")]
+    let mut led_pin = wifi::Led;
 
     let mut main_loop = pico_iox16_firmware::MainLoop::new(&timer);
+    #[cfg(not(any(feature = "mcp4922", feature = "pio-pwm")))]
     let mut output = output::Output::new(
-        OutputPins {
-            gpio0,
-            gpio1,
-            gpio2,
-            gpio3,
-            gpio4,
-            gpio5,
-            gpio6,
-            gpio7,
-            gpio8,
-            gpio9,
-            gpio10,
-            gpio11,
-            gpio12,
-            gpio13,
-            gpio14,
-            gpio15,
-        },
+        pins.outputs,
         rp235x_hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS),
     );
-    let mut input = input::Input::new(
-        gpio22,
-        gpio21,
-        gpio20,
-        Adc::new(pac.ADC, &mut pac.RESETS),
-        AdcPin::new(gpio26).unwrap(),
-        AdcPin::new(gpio27).unwrap(),
+    #[cfg(any(feature = "mcp4922", feature = "pio-pwm"))]
+    let mut output = board::outputs(
+        pins.outputs,
+        rp235x_hal::pwm::Slices::new(pac.PWM, &mut pac.RESETS),
+        board::GroupPeripherals {
+            #[cfg(feature = "mcp4922")]
+            spi1: pac.SPI1,
+            #[cfg(feature = "pio-pwm")]
+            pio1: pac.PIO1,
+            resets: &mut pac.RESETS,
+            peripheral_clock: clocks.peripheral_clock.freq(),
+            system_clock: clocks.system_clock.freq(),
+        },
     );
+    #[cfg(not(feature = "ads1x15"))]
+    let mut input = {
+        let adc = cortex_m::singleton!(: Adc = Adc::new(pac.ADC, &mut pac.RESETS)).unwrap();
+        let ring = cortex_m::singleton!(: input::Ring = input::Ring::new()).unwrap();
+        let dma = pac.DMA.split(&mut pac.RESETS);
+        input::Input::new(
+            pins.sel0,
+            pins.sel1,
+            pins.sel2,
+            adc,
+            AdcPin::new(pins.adc0).unwrap(),
+            AdcPin::new(pins.adc1).unwrap(),
+            dma.ch0,
+            ring,
+        )
+    };
+    #[cfg(feature = "ads1x15")]
+    let mut input = {
+        let i2c = rp235x_hal::I2C::i2c1(
+            pac.I2C1,
+            pins.i2c_sda.reconfigure(),
+            pins.i2c_scl.reconfigure(),
+            400.kHz(),
+            &mut pac.RESETS,
+            clocks.system_clock.freq(),
+        );
+        ads1x15::Ads1x15Input::new(
+            pins.sel0,
+            pins.sel1,
+            pins.sel2,
+            i2c,
+            ads1x15::DEFAULT_ADDRESS,
+            ads1x15::Mux::SingleEnded,
+            hal_timer,
+        )
+    };
 
-    let system = runtime::System;
+    let digital = digital::DigitalInputs::new(pins.digital);
+
+    let system = runtime::System {
+        unique_id: runtime::unique_id(),
+        hardware_revision: board::HARDWARE_REVISION,
+        reset_cause,
+    };
+    info!("Chip ID {=u64:016x}, board revision {}", system.unique_id, system.hardware_revision);
+    #[cfg(feature = "wifi")]
+    let (mut io2, mut io_send2, wifi) = {
+        use rp235x_hal::gpio::{FunctionPio0, Pin, PinState};
+        use rp235x_hal::pio::PIOExt as _;
+        let (pio, sm0, _, _, _) = pac.PIO0.split(&mut pac.RESETS);
+        let dio: Pin<_, FunctionPio0, PullNone> = pins.wl_d.into_function().into_pull_type();
+        let clk: Pin<_, FunctionPio0, PullNone> = pins.wl_clk.into_function().into_pull_type();
+        let spi = wifi::Spi::new(
+            pio,
+            sm0,
+            dio,
+            clk,
+            pins.wl_cs.into_push_pull_output_in_state(PinState::High),
+        );
+        let socket = cortex_m::singleton!(: wifi::Socket = wifi::Socket::new(None)).unwrap();
+        // the chip ID alone would give every boot the same TCP sequence numbers
+        let seed = system.unique_id ^ hal_timer.get_counter().ticks();
+        (
+            wifi::WifiPort::new(socket),
+            runtime::NoPin,
+            wifi::run(spi, pins.wl_on.into_push_pull_output(), socket, seed),
+        )
+    };
+    #[cfg(not(any(feature = "pio-uart", feature = "wifi")))]
     let main = pin!(main_loop.main_loop(
-        &mut uart,
-        &mut uart_send,
+        &mut io,
+        &mut io_send,
+        &timer,
+        &mut output,
+        &mut input,
+        &digital,
+        &nvm,
+        &system,
+        watchdog,
+        &mut led_pin
+    ));
+    #[cfg(all(any(feature = "pio-uart", feature = "wifi"), not(feature = "repeater")))]
+    let main = pin!(main_loop.dual_main_loop(
+        &mut io,
+        &mut io_send,
+        &mut io2,
+        &mut io_send2,
         &timer,
         &mut output,
         &mut input,
+        &digital,
         &nvm,
-        &system
+        &system,
+        watchdog,
+        &mut led_pin
     ));
-    let blink = pin!(blink(&mut led_pin, &timer));
-    let Either::Left((Err(err), _)) = block_on(select(main, blink));
+    #[cfg(feature = "repeater")]
+    let main = pin!(main_loop.repeater_main_loop(
+        &mut io,
+        &mut io_send,
+        &mut io2,
+        &mut io_send2,
+        DOWNSTREAM_TIMEOUT_US,
+        &timer,
+        &mut output,
+        &mut input,
+        &digital,
+        &nvm,
+        &system,
+        watchdog,
+        &mut led_pin
+    ));
+    // the network comes up in the background while the main loop already answers on the UART
+    #[cfg(feature = "wifi")]
+    let main = pin!(async {
+        match select(main, pin!(wifi)).await {
+            futures::future::Either::Left((result, _)) => result,
+            futures::future::Either::Right((never, _)) => never,
+        }
+    });
+    let debounce = pin!(digital.run(&timer));
+    runtime::gate_clocks_in_sleep();
+    let Err(err) = block_on_with_idle(
+        async { select(main, debounce).await.factor_first().0 },
+        runtime::idle,
+    );
     match err {}
 }
-
-async fn blink<LedPin: OutputPin>(led_pin: &mut LedPin, timer: &Timer0) -> ! {
-    let mut until = timer.now();
-    loop {
-        led_pin.set_high().unwrap();
-        until += 500.millis();
-        timer.wait_until(until).await;
-        led_pin.set_low().unwrap();
-        until += 500.millis();
-        timer.wait_until(until).await;
-    }
-}
 /// Program metadata for `picotool info`
 #[unsafe(link_section = ".bi_entries")]
 #[used]
@@ -1,33 +1,135 @@
 use core::{
-    ptr::addr_of,
+    convert::Infallible,
+    ptr::{addr_of, addr_of_mut},
     sync::atomic::{AtomicBool, Ordering},
 };
 
 use cortex_m::interrupt;
-use pico_iox16_firmware::nvm::{NonvolatileStorage, default_nonvolatile_data};
-use rp235x_hal::rom_data::{flash_range_erase, flash_range_program};
+use defmt::info;
+use pico_iox16_firmware::{
+    nvm::{NonvolatileStorage, default_nonvolatile_data},
+    panic::{PanicRecord, PanicStorage},
+};
+use pico_iox16_protocol::{FLASH_CHUNK_SIZE, USER_DATA_SIZE};
+use rp235x_hal::rom_data::{
+    connect_internal_flash, flash_exit_xip, flash_flush_cache, flash_range_erase,
+    flash_range_program,
+};
+use zerocopy::{FromBytes as _, IntoBytes as _};
+
+use crate::{
+    pac,
+    runtime::{Board, Watchdog},
+};
 
-use crate::runtime::Board;
+/// Size of a flash sector, the unit of erasing.
+const SECTOR: usize = 4096;
+/// Size of a flash page, the unit of programming.
+const PAGE: usize = 256;
+// the user data is programmed as one whole page
+const _: () = assert!(USER_DATA_SIZE == PAGE);
+/// Number of sectors the data rotates through, so that each of them wears out that much slower.
+const SLOTS: usize = 6;
+/// Size of a journal entry, the number of a slot and its complement.
+const ENTRY: usize = 2;
+/// Size of the header of a journal, its generation and the complement of it.
+const HEADER: usize = 4;
+/// Number of entries a journal takes before the other one replaces it.
+const ENTRIES: usize = (SECTOR - HEADER) / ENTRY;
+/// Size and command of the flash's 64 KiB block erase, which the ROM uses where it fits.
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+/// Size of the staging area, `LENGTH(STAGING)` in memory.x.
+const STAGING_SIZE: usize = 1004 * 1024;
+// a chunk is programmed as one whole page
+const _: () = assert!(FLASH_CHUNK_SIZE == PAGE);
+/// Address where the flash is mapped, the ROM flash functions take offsets from it.
+const XIP_BASE: u32 = 0x1000_0000;
+/// Boot RAM, where the boot ROM leaves a function that restores the XIP mode it found at boot.
+const BOOTRAM_BASE: *const u32 = 0x400E_0000 as *const u32;
+
+/// Flash region for the nonvolatile data.
+///
+/// Every write goes to the slot after the current one, and then appends the number of that slot
+/// to the current journal. The last valid entry of the journal tells the current slot, no journal
+/// means slot 0. Programming only clears bits, so an erased journal can take [`ENTRIES`] entries
+/// before it needs to be erased again. An entry holds the number along with its complement, so
+/// that one that was only partially programmed when power went away doesn't pass for another slot.
+///
+/// A full journal is replaced by the other one, which is erased and starts with the next
+/// generation and the new entry. Until that is programmed, the full journal still tells the
+/// current slot, and afterwards it loses to the newer one, so a rollover cut short by a power
+/// loss never leaves the data without a journal.
+#[repr(C, align(4096))]
+struct Config {
+    journals: [[u8; SECTOR]; 2],
+    slots: [[u8; SECTOR]; SLOTS],
+}
+
+const fn initial_slots() -> [[u8; SECTOR]; SLOTS] {
+    let mut slots = [[0xFF; SECTOR]; SLOTS];
+    slots[0] = default_nonvolatile_data();
+    slots
+}
 
 #[unsafe(link_section = ".config")]
 #[used]
-static mut CONFIG: [u8; 4096] = default_nonvolatile_data();
+static mut CONFIG: Config = Config {
+    journals: [[0xFF; SECTOR]; 2],
+    slots: initial_slots(),
+};
+
+/// Flash sector for the record of a panic. The record takes its first page, so that the panic
+/// handler only needs to program it, and the next boot erases it again.
+#[repr(C, align(4096))]
+struct PanicSector([u8; SECTOR]);
+
+#[unsafe(link_section = ".panic_record")]
+#[used]
+static mut PANIC_SECTOR: PanicSector = PanicSector([0xFF; SECTOR]);
+
+/// Flash sector for the host's user data, in its first page. Left out of the image, so it is
+/// erased on a new chip and kept by firmware updates.
+#[repr(C, align(4096))]
+struct UserDataSector([u8; SECTOR]);
+
+#[unsafe(link_section = ".user_data")]
+#[used]
+static mut USER_DATA_SECTOR: UserDataSector = UserDataSector([0xFF; SECTOR]);
+
+/// Flash region a firmware update is staged in, as large as the region of the running image
+/// that it is copied over. Left out of the image like the user data.
+#[repr(C, align(4096))]
+struct Staging([u8; STAGING_SIZE]);
+
+#[unsafe(link_section = ".staging")]
+#[used]
+static mut STAGING: Staging = Staging([0xFF; STAGING_SIZE]);
+
+/// The sector [`copy_staged_in_ram`] copies, as the flash can't be read while it is erased.
+static mut SECTOR_BUFFER: [u8; SECTOR] = [0; SECTOR];
 
 static CONFIG_LOCK: AtomicBool = AtomicBool::new(false);
 
-pub struct Nvm(());
+/// Copy of the XIP setup function from boot RAM, which has to run from RAM as well.
+static mut XIP_SETUP: [u32; 64] = [0; 64];
+
+pub struct Nvm {
+    /// Fed right before erasing, which blocks everything else for tens of milliseconds
+    watchdog: &'static Watchdog,
+}
 impl Drop for Nvm {
     fn drop(&mut self) {
         CONFIG_LOCK.store(false, Ordering::Release);
     }
 }
 impl Nvm {
-    pub fn take() -> Option<Self> {
+    pub fn take(watchdog: &'static Watchdog) -> Option<Self> {
         if CONFIG_LOCK
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Some(Self(()))
+            Some(Self { watchdog })
         } else {
             None
         }
@@ -35,21 +137,309 @@ impl Nvm {
 }
 
 impl NonvolatileStorage<Board> for Nvm {
-    type Error = core::convert::Infallible;
+    type Error = Infallible;
 
     fn read(&self) -> nb::Result<[u8; 4096], Self::Error> {
-        Ok(unsafe { addr_of!(CONFIG).read_volatile() })
+        let slot = current_journal().map_or(0, |(_, journal)| journal.slot);
+        Ok(unsafe { addr_of!(CONFIG.slots[slot]).read_volatile() })
     }
 
     fn write(&self, data: &[u8; 4096]) -> nb::Result<(), Self::Error> {
-        interrupt::free(|_| unsafe {
-            flash_range_erase(addr_of!(CONFIG) as u32, 4096, 4096, 0xD8);
-            flash_range_program(
-                addr_of!(CONFIG) as u32,
-                data.as_ptr(),
-                4096,
-            );
-        });
+        let current = current_journal();
+        let slot = (current.as_ref().map_or(0, |(_, journal)| journal.slot) + 1) % SLOTS;
+        let slot_address = unsafe { addr_of!(CONFIG.slots[slot]) } as u32;
+        self.erase(slot_address);
+        program(slot_address, data);
+
+        let mut page = [0xFF; PAGE];
+        match current {
+            Some((index, journal)) if journal.free < ENTRIES => {
+                let offset = HEADER + journal.free * ENTRY;
+                page[offset % PAGE..][..ENTRY].copy_from_slice(&entry(slot as u8));
+                program(
+                    journal_address(index) + (offset - offset % PAGE) as u32,
+                    &page,
+                );
+            }
+            _ => {
+                let (index, generation) = current.map_or((0, 0), |(index, journal)| {
+                    (1 - index, journal.generation.wrapping_add(1))
+                });
+                // may hold what is left of the journal before the current one
+                self.erase(journal_address(index));
+                page[..HEADER].copy_from_slice(&header(generation));
+                page[HEADER..][..ENTRY].copy_from_slice(&entry(slot as u8));
+                program(journal_address(index), &page);
+            }
+        }
         Ok(())
     }
+
+    fn read_user_data(&self) -> nb::Result<[u8; USER_DATA_SIZE], Self::Error> {
+        Ok(unsafe {
+            addr_of!(USER_DATA_SECTOR)
+                .cast::<[u8; USER_DATA_SIZE]>()
+                .read_volatile()
+        })
+    }
+
+    fn write_user_data(&self, data: &[u8; USER_DATA_SIZE]) -> nb::Result<(), Self::Error> {
+        let address = addr_of!(USER_DATA_SECTOR) as u32;
+        self.erase(address);
+        program(address, data);
+        Ok(())
+    }
+
+    fn staging_size(&self) -> u32 {
+        STAGING_SIZE as u32
+    }
+
+    fn erase_staging(&self, offset: u32) -> nb::Result<(), Self::Error> {
+        self.erase(staging_address() + offset);
+        Ok(())
+    }
+
+    fn write_staging(
+        &self,
+        offset: u32,
+        data: &[u8; FLASH_CHUNK_SIZE],
+    ) -> nb::Result<(), Self::Error> {
+        program(staging_address() + offset, data);
+        Ok(())
+    }
+
+    fn read_staging(&self, offset: u32, buf: &mut [u8]) -> nb::Result<(), Self::Error> {
+        let staged = unsafe { addr_of!(STAGING).cast::<u8>().add(offset as usize) };
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { staged.add(i).read_volatile() };
+        }
+        Ok(())
+    }
+
+    /// Copies the image sector by sector and resets. The watchdog is stopped, as nothing can
+    /// feed it while the flash is busy. If power fails before the copy completes, the board only
+    /// boots into the bootloader again, to be flashed over USB.
+    fn boot_staged(&self, len: u32) -> ! {
+        info!("Copying {} bytes of staged firmware", len);
+        // SAFETY: nothing runs anymore that relies on the watchdog
+        let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+        watchdog.ctrl().modify(|_, w| w.enable().clear_bit());
+        let rom = rom_functions();
+        interrupt::disable();
+        unsafe { copy_staged_in_ram(&rom, staging_address(), len as usize) }
+    }
+}
+
+fn staging_address() -> u32 {
+    addr_of!(STAGING) as u32
+}
+
+impl Nvm {
+    /// Erases the sector at the given address.
+    fn erase(&self, address: u32) {
+        pico_iox16_firmware::runtime::Watchdog::feed(self.watchdog);
+        flash_op(address, None);
+    }
+}
+
+/// The panic record sector. Erasing it at boot comes before the watchdog is started.
+pub struct PanicFlash;
+
+impl PanicStorage<Board> for PanicFlash {
+    type Error = Infallible;
+
+    fn store(&self, record: &PanicRecord) {
+        let page = read_panic_page();
+        if page.iter().all(|&byte| byte == 0xFF) {
+            program(panic_sector_address(), record.as_bytes());
+        }
+    }
+
+    fn load(&self) -> Option<PanicRecord> {
+        let page = read_panic_page();
+        if page.iter().all(|&byte| byte == 0xFF) {
+            return None;
+        }
+        PanicRecord::read_from_bytes(&page).ok()
+    }
+
+    fn clear(&self) -> nb::Result<(), Self::Error> {
+        flash_op(panic_sector_address(), None);
+        Ok(())
+    }
+}
+
+fn panic_sector_address() -> u32 {
+    addr_of!(PANIC_SECTOR) as u32
+}
+
+fn read_panic_page() -> [u8; PAGE] {
+    unsafe { addr_of!(PANIC_SECTOR).cast::<[u8; PAGE]>().read_volatile() }
+}
+
+/// Programs the data to the given address, which has to be erased.
+fn program(address: u32, data: &[u8]) {
+    flash_op(address, Some(data));
+}
+
+/// Boot ROM functions for [`flash_op_in_ram`], looked up beforehand since the lookup runs from
+/// flash.
+struct RomFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    xip_setup: unsafe extern "C" fn(),
+}
+
+/// Looks up the boot ROM functions and copies the XIP setup function from boot RAM.
+fn rom_functions() -> RomFunctions {
+    unsafe {
+        for (i, word) in (*addr_of_mut!(XIP_SETUP)).iter_mut().enumerate() {
+            *word = BOOTRAM_BASE.add(i).read_volatile();
+        }
+    }
+    RomFunctions {
+        connect_internal_flash: connect_internal_flash::ptr(),
+        flash_exit_xip: flash_exit_xip::ptr(),
+        flash_range_erase: flash_range_erase::ptr(),
+        flash_range_program: flash_range_program::ptr(),
+        flash_flush_cache: flash_flush_cache::ptr(),
+        // the function is Thumb code
+        xip_setup: unsafe {
+            core::mem::transmute::<usize, unsafe extern "C" fn()>(addr_of!(XIP_SETUP) as usize | 1)
+        },
+    }
+}
+
+/// Erases the sector at the given address, or programs the data to it.
+///
+/// Flash can't be read while it's busy, so interrupts stay disabled throughout, as their handlers
+/// run from flash. Core 1 is never started and the DMA only moves ADC samples, so nothing else
+/// touches flash in the meantime.
+///
+/// Copies the XIP setup function every time, so that this also works from the panic handler,
+/// whichever state the rest of the firmware is in.
+fn flash_op(address: u32, data: Option<&[u8]>) {
+    let rom = rom_functions();
+    let (data, len) = match data {
+        Some(data) => (data.as_ptr(), data.len()),
+        None => (core::ptr::null(), 0),
+    };
+    interrupt::free(|_| unsafe { flash_op_in_ram(&rom, address - XIP_BASE, data, len) });
+}
+
+/// Runs the sequence the datasheet gives for erasing and programming flash: get the flash out of
+/// XIP mode, do the operation, flush the XIP cache and restore the XIP mode from boot.
+///
+/// Erases if `data` is null. Lives in RAM and calls nothing but the boot ROM.
+#[unsafe(link_section = ".data.ram_func")]
+#[inline(never)]
+unsafe fn flash_op_in_ram(rom: &RomFunctions, offset: u32, data: *const u8, len: usize) {
+    unsafe {
+        (rom.connect_internal_flash)();
+        (rom.flash_exit_xip)();
+        if data.is_null() {
+            (rom.flash_range_erase)(offset, SECTOR, BLOCK_SIZE, BLOCK_ERASE_CMD);
+        } else {
+            (rom.flash_range_program)(offset, data, len);
+        }
+        (rom.flash_flush_cache)();
+        (rom.xip_setup)();
+    }
+}
+
+/// Copies `len` bytes from `staging` to the start of flash, overwriting the running firmware,
+/// and resets the chip. Interrupts must be disabled.
+///
+/// Lives in RAM and calls nothing but the boot ROM, like [`flash_op_in_ram`]. The copy loops
+/// use volatile accesses, so that they don't turn into calls of `memcpy` in flash.
+#[unsafe(link_section = ".data.ram_func")]
+#[inline(never)]
+unsafe fn copy_staged_in_ram(rom: &RomFunctions, staging: u32, len: usize) -> ! {
+    let buffer = addr_of_mut!(SECTOR_BUFFER).cast::<u8>();
+    let mut offset = 0;
+    while offset < len {
+        unsafe {
+            let mut i = 0;
+            while i < SECTOR {
+                let byte = ((staging as usize + offset + i) as *const u8).read_volatile();
+                buffer.add(i).write_volatile(byte);
+                i += 1;
+            }
+            (rom.connect_internal_flash)();
+            (rom.flash_exit_xip)();
+            (rom.flash_range_erase)(offset as u32, SECTOR, BLOCK_SIZE, BLOCK_ERASE_CMD);
+            (rom.flash_range_program)(offset as u32, buffer, SECTOR);
+            (rom.flash_flush_cache)();
+            (rom.xip_setup)();
+        }
+        offset += SECTOR;
+    }
+    // SYSRESETREQ through the AIRCR, as `SCB::sys_reset` lives in flash
+    unsafe { (0xE000_ED0C as *mut u32).write_volatile(0x05FA_0004) };
+    loop {}
+}
+
+/// A journal that has at least one valid entry.
+struct Journal {
+    /// Incremented with every rollover, so that the newer of two journals wins
+    generation: u16,
+    /// Index of the first free entry, [`ENTRIES`] if the journal is full
+    free: usize,
+    /// The slot with the current data
+    slot: usize,
+}
+
+fn journal_address(index: usize) -> u32 {
+    unsafe { addr_of!(CONFIG.journals[index]) as u32 }
+}
+
+const fn header(generation: u16) -> [u8; HEADER] {
+    let [low, high] = generation.to_le_bytes();
+    [low, high, !low, !high]
+}
+
+const fn entry(slot: u8) -> [u8; ENTRY] {
+    [slot, !slot]
+}
+
+/// Reads the journal with the given index, `None` if it is erased or has no valid entry.
+fn read_journal(index: usize) -> Option<Journal> {
+    let journal = unsafe { addr_of!(CONFIG.journals[index]).read_volatile() };
+    let generation = u16::from_le_bytes([journal[0], journal[1]]);
+    if header(generation) != journal[..HEADER] {
+        return None;
+    }
+    let entries = journal[HEADER..].chunks_exact(ENTRY);
+    let free = entries
+        .clone()
+        .position(|entry| entry == [0xFF; ENTRY])
+        .unwrap_or(ENTRIES);
+    // the last entry may have been only partially programmed when power went away, which leaves
+    // the one before it
+    let slot = entries
+        .take(free)
+        .rev()
+        .find(|bytes| usize::from(bytes[0]) < SLOTS && entry(bytes[0]) == **bytes)?[0];
+    Some(Journal {
+        generation,
+        free,
+        slot: usize::from(slot),
+    })
+}
+
+/// Returns the journal that tells the current slot along with its index. Of two valid journals,
+/// the older one is only left over from the last rollover.
+fn current_journal() -> Option<(usize, Journal)> {
+    (0..2)
+        .filter_map(|index| Some((index, read_journal(index)?)))
+        .reduce(|older, newer| {
+            if (newer.1.generation.wrapping_sub(older.1.generation) as i16) > 0 {
+                newer
+            } else {
+                older
+            }
+        })
 }
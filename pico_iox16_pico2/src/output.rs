@@ -1,22 +1,62 @@
 use core::convert::Infallible;
 
+use cortex_m::interrupt;
+use pico_iox16_firmware::output::Pwm;
 use rp235x_hal::{
     gpio::{
-        FunctionNull, Pin, PullDown,
+        AnyPin, FunctionNull, Pin, PullDown,
         bank0::{
             Gpio0, Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Gpio9, Gpio10, Gpio11,
             Gpio12, Gpio13, Gpio14, Gpio15,
         },
     },
-    pwm::{FreeRunning, Slice, Slices},
+    pac,
+    pwm::{
+        self, FreeRunning, Pwm0, Pwm1, Pwm2, Pwm3, Pwm4, Pwm5, Pwm6, Pwm7, Slice, SliceId, Slices,
+        ValidPwmOutputPin,
+    },
 };
 
 use crate::runtime::Board;
 
-pub struct Output {
-    slices: Slices,
+/// A group driven by one PWM slice, the default for all groups.
+pub type PwmGroup<S> = Slice<S, FreeRunning>;
+
+/// A group of [`Output`].
+pub trait Group: Pwm<Board, Error = Infallible> {
+    /// The bit of the group's PWM slice in the enable register, 0 without one.
+    const SLICE_MASK: u32 = 0;
+}
+impl<S: SliceId> Group for PwmGroup<S> {
+    const SLICE_MASK: u32 = 1 << S::DYN.num;
+}
+
+/// The eight output groups, each driven by whatever the board has for them: a PWM slice or
+/// e.g. a [DAC](crate::dac).
+pub struct Output<G0, G1, G2, G3, G4, G5, G6, G7> {
+    pub pwm0: G0,
+    pub pwm1: G1,
+    pub pwm2: G2,
+    pub pwm3: G3,
+    pub pwm4: G4,
+    pub pwm5: G5,
+    pub pwm6: G6,
+    pub pwm7: G7,
 }
-impl Output {
+impl
+    Output<
+        PwmGroup<Pwm0>,
+        PwmGroup<Pwm1>,
+        PwmGroup<Pwm2>,
+        PwmGroup<Pwm3>,
+        PwmGroup<Pwm4>,
+        PwmGroup<Pwm5>,
+        PwmGroup<Pwm6>,
+        PwmGroup<Pwm7>,
+    >
+{
+    /// All groups as PWM outputs.
+    #[cfg_attr(any(feature = "mcp4922", feature = "pio-pwm"), allow(dead_code))]
     pub fn new(
         OutputPins {
             gpio0,
@@ -38,83 +78,121 @@ impl Output {
         }: OutputPins,
         mut slices: Slices,
     ) -> Self {
-        slices.pwm0.channel_a.output_to(gpio0);
-        slices.pwm0.channel_b.output_to(gpio1);
-        slices.pwm1.channel_a.output_to(gpio2);
-        slices.pwm1.channel_b.output_to(gpio3);
-        slices.pwm2.channel_a.output_to(gpio4);
-        slices.pwm2.channel_b.output_to(gpio5);
-        slices.pwm3.channel_a.output_to(gpio6);
-        slices.pwm3.channel_b.output_to(gpio7);
-        slices.pwm4.channel_a.output_to(gpio8);
-        slices.pwm4.channel_b.output_to(gpio9);
-        slices.pwm5.channel_a.output_to(gpio10);
-        slices.pwm5.channel_b.output_to(gpio11);
-        slices.pwm6.channel_a.output_to(gpio12);
-        slices.pwm6.channel_b.output_to(gpio13);
-        slices.pwm7.channel_a.output_to(gpio14);
-        slices.pwm7.channel_b.output_to(gpio15);
-        slices.enable_simultaneous(0xFF);
-        Self { slices }
+        slices.enable_simultaneous(Self::SLICE_MASK as u8);
+        Self {
+            pwm0: pwm_group(slices.pwm0, gpio0, gpio1),
+            pwm1: pwm_group(slices.pwm1, gpio2, gpio3),
+            pwm2: pwm_group(slices.pwm2, gpio4, gpio5),
+            pwm3: pwm_group(slices.pwm3, gpio6, gpio7),
+            pwm4: pwm_group(slices.pwm4, gpio8, gpio9),
+            pwm5: pwm_group(slices.pwm5, gpio10, gpio11),
+            pwm6: pwm_group(slices.pwm6, gpio12, gpio13),
+            pwm7: pwm_group(slices.pwm7, gpio14, gpio15),
+        }
     }
 }
-impl pico_iox16_firmware::output::Output<Board> for Output {
+
+/// Routes the channels of a slice to its pins. The slice needs to be enabled already.
+pub fn pwm_group<S: SliceId, A: AnyPin, B: AnyPin>(
+    mut slice: PwmGroup<S>,
+    a: A,
+    b: B,
+) -> PwmGroup<S>
+where
+    A::Id: ValidPwmOutputPin<S, pwm::A>,
+    B::Id: ValidPwmOutputPin<S, pwm::B>,
+{
+    slice.channel_a.output_to(a);
+    slice.channel_b.output_to(b);
+    slice
+}
+
+impl<G0: Group, G1: Group, G2: Group, G3: Group, G4: Group, G5: Group, G6: Group, G7: Group>
+    Output<G0, G1, G2, G3, G4, G5, G6, G7>
+{
+    /// The PWM slices of the groups, as bits of the enable register.
+    pub const SLICE_MASK: u32 = G0::SLICE_MASK
+        | G1::SLICE_MASK
+        | G2::SLICE_MASK
+        | G3::SLICE_MASK
+        | G4::SLICE_MASK
+        | G5::SLICE_MASK
+        | G6::SLICE_MASK
+        | G7::SLICE_MASK;
+}
+impl<G0: Group, G1: Group, G2: Group, G3: Group, G4: Group, G5: Group, G6: Group, G7: Group>
+    pico_iox16_firmware::output::Output<Board> for Output<G0, G1, G2, G3, G4, G5, G6, G7>
+{
     type Error = Infallible;
-    type Pwm0 = Slice<rp235x_hal::pwm::Pwm0, FreeRunning>;
+    type Pwm0 = G0;
     fn pwm0(&self) -> &Self::Pwm0 {
-        &self.slices.pwm0
+        &self.pwm0
     }
     fn pwm0_mut(&mut self) -> &mut Self::Pwm0 {
-        &mut self.slices.pwm0
+        &mut self.pwm0
     }
-    type Pwm1 = Slice<rp235x_hal::pwm::Pwm1, FreeRunning>;
+    type Pwm1 = G1;
     fn pwm1(&self) -> &Self::Pwm1 {
-        &self.slices.pwm1
+        &self.pwm1
     }
     fn pwm1_mut(&mut self) -> &mut Self::Pwm1 {
-        &mut self.slices.pwm1
+        &mut self.pwm1
     }
-    type Pwm2 = Slice<rp235x_hal::pwm::Pwm2, FreeRunning>;
+    type Pwm2 = G2;
     fn pwm2(&self) -> &Self::Pwm2 {
-        &self.slices.pwm2
+        &self.pwm2
     }
     fn pwm2_mut(&mut self) -> &mut Self::Pwm2 {
-        &mut self.slices.pwm2
+        &mut self.pwm2
     }
-    type Pwm3 = Slice<rp235x_hal::pwm::Pwm3, FreeRunning>;
+    type Pwm3 = G3;
     fn pwm3(&self) -> &Self::Pwm3 {
-        &self.slices.pwm3
+        &self.pwm3
     }
     fn pwm3_mut(&mut self) -> &mut Self::Pwm3 {
-        &mut self.slices.pwm3
+        &mut self.pwm3
     }
-    type Pwm4 = Slice<rp235x_hal::pwm::Pwm4, FreeRunning>;
+    type Pwm4 = G4;
     fn pwm4(&self) -> &Self::Pwm4 {
-        &self.slices.pwm4
+        &self.pwm4
     }
     fn pwm4_mut(&mut self) -> &mut Self::Pwm4 {
-        &mut self.slices.pwm4
+        &mut self.pwm4
     }
-    type Pwm5 = Slice<rp235x_hal::pwm::Pwm5, FreeRunning>;
+    type Pwm5 = G5;
     fn pwm5(&self) -> &Self::Pwm5 {
-        &self.slices.pwm5
+        &self.pwm5
     }
     fn pwm5_mut(&mut self) -> &mut Self::Pwm5 {
-        &mut self.slices.pwm5
+        &mut self.pwm5
     }
-    type Pwm6 = Slice<rp235x_hal::pwm::Pwm6, FreeRunning>;
+    type Pwm6 = G6;
     fn pwm6(&self) -> &Self::Pwm6 {
-        &self.slices.pwm6
+        &self.pwm6
     }
     fn pwm6_mut(&mut self) -> &mut Self::Pwm6 {
-        &mut self.slices.pwm6
+        &mut self.pwm6
     }
-    type Pwm7 = Slice<rp235x_hal::pwm::Pwm7, FreeRunning>;
+    type Pwm7 = G7;
     fn pwm7(&self) -> &Self::Pwm7 {
-        &self.slices.pwm7
+        &self.pwm7
     }
     fn pwm7_mut(&mut self) -> &mut Self::Pwm7 {
-        &mut self.slices.pwm7
+        &mut self.pwm7
+    }
+    fn synchronize(&mut self) -> Result<(), Self::Error> {
+        let mask = Self::SLICE_MASK;
+        // SAFETY: only the slices of the groups are touched, which this owns
+        let pwm = unsafe { &*pac::PWM::ptr() };
+        // stop the slices, start their counters over and let them run again on the same cycle
+        interrupt::free(|_| {
+            pwm.en().modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+            for slice in (0..8).filter(|slice| mask & 1 << slice != 0) {
+                pwm.ch(slice).ctr().write(|w| unsafe { w.bits(0) });
+            }
+            pwm.en().modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+        });
+        Ok(())
     }
 }
 
@@ -1,36 +1,26 @@
 use core::panic::PanicInfo;
-use cortex_m::{delay::Delay, interrupt};
+#[cfg(not(feature = "wifi"))]
+use cortex_m::delay::Delay;
+use cortex_m::interrupt;
+#[cfg(not(feature = "wifi"))]
 use embedded_hal::digital::OutputPin;
+use pico_iox16_firmware::panic::{PanicRecord, PanicStorage as _};
+#[cfg(not(feature = "wifi"))]
 use rp235x_hal::{Sio, gpio::Pins, pac::Peripherals};
 
+use crate::nvm::PanicFlash;
+
 /// This function is called on panic.
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
+fn panic(info: &PanicInfo) -> ! {
     // Use a critical section to ensure the setup is atomic.
     interrupt::free(|_| {
-        // Unsafely take ownership of the peripherals.
-        // SAFETY: This is the panic handler. We are halting the system and
-        // providing a debug signal. We can risk taking the peripherals again.
-        let mut pac = unsafe { Peripherals::steal() };
-        let core = unsafe { cortex_m::Peripherals::steal() };
-        let sio = Sio::new(pac.SIO);
-
-        let pins = Pins::new(
-            pac.IO_BANK0,
-            pac.PADS_BANK0,
-            sio.gpio_bank0,
-            &mut pac.RESETS,
-        );
-        let mut delay = Delay::new(core.SYST, 125_000_000);
+        // Keep the message for the next boot to report.
+        PanicFlash.store(&PanicRecord::new(info));
 
-        // Configure GPIO25, the onboard LED pin, as a push-pull output.
-        let mut led = pins.gpio25.into_push_pull_output();
-        loop {
-            let _ = led.set_high();
-            delay.delay_ms(100);
-            let _ = led.set_low();
-            delay.delay_ms(100);
-        }
+        // The Pico 2 W has its LED on the CYW43, which is out of reach here.
+        #[cfg(not(feature = "wifi"))]
+        blink();
     });
 
     // Loop forever to halt the processor.
@@ -38,3 +28,31 @@ fn panic(_info: &PanicInfo) -> ! {
         cortex_m::asm::wfi(); // Wait for interrupt, effectively sleeping
     }
 }
+
+/// Blinks the LED at 5 Hz.
+#[cfg(not(feature = "wifi"))]
+fn blink() -> ! {
+    // Unsafely take ownership of the peripherals.
+    // SAFETY: Only called by the panic handler. We are halting the system and
+    // providing a debug signal. We can risk taking the peripherals again.
+    let mut pac = unsafe { Peripherals::steal() };
+    let core = unsafe { cortex_m::Peripherals::steal() };
+    let sio = Sio::new(pac.SIO);
+
+    let pins = Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+    let mut delay = Delay::new(core.SYST, 125_000_000);
+
+    // Configure GPIO25, the onboard LED pin, as a push-pull output.
+    let mut led = pins.gpio25.into_push_pull_output();
+    loop {
+        let _ = led.set_high();
+        delay.delay_ms(100);
+        let _ = led.set_low();
+        delay.delay_ms(100);
+    }
+}
@@ -0,0 +1,229 @@
+//! Output groups generated by PIO state machines instead of PWM slices, for a finer resolution at
+//! low frequencies.
+//!
+//! A PWM slice counts at most 16 bits, and for low frequencies its divider throws away what the
+//! counter can't cover. The state machines count 32 bit periods in steps of three system clock
+//! cycles instead, which gives 50 000 steps at 1 kHz, 16 bits below 763 Hz and 5 000 000 steps
+//! at 10 Hz.
+//!
+//! Each channel runs in its own state machine, and the two of a group are started together, so
+//! that they stay in phase. A duty cycle of 100% still pulls the pin low for the four cycles
+//! between the periods.
+
+use core::convert::Infallible;
+
+use fugit::HertzU32;
+use pio::{
+    Instruction, InstructionOperands, JmpCondition, MovDestination, MovOperation, MovSource,
+    OutDestination, SideSet,
+};
+use rounded_div::RoundedDiv as _;
+use rp235x_hal::pio::{
+    InstalledProgram, PIO, PIOBuilder, PIOExt, PinDir, PinState, Running, StateMachine,
+    StateMachineIndex, Stopped, Tx, UninitStateMachine,
+};
+
+use crate::runtime::Board;
+
+/// Duty cycle of 100%, as in the protocol.
+const FULL_DUTY_CYCLE: u16 = 0x8000;
+/// System clock cycles per step of a period, the length of the counting loop.
+const CYCLES_PER_STEP: u32 = 3;
+/// Frequency until the first `OutputSet`.
+const INITIAL_FREQUENCY: u16 = 1_000;
+
+/// Installs the program that the channels run, once for all groups in the PIO.
+pub fn install<P: PIOExt>(pio: &mut PIO<P>) -> InstalledProgram<P> {
+    // pull noblock side 0   ; the next level, or the last one again from X
+    // mov x, osr
+    // mov y, isr            ; the number of steps of a period minus one
+    // countloop:
+    // jmp x!=y noset
+    // jmp skip side 1       ; high from the step that equals the level down to 0
+    // noset:
+    // nop
+    // skip:
+    // jmp y-- countloop
+    let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new_with_side_set(
+        SideSet::new(true, 1, false),
+    );
+    let mut countloop = a.label();
+    let mut noset = a.label();
+    let mut skip = a.label();
+    a.pull_with_side_set(false, false, 0);
+    a.mov(MovDestination::X, MovOperation::None, MovSource::OSR);
+    a.mov(MovDestination::Y, MovOperation::None, MovSource::ISR);
+    a.bind(&mut countloop);
+    a.jmp(JmpCondition::XNotEqualY, &mut noset);
+    a.jmp_with_side_set(JmpCondition::Always, &mut skip, 1);
+    a.bind(&mut noset);
+    a.nop();
+    a.bind(&mut skip);
+    a.jmp(JmpCondition::YDecNonZero, &mut countloop);
+    pio.install(&a.assemble_program()).unwrap()
+}
+
+/// An output group with a state machine per channel.
+pub struct PioPwm<P: PIOExt, A: StateMachineIndex, B: StateMachineIndex> {
+    system_clock: u32,
+    channel_a: PioChannel<P, A>,
+    channel_b: PioChannel<P, B>,
+}
+impl<P: PIOExt, A: StateMachineIndex, B: StateMachineIndex> PioPwm<P, A, B> {
+    /// Starts both channels at 0%. The pins need to be in the PIO's function already.
+    pub fn new(
+        program: &InstalledProgram<P>,
+        sm_a: UninitStateMachine<(P, A)>,
+        sm_b: UninitStateMachine<(P, B)>,
+        pin_a: u8,
+        pin_b: u8,
+        system_clock: HertzU32,
+    ) -> Self {
+        let steps = steps(system_clock.to_Hz(), INITIAL_FREQUENCY);
+        let (mut sm_a, mut channel_a) = PioChannel::new(program, sm_a, pin_a, steps);
+        let (mut sm_b, mut channel_b) = PioChannel::new(program, sm_b, pin_b, steps);
+        channel_a.load(&mut sm_a);
+        channel_b.load(&mut sm_b);
+        let (sm_a, sm_b) = sm_a.with(sm_b).start().free();
+        channel_a.sm = Some(sm_a);
+        channel_b.sm = Some(sm_b);
+        Self {
+            system_clock: system_clock.to_Hz(),
+            channel_a,
+            channel_b,
+        }
+    }
+}
+impl<P: PIOExt, A: StateMachineIndex, B: StateMachineIndex> pico_iox16_firmware::output::Pwm<Board>
+    for PioPwm<P, A, B>
+{
+    type Error = Infallible;
+    type ChannelA = PioChannel<P, A>;
+    type ChannelB = PioChannel<P, B>;
+    fn get_frequency(&self) -> Result<u16, Self::Error> {
+        let cycles = CYCLES_PER_STEP * self.channel_a.steps;
+        Ok(self.system_clock.rounded_div(cycles) as u16)
+    }
+    fn channel_a(&self) -> &Self::ChannelA {
+        &self.channel_a
+    }
+    fn channel_b(&self) -> &Self::ChannelB {
+        &self.channel_b
+    }
+    fn set_frequency(&mut self, frequency: u16) -> Result<(), Self::Error> {
+        let steps = steps(self.system_clock, frequency.clamp(10, 50_000));
+        // restarting cuts the running period short
+        if steps == self.channel_a.steps {
+            return Ok(());
+        }
+        let (sm_a, sm_b) = (self.channel_a.sm.take(), self.channel_b.sm.take());
+        let (mut sm_a, mut sm_b) = sm_a.unwrap().with(sm_b.unwrap()).stop().free();
+        self.channel_a.steps = steps;
+        self.channel_b.steps = steps;
+        self.channel_a.load(&mut sm_a);
+        self.channel_b.load(&mut sm_b);
+        let (sm_a, sm_b) = sm_a.with(sm_b).start().free();
+        self.channel_a.sm = Some(sm_a);
+        self.channel_b.sm = Some(sm_b);
+        Ok(())
+    }
+    fn channel_a_mut(&mut self) -> &mut Self::ChannelA {
+        &mut self.channel_a
+    }
+    fn channel_b_mut(&mut self) -> &mut Self::ChannelB {
+        &mut self.channel_b
+    }
+}
+impl<P: PIOExt, A: StateMachineIndex, B: StateMachineIndex> crate::output::Group
+    for PioPwm<P, A, B>
+{
+    // started in phase by the group itself
+}
+
+/// Steps of a period of the given frequency, including the setup of the next period, which
+/// takes as long as a step.
+fn steps(system_clock: u32, frequency: u16) -> u32 {
+    system_clock.rounded_div(CYCLES_PER_STEP * u32::from(frequency))
+}
+
+pub struct PioChannel<P: PIOExt, SM: StateMachineIndex> {
+    /// Only taken while the group changes the period
+    sm: Option<StateMachine<(P, SM), Running>>,
+    tx: Tx<(P, SM)>,
+    steps: u32,
+    duty_cycle: u16,
+}
+impl<P: PIOExt, SM: StateMachineIndex> PioChannel<P, SM> {
+    fn new(
+        program: &InstalledProgram<P>,
+        sm: UninitStateMachine<(P, SM)>,
+        pin: u8,
+        steps: u32,
+    ) -> (StateMachine<(P, SM), Stopped>, Self) {
+        // SAFETY: the program is never uninstalled
+        let program = unsafe { program.share() };
+        let (mut sm, _, tx) = PIOBuilder::from_installed_program(program)
+            .side_set_pin_base(pin)
+            .build(sm);
+        sm.set_pins([(pin, PinState::Low)]);
+        sm.set_pindirs([(pin, PinDir::Output)]);
+        let channel = Self {
+            sm: None,
+            tx,
+            steps,
+            duty_cycle: 0,
+        };
+        (sm, channel)
+    }
+
+    /// Loads the period into the stopped state machine, followed by the level for it.
+    fn load(&mut self, sm: &mut StateMachine<(P, SM), Stopped>) {
+        sm.clear_fifos();
+        self.tx.write(self.steps - 2);
+        sm.exec_instruction(Instruction {
+            operands: InstructionOperands::PULL {
+                if_empty: false,
+                block: false,
+            },
+            delay: 0,
+            side_set: None,
+        });
+        sm.exec_instruction(Instruction {
+            operands: InstructionOperands::OUT {
+                destination: OutDestination::ISR,
+                bit_count: 32,
+            },
+            delay: 0,
+            side_set: None,
+        });
+        self.tx.write(self.level());
+    }
+
+    /// The step from which on the pin is high, or one that never comes for 0%.
+    fn level(&self) -> u32 {
+        let high = (u64::from(self.steps - 1) * u64::from(self.duty_cycle))
+            .rounded_div(u64::from(FULL_DUTY_CYCLE));
+        (high as u32).wrapping_sub(1)
+    }
+}
+impl<P: PIOExt, SM: StateMachineIndex> pico_iox16_firmware::output::PwmChannel<Board>
+    for PioChannel<P, SM>
+{
+    type Error = Infallible;
+    fn max_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(FULL_DUTY_CYCLE)
+    }
+    fn get_duty_cycle(&self) -> Result<u16, Self::Error> {
+        Ok(self.duty_cycle)
+    }
+    fn set_duty_cycle(&mut self, duty_cycle: u16) -> Result<(), Self::Error> {
+        self.duty_cycle = duty_cycle.min(FULL_DUTY_CYCLE);
+        let level = self.level();
+        // only the latest level matters, drop one the state machine didn't get to yet
+        if let Some(sm) = &mut self.sm {
+            sm.clear_fifos();
+        }
+        self.tx.write(level);
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use core::convert::Infallible;
+
+use defmt::info;
+use pico_iox16_firmware::runtime::{Read, ReadError, Write};
+use pio::{InSource, JmpCondition, OutDestination, SetDestination, SideSet, WaitSource};
+use rp235x_hal::pio::{
+    PIO, PIOBuilder, PIOExt, PinDir, Running, Rx, ShiftDirection, StateMachine,
+    StateMachineIndex, Tx, UninitStateMachine,
+};
+
+use crate::runtime::Board;
+
+/// IRQ flag raised by the receiver on a framing error or break.
+const FRAMING_ERROR_IRQ: u8 = 4;
+
+/// 8N1 UART in two PIO state machines, for a second bus or a debug console on spare pins.
+///
+/// There is no transmit enable pin, so an RS-485 transceiver on this port has to switch
+/// direction by itself.
+pub struct PioUart<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> {
+    pio: PIO<P>,
+    _tx_sm: StateMachine<(P, TX), Running>,
+    _rx_sm: StateMachine<(P, RX), Running>,
+    tx: Tx<(P, TX)>,
+    rx: Rx<(P, RX)>,
+}
+impl<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> PioUart<P, TX, RX> {
+    /// Installs the programs and starts the state machines. The pins need to be in the PIO's
+    /// function already.
+    pub fn new(
+        mut pio: PIO<P>,
+        tx_sm: UninitStateMachine<(P, TX)>,
+        rx_sm: UninitStateMachine<(P, RX)>,
+        tx_pin: u8,
+        rx_pin: u8,
+        baudrate: u32,
+        system_clock: u32,
+    ) -> Self {
+        // both programs take 8 cycles per bit
+        let divisor = system_clock as u64 * 256 / (8 * baudrate as u64);
+        let (int, frac) = ((divisor >> 8) as u16, divisor as u8);
+
+        // pull       side 1 [7]   ; stop bit, or idle line while waiting for data
+        // set x, 7   side 0 [7]   ; start bit
+        // bitloop:
+        // out pins, 1
+        // jmp x-- bitloop [6]
+        let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new_with_side_set(
+            SideSet::new(true, 1, false),
+        );
+        let mut bitloop = a.label();
+        a.pull_with_delay_and_side_set(false, true, 7, 1);
+        a.set_with_delay_and_side_set(SetDestination::X, 7, 7, 0);
+        a.bind(&mut bitloop);
+        a.out(OutDestination::PINS, 1);
+        a.jmp_with_delay(JmpCondition::XDecNonZero, &mut bitloop, 6);
+        let program = pio.install(&a.assemble_program()).unwrap();
+        let (mut tx_sm, _, tx) = PIOBuilder::from_installed_program(program)
+            .out_pins(tx_pin, 1)
+            .side_set_pin_base(tx_pin)
+            .out_shift_direction(ShiftDirection::Right)
+            .clock_divisor_fixed_point(int, frac)
+            .build(tx_sm);
+        tx_sm.set_pins([(tx_pin, rp235x_hal::pio::PinState::High)]);
+        tx_sm.set_pindirs([(tx_pin, PinDir::Output)]);
+
+        // start:
+        // wait 0 pin 0           ; start bit
+        // set x, 7 [10]          ; continue in the middle of the first data bit
+        // bitloop:
+        // in pins, 1
+        // jmp x-- bitloop [6]
+        // jmp pin good_stop
+        // irq 4 rel              ; framing error or break
+        // wait 1 pin 0
+        // jmp start
+        // good_stop:
+        // push
+        let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new();
+        let mut start = a.label();
+        let mut bitloop = a.label();
+        let mut good_stop = a.label();
+        a.bind(&mut start);
+        a.wait(0, WaitSource::PIN, 0, false);
+        a.set_with_delay(SetDestination::X, 7, 10);
+        a.bind(&mut bitloop);
+        a.r#in(InSource::PINS, 1);
+        a.jmp_with_delay(JmpCondition::XDecNonZero, &mut bitloop, 6);
+        a.jmp(JmpCondition::PinHigh, &mut good_stop);
+        a.irq(false, false, FRAMING_ERROR_IRQ, true);
+        a.wait(1, WaitSource::PIN, 0, false);
+        a.jmp(JmpCondition::Always, &mut start);
+        a.bind(&mut good_stop);
+        a.push(false, true);
+        let program = pio.install(&a.assemble_program()).unwrap();
+        let (mut rx_sm, rx, _) = PIOBuilder::from_installed_program(program)
+            .in_pin_base(rx_pin)
+            .jmp_pin(rx_pin)
+            .in_shift_direction(ShiftDirection::Right)
+            .clock_divisor_fixed_point(int, frac)
+            .build(rx_sm);
+        rx_sm.set_pindirs([(rx_pin, PinDir::Input)]);
+
+        Self {
+            pio,
+            _tx_sm: tx_sm.start(),
+            _rx_sm: rx_sm.start(),
+            tx,
+            rx,
+        }
+    }
+
+    /// The IRQ flag of the receiver, `irq 4 rel` is relative to the state machine.
+    fn framing_error_flag() -> u8 {
+        1 << ((FRAMING_ERROR_IRQ + RX::id() as u8) % 4)
+    }
+}
+impl<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> Read<Board> for PioUart<P, TX, RX> {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        let flag = Self::framing_error_flag();
+        if self.pio.get_irq_raw() & flag != 0 {
+            self.pio.clear_irq(flag);
+            info!("PIO UART read error: framing error or break");
+            return Err(nb::Error::Other(ReadError::RecoverableError));
+        }
+        let mut n = 0;
+        while n < buf.len() {
+            let Some(value) = self.rx.read() else {
+                break;
+            };
+            // the byte was shifted in from the left
+            buf[n] = (value >> 24) as u8;
+            n += 1;
+        }
+        if n == 0 && !buf.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(n)
+        }
+    }
+}
+impl<P: PIOExt, TX: StateMachineIndex, RX: StateMachineIndex> Write<Board> for PioUart<P, TX, RX> {
+    type Error = Infallible;
+
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        let mut n = 0;
+        for &byte in buf {
+            if !self.tx.write(byte as u32) {
+                break;
+            }
+            self.tx.clear_stalled_flag();
+            n += 1;
+        }
+        if n == 0 && !buf.is_empty() {
+            Err(nb::Error::WouldBlock)
+        } else {
+            Ok(n)
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        // the transmitter stalls on `pull` once it has shifted out the last data bit
+        if self.tx.is_empty() && self.tx.has_stalled() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
@@ -1,72 +1,425 @@
-use core::convert::Infallible;
+use core::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    sync::atomic::{AtomicU8, AtomicU32, AtomicUsize, Ordering},
+};
 
-use defmt::info;
-use embedded_hal::pwm::SetDutyCycle;
+use cortex_m::peripheral::NVIC;
+use defmt::{info, warn};
+use embedded_hal::{
+    digital::{OutputPin as _, StatefulOutputPin as _},
+    pwm::SetDutyCycle,
+};
 use embedded_hal_0_2::PwmPin;
-use fugit::Instant;
+use fugit::{HertzU32, Instant, MicrosDurationU32, MicrosDurationU64, RateExtU32 as _};
 use pico_iox16_firmware::runtime::{Read, ReadError, Write};
+use pico_iox16_protocol::{RebootMode, ResetCause};
 use rounded_div::RoundedDiv as _;
 use rp235x_hal::{
     Timer,
+    gpio::{FunctionSio, Pin, PinId, PullType, SioOutput},
+    pac::{self, UART0, interrupt},
     pwm::{AnySlice, Channel, ChannelId, FreeRunning, Slice, SliceId},
-    timer::CopyableTimer0,
-    uart::{Enabled, UartDevice, UartPeripheral, ValidUartPinout},
+    timer::{Alarm as _, Alarm0, Alarm1, CopyableTimer0},
+    uart::{
+        DataBits, Disabled, Enabled, Parity, StopBits, UartConfig, UartPeripheral,
+        ValidUartPinout,
+    },
 };
 
 pub enum Board {}
 
-pub struct Timer0(pub Timer<CopyableTimer0>);
+/// Timer0, with alarm 0 waking the executor from `wfe` when a wait is over.
+pub struct Timer0 {
+    timer: Timer<CopyableTimer0>,
+    alarm: RefCell<Alarm0<CopyableTimer0>>,
+    /// When the alarm is due, if it was scheduled
+    alarm_at: Cell<Option<Instant<u64, 1, 1_000_000>>>,
+}
+impl Timer0 {
+    pub fn new(mut timer: Timer<CopyableTimer0>) -> Self {
+        let mut alarm = timer.alarm_0().unwrap();
+        alarm.enable_interrupt();
+        // SAFETY: the handler only clears the interrupt of alarm 0
+        unsafe { NVIC::unmask(pac::Interrupt::TIMER0_IRQ_0) };
+        Self {
+            timer,
+            alarm: RefCell::new(alarm),
+            alarm_at: Cell::new(None),
+        }
+    }
+}
 impl pico_iox16_firmware::runtime::Timer<Board, u64, 1, 1_000_000> for Timer0 {
     fn now(&self) -> Instant<u64, 1, 1_000_000> {
-        self.0.get_counter()
+        self.timer.get_counter()
+    }
+    fn wake_at(&self, at: Instant<u64, 1, 1_000_000>) -> bool {
+        // several tasks may be waiting, the earliest one needs the alarm
+        if let Some(due) = self.alarm_at.get()
+            && due > self.now()
+            && due <= at
+        {
+            return true;
+        }
+        if self.alarm.borrow_mut().schedule_at(at).is_err() {
+            return false;
+        }
+        self.alarm_at.set(Some(at));
+        true
+    }
+}
+
+#[interrupt]
+fn TIMER0_IRQ_0() {
+    // SAFETY: alarm 0 is owned by `Timer0`, which only schedules it, so clearing the interrupt
+    // here doesn't interfere
+    let timer = unsafe { &*pac::TIMER0::ptr() };
+    timer.intf().modify(|_, w| w.alarm_0().clear_bit());
+    timer.intr().write(|w| w.alarm_0().clear_bit_by_one());
+}
+
+/// Stops the clocks of the peripherals that are unused while the board sleeps in low-power mode:
+/// the ADC and its DMA, which are paused between sweeps, the PWM slices, whose outputs are at 0%
+/// then, and the peripherals the firmware never uses. Takes effect only in deep sleep.
+pub fn gate_clocks_in_sleep() {
+    // SAFETY: `init_clocks_and_plls` is done with the registers, and only `idle` enters deep sleep
+    let clocks = unsafe { &*pac::CLOCKS::ptr() };
+    clocks.sleep_en0().modify(|_, w| {
+        w.clk_adc()
+            .clear_bit()
+            .clk_sys_adc()
+            .clear_bit()
+            .clk_sys_dma()
+            .clear_bit()
+            .clk_hstx()
+            .clear_bit()
+            .clk_sys_hstx()
+            .clear_bit()
+            .clk_sys_i2c0()
+            .clear_bit()
+            .clk_sys_pio2()
+            .clear_bit()
+            .clk_sys_pwm()
+            .clear_bit()
+            .clk_sys_sha256()
+            .clear_bit()
+    });
+    clocks.sleep_en1().modify(|_, w| {
+        w.clk_peri_spi0()
+            .clear_bit()
+            .clk_sys_spi0()
+            .clear_bit()
+            .clk_sys_timer1()
+            .clear_bit()
+            .clk_sys_trng()
+            .clear_bit()
+    });
+}
+
+/// The idle function of the executor: waits for an interrupt, in deep sleep in low-power mode.
+pub fn idle() {
+    // SAFETY: only the deep sleep bit of the SCB is touched, which nothing else uses
+    let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+    if pico_iox16_firmware::runtime::low_power() {
+        scb.set_sleepdeep();
+    } else {
+        scb.clear_sleepdeep();
+    }
+    cortex_m::asm::wfe();
+}
+
+/// Size of the receive ring buffer, needs to be a power of two.
+const RX_LEN: usize = 256;
+
+#[derive(Clone, Copy, defmt::Format)]
+#[repr(u8)]
+enum RxError {
+    Overrun = 1,
+    Break,
+    Parity,
+    Framing,
+    /// The ring buffer was full, because nobody read it for too long
+    BufferFull,
+}
+
+/// Lock-free ring buffer, filled by the UART0 interrupt and drained by [`Uart`].
+struct RxRing {
+    buf: [AtomicU8; RX_LEN],
+    /// Number of bytes written so far, only changed by the interrupt
+    head: AtomicUsize,
+    /// Number of bytes read so far, only changed by the reader
+    tail: AtomicUsize,
+    /// The first [`RxError`] since the reader last looked, or 0
+    error: AtomicU8,
+}
+#[cfg_attr(feature = "usb", allow(dead_code))]
+impl RxRing {
+    const fn new() -> Self {
+        Self {
+            buf: [const { AtomicU8::new(0) }; RX_LEN],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            error: AtomicU8::new(0),
+        }
+    }
+
+    fn push(&self, byte: u8) {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) >= RX_LEN {
+            self.set_error(RxError::BufferFull);
+            return;
+        }
+        self.buf[head % RX_LEN].store(byte, Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+
+    fn pop(&self, buf: &mut [u8]) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        let n = head.wrapping_sub(tail).min(buf.len());
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = self.buf[tail.wrapping_add(i) % RX_LEN].load(Ordering::Relaxed);
+        }
+        self.tail.store(tail.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    fn set_error(&self, error: RxError) {
+        let _ = self
+            .error
+            .compare_exchange(0, error as u8, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    fn take_error(&self) -> Option<RxError> {
+        match self.error.swap(0, Ordering::Relaxed) {
+            0 => None,
+            1 => Some(RxError::Overrun),
+            2 => Some(RxError::Break),
+            3 => Some(RxError::Parity),
+            4 => Some(RxError::Framing),
+            _ => Some(RxError::BufferFull),
+        }
+    }
+}
+
+static UART0_RX: RxRing = RxRing::new();
+
+#[interrupt]
+fn UART0_IRQ() {
+    // SAFETY: the interrupt only touches the receive side, which `Uart` leaves alone
+    let uart = unsafe { &*pac::UART0::ptr() };
+    while uart.uartfr().read().rxfe().bit_is_clear() {
+        let dr = uart.uartdr().read();
+        if dr.oe().bit_is_set() {
+            UART0_RX.set_error(RxError::Overrun);
+        } else if dr.be().bit_is_set() {
+            UART0_RX.set_error(RxError::Break);
+        } else if dr.pe().bit_is_set() {
+            UART0_RX.set_error(RxError::Parity);
+        } else if dr.fe().bit_is_set() {
+            UART0_RX.set_error(RxError::Framing);
+        } else {
+            UART0_RX.push(dr.data().bits());
+        }
+    }
+    if uart.uartrsr().read().oe().bit_is_set() {
+        // overrun on an empty FIFO, the error sticks until cleared
+        uart.uartrsr().write(|w| unsafe { w.bits(0) });
+        UART0_RX.set_error(RxError::Overrun);
     }
 }
 
-pub struct Uart<D: UartDevice, P: ValidUartPinout<D>>(
-    pub UartPeripheral<Enabled, D, P>,
-    Option<rp235x_hal::uart::ReadErrorType>,
-);
-impl<D: UartDevice, P: ValidUartPinout<D>> Uart<D, P> {
-    pub fn new(peripheral: UartPeripheral<Enabled, D, P>) -> Self {
-        Self(peripheral, None)
+/// GPIO mask of the RS-485 driver enable pin, which the `TIMER0_IRQ_1` handler releases.
+static DE_MASK: AtomicU32 = AtomicU32::new(0);
+/// Time in µs it takes to send one character.
+static CHAR_US: AtomicU32 = AtomicU32::new(0);
+
+#[interrupt]
+fn TIMER0_IRQ_1() {
+    // SAFETY: alarm 1 is owned by `Uart`, which only schedules it
+    let timer = unsafe { &*pac::TIMER0::ptr() };
+    timer.intf().modify(|_, w| w.alarm_1().clear_bit());
+    timer.intr().write(|w| w.alarm_1().clear_bit_by_one());
+    // SAFETY: only reads the flags
+    let uart = unsafe { &*pac::UART0::ptr() };
+    if uart.uartfr().read().busy().bit_is_set() {
+        // the UART is slower than estimated, look again after another character
+        let now = timer.timerawl().read().bits();
+        timer
+            .alarm1()
+            .write(|w| unsafe { w.bits(now.wrapping_add(CHAR_US.load(Ordering::Relaxed))) });
+    } else {
+        // SAFETY: the set and clear registers only touch the bits written, and `Uart` only sets
+        // this pin with interrupts disabled
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_out_clr()
+            .write(|w| unsafe { w.bits(DE_MASK.load(Ordering::Relaxed)) });
     }
 }
-impl<D: UartDevice, P: ValidUartPinout<D>> Read<Board> for Uart<D, P> {
+
+/// The UART settings stored in the configuration, with 8 data bits.
+#[cfg_attr(feature = "usb", allow(dead_code))]
+pub fn uart_config(config: &pico_iox16_firmware::nvm::Config) -> UartConfig {
+    let parity = match config.parity() {
+        pico_iox16_protocol::Parity::None => None,
+        pico_iox16_protocol::Parity::Even => Some(Parity::Even),
+        pico_iox16_protocol::Parity::Odd => Some(Parity::Odd),
+    };
+    let stop_bits = match config.stop_bits() {
+        pico_iox16_protocol::StopBits::One => StopBits::One,
+        pico_iox16_protocol::StopBits::Two => StopBits::Two,
+    };
+    UartConfig::new(
+        config.effective_baudrate().Hz(),
+        DataBits::Eight,
+        parity,
+        stop_bits,
+    )
+}
+
+/// Bits per character of `config`, including start, parity and stop bits.
+#[cfg_attr(feature = "usb", allow(dead_code))]
+fn char_bits(config: &UartConfig) -> u32 {
+    let data_bits = match config.data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let stop_bits = match config.stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    1 + data_bits + u32::from(config.parity.is_some()) + stop_bits
+}
+
+/// UART0 with received bytes collected by its interrupt, so that none are lost while the main
+/// loop is busy. Flash writes still mask the interrupt, but the hardware FIFO covers those.
+///
+/// The RS-485 driver is enabled when writing, and released by an alarm once the UART is done
+/// and the hold time is over, independent of when the main loop gets around to flushing.
+#[cfg_attr(feature = "usb", allow(dead_code))]
+pub struct Uart<P: ValidUartPinout<UART0>, DE: PinId, DP: PullType> {
+    pub peripheral: UartPeripheral<Enabled, UART0, P>,
+    de: Pin<DE, FunctionSio<SioOutput>, DP>,
+    timer: Timer<CopyableTimer0>,
+    alarm: Alarm1<CopyableTimer0>,
+    /// Clock of the UART, to derive the divisor of a new baudrate from
+    frequency: HertzU32,
+    char_time: MicrosDurationU64,
+    hold: MicrosDurationU32,
+    /// When the UART is expected to be done with everything written so far
+    tx_end: Instant<u64, 1, 1_000_000>,
+}
+impl<P: ValidUartPinout<UART0>, DE: PinId, DP: PullType> Uart<P, DE, DP> {
+    #[cfg_attr(feature = "usb", allow(dead_code))]
+    pub fn new(
+        peripheral: UartPeripheral<Disabled, UART0, P>,
+        config: UartConfig,
+        frequency: HertzU32,
+        mut de: Pin<DE, FunctionSio<SioOutput>, DP>,
+        mut timer: Timer<CopyableTimer0>,
+        hold: MicrosDurationU32,
+    ) -> Result<Self, rp235x_hal::uart::Error> {
+        let bits = char_bits(&config);
+        let char_us = (bits * 1_000_000).div_ceil(config.baudrate.to_Hz());
+        let mut peripheral = peripheral.enable(config, frequency)?;
+        peripheral.enable_rx_interrupt();
+        // SAFETY: the handler only uses the lock-free ring buffer
+        unsafe { NVIC::unmask(pac::Interrupt::UART0_IRQ) };
+
+        let _ = de.set_low();
+        DE_MASK.store(1 << de.id().num, Ordering::Relaxed);
+        CHAR_US.store(char_us, Ordering::Relaxed);
+        let mut alarm = timer.alarm_1().unwrap();
+        alarm.enable_interrupt();
+        // SAFETY: the handler only touches the alarm, the flags of UART0 and the DE pin
+        unsafe { NVIC::unmask(pac::Interrupt::TIMER0_IRQ_1) };
+        Ok(Self {
+            peripheral,
+            de,
+            timer,
+            alarm,
+            frequency,
+            char_time: MicrosDurationU64::micros(char_us.into()),
+            hold,
+            tx_end: timer.get_counter(),
+        })
+    }
+}
+impl<P: ValidUartPinout<UART0>, DE: PinId, DP: PullType> Read<Board> for Uart<P, DE, DP> {
     type Error = Infallible;
 
     fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
-        if let Some(e) = self.1.take() {
+        if let Some(e) = UART0_RX.take_error() {
             info!("UART read error: {:?}", e);
             return Err(nb::Error::Other(ReadError::RecoverableError));
         }
-        match self.0.read_raw(buf) {
-            Ok(n) => Ok(n),
-            Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
-            Err(nb::Error::Other(e)) => {
-                if e.discarded.is_empty() {
-                    info!("UART read error: {:?}", e.err_type);
-                    Err(nb::Error::Other(ReadError::RecoverableError))
-                } else {
-                    self.1 = Some(e.err_type);
-                    Ok(e.discarded.len())
-                }
-            }
+        match UART0_RX.pop(buf) {
+            0 if !buf.is_empty() => Err(nb::Error::WouldBlock),
+            n => Ok(n),
         }
     }
+
+    fn wakes_on_receive(&self) -> bool {
+        // by the interrupt that fills the ring buffer
+        true
+    }
+
+    fn set_line(&mut self, config: &pico_iox16_firmware::nvm::Config) -> bool {
+        let config = uart_config(config);
+        let baudrate = config.baudrate.to_Hz();
+        // the divisor in 1/64, rounded like the HAL does when enabling the UART
+        let divisor = 8 * u64::from(self.frequency.to_Hz()) / u64::from(baudrate.max(1)) + 1;
+        let (int, frac) = match divisor >> 7 {
+            0 => (1, 0),
+            int @ 1..65535 => (int as u16, ((divisor & 0x7F) >> 1) as u8),
+            _ => (65535, 0),
+        };
+        let bits = char_bits(&config);
+        let char_us = (bits * 1_000_000).div_ceil(baudrate.max(1));
+        // SAFETY: the peripheral is owned by `self`, the interrupt handlers only read its flags
+        // and its data
+        let uart = unsafe { &*pac::UART0::ptr() };
+        uart.uartcr().modify(|_, w| w.uarten().clear_bit());
+        uart.uartibrd().write(|w| unsafe { w.baud_divint().bits(int) });
+        uart.uartfbrd().write(|w| unsafe { w.baud_divfrac().bits(frac) });
+        // the divisor only takes effect with a write to the line control register
+        uart.uartlcr_h().modify(|_, w| {
+            w.pen().bit(config.parity.is_some());
+            w.eps().bit(matches!(config.parity, Some(Parity::Even)));
+            w.stp2().bit(matches!(config.stop_bits, StopBits::Two))
+        });
+        uart.uartcr().modify(|_, w| w.uarten().set_bit());
+        CHAR_US.store(char_us, Ordering::Relaxed);
+        self.char_time = MicrosDurationU64::micros(char_us.into());
+        info!("UART at {} Hz, {} bits per character", baudrate, bits);
+        true
+    }
 }
-impl<D: UartDevice, P: ValidUartPinout<D>> Write<Board> for Uart<D, P> {
+impl<P: ValidUartPinout<UART0>, DE: PinId, DP: PullType> Write<Board> for Uart<P, DE, DP> {
     type Error = Infallible;
 
     fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
         let len = buf.len();
-        self.0
-            .write_raw(buf)
-            .map(|remaining| len - remaining.len())
-            .map_err(|nb::Error::WouldBlock| nb::Error::WouldBlock)
+        // the alarm must not release the driver between enabling it and being rescheduled
+        cortex_m::interrupt::free(|_| {
+            let _ = self.de.set_high();
+            let written = self
+                .peripheral
+                .write_raw(buf)
+                .map(|remaining| len - remaining.len())
+                .map_err(|nb::Error::WouldBlock| nb::Error::WouldBlock)?;
+            let start = self.tx_end.max(self.timer.get_counter());
+            self.tx_end = start + self.char_time * written as u32;
+            let _ = self.alarm.schedule_at(self.tx_end + self.hold);
+            Ok(written)
+        })
     }
 
     fn flush(&mut self) -> nb::Result<(), Self::Error> {
-        if self.0.uart_is_busy() {
+        // done once the driver is released
+        if self.de.is_set_high().unwrap_or(false) {
             Err(nb::Error::WouldBlock)
         } else {
             Ok(())
@@ -122,14 +475,100 @@ impl<S: AnySlice, C: ChannelId> pico_iox16_firmware::output::PwmChannel<Board> f
     }
 }
 
-pub struct System;
+/// The hardware watchdog, started by `main` before handing it over.
+pub struct Watchdog(pub rp235x_hal::Watchdog);
+impl pico_iox16_firmware::runtime::Watchdog<Board> for Watchdog {
+    fn feed(&self) {
+        self.0.feed();
+    }
+}
+
+/// Unique ID of the chip as reported by the boot ROM. The RP2350 has it in OTP, so it doesn't
+/// depend on the flash chip like on the RP2040.
+pub fn unique_id() -> u64 {
+    match rp235x_hal::rom_data::sys_info_api::chip_info() {
+        Ok(Some(info)) => (u64::from(info.device_id) << 32) | u64::from(info.wafer_id),
+        _ => {
+            warn!("Failed to read the chip ID");
+            0
+        }
+    }
+}
+
+/// Why the chip was last reset, needs to be called before the watchdog is set up.
+pub fn reset_cause(watchdog: &pac::WATCHDOG, powman: &pac::POWMAN) -> ResetCause {
+    let chip_reset = powman.chip_reset().read();
+    if watchdog.reason().read().timer().bit_is_set() {
+        ResetCause::Watchdog
+    } else if chip_reset.had_bor().bit_is_set() {
+        ResetCause::BrownOut
+    } else if chip_reset.had_por().bit_is_set() {
+        ResetCause::PowerOn
+    } else {
+        ResetCause::Other
+    }
+}
+
+/// Enables the brown-out detector with a threshold of 0.946 V, a bit above the default 0.86 V,
+/// so that a sagging supply resets the chip before it misbehaves. The core runs at 1.1 V.
+pub fn enable_brownout_detection(powman: &pac::POWMAN) {
+    // SAFETY: the upper half is the password POWMAN requires for writes, the threshold is one
+    // of the documented values
+    powman
+        .bod()
+        .write(|w| unsafe { w.bits(0x5AFE_0000).vsel().bits(0b01011).en().set_bit() });
+}
+
+pub struct System {
+    pub unique_id: u64,
+    pub hardware_revision: u16,
+    pub reset_cause: ResetCause,
+}
+impl pico_iox16_firmware::runtime::DeviceId<Board> for System {
+    fn unique_id(&self) -> u64 {
+        self.unique_id
+    }
+    fn hardware_revision(&self) -> u16 {
+        self.hardware_revision
+    }
+}
 impl pico_iox16_firmware::runtime::System<Board> for System {
-    fn reboot(&self) -> ! {
+    fn reboot(&self, mode: RebootMode) -> ! {
+        let reboot_type = match mode {
+            RebootMode::Firmware => 0x0000, // REBOOT_TYPE_NORMAL
+            RebootMode::Bootloader => 0x0002, // REBOOT_TYPE_BOOTSEL
+        };
         rp235x_hal::rom_data::reboot(
-            0x0100, // NO_RETURN_ON_SUCCESS
+            0x0100 | reboot_type, // NO_RETURN_ON_SUCCESS
             1, // delay in ms (0 doesn't seem to work)
-            0, // the boot diagnostic "partition" (low 8 bits only)
-            0);
+            0, // normal: the boot diagnostic "partition" (low 8 bits only), BOOTSEL: both USB interfaces
+            0); // BOOTSEL: no activity LED
+        if mode == RebootMode::Firmware {
+            warn!("Reboot through the ROM failed, resetting with the watchdog");
+            // SAFETY: the chip resets right away, nothing relies on the watchdog anymore
+            let watchdog = unsafe { &*pac::WATCHDOG::ptr() };
+            watchdog.ctrl().modify(|_, w| w.trigger().set_bit());
+            loop {
+                cortex_m::asm::nop();
+            }
+        }
         panic!("Reboot failed");
     }
-}
\ No newline at end of file
+    fn reset_cause(&self) -> ResetCause {
+        self.reset_cause
+    }
+}
+/// Stands in for the RS-485 transmit enable pin on transports that don't have one, or that
+/// drive it themselves.
+pub struct NoPin;
+impl embedded_hal::digital::ErrorType for NoPin {
+    type Error = Infallible;
+}
+impl embedded_hal::digital::OutputPin for NoPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use core::convert::Infallible;
+
+use defmt::info;
+use pico_iox16_firmware::runtime::{Read, ReadError, Write};
+use rp235x_hal::usb::UsbBus;
+use usb_device::{
+    UsbError,
+    bus::UsbBusAllocator,
+    device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+};
+use usbd_serial::SerialPort;
+
+use crate::runtime::Board;
+
+/// CDC-ACM serial port on the USB device controller, for using a board point-to-point
+/// without an RS-485 adapter. The device is polled whenever the main loop reads or writes.
+pub struct UsbSerial {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+}
+impl UsbSerial {
+    pub fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let serial = SerialPort::new(bus);
+        // shared VID/PID for CDC-ACM devices, see https://pid.codes
+        let device = UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27dd))
+            .strings(&[StringDescriptors::default()
+                .manufacturer("stbohne")
+                .product("Pico I∴O×16")])
+            .unwrap()
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .build();
+        Self { device, serial }
+    }
+
+    fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.serial]);
+    }
+}
+impl Read<Board> for UsbSerial {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        self.poll();
+        match self.serial.read(buf) {
+            Ok(n) => Ok(n),
+            Err(UsbError::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(e) => {
+                info!("USB read error: {:?}", e);
+                Err(nb::Error::Other(ReadError::RecoverableError))
+            }
+        }
+    }
+}
+impl Write<Board> for UsbSerial {
+    type Error = Infallible;
+
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        self.poll();
+        if self.device.state() != UsbDeviceState::Configured {
+            // nobody is listening, so don't let the main loop wait for the host
+            return Ok(buf.len());
+        }
+        match self.serial.write(buf) {
+            Ok(n) => Ok(n),
+            Err(UsbError::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(e) => {
+                info!("USB write error: {:?}", e);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.poll();
+        if self.device.state() != UsbDeviceState::Configured {
+            return Ok(());
+        }
+        match self.serial.flush() {
+            Ok(()) => Ok(()),
+            Err(UsbError::WouldBlock) => Err(nb::Error::WouldBlock),
+            Err(e) => {
+                info!("USB flush error: {:?}", e);
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! The CYW43 of the Pico 2 W, with a TCP listener as second port next to the RS-485 UART.
+//!
+//! The chip is brought up and joins the network given by `WIFI_SSID` and `WIFI_PASSWORD` at
+//! build time in the background, while the main loop already answers on RS-485. Its firmware
+//! is not part of this repository; copy `43439A0.bin` and `43439A0_clm.bin` from the
+//! `cyw43-firmware` directory of embassy to `cyw43-firmware/` next to `Cargo.toml`.
+//!
+//! One host at a time can connect to [`PORT`] and send the same frames as over the bus, e.g.
+//! `pico_iox16_tool` on a pty from `socat pty,link=/tmp/pico,raw tcp:<address>:4016`. The
+//! socket listens again once the host disconnects or stops answering keep-alives.
+
+use core::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    future::Future,
+    mem::replace,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use cortex_m::{interrupt::Mutex, peripheral::NVIC};
+use cyw43::{JoinOptions, PowerManagementMode};
+use defmt::{info, warn};
+use embassy_net::{
+    Stack, StackResources,
+    tcp::{State, TcpSocket},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{ErrorType, OutputPin};
+use futures::future::{join3, select};
+use pico_iox16_firmware::runtime::{Read, ReadError, Write, yield_now};
+use pio::{
+    InSource, Instruction, InstructionOperands, JmpCondition, OutDestination, SetDestination,
+    SideSet, WaitSource,
+};
+use rp235x_hal::{
+    gpio::{
+        FunctionPio0, FunctionSioOutput, OutputDriveStrength, OutputSlewRate, Pin, PullDown,
+        PullNone,
+        bank0::{Gpio23, Gpio24, Gpio25, Gpio29},
+    },
+    pac::{self, PIO0, interrupt},
+    pio::{
+        PIO, PIOBuilder, PinDir, Running, Rx, SM0, ShiftDirection, StateMachine, Tx,
+        UninitStateMachine,
+    },
+};
+
+use crate::runtime::Board;
+
+/// TCP port the board listens on.
+pub const PORT: u16 = 4016;
+
+const SSID: &str = env!("WIFI_SSID");
+/// Empty for an open network.
+const PASSWORD: &str = env!("WIFI_PASSWORD");
+
+/// Size of the receive and the transmit buffer of the socket, a few frames each.
+const BUFFER_SIZE: usize = 4096;
+
+/// The PIO runs at half the system clock and takes two cycles per bit, 37.5 MHz at 150 MHz.
+const CLOCK_DIVISOR: u16 = 2;
+
+/// IRQ flag the program raises when the chip signals an event on the data line.
+const EVENT_IRQ: u8 = 0;
+
+/// The half-duplex SPI of the CYW43 in a PIO state machine, with the data line doubling as
+/// interrupt line while the chip is not selected.
+pub struct Spi {
+    pio: PIO<PIO0>,
+    /// Only `None` while a transfer is being set up
+    sm: Option<StateMachine<(PIO0, SM0), Running>>,
+    tx: Tx<(PIO0, SM0)>,
+    rx: Rx<(PIO0, SM0)>,
+    cs: Pin<Gpio25, FunctionSioOutput, PullDown>,
+    /// Address of the first instruction of the program
+    start: u8,
+}
+impl Spi {
+    /// Installs the program and starts the state machine, with the chip deselected.
+    pub fn new(
+        mut pio: PIO<PIO0>,
+        sm: UninitStateMachine<(PIO0, SM0)>,
+        mut dio: Pin<Gpio24, FunctionPio0, PullNone>,
+        mut clk: Pin<Gpio29, FunctionPio0, PullNone>,
+        cs: Pin<Gpio25, FunctionSioOutput, PullDown>,
+    ) -> Self {
+        dio.set_drive_strength(OutputDriveStrength::TwelveMilliAmps);
+        dio.set_slew_rate(OutputSlewRate::Fast);
+        clk.set_drive_strength(OutputDriveStrength::TwelveMilliAmps);
+        clk.set_slew_rate(OutputSlewRate::Fast);
+        let (dio, clk) = (dio.id().num, clk.id().num);
+        // SAFETY: only the bit of the data line is touched, which no other state machine uses;
+        // the synchronizer delays the input by two cycles, too long at this clock
+        unsafe { &*pac::PIO0::ptr() }
+            .input_sync_bypass()
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << dio) });
+
+        // write:
+        // out pins, 1    side 0   ; x + 1 bits to the chip
+        // jmp x-- write  side 1
+        // set pindirs, 0 side 0   ; turn the data line around
+        // nop            side 1
+        // nop            side 0
+        // read:
+        // in pins, 1     side 1   ; y + 1 bits from the chip, the status included
+        // jmp y-- read   side 0
+        // wait 1 pin 0   side 0   ; the chip pulls the data line high for an event
+        // irq 0          side 0
+        let mut a = pio::Assembler::<{ pio::RP2040_MAX_PROGRAM_SIZE }>::new_with_side_set(
+            SideSet::new(false, 1, false),
+        );
+        let mut write = a.label();
+        let mut read = a.label();
+        a.bind(&mut write);
+        a.out_with_side_set(OutDestination::PINS, 1, 0);
+        a.jmp_with_side_set(JmpCondition::XDecNonZero, &mut write, 1);
+        a.set_with_side_set(SetDestination::PINDIRS, 0, 0);
+        a.nop_with_side_set(1);
+        a.nop_with_side_set(0);
+        a.bind(&mut read);
+        a.in_with_side_set(InSource::PINS, 1, 1);
+        a.jmp_with_side_set(JmpCondition::YDecNonZero, &mut read, 0);
+        a.wait_with_side_set(1, WaitSource::PIN, 0, false, 0);
+        a.irq_with_side_set(false, false, EVENT_IRQ, false, 0);
+        let program = pio.install(&a.assemble_program()).unwrap();
+        let start = program.offset();
+        let (mut sm, rx, tx) = PIOBuilder::from_installed_program(program)
+            .out_pins(dio, 1)
+            .set_pins(dio, 1)
+            .in_pin_base(dio)
+            .side_set_pin_base(clk)
+            .out_shift_direction(ShiftDirection::Left)
+            .in_shift_direction(ShiftDirection::Left)
+            .autopull(true)
+            .pull_threshold(32)
+            .autopush(true)
+            .push_threshold(32)
+            .clock_divisor_fixed_point(CLOCK_DIVISOR, 0)
+            .build(sm);
+        sm.set_pindirs([(clk, PinDir::Output), (dio, PinDir::Output)]);
+        Self {
+            pio,
+            // waits in the first `out` for data
+            sm: Some(sm.start()),
+            tx,
+            rx,
+            cs,
+            start,
+        }
+    }
+
+    /// Selects the chip, writes the words of `write`, reads the words of `read` and returns the
+    /// status word that follows.
+    async fn transfer(&mut self, write: &[u32], read: &mut [u32]) -> u32 {
+        let mut sm = self.sm.take().unwrap().stop();
+        sm.restart();
+        sm.clear_fifos();
+        // the status of the chip comes with every transfer
+        self.pio.clear_irq(1 << EVENT_IRQ);
+        for (register, bits) in [
+            (OutDestination::X, write.len() * 32 - 1),
+            (OutDestination::Y, read.len() * 32 + 31),
+        ] {
+            self.tx.write(bits as u32);
+            sm.exec_instruction(instruction(InstructionOperands::PULL {
+                if_empty: false,
+                block: true,
+            }));
+            sm.exec_instruction(instruction(InstructionOperands::OUT {
+                destination: register,
+                bit_count: 32,
+            }));
+        }
+        sm.exec_instruction(instruction(InstructionOperands::SET {
+            destination: SetDestination::PINDIRS,
+            data: 1,
+        }));
+        sm.exec_instruction(instruction(InstructionOperands::JMP {
+            condition: JmpCondition::Always,
+            address: self.start,
+        }));
+        let _ = self.cs.set_low();
+        self.sm = Some(sm.start());
+        for &word in write {
+            while !self.tx.write(word) {
+                yield_now().await;
+            }
+        }
+        for word in read.iter_mut() {
+            *word = self.read_word().await;
+        }
+        let status = self.read_word().await;
+        let _ = self.cs.set_high();
+        status
+    }
+
+    async fn read_word(&mut self) -> u32 {
+        loop {
+            if let Some(word) = self.rx.read() {
+                return word;
+            }
+            yield_now().await;
+        }
+    }
+}
+impl cyw43::SpiBusCyw43 for Spi {
+    async fn cmd_write(&mut self, write: &[u32]) -> u32 {
+        self.transfer(write, &mut []).await
+    }
+
+    async fn cmd_read(&mut self, write: u32, read: &mut [u32]) -> u32 {
+        self.transfer(&[write], read).await
+    }
+
+    async fn wait_for_event(&mut self) {
+        // polled like the other ports, the executor keeps running while the chip is up anyway
+        while self.pio.get_irq_raw() & (1 << EVENT_IRQ) == 0 {
+            yield_now().await;
+        }
+        self.pio.clear_irq(1 << EVENT_IRQ);
+    }
+}
+
+/// An instruction to execute directly, with the clock low.
+fn instruction(operands: InstructionOperands) -> Instruction {
+    Instruction {
+        operands,
+        delay: 0,
+        side_set: Some(0),
+    }
+}
+
+/// The time base of the network stack, alarm 2 of TIMER0. The executor polls all tasks on
+/// every interrupt, so an alarm for the earliest deadline is all it takes to wake them.
+struct Clock {
+    /// When the alarm is due, if it was scheduled
+    due: Mutex<Cell<Option<u64>>>,
+}
+impl embassy_time_driver::Driver for Clock {
+    fn now(&self) -> u64 {
+        // SAFETY: only reads the counter
+        let timer = unsafe { &*pac::TIMER0::ptr() };
+        loop {
+            let high = timer.timerawh().read().bits();
+            let low = timer.timerawl().read().bits();
+            if timer.timerawh().read().bits() == high {
+                return (u64::from(high) << 32) | u64::from(low);
+            }
+        }
+    }
+
+    fn schedule_wake(&self, at: u64, waker: &Waker) {
+        cortex_m::interrupt::free(|cs| {
+            let due = self.due.borrow(cs);
+            let now = self.now();
+            if due.get().is_some_and(|due| due > now && due <= at) {
+                return;
+            }
+            // the alarm only compares the lower 32 bits of the counter
+            let at = at.min(now + (1 << 31));
+            // SAFETY: alarm 2 belongs to the clock, and the handler only clears its interrupt
+            let timer = unsafe { &*pac::TIMER0::ptr() };
+            timer.alarm2().write(|w| unsafe { w.bits(at as u32) });
+            due.set(Some(at));
+            // too late for the alarm to fire
+            if self.now() >= at {
+                waker.wake_by_ref();
+            }
+        });
+    }
+}
+
+embassy_time_driver::time_driver_impl!(static CLOCK: Clock = Clock {
+    due: Mutex::new(Cell::new(None)),
+});
+
+#[interrupt]
+fn TIMER0_IRQ_2() {
+    // SAFETY: alarm 2 is owned by `Clock`, which only schedules it
+    let timer = unsafe { &*pac::TIMER0::ptr() };
+    timer.intf().modify(|_, w| w.alarm_2().clear_bit());
+    timer.intr().write(|w| w.alarm_2().clear_bit_by_one());
+}
+
+/// The state the status LED should have, passed from [`Led`] to the task setting it.
+static LED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// The LED of the Pico 2 W, which is on a GPIO of the CYW43. It stays dark until the chip has
+/// joined the network.
+pub struct Led;
+impl ErrorType for Led {
+    type Error = Infallible;
+}
+impl OutputPin for Led {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        LED.signal(false);
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        LED.signal(true);
+        Ok(())
+    }
+}
+
+/// The socket of [`WifiPort`], created by [`run`] once the chip is up.
+pub type Socket = RefCell<Option<TcpSocket<'static>>>;
+
+/// The connection to the host on [`PORT`]. Reads and writes do nothing until a host connects,
+/// and frames cut off by a disconnect are dropped.
+pub struct WifiPort {
+    socket: &'static Socket,
+    connected: bool,
+}
+impl WifiPort {
+    pub fn new(socket: &'static Socket) -> Self {
+        Self {
+            socket,
+            connected: false,
+        }
+    }
+
+    fn disconnected(&mut self) -> nb::Result<usize, ReadError<Infallible>> {
+        if replace(&mut self.connected, false) {
+            info!("WiFi host disconnected");
+            return Err(nb::Error::Other(ReadError::RecoverableError));
+        }
+        Err(nb::Error::WouldBlock)
+    }
+}
+impl Read<Board> for WifiPort {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        let socket = self.socket;
+        let mut socket = socket.borrow_mut();
+        let Some(socket) = socket.as_mut() else {
+            return Err(nb::Error::WouldBlock);
+        };
+        match socket.state() {
+            State::Established | State::FinWait1 | State::FinWait2 | State::CloseWait => {}
+            State::Closed => {
+                // listens right away, the future only waits for the connection
+                let _ = poll_once(socket.accept(PORT));
+                return self.disconnected();
+            }
+            _ => return self.disconnected(),
+        }
+        if !replace(&mut self.connected, true) {
+            info!("WiFi host {} connected", socket.remote_endpoint());
+        }
+        match poll_once(socket.read(buf)) {
+            Poll::Ready(Ok(0)) if !buf.is_empty() => {
+                // the host closed its end
+                socket.close();
+                Err(nb::Error::WouldBlock)
+            }
+            Poll::Ready(Ok(n)) => Ok(n),
+            Poll::Ready(Err(_)) => {
+                socket.abort();
+                Err(nb::Error::WouldBlock)
+            }
+            Poll::Pending => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+impl Write<Board> for WifiPort {
+    type Error = Infallible;
+
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        let mut socket = self.socket.borrow_mut();
+        match socket.as_mut() {
+            Some(socket) if socket.state() == State::Established => {
+                match poll_once(socket.write(buf)) {
+                    Poll::Ready(Ok(n)) => Ok(n),
+                    // the host is gone, like when nobody is connected
+                    Poll::Ready(Err(_)) => Ok(buf.len()),
+                    Poll::Pending => Err(nb::Error::WouldBlock),
+                }
+            }
+            // nobody is listening, so don't let the main loop wait for the host
+            _ => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        let mut socket = self.socket.borrow_mut();
+        match socket.as_mut() {
+            Some(socket) if socket.state() == State::Established => {
+                match poll_once(socket.flush()) {
+                    Poll::Ready(_) => Ok(()),
+                    Poll::Pending => Err(nb::Error::WouldBlock),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Polls the future of a socket call once. They do their work right away and only wait for
+/// the socket afterwards, which the main loop does by polling again.
+fn poll_once<F: Future>(future: F) -> Poll<F::Output> {
+    pin!(future).poll(&mut Context::from_waker(Waker::noop()))
+}
+
+/// Brings up the chip, joins the network and creates the socket of [`WifiPort`], then keeps
+/// the chip, the network stack and the LED going. Needs to be polled alongside the main loop.
+pub async fn run(
+    spi: Spi,
+    power: Pin<Gpio23, FunctionSioOutput, PullDown>,
+    socket: &'static Socket,
+    seed: u64,
+) -> ! {
+    // SAFETY: only the enable bit of alarm 2 is touched, which belongs to `Clock`
+    let timer = unsafe { &*pac::TIMER0::ptr() };
+    timer.inte().modify(|_, w| w.alarm_2().set_bit());
+    // SAFETY: the handler only clears the interrupt of alarm 2
+    unsafe { NVIC::unmask(pac::Interrupt::TIMER0_IRQ_2) };
+
+    let firmware = include_bytes!("../cyw43-firmware/43439A0.bin");
+    let state = cortex_m::singleton!(: cyw43::State = cyw43::State::new()).unwrap();
+    let (device, mut control, chip) = cyw43::new(state, power, spi, firmware).await;
+    info!("CYW43 up");
+
+    let resources = cortex_m::singleton!(: StackResources<3> = StackResources::new()).unwrap();
+    let (stack, mut network) = embassy_net::new(
+        device,
+        embassy_net::Config::dhcpv4(Default::default()),
+        resources,
+        seed,
+    );
+    let rx = cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap();
+    let tx = cortex_m::singleton!(: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE]).unwrap();
+    let mut tcp = TcpSocket::new(stack, rx, tx);
+    // notice a host that went away without closing the connection
+    tcp.set_keep_alive(Some(Duration::from_secs(10)));
+    tcp.set_timeout(Some(Duration::from_secs(30)));
+    *socket.borrow_mut() = Some(tcp);
+
+    let (never, ..) = join3(chip.run(), network.run(), connect(&mut control, stack)).await;
+    never
+}
+
+fn join_options() -> JoinOptions<'static> {
+    if PASSWORD.is_empty() {
+        JoinOptions::new_open()
+    } else {
+        JoinOptions::new(PASSWORD.as_bytes())
+    }
+}
+
+/// Joins the network, again whenever the link goes down, and shows the status on the LED while
+/// it is up.
+async fn connect(control: &mut cyw43::Control<'static>, stack: Stack<'static>) -> ! {
+    control
+        .init(include_bytes!("../cyw43-firmware/43439A0_clm.bin"))
+        .await;
+    // power saving delays received frames by up to a beacon interval, too long for the tool
+    control
+        .set_power_management(PowerManagementMode::Performance)
+        .await;
+    loop {
+        while let Err(err) = control.join(SSID, join_options()).await {
+            warn!("Joining WiFi {} failed with status {}", SSID, err.status);
+            Timer::after_secs(5).await;
+        }
+        select(
+            pin!(stack.wait_link_down()),
+            pin!(async {
+                stack.wait_config_up().await;
+                if let Some(config) = stack.config_v4() {
+                    info!(
+                        "WiFi {} joined as {}, listening on port {}",
+                        SSID, config.address, PORT
+                    );
+                }
+                loop {
+                    control.gpio_set(0, LED.wait().await).await;
+                }
+            }),
+        )
+        .await;
+        warn!("WiFi {} lost, joining again", SSID);
+    }
+}
@@ -0,0 +1,103 @@
+//! Throughput of the parser on the kind of traffic the sniffer sees: responses interleaved with
+//! requests for other devices, preambles and line noise.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use pico_iox16_protocol::{
+    Command, InputGetRes, Message, OutputSetReq, master_next, next_frame, next_message,
+};
+use zerocopy::IntoBytes;
+
+/// Size of each buffer, about a second of a busy bus at 10 Mbaud.
+const LEN: usize = 1 << 20;
+
+/// Deterministic noise, so that runs compare.
+struct XorShift(u32);
+impl XorShift {
+    fn next(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as u8
+    }
+}
+
+/// Frames separated by the preamble and, every `noise_every` frames, a burst of random bytes.
+fn traffic(noise_every: usize) -> Vec<u8> {
+    let response = Message::new_response(
+        0x12,
+        Command::InputGet,
+        0,
+        InputGetRes {
+            values: [1234.into(); 16],
+        },
+    );
+    let request = Message::new_request(0x34, Command::OutputSet, 0, OutputSetReq::default());
+    let mut rng = XorShift(0x2545_f491);
+    let mut bytes = Vec::with_capacity(LEN + 256);
+    let mut frames = 0;
+    while bytes.len() < LEN {
+        bytes.extend_from_slice(&[0xFF, 0xFF]);
+        if frames % 2 == 0 {
+            bytes.extend_from_slice(response.as_bytes());
+        } else {
+            bytes.extend_from_slice(request.as_bytes());
+        }
+        frames += 1;
+        if noise_every > 0 && frames % noise_every == 0 {
+            bytes.extend((0..64).map(|_| rng.next()));
+        }
+    }
+    bytes
+}
+
+/// Parses all of `bytes` with `next`, which tells whether it found something and how many bytes
+/// it processed. Returns how many things were found.
+fn drain(mut bytes: &[u8], next: impl Fn(&[u8]) -> (bool, usize)) -> usize {
+    let mut found = 0;
+    loop {
+        let (is_some, processed) = next(bytes);
+        found += usize::from(is_some);
+        if processed == 0 {
+            return found;
+        }
+        bytes = &bytes[processed..];
+    }
+}
+
+fn parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+    for (name, noise_every) in [("clean", 0), ("noisy", 4), ("garbage", 1)] {
+        let bytes = traffic(noise_every);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(format!("next_frame/{name}"), |b| {
+            b.iter(|| {
+                drain(black_box(&bytes), |bytes| {
+                    let (maybe, processed) = next_frame(bytes);
+                    (maybe.is_some(), processed)
+                })
+            })
+        });
+        group.bench_function(format!("next_message/{name}"), |b| {
+            b.iter(|| {
+                drain(black_box(&bytes), |bytes| {
+                    let (maybe, processed) = next_message(bytes);
+                    (maybe.is_some(), processed)
+                })
+            })
+        });
+        group.bench_function(format!("master_next/{name}"), |b| {
+            b.iter(|| {
+                drain(black_box(&bytes), |bytes| {
+                    let (maybe, processed) = master_next(bytes);
+                    (maybe.is_some(), processed)
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parser);
+criterion_main!(benches);
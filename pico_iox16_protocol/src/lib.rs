@@ -1,25 +1,60 @@
 #![no_std]
 
-use core::fmt::Debug;
-use crc::{CRC_16_KERMIT, Crc};
-use defmt::Format;
+use core::{
+    fmt::Debug,
+    ops::{Range, RangeInclusive},
+};
+use crc::{CRC_16_KERMIT, CRC_32_ISO_HDLC, Crc, Table};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use zerocopy::{
-    I16, I32, Immutable, IntoBytes, KnownLayout, LE, TryFromBytes, U16, U32, U64, Unaligned,
+    ConvertError, I16, I32, Immutable, IntoBytes, KnownLayout, LE, TryFromBytes, U16, U32, U64,
+    Unaligned,
 };
 
+pub mod settings;
+
 pub const MAGIC: [u8; 2] = *b"OM";
 
+/// Address that all devices on the bus accept requests of [`Command::broadcast`] commands at,
+/// e.g. to switch the outputs of several devices at once. Devices never respond to them, as
+/// their responses would collide on the bus. Not usable as the address of a device.
+pub const BROADCAST_ADDRESS: u16 = 0;
+
+/// Version of the commands and the layout of their payloads, reported by devices in
+/// [`InfoGetRes::protocol_version`]. Increased with every change that masters and devices have
+/// to agree on. Devices whose `InfoGet` response predates the field speak version 0, but their
+/// response doesn't parse as an [`InfoGetRes`] anymore.
+///
+/// Version 2 added [`Header::sequence`]. Frames of the versions before don't parse anymore.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Minor version of the protocol, reported by devices in [`ProtocolVersionGetRes::minor`] with
+/// the [`PROTOCOL_VERSION`] as major version. Increased with every change that masters can cope
+/// with by leaving out what the device doesn't know yet, like a new command, and reset to 0
+/// when the major version increases.
+///
+/// Minor version 1 added [`Command::ProtocolVersionGet`]. Devices that reject it with
+/// [`ErrorCode::UnknownCommand`] speak minor version 0. Minor version 2 added
+/// [`Command::OutputSetMasked`], minor version 3 [`Command::InputGetEvents`], minor version 4
+/// [`Command::ReportConfigSet`] and the reports flagged with [`REPORT_FLAG`], minor version 5
+/// the firmware update from [`Command::FlashEraseRegion`] to [`Command::BootSwap`], minor
+/// version 6 [`Command::DeviceIdGet`], minor version 7 [`Command::FailsafeSet`] and
+/// [`Command::FailsafeGet`], minor version 8 [`Command::OutputSetDefaults`], minor version 9
+/// [`Command::BaudratesGet`] and minor version 10 [`Command::OutputGetDefaults`].
+pub const PROTOCOL_VERSION_MINOR: u16 = 10;
+
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, Format, derive_more::Display,
+    Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive, derive_more::Display,
 )]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u16)]
 pub enum Command {
     /// Check if the device is alive and responding.
     Check = 0,
     /// Get information about the device.
     InfoGet = 1,
-    /// Set the current configuration of the device. Persists across reboots.
+    /// Set the current configuration of the device. Persists across reboots. Devices reject
+    /// configurations they couldn't be reached with, see [`Config::validate`].
     ConfigSet = 2,
     /// Get the current configuration of the device.
     ConfigGet = 3,
@@ -53,8 +88,160 @@ pub enum Command {
     InputGetThresholdTimes = 12,
     /// Get the current states of the input thresholds.
     InputGetThresholdStates = 13,
-    /// Reboot the device.
+    /// Reboot the device, into the firmware or the bootloader.
     Reboot = 14,
+    /// Get diagnostics like the cause of the last reset, to tell what a device in the field went
+    /// through.
+    DiagnosticsGet = 15,
+    /// Get the debounced levels of the spare GPIOs that the board reads as digital inputs.
+    DigitalGet = 16,
+    /// Set the power settings of the device. Persists across reboots.
+    ///
+    /// With a sample interval set and all outputs at 0%, the device sleeps between sweeps over
+    /// the inputs and only wakes up for them, its timers and received bytes.
+    PowerSet = 17,
+    /// Get the power settings of the device.
+    PowerGet = 18,
+    /// Get the debounce bookkeeping of each input, to tune thresholds and debounce settings.
+    InputGetDebounce = 19,
+    /// Set the address ranges that the device passes on to its downstream port as a repeater.
+    /// Persists across reboots.
+    ///
+    /// Devices without a downstream port store them but forward nothing.
+    ForwardingSet = 20,
+    /// Get the address ranges that the device passes on to its downstream port.
+    ForwardingGet = 21,
+    /// Read part of the user data, [`USER_DATA_SIZE`] bytes for the host to keep metadata in,
+    /// e.g. the installation date or the serial numbers of the attached sensors.
+    UserDataRead = 22,
+    /// Write part of the user data. Persists across reboots, in flash apart from the
+    /// configuration.
+    UserDataWrite = 23,
+    /// Get the version of the protocol that the device speaks and the optional features it
+    /// supports, for the master to tell what it may send.
+    ProtocolVersionGet = 24,
+    /// Set the duty cycles of some outputs, leaving the others and the frequencies as they are.
+    /// Resets after reboot.
+    OutputSetMasked = 25,
+    /// Take the oldest threshold crossings from the queue of the device, for crossings that
+    /// follow each other faster than the master polls `InputGetThresholdTimes`.
+    InputGetEvents = 26,
+    /// Make the device send the input values on its own at an interval, without being polled,
+    /// for monitoring over a point-to-point link. Resets after reboot.
+    ///
+    /// The reports are [`InputGetRes`] flagged with [`REPORT_FLAG`], and take the values like
+    /// an `InputGet` request does.
+    ReportConfigSet = 27,
+    /// Erase sectors of the staging area that a new firmware image is written to before it
+    /// replaces the running one.
+    ///
+    /// An update erases the staging area, writes the image with `FlashWriteChunk`, checks it
+    /// with `FlashVerify` and has the device boot it with `BootSwap`. Nothing changes for the
+    /// running firmware until `BootSwap`, so an interrupted update can just start over.
+    FlashEraseRegion = 28,
+    /// Program a chunk of the staging area, which has to be erased.
+    FlashWriteChunk = 29,
+    /// Get the size of the staging area and the checksum of the image staged at its start.
+    FlashVerify = 30,
+    /// Copy the staged image over the running firmware and boot it, if its checksum matches.
+    /// The device responds before copying, and comes back with the new firmware at its
+    /// configured address after a few seconds.
+    BootSwap = 31,
+    /// Get the unique ID of the chip and the hardware revision of the board, to tell which
+    /// physical device answers at an address, also after its address changed.
+    DeviceIdGet = 32,
+    /// Set the state the outputs are forced into once the device received no valid request for
+    /// a while, e.g. because the host crashed or the bus broke. Persists across reboots.
+    ///
+    /// The outputs stay in the safe state until the host sets them again. Any valid request
+    /// to the device restarts the countdown, including broadcasts.
+    FailsafeSet = 33,
+    /// Get the failsafe timeout and the safe state of the outputs.
+    FailsafeGet = 34,
+    /// Store the duty cycles and frequencies the outputs are set to when the device starts,
+    /// instead of those the board starts them with. Leaves the current outputs as they are.
+    OutputSetDefaults = 35,
+    /// Get the range of baudrates the device can use, which [`Command::ConfigSet`] accepts.
+    BaudratesGet = 36,
+    /// Get the outputs stored with [`Command::OutputSetDefaults`].
+    OutputGetDefaults = 37,
+}
+
+impl Command {
+    /// All commands, in the order of their values.
+    pub const ALL: [Self; 38] = [
+        Self::Check,
+        Self::InfoGet,
+        Self::ConfigSet,
+        Self::ConfigGet,
+        Self::OutputSet,
+        Self::OutputGet,
+        Self::InputGet,
+        Self::InputGetFull,
+        Self::InputSetCalibrations,
+        Self::InputGetCalibrations,
+        Self::InputSetThresholds,
+        Self::InputGetThresholds,
+        Self::InputGetThresholdTimes,
+        Self::InputGetThresholdStates,
+        Self::Reboot,
+        Self::DiagnosticsGet,
+        Self::DigitalGet,
+        Self::PowerSet,
+        Self::PowerGet,
+        Self::InputGetDebounce,
+        Self::ForwardingSet,
+        Self::ForwardingGet,
+        Self::UserDataRead,
+        Self::UserDataWrite,
+        Self::ProtocolVersionGet,
+        Self::OutputSetMasked,
+        Self::InputGetEvents,
+        Self::ReportConfigSet,
+        Self::FlashEraseRegion,
+        Self::FlashWriteChunk,
+        Self::FlashVerify,
+        Self::BootSwap,
+        Self::DeviceIdGet,
+        Self::FailsafeSet,
+        Self::FailsafeGet,
+        Self::OutputSetDefaults,
+        Self::BaudratesGet,
+        Self::OutputGetDefaults,
+    ];
+
+    /// Whether requests of the command may be sent to [`BROADCAST_ADDRESS`]. Only commands that
+    /// change the state of a device without any response the master would need qualify.
+    pub const fn broadcast(self) -> bool {
+        matches!(
+            self,
+            Self::OutputSet
+                | Self::InputSetThresholds
+                | Self::Reboot
+                | Self::PowerSet
+                | Self::OutputSetMasked
+        )
+    }
+
+    /// The [`PROTOCOL_VERSION_MINOR`] that added the command. Devices that speak an older minor
+    /// version reject it with [`ErrorCode::UnknownCommand`].
+    pub const fn minor_version(self) -> u16 {
+        match self {
+            Self::ProtocolVersionGet => 1,
+            Self::OutputSetMasked => 2,
+            Self::InputGetEvents => 3,
+            Self::ReportConfigSet => 4,
+            Self::FlashEraseRegion | Self::FlashWriteChunk | Self::FlashVerify | Self::BootSwap => {
+                5
+            }
+            Self::DeviceIdGet => 6,
+            Self::FailsafeSet | Self::FailsafeGet => 7,
+            Self::OutputSetDefaults => 8,
+            Self::BaudratesGet => 9,
+            Self::OutputGetDefaults => 10,
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -74,6 +261,29 @@ pub enum Request<'a> {
     InputGetThresholdTimes(&'a InputGetThresholdTimesReq),
     InputGetThresholdStates(&'a InputGetThresholdStatesReq),
     Reboot(&'a RebootReq),
+    DiagnosticsGet(&'a DiagnosticsGetReq),
+    DigitalGet(&'a DigitalGetReq),
+    PowerSet(&'a PowerSetReq),
+    PowerGet(&'a PowerGetReq),
+    InputGetDebounce(&'a InputGetDebounceReq),
+    ForwardingSet(&'a ForwardingSetReq),
+    ForwardingGet(&'a ForwardingGetReq),
+    UserDataRead(&'a UserDataReadReq),
+    UserDataWrite(&'a UserDataWriteReq),
+    ProtocolVersionGet(&'a ProtocolVersionGetReq),
+    OutputSetMasked(&'a OutputSetMaskedReq),
+    InputGetEvents(&'a InputGetEventsReq),
+    ReportConfigSet(&'a ReportConfigSetReq),
+    FlashEraseRegion(&'a FlashEraseRegionReq),
+    FlashWriteChunk(&'a FlashWriteChunkReq),
+    FlashVerify(&'a FlashVerifyReq),
+    BootSwap(&'a BootSwapReq),
+    DeviceIdGet(&'a DeviceIdGetReq),
+    FailsafeSet(&'a FailsafeSetReq),
+    FailsafeGet(&'a FailsafeGetReq),
+    OutputSetDefaults(&'a OutputSetDefaultsReq),
+    BaudratesGet(&'a BaudratesGetReq),
+    OutputGetDefaults(&'a OutputGetDefaultsReq),
 }
 impl Request<'_> {
     pub fn command(&self) -> Command {
@@ -93,6 +303,29 @@ impl Request<'_> {
             Request::InputGetThresholdTimes(_) => Command::InputGetThresholdTimes,
             Request::InputGetThresholdStates(_) => Command::InputGetThresholdStates,
             Request::Reboot(_) => Command::Reboot,
+            Request::DiagnosticsGet(_) => Command::DiagnosticsGet,
+            Request::DigitalGet(_) => Command::DigitalGet,
+            Request::PowerSet(_) => Command::PowerSet,
+            Request::PowerGet(_) => Command::PowerGet,
+            Request::InputGetDebounce(_) => Command::InputGetDebounce,
+            Request::ForwardingSet(_) => Command::ForwardingSet,
+            Request::ForwardingGet(_) => Command::ForwardingGet,
+            Request::UserDataRead(_) => Command::UserDataRead,
+            Request::UserDataWrite(_) => Command::UserDataWrite,
+            Request::ProtocolVersionGet(_) => Command::ProtocolVersionGet,
+            Request::OutputSetMasked(_) => Command::OutputSetMasked,
+            Request::InputGetEvents(_) => Command::InputGetEvents,
+            Request::ReportConfigSet(_) => Command::ReportConfigSet,
+            Request::FlashEraseRegion(_) => Command::FlashEraseRegion,
+            Request::FlashWriteChunk(_) => Command::FlashWriteChunk,
+            Request::FlashVerify(_) => Command::FlashVerify,
+            Request::BootSwap(_) => Command::BootSwap,
+            Request::DeviceIdGet(_) => Command::DeviceIdGet,
+            Request::FailsafeSet(_) => Command::FailsafeSet,
+            Request::FailsafeGet(_) => Command::FailsafeGet,
+            Request::OutputSetDefaults(_) => Command::OutputSetDefaults,
+            Request::BaudratesGet(_) => Command::BaudratesGet,
+            Request::OutputGetDefaults(_) => Command::OutputGetDefaults,
         }
     }
 }
@@ -114,6 +347,34 @@ pub enum Response<'a> {
     InputGetThresholdTimes(&'a InputGetThresholdTimesRes),
     InputGetThresholdStates(&'a InputGetThresholdStatesRes),
     Reboot(&'a RebootRes),
+    DiagnosticsGet(&'a DiagnosticsGetRes),
+    DigitalGet(&'a DigitalGetRes),
+    PowerSet(&'a PowerSetRes),
+    PowerGet(&'a PowerGetRes),
+    InputGetDebounce(&'a InputGetDebounceRes),
+    ForwardingSet(&'a ForwardingSetRes),
+    ForwardingGet(&'a ForwardingGetRes),
+    UserDataRead(&'a UserDataReadRes),
+    UserDataWrite(&'a UserDataWriteRes),
+    ProtocolVersionGet(&'a ProtocolVersionGetRes),
+    OutputSetMasked(&'a OutputSetMaskedRes),
+    InputGetEvents(&'a InputGetEventsRes),
+    ReportConfigSet(&'a ReportConfigSetRes),
+    FlashEraseRegion(&'a FlashEraseRegionRes),
+    FlashWriteChunk(&'a FlashWriteChunkRes),
+    FlashVerify(&'a FlashVerifyRes),
+    BootSwap(&'a BootSwapRes),
+    DeviceIdGet(&'a DeviceIdGetRes),
+    FailsafeSet(&'a FailsafeSetRes),
+    FailsafeGet(&'a FailsafeGetRes),
+    OutputSetDefaults(&'a OutputSetDefaultsRes),
+    BaudratesGet(&'a BaudratesGetRes),
+    OutputGetDefaults(&'a OutputGetDefaultsRes),
+    /// Input values the device sent on its own, see [`REPORT_FLAG`]. Not the response to any
+    /// request.
+    InputReport(&'a InputGetRes),
+    /// The device couldn't handle a request of the command, see [`ERROR_FLAG`].
+    Error(Command, &'a ErrorRes),
 }
 impl Response<'_> {
     pub fn command(&self) -> Command {
@@ -133,6 +394,31 @@ impl Response<'_> {
             Response::InputGetThresholdTimes(_) => Command::InputGetThresholdTimes,
             Response::InputGetThresholdStates(_) => Command::InputGetThresholdStates,
             Response::Reboot(_) => Command::Reboot,
+            Response::DiagnosticsGet(_) => Command::DiagnosticsGet,
+            Response::DigitalGet(_) => Command::DigitalGet,
+            Response::PowerSet(_) => Command::PowerSet,
+            Response::PowerGet(_) => Command::PowerGet,
+            Response::InputGetDebounce(_) => Command::InputGetDebounce,
+            Response::ForwardingSet(_) => Command::ForwardingSet,
+            Response::ForwardingGet(_) => Command::ForwardingGet,
+            Response::UserDataRead(_) => Command::UserDataRead,
+            Response::UserDataWrite(_) => Command::UserDataWrite,
+            Response::ProtocolVersionGet(_) => Command::ProtocolVersionGet,
+            Response::OutputSetMasked(_) => Command::OutputSetMasked,
+            Response::InputGetEvents(_) => Command::InputGetEvents,
+            Response::ReportConfigSet(_) => Command::ReportConfigSet,
+            Response::FlashEraseRegion(_) => Command::FlashEraseRegion,
+            Response::FlashWriteChunk(_) => Command::FlashWriteChunk,
+            Response::FlashVerify(_) => Command::FlashVerify,
+            Response::BootSwap(_) => Command::BootSwap,
+            Response::DeviceIdGet(_) => Command::DeviceIdGet,
+            Response::FailsafeSet(_) => Command::FailsafeSet,
+            Response::FailsafeGet(_) => Command::FailsafeGet,
+            Response::OutputSetDefaults(_) => Command::OutputSetDefaults,
+            Response::BaudratesGet(_) => Command::BaudratesGet,
+            Response::OutputGetDefaults(_) => Command::OutputGetDefaults,
+            Response::InputReport(_) => Command::InputGet,
+            Response::Error(command, _) => *command,
         }
     }
 }
@@ -206,6 +492,11 @@ pub struct InfoGetRes {
     pub firmware_version_patch: U16<LE>,
     /// Uptime in seconds
     pub uptime: U32<LE>,
+    /// The [`PROTOCOL_VERSION`] of the device's firmware
+    pub protocol_version: U16<LE>,
+    /// The [`settings::SCHEMA_VERSION`] of the device's firmware. Devices that predate the
+    /// field send 0.
+    pub config_schema_version: U16<LE>,
 }
 impl RequestTrait for InfoGetReq {
     const COMMAND: Command = Command::InfoGet;
@@ -262,6 +553,54 @@ impl RequestTrait for OutputSetReq {
     }
 }
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputSetMaskedReq {
+    /// Bit `n` selects output `n`, i.e. `duty_cycle[n % 2]` of group `n / 2` of an
+    /// [`OutputSetReq`]
+    pub mask: U16<LE>,
+    #[doc(hidden)]
+    pub _reserved: [u8; 2],
+    /// Duty cycles of the outputs like in [`OutputGroup`]. Those of outputs not selected are
+    /// ignored.
+    pub duty_cycles: [U16<LE>; 16],
+}
+impl OutputSetMaskedReq {
+    /// Sets the outputs in `duty_cycles`, by index, and no others. Indices of 16 and above
+    /// name no output and are skipped.
+    pub fn new(duty_cycles: impl IntoIterator<Item = (usize, u16)>) -> Self {
+        let mut request = Self {
+            mask: 0.into(),
+            _reserved: [0; 2],
+            duty_cycles: [0.into(); 16],
+        };
+        let duty_cycles = duty_cycles.into_iter().filter(|&(output, _)| output < 16);
+        for (output, duty_cycle) in duty_cycles {
+            request.mask |= 1 << output;
+            request.duty_cycles[output] = duty_cycle.into();
+        }
+        request
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputSetMaskedRes;
+impl RequestTrait for OutputSetMaskedReq {
+    const COMMAND: Command = Command::OutputSetMasked;
+    const TIMEOUT_US: u32 = 100;
+    type Response = OutputSetMaskedRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::OutputSetMasked(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
 )]
@@ -559,12 +898,104 @@ impl RequestTrait for InputGetThresholdStatesReq {
 )]
 #[repr(C)]
 pub struct Config {
-    /// Device address. Address `0xFFFF` is reserved for unconfigured devices. Effective only after reboot.
+    /// Device address. Address `0xFFFF` is reserved for unconfigured devices and
+    /// [`BROADCAST_ADDRESS`] for broadcasts. Effective only after reboot.
     pub address: U16<LE>,
-    /// The baudrate to use for communication with the device. Effective only after reboot.
+    /// The baudrate to use for communication with the device, within
+    /// [`settings::BAUDRATES`]. Effective only after reboot.
     pub baudrate: U32<LE>,
-    #[doc(hidden)]
-    pub _reserved: [u8; 2],
+    /// The parity bit of each character. Effective only after reboot.
+    pub parity: Parity,
+    /// The number of stop bits of each character. Effective only after reboot.
+    pub stop_bits: StopBits,
+}
+
+impl Config {
+    /// Checks the values a device can't be reached with, which it rejects with
+    /// [`ErrorCode::InvalidPayload`] in [`Command::ConfigSet`].
+    pub fn validate(&self) -> Result<(), InvalidConfig> {
+        let (address, baudrate) = (self.address.get(), self.baudrate.get());
+        if address == BROADCAST_ADDRESS || address == settings::UNCONFIGURED_ADDRESS {
+            Err(InvalidConfig::ReservedAddress(address))
+        } else if !settings::BAUDRATES.contains(&baudrate) {
+            Err(InvalidConfig::UnsupportedBaudrate(baudrate))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Why [`Config::validate`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InvalidConfig {
+    #[display("address {_0:#06x} is reserved")]
+    ReservedAddress(u16),
+    #[display(
+        "baudrate {_0} is outside of {}..={}",
+        settings::BAUDRATES.start(),
+        settings::BAUDRATES.end()
+    )]
+    UnsupportedBaudrate(u32),
+}
+
+/// Parity bit of the characters on the bus.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    Default,
+    derive_more::Display,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[repr(u8)]
+pub enum Parity {
+    #[default]
+    None = 0,
+    Even = 1,
+    Odd = 2,
+}
+
+/// Number of stop bits of the characters on the bus.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    Default,
+    derive_more::Display,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[repr(u8)]
+pub enum StopBits {
+    #[default]
+    #[display("1")]
+    One = 0,
+    #[display("2")]
+    Two = 1,
 }
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
@@ -614,7 +1045,23 @@ impl RequestTrait for ConfigSetReq {
     Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
 )]
 #[repr(C)]
-pub struct RebootReq;
+pub struct RebootReq {
+    pub mode: RebootMode,
+    #[doc(hidden)]
+    pub _reserved: [u8; 3],
+}
+impl RebootReq {
+    /// What masters that predate [`RebootMode`] mean by a `Reboot` request without payload.
+    pub const FIRMWARE: Self = Self::new(RebootMode::Firmware);
+
+    /// Request to start the given mode after the reboot.
+    pub const fn new(mode: RebootMode) -> Self {
+        Self {
+            mode,
+            _reserved: [0; 3],
+        }
+    }
+}
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
 )]
@@ -632,295 +1079,2244 @@ impl RequestTrait for RebootReq {
     }
 }
 
+/// What the device starts after a `Reboot` request.
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, TryFromBytes, IntoBytes, Unaligned, Immutable, KnownLayout,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    derive_more::Display,
 )]
-#[repr(C)]
-pub struct Header {
-    /// Magic bytes marking the start of a message. Must be `"OM"`.
-    pub magic: [u8; 2],
-    /// The length of the payload in 32-bit words. Must be equal to `!length_inverted`.
-    pub length: u8,
-    /// The bitwise inverse of `length`. Must be equal to `!length`.
-    pub length_inverted: u8,
-    /// The address of the device. For requests, this is the target address. For responses, this is the source address.
-    pub address: U16<LE>,
-    /// The command of the message. Valid values are defined in the [`Command`] enum.
-    pub command: U16<LE>,
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum RebootMode {
+    /// The firmware, which picks up a new configuration.
+    Firmware = 0,
+    /// The bootloader in the boot ROM, which takes a new firmware as UF2 file over USB.
+    Bootloader = 1,
 }
 
+/// Why the device was last reset.
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, TryFromBytes, IntoBytes, Unaligned, Immutable, KnownLayout,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    derive_more::Display,
 )]
-#[repr(C)]
-pub struct Footer {
-    /// The checksum of the message. Must be equal to the CRC-16/Kermit of the header and payload.
-    pub checksum: U16<LE>,
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ResetCause {
+    /// Anything else, e.g. the reset pin, a debugger or a `Reboot` request.
+    Other = 0,
+    /// The supply was switched on.
+    PowerOn = 1,
+    /// The firmware got stuck and the watchdog ran out.
+    Watchdog = 2,
+    /// The supply voltage dropped below the brown-out threshold.
+    BrownOut = 3,
 }
 
-pub const CHECKSUM: Crc<u16> = Crc::<u16>::new(&CRC_16_KERMIT);
-
-#[derive(Debug, Clone, Copy, TryFromBytes, IntoBytes, Immutable)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
 #[repr(C)]
-pub struct Message<T> {
-    pub header: Header,
-    pub payload: T,
-    pub footer: Footer,
+pub struct DiagnosticsGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct DiagnosticsGetRes {
+    /// Why the device was last reset. If it was a brown-out, that was the last one, `uptime` ago.
+    pub reset_cause: ResetCause,
+    /// Conditions to tell the operator about, e.g. [`DiagnosticsGetRes::CONFIG_CORRUPTED`].
+    /// Devices that predate the field send 0.
+    pub flags: u8,
+    #[doc(hidden)]
+    pub _reserved: [u8; 2],
+    /// Number of brown-outs since the configuration was first written. Persists across reboots.
+    pub brownouts: U32<LE>,
+    /// Number of failed conversions of the inputs of the left and right half of the board since
+    /// boot. Their samples are dropped.
+    pub conversion_errors: [U32<LE>; 2],
+    /// Number of times since boot that samples were lost because the converter got ahead of the
+    /// firmware, which loses them of both halves at once.
+    pub overruns: U32<LE>,
+    /// The intervals between the sweeps of the input loop over each input since the previous
+    /// `DiagnosticsGet`, which stretch while the firmware is busy with requests or flash
+    /// writes. They are the time base of the averages of `InputGet` and of the debounce times.
+    pub sample_intervals: [SampleInterval; 16],
 }
+impl DiagnosticsGetRes {
+    /// The stored settings were unreadable, e.g. corrupted or written by a firmware with another
+    /// layout. The device runs with the defaults until it is provisioned again.
+    pub const CONFIG_CORRUPTED: u8 = 1 << 0;
 
-impl<T: IntoBytes + Unaligned + Immutable> Message<T> {
-    fn new_raw(address: u16, command: u16, payload: T) -> Self {
-        assert!(size_of::<T>() <= u8::MAX as usize * 4);
-        assert!(size_of::<T>().is_multiple_of(4));
-        let header = Header {
-            magic: MAGIC,
-            length: u8::try_from(size_of::<T>() / 4).unwrap(),
-            length_inverted: !u8::try_from(size_of::<T>() / 4).unwrap(),
-            address: address.into(),
-            command: command.into(),
-        };
-        let footer = Footer { checksum: 0.into() };
-        let mut message = Message {
-            header,
-            payload,
-            footer,
-        };
-        message.footer.checksum = CHECKSUM
-            .checksum(&message.as_bytes()[..size_of::<Header>() + size_of::<T>()])
-            .into();
-        message
-    }
-    /// Creates a new request message with the given address, command, and payload.
-    pub fn new_request(address: u16, command: Command, payload: T) -> Self {
-        Self::new_raw(address, u16::from(command), payload)
-    }
-    /// Creates a new response message with the given address, command, and payload.
-    pub fn new_response(address: u16, command: Command, payload: T) -> Self {
-        Self::new_raw(address, u16::from(command), payload)
+    pub fn config_corrupted(&self) -> bool {
+        self.flags & Self::CONFIG_CORRUPTED != 0
     }
 }
 
-/// Searches for the next valid message in the given byte slice and returns it along with the number of bytes processed.
-/// If a valid message is found, the returned byte slice will contain the header and payload of the message, but not the footer.
-/// If no valid message is found, the returned byte slice will be `None`.
-/// The number of bytes processed is the number of bytes that were consumed from the input byte slice,
-/// including any invalid data that was skipped over. Therefore it may consume bytes even if no valid message is found.
-pub fn next_message(mut bytes: &[u8]) -> (Option<(&Header, &[u8])>, usize) {
-    let mut processed = 0;
-    while bytes.len() >= MAGIC.len() + 2 {
-        if bytes[0..MAGIC.len()] == MAGIC && bytes[MAGIC.len()] == !bytes[MAGIC.len() + 1] {
-            // valid header marker found
-            let Ok((header, _)) = Header::try_ref_from_prefix(bytes) else {
-                // too short
-                break;
-            };
-            let length = header.length as usize * 4 + size_of::<Header>() + size_of::<Footer>();
-            if bytes.len() < length {
-                // too short
-                break;
-            }
-            processed += length;
-            let payload = &bytes[size_of::<Header>()..length - size_of::<Footer>()];
-            let footer =
-                Footer::try_ref_from_bytes(&bytes[length - size_of::<Footer>()..length]).unwrap();
-            let checksum = CHECKSUM.checksum(&bytes[..length - size_of::<Footer>()]);
-            if footer.checksum.get() == checksum {
-                return (Some((header, payload)), processed);
-            } else {
-                // println!("checksum invalid: {:04x} != {:04x}", footer.checksum.get(), checksum);
-                // Invalid checksum, continue searching
-                bytes = &bytes[length..];
-                continue;
-            }
+/// The distribution of the intervals at which an input was sampled, see
+/// [`DiagnosticsGetRes::sample_intervals`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct SampleInterval {
+    /// The shortest interval in µs, `u32::MAX` if there was none.
+    pub min_us: U32<LE>,
+    /// The longest interval in µs, 0 if there was none.
+    pub max_us: U32<LE>,
+    /// The mean interval in µs, 0 if there was none.
+    pub mean_us: U32<LE>,
+    /// The number of intervals.
+    pub count: U32<LE>,
+}
+impl SampleInterval {
+    /// No interval, e.g. before the input loop swept over the inputs twice.
+    pub const EMPTY: Self = Self {
+        min_us: U32::new(u32::MAX),
+        max_us: U32::ZERO,
+        mean_us: U32::ZERO,
+        count: U32::ZERO,
+    };
+}
+impl RequestTrait for DiagnosticsGetReq {
+    const COMMAND: Command = Command::DiagnosticsGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = DiagnosticsGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::DiagnosticsGet(res) => Some(res),
+            _ => None,
         }
-        bytes = &bytes[1..];
-        processed += 1;
     }
-    (None, processed)
 }
 
-/// Parses the next message from the given byte slice and returns its address and the payload as a [`Response`]
-/// along with the number of bytes processed. Skips invalid message headers and
-/// messages with invalid checksums.
-pub fn master_next<'a>(buffer: &'a [u8]) -> (Option<(u16, Response<'a>)>, usize) {
-    let (maybe_message, processed) = next_message(buffer);
-    let Some((header, payload)) = maybe_message else {
-        return (None, processed);
-    };
-    let address = header.address.get();
-    let command = header.command.get();
-    match Command::try_from(command) {
-        Err(_) => (None, processed),
-        Ok(Command::Check) => (Some((address, Response::Check(&CheckRes))), processed),
-        Ok(Command::InfoGet) => {
-            let Ok(message) = InfoGetRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some((address, Response::InfoGet(message))), processed)
-        }
-        Ok(Command::ConfigGet) => {
-            let Ok(message) = ConfigGetRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some((address, Response::ConfigGet(message))), processed)
-        }
-        Ok(Command::ConfigSet) => (
-            Some((address, Response::ConfigSet(&ConfigSetRes))),
-            processed,
-        ),
-        Ok(Command::OutputGet) => {
-            let Ok(message) = OutputGetRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some((address, Response::OutputGet(message))), processed)
-        }
-        Ok(Command::OutputSet) => (
-            Some((address, Response::OutputSet(&OutputSetRes))),
-            processed,
-        ),
-        Ok(Command::InputGet) => {
-            let Ok(message) = InputGetRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some((address, Response::InputGet(message))), processed)
-        }
-        Ok(Command::InputGetFull) => {
-            let Ok(message) = InputGetFullRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some((address, Response::InputGetFull(message))), processed)
-        }
-        Ok(Command::InputSetCalibrations) => (
-            Some((
-                address,
-                Response::InputSetCalibrations(&InputSetCalibrationsRes),
-            )),
-            processed,
-        ),
-        Ok(Command::InputGetCalibrations) => {
-            let Ok(message) = InputGetCalibrationsRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (
-                Some((address, Response::InputGetCalibrations(message))),
-                processed,
-            )
-        }
-        Ok(Command::InputSetThresholds) => (
-            Some((
-                address,
-                Response::InputSetThresholds(&InputSetThresholdsRes),
-            )),
-            processed,
-        ),
-        Ok(Command::InputGetThresholds) => {
-            let Ok(message) = InputGetThresholdsRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (
-                Some((address, Response::InputGetThresholds(message))),
-                processed,
-            )
-        }
-        Ok(Command::InputGetThresholdTimes) => {
-            let Ok(message) = InputGetThresholdTimesRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (
-                Some((address, Response::InputGetThresholdTimes(message))),
-                processed,
-            )
-        }
-        Ok(Command::InputGetThresholdStates) => {
-            let Ok(message) = InputGetThresholdStatesRes::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (
-                Some((address, Response::InputGetThresholdStates(message))),
-                processed,
-            )
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct DigitalGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct DigitalGetRes {
+    /// The GPIOs that are digital inputs on this board, bit `n` for GPIO `n`.
+    pub available: U32<LE>,
+    /// The debounced levels of the digital inputs, bit `n` for GPIO `n`, set if high. Bits of
+    /// GPIOs that aren't in `available` are zero.
+    pub levels: U32<LE>,
+}
+impl RequestTrait for DigitalGetReq {
+    const COMMAND: Command = Command::DigitalGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = DigitalGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::DigitalGet(res) => Some(res),
+            _ => None,
         }
-        Ok(Command::Reboot) => (Some((address, Response::Reboot(&RebootRes))), processed),
     }
 }
 
-/// Parses the next message with the given address from the given byte slice and returns the payload
-/// as a [`Request`] along with the number of bytes processed. Skips invalid message headers,
-/// messages with invalid checksums and messages with a different address.
-pub fn slave_next<'a>(buffer: &'a [u8], address: u16) -> (Option<Request<'a>>, usize) {
-    let (maybe_message, processed) = next_message(buffer);
-    let Some((header, payload)) = maybe_message else {
-        return (None, processed);
-    };
-    if address != header.address.into() {
-        return (None, processed);
-    }
-    match Command::try_from(u16::from(header.command)) {
-        Err(_) => (None, processed),
-        Ok(Command::Check) => (Some(Request::Check(&CheckReq)), processed),
-        Ok(Command::InfoGet) => (Some(Request::InfoGet(&InfoGetReq)), processed),
-        Ok(Command::ConfigGet) => (Some(Request::ConfigGet(&ConfigGetReq)), processed),
-        Ok(Command::ConfigSet) => {
-            let Ok(message) = ConfigSetReq::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some(Request::ConfigSet(message)), processed)
-        }
-        Ok(Command::OutputGet) => (Some(Request::OutputGet(&OutputGetReq)), processed),
-        Ok(Command::OutputSet) => {
-            let Ok(message) = OutputSetReq::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some(Request::OutputSet(message)), processed)
-        }
-        Ok(Command::InputGet) => (Some(Request::InputGet(&InputGetReq)), processed),
-        Ok(Command::InputGetFull) => (Some(Request::InputGetFull(&InputGetFullReq)), processed),
-        Ok(Command::InputSetCalibrations) => {
-            let Ok(message) = InputSetCalibrationsReq::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some(Request::InputSetCalibrations(message)), processed)
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct Power {
+    /// Time from the start of one sweep over all inputs to the start of the next in ms. Sampling
+    /// stops in between, which saves power but leaves the inputs unwatched. 0 samples all the
+    /// time, which is the default.
+    pub sample_interval_ms: U32<LE>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct PowerSetReq(pub Power);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct PowerSetRes;
+impl RequestTrait for PowerSetReq {
+    const COMMAND: Command = Command::PowerSet;
+    const TIMEOUT_US: u32 = 500000;
+    type Response = PowerSetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::PowerSet(res) => Some(res),
+            _ => None,
         }
-        Ok(Command::InputGetCalibrations) => (
-            Some(Request::InputGetCalibrations(&InputGetCalibrationsReq)),
-            processed,
-        ),
-        Ok(Command::InputSetThresholds) => {
-            let Ok(message) = InputSetThresholdsReq::try_ref_from_bytes(payload) else {
-                return (None, processed);
-            };
-            (Some(Request::InputSetThresholds(message)), processed)
-        }
-        Ok(Command::InputGetThresholds) => (
-            Some(Request::InputGetThresholds(&InputGetThresholdsReq)),
-            processed,
-        ),
-        Ok(Command::InputGetThresholdTimes) => (
-            Some(Request::InputGetThresholdTimes(&InputGetThresholdTimesReq)),
-            processed,
-        ),
-        Ok(Command::InputGetThresholdStates) => (
-            Some(Request::InputGetThresholdStates(
-                &InputGetThresholdStatesReq,
-            )),
-            processed,
-        ),
-        Ok(Command::Reboot) => (Some(Request::Reboot(&RebootReq)), processed),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_message_parsing() {
-        let payload = OutputSetReq::default();
-        let message = Message::new_request(0x1234, Command::OutputSet, payload);
-        let bytes = message.as_bytes();
-        let checksum = CHECKSUM.checksum(&bytes[..bytes.len() - size_of::<Footer>()]);
-        assert_eq!(
-            Footer::try_ref_from_bytes(&bytes[bytes.len() - size_of::<Footer>()..])
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct PowerGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct PowerGetRes(pub Power);
+impl RequestTrait for PowerGetReq {
+    const COMMAND: Command = Command::PowerGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = PowerGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::PowerGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputGetDebounceReq;
+/// The debounce bookkeeping of one input. Times are timer ticks in microseconds since boot.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputDebounce {
+    /// The time the input last went above `threshold_high`, whether or not that crossing passed
+    /// the debounce.
+    pub raw_high: U64<LE>,
+    /// The time the input last went below `threshold_low`, whether or not that crossing passed
+    /// the debounce.
+    pub raw_low: U64<LE>,
+    /// The time of the last high crossing that passed the debounce, as in
+    /// [`InputThresholdTimes::last_high`].
+    pub debounced_high: U64<LE>,
+    /// The time of the last low crossing that passed the debounce, as in
+    /// [`InputThresholdTimes::last_low`].
+    pub debounced_low: U64<LE>,
+    /// The number of consecutive readings above `threshold_high` up to now, 0 if the last one
+    /// wasn't. Saturates at `u16::MAX`.
+    pub above_count: U16<LE>,
+    /// The number of consecutive readings below `threshold_low` up to now, 0 if the last one
+    /// wasn't. Saturates at `u16::MAX`.
+    pub below_count: U16<LE>,
+    /// [`InputDebounce::PENDING_HIGH`] and [`InputDebounce::PENDING_LOW`].
+    pub pending: u8,
+    #[doc(hidden)]
+    pub _reserved: [u8; 3],
+}
+impl InputDebounce {
+    /// The input is above `threshold_high`, but not yet for long enough to pass the debounce.
+    pub const PENDING_HIGH: u8 = 1 << 0;
+    /// The input is below `threshold_low`, but not yet for long enough to pass the debounce.
+    pub const PENDING_LOW: u8 = 1 << 1;
+
+    pub fn pending_high(&self) -> bool {
+        self.pending & Self::PENDING_HIGH != 0
+    }
+    pub fn pending_low(&self) -> bool {
+        self.pending & Self::PENDING_LOW != 0
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputGetDebounceRes {
+    /// Timer ticks in microseconds since boot, to relate the times of the inputs to.
+    pub now: U64<LE>,
+    pub inputs: [InputDebounce; 16],
+}
+impl RequestTrait for InputGetDebounceReq {
+    const COMMAND: Command = Command::InputGetDebounce;
+    const TIMEOUT_US: u32 = 100;
+    type Response = InputGetDebounceRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::InputGetDebounce(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// The most [`InputEvent`]s in one [`InputGetEventsRes`].
+pub const INPUT_EVENTS_PER_RESPONSE: usize = 32;
+
+/// Which threshold an input crossed.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    derive_more::Display,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum InputEdge {
+    /// The input went below `threshold_low`.
+    Low = 0,
+    /// The input went above `threshold_high`.
+    High = 1,
+}
+
+/// A threshold crossing that passed the debounce.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputEvent {
+    /// The time of the crossing in microseconds since boot, like
+    /// [`InputThresholdTimes::last_high`] and [`InputThresholdTimes::last_low`]
+    pub time: U64<LE>,
+    /// The index of the input
+    pub input: u8,
+    pub edge: InputEdge,
+    #[doc(hidden)]
+    pub _reserved: [u8; 6],
+}
+impl InputEvent {
+    /// No event, for the unused entries of [`InputGetEventsRes::events`].
+    pub const EMPTY: Self = Self {
+        time: U64::ZERO,
+        input: 0,
+        edge: InputEdge::Low,
+        _reserved: [0; 6],
+    };
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputGetEventsReq {
+    /// The most events to take, up to [`INPUT_EVENTS_PER_RESPONSE`]
+    pub max: u8,
+    #[doc(hidden)]
+    pub _reserved: [u8; 3],
+}
+impl InputGetEventsReq {
+    pub fn new(max: u8) -> Self {
+        Self {
+            max,
+            _reserved: [0; 3],
+        }
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct InputGetEventsRes {
+    /// Timer ticks in microseconds since boot.
+    pub now: U64<LE>,
+    /// The number of events dropped since the last request because the queue was full, the
+    /// newest ones. Saturates at `u16::MAX`.
+    pub lost: U16<LE>,
+    /// The number of events still in the queue after these.
+    pub remaining: u8,
+    /// The number of events taken, at the start of `events`.
+    pub len: u8,
+    /// The events taken, oldest first, zeros after them.
+    pub events: [InputEvent; INPUT_EVENTS_PER_RESPONSE],
+}
+impl InputGetEventsRes {
+    /// The events taken.
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events[..usize::from(self.len).min(INPUT_EVENTS_PER_RESPONSE)]
+    }
+}
+impl RequestTrait for InputGetEventsReq {
+    const COMMAND: Command = Command::InputGetEvents;
+    const TIMEOUT_US: u32 = 100;
+    type Response = InputGetEventsRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::InputGetEvents(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ReportConfigSetReq {
+    /// Time between two reports in milliseconds, 0 to stop them
+    pub interval_ms: U32<LE>,
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ReportConfigSetRes;
+impl RequestTrait for ReportConfigSetReq {
+    const COMMAND: Command = Command::ReportConfigSet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = ReportConfigSetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::ReportConfigSet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// Size of a flash sector, the unit `FlashEraseRegion` erases.
+pub const FLASH_SECTOR_SIZE: u32 = 4096;
+/// Size of the chunks `FlashWriteChunk` programs, a flash page.
+pub const FLASH_CHUNK_SIZE: usize = 256;
+/// The checksum of staged firmware images, see [`FlashVerifyRes::checksum`].
+pub const IMAGE_CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashEraseRegionReq {
+    /// Offset into the staging area, a multiple of [`FLASH_SECTOR_SIZE`]
+    pub offset: U32<LE>,
+    /// Length of the region, a multiple of [`FLASH_SECTOR_SIZE`]. Erasing takes tens of
+    /// milliseconds per sector, so a request should cover at most 64 KiB to stay within the
+    /// timeout.
+    pub len: U32<LE>,
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashEraseRegionRes;
+impl RequestTrait for FlashEraseRegionReq {
+    const COMMAND: Command = Command::FlashEraseRegion;
+    const TIMEOUT_US: u32 = 2_000_000;
+    type Response = FlashEraseRegionRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::FlashEraseRegion(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashWriteChunkReq {
+    /// Offset into the staging area, a multiple of [`FLASH_CHUNK_SIZE`]
+    pub offset: U32<LE>,
+    /// The bytes to program. Programming `0xFF` leaves a byte as erased, so a short last chunk
+    /// is padded with it.
+    pub data: [u8; FLASH_CHUNK_SIZE],
+}
+impl FlashWriteChunkReq {
+    /// Creates a request for up to [`FLASH_CHUNK_SIZE`] bytes at `offset`, padded with `0xFF`.
+    /// Returns `None` if `data` is longer.
+    pub fn new(offset: u32, data: &[u8]) -> Option<Self> {
+        let mut chunk = [0xFF; FLASH_CHUNK_SIZE];
+        chunk.get_mut(..data.len())?.copy_from_slice(data);
+        Some(Self {
+            offset: offset.into(),
+            data: chunk,
+        })
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashWriteChunkRes;
+impl RequestTrait for FlashWriteChunkReq {
+    const COMMAND: Command = Command::FlashWriteChunk;
+    const TIMEOUT_US: u32 = 10_000;
+    type Response = FlashWriteChunkRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::FlashWriteChunk(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashVerifyReq {
+    /// Length of the image at the start of the staging area, 0 to only get the size of the
+    /// staging area
+    pub len: U32<LE>,
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FlashVerifyRes {
+    /// Size of the staging area in bytes, the largest image the device takes. 0 if the device
+    /// can't update itself.
+    pub size: U32<LE>,
+    /// The [`IMAGE_CHECKSUM`] of the image
+    pub checksum: U32<LE>,
+}
+impl RequestTrait for FlashVerifyReq {
+    const COMMAND: Command = Command::FlashVerify;
+    const TIMEOUT_US: u32 = 1_000_000;
+    type Response = FlashVerifyRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::FlashVerify(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct BootSwapReq {
+    /// Length of the image at the start of the staging area
+    pub len: U32<LE>,
+    /// The [`IMAGE_CHECKSUM`] the image must have, or the request is rejected with
+    /// [`ErrorCode::InvalidPayload`]
+    pub checksum: U32<LE>,
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct BootSwapRes;
+impl RequestTrait for BootSwapReq {
+    const COMMAND: Command = Command::BootSwap;
+    // checks the image first, like `FlashVerify`
+    const TIMEOUT_US: u32 = 1_000_000;
+    type Response = BootSwapRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::BootSwap(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct DeviceIdGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct DeviceIdGetRes {
+    /// Unique ID of the chip, the same as in the info string of [`InfoGetRes`], 0 if unknown
+    pub unique_id: U64<LE>,
+    /// Revision of the board the firmware runs on, 0 if unknown
+    pub hardware_revision: U16<LE>,
+    pub _reserved: [u8; 2],
+}
+impl DeviceIdGetRes {
+    pub fn new(unique_id: u64, hardware_revision: u16) -> Self {
+        Self {
+            unique_id: unique_id.into(),
+            hardware_revision: hardware_revision.into(),
+            _reserved: [0; 2],
+        }
+    }
+}
+impl RequestTrait for DeviceIdGetReq {
+    const COMMAND: Command = Command::DeviceIdGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = DeviceIdGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::DeviceIdGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// The state the outputs are forced into once the device received no valid request for
+/// `timeout_ms`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct Failsafe {
+    /// Time without a valid request in ms after which the outputs are forced into the safe
+    /// state, 0 (the default) to leave them as they are
+    pub timeout_ms: U32<LE>,
+    /// Safe duty cycles of the outputs like in [`OutputGroup`]. The frequencies stay.
+    pub duty_cycles: [U16<LE>; 16],
+}
+impl Failsafe {
+    pub const DISABLED: Self = Self {
+        timeout_ms: U32::ZERO,
+        duty_cycles: [U16::ZERO; 16],
+    };
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FailsafeSetReq(pub Failsafe);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FailsafeSetRes;
+impl RequestTrait for FailsafeSetReq {
+    const COMMAND: Command = Command::FailsafeSet;
+    const TIMEOUT_US: u32 = 500000;
+    type Response = FailsafeSetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::FailsafeSet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FailsafeGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FailsafeGetRes(pub Failsafe);
+impl RequestTrait for FailsafeGetReq {
+    const COMMAND: Command = Command::FailsafeGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = FailsafeGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::FailsafeGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct BaudratesGetReq;
+/// The lowest and highest baudrate the device can use.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct BaudratesGetRes {
+    pub min: U32<LE>,
+    pub max: U32<LE>,
+}
+impl BaudratesGetRes {
+    pub fn new(baudrates: RangeInclusive<u32>) -> Self {
+        Self {
+            min: (*baudrates.start()).into(),
+            max: (*baudrates.end()).into(),
+        }
+    }
+    pub fn range(&self) -> RangeInclusive<u32> {
+        self.min.get()..=self.max.get()
+    }
+}
+impl RequestTrait for BaudratesGetReq {
+    const COMMAND: Command = Command::BaudratesGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = BaudratesGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::BaudratesGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// The outputs as the device starts them, laid out like [`OutputSetReq`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputSetDefaultsReq(pub [OutputGroup; 8]);
+impl Default for OutputSetDefaultsReq {
+    fn default() -> Self {
+        Self(OutputSetReq::default().0)
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputSetDefaultsRes;
+impl RequestTrait for OutputSetDefaultsReq {
+    const COMMAND: Command = Command::OutputSetDefaults;
+    const TIMEOUT_US: u32 = 500000;
+    type Response = OutputSetDefaultsRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::OutputSetDefaults(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputGetDefaultsReq;
+/// The outputs stored with [`OutputSetDefaultsReq`], see [`settings::OutputDefaults`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct OutputGetDefaultsRes {
+    /// 1 if defaults were stored, 0 if the device starts the outputs as the board does. `groups`
+    /// is all 0 then.
+    pub stored: u8,
+    #[doc(hidden)]
+    pub _reserved: [u8; 3],
+    pub groups: [OutputGroup; 8],
+}
+impl OutputGetDefaultsRes {
+    /// The stored defaults, `None` if there are none.
+    pub fn defaults(&self) -> Option<[OutputGroup; 8]> {
+        (self.stored != 0).then_some(self.groups)
+    }
+}
+impl RequestTrait for OutputGetDefaultsReq {
+    const COMMAND: Command = Command::OutputGetDefaults;
+    const TIMEOUT_US: u32 = 100;
+    type Response = OutputGetDefaultsRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::OutputGetDefaults(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// Device addresses from `first` to `last`, both included.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct AddressRange {
+    pub first: U16<LE>,
+    pub last: U16<LE>,
+}
+impl AddressRange {
+    /// A range without addresses, for the unused entries of [`Forwarding::ranges`].
+    pub const EMPTY: Self = Self {
+        first: U16::new(u16::MAX),
+        last: U16::new(0),
+    };
+
+    pub fn new(addresses: RangeInclusive<u16>) -> Self {
+        Self {
+            first: (*addresses.start()).into(),
+            last: (*addresses.end()).into(),
+        }
+    }
+    /// The range has no addresses, as `first` is above `last`.
+    pub fn is_empty(&self) -> bool {
+        self.first.get() > self.last.get()
+    }
+    pub fn contains(&self, address: u16) -> bool {
+        (self.first.get()..=self.last.get()).contains(&address)
+    }
+}
+
+/// The most address ranges a repeater passes on.
+pub const MAX_FORWARDING_RANGES: usize = 4;
+
+/// The requests a repeater passes on to the devices on its downstream port.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct Forwarding {
+    /// The addresses of the downstream devices, [`AddressRange::EMPTY`] where unused. Must not
+    /// cover devices upstream of the repeater, as the repeater can't tell their responses from
+    /// requests. The repeater's own address is never passed on. Nothing is passed on by
+    /// default.
+    pub ranges: [AddressRange; MAX_FORWARDING_RANGES],
+}
+impl Forwarding {
+    pub const NONE: Self = Self {
+        ranges: [AddressRange::EMPTY; MAX_FORWARDING_RANGES],
+    };
+
+    /// Whether requests to `address` are passed on.
+    pub fn forwards(&self, address: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(address))
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ForwardingSetReq(pub Forwarding);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ForwardingSetRes;
+impl RequestTrait for ForwardingSetReq {
+    const COMMAND: Command = Command::ForwardingSet;
+    const TIMEOUT_US: u32 = 500000;
+    type Response = ForwardingSetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::ForwardingSet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ForwardingGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ForwardingGetRes(pub Forwarding);
+impl RequestTrait for ForwardingGetReq {
+    const COMMAND: Command = Command::ForwardingGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = ForwardingGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::ForwardingGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// Size of the user data in bytes.
+pub const USER_DATA_SIZE: usize = 256;
+
+/// The part of the user data `len` bytes from `offset`. The parts of a request beyond
+/// [`USER_DATA_SIZE`] are left out, and the response tells the part that remains.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct UserDataSpan {
+    pub offset: U16<LE>,
+    pub len: U16<LE>,
+}
+impl UserDataSpan {
+    pub fn new(offset: u16, len: u16) -> Self {
+        Self {
+            offset: offset.into(),
+            len: len.into(),
+        }
+    }
+    /// The byte range of the user data, without what lies beyond it.
+    pub fn clamped(&self) -> Range<usize> {
+        let start = usize::from(self.offset.get()).min(USER_DATA_SIZE);
+        start..(start + usize::from(self.len.get())).min(USER_DATA_SIZE)
+    }
+}
+impl From<Range<usize>> for UserDataSpan {
+    fn from(range: Range<usize>) -> Self {
+        Self::new(range.start as u16, range.len() as u16)
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct UserDataReadReq(pub UserDataSpan);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct UserDataReadRes {
+    pub span: UserDataSpan,
+    /// The bytes read at the start, zeros after them. Bytes that were never written are 0xFF.
+    pub data: [u8; USER_DATA_SIZE],
+}
+impl UserDataReadRes {
+    /// The bytes read.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.span.len.get()).min(USER_DATA_SIZE)]
+    }
+}
+impl RequestTrait for UserDataReadReq {
+    const COMMAND: Command = Command::UserDataRead;
+    const TIMEOUT_US: u32 = 100;
+    type Response = UserDataReadRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::UserDataRead(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct UserDataWriteReq {
+    pub span: UserDataSpan,
+    /// The bytes to write at the start, the rest is ignored.
+    pub data: [u8; USER_DATA_SIZE],
+}
+impl UserDataWriteReq {
+    /// Writes `data` at `offset`. Returns `None` if it doesn't fit into the user data.
+    pub fn new(offset: u16, data: &[u8]) -> Option<Self> {
+        let span = UserDataSpan::new(offset, u16::try_from(data.len()).ok()?);
+        if span.clamped().len() != data.len() {
+            return None;
+        }
+        let mut request = Self {
+            span,
+            data: [0; USER_DATA_SIZE],
+        };
+        request.data[..data.len()].copy_from_slice(data);
+        Some(request)
+    }
+}
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct UserDataWriteRes(pub UserDataSpan);
+impl RequestTrait for UserDataWriteReq {
+    const COMMAND: Command = Command::UserDataWrite;
+    const TIMEOUT_US: u32 = 500000;
+    type Response = UserDataWriteRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::UserDataWrite(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// Set in [`ProtocolVersionGetRes::capabilities`] if the device has a downstream port that it
+/// passes requests on to as a repeater, see [`Command::ForwardingSet`].
+pub const CAPABILITY_REPEATER: u32 = 1 << 0;
+/// Set in [`ProtocolVersionGetRes::capabilities`] if the board reads spare GPIOs as digital
+/// inputs, see [`Command::DigitalGet`].
+pub const CAPABILITY_DIGITAL_INPUTS: u32 = 1 << 1;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ProtocolVersionGetReq;
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ProtocolVersionGetRes {
+    /// The [`PROTOCOL_VERSION`] of the device's firmware
+    pub major: U16<LE>,
+    /// The [`PROTOCOL_VERSION_MINOR`] of the device's firmware
+    pub minor: U16<LE>,
+    /// The `CAPABILITY_*` bits of the features the device supports. Unknown bits are reserved
+    /// for later minor versions.
+    pub capabilities: U32<LE>,
+}
+impl ProtocolVersionGetRes {
+    /// Whether the device supports all of the `CAPABILITY_*` bits in `capabilities`.
+    pub fn supports(&self, capabilities: u32) -> bool {
+        self.capabilities.get() & capabilities == capabilities
+    }
+}
+impl RequestTrait for ProtocolVersionGetReq {
+    const COMMAND: Command = Command::ProtocolVersionGet;
+    const TIMEOUT_US: u32 = 100;
+    type Response = ProtocolVersionGetRes;
+    fn get_response(response: Response<'_>) -> Option<&Self::Response> {
+        match response {
+            Response::ProtocolVersionGet(res) => Some(res),
+            _ => None,
+        }
+    }
+}
+
+/// Set in the command of a [`Header`] if the frame is the response to a request that the device
+/// couldn't handle, with an [`ErrorRes`] as payload instead of the command's response. Devices
+/// send it instead of staying silent, so that the master doesn't have to wait for a timeout.
+pub const ERROR_FLAG: u16 = 0x4000;
+
+/// Set in the command of a [`Header`] if the frame is a report that the device sent on its own,
+/// see [`Command::ReportConfigSet`], with the payload of the command's response. Its sequence
+/// number counts the reports, so that the master can tell when one got lost.
+pub const REPORT_FLAG: u16 = 0x2000;
+
+/// Why a device couldn't handle a request, see [`ERROR_FLAG`].
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    IntoBytes,
+    TryFromBytes,
+    Unaligned,
+    Immutable,
+    KnownLayout,
+    derive_more::Display,
+)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The device doesn't know the command, e.g. as its firmware is older than the master.
+    #[display("unknown command")]
+    UnknownCommand = 1,
+    /// The payload doesn't have the length of the command's request.
+    #[display("invalid payload length")]
+    InvalidLength = 2,
+    /// The payload has the right length, but invalid values, e.g. an unknown parity.
+    #[display("invalid payload")]
+    InvalidPayload = 3,
+    /// Storing the settings of the request in flash failed. They may be in effect until the next
+    /// reboot nevertheless.
+    #[display("writing to flash failed")]
+    StorageFailed = 4,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, IntoBytes, TryFromBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct ErrorRes {
+    pub code: ErrorCode,
+    #[doc(hidden)]
+    pub _reserved: [u8; 3],
+}
+impl ErrorRes {
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            _reserved: [0; 3],
+        }
+    }
+}
+
+/// A request addressed to the device that it can't handle, see [`slave_next`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rejected {
+    /// The command of the request as it was received, which may be unknown.
+    pub command: u16,
+    /// The sequence number of the request, for the error response to echo.
+    pub sequence: u8,
+    pub code: ErrorCode,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, TryFromBytes, IntoBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct Header {
+    /// Magic bytes marking the start of a message. Must be `"OM"`.
+    pub magic: [u8; 2],
+    /// The length of the payload in 32-bit words. Must be equal to `!length_inverted`.
+    pub length: u8,
+    /// The bitwise inverse of `length`. Must be equal to `!length`.
+    pub length_inverted: u8,
+    /// The address of the device. For requests, this is the target address. For responses, this is the source address.
+    pub address: U16<LE>,
+    /// The command of the message. Valid values are defined in the [`Command`] enum.
+    pub command: U16<LE>,
+    /// Chosen by the master for each request and echoed in the response, so that the master
+    /// can tell the response to a request from a late one to an earlier request with the same
+    /// address and command, e.g. after a retry.
+    pub sequence: u8,
+}
+
+impl Header {
+    /// Whether the message is a request of a [`Command::broadcast`] command to
+    /// [`BROADCAST_ADDRESS`], which devices execute without responding.
+    pub fn is_broadcast(&self) -> bool {
+        self.address.get() == BROADCAST_ADDRESS
+            && Command::try_from(self.command.get()).is_ok_and(Command::broadcast)
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, TryFromBytes, IntoBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct Footer {
+    /// The checksum of the message. Must be equal to the CRC-16/Kermit of the header and payload.
+    pub checksum: U16<LE>,
+}
+
+/// The checksum of the frames. Uses a slice-by-16 table, as it is computed for every frame the
+/// master and the slaves see on the bus.
+pub const CHECKSUM: Crc<u16, Table<16>> = Crc::<u16, Table<16>>::new(&CRC_16_KERMIT);
+
+#[derive(Debug, Clone, Copy, TryFromBytes, IntoBytes, Immutable)]
+#[repr(C)]
+pub struct Message<T> {
+    pub header: Header,
+    pub payload: T,
+    pub footer: Footer,
+}
+
+impl<T: IntoBytes + Unaligned + Immutable> Message<T> {
+    /// The size of the message on the wire, including header and footer.
+    pub const WIRE_SIZE: usize = size_of::<Self>();
+
+    fn new_raw(address: u16, command: u16, sequence: u8, payload: T) -> Self {
+        assert!(size_of::<T>() <= u8::MAX as usize * 4);
+        assert!(size_of::<T>().is_multiple_of(4));
+        let header = Header {
+            magic: MAGIC,
+            length: u8::try_from(size_of::<T>() / 4).unwrap(),
+            length_inverted: !u8::try_from(size_of::<T>() / 4).unwrap(),
+            address: address.into(),
+            command: command.into(),
+            sequence,
+        };
+        let footer = Footer { checksum: 0.into() };
+        let mut message = Message {
+            header,
+            payload,
+            footer,
+        };
+        message.footer.checksum = CHECKSUM
+            .checksum(&message.as_bytes()[..size_of::<Header>() + size_of::<T>()])
+            .into();
+        message
+    }
+    /// Creates a new request message with the given address, command, sequence number and
+    /// payload.
+    pub fn new_request(address: u16, command: Command, sequence: u8, payload: T) -> Self {
+        Self::new_raw(address, u16::from(command), sequence, payload)
+    }
+    /// Creates a new response message with the given address, command and payload, and the
+    /// sequence number of the request.
+    pub fn new_response(address: u16, command: Command, sequence: u8, payload: T) -> Self {
+        Self::new_raw(address, u16::from(command), sequence, payload)
+    }
+    /// Creates a report the device sends on its own, see [`REPORT_FLAG`], with the number of
+    /// the report as sequence number.
+    pub fn new_report(address: u16, command: Command, sequence: u8, payload: T) -> Self {
+        Self::new_raw(address, u16::from(command) | REPORT_FLAG, sequence, payload)
+    }
+}
+
+impl Message<ErrorRes> {
+    /// Creates the response to a request that the device rejected. Fragments of a request are
+    /// answered with a single frame.
+    pub fn new_error(address: u16, rejected: Rejected) -> Self {
+        Self::new_raw(
+            address,
+            (rejected.command & !FRAGMENT_FLAG) | ERROR_FLAG,
+            rejected.sequence,
+            ErrorRes::new(rejected.code),
+        )
+    }
+}
+
+/// The largest payload of a message, as the [`Header`] counts it in 32-bit words in one byte.
+pub const MAX_PAYLOAD_SIZE: usize = u8::MAX as usize * 4;
+/// The largest frame the [`Header`] can announce, including those of commands this crate doesn't
+/// know.
+pub const MAX_FRAME_SIZE: usize = Message::<[u8; MAX_PAYLOAD_SIZE]>::WIRE_SIZE;
+/// The largest request of any [`Command`] on the wire.
+pub const MAX_REQUEST_SIZE: usize = max_wire_sizes().0;
+/// The largest response of any [`Command`] on the wire.
+pub const MAX_RESPONSE_SIZE: usize = max_wire_sizes().1;
+
+/// The sizes of the request and the response of a command on the wire.
+const fn wire_sizes<P: RequestTrait>() -> (usize, usize) {
+    (Message::<P>::WIRE_SIZE, Message::<P::Response>::WIRE_SIZE)
+}
+
+const fn command_wire_sizes(command: Command) -> (usize, usize) {
+    match command {
+        Command::Check => wire_sizes::<CheckReq>(),
+        Command::InfoGet => wire_sizes::<InfoGetReq>(),
+        Command::ConfigSet => wire_sizes::<ConfigSetReq>(),
+        Command::ConfigGet => wire_sizes::<ConfigGetReq>(),
+        Command::OutputSet => wire_sizes::<OutputSetReq>(),
+        Command::OutputGet => wire_sizes::<OutputGetReq>(),
+        Command::InputGet => wire_sizes::<InputGetReq>(),
+        Command::InputGetFull => wire_sizes::<InputGetFullReq>(),
+        Command::InputSetCalibrations => wire_sizes::<InputSetCalibrationsReq>(),
+        Command::InputGetCalibrations => wire_sizes::<InputGetCalibrationsReq>(),
+        Command::InputSetThresholds => wire_sizes::<InputSetThresholdsReq>(),
+        Command::InputGetThresholds => wire_sizes::<InputGetThresholdsReq>(),
+        Command::InputGetThresholdTimes => wire_sizes::<InputGetThresholdTimesReq>(),
+        Command::InputGetThresholdStates => wire_sizes::<InputGetThresholdStatesReq>(),
+        Command::Reboot => wire_sizes::<RebootReq>(),
+        Command::DiagnosticsGet => wire_sizes::<DiagnosticsGetReq>(),
+        Command::DigitalGet => wire_sizes::<DigitalGetReq>(),
+        Command::PowerSet => wire_sizes::<PowerSetReq>(),
+        Command::PowerGet => wire_sizes::<PowerGetReq>(),
+        Command::InputGetDebounce => wire_sizes::<InputGetDebounceReq>(),
+        Command::ForwardingSet => wire_sizes::<ForwardingSetReq>(),
+        Command::ForwardingGet => wire_sizes::<ForwardingGetReq>(),
+        Command::UserDataRead => wire_sizes::<UserDataReadReq>(),
+        Command::UserDataWrite => wire_sizes::<UserDataWriteReq>(),
+        Command::ProtocolVersionGet => wire_sizes::<ProtocolVersionGetReq>(),
+        Command::OutputSetMasked => wire_sizes::<OutputSetMaskedReq>(),
+        Command::InputGetEvents => wire_sizes::<InputGetEventsReq>(),
+        Command::ReportConfigSet => wire_sizes::<ReportConfigSetReq>(),
+        Command::FlashEraseRegion => wire_sizes::<FlashEraseRegionReq>(),
+        Command::FlashWriteChunk => wire_sizes::<FlashWriteChunkReq>(),
+        Command::FlashVerify => wire_sizes::<FlashVerifyReq>(),
+        Command::BootSwap => wire_sizes::<BootSwapReq>(),
+        Command::DeviceIdGet => wire_sizes::<DeviceIdGetReq>(),
+        Command::FailsafeSet => wire_sizes::<FailsafeSetReq>(),
+        Command::FailsafeGet => wire_sizes::<FailsafeGetReq>(),
+        Command::OutputSetDefaults => wire_sizes::<OutputSetDefaultsReq>(),
+        Command::BaudratesGet => wire_sizes::<BaudratesGetReq>(),
+        Command::OutputGetDefaults => wire_sizes::<OutputGetDefaultsReq>(),
+    }
+}
+
+/// The size of a request with the given command on the wire, including header and footer.
+pub const fn max_request_size(command: Command) -> usize {
+    command_wire_sizes(command).0
+}
+
+/// The size of a response to the given command on the wire, including header and footer.
+pub const fn max_response_size(command: Command) -> usize {
+    command_wire_sizes(command).1
+}
+
+/// The largest request and response of any command.
+const fn max_wire_sizes() -> (usize, usize) {
+    let (mut request, mut response) = (0, 0);
+    let mut i = 0;
+    while i < Command::ALL.len() {
+        let sizes = command_wire_sizes(Command::ALL[i]);
+        if sizes.0 > request {
+            request = sizes.0;
+        }
+        if sizes.1 > response {
+            response = sizes.1;
+        }
+        i += 1;
+    }
+    (request, response)
+}
+
+/// A message whose payload is a byte slice of a length only known at runtime, e.g. a chunk of
+/// a capture, unlike a [`Message`] whose payload is a struct. Payloads that are not whole 32-bit
+/// words are padded with zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRef<'a> {
+    header: Header,
+    payload: &'a [u8],
+    footer: Footer,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Creates a message with any command, e.g. one this crate doesn't know. Returns `None` if
+    /// the payload is longer than [`MAX_PAYLOAD_SIZE`].
+    pub fn new(address: u16, command: u16, sequence: u8, payload: &'a [u8]) -> Option<Self> {
+        let (header, footer) = seal(address, command, sequence, &[payload])?;
+        Some(Self {
+            header,
+            payload,
+            footer,
+        })
+    }
+    /// Creates a request message, see [`MessageRef::new`].
+    pub fn new_request(
+        address: u16,
+        command: Command,
+        sequence: u8,
+        payload: &'a [u8],
+    ) -> Option<Self> {
+        Self::new(address, u16::from(command), sequence, payload)
+    }
+    /// Creates a response message, see [`MessageRef::new`].
+    pub fn new_response(
+        address: u16,
+        command: Command,
+        sequence: u8,
+        payload: &'a [u8],
+    ) -> Option<Self> {
+        Self::new(address, u16::from(command), sequence, payload)
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// The payload without padding.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+    /// The length of the frame including header, padding and footer.
+    pub fn frame_len(&self) -> usize {
+        size_of::<Header>() + usize::from(self.header.length) * 4 + size_of::<Footer>()
+    }
+    /// Writes the frame to the beginning of `buf` and returns its length, or `None` if `buf`
+    /// is shorter than [`MessageRef::frame_len`].
+    pub fn write_to(&self, buf: &mut [u8]) -> Option<usize> {
+        write_frame(buf, &self.header, &[self.payload], &self.footer)
+    }
+}
+
+/// The header and footer of a frame whose payload is the concatenation of `parts`, padded with
+/// zeros to whole 32-bit words. Returns `None` if the payload is longer than
+/// [`MAX_PAYLOAD_SIZE`].
+fn seal(address: u16, command: u16, sequence: u8, parts: &[&[u8]]) -> Option<(Header, Footer)> {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    let length = u8::try_from(len.div_ceil(4)).ok()?;
+    let header = Header {
+        magic: MAGIC,
+        length,
+        length_inverted: !length,
+        address: address.into(),
+        command: command.into(),
+        sequence,
+    };
+    let mut digest = CHECKSUM.digest();
+    digest.update(header.as_bytes());
+    for part in parts {
+        digest.update(part);
+    }
+    digest.update(&[0; 3][..usize::from(length) * 4 - len]);
+    let footer = Footer {
+        checksum: digest.finalize().into(),
+    };
+    Some((header, footer))
+}
+
+/// Writes a frame sealed by [`seal`] to the beginning of `buf` and returns its length, or
+/// `None` if `buf` is too short.
+fn write_frame(buf: &mut [u8], header: &Header, parts: &[&[u8]], footer: &Footer) -> Option<usize> {
+    let len = size_of::<Header>() + usize::from(header.length) * 4 + size_of::<Footer>();
+    let frame = buf.get_mut(..len)?;
+    let (header_bytes, rest) = frame.split_at_mut(size_of::<Header>());
+    let (mut payload, footer_bytes) = rest.split_at_mut(rest.len() - size_of::<Footer>());
+    header_bytes.copy_from_slice(header.as_bytes());
+    for part in parts {
+        let (data, rest) = payload.split_at_mut(part.len());
+        data.copy_from_slice(part);
+        payload = rest;
+    }
+    payload.fill(0);
+    footer_bytes.copy_from_slice(footer.as_bytes());
+    Some(len)
+}
+
+/// Set in the command of a [`Header`] if the frame is a fragment of a payload that is too large
+/// for one frame, see [`Fragments`]. Parsers that don't know fragments skip them like frames
+/// of an unknown command.
+pub const FRAGMENT_FLAG: u16 = 0x8000;
+
+/// Precedes the data in the payload of a fragment.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, TryFromBytes, IntoBytes, Unaligned, Immutable, KnownLayout,
+)]
+#[repr(C)]
+pub struct FragmentHeader {
+    /// The index of the fragment, starting at 0. Fragments are sent in order.
+    pub index: U16<LE>,
+    /// The number of fragments of the payload, at least 1.
+    pub total: U16<LE>,
+    /// The length of the reassembled payload in bytes, so that the padding of the last fragment
+    /// can be told from data.
+    pub size: U32<LE>,
+}
+
+/// The most data a fragment can carry.
+pub const MAX_FRAGMENT_SIZE: usize = MAX_PAYLOAD_SIZE - size_of::<FragmentHeader>();
+
+/// Splits a payload of any length into the frames of its fragments, e.g. a waveform buffer or a
+/// firmware chunk. Every fragment but the last carries the same amount of data, so the receiver
+/// can tell where data goes from the order of the fragments, see [`Reassembler`]. A payload
+/// that fits into one fragment is still sent as one, which keeps its exact length.
+#[derive(Debug, Clone)]
+pub struct Fragments<'a> {
+    address: u16,
+    command: u16,
+    sequence: u8,
+    payload: &'a [u8],
+    fragment_size: usize,
+    index: u16,
+    total: u16,
+}
+
+impl<'a> Fragments<'a> {
+    /// Splits `payload` into fragments of `fragment_size` bytes of data, e.g. fewer than
+    /// [`MAX_FRAGMENT_SIZE`] to keep frames short at low baudrates. Returns `None` if
+    /// `fragment_size` is not a multiple of 4 between 4 and [`MAX_FRAGMENT_SIZE`], if the
+    /// command has the [`FRAGMENT_FLAG`] set already, or if the payload needs more than
+    /// `u16::MAX` fragments.
+    pub fn new(
+        address: u16,
+        command: u16,
+        sequence: u8,
+        payload: &'a [u8],
+        fragment_size: usize,
+    ) -> Option<Self> {
+        if fragment_size == 0
+            || !fragment_size.is_multiple_of(4)
+            || fragment_size > MAX_FRAGMENT_SIZE
+            || command & FRAGMENT_FLAG != 0
+        {
+            return None;
+        }
+        u32::try_from(payload.len()).ok()?;
+        let total = u16::try_from(payload.len().div_ceil(fragment_size).max(1)).ok()?;
+        Some(Self {
+            address,
+            command,
+            sequence,
+            payload,
+            fragment_size,
+            index: 0,
+            total,
+        })
+    }
+    /// Splits the payload of a request, see [`Fragments::new`].
+    pub fn new_request(
+        address: u16,
+        command: Command,
+        sequence: u8,
+        payload: &'a [u8],
+        fragment_size: usize,
+    ) -> Option<Self> {
+        Self::new(
+            address,
+            u16::from(command),
+            sequence,
+            payload,
+            fragment_size,
+        )
+    }
+    /// Splits the payload of a response, see [`Fragments::new`].
+    pub fn new_response(
+        address: u16,
+        command: Command,
+        sequence: u8,
+        payload: &'a [u8],
+        fragment_size: usize,
+    ) -> Option<Self> {
+        Self::new(
+            address,
+            u16::from(command),
+            sequence,
+            payload,
+            fragment_size,
+        )
+    }
+    /// The number of fragments, including those already returned.
+    pub fn total(&self) -> u16 {
+        self.total
+    }
+    /// The length of the frames of all fragments, including those already returned.
+    pub fn wire_size(&self) -> usize {
+        let overhead = size_of::<Header>() + size_of::<FragmentHeader>() + size_of::<Footer>();
+        usize::from(self.total) * overhead + self.payload.len().next_multiple_of(4)
+    }
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Fragment<'a>> {
+        if self.index == self.total {
+            return None;
+        }
+        let start = usize::from(self.index) * self.fragment_size;
+        let end = (start + self.fragment_size).min(self.payload.len());
+        let fragment = FragmentHeader {
+            index: self.index.into(),
+            total: self.total.into(),
+            size: (self.payload.len() as u32).into(),
+        };
+        let data = &self.payload[start..end];
+        let command = self.command | FRAGMENT_FLAG;
+        // the data is at most MAX_FRAGMENT_SIZE long
+        let (header, footer) = seal(
+            self.address,
+            command,
+            self.sequence,
+            &[fragment.as_bytes(), data],
+        )
+        .unwrap();
+        self.index += 1;
+        Some(Fragment {
+            header,
+            fragment,
+            data,
+            footer,
+        })
+    }
+}
+
+/// A frame of one fragment, see [`Fragments`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment<'a> {
+    header: Header,
+    fragment: FragmentHeader,
+    data: &'a [u8],
+    footer: Footer,
+}
+
+impl<'a> Fragment<'a> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    pub fn fragment_header(&self) -> &FragmentHeader {
+        &self.fragment
+    }
+    /// The data of the fragment without padding.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    /// The length of the frame including header, padding and footer.
+    pub fn frame_len(&self) -> usize {
+        size_of::<Header>() + usize::from(self.header.length) * 4 + size_of::<Footer>()
+    }
+    /// Writes the frame to the beginning of `buf` and returns its length, or `None` if `buf`
+    /// is shorter than [`Fragment::frame_len`].
+    pub fn write_to(&self, buf: &mut [u8]) -> Option<usize> {
+        write_frame(
+            buf,
+            &self.header,
+            &[self.fragment.as_bytes(), self.data],
+            &self.footer,
+        )
+    }
+}
+
+/// Why [`Reassembler::push`] rejected a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FragmentError {
+    /// The command of the frame lacks the [`FRAGMENT_FLAG`].
+    #[error("The frame is not a fragment")]
+    NotAFragment,
+    /// The fragment header is inconsistent, e.g. the index is not below the total, the size
+    /// exceeds what the fragments can carry or the data doesn't fit the size.
+    #[error("The fragment is malformed")]
+    Malformed,
+    /// A fragment other than the next one of the payload arrived, e.g. because one was lost or
+    /// it belongs to a payload with another sequence number. The fragments received so far are
+    /// discarded.
+    #[error("Expected fragment {expected}, received fragment {index}")]
+    OutOfOrder { expected: u16, index: u16 },
+}
+
+/// The data of a fragment accepted by [`Reassembler::push`] and where it goes in the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentData<'a> {
+    /// The address of the frame.
+    pub address: u16,
+    /// The command of the frame without the [`FRAGMENT_FLAG`].
+    pub command: u16,
+    /// The length of the reassembled payload in bytes.
+    pub size: usize,
+    /// Where the data goes in the reassembled payload.
+    pub offset: usize,
+    pub data: &'a [u8],
+    /// Whether this was the last fragment, i.e. the payload is complete.
+    pub complete: bool,
+}
+
+/// Follows the fragments of one payload at a time, see [`Fragments`]. It doesn't store the data
+/// itself, so that the caller can reassemble into whatever buffer it has: a static one in the
+/// firmware, a `Vec` in the tool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reassembler {
+    transfer: Option<Transfer>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transfer {
+    address: u16,
+    command: u16,
+    sequence: u8,
+    total: u16,
+    size: usize,
+    next: u16,
+    offset: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Whether some but not all fragments of a payload were received.
+    pub fn in_progress(&self) -> bool {
+        self.transfer.is_some()
+    }
+    /// Discards the fragments received so far, e.g. after a timeout.
+    pub fn reset(&mut self) {
+        self.transfer = None;
+    }
+    /// Takes the frame with the given header and payload, e.g. from [`next_message`], and
+    /// returns its data and where it goes. The first fragment of a payload discards the
+    /// fragments of a previous one that was not complete.
+    pub fn push<'p>(
+        &mut self,
+        header: &Header,
+        payload: &'p [u8],
+    ) -> Result<FragmentData<'p>, FragmentError> {
+        let command = header.command.get();
+        if command & FRAGMENT_FLAG == 0 {
+            return Err(FragmentError::NotAFragment);
+        }
+        let command = command & !FRAGMENT_FLAG;
+        let address = header.address.get();
+        let Ok((fragment, data)) = FragmentHeader::try_ref_from_prefix(payload) else {
+            return Err(FragmentError::Malformed);
+        };
+        let (index, total, size) = (
+            fragment.index.get(),
+            fragment.total.get(),
+            fragment.size.get() as usize,
+        );
+        if index >= total || size > usize::from(total) * MAX_FRAGMENT_SIZE {
+            return Err(FragmentError::Malformed);
+        }
+        let first = Transfer {
+            address,
+            command,
+            sequence: header.sequence,
+            total,
+            size,
+            next: 0,
+            offset: 0,
+        };
+        let transfer = match self.transfer.take() {
+            _ if index == 0 => first,
+            Some(transfer)
+                if transfer
+                    == (Transfer {
+                        next: index,
+                        offset: transfer.offset,
+                        ..first
+                    }) =>
+            {
+                transfer
+            }
+            transfer => {
+                let expected = transfer.map_or(0, |transfer| transfer.next);
+                return Err(FragmentError::OutOfOrder { expected, index });
+            }
+        };
+        let remaining = size
+            .checked_sub(transfer.offset)
+            .ok_or(FragmentError::Malformed)?;
+        let complete = index + 1 == total;
+        let data = if complete {
+            // only the last fragment may be padded
+            if data.len() < remaining || data.len() - remaining >= 4 {
+                return Err(FragmentError::Malformed);
+            }
+            &data[..remaining]
+        } else if data.len() <= remaining {
+            data
+        } else {
+            return Err(FragmentError::Malformed);
+        };
+        let offset = transfer.offset;
+        if !complete {
+            self.transfer = Some(Transfer {
+                next: index + 1,
+                offset: offset + data.len(),
+                ..transfer
+            });
+        }
+        Ok(FragmentData {
+            address,
+            command,
+            size,
+            offset,
+            data,
+            complete,
+        })
+    }
+}
+
+/// A frame found in a byte stream by [`next_frame`], regardless of whether its checksum is valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub header: &'a Header,
+    pub payload: &'a [u8],
+    pub footer: &'a Footer,
+    /// The complete frame including header, payload and footer.
+    pub bytes: &'a [u8],
+}
+impl Frame<'_> {
+    /// Computes the checksum of the header and payload.
+    pub fn checksum(&self) -> u16 {
+        CHECKSUM.checksum(&self.bytes[..self.bytes.len() - size_of::<Footer>()])
+    }
+    /// Returns `true` if the checksum in the footer matches the header and payload.
+    pub fn is_valid(&self) -> bool {
+        self.footer.checksum.get() == self.checksum()
+    }
+}
+impl<'a> Frame<'a> {
+    /// Parses the frame as a response like [`master_next`], but without verifying the checksum
+    /// again. Returns `None` if the payload doesn't fit the command.
+    pub fn response(&self) -> Option<(u16, Response<'a>)> {
+        parse_response(self.header, self.payload)
+    }
+    /// Parses the frame as a request to whatever address it has, like [`slave_next`] but without
+    /// verifying the checksum again. Returns `None` if the payload doesn't fit the command.
+    pub fn request(&self) -> Option<Request<'a>> {
+        parse_request(self.header, self.payload).ok()
+    }
+    /// Splits the payload of a fragment into its [`FragmentHeader`] and its data, including
+    /// the padding of the last fragment. Returns `None` if the frame is not a fragment. See
+    /// [`Reassembler`] to put the data together.
+    pub fn fragment(&self) -> Option<(&'a FragmentHeader, &'a [u8])> {
+        if self.header.command.get() & FRAGMENT_FLAG == 0 {
+            return None;
+        }
+        FragmentHeader::try_ref_from_prefix(self.payload).ok()
+    }
+}
+
+/// Searches for the next frame in the given byte slice and returns it along with the number of bytes processed.
+/// A frame is anything that starts with a valid header marker and is long enough to contain the payload
+/// announced in the header. The checksum is not verified, see [`Frame::is_valid`].
+/// The number of bytes processed includes any data skipped before the frame and the frame itself.
+pub fn next_frame(bytes: &[u8]) -> (Option<Frame<'_>>, usize) {
+    let mut processed = 0;
+    while bytes.len() - processed >= MAGIC.len() + 2 {
+        // only the first byte of the marker is searched for, the rest is checked below
+        let Some(skip) = memchr::memchr(MAGIC[0], &bytes[processed..bytes.len() - MAGIC.len() - 1])
+        else {
+            processed = bytes.len() - MAGIC.len() - 1;
+            break;
+        };
+        processed += skip;
+        let candidate = &bytes[processed..];
+        if candidate[0..MAGIC.len()] == MAGIC
+            && candidate[MAGIC.len()] == !candidate[MAGIC.len() + 1]
+        {
+            // valid header marker found
+            let Ok((header, _)) = Header::try_ref_from_prefix(candidate) else {
+                // too short
+                break;
+            };
+            let length = header.length as usize * 4 + size_of::<Header>() + size_of::<Footer>();
+            if candidate.len() < length {
+                // too short
+                break;
+            }
+            processed += length;
+            let payload = &candidate[size_of::<Header>()..length - size_of::<Footer>()];
+            let footer =
+                Footer::try_ref_from_bytes(&candidate[length - size_of::<Footer>()..length])
+                    .unwrap();
+            let frame = Frame {
+                header,
+                payload,
+                footer,
+                bytes: &candidate[..length],
+            };
+            return (Some(frame), processed);
+        }
+        processed += 1;
+    }
+    (None, processed)
+}
+
+/// Searches for the next valid message in the given byte slice and returns it along with the number of bytes processed.
+/// If a valid message is found, the returned byte slice will contain the header and payload of the message, but not the footer.
+/// If no valid message is found, the returned byte slice will be `None`.
+/// The number of bytes processed is the number of bytes that were consumed from the input byte slice,
+/// including any invalid data that was skipped over. Therefore it may consume bytes even if no valid message is found.
+pub fn next_message(bytes: &[u8]) -> (Option<(&Header, &[u8])>, usize) {
+    let mut processed = 0;
+    loop {
+        let (maybe_frame, frame_processed) = next_frame(&bytes[processed..]);
+        processed += frame_processed;
+        match maybe_frame {
+            Some(frame) if frame.is_valid() => {
+                return (Some((frame.header, frame.payload)), processed);
+            }
+            // Invalid checksum. The header marker may have been noise in front of a real frame,
+            // so continue searching right after it instead of after the frame it announced.
+            Some(frame) => processed -= frame.bytes.len() - 1,
+            None => return (None, processed),
+        }
+    }
+}
+
+/// Parses the next message from the given byte slice and returns its address and the payload as a [`Response`]
+/// along with the number of bytes processed. Skips invalid message headers and
+/// messages with invalid checksums.
+pub fn master_next<'a>(buffer: &'a [u8]) -> (Option<(u16, Response<'a>)>, usize) {
+    let (maybe_message, processed) = next_message(buffer);
+    let response = maybe_message.and_then(|(header, payload)| parse_response(header, payload));
+    (response, processed)
+}
+
+/// Parses the next message with the given address from the given byte slice and returns the payload
+/// as a [`Request`] along with the number of bytes processed. Skips invalid message headers,
+/// messages with invalid checksums, messages with a different address, error responses and
+/// reports.
+/// Requests of unknown commands or with invalid payloads are [`Rejected`], for the device to
+/// answer with [`Message::new_error`]. Requests of [`Command::broadcast`] commands to
+/// [`BROADCAST_ADDRESS`] are accepted too, see [`Header::is_broadcast`]; they must not be answered.
+pub fn slave_next<'a>(
+    buffer: &'a [u8],
+    address: u16,
+) -> (Option<Result<Request<'a>, Rejected>>, usize) {
+    let (maybe_message, processed) = next_message(buffer);
+    let request = maybe_message
+        .filter(|(header, _)| {
+            (header.address.get() == address || header.is_broadcast())
+                && header.command.get() & (ERROR_FLAG | REPORT_FLAG) == 0
+        })
+        .map(|(header, payload)| {
+            parse_request(header, payload).map_err(|code| Rejected {
+                command: header.command.get(),
+                sequence: header.sequence,
+                code,
+            })
+        });
+    (request, processed)
+}
+
+/// Parses the payload of a message as the [`Response`] its header announces.
+fn parse_response<'a>(header: &'a Header, payload: &'a [u8]) -> Option<(u16, Response<'a>)> {
+    let address = header.address.get();
+    let command = header.command.get();
+    if command & ERROR_FLAG != 0 {
+        let command = Command::try_from(command & !ERROR_FLAG).ok()?;
+        let message = ErrorRes::try_ref_from_bytes(payload).ok()?;
+        return Some((address, Response::Error(command, message)));
+    }
+    if command & REPORT_FLAG != 0 {
+        // the only command reported so far
+        if command & !REPORT_FLAG != u16::from(Command::InputGet) {
+            return None;
+        }
+        let message = InputGetRes::try_ref_from_bytes(payload).ok()?;
+        return Some((address, Response::InputReport(message)));
+    }
+    match Command::try_from(command) {
+        Err(_) => None,
+        Ok(Command::Check) => Some((address, Response::Check(&CheckRes))),
+        Ok(Command::InfoGet) => {
+            let Ok(message) = InfoGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InfoGet(message)))
+        }
+        Ok(Command::ConfigGet) => {
+            let Ok(message) = ConfigGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::ConfigGet(message)))
+        }
+        Ok(Command::ConfigSet) => Some((address, Response::ConfigSet(&ConfigSetRes))),
+        Ok(Command::OutputGet) => {
+            let Ok(message) = OutputGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::OutputGet(message)))
+        }
+        Ok(Command::OutputSet) => Some((address, Response::OutputSet(&OutputSetRes))),
+        Ok(Command::InputGet) => {
+            let Ok(message) = InputGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGet(message)))
+        }
+        Ok(Command::InputGetFull) => {
+            let Ok(message) = InputGetFullRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetFull(message)))
+        }
+        Ok(Command::InputSetCalibrations) => Some((
+            address,
+            Response::InputSetCalibrations(&InputSetCalibrationsRes),
+        )),
+        Ok(Command::InputGetCalibrations) => {
+            let Ok(message) = InputGetCalibrationsRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetCalibrations(message)))
+        }
+        Ok(Command::InputSetThresholds) => Some((
+            address,
+            Response::InputSetThresholds(&InputSetThresholdsRes),
+        )),
+        Ok(Command::InputGetThresholds) => {
+            let Ok(message) = InputGetThresholdsRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetThresholds(message)))
+        }
+        Ok(Command::InputGetThresholdTimes) => {
+            let Ok(message) = InputGetThresholdTimesRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetThresholdTimes(message)))
+        }
+        Ok(Command::InputGetThresholdStates) => {
+            let Ok(message) = InputGetThresholdStatesRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetThresholdStates(message)))
+        }
+        Ok(Command::Reboot) => Some((address, Response::Reboot(&RebootRes))),
+        Ok(Command::DiagnosticsGet) => {
+            let Ok(message) = DiagnosticsGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::DiagnosticsGet(message)))
+        }
+        Ok(Command::DigitalGet) => {
+            let Ok(message) = DigitalGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::DigitalGet(message)))
+        }
+        Ok(Command::PowerSet) => Some((address, Response::PowerSet(&PowerSetRes))),
+        Ok(Command::PowerGet) => {
+            let Ok(message) = PowerGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::PowerGet(message)))
+        }
+        Ok(Command::InputGetDebounce) => {
+            let Ok(message) = InputGetDebounceRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetDebounce(message)))
+        }
+        Ok(Command::ForwardingSet) => Some((address, Response::ForwardingSet(&ForwardingSetRes))),
+        Ok(Command::ForwardingGet) => {
+            let Ok(message) = ForwardingGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::ForwardingGet(message)))
+        }
+        Ok(Command::UserDataRead) => {
+            let Ok(message) = UserDataReadRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::UserDataRead(message)))
+        }
+        Ok(Command::UserDataWrite) => {
+            let Ok(message) = UserDataWriteRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::UserDataWrite(message)))
+        }
+        Ok(Command::ProtocolVersionGet) => {
+            let Ok(message) = ProtocolVersionGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::ProtocolVersionGet(message)))
+        }
+        Ok(Command::OutputSetMasked) => {
+            Some((address, Response::OutputSetMasked(&OutputSetMaskedRes)))
+        }
+        Ok(Command::InputGetEvents) => {
+            let Ok(message) = InputGetEventsRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::InputGetEvents(message)))
+        }
+        Ok(Command::ReportConfigSet) => {
+            Some((address, Response::ReportConfigSet(&ReportConfigSetRes)))
+        }
+        Ok(Command::FlashEraseRegion) => {
+            Some((address, Response::FlashEraseRegion(&FlashEraseRegionRes)))
+        }
+        Ok(Command::FlashWriteChunk) => {
+            Some((address, Response::FlashWriteChunk(&FlashWriteChunkRes)))
+        }
+        Ok(Command::FlashVerify) => {
+            let Ok(message) = FlashVerifyRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::FlashVerify(message)))
+        }
+        Ok(Command::BootSwap) => Some((address, Response::BootSwap(&BootSwapRes))),
+        Ok(Command::DeviceIdGet) => {
+            let Ok(message) = DeviceIdGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::DeviceIdGet(message)))
+        }
+        Ok(Command::FailsafeSet) => Some((address, Response::FailsafeSet(&FailsafeSetRes))),
+        Ok(Command::FailsafeGet) => {
+            let Ok(message) = FailsafeGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::FailsafeGet(message)))
+        }
+        Ok(Command::OutputSetDefaults) => {
+            Some((address, Response::OutputSetDefaults(&OutputSetDefaultsRes)))
+        }
+        Ok(Command::BaudratesGet) => {
+            let Ok(message) = BaudratesGetRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::BaudratesGet(message)))
+        }
+        Ok(Command::OutputGetDefaults) => {
+            let Ok(message) = OutputGetDefaultsRes::try_ref_from_bytes(payload) else {
+                return None;
+            };
+            Some((address, Response::OutputGetDefaults(message)))
+        }
+    }
+}
+
+/// Parses the payload of a message as the [`Request`] its header announces.
+fn parse_request<'a>(header: &'a Header, payload: &'a [u8]) -> Result<Request<'a>, ErrorCode> {
+    match Command::try_from(u16::from(header.command)) {
+        Err(_) => Err(ErrorCode::UnknownCommand),
+        Ok(Command::Check) => Ok(Request::Check(&CheckReq)),
+        Ok(Command::InfoGet) => Ok(Request::InfoGet(&InfoGetReq)),
+        Ok(Command::ConfigGet) => Ok(Request::ConfigGet(&ConfigGetReq)),
+        Ok(Command::ConfigSet) => {
+            let message = parse_payload::<ConfigSetReq>(payload)?;
+            Ok(Request::ConfigSet(message))
+        }
+        Ok(Command::OutputGet) => Ok(Request::OutputGet(&OutputGetReq)),
+        Ok(Command::OutputSet) => {
+            let message = parse_payload::<OutputSetReq>(payload)?;
+            Ok(Request::OutputSet(message))
+        }
+        Ok(Command::InputGet) => Ok(Request::InputGet(&InputGetReq)),
+        Ok(Command::InputGetFull) => Ok(Request::InputGetFull(&InputGetFullReq)),
+        Ok(Command::InputSetCalibrations) => {
+            let message = parse_payload::<InputSetCalibrationsReq>(payload)?;
+            Ok(Request::InputSetCalibrations(message))
+        }
+        Ok(Command::InputGetCalibrations) => {
+            Ok(Request::InputGetCalibrations(&InputGetCalibrationsReq))
+        }
+        Ok(Command::InputSetThresholds) => {
+            let message = parse_payload::<InputSetThresholdsReq>(payload)?;
+            Ok(Request::InputSetThresholds(message))
+        }
+        Ok(Command::InputGetThresholds) => Ok(Request::InputGetThresholds(&InputGetThresholdsReq)),
+        Ok(Command::InputGetThresholdTimes) => {
+            Ok(Request::InputGetThresholdTimes(&InputGetThresholdTimesReq))
+        }
+        Ok(Command::InputGetThresholdStates) => Ok(Request::InputGetThresholdStates(
+            &InputGetThresholdStatesReq,
+        )),
+        Ok(Command::Reboot) => {
+            if payload.is_empty() {
+                return Ok(Request::Reboot(&RebootReq::FIRMWARE));
+            }
+            let message = parse_payload::<RebootReq>(payload)?;
+            Ok(Request::Reboot(message))
+        }
+        Ok(Command::DiagnosticsGet) => Ok(Request::DiagnosticsGet(&DiagnosticsGetReq)),
+        Ok(Command::DigitalGet) => Ok(Request::DigitalGet(&DigitalGetReq)),
+        Ok(Command::PowerSet) => {
+            let message = parse_payload::<PowerSetReq>(payload)?;
+            Ok(Request::PowerSet(message))
+        }
+        Ok(Command::PowerGet) => Ok(Request::PowerGet(&PowerGetReq)),
+        Ok(Command::InputGetDebounce) => Ok(Request::InputGetDebounce(&InputGetDebounceReq)),
+        Ok(Command::ForwardingSet) => {
+            let message = parse_payload::<ForwardingSetReq>(payload)?;
+            Ok(Request::ForwardingSet(message))
+        }
+        Ok(Command::ForwardingGet) => Ok(Request::ForwardingGet(&ForwardingGetReq)),
+        Ok(Command::UserDataRead) => {
+            let message = parse_payload::<UserDataReadReq>(payload)?;
+            Ok(Request::UserDataRead(message))
+        }
+        Ok(Command::UserDataWrite) => {
+            let message = parse_payload::<UserDataWriteReq>(payload)?;
+            Ok(Request::UserDataWrite(message))
+        }
+        Ok(Command::ProtocolVersionGet) => Ok(Request::ProtocolVersionGet(&ProtocolVersionGetReq)),
+        Ok(Command::OutputSetMasked) => {
+            let message = parse_payload::<OutputSetMaskedReq>(payload)?;
+            Ok(Request::OutputSetMasked(message))
+        }
+        Ok(Command::InputGetEvents) => {
+            let message = parse_payload::<InputGetEventsReq>(payload)?;
+            Ok(Request::InputGetEvents(message))
+        }
+        Ok(Command::ReportConfigSet) => {
+            let message = parse_payload::<ReportConfigSetReq>(payload)?;
+            Ok(Request::ReportConfigSet(message))
+        }
+        Ok(Command::FlashEraseRegion) => {
+            let message = parse_payload::<FlashEraseRegionReq>(payload)?;
+            Ok(Request::FlashEraseRegion(message))
+        }
+        Ok(Command::FlashWriteChunk) => {
+            let message = parse_payload::<FlashWriteChunkReq>(payload)?;
+            Ok(Request::FlashWriteChunk(message))
+        }
+        Ok(Command::FlashVerify) => {
+            let message = parse_payload::<FlashVerifyReq>(payload)?;
+            Ok(Request::FlashVerify(message))
+        }
+        Ok(Command::BootSwap) => {
+            let message = parse_payload::<BootSwapReq>(payload)?;
+            Ok(Request::BootSwap(message))
+        }
+        Ok(Command::DeviceIdGet) => Ok(Request::DeviceIdGet(&DeviceIdGetReq)),
+        Ok(Command::FailsafeSet) => {
+            let message = parse_payload::<FailsafeSetReq>(payload)?;
+            Ok(Request::FailsafeSet(message))
+        }
+        Ok(Command::FailsafeGet) => Ok(Request::FailsafeGet(&FailsafeGetReq)),
+        Ok(Command::OutputSetDefaults) => {
+            let message = parse_payload::<OutputSetDefaultsReq>(payload)?;
+            Ok(Request::OutputSetDefaults(message))
+        }
+        Ok(Command::BaudratesGet) => Ok(Request::BaudratesGet(&BaudratesGetReq)),
+        Ok(Command::OutputGetDefaults) => Ok(Request::OutputGetDefaults(&OutputGetDefaultsReq)),
+    }
+}
+
+/// Parses the payload of a request, telling a payload of the wrong length from one with invalid
+/// values.
+fn parse_payload<T: TryFromBytes + KnownLayout + Immutable + ?Sized>(
+    payload: &[u8],
+) -> Result<&T, ErrorCode> {
+    T::try_ref_from_bytes(payload).map_err(|err| match err {
+        ConvertError::Validity(_) => ErrorCode::InvalidPayload,
+        ConvertError::Alignment(_) | ConvertError::Size(_) => ErrorCode::InvalidLength,
+    })
+}
+
+// The payloads of the current protocol version. Changing one of them without increasing
+// PROTOCOL_VERSION fails here, changing the version without revisiting them too.
+const _: () = {
+    assert!(PROTOCOL_VERSION == 2);
+    assert!(size_of::<Header>() == 9);
+    assert!(size_of::<Footer>() == 2);
+    assert!(size_of::<FragmentHeader>() == 8);
+    assert!(size_of::<InfoGetRes>() == 44);
+    assert!(size_of::<ConfigSetReq>() == 8);
+    assert!(size_of::<ConfigGetRes>() == 8);
+    assert!(size_of::<OutputSetReq>() == 48);
+    assert!(size_of::<OutputGetRes>() == 48);
+    assert!(size_of::<InputGetRes>() == 32);
+    assert!(size_of::<InputGetFullRes>() == 288);
+    assert!(size_of::<InputSetCalibrationsReq>() == 160);
+    assert!(size_of::<InputGetCalibrationsRes>() == 160);
+    assert!(size_of::<InputSetThresholdsReq>() == 160);
+    assert!(size_of::<InputGetThresholdsRes>() == 160);
+    assert!(size_of::<InputGetThresholdTimesRes>() == 264);
+    assert!(size_of::<InputGetThresholdStatesRes>() == 4);
+    assert!(size_of::<RebootReq>() == 4);
+    assert!(size_of::<DiagnosticsGetRes>() == 276);
+    assert!(size_of::<DigitalGetRes>() == 8);
+    assert!(size_of::<PowerSetReq>() == 4);
+    assert!(size_of::<PowerGetRes>() == 4);
+    assert!(size_of::<InputGetDebounceRes>() == 648);
+    assert!(size_of::<ForwardingSetReq>() == 16);
+    assert!(size_of::<ForwardingGetRes>() == 16);
+    assert!(size_of::<UserDataReadReq>() == 4);
+    assert!(size_of::<UserDataReadRes>() == 260);
+    assert!(size_of::<UserDataWriteReq>() == 260);
+    assert!(size_of::<UserDataWriteRes>() == 4);
+    assert!(size_of::<ProtocolVersionGetRes>() == 8);
+    assert!(size_of::<OutputSetMaskedReq>() == 36);
+    assert!(size_of::<InputGetEventsReq>() == 4);
+    assert!(size_of::<InputGetEventsRes>() == 524);
+    assert!(size_of::<ReportConfigSetReq>() == 4);
+    assert!(size_of::<FlashEraseRegionReq>() == 8);
+    assert!(size_of::<FlashWriteChunkReq>() == 260);
+    assert!(size_of::<FlashVerifyRes>() == 8);
+    assert!(size_of::<BootSwapReq>() == 8);
+    assert!(size_of::<DeviceIdGetRes>() == 12);
+    assert!(size_of::<FailsafeSetReq>() == 36);
+    assert!(size_of::<FailsafeGetRes>() == 36);
+    assert!(size_of::<OutputSetDefaultsReq>() == 48);
+    assert!(size_of::<BaudratesGetRes>() == 8);
+    assert!(size_of::<OutputGetDefaultsRes>() == 52);
+    assert!(size_of::<ErrorRes>() == 4);
+};
+
+/// A frame received by a [`Transport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Received {
+    /// The length of the frame including header and footer.
+    pub len: usize,
+    /// When the frame was received, in microseconds of the transport's clock.
+    pub at_us: u64,
+}
+
+/// Frames in and out of a bus with timestamps, implemented by the firmware's serial port and the
+/// tool's, so that logic on top of them like retrying, sniffing or bridging can be shared.
+pub trait Transport {
+    type Error;
+    /// The current time in microseconds of the clock used for [`Received::at_us`].
+    fn now_us(&self) -> u64;
+    /// Sends a complete frame, e.g. a [`Message`] as bytes.
+    fn send(&mut self, frame: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+    /// Sends a [`Message`].
+    fn send_message<T: IntoBytes + Unaligned + Immutable>(
+        &mut self,
+        message: &Message<T>,
+    ) -> impl Future<Output = Result<(), Self::Error>> {
+        self.send(message.as_bytes())
+    }
+    /// Waits for the next frame with a valid checksum, regardless of its address, and copies it
+    /// to the beginning of `buf`. Frames that don't fit into `buf` are skipped.
+    fn receive(&mut self, buf: &mut [u8]) -> impl Future<Output = Result<Received, Self::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_parsing() {
+        let payload = OutputSetReq::default();
+        let message = Message::new_request(0x1234, Command::OutputSet, 0, payload);
+        let bytes = message.as_bytes();
+        let checksum = CHECKSUM.checksum(&bytes[..bytes.len() - size_of::<Footer>()]);
+        assert_eq!(
+            Footer::try_ref_from_bytes(&bytes[bytes.len() - size_of::<Footer>()..])
                 .unwrap()
                 .checksum
                 .get(),
@@ -936,6 +3332,195 @@ mod tests {
         assert_eq!(*parsed_payload, payload);
     }
 
+    #[test]
+    fn test_wire_sizes() {
+        for value in 0..=u16::MAX {
+            assert_eq!(
+                Command::try_from(value).ok(),
+                Command::ALL.get(usize::from(value)).copied()
+            );
+        }
+        assert_eq!(max_request_size(Command::Check), 11);
+        assert_eq!(max_response_size(Command::InputGetFull), 299);
+        assert_eq!(MAX_REQUEST_SIZE, 271);
+        assert_eq!(MAX_RESPONSE_SIZE, 659);
+        assert_eq!(MAX_FRAME_SIZE, 1031);
+    }
+
+    #[test]
+    fn test_message_ref() {
+        let payload = OutputSetReq::default();
+        let message = Message::new_request(0x1234, Command::OutputSet, 0, payload);
+        let message_ref =
+            MessageRef::new_request(0x1234, Command::OutputSet, 0, payload.as_bytes()).unwrap();
+        let mut bytes = [0xFF; size_of::<Message<OutputSetReq>>() + 1];
+        assert_eq!(
+            message_ref.write_to(&mut bytes),
+            Some(message_ref.frame_len())
+        );
+        assert_eq!(&bytes[..message_ref.frame_len()], message.as_bytes());
+        assert_eq!(
+            message_ref.write_to(&mut bytes[..message_ref.frame_len() - 1]),
+            None
+        );
+
+        let message_ref = MessageRef::new(0x1234, 0xABCD, 0, &[1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(
+            message_ref.frame_len(),
+            size_of::<Header>() + 8 + size_of::<Footer>()
+        );
+        let len = message_ref.write_to(&mut bytes).unwrap();
+        let (maybe_frame, _) = next_frame(&bytes[..len]);
+        let frame = maybe_frame.expect("Failed to find frame");
+        assert!(frame.is_valid());
+        assert_eq!(frame.header.command.get(), 0xABCD);
+        assert_eq!(frame.payload, [1, 2, 3, 4, 5, 0, 0, 0]);
+
+        assert!(MessageRef::new(0, 0, 0, &[0; MAX_PAYLOAD_SIZE]).is_some());
+        assert!(MessageRef::new(0, 0, 0, &[0; MAX_PAYLOAD_SIZE + 1]).is_none());
+    }
+
+    #[test]
+    fn test_fragments() {
+        let payload: [u8; 10] = core::array::from_fn(|i| i as u8);
+        let fragments = Fragments::new(0x1234, 0x0042, 0, &payload, 4).unwrap();
+        assert_eq!(fragments.total(), 3);
+        let mut bytes = [0; 64];
+        let mut stream = [0; 3 * 24];
+        let mut len = 0;
+        for fragment in fragments.clone() {
+            assert_eq!(fragment.header().command.get(), 0x0042 | FRAGMENT_FLAG);
+            len += fragment.write_to(&mut stream[len..]).unwrap();
+        }
+        assert_eq!(len, fragments.wire_size());
+
+        let mut reassembler = Reassembler::new();
+        let mut rest = &stream[..len];
+        for (index, expected) in payload.chunks(4).enumerate() {
+            let (maybe_message, processed) = next_message(rest);
+            let (header, payload) = maybe_message.expect("Failed to find fragment");
+            let data = reassembler.push(header, payload).unwrap();
+            assert_eq!(data.address, 0x1234);
+            assert_eq!(data.command, 0x0042);
+            assert_eq!(data.size, 10);
+            assert_eq!(data.offset, index * 4);
+            assert_eq!(data.data, expected);
+            assert_eq!(data.complete, index == 2);
+            assert_eq!(reassembler.in_progress(), index < 2);
+            rest = &rest[processed..];
+        }
+        assert!(rest.is_empty());
+
+        // an empty payload is one fragment without data
+        let fragment = Fragments::new(0, 0, 0, &[], 4).unwrap().next().unwrap();
+        let len = fragment.write_to(&mut bytes).unwrap();
+        let (header, body) = next_message(&bytes[..len]).0.unwrap();
+        let data = reassembler.push(header, body).unwrap();
+        assert!(data.complete && data.data.is_empty());
+        let frame = next_frame(&bytes[..len]).0.unwrap();
+        assert_eq!(frame.fragment().unwrap().0.total.get(), 1);
+        assert_eq!(frame.response(), None);
+
+        assert!(Fragments::new(0, 0, 0, &payload, 0).is_none());
+        assert!(Fragments::new(0, 0, 0, &payload, 6).is_none());
+        assert!(Fragments::new(0, 0, 0, &payload, MAX_FRAGMENT_SIZE + 4).is_none());
+        assert!(Fragments::new(0, FRAGMENT_FLAG, 0, &payload, 4).is_none());
+    }
+
+    #[test]
+    fn test_reassembler_rejects_out_of_order() {
+        let payload = [0xAB; 12];
+        let mut frames = [[0; 24]; 3];
+        for (fragment, frame) in Fragments::new(7, 1, 0, &payload, 4)
+            .unwrap()
+            .zip(&mut frames)
+        {
+            fragment.write_to(frame).unwrap();
+        }
+        let push = |reassembler: &mut Reassembler, frame: &[u8]| {
+            let (header, payload) = next_message(frame).0.unwrap();
+            reassembler.push(header, payload).map(|data| data.offset)
+        };
+        let mut reassembler = Reassembler::new();
+        assert_eq!(
+            push(&mut reassembler, &frames[1]),
+            Err(FragmentError::OutOfOrder {
+                expected: 0,
+                index: 1
+            })
+        );
+        assert_eq!(push(&mut reassembler, &frames[0]), Ok(0));
+        assert_eq!(
+            push(&mut reassembler, &frames[2]),
+            Err(FragmentError::OutOfOrder {
+                expected: 1,
+                index: 2
+            })
+        );
+        assert!(!reassembler.in_progress());
+
+        // the first fragment starts over
+        assert_eq!(push(&mut reassembler, &frames[0]), Ok(0));
+        assert_eq!(push(&mut reassembler, &frames[0]), Ok(0));
+        assert_eq!(push(&mut reassembler, &frames[1]), Ok(4));
+
+        let message = Message::new_request(7, Command::Check, 0, CheckReq);
+        assert_eq!(
+            push(&mut reassembler, message.as_bytes()),
+            Err(FragmentError::NotAFragment)
+        );
+        // a fragment of another payload of the same length and command
+        let other = Fragments::new(8, 1, 0, &payload, 4)
+            .unwrap()
+            .nth(2)
+            .unwrap();
+        let mut frame = [0; 24];
+        other.write_to(&mut frame).unwrap();
+        assert_eq!(
+            push(&mut reassembler, &frame),
+            Err(FragmentError::OutOfOrder {
+                expected: 2,
+                index: 2
+            })
+        );
+
+        // the next fragment of a retransmission with another sequence number
+        assert_eq!(push(&mut reassembler, &frames[0]), Ok(0));
+        let retry = Fragments::new(7, 1, 1, &payload, 4)
+            .unwrap()
+            .nth(1)
+            .unwrap();
+        retry.write_to(&mut frame).unwrap();
+        assert_eq!(
+            push(&mut reassembler, &frame),
+            Err(FragmentError::OutOfOrder {
+                expected: 1,
+                index: 1
+            })
+        );
+        assert!(!reassembler.in_progress());
+    }
+
+    #[test]
+    fn test_next_frame_reports_invalid_checksum() {
+        let message = Message::new_request(0x1234, Command::Check, 0, ());
+        let mut bytes = [0u8; size_of::<Message<()>>() * 2 + 3];
+        bytes[..3].copy_from_slice(&[0x00, b'O', 0xFF]);
+        bytes[3..3 + size_of::<Message<()>>()].copy_from_slice(message.as_bytes());
+        bytes[3 + size_of::<Message<()>>()..].copy_from_slice(message.as_bytes());
+        bytes[3 + size_of::<Message<()>>() - 1] ^= 0xFF;
+
+        let (maybe_frame, processed) = next_frame(&bytes);
+        assert_eq!(processed, 3 + size_of::<Message<()>>());
+        let frame = maybe_frame.expect("Failed to find frame");
+        assert!(!frame.is_valid());
+        assert_eq!(frame.header.address.get(), 0x1234);
+
+        let (maybe_message, processed) = next_message(&bytes);
+        assert_eq!(processed, bytes.len());
+        assert!(maybe_message.is_some());
+    }
+
     #[test]
     fn test_master_next() {
         let payload = InfoGetRes {
@@ -944,8 +3529,10 @@ mod tests {
             firmware_version_minor: 0,
             firmware_version_patch: 2.into(),
             uptime: 123456.into(),
+            protocol_version: PROTOCOL_VERSION.into(),
+            config_schema_version: settings::SCHEMA_VERSION.into(),
         };
-        let message = Message::new_response(0x1234, Command::InfoGet, payload);
+        let message = Message::new_response(0x1234, Command::InfoGet, 0, payload);
         let bytes = message.as_bytes();
         let (maybe_request, processed) = master_next(bytes);
         assert_eq!(processed, bytes.len());
@@ -959,19 +3546,318 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_protocol_version() {
+        let payload = ProtocolVersionGetRes {
+            major: PROTOCOL_VERSION.into(),
+            minor: PROTOCOL_VERSION_MINOR.into(),
+            capabilities: CAPABILITY_REPEATER.into(),
+        };
+        assert!(payload.supports(CAPABILITY_REPEATER));
+        assert!(!payload.supports(CAPABILITY_REPEATER | CAPABILITY_DIGITAL_INPUTS));
+        assert!(payload.supports(0));
+        let message = Message::new_response(0x1234, Command::ProtocolVersionGet, 0, payload);
+        let (maybe_response, _) = master_next(message.as_bytes());
+        assert_eq!(
+            maybe_response,
+            Some((0x1234, Response::ProtocolVersionGet(&payload)))
+        );
+    }
+
+    #[test]
+    fn test_device_id() {
+        let payload = DeviceIdGetRes::new(0x0123_4567_89ab_cdef, 3);
+        assert_eq!(
+            &payload.as_bytes()[..8],
+            &0x0123_4567_89ab_cdef_u64.to_le_bytes()
+        );
+        let message = Message::new_response(0x1234, Command::DeviceIdGet, 0, payload);
+        let (maybe_response, _) = master_next(message.as_bytes());
+        assert_eq!(
+            maybe_response,
+            Some((0x1234, Response::DeviceIdGet(&payload)))
+        );
+    }
+
+    #[test]
+    fn test_config_validate() {
+        let config = Config {
+            address: 7.into(),
+            baudrate: 115_200.into(),
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        };
+        assert_eq!(config.validate(), Ok(()));
+        for address in [BROADCAST_ADDRESS, settings::UNCONFIGURED_ADDRESS] {
+            let config = Config {
+                address: address.into(),
+                ..config
+            };
+            assert_eq!(
+                config.validate(),
+                Err(InvalidConfig::ReservedAddress(address))
+            );
+        }
+        for baudrate in [0, 300, 10_000_000] {
+            let config = Config {
+                baudrate: baudrate.into(),
+                ..config
+            };
+            assert_eq!(
+                config.validate(),
+                Err(InvalidConfig::UnsupportedBaudrate(baudrate))
+            );
+        }
+    }
+
     #[test]
     fn test_slave_next() {
         let payload = OutputSetReq::default();
-        let message = Message::new_request(0x1234, Command::OutputSet, payload);
+        let message = Message::new_request(0x1234, Command::OutputSet, 0, payload);
         let bytes = message.as_bytes();
         let (maybe_request, processed) = slave_next(bytes, 0x1234);
         assert_eq!(processed, bytes.len());
         let request = maybe_request.expect("Failed to parse message");
         match request {
-            Request::OutputSet(cmd) => {
+            Ok(Request::OutputSet(cmd)) => {
                 assert_eq!(*cmd, payload);
             }
             _ => panic!("Unexpected request type"),
         }
     }
+
+    #[test]
+    fn test_output_set_masked() {
+        let payload = OutputSetMaskedReq::new([(3, 0x4000), (12, 0x8000)]);
+        assert_eq!(payload.mask.get(), 1 << 3 | 1 << 12);
+        assert_eq!(payload.duty_cycles[3].get(), 0x4000);
+        assert_eq!(payload.duty_cycles[12].get(), 0x8000);
+        let message = Message::new_request(0x1234, Command::OutputSetMasked, 0, payload);
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, Some(Ok(Request::OutputSetMasked(&payload))));
+        assert_eq!(
+            OutputSetMaskedReq::new([(3, 0x4000), (16, 0x8000), (usize::MAX, 1)]),
+            OutputSetMaskedReq::new([(3, 0x4000)])
+        );
+    }
+
+    #[test]
+    fn test_input_report() {
+        let payload = InputGetRes {
+            values: core::array::from_fn(|input| (input as i16).into()),
+        };
+        let message = Message::new_report(0x1234, Command::InputGet, 7, payload);
+        let bytes = message.as_bytes();
+        assert_eq!(
+            message.header.command.get(),
+            u16::from(Command::InputGet) | REPORT_FLAG
+        );
+        let (maybe_response, _) = master_next(bytes);
+        assert_eq!(
+            maybe_response,
+            Some((0x1234, Response::InputReport(&payload)))
+        );
+        assert_eq!(InputGetReq::get_response(maybe_response.unwrap().1), None);
+        // not a request, even to the device that sent it
+        assert_eq!(slave_next(bytes, 0x1234).0, None);
+
+        let message = Message::new_report(0x1234, Command::InputGetFull, 7, [0u8; 4]);
+        assert_eq!(master_next(message.as_bytes()).0, None);
+    }
+
+    #[test]
+    fn test_flash_write_chunk() {
+        let request = FlashWriteChunkReq::new(512, &[1, 2, 3]).unwrap();
+        assert_eq!(request.offset.get(), 512);
+        assert_eq!(request.data[..4], [1, 2, 3, 0xFF]);
+        assert!(request.data[3..].iter().all(|&byte| byte == 0xFF));
+        assert!(FlashWriteChunkReq::new(0, &[0; FLASH_CHUNK_SIZE]).is_some());
+        assert!(FlashWriteChunkReq::new(0, &[0; FLASH_CHUNK_SIZE + 1]).is_none());
+
+        let message = Message::new_request(0x1234, Command::FlashWriteChunk, 3, request);
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, Some(Ok(Request::FlashWriteChunk(&request))));
+        // the CRC-32 that zip and PNG use, so images can be checked with common tools
+        assert_eq!(IMAGE_CHECKSUM.checksum(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_input_get_events() {
+        let mut payload = InputGetEventsRes {
+            now: 5000.into(),
+            lost: 1.into(),
+            remaining: 0,
+            len: 2,
+            events: [InputEvent::EMPTY; INPUT_EVENTS_PER_RESPONSE],
+        };
+        payload.events[0] = InputEvent {
+            time: 1000.into(),
+            input: 7,
+            edge: InputEdge::High,
+            _reserved: [0; 6],
+        };
+        payload.events[1].time = 1200.into();
+        payload.events[1].input = 7;
+        assert_eq!(payload.events().len(), 2);
+        assert_eq!(payload.events()[0].edge, InputEdge::High);
+        let message = Message::new_response(0x1234, Command::InputGetEvents, 0, payload);
+        let (maybe_response, _) = master_next(message.as_bytes());
+        assert_eq!(
+            maybe_response,
+            Some((0x1234, Response::InputGetEvents(&payload)))
+        );
+
+        let mut bytes = [0u8; size_of::<Message<InputGetEventsRes>>()];
+        bytes.copy_from_slice(message.as_bytes());
+        // the edge of the first event
+        let offset = size_of::<Header>() + 12 + 9;
+        bytes[offset] = 2;
+        let footer = bytes.len() - size_of::<Footer>();
+        let checksum = CHECKSUM.checksum(&bytes[..footer]);
+        bytes[footer..].copy_from_slice(&checksum.to_le_bytes());
+        let (maybe_response, processed) = master_next(&bytes);
+        assert_eq!(processed, bytes.len());
+        assert_eq!(maybe_response, None);
+    }
+
+    #[test]
+    fn test_master_next_rejects_unknown_reset_cause() {
+        let payload = DiagnosticsGetRes {
+            reset_cause: ResetCause::BrownOut,
+            flags: 0,
+            _reserved: [0; 2],
+            brownouts: 2.into(),
+            conversion_errors: [3.into(), 0.into()],
+            overruns: 1.into(),
+            sample_intervals: [SampleInterval::EMPTY; 16],
+        };
+        let message = Message::new_response(0x1234, Command::DiagnosticsGet, 0, payload);
+        let (maybe_response, _) = master_next(message.as_bytes());
+        assert_eq!(
+            maybe_response,
+            Some((0x1234, Response::DiagnosticsGet(&payload)))
+        );
+
+        let mut bytes = [0u8; size_of::<Message<DiagnosticsGetRes>>()];
+        bytes.copy_from_slice(message.as_bytes());
+        let offset = size_of::<Header>();
+        bytes[offset] = 4;
+        let footer = bytes.len() - size_of::<Footer>();
+        let checksum = CHECKSUM.checksum(&bytes[..footer]);
+        bytes[footer..].copy_from_slice(&checksum.to_le_bytes());
+        let (maybe_response, processed) = master_next(&bytes);
+        assert_eq!(processed, bytes.len());
+        assert_eq!(maybe_response, None);
+    }
+
+    #[test]
+    fn test_slave_next_reboot_mode() {
+        let message = Message::new_request(0x1234, Command::Reboot, 0, ());
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(
+            maybe_request,
+            Some(Ok(Request::Reboot(&RebootReq::FIRMWARE)))
+        );
+
+        let payload = RebootReq::new(RebootMode::Bootloader);
+        let message = Message::new_request(0x1234, Command::Reboot, 0, payload);
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, Some(Ok(Request::Reboot(&payload))));
+    }
+
+    #[test]
+    fn test_slave_next_rejects_unknown_parity() {
+        let payload = ConfigSetReq(Config {
+            address: 12.into(),
+            baudrate: 19200.into(),
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+        });
+        let message = Message::new_request(0x1234, Command::ConfigSet, 0, payload);
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, Some(Ok(Request::ConfigSet(&payload))));
+
+        let mut bytes = [0u8; size_of::<Message<ConfigSetReq>>()];
+        bytes.copy_from_slice(message.as_bytes());
+        bytes[size_of::<Header>() + 6] = 3;
+        let footer = bytes.len() - size_of::<Footer>();
+        let checksum = CHECKSUM.checksum(&bytes[..footer]);
+        bytes[footer..].copy_from_slice(&checksum.to_le_bytes());
+        let (maybe_request, processed) = slave_next(&bytes, 0x1234);
+        assert_eq!(processed, bytes.len());
+        assert_eq!(
+            maybe_request,
+            Some(Err(Rejected {
+                command: Command::ConfigSet.into(),
+                sequence: 0,
+                code: ErrorCode::InvalidPayload,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_error_response() {
+        let message = Message::new_request(0x1234, Command::PowerSet, 42, ());
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        let rejected = Rejected {
+            command: Command::PowerSet.into(),
+            sequence: 42,
+            code: ErrorCode::InvalidLength,
+        };
+        assert_eq!(maybe_request, Some(Err(rejected)));
+
+        let message = Message::new_error(0x1234, rejected);
+        assert_eq!(message.header.sequence, 42);
+        let (maybe_response, processed) = master_next(message.as_bytes());
+        assert_eq!(processed, message.as_bytes().len());
+        assert_eq!(
+            maybe_response,
+            Some((
+                0x1234,
+                Response::Error(Command::PowerSet, &ErrorRes::new(ErrorCode::InvalidLength))
+            ))
+        );
+        // the device doesn't take its own error response for another request
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, None);
+
+        let message = MessageRef::new(0x1234, 0x1fff, 0, &[]).unwrap();
+        let mut bytes = [0; MAX_FRAME_SIZE];
+        let len = message.write_to(&mut bytes).unwrap();
+        let (maybe_request, _) = slave_next(&bytes[..len], 0x1234);
+        assert_eq!(
+            maybe_request,
+            Some(Err(Rejected {
+                command: 0x1fff,
+                sequence: 0,
+                code: ErrorCode::UnknownCommand,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let message = Message::new_request(
+            BROADCAST_ADDRESS,
+            Command::OutputSet,
+            0,
+            OutputSetReq::default(),
+        );
+        let (maybe_request, processed) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(processed, message.as_bytes().len());
+        assert!(matches!(maybe_request, Some(Ok(Request::OutputSet(_)))));
+        assert!(message.header.is_broadcast());
+
+        // requests that read something would be answered by all devices at once
+        let message = Message::new_request(BROADCAST_ADDRESS, Command::Check, 0, CheckReq);
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert_eq!(maybe_request, None);
+        assert!(!message.header.is_broadcast());
+
+        // rejected broadcasts must not be answered either, which `is_broadcast` tells
+        let message = Message::new_request(BROADCAST_ADDRESS, Command::PowerSet, 0, ());
+        let (maybe_request, _) = slave_next(message.as_bytes(), 0x1234);
+        assert!(matches!(maybe_request, Some(Err(_))));
+        assert!(message.header.is_broadcast());
+    }
 }
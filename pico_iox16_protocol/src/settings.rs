@@ -0,0 +1,376 @@
+//! The persisted settings of a device, in one model for the firmware's nonvolatile storage, the
+//! protocol payloads and the tool's dump files.
+//!
+//! The types are laid out as stored in flash, in native byte order and alignment, so the
+//! firmware stores them as they are. The wire types of the protocol convert from and to them,
+//! and with the `serde` feature they are the format of dump files.
+
+use core::ops::RangeInclusive;
+
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, TryFromBytes as _};
+
+use crate::{
+    InputCalibration, InputThreshold, OutputGetDefaultsRes, OutputGroup, OutputSetDefaultsReq,
+    Parity, StopBits,
+};
+
+/// Address of a fresh device, until it is provisioned.
+pub const UNCONFIGURED_ADDRESS: u16 = 0xFFFF;
+/// Baudrate of a fresh device, and the fallback for a stored one outside of [`BAUDRATES`].
+pub const DEFAULT_BAUDRATE: u32 = 1_000_000;
+/// Baudrates the boards can generate.
+pub const BAUDRATES: RangeInclusive<u32> = 1200..=3_000_000;
+/// Version of the layout the firmware stores the settings in, bumped whenever a stored field is
+/// added or changes meaning. The firmware migrates data of older layouts when it starts, and
+/// reports its layout in [`crate::InfoGetRes::config_schema_version`].
+pub const SCHEMA_VERSION: u16 = 5;
+
+// devices keep their settings in flash across firmware updates, so the layout must not change
+const _: () = {
+    assert!(size_of::<Config>() == 8);
+    assert!(size_of::<Calibration>() == 10);
+    assert!(size_of::<Threshold>() == 12);
+    assert!(size_of::<Settings>() == 360);
+    assert!(size_of::<OutputDefaults>() == 52);
+};
+
+/// All settings of a device that survive a reboot and can be changed over the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct Settings {
+    pub config: Config,
+    pub calibrations: [Calibration; 16],
+    pub thresholds: [Threshold; 16],
+}
+
+impl Settings {
+    /// The settings of a fresh device.
+    pub const DEFAULT: Self = Self {
+        config: Config::DEFAULT,
+        calibrations: [Calibration::DEFAULT; 16],
+        thresholds: [Threshold::DEFAULT; 16],
+    };
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The bus settings, see [`crate::Config`]. All of them take effect after a reboot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "ConfigFields", from = "ConfigFields")
+)]
+#[repr(C)]
+pub struct Config {
+    pub address: u16,
+    /// A [`Parity`], or erased flash of a device that predates it
+    pub parity: u8,
+    /// A [`StopBits`], or erased flash of a device that predates it
+    pub stop_bits: u8,
+    pub baudrate: u32,
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        address: UNCONFIGURED_ADDRESS,
+        parity: Parity::None as u8,
+        stop_bits: StopBits::One as u8,
+        baudrate: DEFAULT_BAUDRATE,
+    };
+
+    /// The baudrate to set up the port with, which is the stored one unless that can't work.
+    pub fn effective_baudrate(&self) -> u32 {
+        if BAUDRATES.contains(&self.baudrate) {
+            self.baudrate
+        } else {
+            DEFAULT_BAUDRATE
+        }
+    }
+
+    /// The stored parity, none unless set.
+    pub fn parity(&self) -> Parity {
+        Parity::try_read_from_bytes(&[self.parity]).unwrap_or(Parity::None)
+    }
+
+    /// The stored number of stop bits, one unless set.
+    pub fn stop_bits(&self) -> StopBits {
+        StopBits::try_read_from_bytes(&[self.stop_bits]).unwrap_or(StopBits::One)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<crate::Config> for Config {
+    fn from(value: crate::Config) -> Self {
+        Self {
+            address: value.address.into(),
+            parity: value.parity as u8,
+            stop_bits: value.stop_bits as u8,
+            baudrate: value.baudrate.into(),
+        }
+    }
+}
+
+impl From<Config> for crate::Config {
+    fn from(value: Config) -> Self {
+        Self {
+            address: value.address.into(),
+            baudrate: value.baudrate.into(),
+            parity: value.parity(),
+            stop_bits: value.stop_bits(),
+        }
+    }
+}
+
+/// [`Config`] as written to dump files, where parity and stop bits are optional as older dump
+/// files don't have them.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ConfigFields {
+    address: u16,
+    baudrate: u32,
+    #[serde(default)]
+    parity: Parity,
+    #[serde(default)]
+    stop_bits: StopBits,
+}
+
+#[cfg(feature = "serde")]
+impl From<Config> for ConfigFields {
+    fn from(value: Config) -> Self {
+        Self {
+            address: value.address,
+            baudrate: value.baudrate,
+            parity: value.parity(),
+            stop_bits: value.stop_bits(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ConfigFields> for Config {
+    fn from(value: ConfigFields) -> Self {
+        Self {
+            address: value.address,
+            parity: value.parity as u8,
+            stop_bits: value.stop_bits as u8,
+            baudrate: value.baudrate,
+        }
+    }
+}
+
+/// How a raw reading is turned into the value of an input, see [`InputCalibration`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct Calibration {
+    pub multiply: i16,
+    /// Treated as `1` if `0`.
+    pub divide: i16,
+    pub add: i16,
+    pub min: i16,
+    pub max: i16,
+}
+
+impl Calibration {
+    /// Passes the raw reading through unchanged.
+    pub const DEFAULT: Self = Self {
+        multiply: 1,
+        divide: 1,
+        add: 0,
+        min: i16::MIN,
+        max: i16::MAX,
+    };
+
+    /// Calibrates a raw reading.
+    pub fn apply(&self, raw: u16) -> i16 {
+        let divide = match self.divide {
+            0 => 1,
+            divide => i32::from(divide),
+        };
+        let value = i32::from(raw) * i32::from(self.multiply) / divide + i32::from(self.add);
+        value.clamp(i32::from(self.min), i32::from(self.max)) as i16
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<InputCalibration> for Calibration {
+    fn from(value: InputCalibration) -> Self {
+        Self {
+            multiply: value.multiply.into(),
+            divide: value.divide.into(),
+            add: value.add.into(),
+            min: value.min.into(),
+            max: value.max.into(),
+        }
+    }
+}
+
+impl From<Calibration> for InputCalibration {
+    fn from(value: Calibration) -> Self {
+        Self {
+            multiply: value.multiply.into(),
+            divide: value.divide.into(),
+            add: value.add.into(),
+            min: value.min.into(),
+            max: value.max.into(),
+        }
+    }
+}
+
+/// When an input counts as above or below threshold, see [`InputThreshold`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct Threshold {
+    pub threshold_high: i16,
+    pub threshold_low: i16,
+    pub debounce_time_us: u32,
+    pub debounce_count: u16,
+    #[doc(hidden)]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub _padding: [u8; 2],
+}
+
+impl Threshold {
+    /// Never crosses, as no calibrated value is beyond the limits of `i16`.
+    pub const DEFAULT: Self = Self::new(i16::MAX, i16::MIN, 0, 0);
+
+    pub const fn new(
+        threshold_high: i16,
+        threshold_low: i16,
+        debounce_time_us: u32,
+        debounce_count: u16,
+    ) -> Self {
+        Self {
+            threshold_high,
+            threshold_low,
+            debounce_time_us,
+            debounce_count,
+            _padding: [0; 2],
+        }
+    }
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl From<InputThreshold> for Threshold {
+    fn from(value: InputThreshold) -> Self {
+        Self::new(
+            value.threshold_high.into(),
+            value.threshold_low.into(),
+            value.debounce_time_us.into(),
+            value.debounce_count.into(),
+        )
+    }
+}
+
+impl From<Threshold> for InputThreshold {
+    fn from(value: Threshold) -> Self {
+        Self {
+            threshold_high: value.threshold_high.into(),
+            threshold_low: value.threshold_low.into(),
+            debounce_time_us: value.debounce_time_us.into(),
+            debounce_count: value.debounce_count.into(),
+        }
+    }
+}
+
+/// The outputs a device sets at startup, see [`OutputSetDefaultsReq`]. They are stored apart
+/// from [`Settings`], so that its layout stays as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoBytes, FromBytes, Immutable, KnownLayout)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct OutputDefaults {
+    /// Duty cycles of channel A and B and the frequency of each group
+    pub groups: [[u16; 3]; 8],
+    /// 0 if never stored, leaving the outputs as the board starts them
+    pub stored: u32,
+}
+
+impl OutputDefaults {
+    /// Nothing stored, as on a fresh device.
+    pub const NONE: Self = Self {
+        groups: [[0; 3]; 8],
+        stored: 0,
+    };
+
+    /// The stored outputs, `None` to leave them as the board starts them.
+    pub fn groups(&self) -> Option<[OutputGroup; 8]> {
+        (self.stored != 0).then(|| {
+            self.groups.map(|[a, b, frequency]| OutputGroup {
+                duty_cycle: [a.into(), b.into()],
+                frequency: frequency.into(),
+            })
+        })
+    }
+}
+
+impl Default for OutputDefaults {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+impl From<[OutputGroup; 8]> for OutputDefaults {
+    fn from(value: [OutputGroup; 8]) -> Self {
+        Self {
+            groups: value.map(|group| {
+                let [a, b] = group.duty_cycle.map(|duty_cycle| duty_cycle.get());
+                [a, b, group.frequency.get()]
+            }),
+            stored: 1,
+        }
+    }
+}
+
+impl From<&OutputSetDefaultsReq> for OutputDefaults {
+    fn from(value: &OutputSetDefaultsReq) -> Self {
+        value.0.into()
+    }
+}
+
+impl From<&OutputGetDefaultsRes> for OutputDefaults {
+    fn from(value: &OutputGetDefaultsRes) -> Self {
+        value.defaults().map_or(Self::NONE, Into::into)
+    }
+}
+
+impl From<OutputDefaults> for OutputGetDefaultsRes {
+    fn from(value: OutputDefaults) -> Self {
+        Self {
+            stored: u8::from(value.stored != 0),
+            _reserved: [0; 3],
+            groups: value.groups().unwrap_or(
+                [OutputGroup {
+                    duty_cycle: [0.into(); 2],
+                    frequency: 0.into(),
+                }; 8],
+            ),
+        }
+    }
+}
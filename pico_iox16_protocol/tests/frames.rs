@@ -0,0 +1,194 @@
+//! Properties of the frame parser over arbitrary payloads and byte streams.
+
+use pico_iox16_protocol::{
+    CHECKSUM, Footer, Fragments, Header, MAGIC, MAX_FRAGMENT_SIZE, Reassembler, master_next,
+    next_frame, next_message, slave_next,
+};
+use proptest::prelude::*;
+use zerocopy::IntoBytes;
+
+/// A frame as it goes over the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    address: u16,
+    command: u16,
+    sequence: u8,
+    payload: Vec<u8>,
+}
+impl Frame {
+    fn to_bytes(&self) -> Vec<u8> {
+        let length = u8::try_from(self.payload.len() / 4).unwrap();
+        let header = Header {
+            magic: MAGIC,
+            length,
+            length_inverted: !length,
+            address: self.address.into(),
+            command: self.command.into(),
+            sequence: self.sequence,
+        };
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&self.payload);
+        let footer = Footer {
+            checksum: CHECKSUM.checksum(&bytes).into(),
+        };
+        bytes.extend_from_slice(footer.as_bytes());
+        bytes
+    }
+}
+
+fn frame() -> impl Strategy<Value = Frame> {
+    (any::<u16>(), any::<u16>(), any::<u8>(), 0..=16usize).prop_flat_map(
+        |(address, command, sequence, words)| {
+            proptest::collection::vec(any::<u8>(), words * 4).prop_map(move |payload| Frame {
+                address,
+                command,
+                sequence,
+                payload,
+            })
+        },
+    )
+}
+
+/// Noise on the bus, with plenty of header markers so that it looks like the start of frames.
+fn garbage() -> impl Strategy<Value = Vec<u8>> {
+    proptest::collection::vec(
+        prop_oneof![
+            3 => any::<u8>().prop_map(|byte| vec![byte]),
+            1 => any::<u8>().prop_map(|length| vec![MAGIC[0], MAGIC[1], length, !length]),
+        ],
+        0..16,
+    )
+    .prop_map(|chunks| chunks.concat())
+}
+
+/// Feeds `stream` in chunks of the given sizes to a receive buffer like the firmware's and the
+/// tool's, and returns the frames found.
+fn receive(stream: &[u8], chunk_sizes: &[usize]) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut buf = Vec::new();
+    let mut rest = stream;
+    let mut sizes = chunk_sizes.iter().cycle();
+    while !rest.is_empty() {
+        let size = (*sizes.next().unwrap()).min(rest.len());
+        buf.extend_from_slice(&rest[..size]);
+        rest = &rest[size..];
+        loop {
+            let (maybe_message, processed) = next_message(&buf);
+            assert!(processed <= buf.len());
+            let found = maybe_message.map(|(header, payload)| Frame {
+                address: header.address.get(),
+                command: header.command.get(),
+                sequence: header.sequence,
+                payload: payload.to_vec(),
+            });
+            buf.drain(..processed);
+            match found {
+                Some(frame) => frames.push(frame),
+                None => break,
+            }
+        }
+    }
+    frames
+}
+
+/// Enough idle bytes after a stream to complete any frame that a header marker in the garbage
+/// announced, so that the parser can tell it apart from a real one.
+fn idle() -> Vec<u8> {
+    vec![0; size_of::<Header>() + 255 * 4 + size_of::<Footer>()]
+}
+
+proptest! {
+    #[test]
+    fn valid_frames_round_trip(frame in frame()) {
+        let bytes = frame.to_bytes();
+        let (maybe_message, processed) = next_message(&bytes);
+        prop_assert_eq!(processed, bytes.len());
+        let (header, payload) = maybe_message.expect("no message found");
+        prop_assert_eq!(header.address.get(), frame.address);
+        prop_assert_eq!(header.command.get(), frame.command);
+        prop_assert_eq!(payload, &frame.payload[..]);
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected(frame in frame(), bit in any::<prop::sample::Index>()) {
+        let mut bytes = frame.to_bytes();
+        let bit = bit.index(bytes.len() * 8);
+        bytes[bit / 8] ^= 1 << (bit % 8);
+        // a CRC-16 catches every single bit error
+        prop_assert_eq!(receive(&[bytes, idle()].concat(), &[usize::MAX]), []);
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let (_, processed) = next_frame(&bytes);
+        prop_assert!(processed <= bytes.len());
+        let (_, processed) = next_message(&bytes);
+        prop_assert!(processed <= bytes.len());
+        let (_, processed) = master_next(&bytes);
+        prop_assert!(processed <= bytes.len());
+        let (_, processed) = slave_next(&bytes, 0x1234);
+        prop_assert!(processed <= bytes.len());
+    }
+
+    #[test]
+    fn frames_are_found_after_garbage(
+        frames in proptest::collection::vec((garbage(), frame()), 1..8),
+        chunk_sizes in proptest::collection::vec(1..64usize, 1..8),
+    ) {
+        let mut stream = Vec::new();
+        let mut noise = Vec::new();
+        for (garbage, frame) in &frames {
+            noise.extend(stream.len()..stream.len() + garbage.len());
+            stream.extend_from_slice(garbage);
+            stream.extend_from_slice(&frame.to_bytes());
+        }
+        stream.extend_from_slice(&idle());
+        // one in 65536 frames that the garbage announces has a valid checksum by chance, which
+        // no parser can tell from a real one
+        prop_assume!(noise.into_iter().all(|start| {
+            let (maybe_frame, processed) = next_frame(&stream[start..]);
+            maybe_frame.is_none_or(|frame| processed > frame.bytes.len() || !frame.is_valid())
+        }));
+        let expected: Vec<_> = frames.into_iter().map(|(_, frame)| frame).collect();
+        prop_assert_eq!(receive(&stream, &chunk_sizes), expected);
+    }
+
+    #[test]
+    fn fragments_reassemble(
+        payload in proptest::collection::vec(any::<u8>(), 0..4096),
+        words in 1..=MAX_FRAGMENT_SIZE / 4,
+        garbage in garbage(),
+        chunk_sizes in proptest::collection::vec(1..64usize, 1..8),
+    ) {
+        let mut stream = garbage;
+        let mut frame = [0; MAX_FRAGMENT_SIZE + 32];
+        for fragment in Fragments::new(0x1234, 0x0042, 7, &payload, words * 4).unwrap() {
+            let len = fragment.write_to(&mut frame).unwrap();
+            stream.extend_from_slice(&frame[..len]);
+        }
+        stream.extend_from_slice(&idle());
+        let mut reassembler = Reassembler::new();
+        let mut reassembled = Vec::new();
+        let mut complete = false;
+        for frame in receive(&stream, &chunk_sizes) {
+            let header = Header {
+                magic: MAGIC,
+                length: (frame.payload.len() / 4) as u8,
+                length_inverted: !((frame.payload.len() / 4) as u8),
+                address: frame.address.into(),
+                command: frame.command.into(),
+                sequence: frame.sequence,
+            };
+            // the garbage may announce a frame with a valid checksum by chance, see above
+            let Ok(data) = reassembler.push(&header, &frame.payload) else {
+                continue;
+            };
+            prop_assert_eq!(data.offset, reassembled.len());
+            reassembled.extend_from_slice(data.data);
+            prop_assert_eq!(data.size, payload.len());
+            complete = data.complete;
+        }
+        prop_assert!(complete);
+        prop_assert_eq!(reassembled, payload);
+    }
+}
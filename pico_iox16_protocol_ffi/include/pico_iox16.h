@@ -0,0 +1,603 @@
+/*
+ * C interface to the Pico I∴O×16 wire format, see `pico_iox16_protocol` for the
+ * authoritative definitions.
+ *
+ * All multi-byte fields on the wire are little-endian. The payload structs below are
+ * packed so that on little-endian hosts they can be copied from and to frame payloads
+ * directly.
+ */
+#ifndef PICO_IOX16_H
+#define PICO_IOX16_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PICO_IOX16_HEADER_SIZE 9
+#define PICO_IOX16_FOOTER_SIZE 2
+#define PICO_IOX16_MAX_PAYLOAD_SIZE 1020
+#define PICO_IOX16_MAX_FRAME_SIZE \
+    (PICO_IOX16_HEADER_SIZE + PICO_IOX16_MAX_PAYLOAD_SIZE + PICO_IOX16_FOOTER_SIZE)
+
+/* Version of the wire format this header describes, see pico_iox16_info.protocol_version. */
+#define PICO_IOX16_PROTOCOL_VERSION 2
+/* Minor version of the wire format, see pico_iox16_protocol_version.minor. */
+#define PICO_IOX16_PROTOCOL_VERSION_MINOR 10
+
+/* Address of devices that have not been configured yet. */
+#define PICO_IOX16_UNCONFIGURED_ADDRESS 0xFFFF
+
+/* Address that all devices accept OUTPUT_SET, INPUT_SET_THRESHOLDS, REBOOT, POWER_SET and
+ * OUTPUT_SET_MASKED requests at. None of them responds. */
+#define PICO_IOX16_BROADCAST_ADDRESS 0
+
+typedef enum pico_iox16_command {
+    PICO_IOX16_CHECK = 0,
+    PICO_IOX16_INFO_GET = 1,
+    PICO_IOX16_CONFIG_SET = 2,
+    PICO_IOX16_CONFIG_GET = 3,
+    PICO_IOX16_OUTPUT_SET = 4,
+    PICO_IOX16_OUTPUT_GET = 5,
+    PICO_IOX16_INPUT_GET = 6,
+    PICO_IOX16_INPUT_GET_FULL = 7,
+    PICO_IOX16_INPUT_SET_CALIBRATIONS = 8,
+    PICO_IOX16_INPUT_GET_CALIBRATIONS = 9,
+    PICO_IOX16_INPUT_SET_THRESHOLDS = 10,
+    PICO_IOX16_INPUT_GET_THRESHOLDS = 11,
+    PICO_IOX16_INPUT_GET_THRESHOLD_TIMES = 12,
+    PICO_IOX16_INPUT_GET_THRESHOLD_STATES = 13,
+    PICO_IOX16_REBOOT = 14,
+    PICO_IOX16_DIAGNOSTICS_GET = 15,
+    PICO_IOX16_DIGITAL_GET = 16,
+    PICO_IOX16_POWER_SET = 17,
+    PICO_IOX16_POWER_GET = 18,
+    PICO_IOX16_INPUT_GET_DEBOUNCE = 19,
+    PICO_IOX16_FORWARDING_SET = 20,
+    PICO_IOX16_FORWARDING_GET = 21,
+    PICO_IOX16_USER_DATA_READ = 22,
+    PICO_IOX16_USER_DATA_WRITE = 23,
+    PICO_IOX16_PROTOCOL_VERSION_GET = 24,
+    PICO_IOX16_OUTPUT_SET_MASKED = 25,
+    PICO_IOX16_INPUT_GET_EVENTS = 26,
+    PICO_IOX16_REPORT_CONFIG_SET = 27,
+    PICO_IOX16_FLASH_ERASE_REGION = 28,
+    PICO_IOX16_FLASH_WRITE_CHUNK = 29,
+    PICO_IOX16_FLASH_VERIFY = 30,
+    PICO_IOX16_BOOT_SWAP = 31,
+    PICO_IOX16_DEVICE_ID_GET = 32,
+    PICO_IOX16_FAILSAFE_SET = 33,
+    PICO_IOX16_FAILSAFE_GET = 34,
+    PICO_IOX16_OUTPUT_SET_DEFAULTS = 35,
+    PICO_IOX16_BAUDRATES_GET = 36,
+    PICO_IOX16_OUTPUT_GET_DEFAULTS = 37,
+} pico_iox16_command;
+
+/* Set in the command of a response if the device couldn't handle the request, whose payload is
+   then a pico_iox16_error instead of the command's response. */
+#define PICO_IOX16_ERROR_FLAG 0x4000
+
+/* Set in the command of a frame a device sent on its own after PICO_IOX16_REPORT_CONFIG_SET.
+   Its sequence counts the reports instead of echoing a request. */
+#define PICO_IOX16_REPORT_FLAG 0x2000
+
+#pragma pack(push, 1)
+
+/* Response payload of PICO_IOX16_INFO_GET. */
+typedef struct pico_iox16_info {
+    /* UTF-8 string filled with null bytes (not necessarily null-terminated) */
+    uint8_t info[32];
+    uint8_t firmware_version_major;
+    uint8_t firmware_version_minor;
+    uint16_t firmware_version_patch;
+    /* Uptime in seconds */
+    uint32_t uptime;
+    /* PICO_IOX16_PROTOCOL_VERSION of the device's firmware */
+    uint16_t protocol_version;
+    /* Layout version of the settings the device's firmware stores, 0 if it predates the field */
+    uint16_t config_schema_version;
+} pico_iox16_info;
+
+/* Values of pico_iox16_config.parity. */
+typedef enum pico_iox16_parity {
+    PICO_IOX16_PARITY_NONE = 0,
+    PICO_IOX16_PARITY_EVEN = 1,
+    PICO_IOX16_PARITY_ODD = 2,
+} pico_iox16_parity;
+
+/* Values of pico_iox16_config.stop_bits. */
+typedef enum pico_iox16_stop_bits {
+    PICO_IOX16_STOP_BITS_ONE = 0,
+    PICO_IOX16_STOP_BITS_TWO = 1,
+} pico_iox16_stop_bits;
+
+/* Payload of PICO_IOX16_CONFIG_SET and response payload of PICO_IOX16_CONFIG_GET. Devices
+   reject a reserved address or a baudrate outside of pico_iox16_baudrates with
+   PICO_IOX16_ERROR_INVALID_PAYLOAD. */
+typedef struct pico_iox16_config {
+    /* Effective only after reboot. */
+    uint16_t address;
+    /* Effective only after reboot. */
+    uint32_t baudrate;
+    /* One of pico_iox16_parity, effective only after reboot. */
+    uint8_t parity;
+    /* One of pico_iox16_stop_bits, effective only after reboot. */
+    uint8_t stop_bits;
+} pico_iox16_config;
+
+/* Two outputs sharing a PWM slice. */
+typedef struct pico_iox16_output_group {
+    /* Duty cycle scaled by 32768 (i.e. 50% = 16384, 100% = 32768) */
+    uint16_t duty_cycle[2];
+    /* Frequency in Hz */
+    uint16_t frequency;
+} pico_iox16_output_group;
+
+/* Payload of PICO_IOX16_OUTPUT_SET and PICO_IOX16_OUTPUT_SET_DEFAULTS and response payload of
+   PICO_IOX16_OUTPUT_GET. */
+typedef struct pico_iox16_outputs {
+    pico_iox16_output_group groups[8];
+} pico_iox16_outputs;
+
+/* Response payload of PICO_IOX16_OUTPUT_GET_DEFAULTS: the outputs stored with
+   PICO_IOX16_OUTPUT_SET_DEFAULTS. */
+typedef struct pico_iox16_output_defaults {
+    /* 1 if defaults were stored, 0 if the device starts the outputs as the board does */
+    uint8_t stored;
+    uint8_t _reserved[3];
+    pico_iox16_output_group groups[8];
+} pico_iox16_output_defaults;
+
+/* Payload of PICO_IOX16_OUTPUT_SET_MASKED: bit n of mask selects output n, whose duty cycle is
+   then set to duty_cycles[n]. The other outputs and the frequencies stay as they are. */
+typedef struct pico_iox16_outputs_masked {
+    uint16_t mask;
+    uint8_t reserved[2];
+    uint16_t duty_cycles[16];
+} pico_iox16_outputs_masked;
+
+/* Response payload of PICO_IOX16_INPUT_GET. */
+typedef struct pico_iox16_inputs {
+    /* Calibrated values averaged over the reads since the previous request. */
+    int16_t values[16];
+} pico_iox16_inputs;
+
+typedef struct pico_iox16_input_stat {
+    int32_t sum;
+    uint64_t sum_squares;
+    int16_t min;
+    int16_t max;
+    uint16_t count;
+} pico_iox16_input_stat;
+
+/* Response payload of PICO_IOX16_INPUT_GET_FULL. */
+typedef struct pico_iox16_input_stats {
+    pico_iox16_input_stat stats[16];
+} pico_iox16_input_stats;
+
+/* value = clamp(raw * multiply / divide + add, min, max) */
+typedef struct pico_iox16_input_calibration {
+    int16_t multiply;
+    int16_t divide;
+    int16_t add;
+    int16_t min;
+    int16_t max;
+} pico_iox16_input_calibration;
+
+/* Payload of PICO_IOX16_INPUT_SET_CALIBRATIONS and response payload of
+ * PICO_IOX16_INPUT_GET_CALIBRATIONS. */
+typedef struct pico_iox16_input_calibrations {
+    pico_iox16_input_calibration calibrations[16];
+} pico_iox16_input_calibrations;
+
+typedef struct pico_iox16_input_threshold {
+    int16_t threshold_high;
+    int16_t threshold_low;
+    uint32_t debounce_time_us;
+    uint16_t debounce_count;
+} pico_iox16_input_threshold;
+
+/* Payload of PICO_IOX16_INPUT_SET_THRESHOLDS and response payload of
+ * PICO_IOX16_INPUT_GET_THRESHOLDS. */
+typedef struct pico_iox16_input_thresholds {
+    pico_iox16_input_threshold thresholds[16];
+} pico_iox16_input_thresholds;
+
+typedef struct pico_iox16_input_threshold_times {
+    /* Microseconds since boot, 0 if no crossing has been recorded. */
+    uint64_t last_low;
+    uint64_t last_high;
+} pico_iox16_input_threshold_times;
+
+/* Response payload of PICO_IOX16_INPUT_GET_THRESHOLD_TIMES. */
+typedef struct pico_iox16_threshold_times {
+    /* Microseconds since boot */
+    uint64_t now;
+    pico_iox16_input_threshold_times inputs[16];
+} pico_iox16_threshold_times;
+
+/* Response payload of PICO_IOX16_INPUT_GET_THRESHOLD_STATES. */
+typedef struct pico_iox16_threshold_states {
+    /* Bitmask of the inputs above their high threshold */
+    uint16_t above;
+    /* Bitmask of the inputs below their low threshold */
+    uint16_t below;
+} pico_iox16_threshold_states;
+
+/* Values of pico_iox16_reboot.mode. */
+typedef enum pico_iox16_reboot_mode {
+    PICO_IOX16_REBOOT_FIRMWARE = 0,
+    PICO_IOX16_REBOOT_BOOTLOADER = 1,
+} pico_iox16_reboot_mode;
+
+/* Payload of PICO_IOX16_REBOOT. An empty payload reboots into the firmware. */
+typedef struct pico_iox16_reboot {
+    /* One of pico_iox16_reboot_mode */
+    uint8_t mode;
+    uint8_t reserved[3];
+} pico_iox16_reboot;
+
+/* Values of pico_iox16_diagnostics.reset_cause. */
+typedef enum pico_iox16_reset_cause {
+    PICO_IOX16_RESET_OTHER = 0,
+    PICO_IOX16_RESET_POWER_ON = 1,
+    PICO_IOX16_RESET_WATCHDOG = 2,
+    PICO_IOX16_RESET_BROWN_OUT = 3,
+} pico_iox16_reset_cause;
+
+/* The settings in flash were unreadable, the device runs with the defaults until it is
+   provisioned again. */
+#define PICO_IOX16_DIAGNOSTICS_CONFIG_CORRUPTED 0x01
+
+/* Intervals in microseconds between the sweeps over an input since the previous
+   PICO_IOX16_DIAGNOSTICS_GET. min_us is UINT32_MAX and the others are 0 if count is 0. */
+typedef struct pico_iox16_sample_interval {
+    uint32_t min_us;
+    uint32_t max_us;
+    uint32_t mean_us;
+    uint32_t count;
+} pico_iox16_sample_interval;
+
+/* Response payload of PICO_IOX16_DIAGNOSTICS_GET. */
+typedef struct pico_iox16_diagnostics {
+    /* One of pico_iox16_reset_cause */
+    uint8_t reset_cause;
+    /* Bitwise or of PICO_IOX16_DIAGNOSTICS_* flags */
+    uint8_t flags;
+    uint8_t reserved[2];
+    /* Number of brown-outs, persists across reboots */
+    uint32_t brownouts;
+    /* Failed conversions of the left and right half of the board since boot */
+    uint32_t conversion_errors[2];
+    /* Times samples were lost since boot because the firmware fell behind */
+    uint32_t overruns;
+    pico_iox16_sample_interval sample_intervals[16];
+} pico_iox16_diagnostics;
+
+/* Response payload of PICO_IOX16_DIGITAL_GET. Bit n stands for GPIO n. */
+typedef struct pico_iox16_digital {
+    /* The GPIOs that are digital inputs */
+    uint32_t available;
+    /* Debounced levels, set if high */
+    uint32_t levels;
+} pico_iox16_digital;
+
+/* Payload of PICO_IOX16_POWER_SET and response payload of PICO_IOX16_POWER_GET. */
+typedef struct pico_iox16_power {
+    /* Time between the starts of two sweeps over the inputs in ms, 0 to sample all the time */
+    uint32_t sample_interval_ms;
+} pico_iox16_power;
+
+/* Flags of pico_iox16_input_debounce.pending */
+#define PICO_IOX16_DEBOUNCE_PENDING_HIGH 0x01
+#define PICO_IOX16_DEBOUNCE_PENDING_LOW 0x02
+
+/* Times are microseconds since boot. */
+typedef struct pico_iox16_input_debounce {
+    /* Last crossings, whether or not they passed the debounce */
+    uint64_t raw_high;
+    uint64_t raw_low;
+    /* Last crossings that passed the debounce */
+    uint64_t debounced_high;
+    uint64_t debounced_low;
+    /* Consecutive readings beyond the thresholds, 0 if the last one wasn't */
+    uint16_t above_count;
+    uint16_t below_count;
+    /* Bitwise or of PICO_IOX16_DEBOUNCE_* flags */
+    uint8_t pending;
+    uint8_t reserved[3];
+} pico_iox16_input_debounce;
+
+/* Response payload of PICO_IOX16_INPUT_GET_DEBOUNCE. */
+typedef struct pico_iox16_debounce {
+    /* Microseconds since boot */
+    uint64_t now;
+    pico_iox16_input_debounce inputs[16];
+} pico_iox16_debounce;
+
+/* The most events in one pico_iox16_events. */
+#define PICO_IOX16_INPUT_EVENTS_PER_RESPONSE 32
+
+/* Values of pico_iox16_input_event.edge. */
+typedef enum pico_iox16_input_edge {
+    PICO_IOX16_EDGE_LOW = 0,
+    PICO_IOX16_EDGE_HIGH = 1,
+} pico_iox16_input_edge;
+
+/* A threshold crossing that passed the debounce. */
+typedef struct pico_iox16_input_event {
+    /* Microseconds since boot */
+    uint64_t time;
+    uint8_t input;
+    /* One of pico_iox16_input_edge */
+    uint8_t edge;
+    uint8_t reserved[6];
+} pico_iox16_input_event;
+
+/* Payload of PICO_IOX16_INPUT_GET_EVENTS. */
+typedef struct pico_iox16_events_request {
+    /* The most events to take, up to PICO_IOX16_INPUT_EVENTS_PER_RESPONSE */
+    uint8_t max;
+    uint8_t reserved[3];
+} pico_iox16_events_request;
+
+/* Response payload of PICO_IOX16_INPUT_GET_EVENTS: the oldest events of the device's queue. */
+typedef struct pico_iox16_events {
+    /* Microseconds since boot */
+    uint64_t now;
+    /* Newest events dropped since the last request because the queue was full */
+    uint16_t lost;
+    /* Events left in the queue */
+    uint8_t remaining;
+    /* Events at the start of events, oldest first */
+    uint8_t len;
+    pico_iox16_input_event events[PICO_IOX16_INPUT_EVENTS_PER_RESPONSE];
+} pico_iox16_events;
+
+/* Payload of PICO_IOX16_REPORT_CONFIG_SET, which has an empty response. The device then sends
+   the response payload of PICO_IOX16_INPUT_GET with PICO_IOX16_REPORT_FLAG every interval_ms
+   until it is set to 0 or the device reboots. Only meant for links with a single device. */
+typedef struct pico_iox16_report_config {
+    uint32_t interval_ms;
+} pico_iox16_report_config;
+
+/* Sizes of a flash sector, the unit of PICO_IOX16_FLASH_ERASE_REGION, and of the chunks of
+   PICO_IOX16_FLASH_WRITE_CHUNK. */
+#define PICO_IOX16_FLASH_SECTOR_SIZE 4096
+#define PICO_IOX16_FLASH_CHUNK_SIZE 256
+
+/* Payload of PICO_IOX16_FLASH_ERASE_REGION, which has an empty response: the region of the
+   staging area to erase, both multiples of PICO_IOX16_FLASH_SECTOR_SIZE. */
+typedef struct pico_iox16_flash_region {
+    uint32_t offset;
+    uint32_t len;
+} pico_iox16_flash_region;
+
+/* Payload of PICO_IOX16_FLASH_WRITE_CHUNK, which has an empty response. offset is a multiple of
+   PICO_IOX16_FLASH_CHUNK_SIZE, and a short last chunk is padded with 0xFF. */
+typedef struct pico_iox16_flash_chunk {
+    uint32_t offset;
+    uint8_t data[PICO_IOX16_FLASH_CHUNK_SIZE];
+} pico_iox16_flash_chunk;
+
+/* Response payload of PICO_IOX16_FLASH_VERIFY, whose payload is the uint32_t length of the
+   image at the start of the staging area. */
+typedef struct pico_iox16_flash_verify {
+    /* Size of the staging area, 0 if the device can't update itself */
+    uint32_t size;
+    /* CRC-32 (ISO-HDLC, as zip) of the image */
+    uint32_t checksum;
+} pico_iox16_flash_verify;
+
+/* Payload of PICO_IOX16_BOOT_SWAP, which has an empty response. Rejected with
+   PICO_IOX16_ERROR_INVALID_PAYLOAD unless the staged image of len bytes has the checksum. */
+typedef struct pico_iox16_boot_swap {
+    uint32_t len;
+    uint32_t checksum;
+} pico_iox16_boot_swap;
+
+/* Response payload of PICO_IOX16_DEVICE_ID_GET. */
+typedef struct pico_iox16_device_id {
+    /* Unique ID of the chip, as in the info string of pico_iox16_info, 0 if unknown */
+    uint64_t unique_id;
+    /* Revision of the board, 0 if unknown */
+    uint16_t hardware_revision;
+    uint8_t reserved[2];
+} pico_iox16_device_id;
+
+/* Payload of PICO_IOX16_FAILSAFE_SET and response payload of PICO_IOX16_FAILSAFE_GET: the duty
+   cycles the outputs are forced to once the device received no valid request for timeout_ms.
+   The frequencies stay. */
+typedef struct pico_iox16_failsafe {
+    /* 0 to never force the outputs */
+    uint32_t timeout_ms;
+    /* Scaled by 32768 like in pico_iox16_output_group */
+    uint16_t duty_cycles[16];
+} pico_iox16_failsafe;
+
+/* Response payload of PICO_IOX16_BAUDRATES_GET: the lowest and highest baudrate the device can
+   use. */
+typedef struct pico_iox16_baudrates {
+    uint32_t min;
+    uint32_t max;
+} pico_iox16_baudrates;
+
+/* Device addresses from first to last, both included. Empty if first is above last. */
+typedef struct pico_iox16_address_range {
+    uint16_t first;
+    uint16_t last;
+} pico_iox16_address_range;
+
+/* Payload of PICO_IOX16_FORWARDING_SET and response payload of PICO_IOX16_FORWARDING_GET:
+   the addresses a repeater passes on to its downstream port. */
+typedef struct pico_iox16_forwarding {
+    pico_iox16_address_range ranges[4];
+} pico_iox16_forwarding;
+
+/* Size of the user data in bytes. */
+#define PICO_IOX16_USER_DATA_SIZE 256
+
+/* Payload of PICO_IOX16_USER_DATA_READ and response payload of PICO_IOX16_USER_DATA_WRITE: the
+   part of the user data len bytes from offset. Devices leave out what lies beyond the user data
+   and answer with the part that remains. */
+typedef struct pico_iox16_user_data_span {
+    uint16_t offset;
+    uint16_t len;
+} pico_iox16_user_data_span;
+
+/* Payload of PICO_IOX16_USER_DATA_WRITE and response payload of PICO_IOX16_USER_DATA_READ: the
+   bytes at the start of data, the rest is ignored or zero. Bytes never written read as 0xFF. */
+typedef struct pico_iox16_user_data {
+    pico_iox16_user_data_span span;
+    uint8_t data[PICO_IOX16_USER_DATA_SIZE];
+} pico_iox16_user_data;
+
+/* Bits of pico_iox16_protocol_version.capabilities. */
+#define PICO_IOX16_CAPABILITY_REPEATER (1u << 0)
+#define PICO_IOX16_CAPABILITY_DIGITAL_INPUTS (1u << 1)
+
+/* Response payload of PICO_IOX16_PROTOCOL_VERSION_GET. Devices that answer it with
+   PICO_IOX16_ERROR_UNKNOWN_COMMAND speak minor version 0 of pico_iox16_info.protocol_version. */
+typedef struct pico_iox16_protocol_version {
+    /* PICO_IOX16_PROTOCOL_VERSION of the device's firmware */
+    uint16_t major;
+    /* PICO_IOX16_PROTOCOL_VERSION_MINOR of the device's firmware */
+    uint16_t minor;
+    /* PICO_IOX16_CAPABILITY_* bits */
+    uint32_t capabilities;
+} pico_iox16_protocol_version;
+
+/* Values of pico_iox16_error.code. */
+typedef enum pico_iox16_error_code {
+    PICO_IOX16_ERROR_UNKNOWN_COMMAND = 1,
+    PICO_IOX16_ERROR_INVALID_LENGTH = 2,
+    PICO_IOX16_ERROR_INVALID_PAYLOAD = 3,
+    PICO_IOX16_ERROR_STORAGE_FAILED = 4,
+} pico_iox16_error_code;
+
+/* Response payload of any command with PICO_IOX16_ERROR_FLAG set. */
+typedef struct pico_iox16_error {
+    /* One of pico_iox16_error_code */
+    uint8_t code;
+    uint8_t reserved[3];
+} pico_iox16_error;
+
+#pragma pack(pop)
+
+#if defined(__cplusplus)
+static_assert(sizeof(pico_iox16_info) == 44, "size mismatch");
+static_assert(sizeof(pico_iox16_config) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_outputs) == 48, "size mismatch");
+static_assert(sizeof(pico_iox16_output_defaults) == 52, "size mismatch");
+static_assert(sizeof(pico_iox16_outputs_masked) == 36, "size mismatch");
+static_assert(sizeof(pico_iox16_inputs) == 32, "size mismatch");
+static_assert(sizeof(pico_iox16_input_stats) == 288, "size mismatch");
+static_assert(sizeof(pico_iox16_input_calibrations) == 160, "size mismatch");
+static_assert(sizeof(pico_iox16_input_thresholds) == 160, "size mismatch");
+static_assert(sizeof(pico_iox16_threshold_times) == 264, "size mismatch");
+static_assert(sizeof(pico_iox16_threshold_states) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_reboot) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_diagnostics) == 276, "size mismatch");
+static_assert(sizeof(pico_iox16_digital) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_power) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_debounce) == 648, "size mismatch");
+static_assert(sizeof(pico_iox16_events_request) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_events) == 524, "size mismatch");
+static_assert(sizeof(pico_iox16_report_config) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_flash_region) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_flash_chunk) == 260, "size mismatch");
+static_assert(sizeof(pico_iox16_flash_verify) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_boot_swap) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_device_id) == 12, "size mismatch");
+static_assert(sizeof(pico_iox16_failsafe) == 36, "size mismatch");
+static_assert(sizeof(pico_iox16_baudrates) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_forwarding) == 16, "size mismatch");
+static_assert(sizeof(pico_iox16_user_data_span) == 4, "size mismatch");
+static_assert(sizeof(pico_iox16_user_data) == 260, "size mismatch");
+static_assert(sizeof(pico_iox16_protocol_version) == 8, "size mismatch");
+static_assert(sizeof(pico_iox16_error) == 4, "size mismatch");
+#elif defined(__STDC_VERSION__) && __STDC_VERSION__ >= 201112L
+_Static_assert(sizeof(pico_iox16_info) == 44, "size mismatch");
+_Static_assert(sizeof(pico_iox16_config) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_outputs) == 48, "size mismatch");
+_Static_assert(sizeof(pico_iox16_output_defaults) == 52, "size mismatch");
+_Static_assert(sizeof(pico_iox16_outputs_masked) == 36, "size mismatch");
+_Static_assert(sizeof(pico_iox16_inputs) == 32, "size mismatch");
+_Static_assert(sizeof(pico_iox16_input_stats) == 288, "size mismatch");
+_Static_assert(sizeof(pico_iox16_input_calibrations) == 160, "size mismatch");
+_Static_assert(sizeof(pico_iox16_input_thresholds) == 160, "size mismatch");
+_Static_assert(sizeof(pico_iox16_threshold_times) == 264, "size mismatch");
+_Static_assert(sizeof(pico_iox16_threshold_states) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_reboot) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_diagnostics) == 276, "size mismatch");
+_Static_assert(sizeof(pico_iox16_digital) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_power) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_debounce) == 648, "size mismatch");
+_Static_assert(sizeof(pico_iox16_events_request) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_events) == 524, "size mismatch");
+_Static_assert(sizeof(pico_iox16_report_config) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_flash_region) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_flash_chunk) == 260, "size mismatch");
+_Static_assert(sizeof(pico_iox16_flash_verify) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_boot_swap) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_device_id) == 12, "size mismatch");
+_Static_assert(sizeof(pico_iox16_failsafe) == 36, "size mismatch");
+_Static_assert(sizeof(pico_iox16_baudrates) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_forwarding) == 16, "size mismatch");
+_Static_assert(sizeof(pico_iox16_user_data_span) == 4, "size mismatch");
+_Static_assert(sizeof(pico_iox16_user_data) == 260, "size mismatch");
+_Static_assert(sizeof(pico_iox16_protocol_version) == 8, "size mismatch");
+_Static_assert(sizeof(pico_iox16_error) == 4, "size mismatch");
+#endif
+
+/* A frame found by pico_iox16_next_frame. `payload` points into the searched buffer. */
+typedef struct pico_iox16_frame {
+    uint16_t address;
+    uint16_t command;
+    /* Chosen by the master per request and echoed in the response. */
+    uint8_t sequence;
+    const uint8_t *payload;
+    size_t payload_len;
+    /* Whether the checksum matches the header and payload. */
+    bool valid;
+} pico_iox16_frame;
+
+/* Computes the CRC-16/Kermit checksum used in the frame footer. */
+uint16_t pico_iox16_checksum(const uint8_t *data, size_t len);
+
+/*
+ * Writes a frame with the given address, command, sequence number and payload to `out`.
+ * Responses echo the sequence number of their request.
+ * `payload_len` must be at most PICO_IOX16_MAX_PAYLOAD_SIZE, the payload is padded with
+ * zeros to a multiple of 4 bytes. Returns the length of the frame, or 0 if the payload is
+ * too long or `out_len` is too small. `out` may be NULL if `out_len` is 0.
+ */
+size_t pico_iox16_encode_frame(uint16_t address, uint16_t command, uint8_t sequence,
+                               const uint8_t *payload, size_t payload_len, uint8_t *out,
+                               size_t out_len);
+
+/*
+ * Searches for the next frame in `data`. Returns true and fills `frame` if a complete
+ * frame was found, regardless of its checksum. `processed` receives the number of bytes
+ * that can be discarded from the start of `data`, including the frame itself.
+ */
+bool pico_iox16_next_frame(const uint8_t *data, size_t len, pico_iox16_frame *frame,
+                           size_t *processed);
+
+/* Returns the request payload size of a command, or -1 for unknown commands. */
+int32_t pico_iox16_request_size(uint16_t command);
+
+/* Returns the response payload size of a command, or -1 for unknown commands. */
+int32_t pico_iox16_response_size(uint16_t command);
+
+/* Returns the time a device may take to respond to a command, or 0 for unknown commands. */
+uint32_t pico_iox16_timeout_us(uint16_t command);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PICO_IOX16_H */
@@ -0,0 +1,232 @@
+//! C interface to the protocol, declared in `include/pico_iox16.h`.
+
+use core::{ptr, slice};
+
+use pico_iox16_protocol::{
+    BROADCAST_ADDRESS, BaudratesGetReq, BaudratesGetRes, BootSwapReq, CAPABILITY_DIGITAL_INPUTS,
+    CAPABILITY_REPEATER, CHECKSUM, CheckReq, Command, ConfigGetReq, ConfigGetRes, ConfigSetReq,
+    DeviceIdGetReq, DeviceIdGetRes, DiagnosticsGetReq, DiagnosticsGetRes, DigitalGetReq,
+    DigitalGetRes, ERROR_FLAG, ErrorCode, ErrorRes, FLASH_CHUNK_SIZE, FLASH_SECTOR_SIZE,
+    FailsafeGetReq, FailsafeGetRes, FailsafeSetReq, FlashEraseRegionReq, FlashVerifyReq,
+    FlashVerifyRes, FlashWriteChunkReq, Footer, ForwardingGetReq, ForwardingGetRes,
+    ForwardingSetReq, Header, INPUT_EVENTS_PER_RESPONSE, InfoGetReq, InfoGetRes, InputEdge,
+    InputGetCalibrationsReq, InputGetDebounceReq, InputGetDebounceRes, InputGetEventsReq,
+    InputGetEventsRes, InputGetFullReq, InputGetFullRes, InputGetReq, InputGetRes,
+    InputGetThresholdStatesReq, InputGetThresholdStatesRes, InputGetThresholdTimesReq,
+    InputGetThresholdTimesRes, InputGetThresholdsReq, InputSetCalibrationsReq,
+    InputSetThresholdsReq, MAX_PAYLOAD_SIZE, MessageRef, OutputGetDefaultsReq,
+    OutputGetDefaultsRes, OutputGetReq, OutputSetDefaultsReq, OutputSetMaskedReq, OutputSetReq, PROTOCOL_VERSION, PROTOCOL_VERSION_MINOR, PowerGetReq,
+    PowerGetRes, PowerSetReq, ProtocolVersionGetReq, ProtocolVersionGetRes, REPORT_FLAG, RebootReq,
+    ReportConfigSetReq, RequestTrait, USER_DATA_SIZE, UserDataReadReq, UserDataReadRes,
+    UserDataWriteReq, UserDataWriteRes, next_frame,
+};
+
+// the header hardcodes these sizes, keep them in sync
+const _: () = {
+    assert!(size_of::<Header>() == 9);
+    assert!(size_of::<Footer>() == 2);
+    assert!(size_of::<InfoGetRes>() == 44);
+    assert!(PROTOCOL_VERSION == 2);
+    assert!(PROTOCOL_VERSION_MINOR == 10);
+    assert!(MAX_PAYLOAD_SIZE == 1020);
+    assert!(size_of::<ConfigGetRes>() == 8);
+    assert!(size_of::<OutputSetReq>() == 48);
+    assert!(size_of::<OutputSetDefaultsReq>() == 48);
+    assert!(size_of::<OutputSetMaskedReq>() == 36);
+    assert!(size_of::<InputGetRes>() == 32);
+    assert!(size_of::<InputGetFullRes>() == 288);
+    assert!(size_of::<InputSetCalibrationsReq>() == 160);
+    assert!(size_of::<InputSetThresholdsReq>() == 160);
+    assert!(size_of::<InputGetThresholdTimesRes>() == 264);
+    assert!(size_of::<InputGetThresholdStatesRes>() == 4);
+    assert!(size_of::<RebootReq>() == 4);
+    assert!(size_of::<DiagnosticsGetRes>() == 276);
+    assert!(size_of::<DigitalGetRes>() == 8);
+    assert!(size_of::<PowerSetReq>() == 4);
+    assert!(size_of::<PowerGetRes>() == 4);
+    assert!(size_of::<InputGetDebounceRes>() == 648);
+    assert!(INPUT_EVENTS_PER_RESPONSE == 32);
+    assert!(size_of::<InputGetEventsReq>() == 4);
+    assert!(size_of::<InputGetEventsRes>() == 524);
+    assert!(InputEdge::High as u8 == 1);
+    assert!(size_of::<ReportConfigSetReq>() == 4);
+    assert!(FLASH_SECTOR_SIZE == 4096);
+    assert!(FLASH_CHUNK_SIZE == 256);
+    assert!(size_of::<FlashEraseRegionReq>() == 8);
+    assert!(size_of::<FlashWriteChunkReq>() == 260);
+    assert!(size_of::<FlashVerifyReq>() == 4);
+    assert!(size_of::<FlashVerifyRes>() == 8);
+    assert!(size_of::<BootSwapReq>() == 8);
+    assert!(size_of::<DeviceIdGetRes>() == 12);
+    assert!(size_of::<FailsafeSetReq>() == 36);
+    assert!(size_of::<FailsafeGetRes>() == 36);
+    assert!(size_of::<BaudratesGetRes>() == 8);
+    assert!(size_of::<OutputGetDefaultsRes>() == 52);
+    assert!(size_of::<ForwardingSetReq>() == 16);
+    assert!(size_of::<ForwardingGetRes>() == 16);
+    assert!(USER_DATA_SIZE == 256);
+    assert!(size_of::<UserDataReadReq>() == 4);
+    assert!(size_of::<UserDataReadRes>() == 260);
+    assert!(size_of::<UserDataWriteReq>() == 260);
+    assert!(size_of::<UserDataWriteRes>() == 4);
+    assert!(size_of::<ProtocolVersionGetRes>() == 8);
+    assert!(CAPABILITY_REPEATER == 1 << 0);
+    assert!(CAPABILITY_DIGITAL_INPUTS == 1 << 1);
+    assert!(ERROR_FLAG == 0x4000);
+    assert!(REPORT_FLAG == 0x2000);
+    assert!(BROADCAST_ADDRESS == 0);
+    assert!(size_of::<ErrorRes>() == 4);
+    assert!(ErrorCode::StorageFailed as u8 == 4);
+};
+
+/// A frame found by [`pico_iox16_next_frame`].
+#[repr(C)]
+pub struct FfiFrame {
+    pub address: u16,
+    pub command: u16,
+    pub sequence: u8,
+    pub payload: *const u8,
+    pub payload_len: usize,
+    pub valid: bool,
+}
+
+/// Creates a slice from a pointer that may be null if `len` is 0.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Creates a mutable slice from a pointer that may be null if `len` is 0.
+unsafe fn bytes_mut<'a>(data: *mut u8, len: usize) -> &'a mut [u8] {
+    if len == 0 {
+        &mut []
+    } else {
+        unsafe { slice::from_raw_parts_mut(data, len) }
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico_iox16_checksum(data: *const u8, len: usize) -> u16 {
+    CHECKSUM.checksum(unsafe { bytes(data, len) })
+}
+
+/// # Safety
+///
+/// `payload` must point to `payload_len` readable bytes and `out` to `out_len` writable bytes.
+/// Either may be null if its length is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico_iox16_encode_frame(
+    address: u16,
+    command: u16,
+    sequence: u8,
+    payload: *const u8,
+    payload_len: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> usize {
+    let payload = unsafe { bytes(payload, payload_len) };
+    let Some(message) = MessageRef::new(address, command, sequence, payload) else {
+        return 0;
+    };
+    let out = unsafe { bytes_mut(out, out_len) };
+    message.write_to(out).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// `data` must point to `len` readable bytes. `frame` and `processed` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pico_iox16_next_frame(
+    data: *const u8,
+    len: usize,
+    frame: *mut FfiFrame,
+    processed: *mut usize,
+) -> bool {
+    let (maybe_frame, n) = next_frame(unsafe { bytes(data, len) });
+    unsafe { ptr::write(processed, n) };
+    let Some(found) = maybe_frame else {
+        return false;
+    };
+    unsafe {
+        ptr::write(
+            frame,
+            FfiFrame {
+                address: found.header.address.get(),
+                command: found.header.command.get(),
+                sequence: found.header.sequence,
+                payload: found.payload.as_ptr(),
+                payload_len: found.payload.len(),
+                valid: found.is_valid(),
+            },
+        )
+    };
+    true
+}
+
+/// Returns the payload sizes and the timeout of a command.
+fn command_info(command: u16) -> Option<(usize, usize, u32)> {
+    fn info<P: RequestTrait>() -> Option<(usize, usize, u32)> {
+        Some((size_of::<P>(), size_of::<P::Response>(), P::TIMEOUT_US))
+    }
+    match Command::try_from(command).ok()? {
+        Command::Check => info::<CheckReq>(),
+        Command::InfoGet => info::<InfoGetReq>(),
+        Command::ConfigSet => info::<ConfigSetReq>(),
+        Command::ConfigGet => info::<ConfigGetReq>(),
+        Command::OutputSet => info::<OutputSetReq>(),
+        Command::OutputGet => info::<OutputGetReq>(),
+        Command::InputGet => info::<InputGetReq>(),
+        Command::InputGetFull => info::<InputGetFullReq>(),
+        Command::InputSetCalibrations => info::<InputSetCalibrationsReq>(),
+        Command::InputGetCalibrations => info::<InputGetCalibrationsReq>(),
+        Command::InputSetThresholds => info::<InputSetThresholdsReq>(),
+        Command::InputGetThresholds => info::<InputGetThresholdsReq>(),
+        Command::InputGetThresholdTimes => info::<InputGetThresholdTimesReq>(),
+        Command::InputGetThresholdStates => info::<InputGetThresholdStatesReq>(),
+        Command::Reboot => info::<RebootReq>(),
+        Command::DiagnosticsGet => info::<DiagnosticsGetReq>(),
+        Command::DigitalGet => info::<DigitalGetReq>(),
+        Command::PowerSet => info::<PowerSetReq>(),
+        Command::PowerGet => info::<PowerGetReq>(),
+        Command::InputGetDebounce => info::<InputGetDebounceReq>(),
+        Command::ForwardingSet => info::<ForwardingSetReq>(),
+        Command::ForwardingGet => info::<ForwardingGetReq>(),
+        Command::UserDataRead => info::<UserDataReadReq>(),
+        Command::UserDataWrite => info::<UserDataWriteReq>(),
+        Command::ProtocolVersionGet => info::<ProtocolVersionGetReq>(),
+        Command::OutputSetMasked => info::<OutputSetMaskedReq>(),
+        Command::InputGetEvents => info::<InputGetEventsReq>(),
+        Command::ReportConfigSet => info::<ReportConfigSetReq>(),
+        Command::FlashEraseRegion => info::<FlashEraseRegionReq>(),
+        Command::FlashWriteChunk => info::<FlashWriteChunkReq>(),
+        Command::FlashVerify => info::<FlashVerifyReq>(),
+        Command::BootSwap => info::<BootSwapReq>(),
+        Command::DeviceIdGet => info::<DeviceIdGetReq>(),
+        Command::FailsafeSet => info::<FailsafeSetReq>(),
+        Command::FailsafeGet => info::<FailsafeGetReq>(),
+        Command::OutputSetDefaults => info::<OutputSetDefaultsReq>(),
+        Command::BaudratesGet => info::<BaudratesGetReq>(),
+        Command::OutputGetDefaults => info::<OutputGetDefaultsReq>(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pico_iox16_request_size(command: u16) -> i32 {
+    command_info(command).map_or(-1, |(size, _, _)| size as i32)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pico_iox16_response_size(command: u16) -> i32 {
+    command_info(command).map_or(-1, |(_, size, _)| size as i32)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pico_iox16_timeout_us(command: u16) -> u32 {
+    command_info(command).map_or(0, |(_, _, timeout)| timeout)
+}
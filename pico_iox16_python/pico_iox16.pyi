@@ -0,0 +1,18 @@
+from typing import Dict, Optional, Tuple
+
+COMMANDS: Dict[str, int]
+
+class Frame:
+    address: int
+    command: int
+    sequence: int
+    payload: bytes
+    valid: bool
+
+def encode_frame(address: int, command: int, payload: bytes, sequence: int = 0) -> bytes: ...
+def next_frame(data: bytes) -> Tuple[Optional[Frame], int]: ...
+def command_name(value: int) -> str: ...
+
+class Protocol:
+    def __init__(self, port: str, baudrate: int = 1000000) -> None: ...
+    async def send_request(self, address: int, command: int, payload: bytes = b"") -> bytes: ...
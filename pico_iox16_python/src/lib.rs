@@ -0,0 +1,256 @@
+//! Python bindings for the Pico I∴O×16 protocol and the serial client of `pico_iox16_tool`.
+
+use std::{sync::Arc, time::Duration};
+
+use pico_iox16_protocol::{
+    BaudratesGetReq, BootSwapReq, CheckReq, Command, ConfigGetReq, ConfigSetReq, DeviceIdGetReq,
+    DiagnosticsGetReq, DigitalGetReq, FailsafeGetReq, FailsafeSetReq, FlashEraseRegionReq,
+    FlashVerifyReq, FlashWriteChunkReq, ForwardingGetReq, ForwardingSetReq, InfoGetReq,
+    InputGetCalibrationsReq, InputGetDebounceReq, InputGetEventsReq, InputGetFullReq, InputGetReq,
+    InputGetThresholdStatesReq, InputGetThresholdTimesReq, InputGetThresholdsReq,
+    InputSetCalibrationsReq, InputSetThresholdsReq, MessageRef, OutputGetDefaultsReq, OutputGetReq,
+    OutputSetDefaultsReq, OutputSetMaskedReq, OutputSetReq, PowerGetReq, PowerSetReq,
+    ProtocolVersionGetReq, RebootReq, ReportConfigSetReq, RequestTrait, UserDataReadReq,
+    UserDataWriteReq,
+};
+use pyo3::{
+    exceptions::{PyIOError, PyTimeoutError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+use tokio::sync::Mutex;
+use tokio_serial::SerialPortBuilderExt as _;
+use zerocopy::IntoBytes as _;
+
+fn command(command: u16) -> PyResult<Command> {
+    Command::try_from(command)
+        .map_err(|_| PyValueError::new_err(format!("Unknown command {command}")))
+}
+
+/// A frame decoded by `next_frame`.
+#[pyclass(frozen, module = "pico_iox16")]
+struct Frame {
+    #[pyo3(get)]
+    address: u16,
+    #[pyo3(get)]
+    command: u16,
+    /// The sequence number of the request, echoed in its response.
+    #[pyo3(get)]
+    sequence: u8,
+    payload: Vec<u8>,
+    /// Whether the checksum of the frame is valid.
+    #[pyo3(get)]
+    valid: bool,
+}
+
+#[pymethods]
+impl Frame {
+    #[getter]
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Frame(address={}, command={}, sequence={}, payload={} bytes, valid={})",
+            self.address,
+            self.command,
+            self.sequence,
+            self.payload.len(),
+            if self.valid { "True" } else { "False" }
+        )
+    }
+}
+
+/// Encodes a frame with the given address, command, payload and sequence number.
+/// The payload is padded with zeros to a multiple of 4 bytes and must be at most 1020 bytes.
+/// The device answers a request with its sequence number.
+#[pyfunction]
+#[pyo3(signature = (address, command, payload, sequence = 0))]
+fn encode_frame<'py>(
+    py: Python<'py>,
+    address: u16,
+    command: u16,
+    payload: &[u8],
+    sequence: u8,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let message = MessageRef::new(address, command, sequence, payload)
+        .ok_or_else(|| PyValueError::new_err("Payload must be at most 1020 bytes"))?;
+    let mut bytes = vec![0; message.frame_len()];
+    message.write_to(&mut bytes);
+    Ok(PyBytes::new(py, &bytes))
+}
+
+/// Searches for the next frame in `data`. Returns the frame, or `None` if no complete frame
+/// was found, and the number of bytes processed. Frames with invalid checksums are returned
+/// too, see `Frame.valid`.
+#[pyfunction]
+fn next_frame(data: &[u8]) -> (Option<Frame>, usize) {
+    let (maybe_frame, processed) = pico_iox16_protocol::next_frame(data);
+    let frame = maybe_frame.map(|frame| Frame {
+        address: frame.header.address.get(),
+        command: frame.header.command.get(),
+        sequence: frame.header.sequence,
+        payload: frame.payload.to_vec(),
+        valid: frame.is_valid(),
+    });
+    (frame, processed)
+}
+
+/// Returns the name of a command.
+#[pyfunction]
+fn command_name(value: u16) -> PyResult<String> {
+    Ok(command(value)?.to_string())
+}
+
+/// Raises `TimeoutError` if the device did not respond properly, so scripts can retry, and
+/// `OSError` if the port failed.
+fn error(err: pico_iox16_tool::Error) -> PyErr {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        message += &format!(": {cause}");
+        source = cause.source();
+    }
+    if err.is_timeout() {
+        PyTimeoutError::new_err(message)
+    } else {
+        PyIOError::new_err(message)
+    }
+}
+
+/// Sends a request whose payload is given as bytes and returns the payload of the response.
+async fn send_request(
+    protocol: &mut pico_iox16_tool::Protocol,
+    address: u16,
+    command: Command,
+    payload: &[u8],
+) -> PyResult<Vec<u8>> {
+    async fn send<P: RequestTrait>(
+        protocol: &mut pico_iox16_tool::Protocol,
+        address: u16,
+        payload: &[u8],
+    ) -> PyResult<Vec<u8>> {
+        let request = P::try_read_from_bytes(payload)
+            .map_err(|_| PyValueError::new_err(format!("Invalid payload for {}", P::COMMAND)))?;
+        protocol
+            .send_request(address, request, |response| {
+                Ok(response.as_bytes().to_vec())
+            })
+            .await
+            .map_err(error)
+    }
+
+    match command {
+        Command::Check => send::<CheckReq>(protocol, address, payload).await,
+        Command::InfoGet => send::<InfoGetReq>(protocol, address, payload).await,
+        Command::ConfigSet => send::<ConfigSetReq>(protocol, address, payload).await,
+        Command::ConfigGet => send::<ConfigGetReq>(protocol, address, payload).await,
+        Command::OutputSet => send::<OutputSetReq>(protocol, address, payload).await,
+        Command::OutputGet => send::<OutputGetReq>(protocol, address, payload).await,
+        Command::InputGet => send::<InputGetReq>(protocol, address, payload).await,
+        Command::InputGetFull => send::<InputGetFullReq>(protocol, address, payload).await,
+        Command::InputSetCalibrations => {
+            send::<InputSetCalibrationsReq>(protocol, address, payload).await
+        }
+        Command::InputGetCalibrations => {
+            send::<InputGetCalibrationsReq>(protocol, address, payload).await
+        }
+        Command::InputSetThresholds => {
+            send::<InputSetThresholdsReq>(protocol, address, payload).await
+        }
+        Command::InputGetThresholds => {
+            send::<InputGetThresholdsReq>(protocol, address, payload).await
+        }
+        Command::InputGetThresholdTimes => {
+            send::<InputGetThresholdTimesReq>(protocol, address, payload).await
+        }
+        Command::InputGetThresholdStates => {
+            send::<InputGetThresholdStatesReq>(protocol, address, payload).await
+        }
+        Command::Reboot => send::<RebootReq>(protocol, address, payload).await,
+        Command::DiagnosticsGet => send::<DiagnosticsGetReq>(protocol, address, payload).await,
+        Command::DigitalGet => send::<DigitalGetReq>(protocol, address, payload).await,
+        Command::PowerSet => send::<PowerSetReq>(protocol, address, payload).await,
+        Command::PowerGet => send::<PowerGetReq>(protocol, address, payload).await,
+        Command::InputGetDebounce => send::<InputGetDebounceReq>(protocol, address, payload).await,
+        Command::ForwardingSet => send::<ForwardingSetReq>(protocol, address, payload).await,
+        Command::ForwardingGet => send::<ForwardingGetReq>(protocol, address, payload).await,
+        Command::UserDataRead => send::<UserDataReadReq>(protocol, address, payload).await,
+        Command::UserDataWrite => send::<UserDataWriteReq>(protocol, address, payload).await,
+        Command::ProtocolVersionGet => {
+            send::<ProtocolVersionGetReq>(protocol, address, payload).await
+        }
+        Command::OutputSetMasked => send::<OutputSetMaskedReq>(protocol, address, payload).await,
+        Command::InputGetEvents => send::<InputGetEventsReq>(protocol, address, payload).await,
+        Command::ReportConfigSet => send::<ReportConfigSetReq>(protocol, address, payload).await,
+        Command::FlashEraseRegion => send::<FlashEraseRegionReq>(protocol, address, payload).await,
+        Command::FlashWriteChunk => send::<FlashWriteChunkReq>(protocol, address, payload).await,
+        Command::FlashVerify => send::<FlashVerifyReq>(protocol, address, payload).await,
+        Command::BootSwap => send::<BootSwapReq>(protocol, address, payload).await,
+        Command::DeviceIdGet => send::<DeviceIdGetReq>(protocol, address, payload).await,
+        Command::FailsafeSet => send::<FailsafeSetReq>(protocol, address, payload).await,
+        Command::FailsafeGet => send::<FailsafeGetReq>(protocol, address, payload).await,
+        Command::OutputSetDefaults => {
+            send::<OutputSetDefaultsReq>(protocol, address, payload).await
+        }
+        Command::BaudratesGet => send::<BaudratesGetReq>(protocol, address, payload).await,
+        Command::OutputGetDefaults => {
+            send::<OutputGetDefaultsReq>(protocol, address, payload).await
+        }
+    }
+}
+
+/// Asynchronous client talking to devices on a serial port.
+#[pyclass(frozen, module = "pico_iox16")]
+struct Protocol(Arc<Mutex<pico_iox16_tool::Protocol>>);
+
+#[pymethods]
+impl Protocol {
+    #[new]
+    #[pyo3(signature = (port, baudrate = 1_000_000))]
+    fn new(port: &str, baudrate: u32) -> PyResult<Self> {
+        // the serial port has to be registered with the runtime that later drives the requests
+        let _runtime = pyo3_async_runtimes::tokio::get_runtime().enter();
+        let device = tokio_serial::new(port, baudrate)
+            .timeout(Duration::from_micros(100))
+            .open_native_async()
+            .map_err(|err| PyIOError::new_err(format!("Opening serial port: {err}")))?;
+        Ok(Self(Arc::new(Mutex::new(pico_iox16_tool::Protocol::new(
+            device,
+        )))))
+    }
+
+    /// Sends a request and returns the payload of the response as bytes.
+    #[pyo3(signature = (address, command, payload = b"".to_vec()))]
+    fn send_request<'py>(
+        &self,
+        py: Python<'py>,
+        address: u16,
+        command: u16,
+        payload: Vec<u8>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let command = self::command(command)?;
+        let protocol = self.0.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let mut protocol = protocol.lock().await;
+            let response = send_request(&mut protocol, address, command, &payload).await?;
+            Ok(Python::attach(|py| PyBytes::new(py, &response).unbind()))
+        })
+    }
+}
+
+#[pymodule]
+fn pico_iox16(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Frame>()?;
+    m.add_class::<Protocol>()?;
+    m.add_function(wrap_pyfunction!(encode_frame, m)?)?;
+    m.add_function(wrap_pyfunction!(next_frame, m)?)?;
+    m.add_function(wrap_pyfunction!(command_name, m)?)?;
+    let commands = PyDict::new(m.py());
+    for command in Command::ALL {
+        commands.set_item(command.to_string(), u16::from(command))?;
+    }
+    m.add("COMMANDS", commands)?;
+    Ok(())
+}
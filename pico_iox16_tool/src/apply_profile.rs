@@ -0,0 +1,138 @@
+use anyhow::{Context as _, Result, bail};
+use pico_iox16_protocol::{
+    InputGetCalibrationsReq, InputGetCalibrationsRes, InputGetThresholdsReq, InputGetThresholdsRes,
+    InputSetCalibrationsReq, InputSetCalibrationsRes, InputSetThresholdsReq, InputSetThresholdsRes,
+    OutputGetDefaultsReq, OutputGetDefaultsRes, OutputGetReq, OutputGetRes, OutputGroup,
+    OutputSetDefaultsReq, OutputSetDefaultsRes, OutputSetReq, OutputSetRes,
+};
+use pico_iox16_tool::{Protocol, profile::Profile};
+
+/// Applies the calibration, threshold and output default of a stored profile to the given
+/// channels of a device, or to all channels if none are given. Settings that the profile
+/// leaves out are not changed.
+///
+/// The output is set right away and stored as the default the device starts with. The other
+/// outputs keep their stored defaults, or if there are none or the firmware can't tell them,
+/// their current state becomes their default like with `output-defaults`.
+pub(crate) async fn apply_profile(
+    device: &mut Protocol,
+    address: u16,
+    name: &str,
+    channels: &[usize],
+) -> Result<()> {
+    let profile = Profile::load(name)?;
+    let channels: Vec<usize> = if channels.is_empty() {
+        (0..16).collect()
+    } else {
+        channels.to_vec()
+    };
+    if let Some(&channel) = channels.iter().find(|&&channel| channel >= 16) {
+        bail!("Invalid channel {channel}");
+    }
+    if let Some(output) = &profile.output
+        && !(0.0..=100.0).contains(&output.duty_cycle)
+    {
+        bail!("Profile '{name}': duty cycle must be between 0 and 100 %");
+    }
+
+    if let Some(calibration) = &profile.calibration {
+        let mut calibrations = device
+            .send_request(
+                address,
+                InputGetCalibrationsReq,
+                |InputGetCalibrationsRes(calibrations)| Ok(*calibrations),
+            )
+            .await
+            .context("Retrieving current calibrations")?;
+        for &channel in &channels {
+            calibrations[channel] = (*calibration).into();
+        }
+        device
+            .send_request(
+                address,
+                InputSetCalibrationsReq(calibrations),
+                |InputSetCalibrationsRes| Ok(()),
+            )
+            .await
+            .context("Setting calibrations")?;
+    }
+    if let Some(threshold) = &profile.threshold {
+        let mut thresholds = device
+            .send_request(
+                address,
+                InputGetThresholdsReq,
+                |InputGetThresholdsRes(thresholds)| Ok(*thresholds),
+            )
+            .await
+            .context("Retrieving current thresholds")?;
+        for &channel in &channels {
+            thresholds[channel] = (*threshold).into();
+        }
+        device
+            .send_request(
+                address,
+                InputSetThresholdsReq(thresholds),
+                |InputSetThresholdsRes| Ok(()),
+            )
+            .await
+            .context("Setting thresholds")?;
+    }
+    if let Some(output) = &profile.output {
+        let current = device
+            .send_request(address, OutputGetReq, |OutputGetRes(groups)| Ok(*groups))
+            .await
+            .context("Retrieving current outputs")?;
+        let stored = device
+            .send_request(
+                address,
+                OutputGetDefaultsReq,
+                |response: &OutputGetDefaultsRes| Ok(response.defaults()),
+            )
+            .await;
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(err) if err.is_unsupported() => None,
+            Err(err) => return Err(err).context("Retrieving the output defaults"),
+        };
+        let duty_cycle = ((output.duty_cycle / 100.0 * 32768.0).round() as u16).min(0x8000);
+        let apply = |mut groups: [OutputGroup; 8]| {
+            for &channel in &channels {
+                let group = &mut groups[channel / 2];
+                group.duty_cycle[channel % 2] = duty_cycle.into();
+                group.frequency = output.frequency.into();
+            }
+            groups
+        };
+        device
+            .send_request(address, OutputSetReq(apply(current)), |OutputSetRes| Ok(()))
+            .await
+            .context("Setting outputs")?;
+        device
+            .send_request(
+                address,
+                OutputSetDefaultsReq(apply(stored.unwrap_or(current))),
+                |OutputSetDefaultsRes| Ok(()),
+            )
+            .await
+            .context("Storing the output defaults")?;
+    }
+
+    let applied: Vec<&str> = [
+        profile.calibration.map(|_| "calibration"),
+        profile.threshold.map(|_| "threshold"),
+        profile.output.map(|_| "output"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    println!(
+        "Applied profile '{name}' ({}) to {} channel(s) of device {address}",
+        if applied.is_empty() {
+            "empty".to_string()
+        } else {
+            applied.join(", ")
+        },
+        channels.len()
+    );
+    Ok(())
+}
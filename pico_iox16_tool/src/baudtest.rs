@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use anyhow::{Context as _, Result, bail};
+use pico_iox16_protocol::{
+    CheckReq, CheckRes, Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes,
+    InputGetFullReq, InputGetThresholdsReq, InputGetThresholdsRes, InputSetThresholdsReq,
+    InputSetThresholdsRes, InputThreshold, RebootReq, RebootRes,
+};
+use pico_iox16_tool::{Protocol, device};
+
+/// Baudrates tried if none are given on the command line.
+pub(crate) const DEFAULT_RATES: [u32; 8] = [
+    115_200, 230_400, 460_800, 921_600, 1_000_000, 1_500_000, 2_000_000, 3_000_000,
+];
+
+/// Number of attempts to get the original configuration back onto the device.
+const RESTORE_ATTEMPTS: usize = 10;
+
+/// Writes `config`, reboots the device and follows it with the host port.
+async fn switch(device: &mut Protocol, address: u16, config: Config) -> Result<()> {
+    device
+        .send_request(address, ConfigSetReq(config), |ConfigSetRes| Ok(()))
+        .await
+        .context("Setting configuration")?;
+    device
+        .send_request(address, RebootReq::FIRMWARE, |RebootRes| Ok(()))
+        .await
+        .context("Rebooting")?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Ok(device.set_baudrate(config.baudrate.get())?)
+}
+
+async fn check(device: &mut Protocol, address: u16) -> bool {
+    device
+        .send_request(address, CheckReq, |CheckRes| Ok(()))
+        .await
+        .is_ok()
+}
+
+/// Finds the device at one of the `tried` rates, most likely the last, or the original rate
+/// and writes the original configuration back. A failed switch leaves it unknown which rate
+/// the device is at.
+async fn restore(
+    device: &mut Protocol,
+    address: u16,
+    original: Config,
+    tried: &[u32],
+) -> Result<bool> {
+    let original_rate = original.baudrate.get();
+    for _ in 0..RESTORE_ATTEMPTS {
+        for &rate in tried.iter().rev().chain([&original_rate]) {
+            device.set_baudrate(rate)?;
+            if !check(device, address).await {
+                continue;
+            }
+            if rate != original_rate {
+                let _ = switch(device, address, original).await;
+                device.set_baudrate(original_rate)?;
+            }
+            if check(device, address).await {
+                return Ok(true);
+            }
+        }
+    }
+    device.set_baudrate(original_rate)?;
+    Ok(false)
+}
+
+/// Echoes the thresholds of the device `iterations` times and reads the full input state
+/// in between. Returns the number of failed or mismatching transfers.
+async fn pass(
+    device: &mut Protocol,
+    address: u16,
+    thresholds: [InputThreshold; 16],
+    iterations: usize,
+) -> u64 {
+    let mut errors = 0;
+    for _ in 0..iterations {
+        let echoed = async {
+            device
+                .send_request(
+                    address,
+                    InputSetThresholdsReq(thresholds),
+                    |InputSetThresholdsRes| Ok(()),
+                )
+                .await?;
+            device
+                .send_request(
+                    address,
+                    InputGetThresholdsReq,
+                    |InputGetThresholdsRes(echo)| Ok(*echo == thresholds),
+                )
+                .await
+        }
+        .await;
+        if !matches!(echoed, Ok(true)) {
+            errors += 1;
+        }
+        if device
+            .send_request(address, InputGetFullReq, |_| Ok(()))
+            .await
+            .is_err()
+        {
+            errors += 1;
+        }
+    }
+    errors
+}
+
+/// Steps the device and the host through `rates` in increasing order and runs an echo pass
+/// at each. Stops at the first rate with errors and restores the original configuration.
+pub(crate) async fn baudtest(
+    device: &mut Protocol,
+    address: u16,
+    mut rates: Vec<u32>,
+    iterations: usize,
+) -> Result<()> {
+    if iterations == 0 {
+        bail!("At least one iteration is required");
+    }
+    rates.sort();
+    rates.dedup();
+    let original = device
+        .send_request(address, ConfigGetReq, |ConfigGetRes(config)| Ok(*config))
+        .await
+        .context("Retrieving current configuration")?;
+    if let Err(err) = original.validate() {
+        bail!("Provision the device before testing baudrates, {err}");
+    }
+    let supported = device::baudrates(device, address).await?;
+    if let Some(rate) = rates.iter().find(|rate| !supported.contains(rate)) {
+        bail!(
+            "The device supports baudrates from {} to {} Hz, not {rate} Hz",
+            supported.start(),
+            supported.end()
+        );
+    }
+    let thresholds = device
+        .send_request(
+            address,
+            InputGetThresholdsReq,
+            |InputGetThresholdsRes(thresholds)| Ok(*thresholds),
+        )
+        .await
+        .context("Retrieving thresholds")?;
+    println!(
+        "Testing device {address}, currently at {} Hz, with {iterations} iterations per rate...",
+        original.baudrate
+    );
+    println!(
+        "{:>10} {:>8} {:>8} {:>10}",
+        "Baudrate", "errors", "crc", "result"
+    );
+
+    let mut reliable = None;
+    let mut tried = Vec::new();
+    for &rate in &rates {
+        tried.push(rate);
+        let config = Config {
+            baudrate: rate.into(),
+            ..original
+        };
+        if let Err(err) = switch(device, address, config).await {
+            println!("{rate:>10} switching failed: {err:#}");
+            break;
+        }
+        let before = device.statistics().checksum_errors;
+        let errors = if check(device, address).await {
+            pass(device, address, thresholds, iterations).await
+        } else {
+            iterations as u64 * 2
+        };
+        let checksum_errors = device.statistics().checksum_errors - before;
+        let ok = errors == 0 && checksum_errors == 0;
+        println!(
+            "{rate:>10} {errors:>8} {checksum_errors:>8} {:>10}",
+            if ok { "ok" } else { "unreliable" }
+        );
+        if !ok {
+            break;
+        }
+        reliable = Some(rate);
+    }
+
+    println!(
+        "Restoring original configuration ({} Hz)...",
+        original.baudrate
+    );
+    let restored = restore(device, address, original, &tried).await?;
+    match reliable {
+        Some(rate) => println!("Highest reliable baudrate: {rate} Hz"),
+        None => println!("No reliable baudrate found"),
+    }
+    if !restored {
+        bail!(
+            "Device {address} did not come back at {} Hz, it may still be configured for a test rate",
+            original.baudrate
+        );
+    }
+    Ok(())
+}
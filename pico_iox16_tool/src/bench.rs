@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{
+    CheckReq, ConfigGetReq, InfoGetReq, InputGetCalibrationsReq, InputGetFullReq, InputGetReq,
+    InputGetThresholdStatesReq, InputGetThresholdTimesReq, InputGetThresholdsReq, OutputGetReq,
+    RequestTrait,
+};
+use pico_iox16_tool::Protocol;
+
+/// Returns the value below which `fraction` of the sorted `values` fall.
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+/// Sends `request` `iterations` times and prints the round-trip time statistics.
+async fn measure<P: RequestTrait>(
+    device: &mut Protocol,
+    address: u16,
+    request: P,
+    iterations: usize,
+) {
+    let mut times = Vec::with_capacity(iterations);
+    let mut failed = 0;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        match device.send_request(address, request, |_| Ok(())).await {
+            Ok(()) => times.push(start.elapsed()),
+            Err(_) => failed += 1,
+        }
+    }
+    let command = P::COMMAND.to_string();
+    if times.is_empty() {
+        println!("{command:<24} {:>5} {failed:>5}", 0);
+        return;
+    }
+    times.sort();
+    let us = |d: Duration| format!("{} us", d.as_micros());
+    println!(
+        "{command:<24} {:>5} {failed:>5} {:>10} {:>10} {:>10}",
+        times.len(),
+        us(times[0]),
+        us(percentile(&times, 0.5)),
+        us(percentile(&times, 0.99)),
+    );
+}
+
+/// Measures the round-trip times of all read-only commands. Commands that change the
+/// state of the device are left out so that the benchmark can run on a live installation.
+pub(crate) async fn bench(device: &mut Protocol, address: u16, iterations: usize) -> Result<()> {
+    if iterations == 0 {
+        bail!("At least one iteration is required");
+    }
+    println!(
+        "Benchmarking device {address} at {} Hz with {iterations} iterations per command...",
+        device.baudrate()
+    );
+    println!(
+        "{:<24} {:>5} {:>5} {:>10} {:>10} {:>10}",
+        "Command", "ok", "fail", "min", "median", "p99"
+    );
+    measure(device, address, CheckReq, iterations).await;
+    measure(device, address, InfoGetReq, iterations).await;
+    measure(device, address, ConfigGetReq, iterations).await;
+    measure(device, address, OutputGetReq, iterations).await;
+    measure(device, address, InputGetReq, iterations).await;
+    measure(device, address, InputGetFullReq, iterations).await;
+    measure(device, address, InputGetCalibrationsReq, iterations).await;
+    measure(device, address, InputGetThresholdsReq, iterations).await;
+    measure(device, address, InputGetThresholdTimesReq, iterations).await;
+    measure(device, address, InputGetThresholdStatesReq, iterations).await;
+    Ok(())
+}
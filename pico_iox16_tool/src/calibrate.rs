@@ -1,6 +1,6 @@
 use anyhow::Result;
 use pico_iox16_tool::Protocol;
 
-pub(crate) async fn calibrate(device: &mut Protocol, address: u16) -> Result<()> {
+pub(crate) async fn calibrate(_device: &mut Protocol, _address: u16) -> Result<()> {
     Ok(())
 }
@@ -0,0 +1,88 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::{
+    InputCalibration, InputGetCalibrationsReq, InputGetCalibrationsRes, InputSetCalibrationsReq,
+    InputSetCalibrationsRes,
+};
+use pico_iox16_tool::{
+    Protocol,
+    fit::{Fit, fit, load_reference},
+};
+
+/// Fits a calibration for each channel in the reference file and prints the results with
+/// their residuals.
+pub(crate) fn fit_reference(reference: &Path, verbose: bool) -> Result<BTreeMap<usize, Fit>> {
+    let points = load_reference(reference)?;
+    let mut channels: BTreeMap<usize, Vec<(u16, i16)>> = BTreeMap::new();
+    for point in &points {
+        channels
+            .entry(point.channel)
+            .or_default()
+            .push((point.raw, point.expected));
+    }
+    println!(
+        "{:>7} {:>6} {:>8} {:>6} {:>6} {:>8} {:>8}",
+        "Channel", "points", "multiply", "divide", "add", "rms", "max"
+    );
+    let mut fits = BTreeMap::new();
+    for (channel, points) in channels {
+        let fit = fit(&points).with_context(|| format!("Channel {channel}"))?;
+        let calibration = fit.calibration;
+        println!(
+            "{channel:>7} {:>6} {:>8} {:>6} {:>6} {:>8.2} {:>8}",
+            points.len(),
+            calibration.multiply,
+            calibration.divide,
+            calibration.add,
+            fit.rms(),
+            fit.max_abs()
+        );
+        if verbose {
+            for ((raw, expected), residual) in points.iter().zip(&fit.residuals) {
+                println!("        raw={raw:>5} true={expected:>6} residual={residual:>+6}");
+            }
+        }
+        fits.insert(channel, fit);
+    }
+    Ok(fits)
+}
+
+/// Replaces multiply, divide and add of the fitted channels on the device. The limits and
+/// the other channels are left unchanged.
+pub(crate) async fn write(
+    device: &mut Protocol,
+    address: u16,
+    fits: &BTreeMap<usize, Fit>,
+) -> Result<()> {
+    let mut calibrations = device
+        .send_request(
+            address,
+            InputGetCalibrationsReq,
+            |InputGetCalibrationsRes(calibrations)| Ok(*calibrations),
+        )
+        .await
+        .context("Retrieving current calibrations")?;
+    for (&channel, fit) in fits {
+        let calibration = &mut calibrations[channel];
+        *calibration = InputCalibration {
+            multiply: fit.calibration.multiply.into(),
+            divide: fit.calibration.divide.into(),
+            add: fit.calibration.add.into(),
+            ..*calibration
+        };
+    }
+    device
+        .send_request(
+            address,
+            InputSetCalibrationsReq(calibrations),
+            |InputSetCalibrationsRes| Ok(()),
+        )
+        .await
+        .context("Setting calibrations")?;
+    println!(
+        "Calibrations of {} channel(s) written to device {address}",
+        fits.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs::File,
+    io::{BufRead as _, BufReader, BufWriter, Write as _},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Result, error::IoContext as _, trace::hex};
+
+/// Direction of captured bytes as seen from the tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes transmitted by the tool.
+    Tx,
+    /// Bytes received by the tool.
+    Rx,
+}
+
+/// A chunk of bytes transmitted or received at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Time since the start of the capture.
+    pub at: Duration,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+/// Records chunks to a capture file.
+///
+/// The file contains one chunk per line: the time since the start of the capture in
+/// microseconds, `TX` or `RX` and the bytes in hex, separated by spaces.
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .io_context(|| format!("Creating capture file {}", path.display()))?;
+        Ok(Self {
+            file: BufWriter::new(file),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, direction: Direction, bytes: &[u8]) -> Result<()> {
+        let direction = match direction {
+            Direction::Tx => "TX",
+            Direction::Rx => "RX",
+        };
+        writeln!(
+            self.file,
+            "{} {direction} {}",
+            self.start.elapsed().as_micros(),
+            hex(bytes)
+        )
+        // flush every chunk so nothing is lost if the session is interrupted
+        .and_then(|()| self.file.flush())
+        .io_context(|| "Writing capture file")
+    }
+}
+
+/// Reads all chunks from a capture file written by [`CaptureWriter`].
+pub fn read_capture(path: &Path) -> Result<Vec<Chunk>> {
+    let file =
+        File::open(path).io_context(|| format!("Opening capture file {}", path.display()))?;
+    let mut chunks = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.io_context(|| format!("Reading capture file {}", path.display()))?;
+        let context = || format!("{}:{}", path.display(), i + 1);
+        let mut fields = line.split_whitespace();
+        let Some(at) = fields.next() else {
+            continue;
+        };
+        let at = Duration::from_micros(at.parse().map_err(|err| Error::parse(context(), err))?);
+        let direction = match fields.next() {
+            Some("TX") => Direction::Tx,
+            Some("RX") => Direction::Rx,
+            _ => return Err(Error::Invalid(format!("{}: Expected TX or RX", context()))),
+        };
+        let bytes = fields
+            .map(|b| u8::from_str_radix(b, 16))
+            .collect::<Result<_, _>>()
+            .map_err(|err| Error::parse(context(), err))?;
+        chunks.push(Chunk {
+            at,
+            direction,
+            bytes,
+        });
+    }
+    Ok(chunks)
+}
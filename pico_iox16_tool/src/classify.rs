@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use pico_iox16_protocol::{ERROR_FLAG, Frame};
+
+/// Result of [`Classifier::classify`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// The frame is a request from a master.
+    Request,
+    /// The frame is a response from a slave. `latency` is the time since the matching
+    /// request or `None` if no matching request has been seen.
+    Response { latency: Option<Duration> },
+    /// The payload does not match the command for either direction.
+    Unknown,
+}
+
+/// Tells requests and responses apart on a bus observed from the outside.
+///
+/// Frames whose payload only parses as one of the two are classified accordingly. Frames
+/// that parse as both (e.g. `Check`) are considered a response if they match the address and
+/// command of the last unanswered request. Frames known to be requests, e.g. because the tool
+/// sent them itself, are passed to [`Classifier::request`] instead, and a request that timed
+/// out to [`Classifier::forget`], so that a later frame isn't taken for its response.
+#[derive(Debug, Default)]
+pub struct Classifier {
+    /// Address, command and time of the last unanswered request.
+    outstanding: Option<(u16, u16, Duration)>,
+}
+
+impl Classifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the last request has not been answered yet.
+    pub fn is_waiting(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// Records a frame with a valid checksum sent at time `at` that is known to be a request.
+    pub fn request(&mut self, frame: &Frame<'_>, at: Duration) {
+        self.outstanding = Some((frame.header.address.get(), frame.header.command.get(), at));
+    }
+
+    /// Gives up on the last request, e.g. once it timed out.
+    pub fn forget(&mut self) {
+        self.outstanding = None;
+    }
+
+    /// Classifies a frame with a valid checksum seen at time `at`.
+    pub fn classify(&mut self, frame: &Frame<'_>, at: Duration) -> Classification {
+        let address = frame.header.address.get();
+        let command = frame.header.command.get();
+        let is_request = frame.request().is_some();
+        let is_response = frame.response().is_some();
+        // error responses answer requests of the command without the flag
+        let answered = command & !ERROR_FLAG;
+        let answers_outstanding = self
+            .outstanding
+            .is_some_and(|(a, c, _)| a == address && c == answered);
+        if is_request && !(is_response && answers_outstanding) {
+            self.request(frame, at);
+            Classification::Request
+        } else if is_response {
+            let latency = match self.outstanding.take() {
+                Some((a, c, since)) if a == address && c == answered => Some(at - since),
+                _ => None,
+            };
+            Classification::Response { latency }
+        } else {
+            Classification::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pico_iox16_protocol::{CheckReq, Command, Message, next_frame};
+    use zerocopy::IntoBytes as _;
+
+    use super::*;
+
+    #[test]
+    fn check_frames() {
+        let check = Message::new_request(7, Command::Check, 1, CheckReq);
+        let check = next_frame(check.as_bytes()).0.unwrap();
+        let ms = Duration::from_millis;
+        let mut classifier = Classifier::new();
+        assert_eq!(classifier.classify(&check, ms(0)), Classification::Request);
+        assert_eq!(
+            classifier.classify(&check, ms(1)),
+            Classification::Response {
+                latency: Some(ms(1))
+            }
+        );
+        assert!(!classifier.is_waiting());
+
+        // a request that timed out isn't answered by the next one
+        classifier.request(&check, ms(2));
+        classifier.forget();
+        assert_eq!(classifier.classify(&check, ms(3)), Classification::Request);
+        assert!(classifier.is_waiting());
+    }
+}
@@ -0,0 +1,210 @@
+//! Conversion of device timestamps, e.g. threshold crossing times, to host wall-clock time.
+//!
+//! The device counts microseconds since boot with its own crystal, so relative to the host
+//! its clock has an offset and drifts by some ppm. [`DeviceClock`] fits both to the `now`
+//! samples of [`InputGetThresholdTimesRes`](pico_iox16_protocol::InputGetThresholdTimesRes)
+//! and reports how far off a converted time may be.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The drift assumed before it can be measured. The crystals of the boards are specified to
+/// 30 ppm, this leaves room for temperature and aging.
+const MAX_DRIFT_PPM: f64 = 100.0;
+
+/// One reading of the device clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSample {
+    /// Microseconds since boot of the device.
+    pub device_us: u64,
+    /// Host time before the request was sent.
+    pub sent: SystemTime,
+    /// Host time after the response was received. The device read its clock in between.
+    pub received: SystemTime,
+}
+
+/// A device timestamp converted to host wall-clock time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallClock {
+    pub time: SystemTime,
+    /// The true time is within `time ± error`, unless the host clock was stepped.
+    pub error: Duration,
+}
+
+/// A sample relative to the first sample of the current boot, in microseconds.
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    device: f64,
+    host: f64,
+    /// Half of the round trip, the device read its clock within `host ± uncertainty`.
+    uncertainty: f64,
+}
+
+/// `host = host_mean + rate * (device - device_mean)`, fitted by least squares.
+#[derive(Debug, Clone, Copy)]
+struct Fit {
+    device_mean: f64,
+    host_mean: f64,
+    rate: f64,
+    /// Uncertainty of `rate`.
+    rate_error: f64,
+    /// The largest deviation of a sample from the fit, including its uncertainty.
+    error: f64,
+    /// The range of device times that were sampled.
+    span: (f64, f64),
+}
+
+/// Models the offset and drift of a device clock from periodic [`ClockSample`]s. Only the
+/// latest samples are used, so the model follows slow changes of the drift and recovers
+/// from steps of the host clock.
+#[derive(Debug, Clone)]
+pub struct DeviceClock {
+    /// The first sample of the current boot of the device, which the points are relative to.
+    origin: Option<(u64, SystemTime)>,
+    points: VecDeque<Point>,
+    window: usize,
+    fit: Option<Fit>,
+}
+
+impl DeviceClock {
+    /// Creates a model that fits the latest `window` samples.
+    pub fn new(window: usize) -> Self {
+        Self {
+            origin: None,
+            points: VecDeque::new(),
+            window: window.max(1),
+            fit: None,
+        }
+    }
+
+    /// Adds a sample. If the device clock went backwards, the device rebooted and the model
+    /// starts over, which is reported by returning `false`.
+    pub fn add(&mut self, sample: ClockSample) -> bool {
+        let round_trip = sample
+            .received
+            .duration_since(sample.sent)
+            .unwrap_or_default();
+        let mid = sample.sent + round_trip / 2;
+        let rebooted = self
+            .points
+            .back()
+            .is_some_and(|last| self.relative_device(sample.device_us) < last.device);
+        if rebooted || self.origin.is_none() {
+            self.origin = Some((sample.device_us, mid));
+            self.points.clear();
+        }
+        let (_, host_origin) = self.origin.unwrap();
+        let host = match mid.duration_since(host_origin) {
+            Ok(after) => after.as_secs_f64() * 1e6,
+            Err(before) => -before.duration().as_secs_f64() * 1e6,
+        };
+        self.points.push_back(Point {
+            device: self.relative_device(sample.device_us),
+            host,
+            uncertainty: round_trip.as_secs_f64() * 1e6 / 2.0,
+        });
+        while self.points.len() > self.window {
+            self.points.pop_front();
+        }
+        self.fit = Some(fit(&self.points));
+        !rebooted
+    }
+
+    /// Forgets all samples, e.g. after the device was replaced.
+    pub fn reset(&mut self) {
+        self.origin = None;
+        self.points.clear();
+        self.fit = None;
+    }
+
+    /// The measured drift of the device clock relative to the host clock in ppm, positive if
+    /// the device clock is slow. `None` until the samples span enough time to measure it
+    /// better than [`MAX_DRIFT_PPM`].
+    pub fn drift_ppm(&self) -> Option<f64> {
+        let fit = self.fit?;
+        (fit.rate_error * 1e6 < MAX_DRIFT_PPM).then_some((fit.rate - 1.0) * 1e6)
+    }
+
+    /// Converts microseconds since boot of the device to host wall-clock time, or `None` if
+    /// there are no samples yet. Times long before or after the samples get larger errors,
+    /// as the drift is extrapolated.
+    pub fn to_wall_clock(&self, device_us: u64) -> Option<WallClock> {
+        let fit = self.fit?;
+        let (_, host_origin) = self.origin?;
+        let device = self.relative_device(device_us);
+        let host = fit.host_mean + fit.rate * (device - fit.device_mean);
+        let outside = (fit.span.0 - device).max(device - fit.span.1).max(0.0);
+        let error = fit.error + outside * fit.rate_error;
+        let offset = Duration::from_secs_f64(host.abs() / 1e6);
+        let time = if host < 0.0 {
+            host_origin.checked_sub(offset)?
+        } else {
+            host_origin.checked_add(offset)?
+        };
+        Some(WallClock {
+            time,
+            error: Duration::from_secs_f64(error / 1e6),
+        })
+    }
+
+    fn relative_device(&self, device_us: u64) -> f64 {
+        let (origin, _) = self.origin.unwrap_or((0, UNIX_EPOCH));
+        (device_us as i128 - origin as i128) as f64
+    }
+}
+
+impl Default for DeviceClock {
+    /// Fits the samples of about the last hour at the daemon's default poll interval.
+    fn default() -> Self {
+        Self::new(1 << 15)
+    }
+}
+
+fn fit(points: &VecDeque<Point>) -> Fit {
+    let n = points.len() as f64;
+    let device_mean = points.iter().map(|point| point.device).sum::<f64>() / n;
+    let host_mean = points.iter().map(|point| point.host).sum::<f64>() / n;
+    let spread: f64 = points
+        .iter()
+        .map(|point| (point.device - device_mean).powi(2))
+        .sum();
+    let span = points
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), point| {
+            (min.min(point.device), max.max(point.device))
+        });
+    // over short spans the round trips dominate, so keep the rate physically plausible
+    let max_drift = MAX_DRIFT_PPM * 1e-6;
+    let rate = if span.1 - span.0 > 0.0 {
+        let covariance: f64 = points
+            .iter()
+            .map(|point| (point.device - device_mean) * (point.host - host_mean))
+            .sum();
+        (covariance / spread).clamp(1.0 - max_drift, 1.0 + max_drift)
+    } else {
+        1.0
+    };
+    let error = points
+        .iter()
+        .map(|point| {
+            let residual = point.host - (host_mean + rate * (point.device - device_mean));
+            residual.abs() + point.uncertainty
+        })
+        .fold(0.0, f64::max);
+    // the steepest and flattest lines through the error bands at both ends of the samples
+    let rate_error = if span.1 - span.0 > 0.0 {
+        (2.0 * error / (span.1 - span.0)).min(2.0 * max_drift)
+    } else {
+        max_drift
+    };
+    Fit {
+        device_mean,
+        host_mean,
+        rate,
+        rate_error,
+        error,
+        span,
+    }
+}
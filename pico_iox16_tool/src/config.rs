@@ -0,0 +1,74 @@
+use std::{
+    io::{IsTerminal as _, stdout},
+    path::Path,
+};
+
+use anyhow::Result;
+use crossterm::style::{Color, Stylize as _};
+use pico_iox16_tool::{
+    Protocol,
+    device::Info,
+    dump::{self, Difference},
+};
+
+/// Warns if the device stores its settings in another layout than the tool knows, so that a
+/// dump file and the device may not cover the same settings.
+pub(crate) async fn warn_schema_mismatch(device: &mut Protocol, address: u16) -> Result<()> {
+    let info = Info::fetch(device, address).await?;
+    if let Some(mismatch) = info.config_schema_mismatch() {
+        eprintln!("Warning: device 0x{address:04X}: {mismatch}");
+    }
+    Ok(())
+}
+
+/// Saves the configuration, calibrations, thresholds and output defaults of a device to a TOML
+/// file.
+pub(crate) async fn dump(device: &mut Protocol, address: u16, file: &Path) -> Result<()> {
+    warn_schema_mismatch(device, address).await?;
+    println!("Retrieving settings...");
+    let dump = dump::fetch(device, address).await?;
+    dump::save(&dump, file)?;
+    println!("Settings saved to {}", file.display());
+    Ok(())
+}
+
+/// Compares the settings of a device with a dump file and prints the differing fields.
+/// Returns `true` if the device matches the file.
+pub(crate) async fn diff(device: &mut Protocol, address: u16, file: &Path) -> Result<bool> {
+    let expected = dump::read_dump(file)?;
+    warn_schema_mismatch(device, address).await?;
+    let actual = dump::fetch(device, address).await?;
+    let differences = dump::diff(&expected, &actual)?;
+    if differences.is_empty() {
+        println!("Device 0x{address:04X} matches {}", file.display());
+        return Ok(true);
+    }
+    print_differences(&file.display().to_string(), address, &differences);
+    Ok(false)
+}
+
+/// Prints the fields of a device that differ from the settings in `source` like a diff.
+pub(crate) fn print_differences(source: &str, address: u16, differences: &[Difference]) {
+    // no escape sequences when the output is captured, e.g. by a compliance check
+    let color = stdout().is_terminal();
+    let print = |line: String, c: Color| {
+        if color {
+            println!("{}", line.with(c));
+        } else {
+            println!("{line}");
+        }
+    };
+    print(format!("--- {source}"), Color::Red);
+    print(format!("+++ device 0x{address:04X}"), Color::Green);
+    for difference in differences {
+        print(format!("- {} = {}", difference.path, difference.expected), Color::Red);
+        match &difference.actual {
+            Some(actual) => print(format!("+ {} = {actual}", difference.path), Color::Green),
+            None => print(format!("+ {} missing", difference.path), Color::Green),
+        }
+    }
+    println!(
+        "{} field(s) of device 0x{address:04X} differ from {source}",
+        differences.len(),
+    );
+}
@@ -1,30 +1,58 @@
-use anyhow::Result;
-use pico_iox16_protocol::{Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes, RebootReq, RebootRes};
-use pico_iox16_tool::Protocol;
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{BROADCAST_ADDRESS, Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes, Parity, RebootReq, RebootRes, StopBits};
+use pico_iox16_tool::{Protocol, device};
+
+/// Parity bit of the characters on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ParityArg {
+    None,
+    Even,
+    Odd,
+}
+impl From<ParityArg> for Parity {
+    fn from(value: ParityArg) -> Self {
+        match value {
+            ParityArg::None => Parity::None,
+            ParityArg::Even => Parity::Even,
+            ParityArg::Odd => Parity::Odd,
+        }
+    }
+}
 
 pub(crate) async fn configure(
     device: &mut Protocol,
     address: u16,
     new_address: Option<u16>,
     new_baudrate: Option<u32>,
+    new_parity: Option<ParityArg>,
+    new_stop_bits: Option<u8>,
 ) -> Result<()> {
+    if new_address == Some(BROADCAST_ADDRESS) {
+        bail!("Address {BROADCAST_ADDRESS} is reserved for broadcasts");
+    }
     println!("Retrieving current configuration...");
     let old_config = device
         .send_request(address, ConfigGetReq, |ConfigGetRes(config)| Ok(*config))
         .await?;
-    println!(
-        "Current configuration: address={}, baudrate={} Hz",
-        old_config.address, old_config.baudrate
-    );
+    println!("Current configuration: {}", describe(&old_config));
     let config = Config {
         address: new_address.unwrap_or(old_config.address.into()).into(),
         baudrate: new_baudrate.unwrap_or(old_config.baudrate.into()).into(),
-        _reserved: [0; 2],
+        parity: new_parity.map_or(old_config.parity, Parity::from),
+        stop_bits: match new_stop_bits {
+            Some(1) => StopBits::One,
+            Some(_) => StopBits::Two,
+            None => old_config.stop_bits,
+        },
     };
-    println!(
-        "New configuration: address={}, baudrate={} Hz",
-        config.address, config.baudrate
-    );
+    println!("New configuration: {}", describe(&config));
+    if let Err(err) = config.validate() {
+        bail!("The device would reject the new configuration, {err}");
+    }
+    let baudrates = device::baudrates(device, address).await?;
+    if !baudrates.contains(&config.baudrate.get()) {
+        bail!("The device supports baudrates from {} to {} Hz", baudrates.start(), baudrates.end());
+    }
     println!("Sending new configuration...");
     device
         .send_request(
@@ -36,14 +64,21 @@ pub(crate) async fn configure(
         )
         .await?;
     println!("Rebooting device...");
-    device.send_request(address, RebootReq, |RebootRes| Ok(())).await?;
+    device.send_request(address, RebootReq::FIRMWARE, |RebootRes| Ok(())).await?;
     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     println!("Check after rebooting...");
     let new_config = device.send_request(config.address.into(), ConfigGetReq, |ConfigGetRes(config)| Ok(*config)).await?;
     if new_config == config {
         println!("Configuration successful!");
     } else {
-        println!("Configuration failed! Current configuration: address={}, baudrate={} Hz", new_config.address, new_config.baudrate);
+        println!("Configuration failed! Current configuration: {}", describe(&new_config));
     }
     Ok(())
 }
+
+fn describe(config: &Config) -> String {
+    format!(
+        "address={}, baudrate={} Hz, parity={}, stop bits={}",
+        config.address, config.baudrate, config.parity, config.stop_bits
+    )
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::Path,
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result, bail};
+use pico_iox16_tool::{
+    Protocol,
+    clock::{DeviceClock, WallClock},
+    events::{Crossing, Snapshot},
+    settings::Settings,
+};
+use serde::Deserialize;
+
+/// The configuration of the `daemon` command.
+///
+/// ```toml
+/// interval = 100
+/// syslog = true
+/// hook = ["/usr/local/bin/alarm", "--quiet"]
+/// devices = ["pump-controller", 12]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Time between polls in milliseconds.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Forward the events to the local syslog daemon or journald.
+    #[serde(default)]
+    syslog: bool,
+    /// Command and arguments executed for every event, with the details of the event in
+    /// `PICO_IOX16_*` environment variables.
+    #[serde(default)]
+    hook: Vec<String>,
+    /// The devices to poll, by alias or address.
+    devices: Vec<DeviceName>,
+}
+
+fn default_interval() -> u64 {
+    100
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum DeviceName {
+    Address(u16),
+    Alias(String),
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("Parsing {}", path.display()))?;
+        if config.devices.is_empty() {
+            bail!("{}: no devices configured", path.display());
+        }
+        Ok(config)
+    }
+}
+
+/// Something that happened to a polled device.
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    /// A threshold crossing, with its wall-clock time if known.
+    Crossing(Crossing, Option<WallClock>),
+    /// The device stopped responding.
+    Offline,
+    /// The device responds again.
+    Online,
+    /// The uptime of the device went backwards. Crossings during the reboot are lost.
+    Rebooted,
+    /// The queue of crossings on the device overflowed, and this many were dropped.
+    Lost(u32),
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Crossing(..) => "crossing",
+            Self::Offline => "offline",
+            Self::Online => "online",
+            Self::Rebooted => "rebooted",
+            Self::Lost(_) => "lost",
+        }
+    }
+
+    /// The syslog severity: warning for alarms, notice for everything else.
+    fn severity(&self) -> u8 {
+        match self {
+            Self::Crossing(..) | Self::Offline | Self::Lost(_) => 4,
+            Self::Online | Self::Rebooted => 5,
+        }
+    }
+}
+
+/// What is known about a polled device.
+enum Status {
+    /// The device has not been polled yet.
+    Unknown,
+    /// The device did not respond at the previous poll.
+    Offline,
+    /// The state of the device at the previous poll.
+    Online(Box<Snapshot>),
+}
+
+/// A polled device.
+struct Device {
+    address: u16,
+    label: Option<String>,
+    status: Status,
+    clock: DeviceClock,
+    /// Whether the device queues its crossings, until it turns out that it doesn't.
+    queued: bool,
+}
+
+impl Device {
+    fn name(&self) -> String {
+        match &self.label {
+            Some(label) => format!("device {} ({label})", self.address),
+            None => format!("device {}", self.address),
+        }
+    }
+
+    /// Polls the device and returns the events since the previous poll. Crossings are taken
+    /// from the queue of the device, or detected by their timestamps if its firmware has none,
+    /// so inputs that return to their previous state between two polls are reported as well.
+    /// The first poll only records the state, apart from reporting a device that does not
+    /// respond.
+    async fn poll(&mut self, protocol: &mut Protocol) -> Vec<Event> {
+        let snapshot = match Snapshot::fetch(protocol, self.address).await {
+            Ok(snapshot) => snapshot,
+            Err(_) => {
+                return match std::mem::replace(&mut self.status, Status::Offline) {
+                    Status::Offline => Vec::new(),
+                    Status::Unknown | Status::Online(_) => vec![Event::Offline],
+                };
+            }
+        };
+        self.clock.add(snapshot.clock_sample());
+        // taken at every poll, so that what is left after the device was offline is dropped
+        let queued = if self.queued {
+            match snapshot.take_queued(protocol, self.address).await {
+                Ok(queued) => Some(queued),
+                Err(err) => {
+                    self.queued = !err.is_unsupported();
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        match std::mem::replace(&mut self.status, Status::Online(Box::new(snapshot))) {
+            Status::Unknown => Vec::new(),
+            Status::Offline => vec![Event::Online],
+            Status::Online(previous) => match snapshot.crossings_since(&previous) {
+                Some(crossings) => {
+                    let (crossings, lost) = queued.unwrap_or((crossings, 0));
+                    let lost = (lost > 0).then_some(Event::Lost(lost));
+                    lost.into_iter()
+                        .chain(crossings.into_iter().map(|crossing| {
+                            Event::Crossing(crossing, self.clock.to_wall_clock(crossing.time))
+                        }))
+                        .collect()
+                }
+                None => vec![Event::Rebooted],
+            },
+        }
+    }
+}
+
+/// Where events are forwarded to in addition to stdout.
+struct Forwarder {
+    #[cfg(unix)]
+    syslog: Option<std::os::unix::net::UnixDatagram>,
+    hook: Vec<String>,
+}
+
+impl Forwarder {
+    fn new(config: &Config) -> Result<Self> {
+        #[cfg(unix)]
+        let syslog = if config.syslog {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket
+                .connect("/dev/log")
+                .context("Connecting to the syslog socket /dev/log")?;
+            Some(socket)
+        } else {
+            None
+        };
+        #[cfg(not(unix))]
+        if config.syslog {
+            bail!("Forwarding to syslog is only supported on Unix");
+        }
+        Ok(Self {
+            #[cfg(unix)]
+            syslog,
+            hook: config.hook.clone(),
+        })
+    }
+
+    fn forward(&self, device: &Device, event: &Event) {
+        let mut message = format!("{}: {}", device.name(), event.name());
+        if let Event::Crossing(crossing, wall_clock) = event {
+            message += &format!(
+                " of input {} {} threshold, now {} (at {:.6} s",
+                crossing.channel,
+                crossing.direction,
+                crossing.state,
+                crossing.time as f64 / 1e6
+            );
+            if let Some(wall_clock) = wall_clock {
+                message += &format!(
+                    ", {} ± {:.1} ms",
+                    humantime::format_rfc3339_micros(wall_clock.time),
+                    wall_clock.error.as_secs_f64() * 1e3
+                );
+            }
+            message += ")";
+        }
+        if let Event::Lost(count) = event {
+            message += &format!(" of {count} crossing(s), the queue of the device was full");
+        }
+        println!(
+            "{} {message}",
+            humantime::format_rfc3339_millis(SystemTime::now())
+        );
+
+        #[cfg(unix)]
+        if let Some(socket) = &self.syslog {
+            // facility daemon (3)
+            let line = format!(
+                "<{}>pico_iox16[{}]: {message}",
+                3 * 8 + event.severity(),
+                std::process::id()
+            );
+            if let Err(err) = socket.send(line.as_bytes()) {
+                eprintln!("Sending to syslog failed: {err}");
+            }
+        }
+
+        if let Some((program, args)) = self.hook.split_first() {
+            let mut command = tokio::process::Command::new(program);
+            command
+                .args(args)
+                .stdin(Stdio::null())
+                .env("PICO_IOX16_EVENT", event.name())
+                .env("PICO_IOX16_ADDRESS", device.address.to_string())
+                .env("PICO_IOX16_LABEL", device.label.as_deref().unwrap_or(""))
+                .env("PICO_IOX16_MESSAGE", &message);
+            if let Event::Crossing(crossing, wall_clock) = event {
+                command
+                    .env("PICO_IOX16_CHANNEL", crossing.channel.to_string())
+                    .env("PICO_IOX16_THRESHOLD", crossing.direction.to_string())
+                    .env("PICO_IOX16_STATE", crossing.state.to_string())
+                    .env("PICO_IOX16_TIME_US", crossing.time.to_string());
+                if let Some(wall_clock) = wall_clock {
+                    command
+                        .env(
+                            "PICO_IOX16_WALL_TIME",
+                            humantime::format_rfc3339_micros(wall_clock.time).to_string(),
+                        )
+                        .env(
+                            "PICO_IOX16_WALL_TIME_ERROR_US",
+                            wall_clock.error.as_micros().to_string(),
+                        );
+                }
+            }
+            let program = program.clone();
+            match command.spawn() {
+                Ok(mut child) => {
+                    // hooks run concurrently, so a slow hook does not delay polling
+                    tokio::spawn(async move {
+                        match child.wait().await {
+                            Ok(status) if status.success() => {}
+                            Ok(status) => eprintln!("Hook {program} failed: {status}"),
+                            Err(err) => eprintln!("Hook {program} failed: {err}"),
+                        }
+                    });
+                }
+                Err(err) => eprintln!("Running hook {program} failed: {err}"),
+            }
+        }
+    }
+}
+
+/// Polls the threshold states of the configured devices until Ctrl-C is pressed and
+/// forwards threshold crossings and devices going offline to stdout, syslog and a hook
+/// command.
+pub(crate) async fn daemon(
+    protocol: &mut Protocol,
+    file: &Path,
+    settings: &Settings,
+) -> Result<()> {
+    let config = Config::load(file)?;
+    let mut devices = Vec::new();
+    let mut seen = BTreeSet::new();
+    for name in &config.devices {
+        let (address, label) = match name {
+            DeviceName::Address(address) => (*address, settings.label(*address)),
+            DeviceName::Alias(alias) => (settings.resolve_address(alias)?, Some(alias.as_str())),
+        };
+        if !seen.insert(address) {
+            bail!("Device {address} is configured twice");
+        }
+        devices.push(Device {
+            address,
+            label: label.map(String::from),
+            status: Status::Unknown,
+            clock: DeviceClock::default(),
+            queued: true,
+        });
+    }
+    let forwarder = Forwarder::new(&config)?;
+    let interval = Duration::from_millis(config.interval);
+    eprintln!("Polling {} device(s) every {interval:?}...", devices.len());
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+        for device in &mut devices {
+            for event in device.poll(protocol).await {
+                forwarder.forward(device, &event);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use pico_iox16_tool::{Protocol, device::Debounce};
+
+/// Prints the debounce bookkeeping of each input: where it is relative to its thresholds, how
+/// many consecutive readings it has been there and how long ago it crossed them, before and
+/// after debouncing.
+pub(crate) async fn debounce(device: &mut Protocol, address: u16) -> Result<()> {
+    let inputs = Debounce::fetch(device, address).await?;
+    let ago = |time: Duration| format!("{:.1} ms", time.as_secs_f64() * 1000.0);
+    println!(
+        "{:>5} {:<12} {:>6} {:>6} {:>12} {:>12} {:>12} {:>12}",
+        "Input", "state", "above", "below", "raw high", "high", "raw low", "low"
+    );
+    for (input, debounce) in inputs.iter().enumerate() {
+        let state = if debounce.pending_high {
+            "pending high"
+        } else if debounce.pending_low {
+            "pending low"
+        } else if debounce.above_count > 0 {
+            "high"
+        } else if debounce.below_count > 0 {
+            "low"
+        } else {
+            "between"
+        };
+        println!(
+            "{input:>5} {state:<12} {:>6} {:>6} {:>12} {:>12} {:>12} {:>12}",
+            debounce.above_count,
+            debounce.below_count,
+            ago(debounce.raw_high),
+            ago(debounce.debounced_high),
+            ago(debounce.raw_low),
+            ago(debounce.debounced_low),
+        );
+    }
+    Ok(())
+}
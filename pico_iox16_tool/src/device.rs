@@ -0,0 +1,568 @@
+use std::{cmp::Ordering, ops::RangeInclusive, time::Duration};
+
+use pico_iox16_protocol::{
+    BaudratesGetReq, BaudratesGetRes, DeviceIdGetReq, DeviceIdGetRes, DiagnosticsGetReq, DiagnosticsGetRes, DigitalGetReq, DigitalGetRes, FailsafeGetReq, FailsafeGetRes, FailsafeSetReq, FailsafeSetRes, InfoGetReq, InfoGetRes, InputGetCalibrationsReq, InputGetCalibrationsRes, InputGetDebounceReq, InputGetDebounceRes, InputGetReq,
+    InputGetRes, InputGetThresholdStatesReq, InputGetThresholdStatesRes, InputGetThresholdsReq,
+    InputGetThresholdsRes, InputSetCalibrationsReq, InputSetCalibrationsRes, InputSetThresholdsReq,
+    InputSetThresholdsRes, OutputGetReq, OutputGetRes, OutputGroup, OutputSetDefaultsReq, OutputSetDefaultsRes, OutputSetMaskedReq, OutputSetMaskedRes, OutputSetReq, OutputSetRes,
+    Command, ErrorCode, PROTOCOL_VERSION, ProtocolVersionGetReq, ProtocolVersionGetRes, ReportConfigSetReq, ReportConfigSetRes, ResetCause, SampleInterval,
+    settings::{self, Calibration, Threshold},
+};
+
+use crate::{Error, Protocol, ProtocolClient, Result};
+
+/// The identification of a device as returned by `InfoGet`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub info: String,
+    pub version: (u8, u8, u16),
+    pub uptime: Duration,
+    /// The [`PROTOCOL_VERSION`] of the firmware.
+    pub protocol_version: u16,
+    /// The [`settings::SCHEMA_VERSION`] of the firmware, `None` if it predates reporting it.
+    pub config_schema_version: Option<u16>,
+}
+
+impl Info {
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        protocol
+            .send_request(address, InfoGetReq, |response: &InfoGetRes| {
+                let length = response
+                    .info
+                    .iter()
+                    .position(|&byte| byte == 0)
+                    .unwrap_or(response.info.len());
+                Ok(Self {
+                    info: String::from_utf8_lossy(&response.info[..length]).into_owned(),
+                    version: (
+                        response.firmware_version_major,
+                        response.firmware_version_minor,
+                        response.firmware_version_patch.get(),
+                    ),
+                    uptime: Duration::from_secs(response.uptime.get().into()),
+                    protocol_version: response.protocol_version.get(),
+                    config_schema_version: Some(response.config_schema_version.get())
+                        .filter(|&version| version != 0),
+                })
+            })
+            .await
+    }
+
+    /// The firmware version as `major.minor.patch`.
+    pub fn version_string(&self) -> String {
+        let (major, minor, patch) = self.version;
+        format!("{major}.{minor}.{patch}")
+    }
+
+    /// Fails if the firmware speaks another protocol version than the tool, which may then
+    /// misunderstand it or be misunderstood.
+    pub fn check_protocol_version(&self) -> Result<()> {
+        check_major_version(self.protocol_version)
+    }
+
+    /// Describes how the layout the firmware stores the settings in differs from the one of the
+    /// tool, `None` if they match or the firmware doesn't tell.
+    pub fn config_schema_mismatch(&self) -> Option<String> {
+        let version = self.config_schema_version?;
+        match version.cmp(&settings::SCHEMA_VERSION) {
+            Ordering::Equal => None,
+            Ordering::Less => Some(format!(
+                "the firmware stores settings schema {version}, older than schema {} of this \
+                 tool; update the firmware to use all settings",
+                settings::SCHEMA_VERSION
+            )),
+            Ordering::Greater => Some(format!(
+                "the firmware stores settings schema {version}, newer than schema {} of this \
+                 tool; settings added since are left out",
+                settings::SCHEMA_VERSION
+            )),
+        }
+    }
+
+    /// The unique ID of the chip, which firmware that knows it appends to the info string as
+    /// `id:<hex>`.
+    pub fn unique_id(&self) -> Option<&str> {
+        self.info
+            .split_whitespace()
+            .find_map(|word| word.strip_prefix("id:"))
+            .filter(|id| !id.is_empty())
+    }
+}
+
+/// The identity of a device as returned by `DeviceIdGet`, to tell which board answers at an
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceId {
+    /// The unique ID of the chip in hex, as in [`Info::unique_id`].
+    pub unique_id: Option<String>,
+    /// The revision of the board. `None` if the firmware predates `DeviceIdGet` or doesn't know
+    /// its board.
+    pub hardware_revision: Option<u16>,
+}
+
+impl DeviceId {
+    /// Fetches the identity. For firmware that predates `DeviceIdGet`, the unique ID is taken
+    /// from the info string of `InfoGet`.
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        let response = protocol
+            .send_request(address, DeviceIdGetReq, |response: &DeviceIdGetRes| {
+                Ok(Self {
+                    unique_id: Some(response.unique_id.get()).filter(|&id| id != 0).map(|id| format!("{id:016x}")),
+                    hardware_revision: Some(response.hardware_revision.get()).filter(|&revision| revision != 0),
+                })
+            })
+            .await;
+        match response {
+            Err(err) if err.is_unsupported() => {
+                let info = Info::fetch(protocol, address).await?;
+                Ok(Self { unique_id: info.unique_id().map(String::from), hardware_revision: None })
+            }
+            response => response,
+        }
+    }
+}
+
+/// Fetches the range of baudrates the device can be configured for. Firmware that predates
+/// `BaudratesGet` takes those of [`settings::BAUDRATES`].
+pub async fn baudrates(protocol: &mut impl ProtocolClient, address: u16) -> Result<RangeInclusive<u32>> {
+    let response = protocol.send_request(address, BaudratesGetReq, |response: &BaudratesGetRes| Ok(response.range())).await;
+    match response {
+        Err(err) if err.is_unsupported() => Ok(settings::BAUDRATES),
+        response => response,
+    }
+}
+
+/// Fails if `major` isn't the [`PROTOCOL_VERSION`] of the tool.
+fn check_major_version(major: u16) -> Result<()> {
+    let update = match major.cmp(&PROTOCOL_VERSION) {
+        Ordering::Equal => return Ok(()),
+        Ordering::Less => "the firmware",
+        Ordering::Greater => "pico_iox16_tool",
+    };
+    Err(Error::DeviceError(format!(
+        "The device speaks protocol version {major}, but this tool version {PROTOCOL_VERSION}. Update {update}."
+    )))
+}
+
+/// The protocol version of a device and the optional features it supports, as returned by
+/// `ProtocolVersionGet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    /// The [`PROTOCOL_VERSION`] of the firmware.
+    pub major: u16,
+    /// The [`pico_iox16_protocol::PROTOCOL_VERSION_MINOR`] of the firmware.
+    pub minor: u16,
+    /// The `CAPABILITY_*` bits of the device.
+    pub capabilities: u32,
+}
+
+impl ProtocolVersion {
+    /// Fetches the version. Firmware that predates `ProtocolVersionGet` speaks minor version 0,
+    /// its major version is taken from `InfoGet` and it reports no capabilities.
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        let response = protocol
+            .send_request(address, ProtocolVersionGetReq, |response: &ProtocolVersionGetRes| {
+                Ok(Self {
+                    major: response.major.get(),
+                    minor: response.minor.get(),
+                    capabilities: response.capabilities.get(),
+                })
+            })
+            .await;
+        match response {
+            Err(Error::Rejected { code: ErrorCode::UnknownCommand, .. }) => {
+                let info = Info::fetch(protocol, address).await?;
+                Ok(Self { major: info.protocol_version, minor: 0, capabilities: 0 })
+            }
+            response => response,
+        }
+    }
+
+    /// Whether the device supports all of the `CAPABILITY_*` bits in `capabilities`.
+    pub fn supports(&self, capabilities: u32) -> bool {
+        self.capabilities & capabilities == capabilities
+    }
+
+    /// Fails if the device can't understand `command`: if it speaks another major version than
+    /// the tool, or a minor version older than the command.
+    pub fn check(&self, command: Command) -> Result<()> {
+        check_major_version(self.major)?;
+        if self.minor < command.minor_version() {
+            return Err(Error::Unsupported { command, major: self.major, minor: self.minor });
+        }
+        Ok(())
+    }
+}
+
+/// What a device went through, as returned by `DiagnosticsGet`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub reset_cause: ResetCause,
+    /// The settings in flash were unreadable and the device runs with the defaults until it is
+    /// provisioned again.
+    pub config_corrupted: bool,
+    /// Number of brown-outs over the lifetime of the configuration.
+    pub brownouts: u32,
+    /// Failed conversions of the left and right half of the board since boot.
+    pub conversion_errors: [u32; 2],
+    /// Times since boot that samples were lost because the firmware fell behind.
+    pub overruns: u32,
+    /// How regularly each input was sampled since the previous `DiagnosticsGet`, `None` for
+    /// inputs that were not sampled twice since then.
+    pub sample_intervals: [Option<SampleIntervals>; 16],
+}
+
+/// The distribution of the intervals at which the input loop sampled an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleIntervals {
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    pub count: u32,
+}
+
+impl SampleIntervals {
+    fn new(interval: &SampleInterval) -> Option<Self> {
+        (interval.count.get() > 0).then(|| Self {
+            min: Duration::from_micros(interval.min_us.get().into()),
+            mean: Duration::from_micros(interval.mean_us.get().into()),
+            max: Duration::from_micros(interval.max_us.get().into()),
+            count: interval.count.get(),
+        })
+    }
+
+    /// How much the intervals spread, i.e. the difference between the longest and the shortest.
+    pub fn jitter(&self) -> Duration {
+        self.max - self.min
+    }
+}
+
+impl Diagnostics {
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        protocol
+            .send_request(address, DiagnosticsGetReq, |response: &DiagnosticsGetRes| {
+                Ok(Self {
+                    reset_cause: response.reset_cause,
+                    config_corrupted: response.config_corrupted(),
+                    brownouts: response.brownouts.get(),
+                    conversion_errors: response.conversion_errors.map(|count| count.get()),
+                    overruns: response.overruns.get(),
+                    sample_intervals: response.sample_intervals.each_ref().map(SampleIntervals::new),
+                })
+            })
+            .await
+    }
+}
+
+/// The spare GPIOs a device reads as digital inputs, as returned by `DigitalGet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigitalInputs {
+    /// Bit `n` is set if GPIO `n` is a digital input.
+    pub available: u32,
+    /// Bit `n` is set if GPIO `n` is high, after debouncing.
+    pub levels: u32,
+}
+
+impl DigitalInputs {
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        protocol
+            .send_request(address, DigitalGetReq, |response: &DigitalGetRes| {
+                Ok(Self {
+                    available: response.available.get(),
+                    levels: response.levels.get(),
+                })
+            })
+            .await
+    }
+
+    /// The GPIO numbers of the digital inputs along with whether each is high.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, bool)> + '_ {
+        (0..32)
+            .filter(|gpio| self.available & 1 << gpio != 0)
+            .map(|gpio| (gpio, self.levels & 1 << gpio != 0))
+    }
+}
+
+/// The debounce bookkeeping of one input, as returned by `InputGetDebounce`. The times are how
+/// long before the response the input crossed a threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Debounce {
+    /// The last crossing of the high threshold, whether or not it passed the debounce.
+    pub raw_high: Duration,
+    /// The last crossing of the low threshold, whether or not it passed the debounce.
+    pub raw_low: Duration,
+    /// The last crossing of the high threshold that passed the debounce.
+    pub debounced_high: Duration,
+    /// The last crossing of the low threshold that passed the debounce.
+    pub debounced_low: Duration,
+    /// Consecutive readings above the high threshold, 0 if the input is not above it.
+    pub above_count: u16,
+    /// Consecutive readings below the low threshold, 0 if the input is not below it.
+    pub below_count: u16,
+    /// The input is above the high threshold, but not yet long enough to pass the debounce.
+    pub pending_high: bool,
+    /// The input is below the low threshold, but not yet long enough to pass the debounce.
+    pub pending_low: bool,
+}
+
+impl Debounce {
+    pub async fn fetch(protocol: &mut impl ProtocolClient, address: u16) -> Result<[Self; 16]> {
+        protocol
+            .send_request(address, InputGetDebounceReq, |response: &InputGetDebounceRes| {
+                let now = response.now.get();
+                let ago = |time: u64| Duration::from_micros(now.saturating_sub(time));
+                Ok(response.inputs.map(|input| Self {
+                    raw_high: ago(input.raw_high.get()),
+                    raw_low: ago(input.raw_low.get()),
+                    debounced_high: ago(input.debounced_high.get()),
+                    debounced_low: ago(input.debounced_low.get()),
+                    above_count: input.above_count.get(),
+                    below_count: input.below_count.get(),
+                    pending_high: input.pending_high(),
+                    pending_low: input.pending_low(),
+                }))
+            })
+            .await
+    }
+}
+
+/// The duty cycles and frequencies of the 16 outputs, which are driven in groups of two
+/// sharing one frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outputs {
+    /// Duty cycle of each output in percent.
+    pub duty_cycles: [f64; 16],
+    /// PWM frequency of each group in Hz.
+    pub frequencies: [u16; 8],
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Self::from(&OutputSetReq::default().0)
+    }
+}
+
+impl From<&[OutputGroup; 8]> for Outputs {
+    fn from(groups: &[OutputGroup; 8]) -> Self {
+        Self {
+            duty_cycles: std::array::from_fn(|i| {
+                f64::from(groups[i / 2].duty_cycle[i % 2].get()) * 100.0 / 32768.0
+            }),
+            frequencies: groups.map(|group| group.frequency.get()),
+        }
+    }
+}
+
+/// What the outputs are forced to once the device received no valid request for a while, see
+/// [`pico_iox16_protocol::Command::FailsafeSet`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Failsafe {
+    /// Time without a valid request, `None` if the outputs are never forced.
+    pub timeout: Option<Duration>,
+    /// The safe duty cycles in percent. The frequencies stay.
+    pub duty_cycles: [f64; 16],
+}
+
+/// A duty cycle in percent scaled by 32768 as on the wire.
+fn duty_cycle(percent: f64) -> u16 {
+    ((percent / 100.0 * 32768.0).round().max(0.0) as u16).min(0x8000)
+}
+
+impl From<&Outputs> for [OutputGroup; 8] {
+    fn from(outputs: &Outputs) -> Self {
+        std::array::from_fn(|i| OutputGroup {
+            duty_cycle: [
+                duty_cycle(outputs.duty_cycles[2 * i]).into(),
+                duty_cycle(outputs.duty_cycles[2 * i + 1]).into(),
+            ],
+            frequency: outputs.frequencies[i].into(),
+        })
+    }
+}
+
+/// One device on a bus, with a method for each request. Used by front ends that talk to a
+/// single device, such as the GUI. Each method shows up as a `request` span of the
+/// [`Protocol`] in `tracing`.
+pub struct Device<C = Protocol> {
+    protocol: C,
+    address: u16,
+}
+
+impl<C: ProtocolClient> Device<C> {
+    pub fn new(protocol: C, address: u16) -> Self {
+        Self { protocol, address }
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    pub fn protocol(&mut self) -> &mut C {
+        &mut self.protocol
+    }
+
+    pub async fn info(&mut self) -> Result<Info> {
+        Info::fetch(&mut self.protocol, self.address).await
+    }
+
+    pub async fn protocol_version(&mut self) -> Result<ProtocolVersion> {
+        ProtocolVersion::fetch(&mut self.protocol, self.address).await
+    }
+
+    pub async fn device_id(&mut self) -> Result<DeviceId> {
+        DeviceId::fetch(&mut self.protocol, self.address).await
+    }
+
+    pub async fn baudrates(&mut self) -> Result<RangeInclusive<u32>> {
+        baudrates(&mut self.protocol, self.address).await
+    }
+
+    pub async fn diagnostics(&mut self) -> Result<Diagnostics> {
+        Diagnostics::fetch(&mut self.protocol, self.address).await
+    }
+
+    pub async fn digital_inputs(&mut self) -> Result<DigitalInputs> {
+        DigitalInputs::fetch(&mut self.protocol, self.address).await
+    }
+
+    pub async fn debounce(&mut self) -> Result<[Debounce; 16]> {
+        Debounce::fetch(&mut self.protocol, self.address).await
+    }
+
+    /// Reads the input values, averaged since the previous read.
+    pub async fn inputs(&mut self) -> Result<[i16; 16]> {
+        self.protocol
+            .send_request(self.address, InputGetReq, |InputGetRes { values }| {
+                Ok(values.map(|value| value.get()))
+            })
+            .await
+    }
+
+    /// Reads the bitmasks of the inputs above their high and below their low threshold.
+    pub async fn threshold_states(&mut self) -> Result<(u16, u16)> {
+        self.protocol
+            .send_request(
+                self.address,
+                InputGetThresholdStatesReq,
+                |InputGetThresholdStatesRes { above, below }| Ok((above.get(), below.get())),
+            )
+            .await
+    }
+
+    pub async fn outputs(&mut self) -> Result<Outputs> {
+        self.protocol
+            .send_request(self.address, OutputGetReq, |OutputGetRes(groups)| {
+                Ok(Outputs::from(groups))
+            })
+            .await
+    }
+
+    pub async fn set_outputs(&mut self, outputs: &Outputs) -> Result<()> {
+        self.protocol
+            .send_request(self.address, OutputSetReq(outputs.into()), |OutputSetRes| {
+                Ok(())
+            })
+            .await
+    }
+
+    /// Stores `outputs` for the device to start with, leaving the current ones as they are.
+    pub async fn set_output_defaults(&mut self, outputs: &Outputs) -> Result<()> {
+        let request = OutputSetDefaultsReq(outputs.into());
+        self.protocol
+            .send_request(self.address, request, |OutputSetDefaultsRes| Ok(()))
+            .await
+    }
+
+    /// Sets the duty cycles of the outputs selected in `mask`, bit `n` for output `n`, to those
+    /// in `duty_cycles` in percent. The other outputs and the frequencies stay as they are on the
+    /// device, whatever the caller last read.
+    pub async fn set_duty_cycles(&mut self, mask: u16, duty_cycles: &[f64; 16]) -> Result<()> {
+        let request = OutputSetMaskedReq::new(
+            (0..16)
+                .filter(|output| mask & 1 << output != 0)
+                .map(|output| (output, duty_cycle(duty_cycles[output]))),
+        );
+        self.protocol
+            .send_request(self.address, request, |OutputSetMaskedRes| Ok(()))
+            .await
+    }
+
+    pub async fn calibrations(&mut self) -> Result<[Calibration; 16]> {
+        self.protocol
+            .send_request(
+                self.address,
+                InputGetCalibrationsReq,
+                |InputGetCalibrationsRes(calibrations)| Ok(calibrations.map(Into::into)),
+            )
+            .await
+    }
+
+    pub async fn set_calibrations(&mut self, calibrations: &[Calibration; 16]) -> Result<()> {
+        self.protocol
+            .send_request(
+                self.address,
+                InputSetCalibrationsReq(calibrations.map(Into::into)),
+                |InputSetCalibrationsRes| Ok(()),
+            )
+            .await
+    }
+
+    pub async fn thresholds(&mut self) -> Result<[Threshold; 16]> {
+        self.protocol
+            .send_request(
+                self.address,
+                InputGetThresholdsReq,
+                |InputGetThresholdsRes(thresholds)| Ok(thresholds.map(Into::into)),
+            )
+            .await
+    }
+
+    pub async fn set_thresholds(&mut self, thresholds: &[Threshold; 16]) -> Result<()> {
+        self.protocol
+            .send_request(
+                self.address,
+                InputSetThresholdsReq(thresholds.map(Into::into)),
+                |InputSetThresholdsRes| Ok(()),
+            )
+            .await
+    }
+
+    /// Tells the device to send its input values every `interval`, or to stop with
+    /// [`Duration::ZERO`]. Read them with [`Protocol::next_report`]. Only on links to a single
+    /// device, the reports would collide with other traffic on a bus.
+    pub async fn set_report_interval(&mut self, interval: Duration) -> Result<()> {
+        let interval_ms = u32::try_from(interval.as_millis()).unwrap_or(u32::MAX);
+        self.protocol
+            .send_request(
+                self.address,
+                ReportConfigSetReq { interval_ms: interval_ms.into() },
+                |ReportConfigSetRes| Ok(()),
+            )
+            .await
+    }
+
+    pub async fn failsafe(&mut self) -> Result<Failsafe> {
+        self.protocol
+            .send_request(self.address, FailsafeGetReq, |FailsafeGetRes(failsafe)| {
+                Ok(Failsafe {
+                    timeout: Some(Duration::from_millis(failsafe.timeout_ms.get().into())).filter(|timeout| !timeout.is_zero()),
+                    duty_cycles: failsafe.duty_cycles.map(|d| f64::from(d.get()) * 100.0 / 32768.0),
+                })
+            })
+            .await
+    }
+
+    /// Stores the failsafe. A timeout below a millisecond is rounded up, as 0 disables it.
+    pub async fn set_failsafe(&mut self, failsafe: &Failsafe) -> Result<()> {
+        let timeout_ms = failsafe.timeout.map_or(0, |timeout| {
+            u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX).max(1)
+        });
+        let request = FailsafeSetReq(pico_iox16_protocol::Failsafe {
+            timeout_ms: timeout_ms.into(),
+            duty_cycles: failsafe.duty_cycles.map(|percent| duty_cycle(percent).into()),
+        });
+        self.protocol
+            .send_request(self.address, request, |FailsafeSetRes| Ok(()))
+            .await
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use pico_iox16_protocol::ResetCause;
+use pico_iox16_tool::{Protocol, device::Diagnostics};
+
+/// Prints why the device was last reset, how often its supply browned out, how many samples
+/// of the inputs were lost and how regularly they were taken, and warns if its settings were
+/// lost.
+pub(crate) async fn diagnostics(device: &mut Protocol, address: u16) -> Result<()> {
+    let Diagnostics {
+        reset_cause,
+        config_corrupted,
+        brownouts,
+        conversion_errors: [left, right],
+        overruns,
+        sample_intervals,
+    } = Diagnostics::fetch(device, address).await?;
+    let reset_cause = match reset_cause {
+        ResetCause::Other => "other (reset pin, debugger or reboot request)",
+        ResetCause::PowerOn => "power on",
+        ResetCause::Watchdog => "watchdog, the firmware got stuck",
+        ResetCause::BrownOut => "brown-out, the supply voltage dropped",
+    };
+    println!("Last reset: {reset_cause}");
+    println!("Brown-outs: {brownouts}");
+    println!("Conversion errors: {left} left, {right} right");
+    println!("Overruns: {overruns}");
+    println!("Sample intervals since the previous query:");
+    let ms = |time: Duration| format!("{:.3} ms", time.as_secs_f64() * 1000.0);
+    println!(
+        "{:>5} {:>12} {:>12} {:>12} {:>12} {:>8}",
+        "Input", "min", "mean", "max", "jitter", "count"
+    );
+    for (input, intervals) in sample_intervals.iter().enumerate() {
+        match intervals {
+            Some(intervals) => println!(
+                "{input:>5} {:>12} {:>12} {:>12} {:>12} {:>8}",
+                ms(intervals.min),
+                ms(intervals.mean),
+                ms(intervals.max),
+                ms(intervals.jitter()),
+                intervals.count,
+            ),
+            None => println!("{input:>5} {:>12}", "none"),
+        }
+    }
+    if config_corrupted {
+        println!(
+            "Warning: the settings in flash were corrupted, the device runs with the defaults \
+             until it is provisioned again"
+        );
+    }
+    Ok(())
+}
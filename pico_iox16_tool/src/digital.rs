@@ -0,0 +1,14 @@
+use anyhow::Result;
+use pico_iox16_tool::{Protocol, device::DigitalInputs};
+
+/// Prints the level of each spare GPIO the device reads as digital input.
+pub(crate) async fn digital(device: &mut Protocol, address: u16) -> Result<()> {
+    let inputs = DigitalInputs::fetch(device, address).await?;
+    if inputs.available == 0 {
+        println!("The device has no digital inputs");
+    }
+    for (gpio, high) in inputs.iter() {
+        println!("GP{gpio}: {}", if high { "high" } else { "low" });
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::{fmt::Display, fs, path::Path};
+
+use pico_iox16_protocol::{
+    Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes, InputGetCalibrationsReq,
+    InputGetCalibrationsRes, InputGetThresholdsReq, InputGetThresholdsRes, InputSetCalibrationsReq,
+    InputSetCalibrationsRes, InputSetThresholdsReq, InputSetThresholdsRes, OutputGetDefaultsReq,
+    OutputGetDefaultsRes, OutputSetDefaultsReq, OutputSetDefaultsRes,
+    settings::{OutputDefaults, Settings},
+};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::{Error, ProtocolClient, Result, error::IoContext as _};
+
+/// The content of a dump file: the settings of a device and the outputs it starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    #[serde(flatten)]
+    pub settings: Settings,
+    /// `None` if the firmware predates `OutputGetDefaults` and can't tell them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_defaults: Option<OutputDefaults>,
+}
+
+/// Retrieves the persistent settings from the device at the given address.
+#[tracing::instrument(level = "debug", skip(device))]
+pub async fn fetch(device: &mut impl ProtocolClient, address: u16) -> Result<Dump> {
+    let config = device
+        .send_request(address, ConfigGetReq, |ConfigGetRes(config)| {
+            Ok((*config).into())
+        })
+        .await?;
+    let calibrations = device
+        .send_request(
+            address,
+            InputGetCalibrationsReq,
+            |InputGetCalibrationsRes(calibrations)| Ok(calibrations.map(Into::into)),
+        )
+        .await?;
+    let thresholds = device
+        .send_request(
+            address,
+            InputGetThresholdsReq,
+            |InputGetThresholdsRes(thresholds)| Ok(thresholds.map(Into::into)),
+        )
+        .await?;
+    let output_defaults = device
+        .send_request(
+            address,
+            OutputGetDefaultsReq,
+            |response: &OutputGetDefaultsRes| Ok(response.into()),
+        )
+        .await;
+    let output_defaults = match output_defaults {
+        Ok(output_defaults) => Some(output_defaults),
+        Err(err) if err.is_unsupported() => None,
+        Err(err) => return Err(err),
+    };
+    Ok(Dump {
+        settings: Settings {
+            config,
+            calibrations,
+            thresholds,
+        },
+        output_defaults,
+    })
+}
+
+/// Writes the settings to a TOML dump file.
+pub fn save(dump: &Dump, path: &Path) -> Result<()> {
+    let text =
+        toml::to_string(dump).map_err(|err| Error::parse("Serializing the settings", err))?;
+    fs::write(path, text).io_context(|| format!("Writing dump file {}", path.display()))
+}
+
+/// A field whose value in the dump file differs from the device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// Path of the field, e.g. `calibrations[3].multiply`.
+    pub path: String,
+    /// The value in the dump file.
+    pub expected: Value,
+    /// The value on the device, `None` if the device has no such field.
+    pub actual: Option<Value>,
+}
+
+/// Reads a dump file without requiring all fields to be present.
+pub fn read_dump(path: &Path) -> Result<Value> {
+    let text =
+        fs::read_to_string(path).io_context(|| format!("Reading dump file {}", path.display()))?;
+    toml::from_str(&text)
+        .map_err(|err| Error::parse(format!("Parsing dump file {}", path.display()), err))
+}
+
+/// Compares the fields present in `expected` with `actual`. Fields missing in `expected` are
+/// not compared, so a dump file may pin only the settings that matter.
+pub fn diff(expected: &Value, actual: &Dump) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    diff_values(
+        String::new(),
+        expected,
+        Some(
+            &Value::try_from(actual)
+                .map_err(|err| Error::parse("Serializing the settings", err))?,
+        ),
+        &mut differences,
+    );
+    Ok(differences)
+}
+
+/// The settings of `actual` with the fields present in `expected` replaced, i.e. what the
+/// device should have to match the dump file.
+pub fn merge(expected: &Value, actual: &Dump) -> Result<Dump> {
+    fn overlay(target: &mut Value, expected: &Value) {
+        match (target, expected) {
+            (Value::Table(target), Value::Table(expected)) => {
+                for (key, value) in expected {
+                    match target.get_mut(key) {
+                        Some(target) => overlay(target, value),
+                        None => {
+                            target.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            (Value::Array(target), Value::Array(expected)) => {
+                for (i, value) in expected.iter().enumerate() {
+                    match target.get_mut(i) {
+                        Some(target) => overlay(target, value),
+                        None => target.push(value.clone()),
+                    }
+                }
+            }
+            (target, expected) => *target = expected.clone(),
+        }
+    }
+    let mut merged =
+        Value::try_from(actual).map_err(|err| Error::parse("Serializing the settings", err))?;
+    overlay(&mut merged, expected);
+    merged
+        .try_into()
+        .map_err(|err| Error::parse("Applying the dump file to the settings", err))
+}
+
+/// The groups of settings written by [`apply`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Written {
+    pub calibrations: bool,
+    pub thresholds: bool,
+    pub output_defaults: bool,
+    /// The configuration, which takes effect after a reboot.
+    pub config: bool,
+}
+
+/// Writes the groups of `desired` that differ from `actual`, the settings the device at
+/// `address` has now. The configuration goes last, as a new address or baudrate would cut off
+/// the requests after it once the device reboots.
+#[tracing::instrument(level = "debug", skip(device, actual, desired))]
+pub async fn apply(
+    device: &mut impl ProtocolClient,
+    address: u16,
+    actual: &Dump,
+    desired: &Dump,
+) -> Result<Written> {
+    let mut written = Written::default();
+    // there is no request to clear stored defaults, so a dump without them leaves the device's
+    if let Some(groups) = desired
+        .output_defaults
+        .and_then(|defaults| defaults.groups())
+        && desired.output_defaults != actual.output_defaults
+    {
+        device
+            .send_request(
+                address,
+                OutputSetDefaultsReq(groups),
+                |OutputSetDefaultsRes| Ok(()),
+            )
+            .await?;
+        written.output_defaults = true;
+    }
+    let (actual, desired) = (&actual.settings, &desired.settings);
+    if desired.calibrations != actual.calibrations {
+        device
+            .send_request(
+                address,
+                InputSetCalibrationsReq(desired.calibrations.map(Into::into)),
+                |InputSetCalibrationsRes| Ok(()),
+            )
+            .await?;
+        written.calibrations = true;
+    }
+    if desired.thresholds != actual.thresholds {
+        device
+            .send_request(
+                address,
+                InputSetThresholdsReq(desired.thresholds.map(Into::into)),
+                |InputSetThresholdsRes| Ok(()),
+            )
+            .await?;
+        written.thresholds = true;
+    }
+    // compared as sent, as the stored parity and stop bits may be erased flash
+    let config = Config::from(desired.config);
+    if config != Config::from(actual.config) {
+        device
+            .send_request(address, ConfigSetReq(config), |ConfigSetRes| Ok(()))
+            .await?;
+        written.config = true;
+    }
+    Ok(written)
+}
+
+fn diff_values(
+    path: String,
+    expected: &Value,
+    actual: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    fn join(path: &str, key: impl Display) -> String {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    }
+    match (expected, actual) {
+        (Value::Table(expected), Some(Value::Table(actual))) => {
+            for (key, value) in expected {
+                diff_values(join(&path, key), value, actual.get(key), differences);
+            }
+        }
+        (Value::Array(expected), Some(Value::Array(actual))) => {
+            for (i, value) in expected.iter().enumerate() {
+                diff_values(format!("{path}[{i}]"), value, actual.get(i), differences);
+            }
+        }
+        (expected, actual) if actual != Some(expected) => differences.push(Difference {
+            path,
+            expected: expected.clone(),
+            actual: actual.cloned(),
+        }),
+        _ => {}
+    }
+}
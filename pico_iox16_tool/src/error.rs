@@ -0,0 +1,132 @@
+use std::{error::Error as StdError, io};
+
+use pico_iox16_protocol::{Command, ErrorCode, FragmentError};
+
+/// Errors of the library, to let applications tell a device that does not respond from one
+/// that responds wrongly or a broken port. The command line tool wraps them in `anyhow`.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// No response arrived in time, not even a broken one.
+    #[error("Timed out waiting for {command} response")]
+    Timeout { command: Command },
+    /// No valid response arrived in time, but frames with checksum errors did, e.g. because of
+    /// noise on the bus or a device at another baudrate.
+    #[error(
+        "Timed out waiting for {command} response, received {frames} frame(s) with checksum errors"
+    )]
+    CrcMismatch { command: Command, frames: u64 },
+    /// No response arrived in time, but one from another device did, e.g. because two devices
+    /// share an address or a late response to an earlier request arrived.
+    #[error(
+        "Timed out waiting for {command} response from device {expected}, received one from device {actual}"
+    )]
+    AddressMismatch {
+        command: Command,
+        expected: u16,
+        actual: u16,
+    },
+    /// No response arrived in time, but the device answered another command, e.g. one it does
+    /// not support or a late response to an earlier request.
+    #[error("Timed out waiting for {expected} response, received {actual} response")]
+    UnexpectedCommand { expected: Command, actual: Command },
+    /// No complete response arrived in time, but fragments of one did, e.g. because one of
+    /// them was lost.
+    #[error("Timed out waiting for {command} response, received incomplete fragments")]
+    Fragment {
+        command: Command,
+        source: FragmentError,
+    },
+    /// The device answered that it couldn't handle the request, e.g. because its firmware doesn't
+    /// know the command yet or writing its flash failed.
+    #[error("Device rejected the {command} request: {code}")]
+    Rejected { command: Command, code: ErrorCode },
+    /// The device speaks an older minor protocol version that doesn't know the command yet, so
+    /// the request wasn't sent.
+    #[error(
+        "The device doesn't know {command} requests, it speaks protocol version {major}.{minor}. Update the firmware."
+    )]
+    Unsupported {
+        command: Command,
+        major: u16,
+        minor: u16,
+    },
+    /// Reading or writing the port or a file failed.
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The device responded, but with something the library cannot work with.
+    #[error("{0}")]
+    DeviceError(String),
+    /// A file could not be parsed, or data could not be converted to its format.
+    #[error("{context}")]
+    Parse {
+        context: String,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    /// An argument, the content of a file or the environment is not usable.
+    #[error("{0}")]
+    Invalid(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(
+        context: impl Into<String>,
+        source: impl StdError + Send + Sync + 'static,
+    ) -> Self {
+        Self::Parse {
+            context: context.into(),
+            source: Box::new(source),
+        }
+    }
+
+    /// Whether retrying the request may help, i.e. the device did not respond properly but the
+    /// port works.
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Self::Timeout { .. }
+                | Self::CrcMismatch { .. }
+                | Self::AddressMismatch { .. }
+                | Self::UnexpectedCommand { .. }
+                | Self::Fragment { .. }
+        )
+    }
+
+    /// Whether the device doesn't know the command, whether it said so or its protocol version
+    /// tells.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            Self::Unsupported { .. }
+                | Self::Rejected {
+                    code: ErrorCode::UnknownCommand,
+                    ..
+                }
+        )
+    }
+}
+
+/// Adds the context of an [`Error::Io`] to I/O results.
+pub(crate) trait IoContext<T> {
+    fn io_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|source| Error::io(context(), source))
+    }
+}
@@ -0,0 +1,199 @@
+use std::{fmt, time::SystemTime};
+
+use pico_iox16_protocol::{
+    INPUT_EVENTS_PER_RESPONSE, InputEdge, InputGetEventsReq, InputGetThresholdStatesReq,
+    InputGetThresholdStatesRes, InputGetThresholdTimesReq, InputGetThresholdTimesRes,
+};
+
+use crate::{ProtocolClient, Result, clock::ClockSample};
+
+/// The threshold states and the times of the last crossings of all inputs of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Microseconds since boot of the device.
+    pub now: u64,
+    /// Bitmask of the inputs above their high threshold.
+    pub above: u16,
+    /// Bitmask of the inputs below their low threshold.
+    pub below: u16,
+    /// Time of the last low and high crossing of each input, 0 if none.
+    pub crossings: [(u64, u64); 16],
+    /// Host time before `now` was requested.
+    pub sent: SystemTime,
+    /// Host time after `now` was received.
+    pub received: SystemTime,
+}
+
+impl Snapshot {
+    #[tracing::instrument(level = "debug", skip(device))]
+    pub async fn fetch(device: &mut impl ProtocolClient, address: u16) -> Result<Self> {
+        let sent = SystemTime::now();
+        let (now, crossings) = device
+            .send_request(
+                address,
+                InputGetThresholdTimesReq,
+                |InputGetThresholdTimesRes { now, inputs }| {
+                    Ok((
+                        now.get(),
+                        inputs.map(|times| (times.last_low.get(), times.last_high.get())),
+                    ))
+                },
+            )
+            .await?;
+        let received = SystemTime::now();
+        let (above, below) = device
+            .send_request(
+                address,
+                InputGetThresholdStatesReq,
+                |InputGetThresholdStatesRes { above, below }| Ok((above.get(), below.get())),
+            )
+            .await?;
+        Ok(Self {
+            now,
+            above,
+            below,
+            crossings,
+            sent,
+            received,
+        })
+    }
+
+    /// The reading of the device clock, to convert the crossing times to wall-clock time with
+    /// a [`DeviceClock`](crate::clock::DeviceClock).
+    pub fn clock_sample(&self) -> ClockSample {
+        ClockSample {
+            device_us: self.now,
+            sent: self.sent,
+            received: self.received,
+        }
+    }
+
+    /// The state of an input relative to its thresholds.
+    pub fn state(&self, channel: usize) -> State {
+        if self.above & (1 << channel) != 0 {
+            State::Above
+        } else if self.below & (1 << channel) != 0 {
+            State::Below
+        } else {
+            State::Between
+        }
+    }
+
+    /// Returns the crossings recorded since `previous`, ordered by time, or `None` if the
+    /// device rebooted in between and the crossing times are not comparable.
+    pub fn crossings_since(&self, previous: &Snapshot) -> Option<Vec<Crossing>> {
+        if self.now < previous.now {
+            return None;
+        }
+        let mut crossings = Vec::new();
+        for (channel, (&(low, high), &(previous_low, previous_high))) in
+            self.crossings.iter().zip(&previous.crossings).enumerate()
+        {
+            if low != previous_low && low != 0 {
+                crossings.push(Crossing {
+                    channel,
+                    direction: Direction::Low,
+                    time: low,
+                    state: self.state(channel),
+                });
+            }
+            if high != previous_high && high != 0 {
+                crossings.push(Crossing {
+                    channel,
+                    direction: Direction::High,
+                    time: high,
+                    state: self.state(channel),
+                });
+            }
+        }
+        crossings.sort_by_key(|crossing| crossing.time);
+        Some(crossings)
+    }
+
+    /// Takes the crossings from the queue of the device, ordered by time, along with the number
+    /// of crossings the device dropped since the queue was last taken because it was full.
+    /// Unlike [`Snapshot::crossings_since`], this includes all crossings of an input between
+    /// two snapshots, not only the last of each direction.
+    #[tracing::instrument(level = "debug", skip(self, device))]
+    pub async fn take_queued(
+        &self,
+        device: &mut impl ProtocolClient,
+        address: u16,
+    ) -> Result<(Vec<Crossing>, u32)> {
+        let mut crossings = Vec::new();
+        let mut lost = 0;
+        loop {
+            let (events, remaining, dropped) = device
+                .send_request(
+                    address,
+                    InputGetEventsReq::new(INPUT_EVENTS_PER_RESPONSE as u8),
+                    |res| Ok((res.events().to_vec(), res.remaining, res.lost.get())),
+                )
+                .await?;
+            lost += u32::from(dropped);
+            crossings.extend(events.iter().map(|event| Crossing {
+                channel: usize::from(event.input),
+                direction: event.edge.into(),
+                time: event.time.get(),
+                state: self.state(usize::from(event.input)),
+            }));
+            if remaining == 0 || events.is_empty() {
+                return Ok((crossings, lost));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Above,
+    Below,
+    Between,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Above => "above",
+            Self::Below => "below",
+            Self::Between => "between",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Crossed the low threshold from above.
+    Low,
+    /// Crossed the high threshold from below.
+    High,
+}
+
+impl From<InputEdge> for Direction {
+    fn from(edge: InputEdge) -> Self {
+        match edge {
+            InputEdge::Low => Self::Low,
+            InputEdge::High => Self::High,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::High => "high",
+        })
+    }
+}
+
+/// A threshold crossing of one input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crossing {
+    pub channel: usize,
+    pub direction: Direction,
+    /// Microseconds since boot of the device.
+    pub time: u64,
+    /// The state of the input when the crossing was detected.
+    pub state: State,
+}
@@ -0,0 +1,49 @@
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{
+    Failsafe, FailsafeGetReq, FailsafeGetRes, FailsafeSetReq, FailsafeSetRes,
+};
+use pico_iox16_tool::Protocol;
+
+/// Stores the failsafe of the device if given, and prints the one in effect. `duty_cycles` are
+/// in percent, outputs left out go to 0%.
+pub(crate) async fn failsafe(
+    device: &mut Protocol,
+    address: u16,
+    set: Option<(u32, Vec<f64>)>,
+) -> Result<()> {
+    if let Some((timeout_ms, duty_cycles)) = set {
+        if duty_cycles.len() > 16 {
+            bail!(
+                "The device has 16 outputs, got {} duty cycles",
+                duty_cycles.len()
+            );
+        }
+        if let Some(percent) = duty_cycles.iter().find(|d| !(0.0..=100.0).contains(*d)) {
+            bail!("Duty cycle {percent}% is not within 0 to 100%");
+        }
+        let mut failsafe = Failsafe::DISABLED;
+        failsafe.timeout_ms = timeout_ms.into();
+        for (slot, percent) in failsafe.duty_cycles.iter_mut().zip(duty_cycles) {
+            *slot = ((percent / 100.0 * 32768.0).round() as u16).into();
+        }
+        device
+            .send_request(address, FailsafeSetReq(failsafe), |FailsafeSetRes| Ok(()))
+            .await?;
+    }
+    let failsafe = device
+        .send_request(address, FailsafeGetReq, |FailsafeGetRes(failsafe)| {
+            Ok(*failsafe)
+        })
+        .await?;
+    let timeout_ms = failsafe.timeout_ms.get();
+    if timeout_ms == 0 {
+        println!("No failsafe, the outputs stay as they are when the host goes quiet");
+        return Ok(());
+    }
+    println!("After {timeout_ms} ms without a valid request, the outputs are set to:");
+    for (output, duty_cycle) in failsafe.duty_cycles.iter().enumerate() {
+        let percent = f64::from(duty_cycle.get()) * 100.0 / 32768.0;
+        println!("  Output {output}: {percent:.1}%");
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! Firmware updates over the bus: the image is written to the staging area of the device,
+//! checked and then booted, see [`pico_iox16_protocol::Command::FlashEraseRegion`].
+
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+
+use pico_iox16_protocol::{
+    BootSwapReq, BootSwapRes, FLASH_CHUNK_SIZE, FLASH_SECTOR_SIZE, FlashEraseRegionReq,
+    FlashEraseRegionRes, FlashVerifyReq, FlashVerifyRes, FlashWriteChunkReq, FlashWriteChunkRes,
+    IMAGE_CHECKSUM, RequestTrait,
+};
+use tracing::debug;
+
+use crate::{Error, ProtocolClient, Result, device::Info, error::IoContext as _};
+
+/// Where the flash is mapped on the RP2350, and so where UF2 files put the image.
+const FLASH_BASE: u32 = 0x1000_0000;
+const UF2_BLOCK_SIZE: usize = 512;
+const UF2_MAGIC_START: [u32; 2] = [0x0A32_4655, 0x9E5D_5157];
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+/// UF2 flag of blocks that are not meant for the main flash.
+const UF2_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// Most data bytes in a UF2 block.
+const UF2_MAX_PAYLOAD: usize = 476;
+/// Bytes erased per request, to stay well within the timeout of `FlashEraseRegion`.
+const ERASE_SIZE: u32 = 64 * 1024;
+/// Attempts per request of the update. All of them can be repeated without harm.
+const ATTEMPTS: u32 = 3;
+/// How long the device may take to copy the image and boot it.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Loads a firmware image from a UF2 file, as for flashing over USB, or from a raw binary of the
+/// flash from its start, told apart by the extension `.uf2`.
+pub fn load_image(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).io_context(|| format!("Reading {}", path.display()))?;
+    if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("uf2"))
+    {
+        parse_uf2(&bytes)
+            .map_err(|err| Error::Invalid(format!("Parsing {}: {err}", path.display())))
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Assembles the image from the blocks of a UF2 file. Only the blocks from the start of the
+/// flash up to the first gap make up the image. Those after it, like the initial configuration,
+/// are left out, so that the device keeps its own.
+pub fn parse_uf2(bytes: &[u8]) -> Result<Vec<u8>> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err(Error::Invalid(format!(
+            "{} bytes are no whole number of UF2 blocks",
+            bytes.len()
+        )));
+    }
+    let mut blocks = BTreeMap::new();
+    for (index, block) in bytes.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let word = |i: usize| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+        if [word(0), word(1)] != UF2_MAGIC_START || word(127) != UF2_MAGIC_END {
+            return Err(Error::Invalid(format!("Block {index} is no UF2 block")));
+        }
+        if word(2) & UF2_NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+        let (address, len) = (word(3), word(4) as usize);
+        if len > UF2_MAX_PAYLOAD {
+            return Err(Error::Invalid(format!("Block {index} holds {len} bytes")));
+        }
+        blocks.insert(address, &block[32..32 + len]);
+    }
+    let mut image = Vec::new();
+    for (address, data) in blocks {
+        let next = u64::from(FLASH_BASE) + image.len() as u64;
+        if u64::from(address) > next {
+            break;
+        }
+        if u64::from(address) == next {
+            image.extend_from_slice(data);
+        }
+    }
+    if image.is_empty() {
+        return Err(Error::Invalid(format!(
+            "No blocks at the start of the flash at {FLASH_BASE:#x}"
+        )));
+    }
+    Ok(image)
+}
+
+/// Sends a request of the update, again if no proper response arrived.
+async fn request<P: RequestTrait, R>(
+    protocol: &mut impl ProtocolClient,
+    address: u16,
+    payload: P,
+    handle_response: impl Fn(&P::Response) -> Result<R>,
+) -> Result<R> {
+    let mut attempt = 1;
+    loop {
+        match protocol
+            .send_request(address, payload, &handle_response)
+            .await
+        {
+            Err(err) if err.is_timeout() && attempt < ATTEMPTS => {
+                debug!(attempt, "{err}, retrying");
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Writes `image` to the staging area of the device, checks its checksum and has the device
+/// boot it. Waits for the device to come back and returns its info, with the version of the new
+/// firmware. `progress` is called with the number of bytes written so far.
+///
+/// The running firmware stays as it is until the image is complete, so a failed update can just
+/// be started again.
+pub async fn update(
+    protocol: &mut impl ProtocolClient,
+    address: u16,
+    image: &[u8],
+    mut progress: impl FnMut(usize),
+) -> Result<Info> {
+    let size = request(protocol, address, FlashVerifyReq { len: 0.into() }, |res| {
+        Ok(res.size.get())
+    })
+    .await?;
+    if size == 0 {
+        return Err(Error::Invalid(
+            "The device can't update its firmware over the bus".into(),
+        ));
+    }
+    let len = u32::try_from(image.len())
+        .ok()
+        .filter(|&len| len > 0 && len <= size)
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "The image has {} bytes, the device takes 1 to {size}",
+                image.len()
+            ))
+        })?;
+
+    let erase_len = len.next_multiple_of(FLASH_SECTOR_SIZE);
+    for offset in (0..erase_len).step_by(ERASE_SIZE as usize) {
+        let region = FlashEraseRegionReq {
+            offset: offset.into(),
+            len: ERASE_SIZE.min(erase_len - offset).into(),
+        };
+        request(protocol, address, region, |FlashEraseRegionRes| Ok(())).await?;
+    }
+    for (index, chunk) in image.chunks(FLASH_CHUNK_SIZE).enumerate() {
+        let offset = index * FLASH_CHUNK_SIZE;
+        // erased flash reads as 0xFF already
+        if chunk.iter().any(|&byte| byte != 0xFF) {
+            let request_ = FlashWriteChunkReq::new(offset as u32, chunk).unwrap();
+            request(protocol, address, request_, |FlashWriteChunkRes| Ok(())).await?;
+        }
+        progress(offset + chunk.len());
+    }
+
+    let checksum = IMAGE_CHECKSUM.checksum(image);
+    let verify = FlashVerifyReq { len: len.into() };
+    let staged = request(protocol, address, verify, |res: &FlashVerifyRes| {
+        Ok(res.checksum.get())
+    })
+    .await?;
+    if staged != checksum {
+        return Err(Error::DeviceError(format!(
+            "The staged image has the checksum {staged:#010x} instead of {checksum:#010x}"
+        )));
+    }
+    let swap = BootSwapReq {
+        len: len.into(),
+        checksum: checksum.into(),
+    };
+    // not repeated, a device that got it is busy copying the image and doesn't respond
+    match protocol
+        .send_request(address, swap, |BootSwapRes| Ok(()))
+        .await
+    {
+        Err(err) if err.is_timeout() => debug!("{err}, waiting for the device anyway"),
+        result => result?,
+    }
+
+    let deadline = tokio::time::Instant::now() + BOOT_TIMEOUT;
+    loop {
+        match Info::fetch(protocol, address).await {
+            Err(err) if err.is_timeout() && tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::{fs, path::Path};
+
+use pico_iox16_protocol::settings::Calibration;
+
+use crate::{Error, Result, error::IoContext as _};
+
+/// A reference measurement: the raw reading of a channel and the value it should be
+/// calibrated to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferencePoint {
+    pub channel: usize,
+    pub raw: u16,
+    pub expected: i16,
+}
+
+/// Reads reference measurements from a CSV file with the columns `channel,raw,true`.
+/// A header line, empty lines and lines starting with `#` are skipped.
+pub fn load_reference(path: &Path) -> Result<Vec<ReferencePoint>> {
+    let text = fs::read_to_string(path)
+        .io_context(|| format!("Reading reference measurements {}", path.display()))?;
+    let mut points = Vec::new();
+    let mut first = true;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let parsed = match fields[..] {
+            [channel, raw, expected] => (|| {
+                Some(ReferencePoint {
+                    channel: channel.parse().ok()?,
+                    raw: raw.parse().ok()?,
+                    expected: expected.parse().ok()?,
+                })
+            })(),
+            _ => None,
+        };
+        match parsed {
+            Some(point) if point.channel < 16 => points.push(point),
+            Some(point) => {
+                return Err(Error::Invalid(format!(
+                    "Line {}: invalid channel {}",
+                    number + 1,
+                    point.channel
+                )));
+            }
+            // the first line may be a header
+            None if first => {}
+            None => {
+                return Err(Error::Invalid(format!(
+                    "Line {}: expected `channel,raw,true` with integer values",
+                    number + 1
+                )));
+            }
+        }
+        first = false;
+    }
+    Ok(points)
+}
+
+/// The result of [`fit`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fit {
+    /// The best calibration, with the full value range as limits.
+    pub calibration: Calibration,
+    /// Calibrated minus expected value of each reference point.
+    pub residuals: Vec<i32>,
+}
+
+impl Fit {
+    pub fn rms(&self) -> f64 {
+        let sum: f64 = self.residuals.iter().map(|&r| f64::from(r).powi(2)).sum();
+        (sum / self.residuals.len() as f64).sqrt()
+    }
+
+    pub fn max_abs(&self) -> u32 {
+        self.residuals
+            .iter()
+            .map(|r| r.unsigned_abs())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Finds the integer multiply, divide and add with the least squared error for the given
+/// `(raw, expected)` pairs, taking the integer arithmetic of the firmware into account.
+///
+/// A least squares line gives the slope, then every divisor is tried with the multipliers
+/// closest to the slope and the best offset for them.
+pub fn fit(points: &[(u16, i16)]) -> Result<Fit> {
+    let Some(&(first_raw, _)) = points.first() else {
+        return Err(Error::Invalid("No reference points".into()));
+    };
+    if points.iter().all(|&(raw, _)| raw == first_raw) {
+        return Err(Error::Invalid(
+            "At least two different raw values are required".into(),
+        ));
+    }
+    let n = points.len() as f64;
+    let mean_raw = points.iter().map(|&(raw, _)| f64::from(raw)).sum::<f64>() / n;
+    let mean_expected = points
+        .iter()
+        .map(|&(_, expected)| f64::from(expected))
+        .sum::<f64>()
+        / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(c, v), &(raw, expected)| {
+        let dr = f64::from(raw) - mean_raw;
+        (c + dr * (f64::from(expected) - mean_expected), v + dr * dr)
+    });
+    let slope = covariance / variance;
+    if slope.abs() > f64::from(i16::MAX) {
+        return Err(Error::Invalid(format!(
+            "The slope {slope:.1} cannot be represented"
+        )));
+    }
+
+    let evaluate = |calibration: &Calibration| -> (u64, Vec<i32>) {
+        let residuals: Vec<i32> = points
+            .iter()
+            .map(|&(raw, expected)| i32::from(calibration.apply(raw)) - i32::from(expected))
+            .collect();
+        let error = residuals
+            .iter()
+            .map(|&r| (i64::from(r) * i64::from(r)) as u64)
+            .sum();
+        (error, residuals)
+    };
+    let mut best: Option<(u64, Calibration, Vec<i32>)> = None;
+    'search: for divide in 1..=i16::MAX {
+        let nearest = (slope * f64::from(divide)).round() as i32;
+        for multiply in nearest - 1..=nearest + 1 {
+            let Ok(multiply) = i16::try_from(multiply) else {
+                continue;
+            };
+            let scaled_sum: i64 = points
+                .iter()
+                .map(|&(raw, expected)| {
+                    i64::from(expected)
+                        - i64::from(i32::from(raw) * i32::from(multiply) / i32::from(divide))
+                })
+                .sum();
+            let add = (scaled_sum as f64 / n).round().clamp(-32768.0, 32767.0) as i16;
+            let calibration = Calibration {
+                multiply,
+                divide,
+                add,
+                min: i16::MIN,
+                max: i16::MAX,
+            };
+            let (error, residuals) = evaluate(&calibration);
+            if best
+                .as_ref()
+                .is_none_or(|(best_error, ..)| error < *best_error)
+            {
+                best = Some((error, calibration, residuals));
+                if error == 0 {
+                    break 'search;
+                }
+            }
+        }
+    }
+    let (_, calibration, residuals) = best.expect("at least one divisor is tried");
+    Ok(Fit {
+        calibration,
+        residuals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(name: &str, text: &str) -> Result<Vec<ReferencePoint>> {
+        let path = crate::test_directory(name).join("reference.csv");
+        fs::write(&path, text).unwrap();
+        load_reference(&path)
+    }
+
+    #[test]
+    fn exact_line() {
+        let points: Vec<(u16, i16)> = (0..8)
+            .map(|i| (i * 512, (i * 512 * 5 / 2 + 5) as i16))
+            .collect();
+        let fit = fit(&points).unwrap();
+        assert_eq!(
+            fit.calibration,
+            Calibration {
+                multiply: 5,
+                divide: 2,
+                add: 5,
+                min: i16::MIN,
+                max: i16::MAX,
+            }
+        );
+        assert_eq!(fit.residuals, [0; 8]);
+        assert_eq!(fit.max_abs(), 0);
+    }
+
+    #[test]
+    fn noisy_line() {
+        let noise = [1, -2, 0, 2, -1, 1, -1, 0];
+        let points: Vec<(u16, i16)> = (0..8)
+            .map(|i| (i * 500, (i * 250 + 100) as i16 + noise[usize::from(i)]))
+            .collect();
+        let fit = fit(&points).unwrap();
+        let calibration = fit.calibration;
+        let slope = f64::from(calibration.multiply) / f64::from(calibration.divide);
+        assert!((slope - 0.5).abs() < 0.01, "slope {slope}");
+        assert!(fit.max_abs() <= 2, "residuals {:?}", fit.residuals);
+        assert!(fit.rms() <= 1.5, "rms {}", fit.rms());
+    }
+
+    #[test]
+    fn too_few_raw_values() {
+        assert!(matches!(fit(&[]), Err(Error::Invalid(_))));
+        assert!(matches!(fit(&[(100, 10)]), Err(Error::Invalid(_))));
+        assert!(matches!(
+            fit(&[(100, 10), (100, 20), (100, 30)]),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn reference_file() {
+        let points = reference(
+            "reference_file",
+            "channel,raw,true\n# comment\n\n0, 100, -5\n15,4095,32767\n",
+        )
+        .unwrap();
+        assert_eq!(
+            points,
+            [
+                ReferencePoint {
+                    channel: 0,
+                    raw: 100,
+                    expected: -5
+                },
+                ReferencePoint {
+                    channel: 15,
+                    raw: 4095,
+                    expected: 32767
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_reference_files() {
+        for (name, text, line) in [
+            ("reference_fields", "0,100,5\n0,100\n", 2),
+            ("reference_integer", "channel,raw,true\n0,100,1.5\n", 2),
+            ("reference_channel", "16,100,5\n", 1),
+            ("reference_range", "# raw values\n0,100,5\n1,65536,5\n", 3),
+        ] {
+            let Err(Error::Invalid(message)) = reference(name, text) else {
+                panic!("{name} was accepted");
+            };
+            assert!(
+                message.starts_with(&format!("Line {line}:")),
+                "{name}: {message}"
+            );
+        }
+        assert!(matches!(
+            load_reference(&crate::test_directory("reference_missing").join("reference.csv")),
+            Err(Error::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,59 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use pico_iox16_protocol::{
+    AddressRange, CAPABILITY_REPEATER, Forwarding, ForwardingGetReq, ForwardingGetRes,
+    ForwardingSetReq, ForwardingSetRes, MAX_FORWARDING_RANGES,
+};
+use pico_iox16_tool::{Error, Protocol};
+
+/// Sets the address ranges the repeater passes on to its downstream port if given, and prints
+/// the ones in effect.
+pub(crate) async fn forwarding(
+    device: &mut Protocol,
+    address: u16,
+    ranges: Option<Vec<RangeInclusive<u16>>>,
+) -> Result<()> {
+    if let Some(ranges) = ranges {
+        if ranges.len() > MAX_FORWARDING_RANGES {
+            return Err(Error::Invalid(format!(
+                "At most {MAX_FORWARDING_RANGES} address ranges can be forwarded"
+            ))
+            .into());
+        }
+        let mut forwarding = Forwarding::NONE;
+        for (range, addresses) in forwarding.ranges.iter_mut().zip(ranges) {
+            *range = AddressRange::new(addresses);
+        }
+        device
+            .send_request(address, ForwardingSetReq(forwarding), |ForwardingSetRes| {
+                Ok(())
+            })
+            .await?;
+    }
+    let forwarding = device
+        .send_request(address, ForwardingGetReq, |ForwardingGetRes(forwarding)| {
+            Ok(*forwarding)
+        })
+        .await?;
+    let ranges: Vec<String> = forwarding
+        .ranges
+        .iter()
+        .filter(|range| !range.is_empty())
+        .map(|range| match (range.first.get(), range.last.get()) {
+            (first, last) if first == last => format!("{first}"),
+            (first, last) => format!("{first}-{last}"),
+        })
+        .collect();
+    if ranges.is_empty() {
+        println!("Forwarding nothing");
+    } else {
+        println!("Forwarding requests to {}", ranges.join(", "));
+        if let Some(version) = device.protocol_version(address)
+            && !version.supports(CAPABILITY_REPEATER)
+        {
+            eprintln!("Warning: device {address} has no downstream port and forwards nothing");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use std::{fs, path::Path};
+
+use pico_iox16_protocol::settings::{Calibration, Threshold};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::{Error, Result, error::IoContext as _};
+
+/// List of devices and their desired settings, e.g. of one cabinet.
+///
+/// ```toml
+/// [[device]]
+/// label = "Cabinet 1, left"
+/// address = 65535
+/// new_address = 12
+/// baudrate = 1000000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Inventory {
+    #[serde(rename = "device", default)]
+    pub devices: Vec<InventoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InventoryEntry {
+    /// Free text identifying the device in reports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The serial device or the index of the `--device` option of the bus the device is
+    /// connected to. Defaults to the first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+    /// The current address of the device.
+    pub address: u16,
+    /// The desired address. Left unchanged if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_address: Option<u16>,
+    /// The desired baudrate. Left unchanged if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baudrate: Option<u32>,
+    /// Calibrations of all 16 inputs. Left unchanged if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibrations: Option<Vec<Calibration>>,
+    /// Thresholds of all 16 inputs. Left unchanged if not specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<Vec<Threshold>>,
+    /// The unique ID of the chip. If specified, the device at `address` is only provisioned
+    /// if it reports this ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_id: Option<String>,
+    /// The hardware revision of the board found by `scan`. Informational only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware_revision: Option<u16>,
+    /// The firmware version found by `scan`. Informational only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firmware_version: Option<String>,
+    /// The info string found by `scan`. Informational only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<String>,
+}
+
+impl InventoryEntry {
+    /// Returns the label, falling back to the current address.
+    pub fn name(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None => format!("device {}", self.address),
+        }
+    }
+
+    /// The address of the device once it is provisioned.
+    pub fn desired_address(&self) -> u16 {
+        self.new_address.unwrap_or(self.address)
+    }
+
+    /// The settings the entry asks for in the format of a dump file, with only the fields it
+    /// specifies, see [`crate::dump::diff`].
+    pub fn desired_settings(&self) -> Result<Value> {
+        #[derive(Serialize)]
+        struct Desired<'a> {
+            config: DesiredConfig,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            calibrations: Option<&'a [Calibration]>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            thresholds: Option<&'a [Threshold]>,
+        }
+        #[derive(Serialize)]
+        struct DesiredConfig {
+            address: u16,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            baudrate: Option<u32>,
+        }
+        let check = |what: &str, len: Option<usize>| match len {
+            Some(len) if len != 16 => Err(Error::Invalid(format!(
+                "{}: expected 16 {what}, got {len}",
+                self.name()
+            ))),
+            _ => Ok(()),
+        };
+        check("calibrations", self.calibrations.as_ref().map(Vec::len))?;
+        check("thresholds", self.thresholds.as_ref().map(Vec::len))?;
+        Value::try_from(Desired {
+            config: DesiredConfig {
+                address: self.desired_address(),
+                baudrate: self.baudrate,
+            },
+            calibrations: self.calibrations.as_deref(),
+            thresholds: self.thresholds.as_deref(),
+        })
+        .map_err(|err| Error::parse("Serializing the inventory entry", err))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl Inventory {
+    /// Loads an inventory from a JSON file if the extension is `.json` and from a TOML file
+    /// otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .io_context(|| format!("Reading inventory {}", path.display()))?;
+        let context = || format!("Parsing inventory {}", path.display());
+        if is_json(path) {
+            serde_json::from_str(&text).map_err(|err| Error::parse(context(), err))
+        } else {
+            toml::from_str(&text).map_err(|err| Error::parse(context(), err))
+        }
+    }
+
+    /// Saves the inventory in the format given by the extension, see [`Inventory::load`].
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = if is_json(path) {
+            serde_json::to_string_pretty(self)
+                .map_err(|err| Error::parse("Serializing the inventory", err))?
+                + "\n"
+        } else {
+            toml::to_string(self).map_err(|err| Error::parse("Serializing the inventory", err))?
+        };
+        fs::write(path, text).io_context(|| format!("Writing inventory {}", path.display()))
+    }
+}
@@ -1,23 +1,145 @@
-use std::{cmp::max, time::{Duration, Instant}};
+use std::{cmp::max, collections::{BTreeMap, VecDeque}, future::Future, io, ops::RangeTo, time::{Duration, Instant}};
 
-use anyhow::{Context as _, Result};
-use pico_iox16_protocol::{Message, RequestTrait, master_next};
-use tokio::{io::{AsyncReadExt as _, AsyncWriteExt as _}, time::timeout};
+use pico_iox16_protocol::{BROADCAST_ADDRESS, CheckReq, CheckRes, Command, ERROR_FLAG, FRAGMENT_FLAG, Footer, Fragments, Header, InputGetRes, MAX_FRAME_SIZE, MAX_RESPONSE_SIZE, Message, Reassembler, Received, RequestTrait, Response, Transport, master_next, next_frame, next_message};
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tracing::{Instrument as _, debug, debug_span, field, trace, warn};
 use tokio_serial::{SerialPort, SerialStream};
-use zerocopy::{IntoBytes, };
+use zerocopy::TryFromBytes as _;
 
+pub mod capture;
+pub mod classify;
+pub mod clock;
+pub mod device;
+pub mod dump;
+pub mod error;
+pub mod events;
+pub mod firmware;
+pub mod fit;
+pub mod inventory;
+pub mod mock;
+pub mod pattern;
+pub mod profile;
+pub mod rotate;
+pub mod sample;
+pub mod settings;
+pub mod trace;
+pub mod units;
+
+use capture::{CaptureWriter, Direction};
+pub use error::{Error, Result};
+use error::IoContext as _;
+
+/// Counters of the requests sent by a [`Protocol`] and of the errors encountered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub requests: u64,
+    pub timeouts: u64,
+    /// Received frames whose checksum did not match.
+    pub checksum_errors: u64,
+    /// Responses from an unexpected address or with an unexpected command, typically late
+    /// responses to a request that timed out. They are discarded.
+    pub unexpected_responses: u64,
+}
+
+/// Input values a device sent on its own after [`device::Device::set_report_interval`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub address: u16,
+    /// Counts the reports of the device, so a gap tells how many were lost
+    pub sequence: u8,
+    pub values: [i16; 16],
+}
+
+/// Reports kept until [`Protocol::next_report`] takes them, the oldest are dropped beyond.
+const MAX_REPORTS: usize = 256;
+
+/// Counts the frames with invalid checksums in `bytes`.
+fn count_invalid_frames(bytes: &[u8]) -> u64 {
+    count_frames(bytes).1
+}
+
+/// Counts the frames with valid and with invalid checksums in `bytes`.
+fn count_frames(mut bytes: &[u8]) -> (u64, u64) {
+    let mut count = (0, 0);
+    loop {
+        let (maybe_frame, processed) = next_frame(bytes);
+        match maybe_frame {
+            Some(frame) if !frame.is_valid() => count.1 += 1,
+            Some(_) => count.0 += 1,
+            None => return count,
+        }
+        bytes = &bytes[processed..];
+    }
+}
+
+/// Bits of a byte on the wire at most: start bit, 8 data bits, parity bit and two stop bits.
+const BITS_PER_BYTE: u64 = 12;
+/// Bytes devices send ahead of a response to settle the bus.
+const PREAMBLE: usize = 2;
+
+/// The byte stream a [`Protocol`] talks over, a serial port or e.g. an in-memory stream to a
+/// firmware running on the host.
+pub trait Port: AsyncRead + AsyncWrite + Unpin + Send {
+    fn baud_rate(&self) -> io::Result<u32>;
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()>;
+}
+
+impl Port for SerialStream {
+    fn baud_rate(&self) -> io::Result<u32> {
+        Ok(SerialPort::baud_rate(self)?)
+    }
+    fn set_baud_rate(&mut self, baudrate: u32) -> io::Result<()> {
+        Ok(SerialPort::set_baud_rate(self, baudrate)?)
+    }
+}
+
+/// Talks to devices over a [`Port`].
+///
+/// Every request runs in a `tracing` span named `request` with the command and address, and
+/// records the attempts and the time to the response. Responses, retries, discarded data and
+/// checksum errors are logged at debug level, the bytes sent and received at trace level.
 pub struct Protocol {
-    device: SerialStream,
+    device: Box<dyn Port>,
+    trace_frames: bool,
+    record: Option<CaptureWriter>,
+    statistics: Statistics,
+    retries: u32,
+    min_timeout: Duration,
+    /// Start of the clock of [`Transport::now_us`]
+    epoch: Instant,
+    /// When the last bytes were read, in microseconds since `epoch`
+    last_receive_us: u64,
     buf_len: usize,
-    buf: [u8; size_of::<Message<[u8; 1024]>>()],
+    buf: [u8; MAX_FRAME_SIZE],
+    /// Unique IDs to check before the first request to their address, with the alias they were
+    /// configured for, see [`Protocol::expect_unique_id`]
+    expected_ids: BTreeMap<u16, (String, String)>,
+    /// The protocol versions of the addresses requests were sent to, `None` for devices that
+    /// didn't tell, see [`Protocol::protocol_version`]
+    versions: BTreeMap<u16, Option<device::ProtocolVersion>>,
+    /// The sequence number of the last request, see [`Header::sequence`]
+    sequence: u8,
+    /// Reports received while waiting for responses, oldest first
+    reports: VecDeque<Report>,
 }
 
 impl Protocol {
-    pub fn new(device: SerialStream) -> Self {
+    pub fn new(device: impl Port + 'static) -> Self {
         Self {
-            device,
+            device: Box::new(device),
+            trace_frames: false,
+            record: None,
+            statistics: Statistics::default(),
+            retries: 0,
+            min_timeout: Duration::from_millis(1),
+            epoch: Instant::now(),
+            last_receive_us: 0,
             buf_len: 0,
-            buf: [0; size_of::<Message<[u8; 1024]>>()],
+            buf: [0; MAX_FRAME_SIZE],
+            expected_ids: BTreeMap::new(),
+            versions: BTreeMap::new(),
+            sequence: 0,
+            reports: VecDeque::new(),
         }
     }
 
@@ -25,45 +147,636 @@ impl Protocol {
         self.device.baud_rate().unwrap()
     }
 
+    /// Changes the baudrate of the serial port, e.g. to follow a device that was
+    /// reconfigured.
+    pub fn set_baudrate(&mut self, baudrate: u32) -> Result<()> {
+        self.device.set_baud_rate(baudrate).io_context(|| "Setting baudrate")
+    }
+
+    /// Enables hex dumps of all transmitted and received frames to stderr.
+    pub fn set_trace_frames(&mut self, trace_frames: bool) {
+        self.trace_frames = trace_frames;
+    }
+
+    /// Records all transmitted and received bytes to the given capture.
+    pub fn set_record(&mut self, record: Option<CaptureWriter>) {
+        self.record = record;
+    }
+
+    /// Sets how many times a request is sent again if no response was received in time.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Sets the minimum time to wait for a response. Commands whose `TIMEOUT_US` is
+    /// longer keep their own timeout. Defaults to 1 ms. The time the request and the response
+    /// take on the wire at the current baudrate is added to it.
+    pub fn set_min_timeout(&mut self, min_timeout: Duration) {
+        self.min_timeout = min_timeout;
+    }
+
+    /// Expects the device at `address` to have `unique_id`, as configured for `alias`. Before the
+    /// first request to the address, its unique ID is fetched and a warning printed if it differs,
+    /// so that a swapped board doesn't silently get the commands meant for another one.
+    pub fn expect_unique_id(&mut self, address: u16, alias: &str, unique_id: &str) {
+        self.expected_ids.insert(address, (alias.to_owned(), unique_id.to_owned()));
+    }
+
+    /// Checks the unique ID expected of `address`, if any and not checked yet. A device that
+    /// doesn't answer is left to the request that follows.
+    async fn check_unique_id(&mut self, address: u16) {
+        let Some((alias, expected)) = self.expected_ids.remove(&address) else {
+            return;
+        };
+        // boxed as fetching the ID sends a request itself
+        let Ok(id) = Box::pin(device::DeviceId::fetch(self, address)).await else {
+            return;
+        };
+        match id.unique_id.as_deref() {
+            Some(id) if id.eq_ignore_ascii_case(&expected) => {}
+            Some(id) => warn!("device {address} has unique ID {id}, but '{alias}' is configured with {expected}"),
+            None => warn!("device {address} doesn't report a unique ID, '{alias}' is configured with {expected}"),
+        }
+    }
+
+    /// The protocol version of the device at `address`, fetched before the first request to it.
+    /// `None` before that or if the device didn't answer.
+    pub fn protocol_version(&self, address: u16) -> Option<device::ProtocolVersion> {
+        self.versions.get(&address).copied().flatten()
+    }
+
+    /// Fetches the protocol version of `address` before the first request to it, and fails if
+    /// the device can't understand `command`, see [`device::ProtocolVersion::check`]. A device
+    /// that doesn't answer is left to the request that follows, and not asked again.
+    async fn check_protocol_version(&mut self, address: u16, command: Command) -> Result<()> {
+        // the requests that find out the version are understood by all devices of the major version
+        if matches!(command, Command::ProtocolVersionGet | Command::InfoGet) {
+            return Ok(());
+        }
+        if !self.versions.contains_key(&address) {
+            // boxed as fetching the version sends requests itself
+            let version = Box::pin(device::ProtocolVersion::fetch(self, address)).await.ok();
+            debug!(address, ?version, "Fetched protocol version");
+            self.versions.insert(address, version);
+        }
+        match self.protocol_version(address) {
+            Some(version) => version.check(command),
+            None => Ok(()),
+        }
+    }
+
+    /// The sequence number for the next request. Every attempt gets its own, so that the
+    /// response to an attempt that timed out can't pass for that to the next one.
+    fn next_sequence(&mut self) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.sequence
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// How long [`Protocol::send_request`] waits for the response to a request of `P`.
+    pub fn timeout<P: RequestTrait>(&self) -> Duration {
+        max(Duration::from_micros(P::TIMEOUT_US.into()), self.min_timeout) + self.transmission_time::<P>()
+    }
+
+    /// How long an exchange of `P` occupies the bus at most: the request and the response on
+    /// the wire and the time the device takes in between. Unlike [`Protocol::timeout`], there is
+    /// no allowance for the latency of the port.
+    pub fn bus_time<P: RequestTrait>(&self) -> Duration {
+        Duration::from_micros(P::TIMEOUT_US.into()) + self.transmission_time::<P>()
+    }
+
+    /// How long the request and the response of `P` take on the wire at the current baudrate.
+    fn transmission_time<P: RequestTrait>(&self) -> Duration {
+        self.wire_time(Message::<P>::WIRE_SIZE + PREAMBLE + Message::<P::Response>::WIRE_SIZE)
+    }
+
+    /// How long `bytes` take on the wire at the current baudrate.
+    fn wire_time(&self, bytes: usize) -> Duration {
+        match self.device.baud_rate() {
+            Ok(baudrate) if baudrate > 0 => Duration::from_micros(bytes as u64 * BITS_PER_BYTE * 1_000_000 / u64::from(baudrate)),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Discards buffered data and everything that can be read from the port without waiting,
+    /// e.g. responses that arrived after their request timed out. Returns the number of
+    /// bytes discarded.
+    pub async fn resync(&mut self) -> Result<usize> {
+        let mut discarded = 0;
+        loop {
+            if self.buf_len > 0 {
+                let stale = &self.buf[..self.buf_len];
+                if self.trace_frames {
+                    trace::trace_frames("RX stale", stale);
+                }
+                debug!(bytes = stale.len(), "Discarding stale data");
+                let (valid, invalid) = count_frames(stale);
+                let reports = self.keep_reports(..self.buf_len);
+                self.statistics.unexpected_responses += valid - reports;
+                self.statistics.checksum_errors += invalid;
+                discarded += self.buf_len;
+                self.buf_len = 0;
+            }
+            // polls the read once, a timeout of zero does not wait
+            let Ok(n) = tokio::time::timeout(Duration::ZERO, self.device.read(&mut self.buf)).await else {
+                return Ok(discarded);
+            };
+            let n = n.io_context(|| "Draining serial port")?;
+            if n == 0 {
+                return Ok(discarded);
+            }
+            if let Some(record) = &mut self.record {
+                record.write(Direction::Rx, &self.buf[..n])?;
+            }
+            self.buf_len = n;
+        }
+    }
+
+    /// Sends a request and waits for the matching response. Responses that do not match the
+    /// address, command and sequence number of the request are discarded. If no matching
+    /// response arrives, the error tells what arrived instead, see [`Error::is_timeout`].
     pub async fn send_request<P: RequestTrait, R>(
         &mut self,
         address: u16,
         payload: P,
         handle_response: impl for<'v> FnOnce(&P::Response) -> Result<R>,
     ) -> Result<R> {
-        let timeout = Duration::from_micros(max(P::TIMEOUT_US.into(), 1000));
-        let message = Message::new_request(address, P::COMMAND, payload);
-        self.device.write_all(message.as_bytes()).await.context(format!("Sending {} request", P::COMMAND))?;
-        self.device.flush().await.context(format!("Sending {} request", P::COMMAND))?;
-        let start = Instant::now();
-        let mut elapsed = Duration::ZERO;
+        self.check_unique_id(address).await;
+        self.check_protocol_version(address, P::COMMAND).await?;
+        let timeout = self.timeout::<P>();
+        let span = debug_span!("request", command = %P::COMMAND, address, attempts = field::Empty, elapsed_us = field::Empty);
+        async move {
+            let mut frame = [0; MAX_RESPONSE_SIZE];
+            let mut attempt = 0;
+            'retry: loop {
+                self.resync().await?;
+                let sequence = self.next_sequence();
+                self.send_message(&Message::new_request(address, P::COMMAND, sequence, payload)).await?;
+                self.statistics.requests += 1;
+                let checksum_errors = self.statistics.checksum_errors;
+                let mut unexpected = None;
+                let start = Instant::now();
+                let mut elapsed = Duration::ZERO;
+                loop {
+                    if elapsed >= timeout {
+                        self.statistics.timeouts += 1;
+                        if attempt < self.retries {
+                            attempt += 1;
+                            debug!(attempt, timeout_us = timeout.as_micros() as u64, "Timed out, retrying");
+                            continue 'retry;
+                        }
+                        tracing::Span::current().record("attempts", attempt + 1);
+                        debug!(timeout_us = timeout.as_micros() as u64, "Timed out");
+                        let frames = self.statistics.checksum_errors - checksum_errors;
+                        return Err(match unexpected {
+                            Some((actual, _)) if actual != address => Error::AddressMismatch { command: P::COMMAND, expected: address, actual },
+                            Some((_, actual)) => Error::UnexpectedCommand { expected: P::COMMAND, actual },
+                            None if frames > 0 => Error::CrcMismatch { command: P::COMMAND, frames },
+                            None => Error::Timeout { command: P::COMMAND },
+                        });
+                    }
+                    let Ok(received) = tokio::time::timeout(timeout - elapsed, self.receive(&mut frame)).await else {
+                        elapsed = start.elapsed();
+                        continue;
+                    };
+                    let received = received?;
+                    if let (Some((response_address, response)), _) = master_next(&frame[..received.len]) {
+                        // the frame was received whole, so it starts with its header
+                        let response_sequence = Header::try_ref_from_prefix(&frame[..received.len]).unwrap().0.sequence;
+                        match (P::get_response(response), response) {
+                            (_, Response::InputReport(values)) => self.keep_report(response_address, response_sequence, values),
+                            // a late response to an earlier attempt or request, or a duplicate
+                            _ if response_address == address && response_sequence != sequence => {
+                                self.statistics.unexpected_responses += 1;
+                                debug!(sequence = response_sequence, expected = sequence, "Discarding response with another sequence number");
+                            }
+                            (Some(response), _) if response_address == address => {
+                                let elapsed_us = start.elapsed().as_micros() as u64;
+                                let span = tracing::Span::current();
+                                span.record("attempts", attempt + 1);
+                                span.record("elapsed_us", elapsed_us);
+                                debug!("Received response");
+                                return handle_response(response);
+                            }
+                            // retrying wouldn't change the device's mind
+                            (_, Response::Error(command, error)) if response_address == address && command == P::COMMAND => {
+                                tracing::Span::current().record("attempts", attempt + 1);
+                                debug!(code = %error.code, "Request rejected");
+                                return Err(Error::Rejected { command, code: error.code });
+                            }
+                            _ => {
+                                // a stale response, keep waiting for the one to this request
+                                self.statistics.unexpected_responses += 1;
+                                debug!(address = response_address, command = %response.command(), "Discarding unexpected response");
+                                unexpected = Some((response_address, response.command()));
+                            }
+                        }
+                    }
+                    elapsed = start.elapsed();
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Waits for the next report of a device that was told to send its inputs on its own, see
+    /// [`device::Device::set_report_interval`]. Takes reports that arrived during requests
+    /// first. Other frames are discarded. Can be cancelled, e.g. by a timeout, without losing
+    /// reports.
+    pub async fn next_report(&mut self) -> Result<Report> {
+        let mut frame = [0; MAX_RESPONSE_SIZE];
+        loop {
+            if let Some(report) = self.reports.pop_front() {
+                return Ok(report);
+            }
+            let received = self.receive(&mut frame).await?;
+            match master_next(&frame[..received.len]) {
+                (Some((address, Response::InputReport(values))), _) => {
+                    let sequence = Header::try_ref_from_prefix(&frame[..received.len]).unwrap().0.sequence;
+                    self.keep_report(address, sequence, values);
+                }
+                (Some((address, response)), _) => {
+                    self.statistics.unexpected_responses += 1;
+                    debug!(address, command = %response.command(), "Discarding unexpected response");
+                }
+                (None, _) => {}
+            }
+        }
+    }
+
+    /// Queues a report for [`Protocol::next_report`].
+    fn keep_report(&mut self, address: u16, sequence: u8, values: &InputGetRes) {
+        trace!(address, sequence, "Received report");
+        if self.reports.len() == MAX_REPORTS {
+            self.reports.pop_front();
+        }
+        self.reports.push_back(Report { address, sequence, values: values.values.map(|value| value.get()) });
+    }
+
+    /// Queues the reports among the buffered frames in `range`. Returns how many there were.
+    /// Splits the frames like [`count_frames`], so the reports are among the frames it counts
+    /// as valid.
+    fn keep_reports(&mut self, range: RangeTo<usize>) -> u64 {
+        let mut bytes = &self.buf[range];
+        let mut reports = Vec::new();
         loop {
-            if elapsed >= timeout {
-                return Err(anyhow::anyhow!("Timed out waiting for response"));
+            let (maybe_frame, processed) = next_frame(bytes);
+            let Some(frame) = maybe_frame else {
+                break;
+            };
+            if let (Some((address, Response::InputReport(values))), _) = master_next(frame.bytes) {
+                reports.push((address, frame.header.sequence, *values));
+            }
+            bytes = &bytes[processed..];
+        }
+        let count = reports.len() as u64;
+        for (address, sequence, values) in reports {
+            self.keep_report(address, sequence, &values);
+        }
+        count
+    }
+
+    /// Sends a request to all devices on the bus at [`BROADCAST_ADDRESS`], e.g. to switch their
+    /// outputs at the same time. They don't respond, so this just waits as long as they take
+    /// to handle the request. Only for [`Command::broadcast`] commands.
+    pub async fn send_broadcast<P: RequestTrait>(&mut self, payload: P) -> Result<()> {
+        if !P::COMMAND.broadcast() {
+            return Err(Error::Invalid(format!("{} requests cannot be broadcast", P::COMMAND)));
+        }
+        debug!(command = %P::COMMAND, "Broadcasting");
+        self.resync().await?;
+        let sequence = self.next_sequence();
+        self.send_message(&Message::new_request(BROADCAST_ADDRESS, P::COMMAND, sequence, payload)).await?;
+        self.statistics.requests += 1;
+        tokio::time::sleep(self.timeout::<P>()).await;
+        Ok(())
+    }
+}
+
+impl Protocol {
+    /// Sends a payload of any length in fragments of `fragment_size` bytes, see [`Fragments`],
+    /// and returns the payload of the response, reassembled if the device sent it in fragments.
+    /// For commands whose payloads don't fit into a frame, e.g. waveform buffers, or whose
+    /// frames must be short at low baudrates. `timeout` is how long the device takes to respond
+    /// and at most between two fragments of its response, the time on the wire is added.
+    pub async fn send_fragmented(&mut self, address: u16, command: Command, payload: &[u8], fragment_size: usize, timeout: Duration) -> Result<Vec<u8>> {
+        self.check_unique_id(address).await;
+        let split = |sequence| Fragments::new_request(address, command, sequence, payload, fragment_size)
+            .ok_or_else(|| Error::Invalid(format!("Cannot split {} bytes into fragments of {fragment_size} bytes", payload.len())));
+        let total = split(0)?.total();
+        let timeout = max(timeout, self.min_timeout);
+        let span = debug_span!("request", %command, address, fragments = total, attempts = field::Empty, elapsed_us = field::Empty);
+        async move {
+            let mut frame = [0; MAX_FRAME_SIZE];
+            let mut attempt = 0;
+            loop {
+                self.resync().await?;
+                let sequence = self.next_sequence();
+                let fragments = split(sequence)?;
+                for fragment in fragments.clone() {
+                    let len = fragment.write_to(&mut frame).unwrap();
+                    self.send(&frame[..len]).await?;
+                }
+                self.statistics.requests += 1;
+                let start = Instant::now();
+                let first_timeout = timeout + self.wire_time(fragments.wire_size() + PREAMBLE + MAX_FRAME_SIZE);
+                match self.receive_fragmented(address, command, sequence, first_timeout, timeout, &mut frame).await {
+                    Ok(payload) => {
+                        let span = tracing::Span::current();
+                        span.record("attempts", attempt + 1);
+                        span.record("elapsed_us", start.elapsed().as_micros() as u64);
+                        debug!(bytes = payload.len(), "Received response");
+                        return Ok(payload);
+                    }
+                    Err(err) if err.is_timeout() => {
+                        self.statistics.timeouts += 1;
+                        if attempt == self.retries {
+                            tracing::Span::current().record("attempts", attempt + 1);
+                            debug!(%err, "Timed out");
+                            return Err(err);
+                        }
+                        attempt += 1;
+                        debug!(attempt, %err, "Timed out, retrying");
+                    }
+                    Err(err) => return Err(err),
+                }
             }
-            let Ok(n) = tokio::time::timeout(timeout - elapsed, self.device.read(&mut self.buf[self.buf_len..])).await else {
-                elapsed = start.elapsed();
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Waits for the response of [`Protocol::send_fragmented`] with `sequence`, `first_timeout`
+    /// for its first frame and `timeout` plus the time on the wire for each further fragment.
+    async fn receive_fragmented(&mut self, address: u16, command: Command, sequence: u8, first_timeout: Duration, timeout: Duration, frame: &mut [u8]) -> Result<Vec<u8>> {
+        let checksum_errors = self.statistics.checksum_errors;
+        let mut reassembler = Reassembler::new();
+        let mut payload = Vec::new();
+        let mut unexpected = None;
+        let mut fragment_error = None;
+        let mut deadline = Instant::now() + first_timeout;
+        loop {
+            let Ok(received) = tokio::time::timeout_at(deadline.into(), self.receive(frame)).await else {
+                let frames = self.statistics.checksum_errors - checksum_errors;
+                return Err(match (fragment_error, unexpected) {
+                    (Some(source), _) => Error::Fragment { command, source },
+                    (None, Some((actual, _))) if actual != address => Error::AddressMismatch { command, expected: address, actual },
+                    (None, Some((_, Some(actual)))) => Error::UnexpectedCommand { expected: command, actual },
+                    _ if frames > 0 => Error::CrcMismatch { command, frames },
+                    _ => Error::Timeout { command },
+                });
+            };
+            let received = received?;
+            let Some((header, body)) = next_message(&frame[..received.len]).0 else {
                 continue;
             };
-            let n = n.context(format!("Waiting for  {} response", P::COMMAND))?;
-            self.buf_len += n;
-            let (maybe_message, processed) = master_next(&self.buf[..self.buf_len]);
-            if let Some((response_address, response)) = maybe_message {
-                if response_address != address {
-                    return Err(anyhow::anyhow!("Received response from unexpected address 0x{:02X} (expected 0x{:02X})", response_address, address));
+            let (response_address, response_command) = (header.address.get(), header.command.get());
+            if response_address == address && header.sequence != sequence {
+                // a late response to an earlier attempt or request, or a duplicate
+                self.statistics.unexpected_responses += 1;
+                debug!(sequence = header.sequence, expected = sequence, "Discarding response with another sequence number");
+                continue;
+            }
+            if response_address == address && response_command == u16::from(command) | ERROR_FLAG
+                && let (Some((_, Response::Error(command, error))), _) = master_next(&frame[..received.len]) {
+                debug!(code = %error.code, "Request rejected");
+                return Err(Error::Rejected { command, code: error.code });
+            }
+            if response_address != address || response_command & !FRAGMENT_FLAG != u16::from(command) {
+                // a stale response, keep waiting for the one to this request
+                self.statistics.unexpected_responses += 1;
+                debug!(address = response_address, command = %trace::command_name(response_command), "Discarding unexpected response");
+                unexpected = Some((response_address, Command::try_from(response_command & !FRAGMENT_FLAG).ok()));
+                continue;
+            }
+            if response_command & FRAGMENT_FLAG == 0 {
+                // the response fits into one frame
+                return Ok(body.to_vec());
+            }
+            match reassembler.push(header, body) {
+                Ok(data) => {
+                    payload.resize(data.size, 0);
+                    payload[data.offset..data.offset + data.data.len()].copy_from_slice(data.data);
+                    if data.complete {
+                        return Ok(payload);
+                    }
+                    deadline = Instant::now() + timeout + self.wire_time(MAX_FRAME_SIZE);
+                }
+                Err(err) => {
+                    debug!(%err, "Discarding fragment");
+                    fragment_error = Some(err);
+                }
+            }
+        }
+    }
+
+    /// Sends a `Check` request to each of the addresses and returns those that responded, in the
+    /// order of their responses. `progress` is called before each request.
+    ///
+    /// Unlike a [`Protocol::send_request`] per address, the next request does not wait for the
+    /// timeout of the previous one, only for the bus to be idle for its [`Protocol::bus_time`]
+    /// or after a response. Responses are matched to all requests within their timeout, so a
+    /// device answering late is still found. Devices must answer within the `TIMEOUT_US` of
+    /// `CheckReq` though, or their response may collide with the next request.
+    pub async fn scan(&mut self, addresses: impl IntoIterator<Item = u16>, mut progress: impl FnMut(u16)) -> Result<Vec<u16>> {
+        let timeout = self.timeout::<CheckReq>();
+        let bus_time = self.bus_time::<CheckReq>();
+        let mut addresses = addresses.into_iter().peekable();
+        // requests without a response yet along with their sequence numbers and deadlines,
+        // oldest first
+        let mut outstanding = VecDeque::<(u16, u8, Instant)>::new();
+        let mut found = Vec::new();
+        let mut frame = [0; Message::<CheckRes>::WIRE_SIZE];
+        let mut bus_idle = Instant::now();
+        self.resync().await?;
+        loop {
+            let now = Instant::now();
+            while outstanding.front().is_some_and(|&(_, _, deadline)| deadline <= now) {
+                outstanding.pop_front();
+                self.statistics.timeouts += 1;
+            }
+            let wake = match addresses.peek() {
+                Some(&address) if now >= bus_idle => {
+                    addresses.next();
+                    progress(address);
+                    let sequence = self.next_sequence();
+                    self.send_message(&Message::new_request(address, Command::Check, sequence, CheckReq)).await?;
+                    self.statistics.requests += 1;
+                    let sent = Instant::now();
+                    outstanding.push_back((address, sequence, sent + timeout));
+                    bus_idle = sent + bus_time;
+                    continue;
+                }
+                Some(_) => bus_idle,
+                None => match outstanding.back() {
+                    Some(&(_, _, deadline)) => deadline,
+                    None => break,
+                },
+            };
+            // tokio's timers have a resolution of 1 ms, which is longer than a bus time at high
+            // baudrates, so shorter waits poll the port instead
+            let wait = if wake.saturating_duration_since(now) < Duration::from_millis(1) {
+                tokio::time::timeout(Duration::ZERO, self.receive(&mut frame)).await
+            } else {
+                tokio::time::timeout_at(wake.into(), self.receive(&mut frame)).await
+            };
+            let Ok(received) = wait else {
+                tokio::task::yield_now().await;
+                continue;
+            };
+            let received = received?;
+            let Some((address, response)) = master_next(&frame[..received.len]).0 else {
+                continue;
+            };
+            let sequence = Header::try_ref_from_prefix(&frame[..received.len]).unwrap().0.sequence;
+            let index = outstanding.iter().position(|&(a, s, _)| (a, s) == (address, sequence));
+            match (response, index) {
+                (Response::Check(_), Some(index)) => {
+                    outstanding.remove(index);
+                    debug!(address, "Received response to scan");
+                    found.push(address);
+                    // the response to the last request just ended, so the bus is free again
+                    if index == outstanding.len() {
+                        bus_idle = Instant::now();
+                    }
                 }
-                if let Some(response) = P::get_response(response) {
-                    let result = handle_response(response);
-                    self.buf_len -= processed;
-                    self.buf.copy_within(processed.., 0);
-                    return result;
-                } else {
-                    return Err(anyhow::anyhow!("Received response with unexpected command {:?} (expected {:?})", response.command(), P::COMMAND));
+                _ => {
+                    self.statistics.unexpected_responses += 1;
+                    debug!(address, command = %response.command(), "Discarding unexpected response");
                 }
             }
-            self.buf_len -= processed;
-            self.buf.copy_within(processed.., 0);
-            elapsed = start.elapsed();
         }
+        Ok(found)
+    }
+}
+
+/// Sends requests to devices. Code that only needs [`Protocol::send_request`] can take any
+/// client, so it can be tested against a [`mock::MockProtocol`] instead of a port.
+pub trait ProtocolClient {
+    /// Sends a request and passes the response to `handle_response`, see
+    /// [`Protocol::send_request`].
+    fn send_request<P: RequestTrait, R>(&mut self, address: u16, payload: P, handle_response: impl FnOnce(&P::Response) -> Result<R>) -> impl Future<Output = Result<R>>;
+}
+
+impl ProtocolClient for Protocol {
+    fn send_request<P: RequestTrait, R>(&mut self, address: u16, payload: P, handle_response: impl FnOnce(&P::Response) -> Result<R>) -> impl Future<Output = Result<R>> {
+        Protocol::send_request(self, address, payload, handle_response)
+    }
+}
+
+/// Frames over the port, traced, recorded and counted in the [`Statistics`] like those of
+/// [`Protocol::send_request`]. Reads from the port can be cancelled, e.g. by a timeout, without
+/// losing data.
+impl Transport for Protocol {
+    type Error = Error;
+
+    fn now_us(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    async fn send(&mut self, frame: &[u8]) -> Result<()> {
+        if self.trace_frames {
+            trace::trace_frames("TX", frame);
+        }
+        trace!(bytes = frame.len(), "Sending");
+        self.device.write_all(frame).await.io_context(|| "Writing to the port")?;
+        self.device.flush().await.io_context(|| "Writing to the port")?;
+        if let Some(record) = &mut self.record {
+            record.write(Direction::Tx, frame)?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self, buf: &mut [u8]) -> Result<Received> {
+        loop {
+            // several frames may have arrived at once, e.g. a stale and the expected response
+            loop {
+                let (maybe_message, processed) = next_message(&self.buf[..self.buf_len]);
+                if self.trace_frames {
+                    trace::trace_frames("RX", &self.buf[..processed]);
+                }
+                let checksum_errors = count_invalid_frames(&self.buf[..processed]);
+                if checksum_errors > 0 {
+                    debug!(frames = checksum_errors, "Discarding frames with checksum errors");
+                }
+                self.statistics.checksum_errors += checksum_errors;
+                let frame = maybe_message.map(|(_, payload)| {
+                    let len = size_of::<Header>() + payload.len() + size_of::<Footer>();
+                    processed - len..processed
+                });
+                if let Some(frame) = &frame && let Some(buf) = buf.get_mut(..frame.len()) {
+                    buf.copy_from_slice(&self.buf[frame.clone()]);
+                }
+                self.buf_len -= processed;
+                self.buf.copy_within(processed.., 0);
+                match frame {
+                    Some(frame) if frame.len() <= buf.len() => {
+                        return Ok(Received { len: frame.len(), at_us: self.last_receive_us });
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+            let n = self.device.read(&mut self.buf[self.buf_len..]).await.io_context(|| "Reading from the port")?;
+            if n == 0 {
+                return Err(Error::io("Reading from the port", io::Error::new(io::ErrorKind::UnexpectedEof, "The port was closed")));
+            }
+            trace!(bytes = n, "Received");
+            if let Some(record) = &mut self.record {
+                record.write(Direction::Rx, &self.buf[self.buf_len..self.buf_len + n])?;
+            }
+            self.last_receive_us = self.now_us();
+            self.buf_len += n;
+        }
+    }
+}
+
+/// Creates an empty directory for the files of the test `name`.
+#[cfg(test)]
+fn test_directory(name: &str) -> std::path::PathBuf {
+    let directory = std::env::temp_dir().join(format!("pico_iox16_tool-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+#[cfg(test)]
+mod tests {
+    use pico_iox16_protocol::{Command, InputGetRes};
+    use tokio::io::DuplexStream;
+    use zerocopy::IntoBytes as _;
+
+    use super::*;
+
+    impl Port for DuplexStream {
+        fn baud_rate(&self) -> io::Result<u32> {
+            Ok(115_200)
+        }
+        fn set_baud_rate(&mut self, _baudrate: u32) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn resync_with_a_corrupted_length() -> anyhow::Result<()> {
+        let (mut device, host) = tokio::io::duplex(1024);
+        let mut protocol = Protocol::new(host);
+        let report = |sequence| Message::new_report(7, Command::InputGet, sequence, InputGetRes { values: [i16::from(sequence).into(); 16] });
+        // the length of the response announces most of the first report as its payload
+        let mut bytes = Message::new_response(7, Command::Check, 1, CheckRes).as_bytes().to_vec();
+        let length = (report(2).as_bytes().len() / 4) as u8;
+        (bytes[2], bytes[3]) = (length, !length);
+        bytes.extend_from_slice(report(2).as_bytes());
+        bytes.extend_from_slice(report(3).as_bytes());
+        device.write_all(&bytes).await?;
+        assert_eq!(protocol.resync().await?, bytes.len());
+        assert_eq!(protocol.statistics().checksum_errors, 1);
+        assert_eq!(protocol.statistics().unexpected_responses, 0);
+        assert_eq!(protocol.reports.iter().map(|report| report.sequence).collect::<Vec<_>>(), [3]);
+        Ok(())
     }
 }
@@ -0,0 +1,67 @@
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::{CheckReq, CheckRes};
+use pico_iox16_tool::Protocol;
+use tokio_serial::{SerialPortInfo, SerialPortType, available_ports};
+
+fn describe(port: &SerialPortInfo) -> String {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut description = format!("USB {:04x}:{:04x}", usb.vid, usb.pid);
+            for field in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                description += &format!(" {field}");
+            }
+            if let Some(serial_number) = &usb.serial_number {
+                description += &format!(", serial number {serial_number}");
+            }
+            description
+        }
+        SerialPortType::PciPort => "PCI".to_string(),
+        SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+        SerialPortType::Unknown => "unknown".to_string(),
+    }
+}
+
+/// Sends `Check` requests to addresses `0..=max_address` and 0xFFFF and returns the
+/// addresses that responded.
+async fn probe(port: &SerialPortInfo, baudrate: u32, max_address: u16) -> Result<Vec<u16>> {
+    let mut device = Protocol::new(crate::open_port(&port.port_name, baudrate)?);
+    let mut found = Vec::new();
+    for address in (0..=max_address).chain((max_address < 0xFFFF).then_some(0xFFFF)) {
+        if device
+            .send_request(address, CheckReq, |CheckRes| Ok(()))
+            .await
+            .is_ok()
+        {
+            found.push(address);
+        }
+    }
+    Ok(found)
+}
+
+/// Lists the available serial ports and optionally probes each of them for devices.
+pub(crate) async fn list_ports(baudrate: u32, probe_max_address: Option<u16>) -> Result<()> {
+    let ports = available_ports().context("Enumerating serial ports")?;
+    if ports.is_empty() {
+        println!("No serial ports found.");
+        return Ok(());
+    }
+    for port in &ports {
+        println!("{}  {}", port.port_name, describe(port));
+        let Some(max_address) = probe_max_address else {
+            continue;
+        };
+        match probe(port, baudrate, max_address).await {
+            Ok(found) if found.is_empty() => println!("    no devices at {baudrate} Hz"),
+            Ok(found) => println!(
+                "    devices at {baudrate} Hz: {}",
+                found
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Err(err) => println!("    probing failed: {err:#}"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Result;
+use pico_iox16_tool::{
+    rotate::{RotatingFile, Rotation},
+    sample::Format,
+};
+
+use crate::monitor::Bus;
+
+/// Appends the inputs of the devices on the given buses to `output` periodically until
+/// Ctrl-C is pressed. The header of the format is only written to new or empty files, and
+/// to every file started by the rotation.
+pub(crate) async fn log(
+    buses: Vec<Bus>,
+    interval: Duration,
+    format: Format,
+    output: &Path,
+    rotation: Rotation,
+) -> Result<()> {
+    let mut file = RotatingFile::open(output, rotation, format.header())?;
+    eprintln!(
+        "Logging {} device(s) to {} every {interval:?}, press Ctrl-C to stop...",
+        buses.iter().map(|bus| bus.sources.len()).sum::<usize>(),
+        output.display()
+    );
+    let mut samples = 0u64;
+    let result = crate::monitor::poll(buses, interval, false, |sample| {
+        file.write(format.format(sample).as_bytes())?;
+        samples += 1;
+        Ok(())
+    })
+    .await;
+    file.flush()?;
+    eprintln!("Wrote {samples} samples");
+    result
+}
@@ -1,59 +1,703 @@
-use std::time::Duration;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use clap::Parser;
-use anyhow::{Context as _, Result};
-use pico_iox16_tool::Protocol;
-use tokio_serial::SerialPortBuilderExt;
+use anyhow::{Context as _, Result, bail};
+use pico_iox16_tool::{Protocol, capture::CaptureWriter, rotate::{Rotation, parse_size}, sample::{Format, Source}, settings::{Alias, Settings, parse_address_range}, units::Units};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tracing_subscriber::{EnvFilter, filter::LevelFilter};
 
 mod scan;
 mod configure;
 mod calibrate;
+mod selftest;
+mod sniff;
+mod replay;
+#[cfg(feature = "simulate")]
+mod simulate;
+mod config;
+mod bench;
+mod stress;
+mod provision;
+mod verify;
+mod list_ports;
+mod read;
+mod ping;
+mod diagnostics;
+mod digital;
+mod debounce;
+mod power;
+mod forwarding;
+mod failsafe;
+mod output_defaults;
+mod user_data;
+mod reboot;
+mod update;
+mod baudtest;
+mod plot;
+mod monitor;
+mod log;
+mod sequence;
+mod calibrations;
+mod daemon;
+mod apply_profile;
+mod completions;
+mod manpages;
+
+use monitor::Bus;
 
 #[derive(Debug, Parser)]
 struct Args {
-    /// The serial device to use, e.g. /dev/ttyUSB0
-    device: String,
-    /// The baud rate for the serial connection
-    #[clap(short, long, default_value = "1000000")]
-    baudrate: u32,
+    /// The serial device to use, e.g. /dev/ttyUSB0. Required by all commands but `list-ports`,
+    /// `completions` and `manpages`. Defaults to `device` in ~/.config/pico_iox16/config.toml.
+    #[clap(env = "PICO_IOX16_DEVICE")]
+    device: Option<String>,
+    /// Additional serial devices, each connected to a separate bus. Supported by `monitor`, `log`
+    /// and `provision`. Addresses can be prefixed with the device or its index to select the bus,
+    /// e.g. `/dev/ttyUSB1:12` or `1:12`.
+    #[clap(short = 'D', long = "device", value_name = "DEVICE")]
+    more_devices: Vec<String>,
+    /// The baud rate for the serial connection [default: 1000000]
+    #[clap(short, long, env = "PICO_IOX16_BAUDRATE")]
+    baudrate: Option<u32>,
+    /// Number of times a request is repeated after a timeout [default: 0]
+    #[clap(long, env = "PICO_IOX16_RETRIES")]
+    retries: Option<u32>,
+    /// Minimum time in milliseconds to wait for a response. Increase this for USB-serial
+    /// adapters with a high latency timer. [default: 1]
+    #[clap(short, long, env = "PICO_IOX16_TIMEOUT")]
+    timeout: Option<u64>,
+    /// Hex-dump every transmitted and received frame to stderr
+    #[clap(long)]
+    trace_frames: bool,
+    /// Record all transmitted and received bytes to a capture file that can be replayed later
+    #[clap(long)]
+    record: Option<PathBuf>,
     #[clap(subcommand)]
     command: Command,
 }
 
 #[derive(Debug, Parser)]
 enum Command {
+    /// Lists the available serial ports with USB vendor/product IDs and serial numbers.
+    ListPorts{
+        /// Probe each port for devices at addresses 0 to the given address and 0xFFFF.
+        #[clap(short, long, value_name = "MAX_ADDRESS", num_args = 0..=1, default_missing_value = "15")]
+        probe: Option<u16>,
+    },
+    /// Prints the current input values of a device.
+    Read{
+        /// The address or alias of the device to read.
+        address: String,
+        /// Print raw input values instead of the units from the configuration file.
+        #[clap(long)]
+        raw: bool,
+    },
+    /// Sends `Check` requests to a device like ICMP ping and prints the round-trip times
+    /// and the loss rate. Runs until interrupted unless a count is given.
+    Ping{
+        /// The address or alias of the device to ping.
+        address: String,
+        /// Stop after this many requests.
+        #[clap(short = 'c', long)]
+        count: Option<u64>,
+        /// Time between requests in seconds.
+        #[clap(short, long, default_value = "1")]
+        interval: f64,
+    },
+    /// Prints the cause of the last reset, the number of brown-outs and the lost input samples of
+    /// a device, to tell whether its supply is undersized or its inputs are failing.
+    Diagnostics{
+        /// The address or alias of the device.
+        address: String,
+    },
+    /// Prints the levels of the spare GPIOs a device reads as digital inputs, e.g. door switches.
+    Digital{
+        /// The address or alias of the device.
+        address: String,
+    },
+    /// Prints, per input, the consecutive readings beyond its thresholds, whether a crossing is
+    /// waiting for the debounce, and its last crossings before and after debouncing, to tune the
+    /// thresholds and debounce settings.
+    Debounce{
+        /// The address or alias of the device.
+        address: String,
+    },
+    /// Prints the sample interval of a device, or sets it. With an interval and all outputs at 0%
+    /// the device sleeps between sweeps over its inputs, e.g. to run from a battery.
+    Power{
+        /// The address or alias of the device.
+        #[clap(required_unless_present = "broadcast")]
+        address: Option<String>,
+        /// Time between two sweeps over the inputs in milliseconds, 0 to sample continuously.
+        /// Persists across reboots.
+        #[clap(long)]
+        sample_interval: Option<u32>,
+        /// Set the sample interval of all devices on the bus at once. They don't respond, so
+        /// the interval in effect isn't printed.
+        #[clap(long, conflicts_with = "address", requires = "sample_interval")]
+        broadcast: bool,
+    },
+    /// Prints the address ranges a device built as repeater passes on to the bus segment behind
+    /// it, or sets them. It forwards nothing until they are set.
+    Forwarding{
+        /// The address or alias of the repeater.
+        address: String,
+        /// Address ranges like 0x100-0x1ff or single addresses, comma separated, at most 4.
+        /// Persists across reboots.
+        #[clap(long, value_delimiter = ',', value_parser = parse_address_range, conflicts_with = "clear")]
+        ranges: Option<Vec<std::ops::RangeInclusive<u16>>>,
+        /// Stop forwarding.
+        #[clap(long)]
+        clear: bool,
+    },
+    /// Stores the current duty cycles and frequencies of the outputs of a device for it to set
+    /// when it starts, instead of having them all at 0%.
+    OutputDefaults{
+        /// The address or alias of the device.
+        address: String,
+    },
+    /// Prints the failsafe of a device, or sets it: the duty cycles its outputs are forced to
+    /// once it receives no valid request for a while, e.g. because the host crashed. Persists
+    /// across reboots.
+    Failsafe{
+        /// The address or alias of the device.
+        address: String,
+        /// Time without a valid request in ms after which the outputs are forced, 0 to disable
+        /// the failsafe.
+        #[clap(long)]
+        timeout: Option<u32>,
+        /// Safe duty cycles in percent, comma separated from output 0. Outputs left out go to 0%.
+        #[clap(short, long, value_delimiter = ',', requires = "timeout")]
+        duty_cycles: Vec<f64>,
+    },
+    /// Prints the user data of a device, 256 bytes the host can keep metadata in, e.g. the
+    /// installation date or the serial numbers of the attached sensors. Or writes to it.
+    /// Persists across reboots and firmware updates.
+    #[clap(group(clap::ArgGroup::new("data")))]
+    UserData{
+        /// The address or alias of the device.
+        address: String,
+        /// The first byte to print or write.
+        #[clap(long, default_value_t = 0)]
+        offset: u16,
+        /// The number of bytes to print, up to the end by default.
+        #[clap(long, conflicts_with = "data")]
+        len: Option<u16>,
+        /// Write this text.
+        #[clap(long, group = "data")]
+        write: Option<String>,
+        /// Write these bytes given as hex digits, e.g. "01 ff".
+        #[clap(long, group = "data")]
+        write_hex: Option<String>,
+    },
+    /// Reboots a device, e.g. into the bootloader to flash a new firmware as UF2 file over its
+    /// USB port without pressing the BOOTSEL button.
+    Reboot{
+        /// The address or alias of the device.
+        #[clap(required_unless_present = "broadcast")]
+        address: Option<String>,
+        /// Start the bootloader in the boot ROM instead of the firmware.
+        #[clap(long)]
+        bootloader: bool,
+        /// Reboot all devices on the bus at once, without confirmation from any of them.
+        #[clap(long, conflicts_with = "address")]
+        broadcast: bool,
+    },
+    /// Writes a new firmware to a device over the bus and boots it, from a UF2 file or a raw
+    /// binary. The old firmware keeps running until the new one is complete and checked. The
+    /// configuration and user data are kept.
+    Update {
+        /// The address or alias of the device.
+        address: String,
+        /// The firmware image, a `.uf2` file or a binary of the flash from its start.
+        file: PathBuf,
+    },
+    /// Steps the device and the host through increasing baudrates, runs an echo pass at
+    /// each and reports the highest reliable rate. The original configuration is restored
+    /// afterwards. Note that every step writes the configuration to flash.
+    Baudtest{
+        /// The address or alias of the device to test.
+        address: String,
+        /// Baudrates to test, comma separated. Defaults to 115200 up to 3000000.
+        #[clap(short, long, value_delimiter = ',')]
+        rates: Vec<u32>,
+        /// Number of echo iterations per baudrate.
+        #[clap(short = 'n', long, default_value = "100")]
+        iterations: usize,
+    },
+    /// Draws a scrolling chart of selected input channels in the terminal. Press q to quit.
+    Plot{
+        /// The address or alias of the device to plot.
+        address: String,
+        /// The input channels to plot, comma separated.
+        #[clap(short, long, value_delimiter = ',', default_value = "0")]
+        channels: Vec<usize>,
+        /// Time between samples in milliseconds.
+        #[clap(short, long, default_value = "100")]
+        interval: u64,
+        /// Draw with plain ASCII characters instead of colored dots.
+        #[clap(long)]
+        ascii: bool,
+    },
+    /// Prints the input values of one or several devices periodically until interrupted.
+    Monitor{
+        /// The addresses or aliases of the devices to monitor.
+        #[clap(required = true)]
+        addresses: Vec<String>,
+        /// Time between samples in milliseconds.
+        #[clap(short, long, default_value = "1000")]
+        interval: u64,
+        /// The output format.
+        #[clap(short, long, value_enum, default_value = "text")]
+        format: Format,
+        /// Print raw input values instead of the units from the configuration file.
+        #[clap(long)]
+        raw: bool,
+        /// Have the devices send their inputs every interval on their own instead of asking for
+        /// them. Needs a port for each device, see `--device`.
+        #[clap(long)]
+        push: bool,
+    },
+    /// Appends the input values of one or several devices to a file periodically until interrupted.
+    #[clap(group(clap::ArgGroup::new("rotation").multiple(true)))]
+    Log{
+        /// The addresses or aliases of the devices to log.
+        #[clap(required = true)]
+        addresses: Vec<String>,
+        /// The file to append to.
+        #[clap(short, long)]
+        output: PathBuf,
+        /// Time between samples in milliseconds.
+        #[clap(short, long, default_value = "1000")]
+        interval: u64,
+        /// The output format.
+        #[clap(short, long, value_enum, default_value = "csv")]
+        format: Format,
+        /// Print raw input values instead of the units from the configuration file.
+        #[clap(long)]
+        raw: bool,
+        /// Start a new file when the current one would exceed this size, e.g. `100M`.
+        /// The old file is renamed with the time of the rotation appended to its stem.
+        #[clap(long, value_name = "SIZE", value_parser = parse_size, group = "rotation")]
+        rotate_size: Option<u64>,
+        /// Start a new file after this time, e.g. `1day` or `12h`.
+        #[clap(long, value_name = "DURATION", value_parser = humantime::parse_duration, group = "rotation")]
+        rotate_interval: Option<Duration>,
+        /// Compress rotated files with gzip.
+        #[clap(long, requires = "rotation")]
+        compress: bool,
+        /// Delete the oldest rotated files when all files together exceed this size, e.g. `10G`.
+        #[clap(long, value_name = "SIZE", value_parser = parse_size, requires = "rotation")]
+        max_disk: Option<u64>,
+    },
+    /// Computes input calibrations from reference measurements.
+    Calibrations{
+        #[clap(subcommand)]
+        command: CalibrationsCommand,
+    },
+    /// Applies the calibration, threshold and output default of a stored profile from
+    /// ~/.config/pico_iox16/profiles/<NAME>.toml to channels of a device.
+    ApplyProfile{
+        /// The address or alias of the device.
+        address: String,
+        /// The name of the profile.
+        name: String,
+        /// The channels to apply the profile to, comma separated. Defaults to all channels.
+        /// The output frequency is shared by outputs 2n and 2n+1.
+        #[clap(short, long, value_delimiter = ',')]
+        channels: Vec<usize>,
+    },
+    /// Prints a completion script for a shell, e.g. `pico_iox16_tool completions bash >
+    /// /etc/bash_completion.d/pico_iox16_tool`.
+    Completions{
+        /// The shell to generate the script for.
+        shell: clap_complete::Shell,
+    },
+    /// Writes man pages for the tool and each of its commands to a directory.
+    Manpages{
+        /// The directory to write the man pages to. Created if it does not exist.
+        #[clap(default_value = ".")]
+        directory: PathBuf,
+    },
+    /// Polls the threshold states of the devices listed in a TOML file and forwards
+    /// threshold crossings to stdout, syslog and a hook command, until interrupted.
+    Daemon{
+        /// The daemon configuration file.
+        config: PathBuf,
+    },
+    /// Plays back output patterns.
+    Sequence{
+        #[clap(subcommand)]
+        command: SequenceCommand,
+    },
     /// Scans all addresses for the presence of a device and prints the results.
     Scan{
         /// Highest address to scan. If not specified, scans all addresses up to 0xFFFF.
         /// Address 0xFFFF is always scanned, even if a lower max address is specified.
+        #[clap(conflicts_with = "range")]
         max_address: Option<u16>,
+        /// Scan only these address ranges like 1-200 or single addresses, comma separated,
+        /// e.g. where the address plan of a bus puts its devices.
+        #[clap(long, value_delimiter = ',', value_parser = parse_address_range)]
+        range: Vec<std::ops::RangeInclusive<u16>>,
+        /// Skip these address ranges or single addresses, comma separated, e.g. 50,60-70.
+        #[clap(long, value_delimiter = ',', value_parser = parse_address_range)]
+        exclude: Vec<std::ops::RangeInclusive<u16>>,
+        /// Save the progress to this file while scanning, and continue where the last scan
+        /// left off if it exists, e.g. after an interruption. Removed when the scan completes.
+        #[clap(long, value_name = "FILE")]
+        resume: Option<PathBuf>,
+        /// Send each request as soon as the bus is free instead of after the timeout of the
+        /// previous one, several times faster at high baudrates. Devices answering slower
+        /// than their command timeout may collide with the next request.
+        #[clap(long)]
+        pipeline: bool,
+        /// Write the found devices with their firmware version, info string, label and unique
+        /// ID to an inventory file for `provision`. JSON if the extension is `.json`, TOML
+        /// otherwise.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
     },
     /// Sets address and baudrate for a device and reboots it.
     Configure{
-        /// The address of the device to configure.
-        address: u16,
-        /// The new address to set for the device.
+        /// The address or alias of the device to configure.
+        address: String,
+        /// The new address to set for the device. Not 0, which is reserved for broadcasts.
         #[clap(short = 'a', long)]
         new_address: Option<u16>,
         /// The new baud rate to set for the device.
         #[clap(short = 'b', long)]
         new_baudrate: Option<u32>,
+        /// The new parity to set for the device, e.g. `even` for 8E1 buses.
+        #[clap(long)]
+        new_parity: Option<configure::ParityArg>,
+        /// The new number of stop bits to set for the device.
+        #[clap(long, value_parser = clap::value_parser!(u8).range(1..=2))]
+        new_stop_bits: Option<u8>,
     },
     /// Interactive calibration of the inputs and outputs of the device at the given address
     Calibrate{
-        /// The address of the device to calibrate.
+        /// The address or alias of the device to calibrate.
+        address: String,
+    },
+    /// Production test for boards with each output wired to the input with the same index.
+    /// Drives known duty cycles and checks that the inputs follow within tolerance.
+    Selftest{
+        /// The address or alias of the device to test.
+        address: String,
+        /// Allowed deviation from the expected value in percent of the measured span.
+        #[clap(short, long, default_value = "5")]
+        tolerance: f64,
+        /// PWM frequency in Hz used while testing.
+        #[clap(short, long, default_value = "1000")]
+        frequency: u16,
+        /// Time in milliseconds to let the inputs settle and to average over at each step.
+        #[clap(short, long, default_value = "200")]
+        settle_ms: u64,
+    },
+    /// Applies address, baudrate, calibrations and thresholds to all devices listed in an
+    /// inventory file, verifies them after rebooting and writes a report.
+    Provision{
+        /// The inventory file, see `pico_iox16_tool::inventory::Inventory` for the format.
+        inventory: PathBuf,
+        /// The report file to write. Defaults to the inventory file with extension `report.toml`.
+        #[clap(short, long)]
+        report: Option<PathBuf>,
+    },
+    /// Compares a device with a dump file, or all devices of an inventory with the settings
+    /// they should have once provisioned, and prints the differing fields. Exits with status 1
+    /// if any device differs.
+    Verify{
+        /// A dump file as written by `config dump` if an address is given, which may pin only
+        /// some fields, or an inventory as read by `provision`.
+        file: PathBuf,
+        /// The address or alias of the device to compare with a dump file.
+        #[clap(short, long)]
+        address: Option<String>,
+        /// Write the differing settings, the configuration last, and reboot devices whose
+        /// configuration changed.
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Measures request/response round-trip times per command and prints min/median/p99.
+    /// Only read-only commands are benchmarked.
+    Bench{
+        /// The address or alias of the device to benchmark.
+        address: String,
+        /// Number of requests per command.
+        #[clap(short = 'n', long, default_value = "100")]
+        iterations: usize,
+    },
+    /// Sends a mix of read-only requests to one or several devices at maximum rate and reports
+    /// error, timeout and checksum statistics. Useful for qualifying cabling and termination.
+    Stress{
+        /// The addresses or aliases of the devices to stress.
+        #[clap(required = true)]
+        addresses: Vec<String>,
+        /// Duration of the test in seconds.
+        #[clap(short, long, default_value = "10")]
+        duration: u64,
+    },
+    /// Passively listens on the bus and prints a decoded timeline of the requests and responses
+    /// of other masters. Never transmits anything.
+    Sniff{
+        /// Also print a hex dump of each frame.
+        #[clap(long)]
+        dump: bool,
+    },
+    /// Replays the requests of a capture recorded with `--record` and prints the responses.
+    Replay{
+        /// The capture file to replay.
+        capture: PathBuf,
+        /// Replay speed relative to the original timing. 0 sends each request as soon as
+        /// the previous one has been answered.
+        #[clap(short, long, default_value = "1")]
+        speed: f64,
+        /// Time in milliseconds to wait for each response.
+        #[clap(short, long, default_value = "100")]
+        timeout_ms: u64,
+        /// Also print a hex dump of each frame.
+        #[clap(long)]
+        dump: bool,
+    },
+    /// Saves or checks the configuration, calibrations and thresholds of a device.
+    Config{
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Runs the firmware on a mock board on the serial port, e.g. one end of a pseudo terminal
+    /// pair, so that host software can be tested without hardware. The inputs read fixed values.
+    #[cfg(feature = "simulate")]
+    Simulate{
+        /// The address of the simulated device.
+        #[clap(short, long, default_value = "65535")]
         address: u16,
     },
 }
 
+#[derive(Debug, Parser)]
+enum CalibrationsCommand {
+    /// Fits the integer multiply, divide and add of each channel to reference measurements
+    /// and prints the residuals. The CSV file has the columns `channel,raw,true`, where `raw`
+    /// is the uncalibrated reading and `true` the value it should be calibrated to.
+    Fit{
+        /// The CSV file with the reference measurements.
+        reference: PathBuf,
+        /// Write the calibrations of the fitted channels to the device with this address or alias.
+        #[clap(short, long, value_name = "ADDRESS")]
+        write: Option<String>,
+        /// Print the residual of every reference point.
+        #[clap(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Debug, Parser)]
+enum SequenceCommand {
+    /// Sends the keyframes of a TOML pattern file as timed `OutputSet` requests, with
+    /// optional ramps. Outputs are switched off if interrupted with Ctrl-C.
+    Play{
+        /// The pattern file.
+        file: PathBuf,
+        /// The address or alias of the device.
+        #[clap(required_unless_present = "broadcast")]
+        address: Option<String>,
+        /// Repeat the pattern until interrupted.
+        #[clap(short, long)]
+        r#loop: bool,
+        /// Play the pattern on all devices on the bus at once, in step. Failed frames go
+        /// unnoticed, as the devices don't respond.
+        #[clap(long, conflicts_with = "address")]
+        broadcast: bool,
+    },
+}
+
+#[derive(Debug, Parser)]
+enum ConfigCommand {
+    /// Saves the settings of a device to a TOML dump file.
+    Dump{
+        /// The address or alias of the device.
+        address: String,
+        /// The dump file to write.
+        file: PathBuf,
+    },
+    /// Compares the settings of a device with a dump file and prints a field-level diff.
+    /// Only the fields present in the file are compared. Exits with status 1 if any differ.
+    Diff{
+        /// The address or alias of the device.
+        address: String,
+        /// The dump file to compare with.
+        file: PathBuf,
+    },
+}
+
+/// Finds a bus by its serial device or index.
+fn find_bus(bus: &str, devices: &[String]) -> Option<usize> {
+    devices.iter().position(|device| device == bus)
+        .or_else(|| bus.parse().ok().filter(|&index| index < devices.len()))
+}
+
+fn open_port(device: &str, baudrate: u32) -> Result<SerialStream> {
+    tokio_serial::new(device, baudrate).timeout(Duration::from_micros(100))
+        .open_native_async().with_context(|| format!("Opening serial port {device}"))
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
+    // diagnostics of the library, e.g. `RUST_LOG=pico_iox16_tool=debug` for retries, warnings by default
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::builder().with_default_directive(LevelFilter::WARN.into()).from_env_lossy()).with_writer(std::io::stderr).init();
     let args = Args::parse();
-    let mut device = Protocol::new(tokio_serial::new(&args.device, args.baudrate).timeout(Duration::from_micros(100))
-        .open_native_async().context("Opening serial port")?);
+    match &args.command {
+        Command::Completions { shell } => return completions::completions(*shell),
+        Command::Manpages { directory } => return manpages::manpages(directory),
+        _ => {}
+    }
+    let settings = Settings::load()?;
+    let baudrate = args.baudrate.or(settings.baudrate).unwrap_or(1_000_000);
+    if let Command::ListPorts { probe } = args.command {
+        return list_ports::list_ports(baudrate, probe).await;
+    }
+    if let Command::Calibrations { command: CalibrationsCommand::Fit { reference, write: None, verbose } } = &args.command {
+        calibrations::fit_reference(reference, *verbose)?;
+        return Ok(());
+    }
+    let devices: Vec<String> = args.device.clone().or(settings.device.clone()).into_iter().chain(args.more_devices.clone()).collect();
+    let Some(device) = devices.first() else {
+        bail!("No serial device specified");
+    };
+    let protocol = |name: &str, port: SerialStream, record: Option<CaptureWriter>| {
+        let mut device = Protocol::new(port);
+        for (alias, address, unique_id) in settings.unique_ids(name) {
+            device.expect_unique_id(address, alias, unique_id);
+        }
+        device.set_trace_frames(args.trace_frames);
+        device.set_record(record);
+        device.set_retries(args.retries.or(settings.retries).unwrap_or(0));
+        device.set_min_timeout(Duration::from_millis(args.timeout.or(settings.timeout).unwrap_or(1)));
+        device
+    };
+    let resolve = |address: &str| settings.resolve_address_on(address, device);
+    // no address for commands that broadcast instead
+    let target = |address: Option<&str>| address.map(resolve).transpose();
+    let units = |address: u16, raw: bool| if raw { Ok(Units::default()) } else { settings.units(address) };
+    // the alias to tag samples with prefers the one given by the user
+    let source = |bus: Option<&String>, address: &str, raw: bool| -> Result<Source> {
+        let resolved = resolve(address)?;
+        let label = if settings.aliases.contains_key(address) { Some(address) } else { settings.label(resolved) };
+        Ok(Source { bus: bus.cloned(), address: resolved, label: label.map(String::from), units: Arc::new(units(resolved, raw)?) })
+    };
+    // assigns the addresses, optionally prefixed with the bus, to the buses
+    let buses = |addresses: &[String], raw: bool| -> Result<Vec<Bus>> {
+        let mut buses = devices.iter()
+            .map(|device| Ok(Bus { protocol: protocol(device, open_port(device, baudrate)?, None), sources: Vec::new() }))
+            .collect::<Result<Vec<_>>>()?;
+        let multiple = devices.len() > 1;
+        for address in addresses {
+            let (bus, address) = match address.rsplit_once(':') {
+                Some((bus, rest)) if !settings.aliases.contains_key(address) => {
+                    (find_bus(bus, &devices).with_context(|| format!("Unknown bus '{bus}'"))?, rest)
+                }
+                // an alias of a device on a port goes to that bus
+                _ => match settings.aliases.get(address).and_then(Alias::port) {
+                    Some(port) => (find_bus(port, &devices).with_context(|| format!("'{address}' is a device on {port}, which is not among the buses"))?, address.as_str()),
+                    None => (0, address.as_str()),
+                },
+            };
+            settings.resolve_address_on(address, &devices[bus])?;
+            buses[bus].sources.push(source(multiple.then(|| &devices[bus]), address, raw)?);
+        }
+        Ok(buses)
+    };
+    if args.record.is_some() && devices.len() > 1 {
+        bail!("Recording is only supported with a single device");
+    }
+    match &args.command {
+        Command::Monitor { addresses, interval, format, raw, push } => return monitor::monitor(buses(addresses, *raw)?, Duration::from_millis(*interval), *push, *format).await,
+        Command::Log { addresses, output, interval, format, raw, rotate_size, rotate_interval, compress, max_disk } => {
+            let rotation = Rotation { max_size: *rotate_size, max_age: *rotate_interval, compress: *compress, max_disk: *max_disk };
+            return log::log(buses(addresses, *raw)?, Duration::from_millis(*interval), *format, output, rotation).await;
+        }
+        Command::Provision { inventory, report } => {
+            let report = report.clone().unwrap_or_else(|| inventory.with_extension("report.toml"));
+            let buses = devices.iter().map(|device| Ok((device.clone(), protocol(device, open_port(device, baudrate)?, None)))).collect::<Result<Vec<_>>>()?;
+            return provision::provision(buses, inventory, &report).await;
+        }
+        _ if devices.len() > 1 => bail!("Only `monitor`, `log` and `provision` support several devices"),
+        _ => {}
+    }
+    let port = open_port(device, baudrate)?;
+    let record = args.record.as_deref().map(CaptureWriter::create).transpose()?;
+    match args.command {
+        Command::Sniff { dump } => return sniff::sniff(port, dump, record).await,
+        Command::Replay { capture, speed, timeout_ms, dump } => return replay::replay(port, &capture, speed, Duration::from_millis(timeout_ms), dump).await,
+        #[cfg(feature = "simulate")]
+        Command::Simulate { address } => return simulate::simulate(port, address).await,
+        _ => {}
+    }
+    let mut device = protocol(device, port, record);
     match args.command {
-        Command::Scan { max_address } => scan::scan(&mut device, max_address).await,
-        Command::Configure { address, new_address, new_baudrate } => configure::configure(&mut device, address, new_address, new_baudrate).await,
-        Command::Calibrate { address } => calibrate::calibrate(&mut device, address).await,
+        Command::Read { address, raw } => {
+            let address = resolve(&address)?;
+            read::read(&mut device, address, &units(address, raw)?).await
+        }
+        Command::Ping { address, count, interval } => ping::ping(&mut device, resolve(&address)?, count, Duration::try_from_secs_f64(interval)?).await,
+        Command::Diagnostics { address } => diagnostics::diagnostics(&mut device, resolve(&address)?).await,
+        Command::Digital { address } => digital::digital(&mut device, resolve(&address)?).await,
+        Command::Debounce { address } => debounce::debounce(&mut device, resolve(&address)?).await,
+        Command::Power { address, sample_interval, .. } => power::power(&mut device, target(address.as_deref())?, sample_interval).await,
+        Command::UserData { address, offset, len, write, write_hex } => {
+            let write_hex = write_hex.as_deref().map(user_data::parse_hex).transpose()?;
+            let data = write.map(String::into_bytes).or(write_hex);
+            user_data::user_data(&mut device, resolve(&address)?, offset, len, data).await
+        }
+        Command::Forwarding { address, ranges, clear } => forwarding::forwarding(&mut device, resolve(&address)?, if clear { Some(Vec::new()) } else { ranges }).await,
+        Command::OutputDefaults { address } => output_defaults::output_defaults(&mut device, resolve(&address)?).await,
+        Command::Failsafe { address, timeout, duty_cycles } => failsafe::failsafe(&mut device, resolve(&address)?, timeout.map(|timeout| (timeout, duty_cycles))).await,
+        Command::Reboot { address, bootloader, .. } => reboot::reboot(&mut device, target(address.as_deref())?, bootloader).await,
+        Command::Update { address, file } => update::update(&mut device, resolve(&address)?, &file).await,
+        Command::Baudtest { address, rates, iterations } => {
+            let rates = if rates.is_empty() { baudtest::DEFAULT_RATES.to_vec() } else { rates };
+            baudtest::baudtest(&mut device, resolve(&address)?, rates, iterations).await
+        }
+        Command::Plot { address, channels, interval, ascii } => plot::plot(&mut device, resolve(&address)?, &channels, Duration::from_millis(interval), ascii).await,
+        Command::Calibrations { command: CalibrationsCommand::Fit { reference, write, verbose } } => {
+            // fitting without writing is handled before opening the port
+            let address = resolve(write.as_deref().unwrap())?;
+            let fits = calibrations::fit_reference(&reference, verbose)?;
+            calibrations::write(&mut device, address, &fits).await
+        }
+        Command::Sequence { command: SequenceCommand::Play { file, address, r#loop, .. } } => sequence::play(&mut device, target(address.as_deref())?, &file, r#loop).await,
+        Command::ApplyProfile { address, name, channels } => apply_profile::apply_profile(&mut device, resolve(&address)?, &name, &channels).await,
+        Command::Daemon { config } => daemon::daemon(&mut device, &config, &settings).await,
+        Command::Scan { max_address, range, exclude, resume, pipeline, output } => {
+            let addresses = scan::addresses(max_address, &range, &exclude);
+            scan::scan(&mut device, &addresses, pipeline, output.as_deref(), resume.as_deref(), &settings).await
+        }
+        Command::Configure { address, new_address, new_baudrate, new_parity, new_stop_bits } => configure::configure(&mut device, resolve(&address)?, new_address, new_baudrate, new_parity, new_stop_bits).await,
+        Command::Calibrate { address } => calibrate::calibrate(&mut device, resolve(&address)?).await,
+        Command::Config { command: ConfigCommand::Dump { address, file } } => config::dump(&mut device, resolve(&address)?, &file).await,
+        Command::Config { command: ConfigCommand::Diff { address, file } } => {
+            if !config::diff(&mut device, resolve(&address)?, &file).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Verify { file, address, fix } => {
+            let address = address.as_deref().map(resolve).transpose()?;
+            if !verify::verify(&mut device, &devices[0], &file, address, fix).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Bench { address, iterations } => bench::bench(&mut device, resolve(&address)?, iterations).await,
+        Command::Stress { addresses, duration } => {
+            let addresses = addresses.iter().map(|address| resolve(address)).collect::<Result<Vec<_>, _>>()?;
+            stress::stress(&mut device, &addresses, Duration::from_secs(duration)).await
+        }
+        Command::ListPorts { .. } | Command::Completions { .. } | Command::Manpages { .. } | Command::Sniff { .. } | Command::Replay { .. }
+            | Command::Monitor { .. } | Command::Log { .. } | Command::Provision { .. } => unreachable!(),
+        #[cfg(feature = "simulate")]
+        Command::Simulate { .. } => unreachable!(),
+        Command::Selftest { address, tolerance, frequency, settle_ms } => selftest::selftest(&mut device, resolve(&address)?, tolerance, frequency, Duration::from_millis(settle_ms)).await,
     }
 }
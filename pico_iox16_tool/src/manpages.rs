@@ -0,0 +1,15 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context as _, Result};
+use clap::CommandFactory as _;
+
+use crate::Args;
+
+/// Writes a man page for the tool and one for each subcommand to `directory`.
+pub(crate) fn manpages(directory: &Path) -> Result<()> {
+    fs::create_dir_all(directory).with_context(|| format!("Creating {}", directory.display()))?;
+    clap_mangen::generate_to(Args::command(), directory)
+        .with_context(|| format!("Writing man pages to {}", directory.display()))?;
+    println!("Man pages written to {}", directory.display());
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! A scripted [`ProtocolClient`] to unit test code that talks to devices without a port.
+//!
+//! ```
+//! use pico_iox16_protocol::{InputGetReq, InputGetRes, RequestTrait as _};
+//! use pico_iox16_tool::{Error, ProtocolClient, Result, mock::MockProtocol};
+//!
+//! /// The code under test: whether any input is above 1000.
+//! async fn any_high(client: &mut impl ProtocolClient, address: u16) -> Result<bool> {
+//!     client
+//!         .send_request(address, InputGetReq, |InputGetRes { values }| {
+//!             Ok(values.iter().any(|value| value.get() > 1000))
+//!         })
+//!         .await
+//! }
+//!
+//! let mut mock = MockProtocol::new();
+//! let mut values = [0.into(); 16];
+//! values[3] = 1500.into();
+//! mock.expect(5, InputGetReq, InputGetRes { values });
+//! mock.expect_error(5, InputGetReq, Error::Timeout { command: InputGetReq::COMMAND });
+//!
+//! let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! runtime.block_on(async {
+//!     assert!(any_high(&mut mock, 5).await.unwrap());
+//!     assert!(any_high(&mut mock, 5).await.unwrap_err().is_timeout());
+//! });
+//! ```
+
+use std::{collections::VecDeque, future, thread};
+
+use pico_iox16_protocol::{Command, RequestTrait};
+use zerocopy::{IntoBytes as _, TryFromBytes as _};
+
+use crate::{Error, ProtocolClient, Result};
+
+/// A request the code under test is expected to send next, and what it gets back.
+#[derive(Debug)]
+struct Exchange {
+    address: u16,
+    command: Command,
+    /// The expected payload, `None` if any payload is fine.
+    payload: Option<Vec<u8>>,
+    response: Result<Vec<u8>>,
+}
+
+/// Answers requests from a script instead of a device.
+///
+/// Requests must arrive in the order they were expected. A request that was not expected
+/// next panics, as does dropping the mock while expected requests are left, so a test fails
+/// even if the code under test swallows errors.
+#[derive(Debug, Default)]
+pub struct MockProtocol {
+    script: VecDeque<Exchange>,
+}
+
+impl MockProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expects `request` to be sent to `address` and answers with `response`.
+    pub fn expect<P: RequestTrait>(&mut self, address: u16, request: P, response: P::Response) {
+        self.push::<P>(
+            address,
+            Some(request.as_bytes().to_vec()),
+            Ok(response.as_bytes().to_vec()),
+        );
+    }
+
+    /// Expects a request with the command of `P` and any payload to be sent to `address`,
+    /// and answers with `response`.
+    pub fn expect_any<P: RequestTrait>(&mut self, address: u16, response: P::Response) {
+        self.push::<P>(address, None, Ok(response.as_bytes().to_vec()));
+    }
+
+    /// Expects `request` to be sent to `address` and fails it with `error`, e.g. an
+    /// [`Error::Timeout`] to simulate a device that does not respond.
+    pub fn expect_error<P: RequestTrait>(&mut self, address: u16, request: P, error: Error) {
+        self.push::<P>(address, Some(request.as_bytes().to_vec()), Err(error));
+    }
+
+    /// The number of expected requests that were not sent yet.
+    pub fn remaining(&self) -> usize {
+        self.script.len()
+    }
+
+    fn push<P: RequestTrait>(
+        &mut self,
+        address: u16,
+        payload: Option<Vec<u8>>,
+        response: Result<Vec<u8>>,
+    ) {
+        self.script.push_back(Exchange {
+            address,
+            command: P::COMMAND,
+            payload,
+            response,
+        });
+    }
+}
+
+impl ProtocolClient for MockProtocol {
+    fn send_request<P: RequestTrait, R>(
+        &mut self,
+        address: u16,
+        payload: P,
+        handle_response: impl FnOnce(&P::Response) -> Result<R>,
+    ) -> impl Future<Output = Result<R>> {
+        let Some(exchange) = self.script.pop_front() else {
+            panic!("Unexpected {} request to device {address}", P::COMMAND);
+        };
+        assert!(
+            exchange.address == address && exchange.command == P::COMMAND,
+            "Expected {} request to device {}, got {} request to device {address}",
+            exchange.command,
+            exchange.address,
+            P::COMMAND,
+        );
+        if let Some(expected) = &exchange.payload {
+            assert!(
+                expected == payload.as_bytes(),
+                "Unexpected payload of {} request to device {address}: {payload:?}",
+                P::COMMAND,
+            );
+        }
+        let result = exchange.response.and_then(|bytes| {
+            // the bytes were written from a `P::Response`, as the command matches
+            let response = P::Response::try_read_from_bytes(&bytes).unwrap();
+            handle_response(&response)
+        });
+        future::ready(result)
+    }
+}
+
+impl Drop for MockProtocol {
+    fn drop(&mut self) {
+        if let Some(exchange) = self.script.front()
+            && !thread::panicking()
+        {
+            panic!(
+                "{} expected request(s) not sent, the next is {} to device {}",
+                self.script.len(),
+                exchange.command,
+                exchange.address,
+            );
+        }
+    }
+}
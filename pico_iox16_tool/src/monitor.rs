@@ -0,0 +1,184 @@
+use std::{
+    io::{Write as _, stdout},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use pico_iox16_tool::{
+    Protocol,
+    device::Device,
+    sample::{Format, Sample, Source},
+};
+use tokio::sync::{mpsc, watch};
+
+/// A serial port with the devices to sample on it.
+pub(crate) struct Bus {
+    pub(crate) protocol: Protocol,
+    pub(crate) sources: Vec<Source>,
+}
+
+/// Reads the inputs of all sources every `interval` and passes the samples to `sink` until
+/// Ctrl-C is pressed. The buses are polled concurrently. Devices that do not respond are
+/// reported on stderr and skipped.
+///
+/// With `push`, the devices send their inputs on their own instead, which needs a port of its
+/// own for each device. Lost reports are counted on stderr, and the devices are told to stop
+/// before returning.
+pub(crate) async fn poll(
+    buses: Vec<Bus>,
+    interval: Duration,
+    push: bool,
+    mut sink: impl FnMut(&Sample) -> Result<()>,
+) -> Result<()> {
+    if buses.iter().all(|bus| bus.sources.is_empty()) {
+        bail!("At least one address is required");
+    }
+    if push && buses.iter().any(|bus| bus.sources.len() > 1) {
+        bail!("Reports need a port for each device, the reports of several would collide");
+    }
+    let (tx, mut rx) = mpsc::channel(64);
+    let (stop, stopped) = watch::channel(());
+    let mut pushing = Vec::new();
+    for Bus {
+        mut protocol,
+        sources,
+    } in buses
+    {
+        if sources.is_empty() {
+            continue;
+        }
+        let tx = tx.clone();
+        if push {
+            let source = sources.into_iter().next().unwrap();
+            let device = Device::new(protocol, source.address);
+            pushing.push(tokio::spawn(receive_reports(
+                device,
+                source,
+                interval,
+                tx,
+                stopped.clone(),
+            )));
+            continue;
+        }
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                for source in &sources {
+                    match Sample::fetch(&mut protocol, source).await {
+                        Ok(sample) => {
+                            if tx.send(sample).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(err) => report_error(source, &err),
+                    }
+                }
+            }
+        });
+    }
+    drop(tx);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let result = loop {
+        tokio::select! {
+            sample = rx.recv() => match sample {
+                Some(sample) => if let Err(err) = sink(&sample) {
+                    break Err(err);
+                },
+                None => break Ok(()),
+            },
+            _ = &mut ctrl_c => break Ok(()),
+        }
+    };
+    stop.send_replace(());
+    // the reports stop only after the devices were told
+    drop(rx);
+    for task in pushing {
+        task.await?;
+    }
+    result
+}
+
+/// Prints an error of a source on stderr.
+fn report_error(source: &Source, err: &pico_iox16_tool::Error) {
+    match &source.bus {
+        Some(bus) => eprintln!("Device {bus}:{}: {err:#}", source.address),
+        None => eprintln!("Device {}: {err:#}", source.address),
+    }
+}
+
+/// Has the device of `source` send its inputs every `interval` and passes them on as samples
+/// until `stop` changes. Sets the interval again if the reports stop coming, e.g. after the
+/// device rebooted.
+async fn receive_reports(
+    mut device: Device,
+    source: Source,
+    interval: Duration,
+    tx: mpsc::Sender<Sample>,
+    mut stop: watch::Receiver<()>,
+) {
+    let patience = interval * 3 + Duration::from_secs(1);
+    let mut configured = false;
+    let mut expected = None;
+    loop {
+        if !configured {
+            match device.set_report_interval(interval).await {
+                Ok(()) => configured = true,
+                Err(err) => report_error(&source, &err),
+            }
+        }
+        let report = tokio::select! {
+            report = tokio::time::timeout(patience, device.protocol().next_report()) => report,
+            _ = stop.changed() => break,
+        };
+        let report = match report {
+            Ok(Ok(report)) if report.address == source.address => report,
+            Ok(Ok(_)) => continue,
+            // the port is gone
+            Ok(Err(err)) => {
+                report_error(&source, &err);
+                return;
+            }
+            Err(_) => {
+                if configured {
+                    eprintln!("Device {}: no report within {patience:?}", source.address);
+                }
+                configured = false;
+                expected = None;
+                continue;
+            }
+        };
+        if let Some(expected) = expected {
+            let lost = report.sequence.wrapping_sub(expected);
+            if lost > 0 {
+                eprintln!("Device {}: lost {lost} report(s)", source.address);
+            }
+        }
+        expected = Some(report.sequence.wrapping_add(1));
+        // keeps receiving if the sink is gone, until told to stop
+        let _ = tx.send(Sample::new(&source, report.values)).await;
+    }
+    if let Err(err) = device.set_report_interval(Duration::ZERO).await {
+        report_error(&source, &err);
+    }
+}
+
+/// Prints the inputs of the devices on the given buses periodically.
+pub(crate) async fn monitor(
+    buses: Vec<Bus>,
+    interval: Duration,
+    push: bool,
+    format: Format,
+) -> Result<()> {
+    let mut stdout = stdout();
+    if let Some(header) = format.header() {
+        stdout.write_all(header.as_bytes())?;
+    }
+    poll(buses, interval, push, |sample| {
+        stdout.write_all(format.format(sample).as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    })
+    .await
+}
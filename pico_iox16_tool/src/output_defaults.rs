@@ -0,0 +1,28 @@
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::{OutputGetReq, OutputGetRes, OutputSetDefaultsReq, OutputSetDefaultsRes};
+use pico_iox16_tool::Protocol;
+
+/// Stores the current outputs of the device for it to set when it starts.
+pub(crate) async fn output_defaults(device: &mut Protocol, address: u16) -> Result<()> {
+    let groups = device
+        .send_request(address, OutputGetReq, |OutputGetRes(groups)| Ok(*groups))
+        .await
+        .context("Retrieving current outputs")?;
+    device
+        .send_request(
+            address,
+            OutputSetDefaultsReq(groups),
+            |OutputSetDefaultsRes| Ok(()),
+        )
+        .await
+        .context("Storing the outputs")?;
+    for (output, group) in groups.iter().flat_map(|group| [group; 2]).enumerate() {
+        let percent = f64::from(group.duty_cycle[output % 2].get()) * 100.0 / 32768.0;
+        println!(
+            "Output {output}: {percent:.1}% at {} Hz",
+            group.frequency.get()
+        );
+    }
+    println!("Device {address} starts with these outputs from now on");
+    Ok(())
+}
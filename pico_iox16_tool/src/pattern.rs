@@ -0,0 +1,272 @@
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+
+use pico_iox16_protocol::{OutputGroup, OutputSetReq};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result, error::IoContext as _};
+
+/// Timed output states played back by `sequence play`.
+///
+/// ```toml
+/// frequency = 1000
+/// period = 4000
+///
+/// [[keyframe]]
+/// time = 0
+/// outputs = { 0 = 0, 1 = 100 }
+///
+/// [[keyframe]]
+/// time = 2000
+/// outputs = { 0 = 100, 1 = 0 }
+/// ramp = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pattern {
+    /// PWM frequency of all outputs in Hz.
+    #[serde(default = "default_frequency")]
+    pub frequency: u16,
+    /// Time between the frames of a ramp in milliseconds.
+    #[serde(default = "default_step")]
+    pub step: u64,
+    /// Length of one loop in milliseconds. Defaults to the time of the last keyframe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(rename = "keyframe", default)]
+    pub keyframes: Vec<Keyframe>,
+}
+
+fn default_frequency() -> u16 {
+    1000
+}
+
+fn default_step() -> u64 {
+    50
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    /// Time since the start of the loop in milliseconds.
+    pub time: u64,
+    /// Duty cycles in percent by output index. Outputs not listed keep their previous
+    /// value, starting from 0 %.
+    pub outputs: BTreeMap<usize, f64>,
+    /// Ramp linearly from the previous keyframe instead of jumping at `time`.
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+/// Output state at a point in time within one loop of a [`Pattern`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub time: Duration,
+    /// Duty cycles in percent.
+    pub duty_cycles: [f64; 16],
+}
+
+impl Frame {
+    pub fn request(&self, frequency: u16) -> OutputSetReq {
+        let duty_cycle = |percent: f64| {
+            ((percent / 100.0 * 32768.0).round() as u16)
+                .min(0x8000)
+                .into()
+        };
+        let mut request = OutputSetReq::default();
+        for (i, group) in request.0.iter_mut().enumerate() {
+            *group = OutputGroup {
+                duty_cycle: [
+                    duty_cycle(self.duty_cycles[2 * i]),
+                    duty_cycle(self.duty_cycles[2 * i + 1]),
+                ],
+                frequency: frequency.into(),
+            };
+        }
+        request
+    }
+}
+
+impl Pattern {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .io_context(|| format!("Reading pattern {}", path.display()))?;
+        let pattern: Self = toml::from_str(&text)
+            .map_err(|err| Error::parse(format!("Parsing pattern {}", path.display()), err))?;
+        pattern.validate()?;
+        Ok(pattern)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.keyframes.is_empty() {
+            return Err(Error::Invalid("The pattern has no keyframes".into()));
+        }
+        if self.step == 0 {
+            return Err(Error::Invalid("The ramp step must not be 0".into()));
+        }
+        for (i, keyframe) in self.keyframes.iter().enumerate() {
+            if i > 0 && keyframe.time < self.keyframes[i - 1].time {
+                return Err(Error::Invalid(format!(
+                    "Keyframe {i} at {} ms is before the previous one",
+                    keyframe.time
+                )));
+            }
+            for (&output, &percent) in &keyframe.outputs {
+                if output >= 16 {
+                    return Err(Error::Invalid(format!(
+                        "Keyframe {i}: invalid output {output}, the device has 16 outputs"
+                    )));
+                }
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(Error::Invalid(format!(
+                        "Keyframe {i}: duty cycle {percent} % of output {output} out of range"
+                    )));
+                }
+            }
+        }
+        if self
+            .period
+            .is_some_and(|period| period < self.keyframes.last().unwrap().time)
+        {
+            return Err(Error::Invalid(
+                "The period is shorter than the time of the last keyframe".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Length of one loop.
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(
+            self.period
+                .unwrap_or_else(|| self.keyframes.last().map_or(0, |keyframe| keyframe.time)),
+        )
+    }
+
+    /// Expands the keyframes into the frames to send, including the intermediate frames of
+    /// ramps every `step` milliseconds.
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut duty_cycles = [0.0; 16];
+        let mut previous_time = 0;
+        for keyframe in &self.keyframes {
+            let start = duty_cycles;
+            for (&output, &percent) in &keyframe.outputs {
+                duty_cycles[output] = percent;
+            }
+            if keyframe.ramp && !frames.is_empty() {
+                let span = keyframe.time - previous_time;
+                for time in (previous_time + self.step..keyframe.time).step_by(self.step as usize) {
+                    let fraction = (time - previous_time) as f64 / span as f64;
+                    frames.push(Frame {
+                        time: Duration::from_millis(time),
+                        duty_cycles: std::array::from_fn(|i| {
+                            start[i] + (duty_cycles[i] - start[i]) * fraction
+                        }),
+                    });
+                }
+            }
+            frames.push(Frame {
+                time: Duration::from_millis(keyframe.time),
+                duty_cycles,
+            });
+            previous_time = keyframe.time;
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "
+        frequency = 1000
+        period = 4000
+
+        [[keyframe]]
+        time = 0
+        outputs = { 0 = 0, 1 = 100 }
+
+        [[keyframe]]
+        time = 2000
+        outputs = { 0 = 100, 1 = 0 }
+        ramp = true
+    ";
+
+    fn pattern(text: &str) -> Result<Pattern> {
+        let pattern: Pattern = toml::from_str(text).unwrap();
+        pattern.validate()?;
+        Ok(pattern)
+    }
+
+    #[test]
+    fn ramp() {
+        let pattern = pattern(EXAMPLE).unwrap();
+        assert_eq!(pattern.period(), Duration::from_secs(4));
+        let frames = pattern.frames();
+        // the keyframes and the 39 frames of the ramp between them
+        assert_eq!(frames.len(), 41);
+        assert_eq!(frames[0].duty_cycles[..2], [0.0, 100.0]);
+        assert_eq!(frames[20].time, Duration::from_millis(1000));
+        assert_eq!(frames[20].duty_cycles[..2], [50.0, 50.0]);
+        assert_eq!(frames[40].time, Duration::from_millis(2000));
+        assert_eq!(frames[40].duty_cycles[..2], [100.0, 0.0]);
+        assert!(
+            frames
+                .iter()
+                .all(|frame| frame.duty_cycles[2..] == [0.0; 14])
+        );
+    }
+
+    #[test]
+    fn outputs_keep_their_values() {
+        let pattern = pattern(
+            "
+            [[keyframe]]
+            time = 100
+            outputs = { 3 = 25 }
+
+            [[keyframe]]
+            time = 300
+            outputs = { 4 = 75 }
+            ",
+        )
+        .unwrap();
+        assert_eq!(pattern.period(), Duration::from_millis(300));
+        let frames = pattern.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].duty_cycles[3..5], [25.0, 75.0]);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for text in [
+            "",
+            "step = 0\n[[keyframe]]\ntime = 0\noutputs = {}",
+            "[[keyframe]]\ntime = 10\noutputs = {}\n[[keyframe]]\ntime = 5\noutputs = {}",
+            "[[keyframe]]\ntime = 0\noutputs = { 16 = 0 }",
+            "[[keyframe]]\ntime = 0\noutputs = { 0 = 100.5 }",
+            "period = 5\n[[keyframe]]\ntime = 10\noutputs = {}",
+        ] {
+            assert!(
+                matches!(pattern(text), Err(Error::Invalid(_))),
+                "{text:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn request() {
+        let mut duty_cycles = [0.0; 16];
+        duty_cycles[1] = 50.0;
+        duty_cycles[15] = 100.0;
+        let frame = Frame {
+            time: Duration::ZERO,
+            duty_cycles,
+        };
+        let request = frame.request(500);
+        assert_eq!(request.0[0].duty_cycle.map(|d| d.get()), [0, 0x4000]);
+        assert_eq!(request.0[7].duty_cycle.map(|d| d.get()), [0, 0x8000]);
+        assert!(request.0.iter().all(|group| group.frequency.get() == 500));
+    }
+}
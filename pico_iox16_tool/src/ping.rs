@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use pico_iox16_protocol::{CheckReq, CheckRes};
+use pico_iox16_tool::Protocol;
+
+/// Sends `CheckReq` to the device every `interval` and prints the round-trip time of each
+/// reply. Stops after `count` requests or on Ctrl-C and prints a summary.
+pub(crate) async fn ping(
+    device: &mut Protocol,
+    address: u16,
+    count: Option<u64>,
+    interval: Duration,
+) -> Result<()> {
+    println!("Pinging device {address} at {} Hz...", device.baudrate());
+    let mut times = Vec::new();
+    let mut sent = 0;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    while count.is_none_or(|count| sent < count) {
+        if sent > 0 {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = &mut ctrl_c => break,
+            }
+        }
+        let start = Instant::now();
+        let result = tokio::select! {
+            result = device.send_request(address, CheckReq, |CheckRes| Ok(())) => result,
+            _ = &mut ctrl_c => break,
+        };
+        match result {
+            Ok(()) => {
+                let time = start.elapsed();
+                println!(
+                    "Reply from {address}: seq={sent} time={:.3} ms",
+                    time.as_secs_f64() * 1000.0
+                );
+                times.push(time);
+            }
+            Err(err) => println!("No reply from {address}: seq={sent} {err:#}"),
+        }
+        sent += 1;
+    }
+
+    println!();
+    println!("--- device {address} ping statistics ---");
+    let received = times.len() as u64;
+    println!(
+        "{sent} requests sent, {received} replies received, {:.1}% loss",
+        (sent - received) as f64 * 100.0 / sent.max(1) as f64
+    );
+    if !times.is_empty() {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let min = times.iter().min().unwrap();
+        let max = times.iter().max().unwrap();
+        let avg = times.iter().sum::<Duration>() / times.len() as u32;
+        println!(
+            "rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+            ms(*min),
+            ms(avg),
+            ms(*max)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::VecDeque,
+    io::{Write as _, stdout},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Color, Print, PrintStyledContent, Stylize as _},
+    terminal::{
+        self, BeginSynchronizedUpdate, Clear, ClearType, EndSynchronizedUpdate,
+        EnterAlternateScreen, LeaveAlternateScreen,
+    },
+};
+use pico_iox16_protocol::{InputGetReq, InputGetRes};
+use pico_iox16_tool::Protocol;
+
+const COLORS: [Color; 6] = [
+    Color::Green,
+    Color::Yellow,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Red,
+    Color::Blue,
+];
+
+/// Width of the y axis labels.
+const LABEL_WIDTH: usize = 8;
+
+/// Switches the terminal to the alternate screen in raw mode and back when dropped,
+/// also if plotting fails.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Self)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), LeaveAlternateScreen, Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Returns `true` if the user asked to quit with `q`, `Esc` or Ctrl-C.
+fn quit_requested() -> Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+            && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                || key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn draw(
+    address: u16,
+    channels: &[usize],
+    history: &VecDeque<Vec<i16>>,
+    errors: u64,
+    ascii: bool,
+) -> Result<()> {
+    let (width, height) = terminal::size()?;
+    let plot_width = (width as usize).saturating_sub(LABEL_WIDTH + 1);
+    let plot_height = (height as usize).saturating_sub(2).max(2);
+    let min = history.iter().flatten().copied().min().unwrap_or(0);
+    let max = history
+        .iter()
+        .flatten()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(min + 1);
+    let row_of = |value: i16| {
+        let fraction = (value - min) as f64 / (max - min) as f64;
+        plot_height - 1 - (fraction * (plot_height - 1) as f64).round() as usize
+    };
+
+    // one cell per sample and row, later channels are drawn on top
+    let mut grid = vec![vec![None; plot_width]; plot_height];
+    let skip = history.len().saturating_sub(plot_width);
+    for (column, sample) in history.iter().skip(skip).enumerate() {
+        for (i, &value) in sample.iter().enumerate() {
+            grid[row_of(value)][column] = Some(i);
+        }
+    }
+
+    let mut stdout = stdout();
+    queue!(
+        stdout,
+        BeginSynchronizedUpdate,
+        MoveTo(0, 0),
+        Clear(ClearType::All)
+    )?;
+    for (row, cells) in grid.iter().enumerate() {
+        let label = if row == 0 {
+            format!("{max:>7}")
+        } else if row == plot_height - 1 {
+            format!("{min:>7}")
+        } else if row == plot_height / 2 {
+            format!("{:>7}", (min as i32 + max as i32) / 2)
+        } else {
+            String::new()
+        };
+        queue!(
+            stdout,
+            MoveTo(0, row as u16),
+            Print(format!("{label:>7} |"))
+        )?;
+        for cell in cells {
+            match cell {
+                Some(i) if ascii => queue!(stdout, Print(format!("{:X}", channels[*i])))?,
+                Some(i) => queue!(
+                    stdout,
+                    PrintStyledContent("•".with(COLORS[i % COLORS.len()]))
+                )?,
+                None => queue!(stdout, Print(' '))?,
+            }
+        }
+    }
+    queue!(
+        stdout,
+        MoveTo(0, plot_height as u16),
+        Print(format!("Device {address}, {errors} errors, q to quit |"))
+    )?;
+    if let Some(latest) = history.back() {
+        for (i, (&channel, value)) in channels.iter().zip(latest).enumerate() {
+            let marker = if ascii {
+                format!("{channel:X}").stylize()
+            } else {
+                "•".to_string().with(COLORS[i % COLORS.len()])
+            };
+            queue!(
+                stdout,
+                Print(format!(" {channel}:")),
+                PrintStyledContent(marker),
+                Print(format!(" {value}"))
+            )?;
+        }
+    }
+    queue!(stdout, EndSynchronizedUpdate)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Reads the given input channels every `interval` and draws them as a scrolling chart
+/// until `q` is pressed. The y axis scales to the visible values.
+pub(crate) async fn plot(
+    device: &mut Protocol,
+    address: u16,
+    channels: &[usize],
+    interval: Duration,
+    ascii: bool,
+) -> Result<()> {
+    if channels.is_empty() {
+        bail!("At least one channel is required");
+    }
+    if let Some(channel) = channels.iter().find(|&&channel| channel >= 16) {
+        bail!("Invalid channel {channel}, the device has 16 inputs");
+    }
+    let _screen = Screen::enter()?;
+    let mut history = VecDeque::new();
+    let mut errors = 0;
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        if quit_requested()? {
+            return Ok(());
+        }
+        match device
+            .send_request(address, InputGetReq, |InputGetRes { values }| {
+                Ok(channels
+                    .iter()
+                    .map(|&channel| values[channel].get())
+                    .collect())
+            })
+            .await
+        {
+            Ok(sample) => history.push_back(sample),
+            Err(_) => errors += 1,
+        }
+        let (width, _) = terminal::size()?;
+        while history.len() > width as usize {
+            history.pop_front();
+        }
+        draw(address, channels, &history, errors, ascii)?;
+    }
+}
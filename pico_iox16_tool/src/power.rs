@@ -0,0 +1,39 @@
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::{Power, PowerGetReq, PowerGetRes, PowerSetReq, PowerSetRes};
+use pico_iox16_tool::Protocol;
+
+/// Sets the sample interval of the device if given, and prints the one in effect. Without an
+/// address, sets it on all devices.
+pub(crate) async fn power(
+    device: &mut Protocol,
+    address: Option<u16>,
+    sample_interval: Option<u32>,
+) -> Result<()> {
+    let power = sample_interval.map(|sample_interval| Power {
+        sample_interval_ms: sample_interval.into(),
+    });
+    let Some(address) = address else {
+        let power = power.context("Broadcasting needs a sample interval to set")?;
+        device.send_broadcast(PowerSetReq(power)).await?;
+        println!("Sent the sample interval to all devices");
+        return Ok(());
+    };
+    if let Some(power) = power {
+        device
+            .send_request(address, PowerSetReq(power), |PowerSetRes| Ok(()))
+            .await?;
+    }
+    let sample_interval = device
+        .send_request(address, PowerGetReq, |PowerGetRes(power)| {
+            Ok(power.sample_interval_ms.get())
+        })
+        .await?;
+    if sample_interval == 0 {
+        println!("Sampling continuously");
+    } else {
+        println!(
+            "Sampling every {sample_interval} ms, sleeping in between while all outputs are at 0%"
+        );
+    }
+    Ok(())
+}
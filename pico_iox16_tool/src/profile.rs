@@ -0,0 +1,173 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use pico_iox16_protocol::settings::{Calibration, Threshold};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result, error::IoContext as _, settings::Settings};
+
+/// Settings for one type of sensor, applied to selected channels of a device with
+/// `apply-profile`. Profiles are stored as `~/.config/pico_iox16/profiles/<name>.toml`.
+///
+/// ```toml
+/// description = "0-10 V level sensor"
+/// calibration = { multiply = 10000, divide = 4095, add = 0, min = 0, max = 10000 }
+/// threshold = { threshold_high = 9000, threshold_low = 1000, debounce_time_us = 0, debounce_count = 3 }
+/// output = { duty_cycle = 100, frequency = 1000 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub description: Option<String>,
+    pub calibration: Option<Calibration>,
+    pub threshold: Option<Threshold>,
+    /// The default of the output with the same index as the input, e.g. to supply the sensor.
+    pub output: Option<OutputDefault>,
+}
+
+/// See [`Profile::output`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputDefault {
+    /// Duty cycle in percent.
+    pub duty_cycle: f64,
+    /// PWM frequency in Hz, shared by the two outputs of a group.
+    pub frequency: u16,
+}
+
+impl Profile {
+    /// The directory containing the profiles, next to the configuration file.
+    pub fn directory() -> Option<PathBuf> {
+        Some(Settings::default_path()?.parent()?.join("profiles"))
+    }
+
+    /// Returns the names of the stored profiles, sorted.
+    pub fn names() -> Result<Vec<String>> {
+        match Self::directory() {
+            Some(directory) => Self::names_in(&directory),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn names_in(directory: &Path) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(Error::io(format!("Listing {}", directory.display()), err)),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry
+                .io_context(|| format!("Listing {}", directory.display()))?
+                .path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+                && let Some(stem) = path.file_stem()
+            {
+                names.push(stem.to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Loads a stored profile by name.
+    pub fn load(name: &str) -> Result<Self> {
+        let directory = Self::directory()
+            .ok_or_else(|| Error::Invalid("Cannot determine the profile directory".into()))?;
+        Self::load_from(&directory, name)
+    }
+
+    fn load_from(directory: &Path, name: &str) -> Result<Self> {
+        let path = directory.join(format!("{name}.toml"));
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let names = Self::names_in(directory)?;
+                if names.is_empty() {
+                    return Err(Error::Invalid(format!(
+                        "Unknown profile '{name}', no profiles in {}",
+                        directory.display()
+                    )));
+                }
+                return Err(Error::Invalid(format!(
+                    "Unknown profile '{name}', available profiles: {}",
+                    names.join(", ")
+                )));
+            }
+            Err(err) => return Err(Error::io(format!("Reading {}", path.display()), err)),
+        };
+        toml::from_str(&text)
+            .map_err(|err| Error::parse(format!("Parsing {}", path.display()), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENSOR: &str = r#"
+        description = "0-10 V level sensor"
+        calibration = { multiply = 10000, divide = 4095, add = 0, min = 0, max = 10000 }
+        threshold = { threshold_high = 9000, threshold_low = 1000, debounce_time_us = 0, debounce_count = 3 }
+        output = { duty_cycle = 100, frequency = 1000 }
+    "#;
+
+    #[test]
+    fn load() {
+        let directory = crate::test_directory("profiles");
+        fs::write(directory.join("level.toml"), SENSOR).unwrap();
+        fs::write(directory.join("empty.toml"), "").unwrap();
+        fs::write(directory.join("notes.txt"), "").unwrap();
+        assert_eq!(Profile::names_in(&directory).unwrap(), ["empty", "level"]);
+
+        let profile = Profile::load_from(&directory, "level").unwrap();
+        assert_eq!(profile.description.as_deref(), Some("0-10 V level sensor"));
+        let calibration = profile.calibration.unwrap();
+        assert_eq!((calibration.multiply, calibration.divide), (10000, 4095));
+        assert_eq!(profile.threshold.unwrap().debounce_count, 3);
+        assert_eq!(
+            profile.output,
+            Some(OutputDefault {
+                duty_cycle: 100.0,
+                frequency: 1000
+            })
+        );
+        assert_eq!(
+            Profile::load_from(&directory, "empty").unwrap(),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let directory = crate::test_directory("unknown_profile");
+        let Err(Error::Invalid(message)) = Profile::load_from(&directory, "level") else {
+            panic!("missing profile was loaded");
+        };
+        assert!(message.contains("no profiles"), "{message}");
+        fs::write(directory.join("pump.toml"), "").unwrap();
+        let Err(Error::Invalid(message)) = Profile::load_from(&directory, "level") else {
+            panic!("missing profile was loaded");
+        };
+        assert!(message.ends_with("available profiles: pump"), "{message}");
+        assert_eq!(
+            Profile::names_in(&directory.join("missing")).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        let directory = crate::test_directory("unknown_field");
+        fs::write(directory.join("typo.toml"), "descripton = \"typo\"").unwrap();
+        assert!(matches!(
+            Profile::load_from(&directory, "typo"),
+            Err(Error::Parse { .. })
+        ));
+    }
+}
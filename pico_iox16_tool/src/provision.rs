@@ -0,0 +1,243 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use pico_iox16_protocol::{
+    Config, ConfigGetReq, ConfigGetRes, ConfigSetReq, ConfigSetRes, InputCalibration,
+    InputSetCalibrationsReq, InputSetCalibrationsRes, InputSetThresholdsReq, InputSetThresholdsRes,
+    InputThreshold, RebootReq, RebootRes,
+};
+use pico_iox16_tool::{
+    Protocol,
+    device::{DeviceId, Info},
+    dump,
+    inventory::{Inventory, InventoryEntry},
+};
+use serde::Serialize;
+
+/// Outcome of provisioning one device, written to the report.
+#[derive(Debug, Serialize)]
+struct ReportEntry {
+    label: String,
+    /// The serial device, if several buses were provisioned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bus: Option<String>,
+    address: u16,
+    /// The address after provisioning, missing if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_address: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baudrate: Option<u32>,
+    /// `"ok"` or the error message.
+    result: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    #[serde(rename = "device")]
+    devices: Vec<ReportEntry>,
+}
+
+fn to_array<T: Copy, U: From<T>>(values: &[T], what: &str) -> Result<[U; 16]> {
+    values
+        .iter()
+        .map(|&value| U::from(value))
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| anyhow!("Expected 16 {what}, got {}", values.len()))
+}
+
+/// Applies the settings of one entry and verifies them after rebooting.
+/// Returns the resulting address and baudrate.
+async fn provision_entry(device: &mut Protocol, entry: &InventoryEntry) -> Result<Config> {
+    let address = entry.address;
+    // validate everything before writing anything
+    let calibrations = entry
+        .calibrations
+        .as_deref()
+        .map(|calibrations| to_array::<_, InputCalibration>(calibrations, "calibrations"))
+        .transpose()?;
+    let thresholds = entry
+        .thresholds
+        .as_deref()
+        .map(|thresholds| to_array::<_, InputThreshold>(thresholds, "thresholds"))
+        .transpose()?;
+    let info = Info::fetch(device, address)
+        .await
+        .context("Retrieving device info")?;
+    // the payloads written below have the layout of the tool's version
+    info.check_protocol_version()?;
+    if let Some(mismatch) = info.config_schema_mismatch() {
+        eprintln!("Warning: device 0x{address:04X}: {mismatch}");
+    }
+    if let Some(unique_id) = &entry.unique_id {
+        let id = DeviceId::fetch(device, address)
+            .await
+            .context("Retrieving unique ID")?;
+        match id.unique_id.as_deref() {
+            Some(found) if found.eq_ignore_ascii_case(unique_id) => {}
+            Some(found) => bail!("Expected unique ID {unique_id}, found {found}"),
+            None => bail!("Expected unique ID {unique_id}, but the device does not report one"),
+        }
+    }
+    let current = device
+        .send_request(address, ConfigGetReq, |ConfigGetRes(config)| Ok(*config))
+        .await
+        .context("Retrieving current configuration")?;
+    let config = Config {
+        address: entry.new_address.unwrap_or(current.address.get()).into(),
+        baudrate: entry.baudrate.unwrap_or(current.baudrate.get()).into(),
+        ..current
+    };
+    if let Some(calibrations) = calibrations {
+        device
+            .send_request(
+                address,
+                InputSetCalibrationsReq(calibrations),
+                |InputSetCalibrationsRes| Ok(()),
+            )
+            .await
+            .context("Setting calibrations")?;
+    }
+    if let Some(thresholds) = thresholds {
+        device
+            .send_request(
+                address,
+                InputSetThresholdsReq(thresholds),
+                |InputSetThresholdsRes| Ok(()),
+            )
+            .await
+            .context("Setting thresholds")?;
+    }
+    device
+        .send_request(address, ConfigSetReq(config), |ConfigSetRes| Ok(()))
+        .await
+        .context("Setting configuration")?;
+    device
+        .send_request(address, RebootReq::FIRMWARE, |RebootRes| Ok(()))
+        .await
+        .context("Rebooting")?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let baudrate = device.baudrate();
+    device.set_baudrate(config.baudrate.get())?;
+    let result = dump::fetch(device, config.address.get()).await;
+    device.set_baudrate(baudrate)?;
+    let actual = result.context("Verifying after reboot")?;
+    if actual.settings.config.address != config.address.get()
+        || actual.settings.config.baudrate != config.baudrate.get()
+    {
+        bail!(
+            "Verification failed: address={}, baudrate={} Hz after reboot",
+            actual.settings.config.address,
+            actual.settings.config.baudrate
+        );
+    }
+    if entry
+        .calibrations
+        .as_ref()
+        .is_some_and(|calibrations| *calibrations != actual.settings.calibrations)
+    {
+        bail!("Verification failed: calibrations differ after reboot");
+    }
+    if entry
+        .thresholds
+        .as_ref()
+        .is_some_and(|thresholds| *thresholds != actual.settings.thresholds)
+    {
+        bail!("Verification failed: thresholds differ after reboot");
+    }
+    Ok(config)
+}
+
+/// Provisions the entries assigned to one bus one after the other. Returns the index of
+/// each entry in the inventory with its report entry.
+async fn provision_bus(
+    mut device: Protocol,
+    bus: Option<String>,
+    entries: Vec<(usize, InventoryEntry)>,
+) -> Vec<(usize, ReportEntry)> {
+    let prefix = bus
+        .as_ref()
+        .map_or(String::new(), |bus| format!("[{bus}] "));
+    let mut results = Vec::new();
+    for (index, entry) in entries {
+        println!("{prefix}Provisioning {}...", entry.name());
+        let (config, result) = match provision_entry(&mut device, &entry).await {
+            Ok(config) => {
+                println!(
+                    "{prefix}  {}: ok: address={}, baudrate={} Hz",
+                    entry.name(),
+                    config.address,
+                    config.baudrate
+                );
+                (Some(config), "ok".to_string())
+            }
+            Err(err) => {
+                println!("{prefix}  {}: failed: {err:#}", entry.name());
+                (None, format!("{err:#}"))
+            }
+        };
+        results.push((
+            index,
+            ReportEntry {
+                label: entry.name(),
+                bus: bus.clone(),
+                address: entry.address,
+                new_address: config.map(|config| config.address.get()),
+                baudrate: config.map(|config| config.baudrate.get()),
+                result,
+            },
+        ));
+    }
+    results
+}
+
+/// Provisions all devices listed in an inventory file and writes a report.
+/// Continues with the next device if one fails. Several buses are provisioned concurrently.
+pub(crate) async fn provision(
+    buses: Vec<(String, Protocol)>,
+    inventory: &Path,
+    report: &Path,
+) -> Result<()> {
+    let inventory = Inventory::load(inventory)?;
+    let names: Vec<String> = buses.iter().map(|(name, _)| name.clone()).collect();
+    // assign all entries before touching any device
+    let mut assigned = vec![Vec::new(); buses.len()];
+    for (index, entry) in inventory.devices.iter().enumerate() {
+        let bus = match &entry.bus {
+            Some(bus) => crate::find_bus(bus, &names)
+                .with_context(|| format!("{}: unknown bus '{bus}'", entry.name()))?,
+            None => 0,
+        };
+        assigned[bus].push((index, entry.clone()));
+    }
+    let multiple = buses.len() > 1;
+    let tasks: Vec<_> = buses
+        .into_iter()
+        .zip(assigned)
+        .map(|((name, device), entries)| {
+            tokio::spawn(provision_bus(device, multiple.then_some(name), entries))
+        })
+        .collect();
+    let mut results = Vec::new();
+    for task in tasks {
+        results.extend(task.await?);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    let entries: Vec<ReportEntry> = results.into_iter().map(|(_, entry)| entry).collect();
+    let failed = entries.iter().filter(|entry| entry.result != "ok").count();
+
+    let report_text = toml::to_string(&Report { devices: entries })?;
+    std::fs::write(report, report_text)
+        .with_context(|| format!("Writing report {}", report.display()))?;
+    println!(
+        "Provisioned {} of {} devices. Report written to {}",
+        inventory.devices.len() - failed,
+        inventory.devices.len(),
+        report.display()
+    );
+    if failed > 0 {
+        bail!("{failed} device(s) failed");
+    }
+    Ok(())
+}
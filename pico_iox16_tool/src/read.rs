@@ -0,0 +1,17 @@
+use anyhow::Result;
+use pico_iox16_protocol::{InputGetReq, InputGetRes};
+use pico_iox16_tool::{Protocol, units::Units};
+
+/// Prints the current input values of a device, averaged since the previous read, in the
+/// configured units.
+pub(crate) async fn read(device: &mut Protocol, address: u16, units: &Units) -> Result<()> {
+    let values = device
+        .send_request(address, InputGetReq, |InputGetRes { values }| {
+            Ok(values.map(|value| value.get()))
+        })
+        .await?;
+    for (i, &value) in values.iter().enumerate() {
+        println!("Input {i:>2}: {:>6}", units.format(i, value));
+    }
+    Ok(())
+}
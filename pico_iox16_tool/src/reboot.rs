@@ -0,0 +1,31 @@
+use anyhow::Result;
+use pico_iox16_protocol::{RebootMode, RebootReq, RebootRes};
+use pico_iox16_tool::Protocol;
+
+/// Reboots the device into the firmware or the bootloader, or all devices if no address is
+/// given.
+pub(crate) async fn reboot(
+    device: &mut Protocol,
+    address: Option<u16>,
+    bootloader: bool,
+) -> Result<()> {
+    let mode = if bootloader {
+        RebootMode::Bootloader
+    } else {
+        RebootMode::Firmware
+    };
+    let Some(address) = address else {
+        device.send_broadcast(RebootReq::new(mode)).await?;
+        println!("Sent the reboot to all devices");
+        return Ok(());
+    };
+    device
+        .send_request(address, RebootReq::new(mode), |RebootRes| Ok(()))
+        .await?;
+    if bootloader {
+        println!("Rebooted into the bootloader, the device shows up as USB drive for the UF2 file");
+    } else {
+        println!("Rebooted");
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::next_frame;
+use pico_iox16_tool::{
+    capture::{Chunk, Direction, read_capture},
+    classify::{Classification, Classifier},
+};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    time::{Instant, sleep_until, timeout_at},
+};
+use tokio_serial::SerialStream;
+
+use crate::sniff::Timeline;
+
+/// Extracts the master-side frames from a capture along with the time they were sent.
+///
+/// Frames transmitted by the tool are requests by definition, frames received from the bus
+/// are told apart with the same heuristics as used by `sniff`, knowing which requests the tool
+/// sent.
+fn requests(chunks: &[Chunk]) -> Vec<(Duration, Vec<u8>)> {
+    let mut requests = Vec::new();
+    let mut classifier = Classifier::new();
+    let mut rx = Vec::new();
+    for chunk in chunks {
+        let mut bytes = match chunk.direction {
+            Direction::Tx => &chunk.bytes[..],
+            Direction::Rx => {
+                rx.extend_from_slice(&chunk.bytes);
+                &rx[..]
+            }
+        };
+        let mut consumed = 0;
+        loop {
+            let (maybe_frame, processed) = next_frame(bytes);
+            if let Some(frame) = maybe_frame.filter(|frame| frame.is_valid()) {
+                let is_request = match chunk.direction {
+                    Direction::Tx => {
+                        classifier.request(&frame, chunk.at);
+                        true
+                    }
+                    Direction::Rx => {
+                        classifier.classify(&frame, chunk.at) == Classification::Request
+                    }
+                };
+                if is_request {
+                    requests.push((chunk.at, frame.bytes.to_vec()));
+                }
+            }
+            if processed == 0 {
+                break;
+            }
+            consumed += processed;
+            bytes = &bytes[processed..];
+        }
+        if chunk.direction == Direction::Rx {
+            rx.drain(..consumed);
+        }
+    }
+    requests
+}
+
+/// Replays the master-side frames of a capture and prints the responses.
+///
+/// With `speed` 1.0 the original timing is reproduced, larger values accelerate the replay
+/// and 0 sends each request as soon as the previous one has been answered or timed out.
+pub(crate) async fn replay(
+    mut port: SerialStream,
+    path: &Path,
+    speed: f64,
+    response_timeout: Duration,
+    dump: bool,
+) -> Result<()> {
+    let requests = requests(&read_capture(path)?);
+    let Some((first, _)) = requests.first().cloned() else {
+        println!("No requests found in {}", path.display());
+        return Ok(());
+    };
+    println!("Replaying {} requests from {}...", requests.len(), path.display());
+    let start = Instant::now();
+    let mut timeline = Timeline::new(dump);
+    let mut chunk = [0u8; 4096];
+    let mut unanswered = 0;
+    for (at, bytes) in &requests {
+        if speed > 0.0 {
+            sleep_until(start + (*at - first).div_f64(speed)).await;
+        }
+        port.write_all(bytes).await.context("Sending request")?;
+        port.flush().await.context("Sending request")?;
+        timeline.print_request(start.elapsed(), &next_frame(bytes).0.unwrap());
+        let deadline = Instant::now() + response_timeout;
+        while timeline.is_waiting() {
+            let Ok(n) = timeout_at(deadline, port.read(&mut chunk)).await else {
+                println!("{:>13}  no response within {response_timeout:?}", "");
+                timeline.forget_request();
+                unanswered += 1;
+                break;
+            };
+            let n = n.context("Waiting for response")?;
+            timeline.feed(start.elapsed(), &chunk[..n]);
+        }
+    }
+    println!(
+        "Replay complete. {} of {} requests were answered.",
+        requests.len() - unanswered,
+        requests.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,368 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write as _},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use flate2::{Compression, write::GzEncoder};
+
+use crate::{Error, Result, error::IoContext as _};
+
+/// When to start a new file and how to keep the old ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file before the current one would grow beyond this size in bytes.
+    pub max_size: Option<u64>,
+    /// Start a new file after this time.
+    pub max_age: Option<Duration>,
+    /// Compress rotated files with gzip.
+    pub compress: bool,
+    /// Delete the oldest rotated files when all files together exceed this size in bytes.
+    pub max_disk: Option<u64>,
+}
+
+/// Parses a size in bytes with an optional binary suffix, e.g. `500K`, `100M` or `2G`.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let factor: u64 = match size[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        suffix => return Err(Error::Invalid(format!("Unknown size suffix '{suffix}'"))),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(factor))
+        .ok_or_else(|| Error::Invalid(format!("Invalid size '{size}'")))
+}
+
+/// A file that is appended to and rotated according to a [`Rotation`].
+///
+/// Rotated files are renamed to `<stem>.<time>.<extension>`, e.g.
+/// `log.2024-05-01T12-00-00.000Z.csv`, and optionally compressed to `<name>.gz`. When the
+/// disk limit is exceeded, the files modified least recently are deleted first. The age of
+/// the current file is counted from when it was opened. Compression happens synchronously, so
+/// writes are delayed while a large file is compressed.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    /// Written at the start of every new file.
+    header: Option<String>,
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    /// Opens `path` for appending. The header is written if the file is new or empty.
+    pub fn open(path: &Path, rotation: Rotation, header: Option<String>) -> Result<Self> {
+        let (file, size) = Self::open_file(path, header.as_deref())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            header,
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn open_file(path: &Path, header: Option<&str>) -> Result<(File, u64)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .io_context(|| format!("Opening {}", path.display()))?;
+        let mut size = file
+            .metadata()
+            .io_context(|| format!("Opening {}", path.display()))?
+            .len();
+        if let Some(header) = header
+            && size == 0
+        {
+            file.write_all(header.as_bytes())
+                .io_context(|| format!("Writing {}", path.display()))?;
+            size = header.len() as u64;
+        }
+        Ok((file, size))
+    }
+
+    /// Appends `data`, rotating the file first if it is due.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let header = self.header.as_ref().map_or(0, String::len) as u64;
+        let too_big = self
+            .rotation
+            .max_size
+            .is_some_and(|max_size| self.size > header && self.size + data.len() as u64 > max_size);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max_age| self.opened.elapsed() >= max_age);
+        if too_big || too_old {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(data)
+            .io_context(|| format!("Writing {}", self.path.display()))?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file
+            .flush()
+            .io_context(|| format!("Writing {}", self.path.display()))
+    }
+
+    /// Renames the current file, compresses it if requested, opens a new file and deletes
+    /// old files exceeding the disk limit.
+    fn rotate(&mut self) -> Result<()> {
+        self.flush()?;
+        let rotated = self.rotated_path()?;
+        fs::rename(&self.path, &rotated)
+            .io_context(|| format!("Renaming {} to {}", self.path.display(), rotated.display()))?;
+        (self.file, self.size) = Self::open_file(&self.path, self.header.as_deref())?;
+        self.opened = Instant::now();
+        if self.rotation.compress {
+            compress(&rotated)?;
+        }
+        if let Some(max_disk) = self.rotation.max_disk {
+            self.delete_old(max_disk)?;
+        }
+        Ok(())
+    }
+
+    /// The stem and the extension (with leading dot, if any) of the current file name.
+    fn name_parts(&self) -> (String, String) {
+        let stem = self
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let extension = self
+            .path
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        (stem, extension)
+    }
+
+    /// A name for the rotated file that does not exist yet.
+    fn rotated_path(&self) -> Result<PathBuf> {
+        let (stem, extension) = self.name_parts();
+        // colons are not allowed in file names on Windows
+        let time = humantime::format_rfc3339_millis(SystemTime::now())
+            .to_string()
+            .replace(':', "-");
+        for index in 0.. {
+            let suffix = if index == 0 {
+                String::new()
+            } else {
+                format!("-{index}")
+            };
+            let path = self
+                .path
+                .with_file_name(format!("{stem}.{time}{suffix}{extension}"));
+            let compressed = PathBuf::from(format!("{}.gz", path.display()));
+            if !path.exists() && !compressed.exists() {
+                return Ok(path);
+            }
+        }
+        unreachable!()
+    }
+
+    /// Returns the rotated files of the current file, oldest first.
+    fn rotated_files(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let (stem, extension) = self.name_parts();
+        let prefix = format!("{stem}.");
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut files = Vec::new();
+        for entry in
+            fs::read_dir(directory).io_context(|| format!("Listing {}", directory.display()))?
+        {
+            let entry = entry.io_context(|| format!("Listing {}", directory.display()))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let name_without_gz = name.strip_suffix(".gz").unwrap_or(&name);
+            let Some(time) = name_without_gz
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(extension.as_str()))
+            else {
+                continue;
+            };
+            // only names produced by `rotated_path`, which start with the year
+            if time.len() < 4 || !time.bytes().take(4).all(|byte| byte.is_ascii_digit()) {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .io_context(|| format!("Listing {}", directory.display()))?;
+            let modified = metadata
+                .modified()
+                .io_context(|| format!("Listing {}", directory.display()))?;
+            files.push((modified, entry.path(), metadata.len()));
+        }
+        files.sort();
+        Ok(files
+            .into_iter()
+            .map(|(_, path, size)| (path, size))
+            .collect())
+    }
+
+    fn delete_old(&self, max_disk: u64) -> Result<()> {
+        let files = self.rotated_files()?;
+        let mut total = self.size + files.iter().map(|(_, size)| size).sum::<u64>();
+        for (path, size) in files {
+            if total <= max_disk {
+                break;
+            }
+            fs::remove_file(&path).io_context(|| format!("Deleting {}", path.display()))?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+/// Compresses a file to `<name>.gz` and deletes the original.
+fn compress(path: &Path) -> Result<()> {
+    let compressed = PathBuf::from(format!("{}.gz", path.display()));
+    let result = (|| -> io::Result<()> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut encoder = GzEncoder::new(
+            BufWriter::new(File::create(&compressed)?),
+            Compression::default(),
+        );
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.flush()
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_file(&compressed);
+        return Err(Error::io(format!("Compressing {}", path.display()), err));
+    }
+    fs::remove_file(path).io_context(|| format!("Deleting {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read as _;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    /// The rotated files next to `log.csv`, sorted by name.
+    fn rotated(directory: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !path.ends_with("log.csv"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1234").unwrap(), 1234);
+        assert_eq!(parse_size(" 500K ").unwrap(), 500 << 10);
+        assert_eq!(parse_size("100mb").unwrap(), 100 << 20);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        for size in ["", "K", "1.5M", "10X", "-1", "16777216T"] {
+            assert!(
+                matches!(parse_size(size), Err(Error::Invalid(_))),
+                "{size:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let directory = crate::test_directory("rotate_by_size");
+        let path = directory.join("log.csv");
+        let rotation = Rotation {
+            max_size: Some(10),
+            ..Rotation::default()
+        };
+        let mut file = RotatingFile::open(&path, rotation, Some("h\n".into())).unwrap();
+        file.write(b"first\n").unwrap();
+        assert!(rotated(&directory).is_empty());
+        file.write(b"second\n").unwrap();
+        file.flush().unwrap();
+        let rotated = rotated(&directory);
+        assert_eq!(rotated.len(), 1);
+        let name = rotated[0].file_name().unwrap().to_string_lossy();
+        assert!(
+            name.starts_with("log.2") && name.ends_with(".csv"),
+            "{name}"
+        );
+        assert_eq!(fs::read_to_string(&rotated[0]).unwrap(), "h\nfirst\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "h\nsecond\n");
+    }
+
+    #[test]
+    fn oversized_writes_go_to_a_new_file() {
+        let directory = crate::test_directory("oversized_writes");
+        let path = directory.join("log.csv");
+        let rotation = Rotation {
+            max_size: Some(4),
+            ..Rotation::default()
+        };
+        let mut file = RotatingFile::open(&path, rotation, Some("h\n".into())).unwrap();
+        // a file containing only the header isn't rotated
+        file.write(b"too long\n").unwrap();
+        file.flush().unwrap();
+        assert!(rotated(&directory).is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "h\ntoo long\n");
+    }
+
+    #[test]
+    fn compress_and_delete() {
+        let directory = crate::test_directory("compress_and_delete");
+        let path = directory.join("log.csv");
+        let rotation = Rotation {
+            max_size: Some(4),
+            compress: true,
+            max_disk: Some(200),
+            ..Rotation::default()
+        };
+        let mut file = RotatingFile::open(&path, rotation, None).unwrap();
+        for line in ["one\n", "two\n", "three\n"] {
+            // rotated files with distinct names and modification times, oldest first
+            std::thread::sleep(Duration::from_millis(10));
+            file.write(line.as_bytes()).unwrap();
+        }
+        let files = rotated(&directory);
+        assert_eq!(files.len(), 2);
+        let mut text = String::new();
+        for path in &files {
+            assert_eq!(path.extension().unwrap(), "gz");
+            GzDecoder::new(File::open(path).unwrap())
+                .read_to_string(&mut text)
+                .unwrap();
+        }
+        assert_eq!(text, "one\ntwo\n");
+
+        // room for the current file and one rotated file
+        let size = fs::metadata(&files[0]).unwrap().len();
+        file.rotation.max_disk = Some(size + 10);
+        std::thread::sleep(Duration::from_millis(10));
+        file.write(b"four\n").unwrap();
+        let remaining = rotated(&directory);
+        assert_eq!(remaining.len(), 1);
+        let mut text = String::new();
+        GzDecoder::new(File::open(&remaining[0]).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "three\n");
+    }
+}
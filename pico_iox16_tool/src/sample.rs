@@ -0,0 +1,170 @@
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use pico_iox16_protocol::{InputGetReq, InputGetRes};
+
+use crate::{ProtocolClient, Result, units::Units};
+
+/// A device sampled by `monitor` and `log`.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// The serial device of the bus, if several buses are sampled.
+    pub bus: Option<String>,
+    pub address: u16,
+    /// The alias of the device, if it has one.
+    pub label: Option<String>,
+    pub units: Arc<Units>,
+}
+
+/// The input values of one device at one point in time, as produced by `monitor` and `log`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    /// The serial device of the bus, if several buses are sampled.
+    pub bus: Option<String>,
+    pub address: u16,
+    /// The alias of the device, if it has one.
+    pub label: Option<String>,
+    pub units: Arc<Units>,
+    pub values: [i16; 16],
+}
+
+impl Sample {
+    /// Reads the input values of a device, averaged since the previous read.
+    pub async fn fetch(device: &mut impl ProtocolClient, source: &Source) -> Result<Self> {
+        let values = device
+            .send_request(source.address, InputGetReq, |InputGetRes { values }| {
+                Ok(values.map(|value| value.get()))
+            })
+            .await?;
+        Ok(Self::new(source, values))
+    }
+
+    /// A sample of `source` taken now, e.g. from a [`crate::Report`].
+    pub fn new(source: &Source, values: [i16; 16]) -> Self {
+        Self {
+            time: SystemTime::now(),
+            bus: source.bus.clone(),
+            address: source.address,
+            label: source.label.clone(),
+            units: source.units.clone(),
+            values,
+        }
+    }
+}
+
+/// Output format of the sample pipelines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns for reading in a terminal.
+    Text,
+    /// Comma separated values with a header line. Values with a unit are converted, but the
+    /// unit itself is left out to keep the columns numeric.
+    Csv,
+    /// InfluxDB line protocol with one line per channel, tagged by bus, address, label,
+    /// channel and unit. Values are always written as floats, as Influx requires a field to have
+    /// the same type in all series of a measurement. Can be piped into `influx write`.
+    Influx,
+}
+
+/// Quotes a CSV field if necessary.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Escapes a tag value for the InfluxDB line protocol.
+fn influx_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+impl Format {
+    /// The line written once before the samples, if the format has one.
+    pub fn header(self) -> Option<String> {
+        match self {
+            Self::Text | Self::Influx => None,
+            Self::Csv => {
+                let mut header = "time,bus,address,label".to_string();
+                for channel in 0..16 {
+                    write!(header, ",in{channel}").unwrap();
+                }
+                Some(header + "\n")
+            }
+        }
+    }
+
+    /// Formats a sample as one or more lines, each terminated by a newline.
+    pub fn format(self, sample: &Sample) -> String {
+        let label = sample.label.as_deref().unwrap_or("");
+        let bus = sample.bus.as_deref().unwrap_or("");
+        let mut out = String::new();
+        match self {
+            Self::Text => {
+                write!(out, "{} ", humantime::format_rfc3339_millis(sample.time)).unwrap();
+                if !bus.is_empty() {
+                    write!(out, "{bus} ").unwrap();
+                }
+                write!(out, "{:>5} {label:<16}", sample.address).unwrap();
+                for (channel, &value) in sample.values.iter().enumerate() {
+                    write!(out, " {:>6}", sample.units.format(channel, value)).unwrap();
+                }
+                out.push('\n');
+            }
+            Self::Csv => {
+                write!(
+                    out,
+                    "{},{},{},{}",
+                    humantime::format_rfc3339_millis(sample.time),
+                    csv_field(bus),
+                    sample.address,
+                    csv_field(label)
+                )
+                .unwrap();
+                for (channel, &value) in sample.values.iter().enumerate() {
+                    write!(out, ",{}", sample.units.format_number(channel, value)).unwrap();
+                }
+                out.push('\n');
+            }
+            Self::Influx => {
+                let nanos = sample
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos();
+                let mut tags = String::new();
+                if !bus.is_empty() {
+                    write!(tags, "bus={},", influx_tag(bus)).unwrap();
+                }
+                write!(tags, "address={}", sample.address).unwrap();
+                if !label.is_empty() {
+                    write!(tags, ",label={}", influx_tag(label)).unwrap();
+                }
+                for (channel, &value) in sample.values.iter().enumerate() {
+                    match sample.units.convert(channel, value) {
+                        Some((converted, unit)) => writeln!(
+                            out,
+                            "pico_iox16,{tags},channel={channel},unit={} value={converted} {nanos}",
+                            influx_tag(&unit.unit)
+                        ),
+                        None => writeln!(
+                            out,
+                            "pico_iox16,{tags},channel={channel} value={value} {nanos}"
+                        ),
+                    }
+                    .unwrap();
+                }
+            }
+        }
+        out
+    }
+}
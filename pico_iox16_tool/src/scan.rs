@@ -1,6 +1,6 @@
-use std::iter::chain;
+use std::{collections::BTreeSet, fs, io::ErrorKind, ops::RangeInclusive, path::Path};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use crossterm::{
     cursor::{RestorePosition, SavePosition},
     execute,
@@ -8,43 +8,167 @@ use crossterm::{
     terminal::{Clear, ClearType},
 };
 use pico_iox16_protocol::{CheckReq, CheckRes};
-use pico_iox16_tool::Protocol;
+use pico_iox16_tool::{
+    Protocol,
+    device::{DeviceId, Info},
+    inventory::{Inventory, InventoryEntry},
+    settings::Settings,
+};
+use serde::{Deserialize, Serialize};
+
+/// How many addresses are scanned between two saves of the progress.
+const CHUNK: usize = 256;
 
-pub(crate) async fn scan(device: &mut Protocol, max_address: Option<u16>) -> Result<()> {
+/// The state of an interrupted scan, saved to the file given with `--resume`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Progress {
+    /// The highest address scanned so far. Lower addresses are skipped when resuming.
+    last: Option<u16>,
+    /// The addresses of the devices found so far
+    found: Vec<u16>,
+}
+
+impl Progress {
+    /// Loads the progress, or starts afresh if there is no file yet.
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Parsing scan progress {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => {
+                Err(err).with_context(|| format!("Reading scan progress {}", path.display()))
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Writing scan progress {}", path.display()))
+    }
+}
+
+/// The addresses to scan in ascending order: those in `ranges` if any, else all up to
+/// `max_address` and 0xFFFF, which unconfigured devices answer at, without the ones in
+/// `exclude`.
+pub(crate) fn addresses(
+    max_address: Option<u16>,
+    ranges: &[RangeInclusive<u16>],
+    exclude: &[RangeInclusive<u16>],
+) -> Vec<u16> {
+    let mut addresses: BTreeSet<u16> = if ranges.is_empty() {
+        (0..=max_address.unwrap_or(0xFFFF))
+            .chain([0xFFFF])
+            .collect()
+    } else {
+        ranges.iter().cloned().flatten().collect()
+    };
+    for range in exclude {
+        addresses.retain(|address| !range.contains(address));
+    }
+    addresses.into_iter().collect()
+}
+
+/// Describes a found device for the inventory. Devices not answering `InfoGet` are listed
+/// with their address and label only.
+fn inventory_entry(
+    address: u16,
+    info: Option<Info>,
+    id: Option<DeviceId>,
+    settings: &Settings,
+) -> InventoryEntry {
+    InventoryEntry {
+        label: settings.label(address).map(String::from),
+        bus: None,
+        address,
+        new_address: None,
+        baudrate: None,
+        calibrations: None,
+        thresholds: None,
+        unique_id: id.as_ref().and_then(|id| id.unique_id.clone()),
+        hardware_revision: id.and_then(|id| id.hardware_revision),
+        firmware_version: info.as_ref().map(Info::version_string),
+        info: info.map(|info| info.info),
+    }
+}
+
+/// Scans the addresses and prints the ones that respond. If `output` is given, the found
+/// devices are also written to an inventory file, which can be edited and passed to
+/// `provision`. With `pipeline`, each request is sent as soon as the bus is free instead of
+/// after the timeout of the previous one, and the found devices are listed at the end.
+///
+/// With `resume`, the progress is saved to that file every few hundred addresses, and a scan
+/// interrupted before continues after the last address it saved. The file is removed once the
+/// scan is complete.
+pub(crate) async fn scan(
+    device: &mut Protocol,
+    addresses: &[u16],
+    pipeline: bool,
+    output: Option<&Path>,
+    resume: Option<&Path>,
+    settings: &Settings,
+) -> Result<()> {
     let mut stdout = std::io::stdout();
     let baudrate = device.baudrate();
+    let mut progress_file = resume.map(Progress::load).transpose()?.unwrap_or_default();
+    let skipped = match progress_file.last {
+        Some(last) => addresses.partition_point(|&address| address <= last),
+        None => 0,
+    };
+    if skipped > 0 {
+        println!(
+            "Resuming after address {}, {} devices found before",
+            addresses[skipped - 1],
+            progress_file.found.len()
+        );
+    }
     execute!(stdout, SavePosition)?;
-    let addresses = chain(
-        0..=max_address.unwrap_or(0xFFFF),
-        if matches!(max_address, Some(0xFFFF) | None) {
-            None.into_iter()
-        } else {
-            Some(0xFFFF).into_iter()
-        },
-    );
-    let mut scanned = 0;
-    let mut found = 0;
-    for address in addresses {
+    let progress = |address| {
         execute!(
-            stdout,
+            std::io::stdout(),
             RestorePosition,
             Clear(ClearType::FromCursorDown),
             Print(format!("Scanning address {address} at {baudrate} Hz...")),
-        )?;
-        scanned += 1;
-        if device
-            .send_request(address, CheckReq, |CheckRes| Ok(()))
-            .await
-            .is_ok()
-        {
-            found += 1;
-            execute!(
-                stdout,
-                RestorePosition,
-                Clear(ClearType::FromCursorDown),
-                Print(format!("{address}\n")),
-                SavePosition
-            )?;
+        )
+    };
+    let mut inventory = Inventory::default();
+    let mut scanned = skipped;
+    let mut found = 0;
+    for &address in &progress_file.found {
+        found += 1;
+        report(device, address, output.is_some(), &mut inventory, settings).await?;
+    }
+    for chunk in addresses[skipped..].chunks(CHUNK) {
+        if pipeline {
+            let responded = device
+                .scan(chunk.iter().copied(), |address| {
+                    scanned += 1;
+                    // only the progress display, not worth aborting the scan for
+                    progress(address).ok();
+                })
+                .await?;
+            for address in responded {
+                found += 1;
+                progress_file.found.push(address);
+                report(device, address, output.is_some(), &mut inventory, settings).await?;
+            }
+        } else {
+            for &address in chunk {
+                progress(address)?;
+                scanned += 1;
+                if device
+                    .send_request(address, CheckReq, |CheckRes| Ok(()))
+                    .await
+                    .is_ok()
+                {
+                    found += 1;
+                    progress_file.found.push(address);
+                    report(device, address, output.is_some(), &mut inventory, settings).await?;
+                }
+            }
+        }
+        if let Some(resume) = resume {
+            progress_file.last = chunk.last().copied();
+            progress_file.save(resume)?;
         }
     }
     execute!(
@@ -53,5 +177,53 @@ pub(crate) async fn scan(device: &mut Protocol, max_address: Option<u16>) -> Res
         Clear(ClearType::FromCursorDown),
         Print(format!("Scan complete. Found {found} out of {scanned} devices.\n")),
     )?;
+    if let Some(output) = output {
+        inventory.save(output)?;
+        println!("Inventory written to {}", output.display());
+    }
+    if let Some(resume) = resume {
+        fs::remove_file(resume)
+            .or_else(|err| match err.kind() {
+                ErrorKind::NotFound => Ok(()),
+                _ => Err(err),
+            })
+            .with_context(|| format!("Removing scan progress {}", resume.display()))?;
+    }
+    Ok(())
+}
+
+/// Prints a found device, with a warning if its protocol version doesn't fit, and adds it to
+/// the inventory if `record` is set.
+async fn report(
+    device: &mut Protocol,
+    address: u16,
+    record: bool,
+    inventory: &mut Inventory,
+    settings: &Settings,
+) -> Result<()> {
+    let info = Info::fetch(device, address).await.ok();
+    let warning = match &info {
+        Some(info) => info
+            .check_protocol_version()
+            .err()
+            .map(|err| format!(" ({err})")),
+        None => Some(" (no answer to InfoGet, the firmware may predate protocol versions)".into()),
+    };
+    if record {
+        let id = match info {
+            Some(_) => DeviceId::fetch(device, address).await.ok(),
+            None => None,
+        };
+        inventory
+            .devices
+            .push(inventory_entry(address, info, id, settings));
+    }
+    execute!(
+        std::io::stdout(),
+        RestorePosition,
+        Clear(ClearType::FromCursorDown),
+        Print(format!("{address}{}\n", warning.unwrap_or_default())),
+        SavePosition
+    )?;
     Ok(())
 }
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{InputGetReq, InputGetRes, OutputGroup, OutputSetReq, OutputSetRes};
+use pico_iox16_tool::Protocol;
+
+/// Duty cycles driven during the self-test, in percent. The first and the last
+/// step are used to determine the span of each input.
+const STEPS: [u16; 5] = [0, 25, 50, 75, 100];
+
+/// Drives all outputs with the same duty cycle.
+async fn set_outputs(device: &mut Protocol, address: u16, duty_percent: u16, frequency: u16) -> Result<()> {
+    let duty_cycle = (u32::from(duty_percent) * 0x8000 / 100) as u16;
+    let request = OutputSetReq(
+        [OutputGroup {
+            duty_cycle: [duty_cycle.into(); 2],
+            frequency: frequency.into(),
+        }; 8],
+    );
+    device
+        .send_request(address, request, |OutputSetRes| Ok(()))
+        .await?;
+    Ok(())
+}
+
+/// Reads the input values averaged over the given duration.
+async fn read_inputs(device: &mut Protocol, address: u16, duration: Duration) -> Result<[i16; 16]> {
+    // the first request resets the accumulated values
+    device
+        .send_request(address, InputGetReq, |_: &InputGetRes| Ok(()))
+        .await?;
+    tokio::time::sleep(duration).await;
+    let values = device
+        .send_request(address, InputGetReq, |InputGetRes { values }| {
+            Ok(values.map(|v| v.get()))
+        })
+        .await?;
+    Ok(values)
+}
+
+/// Production test for boards whose output `n` is wired to input `n`.
+///
+/// Drives all outputs through a series of duty cycles, reads back the averaged inputs
+/// and checks that each input follows its output linearly within `tolerance` percent
+/// of the span between 0% and 100% duty cycle.
+pub(crate) async fn selftest(
+    device: &mut Protocol,
+    address: u16,
+    tolerance: f64,
+    frequency: u16,
+    settle: Duration,
+) -> Result<()> {
+    let mut readings = [[0i16; 16]; STEPS.len()];
+    for (step, duty_percent) in STEPS.iter().copied().enumerate() {
+        println!("Driving outputs at {duty_percent}% duty cycle...");
+        set_outputs(device, address, duty_percent, frequency).await?;
+        tokio::time::sleep(settle).await;
+        readings[step] = read_inputs(device, address, settle).await?;
+    }
+    println!("Switching outputs off...");
+    set_outputs(device, address, 0, frequency).await?;
+
+    let mut failed = 0;
+    for channel in 0..16 {
+        let low = f64::from(readings[0][channel]);
+        let high = f64::from(readings[STEPS.len() - 1][channel]);
+        let span = high - low;
+        let values = readings
+            .iter()
+            .map(|r| r[channel].to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if span <= 0.0 {
+            failed += 1;
+            println!("Channel {channel:2}: FAIL (input does not follow output: {values})");
+            continue;
+        }
+        let max_error = STEPS
+            .iter()
+            .zip(readings.iter())
+            .map(|(duty_percent, r)| {
+                let expected = low + span * f64::from(*duty_percent) / 100.0;
+                (f64::from(r[channel]) - expected).abs() / span * 100.0
+            })
+            .fold(0.0, f64::max);
+        if max_error <= tolerance {
+            println!("Channel {channel:2}: PASS (max error {max_error:.1}%: {values})");
+        } else {
+            failed += 1;
+            println!("Channel {channel:2}: FAIL (max error {max_error:.1}%: {values})");
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} of 16 channels failed the self-test");
+    }
+    println!("All channels passed the self-test.");
+    Ok(())
+}
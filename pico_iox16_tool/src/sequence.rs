@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{OutputSetReq, OutputSetRes};
+use pico_iox16_tool::{Protocol, pattern::Pattern};
+use tokio::time::Instant;
+
+/// Plays the output pattern from a file, optionally looping, until it ends or Ctrl-C is
+/// pressed. Outputs are switched off when interrupted. Frames that cannot be sent are
+/// counted and skipped, so a long-running test is not stopped by a single error. Without an
+/// address, the frames are broadcast to all devices.
+pub(crate) async fn play(
+    device: &mut Protocol,
+    address: Option<u16>,
+    file: &Path,
+    looping: bool,
+) -> Result<()> {
+    let pattern = Pattern::load(file)?;
+    let frames = pattern.frames();
+    let period = pattern.period();
+    if looping && period.is_zero() {
+        bail!("Cannot loop a pattern with a period of 0");
+    }
+    let target = match address {
+        Some(address) => format!("device {address}"),
+        None => "all devices".to_owned(),
+    };
+    println!(
+        "Playing {} keyframes ({} frames, {period:?} per loop) on {target}...",
+        pattern.keyframes.len(),
+        frames.len()
+    );
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let start = Instant::now();
+    let mut failed = 0u64;
+    let mut sent = 0u64;
+    let mut iteration = 0;
+    let interrupted = 'play: loop {
+        let loop_start = start + period * iteration;
+        for frame in &frames {
+            tokio::select! {
+                _ = tokio::time::sleep_until(loop_start + frame.time) => {}
+                _ = &mut ctrl_c => break 'play true,
+            }
+            match send(device, address, frame.request(pattern.frequency)).await {
+                Ok(()) => sent += 1,
+                Err(err) => {
+                    failed += 1;
+                    eprintln!("Frame at {:?}: {err:#}", frame.time);
+                }
+            }
+        }
+        iteration += 1;
+        if !looping {
+            break false;
+        }
+        println!("Loop {iteration} done, {sent} frames sent, {failed} failed");
+    };
+    if interrupted {
+        println!("Interrupted, switching outputs off...");
+        send(device, address, OutputSetReq::default()).await?;
+    }
+    println!("{sent} frames sent, {failed} failed");
+    Ok(())
+}
+
+/// Sends a frame to the device, or to all devices without an address.
+async fn send(
+    device: &mut Protocol,
+    address: Option<u16>,
+    request: OutputSetReq,
+) -> pico_iox16_tool::Result<()> {
+    match address {
+        Some(address) => {
+            device
+                .send_request(address, request, |OutputSetRes| Ok(()))
+                .await
+        }
+        None => device.send_broadcast(request).await,
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::BTreeMap, env, fs, io::ErrorKind, num::ParseIntError, ops::RangeInclusive,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, Result,
+    units::{ChannelUnit, Units},
+};
+
+/// Defaults for the command line options, read from `~/.config/pico_iox16/config.toml`.
+///
+/// ```toml
+/// device = "/dev/ttyUSB0"
+/// baudrate = 1000000
+/// retries = 2
+/// timeout = 50
+///
+/// [aliases]
+/// pump-controller = 12
+/// valve-controller = { address = 13, port = "/dev/ttyUSB0", unique_id = "e6614c311b4f8a2b" }
+///
+/// [units.pump-controller]
+/// 0 = { unit = "bar", scale = 2.5, offset = -1.25 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// The serial device to use if none is given on the command line.
+    pub device: Option<String>,
+    pub baudrate: Option<u32>,
+    /// Number of times a request is repeated after a timeout.
+    pub retries: Option<u32>,
+    /// Minimum time to wait for a response in milliseconds.
+    pub timeout: Option<u64>,
+    /// Names that can be used instead of numeric addresses.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Alias>,
+    /// ADC reference voltage in volts for unit conversions. Defaults to 3.3 V.
+    pub adc_reference: Option<f64>,
+    /// ADC value corresponding to the reference voltage. Defaults to 4096.
+    pub adc_full_scale: Option<f64>,
+    /// Units of the input channels by device alias or address and channel index.
+    #[serde(default)]
+    pub units: BTreeMap<String, BTreeMap<usize, ChannelUnit>>,
+}
+
+/// What an alias in [`Settings::aliases`] names: just an address, or a particular device on a
+/// particular bus.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Alias {
+    Address(u16),
+    Device(AliasedDevice),
+}
+
+/// A device known by an alias, see [`Alias`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AliasedDevice {
+    pub address: u16,
+    /// The serial port of the bus the device is on. The alias can't be used on other ports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// The unique ID of the device, see [`crate::device::Info::unique_id`]. A warning is
+    /// printed if another device answers at its address, e.g. after a board was swapped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_id: Option<String>,
+}
+
+impl Alias {
+    pub fn address(&self) -> u16 {
+        match self {
+            Self::Address(address) => *address,
+            Self::Device(device) => device.address,
+        }
+    }
+
+    pub fn port(&self) -> Option<&str> {
+        match self {
+            Self::Address(_) => None,
+            Self::Device(device) => device.port.as_deref(),
+        }
+    }
+
+    pub fn unique_id(&self) -> Option<&str> {
+        match self {
+            Self::Address(_) => None,
+            Self::Device(device) => device.unique_id.as_deref(),
+        }
+    }
+}
+
+impl Settings {
+    /// The path of the configuration file. `$XDG_CONFIG_HOME` takes precedence over `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("pico_iox16").join("config.toml"))
+    }
+
+    /// Loads the configuration file at the default path. A missing file is not an error.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::default_path() else {
+            return Ok(Self::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Error::io(format!("Reading {}", path.display()), err)),
+        };
+        toml::from_str(&text)
+            .map_err(|err| Error::parse(format!("Parsing {}", path.display()), err))
+    }
+
+    /// Returns the alias of an address, if any.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.aliases
+            .iter()
+            .find(|(_, alias)| alias.address() == address)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    /// Returns the aliases with a unique ID that can be used on `port`, along with their
+    /// addresses and unique IDs.
+    pub fn unique_ids<'a>(
+        &'a self,
+        port: &'a str,
+    ) -> impl Iterator<Item = (&'a str, u16, &'a str)> {
+        self.aliases.iter().filter_map(move |(name, alias)| {
+            let unique_id = alias.unique_id()?;
+            alias
+                .port()
+                .is_none_or(|aliased| aliased == port)
+                .then_some((name.as_str(), alias.address(), unique_id))
+        })
+    }
+
+    /// Returns the units of the channels of a device.
+    pub fn units(&self, address: u16) -> Result<Units> {
+        let defaults = Units::default();
+        let mut units = Units {
+            reference: self.adc_reference.unwrap_or(defaults.reference),
+            full_scale: self.adc_full_scale.unwrap_or(defaults.full_scale),
+            ..defaults
+        };
+        for (device, channels) in &self.units {
+            if self.resolve_address(device)? != address {
+                continue;
+            }
+            for (&channel, unit) in channels {
+                let slot = units.channels.get_mut(channel).ok_or_else(|| {
+                    Error::Invalid(format!("Units of '{device}': invalid channel {channel}"))
+                })?;
+                *slot = Some(unit.clone());
+            }
+        }
+        Ok(units)
+    }
+
+    /// Resolves an alias or a numeric address, either decimal or hexadecimal with `0x` prefix.
+    pub fn resolve_address(&self, address: &str) -> Result<u16> {
+        if let Some(alias) = self.aliases.get(address) {
+            return Ok(alias.address());
+        }
+        parse_address(address).map_err(|_| {
+            Error::Invalid(format!(
+                "'{address}' is neither an address nor a known alias"
+            ))
+        })
+    }
+
+    /// Like [`Settings::resolve_address`], but fails for aliases of devices on another port than
+    /// `port`.
+    pub fn resolve_address_on(&self, address: &str, port: &str) -> Result<u16> {
+        match self.aliases.get(address).and_then(Alias::port) {
+            Some(aliased) if aliased != port => Err(Error::Invalid(format!(
+                "'{address}' is a device on {aliased}, not on {port}"
+            ))),
+            _ => self.resolve_address(address),
+        }
+    }
+}
+
+/// Parses a numeric address, either decimal or hexadecimal with `0x` prefix.
+fn parse_address(address: &str) -> Result<u16, ParseIntError> {
+    match address.strip_prefix("0x").or(address.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => address.parse(),
+    }
+}
+
+/// Parses a range of numeric addresses like `0x100-0x1ff`, or a single address.
+pub fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>> {
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    match (parse_address(first.trim()), parse_address(last.trim())) {
+        (Ok(first), Ok(last)) if first <= last => Ok(first..=last),
+        _ => Err(Error::Invalid(format!("Invalid address range '{range}'"))),
+    }
+}
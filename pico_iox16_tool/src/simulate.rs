@@ -0,0 +1,86 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use anyhow::{Context as _, Result};
+use pico_iox16_firmware::{
+    mock::{self, Flash, Host},
+    nvm::Config,
+    runtime::{Read, ReadError, Write},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_serial::{SerialPort as _, SerialStream};
+
+/// The firmware's end of the serial port. Closing the other end reads as an unrecoverable
+/// error, which ends the main loop.
+struct Port(SerialStream);
+impl Port {
+    /// Polls the port once. The executor of the firmware polls again on its own, so nothing
+    /// needs to be woken.
+    fn poll<T>(
+        &mut self,
+        f: impl FnOnce(Pin<&mut SerialStream>, &mut Context) -> Poll<T>,
+    ) -> Option<T> {
+        match f(
+            Pin::new(&mut self.0),
+            &mut Context::from_waker(Waker::noop()),
+        ) {
+            Poll::Ready(v) => Some(v),
+            Poll::Pending => None,
+        }
+    }
+}
+impl Read<Host> for Port {
+    type Error = io::Error;
+    fn read(&mut self, buf: &mut [u8]) -> nb::Result<usize, ReadError<Self::Error>> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut read_buf = ReadBuf::new(buf);
+        match self.poll(|port, cx| port.poll_read(cx, &mut read_buf)) {
+            None => Err(nb::Error::WouldBlock),
+            Some(Err(err)) => Err(nb::Error::Other(ReadError::UnrecoverableError(err))),
+            Some(Ok(())) if read_buf.filled().is_empty() => Err(nb::Error::Other(
+                ReadError::UnrecoverableError(io::ErrorKind::UnexpectedEof.into()),
+            )),
+            Some(Ok(())) => Ok(read_buf.filled().len()),
+        }
+    }
+    fn set_line(&mut self, config: &Config) -> bool {
+        self.0.set_baud_rate(config.baudrate).is_ok()
+    }
+}
+impl Write<Host> for Port {
+    type Error = io::Error;
+    fn write(&mut self, buf: &[u8]) -> nb::Result<usize, Self::Error> {
+        self.poll(|port, cx| port.poll_write(cx, buf))
+            .ok_or(nb::Error::WouldBlock)?
+            .map_err(nb::Error::Other)
+    }
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.poll(|port, cx| port.poll_flush(cx))
+            .ok_or(nb::Error::WouldBlock)?
+            .map_err(nb::Error::Other)
+    }
+}
+
+/// Runs the firmware on the mock board of [`pico_iox16_firmware::mock`] on the given port, so
+/// that host software can be tested without hardware. Typically used with one end of a pseudo
+/// terminal pair, e.g. created by `socat`. Returns when the other end is closed.
+pub(crate) async fn simulate(port: SerialStream, address: u16) -> Result<()> {
+    let flash = Flash::with_config(Config {
+        address,
+        baudrate: port.baud_rate()?,
+        ..Config::DEFAULT
+    });
+    println!("Simulating device at address {address}...");
+    tokio::task::spawn_blocking(move || {
+        mock::run(&mut Port(port), &flash, |mode| println!("Rebooting into {mode}"));
+    })
+    .await
+    .context("Running the firmware")?;
+    println!("The serial port was closed");
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result, bail};
+use pico_iox16_protocol::{Frame, next_frame};
+use pico_iox16_tool::{
+    capture::{CaptureWriter, Direction},
+    classify::{Classification, Classifier},
+    trace::{command_name, describe_frame, hex},
+};
+use tokio::io::AsyncReadExt as _;
+use tokio_serial::SerialStream;
+
+/// Decodes bytes seen on the bus and prints them as a timeline.
+pub(crate) struct Timeline {
+    classifier: Classifier,
+    buf: Vec<u8>,
+    dump: bool,
+}
+
+impl Timeline {
+    pub(crate) fn new(dump: bool) -> Self {
+        Self {
+            classifier: Classifier::new(),
+            buf: Vec::new(),
+            dump,
+        }
+    }
+
+    /// Returns `true` if the last request has not been answered yet.
+    pub(crate) fn is_waiting(&self) -> bool {
+        self.classifier.is_waiting()
+    }
+
+    /// Gives up on the last request, e.g. once it timed out.
+    pub(crate) fn forget_request(&mut self) {
+        self.classifier.forget();
+    }
+
+    /// Prints a single frame of the timeline.
+    pub(crate) fn print_frame(&mut self, at: Duration, frame: &Frame<'_>) {
+        self.print(at, frame, false);
+    }
+
+    /// Prints a frame the tool sent, which is a request whatever its payload.
+    pub(crate) fn print_request(&mut self, at: Duration, frame: &Frame<'_>) {
+        self.print(at, frame, true);
+    }
+
+    fn print(&mut self, at: Duration, frame: &Frame<'_>, sent: bool) {
+        let timestamp = format!("{:10.3} ms", at.as_secs_f64() * 1000.0);
+        let address = frame.header.address.get();
+        let command = frame.header.command.get();
+        if !frame.is_valid() {
+            println!("{timestamp}  ???  {}", describe_frame(frame));
+        } else if let Some((fragment, _)) = frame.fragment() {
+            println!(
+                "{timestamp}  FRG  0x{address:04X} {}: {}/{} of {} bytes",
+                command_name(command),
+                fragment.index.get() + 1,
+                fragment.total.get(),
+                fragment.size.get()
+            );
+        } else {
+            let classification = if sent {
+                self.classifier.request(frame, at);
+                Classification::Request
+            } else {
+                self.classifier.classify(frame, at)
+            };
+            match classification {
+                Classification::Request => {
+                    let request = frame.request();
+                    println!(
+                        "{timestamp}  REQ  0x{address:04X} {}: {:?}",
+                        command_name(command),
+                        request.unwrap()
+                    );
+                }
+                Classification::Response { latency } => {
+                    let response = frame.response();
+                    let latency = match latency {
+                        Some(latency) => format!(" after {} us", latency.as_micros()),
+                        None => " (unsolicited)".to_string(),
+                    };
+                    println!(
+                        "{timestamp}  RES  0x{address:04X} {}{latency}: {:?}",
+                        command_name(command),
+                        response.unwrap().1
+                    );
+                }
+                Classification::Unknown => println!(
+                    "{timestamp}  ???  0x{address:04X} {}: payload of {} bytes does not match command",
+                    command_name(command),
+                    frame.payload.len()
+                ),
+            }
+        }
+        if self.dump {
+            println!("{:>13}  {}", "", hex(frame.bytes));
+        }
+    }
+
+    /// Appends received bytes and prints all complete frames.
+    pub(crate) fn feed(&mut self, at: Duration, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        loop {
+            let buf = std::mem::take(&mut self.buf);
+            let (maybe_frame, processed) = next_frame(&buf);
+            let skipped = match maybe_frame {
+                Some(frame) => processed - frame.bytes.len(),
+                None => processed,
+            };
+            // the 0xFF preamble in front of each response is not worth reporting
+            if buf[..skipped].iter().any(|&b| b != 0xFF) {
+                println!(
+                    "{:10.3} ms  ---  {skipped} bytes of noise: {}",
+                    at.as_secs_f64() * 1000.0,
+                    hex(&buf[..skipped])
+                );
+            }
+            if let Some(frame) = maybe_frame {
+                self.print_frame(at, &frame);
+            }
+            self.buf = buf;
+            if processed == 0 {
+                break;
+            }
+            self.buf.drain(..processed);
+        }
+    }
+}
+
+/// Passively listens on the bus and prints a decoded timeline of all frames.
+/// Never transmits anything, so it can be used on a running installation.
+pub(crate) async fn sniff(
+    mut port: SerialStream,
+    dump: bool,
+    mut record: Option<CaptureWriter>,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut timeline = Timeline::new(dump);
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = port.read(&mut chunk).await.context("Reading from serial port")?;
+        if n == 0 {
+            bail!("The serial port was closed");
+        }
+        if let Some(record) = &mut record {
+            record.write(Direction::Rx, &chunk[..n])?;
+        }
+        timeline.feed(start.elapsed(), &chunk[..n]);
+    }
+}
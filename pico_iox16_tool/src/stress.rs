@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use pico_iox16_protocol::{
+    CheckReq, ConfigGetReq, InputGetFullReq, InputGetReq, InputGetThresholdStatesReq,
+    InputGetThresholdTimesReq, OutputGetReq, RequestTrait,
+};
+use pico_iox16_tool::Protocol;
+
+/// Outcome of the requests sent to one device.
+#[derive(Debug, Default)]
+struct DeviceResult {
+    ok: u64,
+    failed: u64,
+}
+
+async fn send<P: RequestTrait>(device: &mut Protocol, address: u16, request: P) -> bool {
+    device.send_request(address, request, |_| Ok(())).await.is_ok()
+}
+
+/// Sends the `index`-th request of the mix. Short and long frames alternate to exercise
+/// both the latency and the throughput of the bus.
+async fn send_mixed(device: &mut Protocol, address: u16, index: usize) -> bool {
+    match index % 7 {
+        0 => send(device, address, CheckReq).await,
+        1 => send(device, address, InputGetFullReq).await,
+        2 => send(device, address, InputGetReq).await,
+        3 => send(device, address, InputGetThresholdTimesReq).await,
+        4 => send(device, address, OutputGetReq).await,
+        5 => send(device, address, InputGetThresholdStatesReq).await,
+        _ => send(device, address, ConfigGetReq).await,
+    }
+}
+
+/// Sends a mix of read-only requests to the given devices back to back for `duration`
+/// and prints error statistics.
+pub(crate) async fn stress(device: &mut Protocol, addresses: &[u16], duration: Duration) -> Result<()> {
+    if addresses.is_empty() {
+        bail!("At least one address is required");
+    }
+    println!(
+        "Stressing {} device(s) at {} Hz for {duration:?}...",
+        addresses.len(),
+        device.baudrate()
+    );
+    let before = *device.statistics();
+    let mut results: Vec<DeviceResult> = addresses.iter().map(|_| DeviceResult::default()).collect();
+    let start = Instant::now();
+    let mut index = 0;
+    while start.elapsed() < duration {
+        let i = index % addresses.len();
+        if send_mixed(device, addresses[i], index / addresses.len()).await {
+            results[i].ok += 1;
+        } else {
+            results[i].failed += 1;
+        }
+        index += 1;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let statistics = device.statistics();
+    let requests = statistics.requests - before.requests;
+    println!("{:<8} {:>10} {:>10} {:>10}", "Address", "ok", "failed", "error %");
+    for (address, result) in addresses.iter().zip(&results) {
+        let total = result.ok + result.failed;
+        println!(
+            "{address:<8} {:>10} {:>10} {:>9.3}%",
+            result.ok,
+            result.failed,
+            result.failed as f64 * 100.0 / total.max(1) as f64
+        );
+    }
+    println!(
+        "{requests} requests in {elapsed:.1} s ({:.0} requests/s)",
+        requests as f64 / elapsed
+    );
+    println!("Timeouts:             {}", statistics.timeouts - before.timeouts);
+    println!(
+        "Checksum errors:      {}",
+        statistics.checksum_errors - before.checksum_errors
+    );
+    println!(
+        "Unexpected responses: {}",
+        statistics.unexpected_responses - before.unexpected_responses
+    );
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::fmt::Write as _;
+
+use pico_iox16_protocol::{Command, ERROR_FLAG, FRAGMENT_FLAG, Frame, next_frame};
+
+/// Formats bytes as space separated hex.
+pub fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 3);
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        write!(s, "{b:02X}").unwrap();
+    }
+    s
+}
+
+/// Formats the command of a frame, falling back to the raw value for unknown commands.
+/// Fragments are marked as such, see [`pico_iox16_protocol::Fragments`].
+pub fn command_name(command: u16) -> String {
+    if command & FRAGMENT_FLAG != 0 {
+        return format!("{} fragment", command_name(command & !FRAGMENT_FLAG));
+    }
+    if command & ERROR_FLAG != 0 {
+        return format!("{} error", command_name(command & !ERROR_FLAG));
+    }
+    match Command::try_from(command) {
+        Ok(command) => command.to_string(),
+        Err(_) => format!("unknown ({command})"),
+    }
+}
+
+/// Formats the decoded header fields and the checksum verdict of a frame.
+pub fn describe_frame(frame: &Frame<'_>) -> String {
+    let checksum = frame.checksum();
+    format!(
+        "address=0x{:04X} command={} sequence={} length={} words checksum=0x{:04X} ({})",
+        frame.header.address.get(),
+        command_name(frame.header.command.get()),
+        frame.header.sequence,
+        frame.header.length,
+        frame.footer.checksum.get(),
+        if frame.footer.checksum.get() == checksum {
+            "ok".to_string()
+        } else {
+            format!("INVALID, expected 0x{checksum:04X}")
+        },
+    )
+}
+
+/// Prints a hex dump of all frames and skipped bytes in `bytes` to stderr.
+pub fn trace_frames(direction: &str, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        let (maybe_frame, processed) = next_frame(bytes);
+        let skipped = match maybe_frame {
+            Some(frame) => processed - frame.bytes.len(),
+            None => processed,
+        };
+        if skipped > 0 {
+            eprintln!("{direction} {skipped} bytes skipped: {}", hex(&bytes[..skipped]));
+        }
+        if let Some(frame) = maybe_frame {
+            eprintln!("{direction} {} bytes: {}", frame.bytes.len(), hex(frame.bytes));
+            eprintln!("{direction}   {}", describe_frame(&frame));
+        }
+        if processed == 0 {
+            break;
+        }
+        bytes = &bytes[processed..];
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+/// Conversion of the input values of one channel into an engineering unit.
+///
+/// The calibrated input value is first converted to the voltage at the ADC using the
+/// reference voltage and full scale of [`Units`], then mapped linearly:
+/// `value = volts * scale + offset`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelUnit {
+    pub unit: String,
+    /// Units per volt.
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Value at 0 V.
+    #[serde(default)]
+    pub offset: f64,
+    /// Number of decimals shown.
+    #[serde(default = "default_decimals")]
+    pub decimals: usize,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_decimals() -> usize {
+    2
+}
+
+/// The units of all channels of a device. Channels without a unit stay in raw counts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Units {
+    /// ADC reference voltage in volts.
+    pub reference: f64,
+    /// ADC value corresponding to the reference voltage.
+    pub full_scale: f64,
+    pub channels: [Option<ChannelUnit>; 16],
+}
+
+impl Default for Units {
+    /// 12 bit ADC with 3.3 V reference, no channel units.
+    fn default() -> Self {
+        Self {
+            reference: 3.3,
+            full_scale: 4096.0,
+            channels: Default::default(),
+        }
+    }
+}
+
+impl Units {
+    /// Converts an input value to the unit of the channel, if it has one.
+    pub fn convert(&self, channel: usize, value: i16) -> Option<(f64, &ChannelUnit)> {
+        let unit = self.channels[channel].as_ref()?;
+        let volts = f64::from(value) * self.reference / self.full_scale;
+        Some((volts * unit.scale + unit.offset, unit))
+    }
+
+    /// Formats an input value with its unit, e.g. `3.42 bar`, or as raw count.
+    pub fn format(&self, channel: usize, value: i16) -> String {
+        match self.convert(channel, value) {
+            Some((converted, unit)) => format!("{converted:.*} {}", unit.decimals, unit.unit),
+            None => value.to_string(),
+        }
+    }
+
+    /// Formats an input value as a plain number in the unit of the channel.
+    pub fn format_number(&self, channel: usize, value: i16) -> String {
+        match self.convert(channel, value) {
+            Some((converted, unit)) => format!("{converted:.*}", unit.decimals),
+            None => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert() {
+        let mut units = Units::default();
+        units.channels[2] = Some(ChannelUnit {
+            unit: "bar".into(),
+            scale: 2.0,
+            offset: -1.0,
+            decimals: 1,
+        });
+        let (value, unit) = units.convert(2, 2048).unwrap();
+        assert!((value - 2.3).abs() < 1e-9, "{value}");
+        assert_eq!(unit.unit, "bar");
+        assert_eq!(units.format(2, 4096), "5.6 bar");
+        assert_eq!(units.format_number(2, 0), "-1.0");
+    }
+
+    #[test]
+    fn raw_counts() {
+        let units = Units::default();
+        assert_eq!(units.convert(0, 123), None);
+        assert_eq!(units.format(0, -123), "-123");
+        assert_eq!(units.format_number(15, 4095), "4095");
+    }
+
+    #[test]
+    fn defaults() {
+        let unit: ChannelUnit = toml::from_str("unit = \"V\"").unwrap();
+        assert_eq!(
+            unit,
+            ChannelUnit {
+                unit: "V".into(),
+                scale: 1.0,
+                offset: 0.0,
+                decimals: 2,
+            }
+        );
+    }
+}
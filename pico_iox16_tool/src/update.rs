@@ -0,0 +1,28 @@
+use std::{
+    io::{Write as _, stderr},
+    path::Path,
+};
+
+use anyhow::Result;
+use pico_iox16_tool::{Protocol, device::Info, firmware};
+
+/// Updates the firmware of the device to the image in `file`, showing the progress on stderr.
+pub(crate) async fn update(device: &mut Protocol, address: u16, file: &Path) -> Result<()> {
+    let image = firmware::load_image(file)?;
+    let old = Info::fetch(device, address).await?;
+    eprintln!(
+        "Updating device {address} from firmware {} with {} bytes",
+        old.version_string(),
+        image.len()
+    );
+    let info = firmware::update(device, address, &image, |written| {
+        eprint!("\rWriting {:3}%", written * 100 / image.len());
+        stderr().flush().ok();
+    })
+    .await;
+    eprintln!();
+    let info = info?;
+    println!("Device {address} runs firmware {}", info.version_string());
+    info.check_protocol_version()?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use pico_iox16_protocol::{
+    USER_DATA_SIZE, UserDataReadReq, UserDataReadRes, UserDataSpan, UserDataWriteReq,
+    UserDataWriteRes,
+};
+use pico_iox16_tool::{Error, Protocol, trace::hex};
+
+/// Parses bytes given as hex digits, optionally separated by spaces, e.g. `01 ff` or `01ff`.
+pub(crate) fn parse_hex(text: &str) -> Result<Vec<u8>, Error> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16)
+                .ok()
+                .filter(|_| pair.len() == 2)
+                .ok_or_else(|| Error::Invalid(format!("Invalid hex bytes '{text}'")))
+        })
+        .collect()
+}
+
+/// Writes `data` to the user data of the device at `offset` if given, and prints the user data
+/// from `offset`, `len` bytes or up to its end, or the part just written.
+pub(crate) async fn user_data(
+    device: &mut Protocol,
+    address: u16,
+    offset: u16,
+    len: Option<u16>,
+    data: Option<Vec<u8>>,
+) -> Result<()> {
+    let span = match data {
+        Some(data) => {
+            let request = UserDataWriteReq::new(offset, &data).ok_or_else(|| {
+                Error::Invalid(format!(
+                    "{} bytes at offset {offset} don't fit into the {USER_DATA_SIZE} bytes of user data",
+                    data.len()
+                ))
+            })?;
+            device
+                .send_request(address, request, |UserDataWriteRes(span)| Ok(*span))
+                .await?
+        }
+        None => UserDataSpan::new(offset, len.unwrap_or(USER_DATA_SIZE as u16)),
+    };
+    let (span, data) = device
+        .send_request(address, UserDataReadReq(span), |res: &UserDataReadRes| {
+            Ok((res.span, res.data().to_vec()))
+        })
+        .await?;
+    if data.is_empty() {
+        println!("Nothing at offset {offset}, the user data has {USER_DATA_SIZE} bytes");
+    }
+    // 16 bytes per line, with the printable ones as text
+    for (offset, line) in (usize::from(span.offset.get())..)
+        .step_by(16)
+        .zip(data.chunks(16))
+    {
+        let text: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("{offset:3}: {:47}  {text}", hex(line));
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context as _, Result};
+use pico_iox16_protocol::{RebootReq, RebootRes};
+use pico_iox16_tool::{Protocol, dump, inventory::Inventory};
+use toml::Value;
+
+use crate::config::{print_differences, warn_schema_mismatch};
+
+/// Compares the device at `address` with the settings in `expected`, see [`dump::diff`]. With
+/// `fix`, writes the differing settings, reboots the device if its configuration changed and
+/// compares again. Returns whether the device matches in the end.
+async fn verify_device(
+    device: &mut Protocol,
+    address: u16,
+    expected: &Value,
+    source: &str,
+    fix: bool,
+) -> Result<bool> {
+    warn_schema_mismatch(device, address)
+        .await
+        .context("Retrieving device info")?;
+    let actual = dump::fetch(device, address)
+        .await
+        .context("Retrieving settings")?;
+    let differences = dump::diff(expected, &actual)?;
+    if differences.is_empty() {
+        println!("Device 0x{address:04X} matches {source}");
+        return Ok(true);
+    }
+    print_differences(source, address, &differences);
+    if !fix {
+        return Ok(false);
+    }
+    let desired = dump::merge(expected, &actual)?;
+    let written = dump::apply(device, address, &actual, &desired)
+        .await
+        .context("Writing settings")?;
+    let (address, actual) = if written.config {
+        println!("Rebooting device 0x{address:04X} to apply its configuration...");
+        device
+            .send_request(address, RebootReq::FIRMWARE, |RebootRes| Ok(()))
+            .await
+            .context("Rebooting")?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let baudrate = device.baudrate();
+        device.set_baudrate(desired.settings.config.effective_baudrate())?;
+        let result = dump::fetch(device, desired.settings.config.address).await;
+        device.set_baudrate(baudrate)?;
+        let actual = result.context("Verifying after reboot")?;
+        (desired.settings.config.address, actual)
+    } else {
+        let actual = dump::fetch(device, address).await.context("Verifying")?;
+        (address, actual)
+    };
+    let remaining = dump::diff(expected, &actual)?;
+    if !remaining.is_empty() {
+        println!("After fixing:");
+        print_differences(source, address, &remaining);
+        return Ok(false);
+    }
+    println!(
+        "Fixed {} field(s) of device 0x{address:04X}",
+        differences.len()
+    );
+    Ok(true)
+}
+
+/// Compares the device at `address` with a dump file, or without an address, all devices of
+/// an inventory on the bus `bus` with the settings they should have once provisioned. With
+/// `fix`, the differing settings are written. Returns whether all devices match.
+pub(crate) async fn verify(
+    device: &mut Protocol,
+    bus: &str,
+    file: &Path,
+    address: Option<u16>,
+    fix: bool,
+) -> Result<bool> {
+    let source = file.display().to_string();
+    if let Some(address) = address {
+        let expected = dump::read_dump(file)?;
+        return verify_device(device, address, &expected, &source, fix).await;
+    }
+    let inventory = Inventory::load(file)?;
+    let buses = [bus.to_string()];
+    let mut checked = 0;
+    let mut matching = 0;
+    for entry in &inventory.devices {
+        if let Some(other) = &entry.bus
+            && crate::find_bus(other, &buses).is_none()
+        {
+            println!("Skipping {}, which is on bus {other}", entry.name());
+            continue;
+        }
+        checked += 1;
+        // provisioned devices listen at the address and baudrate of the inventory, others
+        // where `provision` expects them
+        let baudrate = device.baudrate();
+        device.set_baudrate(entry.baudrate.unwrap_or(baudrate))?;
+        let provisioned = !device
+            .scan([entry.desired_address()], |_| {})
+            .await?
+            .is_empty();
+        let address = if provisioned {
+            entry.desired_address()
+        } else {
+            device.set_baudrate(baudrate)?;
+            entry.address
+        };
+        let result = match entry.desired_settings() {
+            Ok(expected) => verify_device(device, address, &expected, &source, fix).await,
+            Err(err) => Err(err.into()),
+        };
+        device.set_baudrate(baudrate)?;
+        match result {
+            Ok(true) => matching += 1,
+            Ok(false) => {}
+            Err(err) => println!("{}: failed: {err:#}", entry.name()),
+        }
+    }
+    println!("{matching} of {checked} devices match {source}");
+    Ok(matching == checked)
+}
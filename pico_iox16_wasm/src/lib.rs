@@ -0,0 +1,112 @@
+//! JavaScript bindings for encoding and decoding frames in the browser, e.g. for talking to
+//! a device through WebSerial. See `www/index.html` for an example.
+
+use pico_iox16_protocol::{Command, MessageRef, next_frame};
+use wasm_bindgen::prelude::*;
+
+/// A frame found by [`next_frame_js`].
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct Frame {
+    address: u16,
+    command: u16,
+    sequence: u8,
+    payload: Vec<u8>,
+    valid: bool,
+}
+
+#[wasm_bindgen]
+impl Frame {
+    #[wasm_bindgen(getter)]
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+    #[wasm_bindgen(getter)]
+    pub fn command(&self) -> u16 {
+        self.command
+    }
+    /// The sequence number of the request, echoed in its response.
+    #[wasm_bindgen(getter)]
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+    #[wasm_bindgen(getter)]
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+    /// Whether the checksum matches the header and payload.
+    #[wasm_bindgen(getter)]
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+}
+
+/// Result of [`next_frame_js`].
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct FrameSearch {
+    frame: Option<Frame>,
+    processed: usize,
+}
+
+#[wasm_bindgen]
+impl FrameSearch {
+    /// The frame found, or `undefined` if no complete frame was found.
+    #[wasm_bindgen(getter)]
+    pub fn frame(&self) -> Option<Frame> {
+        self.frame.clone()
+    }
+    /// Number of bytes that can be discarded from the start of the searched data,
+    /// including the frame itself.
+    #[wasm_bindgen(getter)]
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+}
+
+/// Encodes a frame. The payload is padded with zeros to a multiple of 4 bytes and must be at
+/// most 1020 bytes. The device answers a request with its `sequence`.
+#[wasm_bindgen(js_name = encodeFrame)]
+pub fn encode_frame(
+    address: u16,
+    command: u16,
+    sequence: u8,
+    payload: &[u8],
+) -> Result<Vec<u8>, JsError> {
+    let message = MessageRef::new(address, command, sequence, payload)
+        .ok_or_else(|| JsError::new("Payload must be at most 1020 bytes"))?;
+    let mut bytes = vec![0; message.frame_len()];
+    message.write_to(&mut bytes);
+    Ok(bytes)
+}
+
+/// Searches for the next frame in `data`, regardless of whether its checksum is valid.
+#[wasm_bindgen(js_name = nextFrame)]
+pub fn next_frame_js(data: &[u8]) -> FrameSearch {
+    let (maybe_frame, processed) = next_frame(data);
+    FrameSearch {
+        frame: maybe_frame.map(|frame| Frame {
+            address: frame.header.address.get(),
+            command: frame.header.command.get(),
+            sequence: frame.header.sequence,
+            payload: frame.payload.to_vec(),
+            valid: frame.is_valid(),
+        }),
+        processed,
+    }
+}
+
+/// Returns the name of a command, or `undefined` for unknown commands.
+#[wasm_bindgen(js_name = commandName)]
+pub fn command_name(command: u16) -> Option<String> {
+    Command::try_from(command).ok().map(|c| c.to_string())
+}
+
+/// Returns the value of a command given its name, or `undefined` for unknown names.
+#[wasm_bindgen(js_name = commandValue)]
+pub fn command_value(name: &str) -> Option<u16> {
+    Command::ALL
+        .into_iter()
+        .find(|command| command.to_string() == name)
+        .map(u16::from)
+}